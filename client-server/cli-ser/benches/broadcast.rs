@@ -0,0 +1,72 @@
+//! Throughput of broadcasting one message to many clients, encoded for each of them or once.
+//!
+//! ```sh
+//! cargo bench --bench broadcast
+//! ```
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::{io, runtime::Runtime};
+
+use cli_ser::{ser, Data, Encoded, Image, Messageable, User};
+
+/// Numbers of recipients of the broadcast.
+const RECIPIENTS: [usize; 3] = [10, 100, 1000];
+
+fn messages(runtime: &Runtime) -> Vec<(&'static str, ser::Msg)> {
+    let image = runtime
+        .block_on(Image::from_path("../example-images/hexagon.jpeg"))
+        .unwrap();
+    let from: User = "bench".to_string().into();
+    let msg = |data: Data| ser::Msg::DataFrom {
+        data,
+        from: from.clone(),
+        msg_id: None,
+        display_name: None,
+        mentions: vec![],
+        guest: false,
+        urgent: false,
+    };
+    vec![
+        (
+            "text",
+            msg(Data::Text("Hello, how are you doing today?".to_string())),
+        ),
+        ("image", msg(image.into())),
+    ]
+}
+
+fn broadcast(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    for (name, msg) in &messages(&runtime) {
+        let mut group = c.benchmark_group(format!("broadcast {name}"));
+        for recipients in RECIPIENTS {
+            group.throughput(Throughput::Elements(recipients as u64));
+            group.bench_with_input(
+                BenchmarkId::new("encoded per recipient", recipients),
+                &recipients,
+                |b, &recipients| {
+                    b.to_async(&runtime).iter(|| async move {
+                        for _ in 0..recipients {
+                            msg.clone().send(&mut io::sink()).await.unwrap();
+                        }
+                    })
+                },
+            );
+            group.bench_with_input(
+                BenchmarkId::new("encoded once", recipients),
+                &recipients,
+                |b, &recipients| {
+                    b.to_async(&runtime).iter(|| async move {
+                        let encoded = Encoded::new(msg).unwrap();
+                        for _ in 0..recipients {
+                            encoded.clone().send(&mut io::sink()).await.unwrap();
+                        }
+                    })
+                },
+            );
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, broadcast);
+criterion_main!(benches);
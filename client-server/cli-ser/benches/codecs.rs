@@ -0,0 +1,93 @@
+//! Compares size and speed of the [Codec]s on representative messages.
+//!
+//! ```sh
+//! cargo bench --features postcard
+//! ```
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use cli_ser::{ser, Codec, Data, File, Image, Messageable, User};
+
+fn messages() -> Vec<(&'static str, ser::Msg)> {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let image = runtime
+        .block_on(Image::from_path("../example-images/hexagon.jpeg"))
+        .unwrap();
+    let file = runtime.block_on(File::from_path("Cargo.toml")).unwrap();
+    let from: User = "bench".to_string().into();
+    vec![
+        ("error", ser::Msg::Error(ser::Error::WrongPassword)),
+        (
+            "text",
+            ser::Msg::DataFrom {
+                data: Data::Text("Hello, how are you doing today?".to_string()),
+                from: from.clone(),
+                msg_id: Some(1),
+                display_name: None,
+                mentions: vec![],
+                guest: false,
+                urgent: false,
+            },
+        ),
+        (
+            "file",
+            ser::Msg::DataFrom {
+                data: file.into(),
+                from: from.clone(),
+                msg_id: Some(1),
+                display_name: None,
+                mentions: vec![],
+                guest: false,
+                urgent: false,
+            },
+        ),
+        (
+            "image",
+            ser::Msg::DataFrom {
+                data: image.into(),
+                from,
+                msg_id: Some(1),
+                display_name: None,
+                mentions: vec![],
+                guest: false,
+                urgent: false,
+            },
+        ),
+    ]
+}
+
+fn codecs(c: &mut Criterion) {
+    let codecs = [Codec::Bincode, Codec::Postcard];
+    let messages = messages();
+    for (name, msg) in &messages {
+        for codec in codecs {
+            let len = msg.to_bytes_with(codec).unwrap().len();
+            println!("{name} encoded by {codec:?} takes {len} bytes");
+        }
+    }
+    let mut group = c.benchmark_group("encode");
+    for (name, msg) in &messages {
+        for codec in codecs {
+            group.bench_with_input(
+                BenchmarkId::new(format!("{codec:?}"), name),
+                msg,
+                |b, msg| b.iter(|| black_box(msg.to_bytes_with(codec).unwrap())),
+            );
+        }
+    }
+    group.finish();
+    let mut group = c.benchmark_group("decode");
+    for (name, msg) in &messages {
+        for codec in codecs {
+            let bytes = msg.to_bytes_with(codec).unwrap();
+            group.bench_with_input(
+                BenchmarkId::new(format!("{codec:?}"), name),
+                &bytes,
+                |b, bytes| b.iter(|| black_box(ser::Msg::from_bytes_with(codec, bytes).unwrap())),
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, codecs);
+criterion_main!(benches);
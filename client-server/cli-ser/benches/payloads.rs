@@ -0,0 +1,57 @@
+//! Speed of encoding and decoding messages by the size of their payload.
+//!
+//! ```sh
+//! cargo bench --bench payloads
+//! ```
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use cli_ser::{ser, Data, File, Messageable, User};
+
+/// Sizes of the payloads in bytes, from a short text to a photo.
+const SIZES: [usize; 5] = [64, 1024, 64 * 1024, 1024 * 1024, 8 * 1024 * 1024];
+
+/// Text and file messages carrying `size` bytes.
+fn messages(size: usize) -> [(&'static str, ser::Msg); 2] {
+    let msg = |data| ser::Msg::DataFrom {
+        data,
+        from: User::from("bench".to_string()),
+        msg_id: Some(1),
+        display_name: None,
+        mentions: vec![],
+        guest: false,
+        urgent: false,
+    };
+    let text = "x".repeat(size);
+    let file = File::new("bench.bin", (0..size).map(|i| i as u8).collect::<Vec<_>>());
+    [
+        ("text", msg(Data::Text(text))),
+        ("file", msg(Data::File(file))),
+    ]
+}
+
+fn payloads(c: &mut Criterion) {
+    let mut encode = c.benchmark_group("to_bytes");
+    for size in SIZES {
+        encode.throughput(Throughput::Bytes(size as u64));
+        for (name, msg) in messages(size) {
+            encode.bench_with_input(BenchmarkId::new(name, size), &msg, |b, msg| {
+                b.iter(|| black_box(msg.to_bytes().unwrap()))
+            });
+        }
+    }
+    encode.finish();
+    let mut decode = c.benchmark_group("from_bytes");
+    for size in SIZES {
+        decode.throughput(Throughput::Bytes(size as u64));
+        for (name, msg) in messages(size) {
+            let bytes = msg.to_bytes().unwrap();
+            decode.bench_with_input(BenchmarkId::new(name, size), &bytes, |b, bytes| {
+                b.iter(|| black_box(ser::Msg::from_bytes(bytes).unwrap()))
+            });
+        }
+    }
+    decode.finish();
+}
+
+criterion_group!(benches, payloads);
+criterion_main!(benches);
@@ -0,0 +1,27 @@
+//! Arbitrary bytes read as a stream of frames and decoded as messages of either side.
+//!
+//! ```sh
+//! cargo +nightly fuzz run frames
+//! ```
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use cli_ser::{cli, ser, Messageable};
+
+fuzz_target!(|data: &[u8]| {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let mut stream = data;
+        // Undecodable frames are skipped, the stream is read on until it ends.
+        while let Ok((_, msg)) = ser::Msg::receive_sized(&mut stream).await {
+            if let Ok(msg) = msg {
+                let _ = msg.to_string();
+            }
+        }
+    });
+    let _ = cli::Msg::from_bytes(data);
+    let _ = ser::Msg::from_bytes(data);
+});
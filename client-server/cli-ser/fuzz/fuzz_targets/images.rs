@@ -0,0 +1,27 @@
+//! Arbitrary bytes validated as images and recognized as media, nothing may panic.
+//!
+//! ```sh
+//! cargo +nightly fuzz run images
+//! ```
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use cli_ser::{Image, ImageLimits, Media};
+
+/// Small enough for the fuzzer to get through many inputs per second.
+const LIMITS: ImageLimits = ImageLimits {
+    max_bytes: 1024 * 1024,
+    max_width: 1024,
+    max_height: 1024,
+    max_pixels: 256 * 1024,
+};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(image) = Image::from_bytes_with_limits(data.to_vec(), &LIMITS) {
+        let _ = image.normalized();
+    }
+    if let Ok(media) = Media::from_bytes("fuzz", data.to_vec()) {
+        let _ = media.is_animated();
+    }
+});
@@ -0,0 +1,322 @@
+//! [Arbitrary] messages for property tests, needs the `proptest` feature.
+//!
+//! Every value survives encoding and decoding by each [Codec](crate::Codec) unchanged,
+//! e.g. [ser::Error::Other] gets only codes no other kind has.
+use std::net::{IpAddr, SocketAddr};
+
+use proptest::{
+    arbitrary::{any, Arbitrary},
+    collection::vec,
+    option, prop_oneof,
+    sample::select,
+    strategy::{BoxedStrategy, Just, Strategy},
+};
+
+use crate::{
+    cli, ser, Bytes, Codec, Data, File, Image, ImageFormat, Media, MsgId, Preferences, Presence,
+    User, UserProfile,
+};
+
+/// Lowest code of [ser::Error::Other], far above the codes of the known kinds.
+const OTHER_CODES: u16 = 1000;
+
+/// Short bytes, the size of the payload does not matter to the encoding.
+fn bytes() -> impl Strategy<Value = Bytes> {
+    vec(any::<u8>(), 0..64).prop_map(Bytes::from)
+}
+
+macro_rules! arbitrary {
+    ($type:ty, $strategy:expr) => {
+        impl Arbitrary for $type {
+            type Parameters = ();
+            type Strategy = BoxedStrategy<Self>;
+
+            fn arbitrary_with(_: ()) -> Self::Strategy {
+                $strategy.boxed()
+            }
+        }
+    };
+}
+
+arbitrary!(User, any::<String>().prop_map(User));
+arbitrary!(MsgId, any::<u64>().prop_map(MsgId));
+arbitrary!(
+    File,
+    (any::<String>(), bytes()).prop_map(|(name, bytes)| File { name, bytes })
+);
+arbitrary!(
+    Image,
+    (
+        select(vec![
+            ImageFormat::Png,
+            ImageFormat::Jpeg,
+            ImageFormat::Gif,
+            ImageFormat::WebP,
+            ImageFormat::Bmp,
+            ImageFormat::Tiff,
+        ]),
+        bytes(),
+    )
+        .prop_map(|(format, bytes)| Image { format, bytes })
+);
+arbitrary!(
+    Media,
+    (
+        any::<String>(),
+        select(vec![
+            "image/gif",
+            "video/mp4",
+            "video/quicktime",
+            "video/webm"
+        ]),
+        any::<Option<u64>>(),
+        bytes(),
+    )
+        .prop_map(|(name, mime, duration_ms, bytes)| Media {
+            name,
+            mime: mime.to_string(),
+            duration_ms,
+            bytes,
+        })
+);
+arbitrary!(
+    Data,
+    prop_oneof![
+        any::<String>().prop_map(Data::Text),
+        any::<File>().prop_map(Data::File),
+        any::<Image>().prop_map(Data::Image),
+        any::<Media>().prop_map(Data::Media),
+    ]
+);
+arbitrary!(
+    UserProfile,
+    (
+        any::<Option<String>>(),
+        any::<Option<String>>(),
+        option::of(any::<Image>()),
+    )
+        .prop_map(|(display_name, status, avatar)| UserProfile {
+            display_name,
+            status,
+            avatar,
+        })
+);
+
+arbitrary!(
+    Presence,
+    select(vec![
+        Presence::Online,
+        Presence::Away,
+        Presence::DoNotDisturb
+    ])
+);
+
+arbitrary!(
+    Preferences,
+    (
+        vec(any::<User>(), 0..4),
+        vec(any::<String>(), 0..4),
+        any::<bool>(),
+        any::<Option<u32>>(),
+    )
+        .prop_map(
+            |(muted, patterns, mentions_only, history_length)| Preferences {
+                muted,
+                patterns,
+                mentions_only,
+                history_length,
+            }
+        )
+);
+
+arbitrary!(
+    cli::Credentials,
+    (any::<User>(), any::<String>())
+        .prop_map(|(user, password)| cli::Credentials { user, password })
+);
+arbitrary!(
+    cli::Auth,
+    prop_oneof![
+        any::<cli::Credentials>().prop_map(cli::Auth::LogIn),
+        any::<cli::Credentials>().prop_map(cli::Auth::SignUp),
+        any::<User>().prop_map(cli::Auth::Guest),
+        any::<String>().prop_map(cli::Auth::OidcToken),
+        any::<String>().prop_map(cli::Auth::RequestReset),
+        (any::<String>(), any::<String>())
+            .prop_map(|(code, new_password)| cli::Auth::ResetPassword { code, new_password }),
+        any::<String>().prop_map(cli::Auth::TotpCode),
+        (any::<cli::Credentials>(), any::<String>()).prop_map(|(credentials, invite)| {
+            cli::Auth::SignUpInvited {
+                credentials,
+                invite,
+            }
+        }),
+        any::<u64>().prop_map(cli::Auth::Solution),
+    ]
+);
+arbitrary!(
+    cli::TwoFactor,
+    prop_oneof![
+        Just(cli::TwoFactor::Enable),
+        any::<String>().prop_map(cli::TwoFactor::Confirm),
+        any::<String>().prop_map(cli::TwoFactor::Disable),
+    ]
+);
+arbitrary!(
+    cli::Admin,
+    prop_oneof![
+        any::<String>().prop_map(cli::Admin::SetMotd),
+        (any::<User>(), any::<Option<u64>>())
+            .prop_map(|(user, bytes)| cli::Admin::SetQuota { user, bytes }),
+        (any::<u32>(), any::<Option<std::time::Duration>>())
+            .prop_map(|(uses, valid_for)| cli::Admin::CreateInvite { uses, valid_for }),
+    ]
+);
+arbitrary!(
+    cli::ProfileChange,
+    prop_oneof![
+        any::<String>().prop_map(cli::ProfileChange::DisplayName),
+        any::<String>().prop_map(cli::ProfileChange::Status),
+        any::<Image>().prop_map(cli::ProfileChange::Avatar),
+        any::<String>().prop_map(cli::ProfileChange::Email),
+    ]
+);
+arbitrary!(
+    cli::Msg,
+    prop_oneof![
+        any::<cli::Auth>().prop_map(cli::Msg::Auth),
+        any::<Data>().prop_map(cli::Msg::ToAll),
+        any::<cli::Admin>().prop_map(cli::Msg::Admin),
+        any::<i64>().prop_map(|msg_id| cli::Msg::MarkRead { msg_id }),
+        any::<i64>().prop_map(|msg_id| cli::Msg::ReadStatus { msg_id }),
+        any::<cli::ProfileChange>().prop_map(cli::Msg::SetProfile),
+        any::<User>().prop_map(cli::Msg::GetProfile),
+        any::<cli::TwoFactor>().prop_map(cli::Msg::TwoFactor),
+        Just(cli::Msg::Stats),
+        (any::<Presence>(), any::<Option<String>>())
+            .prop_map(|(presence, message)| cli::Msg::SetPresence { presence, message }),
+        any::<String>().prop_map(cli::Msg::Urgent),
+        any::<Preferences>().prop_map(cli::Msg::SetPrefs),
+        Just(cli::Msg::GetPrefs),
+        vec(any::<u8>(), 0..4).prop_map(cli::Msg::Codecs),
+    ]
+);
+arbitrary!(
+    cli::Envelope,
+    (any::<Option<MsgId>>(), any::<cli::Msg>()).prop_map(|(id, msg)| cli::Envelope { id, msg })
+);
+
+arbitrary!(
+    ser::Refusal,
+    prop_oneof![
+        Just(ser::Refusal::TooManyConnections),
+        Just(ser::Refusal::TooManyFromAddress),
+        Just(ser::Refusal::AddressNotAllowed),
+    ]
+);
+arbitrary!(
+    ser::Error,
+    prop_oneof![
+        any::<u64>().prop_map(|len| ser::Error::Malformed { len }),
+        (any::<cli::Msg>(), any::<User>()).prop_map(|(msg, user)| ser::Error::SendMsgTo(msg, user)),
+        any::<cli::Msg>().prop_map(ser::Error::NotAuthenticated),
+        Just(ser::Error::AlreadyAuthenticated),
+        Just(ser::Error::WrongUser),
+        Just(ser::Error::WrongPassword),
+        Just(ser::Error::UsernameTaken),
+        Just(ser::Error::NotAdmin),
+        any::<i64>().prop_map(ser::Error::UnknownMessage),
+        any::<User>().prop_map(ser::Error::UnknownUser),
+        any::<ser::Refusal>().prop_map(ser::Error::Refused),
+        any::<String>().prop_map(ser::Error::Rejected),
+        (any::<u64>(), any::<u64>())
+            .prop_map(|(used, limit)| ser::Error::QuotaExceeded { used, limit }),
+        Just(ser::Error::SessionActive),
+        Just(ser::Error::GuestsNotAllowed),
+        any::<String>().prop_map(ser::Error::InvalidToken),
+        Just(ser::Error::InvalidCredentials),
+        Just(ser::Error::InvalidResetCode),
+        Just(ser::Error::InvalidTotpCode),
+        Just(ser::Error::InviteRequired),
+        Just(ser::Error::InvalidInvite),
+        Just(ser::Error::ChallengeFailed),
+        (OTHER_CODES.., any::<String>())
+            .prop_map(|(code, detail)| ser::Error::Other { code, detail }),
+    ]
+);
+arbitrary!(
+    ser::Msg,
+    prop_oneof![
+        Just(ser::Msg::Authenticated),
+        Just(ser::Msg::TotpRequired),
+        any::<String>().prop_map(|url| ser::Msg::TotpEnrollment { url }),
+        any::<ser::Error>().prop_map(ser::Msg::Error),
+        (
+            any::<Data>(),
+            any::<User>(),
+            any::<Option<i64>>(),
+            any::<Option<String>>(),
+            vec(any::<User>(), 0..4),
+            any::<bool>(),
+            any::<bool>(),
+        )
+            .prop_map(
+                |(data, from, msg_id, display_name, mentions, guest, urgent)| ser::Msg::DataFrom {
+                    data,
+                    from,
+                    msg_id,
+                    display_name,
+                    mentions,
+                    guest,
+                    urgent,
+                }
+            ),
+        (any::<MsgId>(), any::<i64>()).prop_map(|(id, msg_id)| ser::Msg::Stored { id, msg_id }),
+        (any::<User>(), any::<UserProfile>())
+            .prop_map(|(user, profile)| ser::Msg::Profile { user, profile }),
+        (any::<i64>(), vec(any::<User>(), 0..4))
+            .prop_map(|(msg_id, users)| ser::Msg::ReadBy { msg_id, users }),
+        any::<String>().prop_map(ser::Msg::ServerInfo),
+        any::<MsgId>().prop_map(ser::Msg::Ack),
+        (any::<MsgId>(), any::<ser::Error>()).prop_map(|(id, error)| ser::Msg::Rejected(id, error)),
+        // Flow info and scope of IPv6 addresses are not encoded.
+        (any::<IpAddr>(), any::<u16>(), any::<String>()).prop_map(|(ip, port, time)| {
+            ser::Msg::NewSession {
+                addr: SocketAddr::new(ip, port),
+                time,
+            }
+        }),
+        (
+            any::<std::time::Duration>(),
+            any::<u64>(),
+            any::<u64>(),
+            any::<String>()
+        )
+            .prop_map(|(uptime, connected, messages_today, version)| {
+                ser::Msg::Stats {
+                    uptime,
+                    connected,
+                    messages_today,
+                    version,
+                }
+            }),
+        (any::<User>(), any::<Presence>(), any::<Option<String>>()).prop_map(
+            |(user, presence, message)| ser::Msg::PresenceChanged {
+                user,
+                presence,
+                message,
+            }
+        ),
+        any::<Preferences>().prop_map(ser::Msg::Prefs),
+        (any::<String>(), any::<u32>(), any::<Option<String>>()).prop_map(
+            |(code, uses, expires)| ser::Msg::Invite {
+                code,
+                uses,
+                expires
+            }
+        ),
+        (any::<String>(), any::<u8>())
+            .prop_map(|(prefix, difficulty)| ser::Msg::Challenge { prefix, difficulty }),
+        select(Codec::ALL).prop_map(ser::Msg::CodecChosen),
+    ]
+);
@@ -0,0 +1,61 @@
+//! Proof of work asked of clients signing up, see [ser::Msg::Challenge][crate::ser::Msg::Challenge].
+//!
+//! A solution is a nonce such that the SHA-256 hash of the prefix followed by the nonce
+//! (its decimal digits) begins with `difficulty` zero bits. Finding one takes about `2^difficulty` hashes,
+//! checking it a single one.
+//!
+//! ```
+//! use cli_ser::challenge;
+//!
+//! let nonce = challenge::solve("3f2a9c41d07be865", 8).unwrap();
+//! assert!(challenge::verify("3f2a9c41d07be865", 8, nonce));
+//! ```
+
+use sha2::{Digest, Sha256};
+
+/// Hardest difficulty [solve] attempts, a harder challenge is unreasonable to ask of a client.
+pub const MAX_DIFFICULTY: u8 = 32;
+
+/// Finds the first nonce solving the challenge, `None` when the difficulty exceeds [MAX_DIFFICULTY].
+///
+/// It runs until found, use e.g. [Executor::spawn_blocking][crate::rt::Executor::spawn_blocking] not to hold up other tasks.
+pub fn solve(prefix: &str, difficulty: u8) -> Option<u64> {
+    if difficulty > MAX_DIFFICULTY {
+        return None;
+    }
+    (0..).find(|&nonce| verify(prefix, difficulty, nonce))
+}
+
+/// Whether the nonce solves the challenge.
+pub fn verify(prefix: &str, difficulty: u8, nonce: u64) -> bool {
+    let hash: [u8; 32] = Sha256::digest(format!("{prefix}{nonce}")).into();
+    leading_zeros(&hash) >= u32::from(difficulty)
+}
+
+fn leading_zeros(hash: &[u8; 32]) -> u32 {
+    let mut zeros = 0;
+    for byte in hash {
+        zeros += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    zeros
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn solutions() {
+        assert!(verify("prefix", 0, 0));
+        let nonce = solve("prefix", 12).unwrap();
+        assert!(verify("prefix", 12, nonce));
+        let hash: [u8; 32] = Sha256::digest(format!("prefix{nonce}")).into();
+        assert!(leading_zeros(&hash) >= 12);
+        assert!((0..nonce).all(|n| !verify("prefix", 12, n)));
+        assert_eq!(solve("prefix", MAX_DIFFICULTY + 1), None);
+        assert_eq!(leading_zeros(&[0; 32]), 256);
+    }
+}
@@ -0,0 +1,224 @@
+//! Codes of errors shared by the protocol, the client and the server, see [ErrorCode].
+use std::{
+    error,
+    fmt::{self, Display},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ser, Error};
+
+/// What went wrong, in broad terms, told by the variant of an error or, for a code alone, by the [ErrorCode].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// A message could not be encoded, decoded or was not expected.
+    Protocol,
+    /// Reading or writing a stream or a file failed.
+    Io,
+    /// A file, an image or a video is not valid or not supported.
+    Data,
+    /// Storing or querying the data of the server failed.
+    Database,
+    /// Authentication failed or the user is not allowed to do it.
+    Auth,
+    /// The user or the message is not known.
+    NotFound,
+    /// A limit of the server was reached, e.g. of connections or of storage.
+    Limit,
+    /// The server's filters or moderation did not let the message through.
+    Rejected,
+    /// The code is not known to this side.
+    Other,
+}
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            Self::Protocol => "protocol",
+            Self::Io => "io",
+            Self::Data => "data",
+            Self::Database => "database",
+            Self::Auth => "auth",
+            Self::NotFound => "not found",
+            Self::Limit => "limit",
+            Self::Rejected => "rejected",
+            Self::Other => "other",
+        };
+        f.write_str(kind)
+    }
+}
+
+/// Number identifying an error the same way in the protocol, the client, the server and their logs,
+/// shown as e.g. `E0012`.
+///
+/// It is sent as the number alone. Codes never change their meaning, the ranges say the [kind][Self::kind]:
+///
+/// | Codes     | Errors                                                    |
+/// |-----------|-----------------------------------------------------------|
+/// | 1–999     | of the server sent to clients, see [ser::Error::kind]     |
+/// | 1000–1999 | [Io][ErrorKind::Io]                                       |
+/// | 2000–2999 | [Protocol][ErrorKind::Protocol]                           |
+/// | 3000–3999 | [Data][ErrorKind::Data]                                   |
+/// | 4000–4999 | [Database][ErrorKind::Database]                           |
+/// | 5000–5999 | [Auth][ErrorKind::Auth]                                   |
+/// | 6000–6999 | [Limit][ErrorKind::Limit]                                 |
+///
+/// Within the ranges, `cli-ser` uses `x000`–`x199` (see [Error::code]), the client `x200`–`x499`
+/// and the server `x500`–`x999`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(transparent)]
+pub struct ErrorCode(pub u16);
+impl ErrorCode {
+    /// Kind of the errors of the code's range, for a code received without its error, e.g. [ser::Error::Other].
+    ///
+    /// Errors at hand tell their kind by their variant, see [Error::kind] and [ser::Error::kind],
+    /// the ranges agree with them. Errors of the server sent to clients (1–999) have no range,
+    /// the code alone is [Other][ErrorKind::Other].
+    pub fn kind(self) -> ErrorKind {
+        match self.0 {
+            1000..=1999 => ErrorKind::Io,
+            2000..=2999 => ErrorKind::Protocol,
+            3000..=3999 => ErrorKind::Data,
+            4000..=4999 => ErrorKind::Database,
+            5000..=5999 => ErrorKind::Auth,
+            6000..=6999 => ErrorKind::Limit,
+            _ => ErrorKind::Other,
+        }
+    }
+
+    /// Code of the first error in the chain of sources (the error itself included) which has one.
+    ///
+    /// Only [cli-ser][crate] errors are known here, e.g. `ErrorCode::of(anyhow_error.as_ref())`.
+    pub fn of(error: &(dyn error::Error + 'static)) -> Option<ErrorCode> {
+        let mut next = Some(error);
+        while let Some(error) = next {
+            if let Some(error) = error.downcast_ref::<Error>() {
+                return Some(error.code());
+            }
+            if let Some(error) = error.downcast_ref::<ser::Error>() {
+                return Some(error.code());
+            }
+            next = error.source();
+        }
+        None
+    }
+}
+impl From<u16> for ErrorCode {
+    fn from(code: u16) -> Self {
+        ErrorCode(code)
+    }
+}
+impl Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "E{:04}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Error::*;
+
+    #[derive(thiserror::Error, Debug)]
+    #[error("wrapping")]
+    struct Wrapping(#[source] Error);
+
+    #[test]
+    fn kinds_and_chains() {
+        assert_eq!(ErrorCode(12).to_string(), "E0012");
+        assert_eq!(ser::Error::WrongUser.kind(), ErrorKind::Auth);
+        assert_eq!(
+            ser::Error::Rejected(String::new()).kind(),
+            ErrorKind::Rejected
+        );
+        let other = ser::Error::Other {
+            code: 4501,
+            detail: "database failed".to_string(),
+        };
+        assert_eq!(other.code(), ErrorCode(4501));
+        assert_eq!(other.kind(), ErrorKind::Database);
+        assert_eq!(ErrorCode(999).kind(), ErrorKind::Other);
+        assert_eq!(
+            ser::Error::UnknownUser(String::new().into()).kind(),
+            ErrorKind::NotFound
+        );
+        assert_eq!(ser::Error::Malformed { len: 4 }.kind(), ErrorKind::Protocol);
+        // A newer server's error, or one whose payload is not understood, has no variant to tell the kind.
+        let undecoded = ser::Error::Other {
+            code: 12,
+            detail: "rejected".to_string(),
+        };
+        assert_eq!(undecoded.kind(), ErrorKind::Other);
+
+        let io = std::io::Error::new(std::io::ErrorKind::BrokenPipe, "gone");
+        assert_eq!(SendBytes(io).kind(), ErrorKind::Io);
+        let refused = Authentication(ser::Error::InvalidTotpCode);
+        assert_eq!(refused.code(), ser::Error::InvalidTotpCode.code());
+        assert_eq!(refused.kind(), ErrorKind::Auth);
+        assert_eq!(ErrorCode::of(&refused), Some(ErrorCode(19)));
+        let unrelated = std::fmt::Error;
+        assert_eq!(ErrorCode::of(&unrelated), None);
+        let wrapping = Wrapping(UnsupportedMedia);
+        assert_eq!(ErrorCode::of(&wrapping), Some(UnsupportedMedia.code()));
+    }
+
+    #[test]
+    fn every_variant_has_its_kind() {
+        let io = || std::io::Error::new(std::io::ErrorKind::Other, "io");
+        let bincode = || Box::new(bincode::ErrorKind::SizeLimit);
+        let image = || image::load_from_memory(b"not an image").unwrap_err();
+        let errors = [
+            (ReceiveBytes(io()), ErrorKind::Io),
+            (DisconnectedStream(io()), ErrorKind::Io),
+            (SendBytes(io()), ErrorKind::Io),
+            (Connect(io()), ErrorKind::Io),
+            (LoadFile(io()), ErrorKind::Io),
+            (SaveFile(io()), ErrorKind::Io),
+            (TimedOut(std::time::Duration::ZERO), ErrorKind::Io),
+            (SerializeMsg(bincode()), ErrorKind::Protocol),
+            (DeserializeMsg(bincode()), ErrorKind::Protocol),
+            #[cfg(feature = "postcard")]
+            (
+                SerializePostcard(postcard::Error::SerializeBufferFull),
+                ErrorKind::Protocol,
+            ),
+            #[cfg(feature = "postcard")]
+            (
+                DeserializePostcard(postcard::Error::DeserializeUnexpectedEnd),
+                ErrorKind::Protocol,
+            ),
+            #[cfg(feature = "json")]
+            (
+                SerializeJson(serde_json::from_str::<()>("x").unwrap_err()),
+                ErrorKind::Protocol,
+            ),
+            #[cfg(feature = "json")]
+            (
+                DeserializeJson(serde_json::from_str::<()>("x").unwrap_err()),
+                ErrorKind::Protocol,
+            ),
+            (FrameTooLarge(0), ErrorKind::Protocol),
+            (DecodeImg(image()), ErrorKind::Data),
+            (ConvertImg(image()), ErrorKind::Data),
+            (ImageTooLarge(String::new()), ErrorKind::Data),
+            (UnsupportedMedia, ErrorKind::Data),
+            (
+                UnsupportedFormat(image::ImageOutputFormat::Png),
+                ErrorKind::Data,
+            ),
+            (ParseImageFormat(String::new()), ErrorKind::Data),
+            (Authentication(ser::Error::NotAdmin), ErrorKind::Auth),
+            (
+                Authentication(ser::Error::Refused(ser::Refusal::TooManyConnections)),
+                ErrorKind::Limit,
+            ),
+        ];
+        for (error, kind) in errors {
+            assert_eq!(error.kind(), kind, "{error:?}");
+            // A code received alone has the same kind, as long as it has a range.
+            if error.code().0 >= 1000 {
+                assert_eq!(error.code().kind(), kind, "{error:?}");
+            }
+        }
+    }
+}
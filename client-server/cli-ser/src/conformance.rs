@@ -0,0 +1,506 @@
+//! Protocol conformance suite.
+//!
+//! Scripted exchanges any server implementation can be [run against][run_server_suite]
+//! and any client implementation, driven through the [Client] trait, [against][run_client_suite],
+//! the outcome of every scenario is collected into a [Report].
+//!
+//! Only the wire format is assumed, so the suite works for implementations not written in Rust as well,
+//! e.g. a [Client] driving another program speaking [JSON][crate::Codec] (the `json` feature).
+use std::{
+    fmt::{self, Display},
+    future::Future,
+    net::SocketAddr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+
+use crate::{challenge, cli, ser, write_bytes, Codec, Data, File, Messageable, User};
+
+/// How long to wait for a single server response.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Size of the payload in the big frame scenario.
+const BIG_FRAME_LEN: usize = 4 * 1024 * 1024;
+
+type Outcome = Result<(), String>;
+
+/// Pass/fail results of all scenarios of the suite.
+#[derive(Debug)]
+pub struct Report {
+    /// Name of each scenario with its outcome, in the order they were run.
+    pub results: Vec<(&'static str, Outcome)>,
+}
+impl Report {
+    /// Returns true when every scenario passed.
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(|(_, outcome)| outcome.is_ok())
+    }
+}
+impl Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, outcome) in &self.results {
+            match outcome {
+                Ok(()) => writeln!(f, "PASS {name}")?,
+                Err(e) => writeln!(f, "FAIL {name}: {e}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs all scenarios against the server at `addr`.
+///
+/// The `creds` are signed up (unless the user already exists) and used for logging in,
+/// the password must be correct in case the user exists.
+pub async fn run_server_suite(addr: SocketAddr, creds: cli::Credentials) -> Report {
+    let mut results = Vec::new();
+    macro_rules! scenario {
+        ($name:ident) => {
+            results.push((stringify!($name), $name(addr, &creds).await))
+        };
+    }
+    scenario!(sign_up);
+    scenario!(sign_up_taken);
+    scenario!(log_in);
+    scenario!(log_in_wrong_password);
+    scenario!(log_in_unknown_user);
+    scenario!(not_authenticated);
+    scenario!(already_authenticated);
+    scenario!(broadcast);
+    scenario!(big_frame);
+    scenario!(unknown_variant);
+    Report { results }
+}
+
+/// Awaits the `future` for at most [RESPONSE_TIMEOUT].
+async fn timed<T>(future: impl Future<Output = crate::Result<T>>) -> Result<T, String> {
+    match timeout(RESPONSE_TIMEOUT, future).await {
+        Ok(res) => res.map_err(|e| format!("{e}")),
+        Err(_) => Err("the server did not respond in time".to_string()),
+    }
+}
+
+async fn connect(addr: SocketAddr) -> Result<TcpStream, String> {
+    TcpStream::connect(addr)
+        .await
+        .map_err(|e| format!("connecting failed: {e}"))
+}
+
+async fn exchange(stream: &mut TcpStream, msg: cli::Msg) -> Result<ser::Msg, String> {
+    timed(msg.send(stream)).await?;
+    timed(ser::Msg::receive(stream)).await
+}
+
+fn expect(got: ser::Msg, expected: ser::Msg) -> Outcome {
+    if got == expected {
+        Ok(())
+    } else {
+        Err(format!("expected {expected}, got {got}"))
+    }
+}
+
+/// Connects and logs in with the `creds`.
+async fn logged_in(addr: SocketAddr, creds: &cli::Credentials) -> Result<TcpStream, String> {
+    let mut stream = connect(addr).await?;
+    let msg = cli::Msg::Auth(cli::Auth::LogIn(creds.clone()));
+    expect(exchange(&mut stream, msg).await?, ser::Msg::Authenticated)?;
+    Ok(stream)
+}
+
+/// Returns a username which most likely does not exist yet.
+fn unique_user(prefix: &str) -> User {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    format!("{prefix}-{nanos}").into()
+}
+
+async fn sign_up(addr: SocketAddr, creds: &cli::Credentials) -> Outcome {
+    let mut stream = connect(addr).await?;
+    let msg = cli::Msg::Auth(cli::Auth::SignUp(creds.clone()));
+    match exchange(&mut stream, msg).await? {
+        ser::Msg::Authenticated | ser::Msg::Error(ser::Error::UsernameTaken) => Ok(()),
+        other => Err(format!("unexpected {other}")),
+    }
+}
+
+async fn sign_up_taken(addr: SocketAddr, creds: &cli::Credentials) -> Outcome {
+    let mut stream = connect(addr).await?;
+    let msg = cli::Msg::Auth(cli::Auth::SignUp(creds.clone()));
+    expect(
+        exchange(&mut stream, msg).await?,
+        ser::Error::UsernameTaken.into(),
+    )
+}
+
+async fn log_in(addr: SocketAddr, creds: &cli::Credentials) -> Outcome {
+    logged_in(addr, creds).await.map(|_| ())
+}
+
+async fn log_in_wrong_password(addr: SocketAddr, creds: &cli::Credentials) -> Outcome {
+    let mut stream = connect(addr).await?;
+    let wrong = cli::Credentials {
+        password: format!("{}-wrong", creds.password),
+        ..creds.clone()
+    };
+    let msg = cli::Msg::Auth(cli::Auth::LogIn(wrong));
+    match exchange(&mut stream, msg).await? {
+        ser::Msg::Error(ser::Error::InvalidCredentials) => Ok(()),
+        got => expect(got, ser::Error::WrongPassword.into()),
+    }
+}
+
+async fn log_in_unknown_user(addr: SocketAddr, creds: &cli::Credentials) -> Outcome {
+    let mut stream = connect(addr).await?;
+    let unknown = cli::Credentials {
+        user: unique_user("conformance-unknown"),
+        ..creds.clone()
+    };
+    let msg = cli::Msg::Auth(cli::Auth::LogIn(unknown));
+    // Servers may not tell unknown users from wrong passwords.
+    match exchange(&mut stream, msg).await? {
+        ser::Msg::Error(ser::Error::InvalidCredentials) => Ok(()),
+        got => expect(got, ser::Error::WrongUser.into()),
+    }
+}
+
+async fn not_authenticated(addr: SocketAddr, _: &cli::Credentials) -> Outcome {
+    let mut stream = connect(addr).await?;
+    let msg = cli::Msg::ToAll(Data::Text("anybody?".to_string()));
+    expect(
+        exchange(&mut stream, msg.clone()).await?,
+        ser::Error::NotAuthenticated(msg).into(),
+    )
+}
+
+async fn already_authenticated(addr: SocketAddr, creds: &cli::Credentials) -> Outcome {
+    let mut stream = logged_in(addr, creds).await?;
+    let msg = cli::Msg::Auth(cli::Auth::LogIn(creds.clone()));
+    expect(
+        exchange(&mut stream, msg).await?,
+        ser::Error::AlreadyAuthenticated.into(),
+    )
+}
+
+/// Sends the `data` from one connection and checks another one receives it.
+async fn deliver(addr: SocketAddr, creds: &cli::Credentials, data: Data) -> Outcome {
+    let mut sender = logged_in(addr, creds).await?;
+    let mut receiver = logged_in(addr, creds).await?;
+    timed(cli::Msg::ToAll(data.clone()).send(&mut sender)).await?;
+    let got = match timed(ser::Msg::receive(&mut receiver)).await? {
+        // Ids and display names are up to the server, they are not compared.
+        ser::Msg::DataFrom {
+            data,
+            from,
+            mentions,
+            ..
+        } => ser::Msg::DataFrom {
+            data,
+            from,
+            msg_id: None,
+            display_name: None,
+            mentions,
+            guest: false,
+            urgent: false,
+        },
+        other => other,
+    };
+    expect(
+        got,
+        ser::Msg::DataFrom {
+            data,
+            from: creds.user.clone(),
+            msg_id: None,
+            display_name: None,
+            mentions: vec![],
+            guest: false,
+            urgent: false,
+        },
+    )
+}
+
+async fn broadcast(addr: SocketAddr, creds: &cli::Credentials) -> Outcome {
+    deliver(addr, creds, Data::Text("hello conformance".to_string())).await
+}
+
+async fn big_frame(addr: SocketAddr, creds: &cli::Credentials) -> Outcome {
+    let bytes = (0..BIG_FRAME_LEN).map(|i| i as u8).collect();
+    let file = File {
+        name: "big.bin".to_string(),
+        bytes,
+    };
+    deliver(addr, creds, file.into()).await
+}
+
+/// Sends a frame with a message variant unknown to the server, the connection must survive it.
+async fn unknown_variant(addr: SocketAddr, creds: &cli::Credentials) -> Outcome {
+    let mut stream = logged_in(addr, creds).await?;
+    // bincode encodes enum variants as u32 indexes.
+    timed(write_bytes(&mut stream, &u32::MAX.to_le_bytes())).await?;
+    match timed(ser::Msg::receive(&mut stream)).await? {
+        ser::Msg::Error(ser::Error::Malformed { len: 4 }) => {}
+        other => return Err(format!("expected a Malformed error, got {other}")),
+    }
+    let msg = cli::Msg::Auth(cli::Auth::LogIn(creds.clone()));
+    expect(
+        exchange(&mut stream, msg).await?,
+        ser::Error::AlreadyAuthenticated.into(),
+    )
+}
+
+/// Client implementation under test of the [client suite][run_client_suite].
+///
+/// Errors are described by the text, the suite only tells whether there was one.
+#[async_trait]
+pub trait Client: Send {
+    /// Connects to the server at `addr`, authenticates by the `auth` and returns once the server accepted it.
+    ///
+    /// A [challenge][ser::Msg::Challenge] of the server is to be solved, an [error][ser::Msg::Error] returned.
+    async fn connect(&mut self, addr: SocketAddr, auth: cli::Auth) -> Result<(), String>;
+
+    /// Sends the `data` to everyone over the last connection.
+    async fn send(&mut self, data: Data) -> Result<(), String>;
+
+    /// Returns the next message of the server received over the last connection.
+    async fn recv(&mut self) -> Result<ser::Msg, String>;
+}
+
+/// Runs all scenarios with the `client`, each against a scripted server of its own.
+pub async fn run_client_suite(client: &mut impl Client) -> Report {
+    let mut results = Vec::new();
+    macro_rules! scenario {
+        ($name:ident) => {
+            results.push((stringify!($name), $name(client).await))
+        };
+    }
+    scenario!(client_log_in);
+    scenario!(client_log_in_refused);
+    scenario!(client_sign_up_challenge);
+    scenario!(client_send);
+    scenario!(client_receive);
+    scenario!(client_big_frame);
+    scenario!(client_unknown_variant);
+    scenario!(client_disconnected);
+    Report { results }
+}
+
+/// Awaits the client's `future` for at most [RESPONSE_TIMEOUT].
+async fn within<T>(future: impl Future<Output = Result<T, String>>) -> Result<T, String> {
+    match timeout(RESPONSE_TIMEOUT, future).await {
+        Ok(res) => res,
+        Err(_) => Err("the client did not finish in time".to_string()),
+    }
+}
+
+/// Credentials the clients authenticate by.
+fn credentials() -> cli::Credentials {
+    cli::Credentials {
+        user: "conformance".to_string().into(),
+        password: "conformance-password".to_string(),
+    }
+}
+
+/// Scripted server side of a client scenario.
+struct Mock {
+    stream: TcpStream,
+    /// Codec of the messages after the authentication, the one the client offered.
+    codec: Codec,
+}
+impl Mock {
+    /// Listens on a port of its own.
+    async fn listen() -> Result<(TcpListener, SocketAddr), String> {
+        let listener = TcpListener::bind(("127.0.0.1", 0))
+            .await
+            .map_err(|e| format!("listening failed: {e}"))?;
+        let addr = listener
+            .local_addr()
+            .map_err(|e| format!("listening failed: {e}"))?;
+        Ok((listener, addr))
+    }
+
+    /// Accepts the client, answers its codec offer and checks it sends the `auth`.
+    async fn accept(listener: &TcpListener, auth: &cli::Auth) -> Result<Mock, String> {
+        let accepted = async { listener.accept().await.map_err(crate::Error::Connect) };
+        let (stream, _) = timed(accepted).await?;
+        let mut mock = Mock {
+            stream,
+            codec: Codec::default(),
+        };
+        loop {
+            match timed(cli::Msg::receive(&mut mock.stream)).await? {
+                cli::Msg::Codecs(offered) => {
+                    mock.codec = Codec::choose(&offered);
+                    timed(ser::Msg::CodecChosen(mock.codec).send(&mut mock.stream)).await?;
+                }
+                cli::Msg::Auth(got) if got == *auth => return Ok(mock),
+                other => return Err(format!("expected {auth:?}, got {other}")),
+            }
+        }
+    }
+
+    /// Accepts the client like [accept][Self::accept] and confirms its authentication.
+    async fn authenticated(listener: &TcpListener, auth: &cli::Auth) -> Result<Mock, String> {
+        let mut mock = Mock::accept(listener, auth).await?;
+        timed(ser::Msg::Authenticated.send(&mut mock.stream)).await?;
+        Ok(mock)
+    }
+
+    async fn send(&mut self, msg: ser::Msg) -> Outcome {
+        timed(msg.send_with(self.codec, &mut self.stream)).await
+    }
+
+    async fn receive(&mut self) -> Result<cli::Msg, String> {
+        timed(cli::Msg::receive_with(self.codec, &mut self.stream)).await
+    }
+}
+
+/// Connects the client to a scripted server, returns the server side once the client is logged in.
+async fn connected(client: &mut impl Client) -> Result<Mock, String> {
+    let (listener, addr) = Mock::listen().await?;
+    let auth = cli::Auth::LogIn(credentials());
+    let (mock, connected) = tokio::join!(
+        Mock::authenticated(&listener, &auth),
+        within(client.connect(addr, auth.clone()))
+    );
+    connected?;
+    mock
+}
+
+/// Text of the `from` user, as servers relay it.
+fn data_from(from: &str, data: Data) -> ser::Msg {
+    ser::Msg::DataFrom {
+        data,
+        from: from.to_string().into(),
+        msg_id: Some(1),
+        display_name: None,
+        mentions: vec![],
+        guest: false,
+        urgent: false,
+    }
+}
+
+/// The client receives the `msg` sent by the `mock`.
+async fn delivered(client: &mut impl Client, mock: &mut Mock, msg: ser::Msg) -> Outcome {
+    let (sent, received) = tokio::join!(mock.send(msg.clone()), within(client.recv()));
+    sent?;
+    expect(received?, msg)
+}
+
+async fn client_log_in(client: &mut impl Client) -> Outcome {
+    connected(client).await.map(|_| ())
+}
+
+async fn client_log_in_refused(client: &mut impl Client) -> Outcome {
+    let (listener, addr) = Mock::listen().await?;
+    let auth = cli::Auth::LogIn(credentials());
+    let refuse = async {
+        let mut mock = Mock::accept(&listener, &auth).await?;
+        timed(ser::Msg::from(ser::Error::WrongPassword).send(&mut mock.stream)).await
+    };
+    let (refused, connected) = tokio::join!(refuse, within(client.connect(addr, auth.clone())));
+    refused?;
+    match connected {
+        Ok(()) => Err("the client did not report the refusal".to_string()),
+        Err(_) => Ok(()),
+    }
+}
+
+async fn client_sign_up_challenge(client: &mut impl Client) -> Outcome {
+    const PREFIX: &str = "conformance";
+    const DIFFICULTY: u8 = 4;
+    let (listener, addr) = Mock::listen().await?;
+    let auth = cli::Auth::SignUp(credentials());
+    let challenged = async {
+        let mut mock = Mock::accept(&listener, &auth).await?;
+        let challenge = ser::Msg::Challenge {
+            prefix: PREFIX.to_string(),
+            difficulty: DIFFICULTY,
+        };
+        timed(challenge.send(&mut mock.stream)).await?;
+        match timed(cli::Msg::receive(&mut mock.stream)).await? {
+            cli::Msg::Auth(cli::Auth::Solution(nonce))
+                if challenge::verify(PREFIX, DIFFICULTY, nonce) => {}
+            other => return Err(format!("expected a solution of the challenge, got {other}")),
+        }
+        timed(ser::Msg::Authenticated.send(&mut mock.stream)).await
+    };
+    let (challenged, connected) =
+        tokio::join!(challenged, within(client.connect(addr, auth.clone())));
+    challenged?;
+    connected
+}
+
+async fn client_send(client: &mut impl Client) -> Outcome {
+    let mut mock = connected(client).await?;
+    let data = Data::Text("hello conformance".to_string());
+    within(client.send(data.clone())).await?;
+    // Clients may tag their messages, the id is up to them.
+    match mock.receive().await? {
+        cli::Msg::ToAll(got) if got == data => Ok(()),
+        other => Err(format!("expected the text to all, got {other}")),
+    }
+}
+
+async fn client_receive(client: &mut impl Client) -> Outcome {
+    let mut mock = connected(client).await?;
+    let text = Data::Text("hello conformance".to_string());
+    delivered(client, &mut mock, data_from("alice", text)).await
+}
+
+async fn client_big_frame(client: &mut impl Client) -> Outcome {
+    let mut mock = connected(client).await?;
+    let bytes = (0..BIG_FRAME_LEN).map(|i| i as u8).collect();
+    let file = File {
+        name: "big.bin".to_string(),
+        bytes,
+    };
+    delivered(client, &mut mock, data_from("alice", file.into())).await
+}
+
+/// Frame of a server message variant unknown to the client, encoded by the `codec`.
+fn unknown_variant_frame(codec: Codec) -> Vec<u8> {
+    match codec {
+        // bincode encodes enum variants as u32 indexes.
+        Codec::Bincode => u32::MAX.to_le_bytes().to_vec(),
+        // postcard as varints, this is u32::MAX.
+        #[cfg(feature = "postcard")]
+        Codec::Postcard => vec![0xff, 0xff, 0xff, 0xff, 0x0f],
+        // JSON as their names.
+        #[cfg(feature = "json")]
+        Codec::Json => b"\"Unknown\"".to_vec(),
+    }
+}
+
+/// Sends a frame with a message variant unknown to the client, the connection must survive it.
+async fn client_unknown_variant(client: &mut impl Client) -> Outcome {
+    let mut mock = connected(client).await?;
+    let unknown = unknown_variant_frame(mock.codec);
+    timed(write_bytes(&mut mock.stream, &unknown)).await?;
+    let text = data_from("alice", Data::Text("still there".to_string()));
+    let (sent, received) = tokio::join!(mock.send(text.clone()), async {
+        // The unknown one may be reported, the next message must be received all the same.
+        match within(client.recv()).await {
+            Err(_) => within(client.recv()).await,
+            received => received,
+        }
+    });
+    sent?;
+    expect(received?, text)
+}
+
+/// The server closes the connection, the client must tell instead of waiting.
+async fn client_disconnected(client: &mut impl Client) -> Outcome {
+    let mock = connected(client).await?;
+    drop(mock);
+    match timeout(RESPONSE_TIMEOUT, client.recv()).await {
+        Ok(Err(_)) => Ok(()),
+        Ok(Ok(msg)) => Err(format!("expected the disconnection, got {msg}")),
+        Err(_) => Err("the client did not notice the closed connection".to_string()),
+    }
+}
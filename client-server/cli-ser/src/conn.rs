@@ -0,0 +1,208 @@
+//! Client side of a connection to the server, see [Connection].
+use std::net::SocketAddr;
+
+use tokio::net::{
+    tcp::{OwnedReadHalf, OwnedWriteHalf},
+    TcpStream,
+};
+
+use crate::{
+    challenge, cli,
+    retry::{deadline, receive_with_timeout, TIMEOUT_DEFAULT},
+    rt::{Executor, Runtime},
+    ser, Codec, ConnectionStats, Counted, Data,
+    Error::*,
+    Messageable, Result, User,
+};
+
+/// Authenticated connection to the server.
+///
+/// ```no_run
+/// # async fn chat() -> Result<(), cli_ser::Error> {
+/// use cli_ser::{cli::Credentials, conn::Connection, Data};
+///
+/// let creds = Credentials {
+///     user: "alice".to_string().into(),
+///     password: "secret".to_string(),
+/// };
+/// let mut conn = Connection::connect(([127, 0, 0, 1], 11111), creds).await?;
+/// conn.send(Data::Text("hi".to_string())).await?;
+/// println!("{}", conn.recv().await?);
+/// # Ok(())
+/// # }
+/// ```
+pub struct Connection {
+    reader: Reader,
+    writer: Writer,
+}
+impl Connection {
+    /// Connects to the server at `addr` and logs in with the `creds`.
+    pub async fn connect(addr: impl Into<SocketAddr>, creds: cli::Credentials) -> Result<Self> {
+        Self::authenticate(addr.into(), cli::Auth::LogIn(creds), None).await
+    }
+
+    /// Connects to the server at `addr`, logs in with the `creds` and gives the two-factor `code`
+    /// when the server asks for it, see [cli::Auth::TotpCode].
+    pub async fn connect_with_totp(
+        addr: impl Into<SocketAddr>,
+        creds: cli::Credentials,
+        code: impl Into<String>,
+    ) -> Result<Self> {
+        Self::authenticate(addr.into(), cli::Auth::LogIn(creds), Some(code.into())).await
+    }
+
+    /// Connects to the server at `addr` and signs up with the `creds`,
+    /// the [challenge][ser::Msg::Challenge] the server may ask for is solved on a blocking thread.
+    pub async fn sign_up(addr: impl Into<SocketAddr>, creds: cli::Credentials) -> Result<Self> {
+        Self::authenticate(addr.into(), cli::Auth::SignUp(creds), None).await
+    }
+
+    /// Connects to the server at `addr` and signs up with the `creds` and the `invite` code,
+    /// see [cli::Auth::SignUpInvited].
+    pub async fn sign_up_invited(
+        addr: impl Into<SocketAddr>,
+        creds: cli::Credentials,
+        invite: impl Into<String>,
+    ) -> Result<Self> {
+        let auth = cli::Auth::SignUpInvited {
+            credentials: creds,
+            invite: invite.into(),
+        };
+        Self::authenticate(addr.into(), auth, None).await
+    }
+
+    /// Connects to the server at `addr` and logs in with the bearer `token`, see [cli::Auth::OidcToken].
+    ///
+    /// The first log-in of its subject signs up, the challenge is solved as by [sign_up][Self::sign_up].
+    pub async fn connect_with_token(
+        addr: impl Into<SocketAddr>,
+        token: impl Into<String>,
+    ) -> Result<Self> {
+        Self::authenticate(addr.into(), cli::Auth::OidcToken(token.into()), None).await
+    }
+
+    /// Connects to the server at `addr` and joins as a guest of the `name`, see [cli::Auth::Guest].
+    pub async fn guest(addr: impl Into<SocketAddr>, name: impl Into<User>) -> Result<Self> {
+        Self::authenticate(addr.into(), cli::Auth::Guest(name.into()), None).await
+    }
+
+    /// Sends the `auth` and waits until the server confirms it, errors of the server are returned as [Authentication].
+    ///
+    /// The `totp` code is sent when the server requires it, without one it is [ser::Error::InvalidTotpCode].
+    /// Connecting and each answer of the server take [TIMEOUT_DEFAULT] at most, it is [TimedOut] then.
+    ///
+    /// Builds with more than one [Codec] offer them all first, see [cli::Msg::Codecs],
+    /// the connection uses the one the server chose. Others use bincode and offer nothing,
+    /// so that they connect to servers which do not negotiate.
+    async fn authenticate(
+        addr: SocketAddr,
+        auth: cli::Auth,
+        mut totp: Option<String>,
+    ) -> Result<Self> {
+        let connect = async { TcpStream::connect(addr).await.map_err(Connect) };
+        let stats = ConnectionStats::new();
+        let mut stream = stats.count(deadline(TIMEOUT_DEFAULT, connect).await?);
+        if Codec::ALL.len() > 1 {
+            let ids = Codec::ALL.iter().map(|codec| codec.id()).collect();
+            cli::Msg::Codecs(ids).send(&mut stream).await?;
+        }
+        cli::Msg::Auth(auth).send(&mut stream).await?;
+        let mut codec = Codec::default();
+        loop {
+            match receive_with_timeout(&mut stream, TIMEOUT_DEFAULT).await? {
+                ser::Msg::Authenticated => break,
+                ser::Msg::CodecChosen(chosen) => codec = chosen,
+                ser::Msg::TotpRequired => match totp.take() {
+                    Some(code) => {
+                        let msg = cli::Msg::Auth(cli::Auth::TotpCode(code));
+                        msg.send(&mut stream).await?;
+                    }
+                    None => return Err(Authentication(ser::Error::InvalidTotpCode)),
+                },
+                ser::Msg::Challenge { prefix, difficulty } => {
+                    let solve =
+                        Runtime::spawn_blocking(move || challenge::solve(&prefix, difficulty));
+                    match solve.await {
+                        Some(nonce) => {
+                            let msg = cli::Msg::Auth(cli::Auth::Solution(nonce));
+                            msg.send(&mut stream).await?;
+                        }
+                        None => return Err(Authentication(ser::Error::ChallengeFailed)),
+                    }
+                }
+                ser::Msg::Error(e) | ser::Msg::Rejected(_, e) => return Err(Authentication(e)),
+                // Nothing else is sent before the authentication.
+                _ => continue,
+            }
+        }
+        // Nothing is in flight after the authentication, the halves count on from whole frames.
+        let (reader, writer) = stream.into_inner().into_split();
+        Ok(Connection {
+            reader: Reader(stats.count(reader), codec),
+            writer: Writer(stats.count(writer), codec),
+        })
+    }
+
+    /// Codec of the messages after the authentication, the one the server chose.
+    pub fn codec(&self) -> Codec {
+        self.reader.1
+    }
+
+    /// Sends the `data` to everyone.
+    pub async fn send(&mut self, data: Data) -> Result<()> {
+        self.writer.send(data).await
+    }
+
+    /// Sends any client message, e.g. a [tagged][cli::Msg::tagged] one.
+    pub async fn send_msg(&mut self, msg: impl Into<cli::Envelope>) -> Result<()> {
+        self.writer.send_msg(msg).await
+    }
+
+    /// Receives the next message of the server.
+    pub async fn recv(&mut self) -> Result<ser::Msg> {
+        self.reader.recv().await
+    }
+
+    /// Traffic of the connection, the authentication included, shared by its halves.
+    pub fn stats(&self) -> &ConnectionStats {
+        self.reader.stats()
+    }
+
+    /// Splits the connection, so that it can be read and written by different tasks.
+    pub fn split(self) -> (Reader, Writer) {
+        (self.reader, self.writer)
+    }
+}
+
+/// Receiving half of a [Connection].
+pub struct Reader(Counted<OwnedReadHalf>, Codec);
+impl Reader {
+    /// Traffic of the whole connection.
+    pub fn stats(&self) -> &ConnectionStats {
+        self.0.stats()
+    }
+
+    /// Receives the next message of the server.
+    pub async fn recv(&mut self) -> Result<ser::Msg> {
+        ser::Msg::receive_with(self.1, &mut self.0).await
+    }
+}
+
+/// Sending half of a [Connection].
+pub struct Writer(Counted<OwnedWriteHalf>, Codec);
+impl Writer {
+    /// Traffic of the whole connection.
+    pub fn stats(&self) -> &ConnectionStats {
+        self.0.stats()
+    }
+
+    /// Sends the `data` to everyone.
+    pub async fn send(&mut self, data: Data) -> Result<()> {
+        self.send_msg(cli::Msg::ToAll(data)).await
+    }
+
+    /// Sends any client message, e.g. a [tagged][cli::Msg::tagged] one.
+    pub async fn send_msg(&mut self, msg: impl Into<cli::Envelope>) -> Result<()> {
+        msg.into().send_with(self.1, &mut self.0).await
+    }
+}
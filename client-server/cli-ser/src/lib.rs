@@ -1,51 +1,201 @@
 //! Client-Server
 //!
 //! Foundations for communication between a client and a server.
+//!
+//! Runs on tokio by default, other runtimes are supported via features, see [rt].
+//!
+//! With tokio, [Connection] connects and authenticates to the server in one call.
+//!
+//! ## Protocol
+//!
+//! Clients send [cli::Msg]s, the server answers with [ser::Msg]s, both are [Message]s.
+//! Every message travels in a frame, the length of the encoded message (a big-endian `u32`)
+//! followed by the message [encoded][Codec]. Errors of the server carry stable codes, see [WireError].
+//!
+//! ## Versioning
+//!
+//! The crate follows [semantic versioning](https://semver.org), the API documented here is all there is,
+//! changes are listed in `CHANGELOG.md`.
+#![deny(missing_docs)]
 // TODO: buffered read and write <https://tokio.rs/tokio/tutorial/framing>
 // TODO: <https://docs.rs/futures> combinators for read_bytes and write_bytes
 // TODO: check if [async_trait] can be removed since rust 1.75 (warnings)
 
 use std::{
     fmt::{self, Display},
-    io::Cursor,
+    io::{self, Cursor},
+    marker::Unpin,
+    net::SocketAddr,
     path::{Path, PathBuf},
     result,
+    time::Duration,
 };
 
 use async_trait::async_trait;
-use chrono::{offset::Utc, SecondsFormat};
-use image::ImageFormat;
+use chrono::{
+    offset::{Local, Utc},
+    SecondsFormat,
+};
 use serde::{Deserialize, Serialize};
-use tokio::{
-    fs,
-    io::{self, AsyncReadExt, AsyncWriteExt, ErrorKind},
+
+use crate::{
+    rt::{fs, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    Error::*,
 };
 
-use crate::Error::*;
+#[cfg(feature = "proptest")]
+mod arbitrary;
+pub mod challenge;
+mod code;
+#[cfg(feature = "conformance")]
+pub mod conformance;
+#[cfg(feature = "tokio")]
+pub mod conn;
+pub mod retry;
+pub mod rt;
+mod stats;
 
-type Result<T> = result::Result<T, Error>;
+/// Re-exported, [File]s, [Image]s and [Media] hold their bytes in it, clones share them.
+pub use bytes::Bytes;
+pub use code::{ErrorCode, ErrorKind};
+#[cfg(feature = "tokio")]
+pub use conn::Connection;
+/// Re-exported for specifying image conversions, see [Image::save_as].
+pub use image::{ImageFormat, ImageOutputFormat};
+pub use ser::Error as WireError;
+pub use stats::{ConnectionStats, Counted, Traffic};
+pub use Messageable as Message;
+
+/// Result of the [cli-ser][self] operations.
+pub type Result<T> = result::Result<T, Error>;
 
 /// [cli-ser][self] errors, provides a brief explanation and access to the underlying source error.
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
+    /// Reading from the stream failed.
     #[error("receiving bytes from the stream failed")]
     ReceiveBytes(io::Error),
+    /// The other side closed the stream.
     #[error("the stream was disconnected")]
     DisconnectedStream(io::Error),
+    /// Writing to the stream failed.
     #[error("sending bytes over the stream failed")]
     SendBytes(io::Error),
+    /// The server could not be reached, see [Connection].
+    #[error("connecting to the server failed")]
+    Connect(io::Error),
+    /// The other side did not answer in time, see [retry::deadline].
+    #[error("no answer within {0:?}")]
+    TimedOut(Duration),
+    /// The server answered the authentication with the error, see [Connection].
+    #[error("the server did not authenticate the user: {0}")]
+    Authentication(ser::Error),
+    /// The message could not be encoded.
     #[error("message serialization failed")]
     SerializeMsg(bincode::Error),
+    /// The bytes are not a message of the expected type.
     #[error("deserialization of the message failed")]
     DeserializeMsg(bincode::Error),
+    /// The message could not be encoded by [Codec::Postcard].
+    #[cfg(feature = "postcard")]
+    #[error("message serialization (postcard) failed")]
+    SerializePostcard(postcard::Error),
+    /// The bytes are not a message of the expected type encoded by [Codec::Postcard].
+    #[cfg(feature = "postcard")]
+    #[error("deserialization of the message (postcard) failed")]
+    DeserializePostcard(postcard::Error),
+    /// The message could not be encoded by [Codec::Json].
+    #[cfg(feature = "json")]
+    #[error("message serialization (JSON) failed")]
+    SerializeJson(serde_json::Error),
+    /// The bytes are not a message of the expected type encoded by [Codec::Json].
+    #[cfg(feature = "json")]
+    #[error("deserialization of the message (JSON) failed")]
+    DeserializeJson(serde_json::Error),
+    /// The file at the path could not be read.
     #[error("loading file for a given path failed")]
     LoadFile(io::Error),
+    /// The file could not be written.
     #[error("saving the file failed")]
     SaveFile(io::Error),
+    /// The bytes are not an image of a known format.
     #[error("decoding the image failed")]
     DecodeImg(image::error::ImageError),
+    /// The frame is longer than [MAX_FRAME_SIZE], its length is told, nothing of it was read or written.
+    #[error("the frame of {0} bytes exceeds the maximum of {MAX_FRAME_SIZE} bytes")]
+    FrameTooLarge(usize),
+    /// Encoding the image to the other format failed.
     #[error("converting image to another type failed")]
     ConvertImg(image::error::ImageError),
+    /// The image exceeds the [ImageLimits], the text says which one.
+    #[error("the image is too large, {0}")]
+    ImageTooLarge(String),
+    /// The bytes are not an animation or a video of a known format, see [Media::from_path].
+    #[error("the media is not a GIF, MP4, QuickTime or WebM")]
+    UnsupportedMedia,
+    /// The output format has no file format to be saved as.
+    #[error("the image can not be saved in the requested format")]
+    UnsupportedFormat(ImageOutputFormat),
+    /// The text is not an image format, see [parse_image_format], or dimensions, see [parse_dimensions].
+    #[error("parsing the image format failed: {0}")]
+    ParseImageFormat(String),
+}
+impl Error {
+    /// Code of the error, see [ErrorCode], the one of the server's error for [Authentication].
+    pub fn code(&self) -> ErrorCode {
+        let code = match self {
+            ReceiveBytes(_) => 1001,
+            DisconnectedStream(_) => 1002,
+            SendBytes(_) => 1003,
+            Connect(_) => 1004,
+            LoadFile(_) => 1005,
+            SaveFile(_) => 1006,
+            TimedOut(_) => 1007,
+            SerializeMsg(_) => 2001,
+            DeserializeMsg(_) => 2002,
+            #[cfg(feature = "postcard")]
+            SerializePostcard(_) => 2003,
+            #[cfg(feature = "postcard")]
+            DeserializePostcard(_) => 2004,
+            #[cfg(feature = "json")]
+            SerializeJson(_) => 2005,
+            #[cfg(feature = "json")]
+            DeserializeJson(_) => 2006,
+            FrameTooLarge(_) => 2007,
+            DecodeImg(_) => 3001,
+            ConvertImg(_) => 3002,
+            ImageTooLarge(_) => 3003,
+            UnsupportedMedia => 3004,
+            UnsupportedFormat(_) => 3005,
+            ParseImageFormat(_) => 3006,
+            Authentication(error) => return error.code(),
+        };
+        ErrorCode(code)
+    }
+
+    /// Kind of the error, by its variant, the one of the server's error for [Authentication].
+    ///
+    /// It is the kind of the range of its [code][Self::code] as well.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            ReceiveBytes(_)
+            | DisconnectedStream(_)
+            | SendBytes(_)
+            | Connect(_)
+            | LoadFile(_)
+            | SaveFile(_)
+            | TimedOut(_) => ErrorKind::Io,
+            SerializeMsg(_) | DeserializeMsg(_) | FrameTooLarge(_) => ErrorKind::Protocol,
+            #[cfg(feature = "postcard")]
+            SerializePostcard(_) | DeserializePostcard(_) => ErrorKind::Protocol,
+            #[cfg(feature = "json")]
+            SerializeJson(_) | DeserializeJson(_) => ErrorKind::Protocol,
+            DecodeImg(_) | ConvertImg(_) | ImageTooLarge(_) | UnsupportedMedia
+            | UnsupportedFormat(_) | ParseImageFormat(_) => ErrorKind::Data,
+            Authentication(error) => error.kind(),
+        }
+    }
 }
 
 /// Remote definition of image::ImageFormat for de/serialization.
@@ -72,80 +222,430 @@ enum ImageFormatDef {
     Qoi,
 }
 
-/// An image type, can be [loaded from a path][Self::from_path] (with a validity check) and [saved to a path][Self::save] (optionally [as PNG][Self::save_as_png]).
+/// An image type, can be [loaded from a path][Self::from_path] (with a validity check) and [saved to a path][Self::save] (optionally [converted][Self::save_as]).
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Image {
     #[serde(with = "ImageFormatDef")]
     format: ImageFormat,
-    bytes: Vec<u8>,
+    bytes: Bytes,
 }
 impl Image {
-    /// Creates Image from the bytes read at the `path`.
+    /// Creates Image from the bytes read at the `path`, the [default limits][ImageLimits::default] apply.
     ///
     /// Guesses the image format based on the data or the path.
     ///
     /// Decodes the image in order to check the validity.
     pub async fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_path_with_limits(path, &ImageLimits::default()).await
+    }
+
+    /// Creates Image from the bytes read at the `path`, images exceeding the `limits` are [ImageTooLarge].
+    ///
+    /// The limits are [checked][Self::check] before the image is decoded.
+    pub async fn from_path_with_limits(
+        path: impl AsRef<Path>,
+        limits: &ImageLimits,
+    ) -> Result<Self> {
         let bytes = fs::read(&path).await.map_err(LoadFile)?;
         let format = image::guess_format(&bytes)
             .or_else(|_| image::ImageFormat::from_path(path))
             .map_err(DecodeImg)?;
-        image::io::Reader::with_format(Cursor::new(&bytes), format)
-            .decode()
-            .map_err(DecodeImg)?;
-        Ok(Image { format, bytes })
+        Image {
+            format,
+            bytes: bytes.into(),
+        }
+        .validated(limits)
+    }
+
+    /// Creates Image from the `bytes` like [Self::from_path] does, without the file system.
+    ///
+    /// The format is guessed from the bytes only, the [default limits][ImageLimits::default] apply.
+    pub fn from_bytes(bytes: impl Into<Bytes>) -> Result<Self> {
+        Self::from_bytes_with_limits(bytes, &ImageLimits::default())
+    }
+
+    /// Creates Image from the `bytes` like [Self::from_path_with_limits] does, without the file system.
+    ///
+    /// The format is guessed from the bytes only.
+    pub fn from_bytes_with_limits(bytes: impl Into<Bytes>, limits: &ImageLimits) -> Result<Self> {
+        let bytes = bytes.into();
+        let format = image::guess_format(&bytes).map_err(DecodeImg)?;
+        Image { format, bytes }.validated(limits)
+    }
+
+    /// Checks the image against the `limits`, then decodes it to check the validity.
+    fn validated(self, limits: &ImageLimits) -> Result<Self> {
+        self.check(limits)?;
+        let mut reader = image::io::Reader::with_format(Cursor::new(&self.bytes), self.format);
+        reader.limits(limits.decoding());
+        reader.decode().map_err(DecodeImg)?;
+        Ok(self)
+    }
+
+    /// Checks the image against the `limits` without decoding it, only the header is read.
+    ///
+    /// Meant for images of others, e.g. received by the server, before they are decoded or passed on.
+    pub fn check(&self, limits: &ImageLimits) -> Result<()> {
+        if self.bytes.len() > limits.max_bytes {
+            return Err(ImageTooLarge(format!(
+                "{} bytes exceed {}",
+                self.bytes.len(),
+                limits.max_bytes
+            )));
+        }
+        let (width, height) = self.dimensions()?;
+        if width > limits.max_width || height > limits.max_height {
+            return Err(ImageTooLarge(format!(
+                "{width}x{height} pixels exceed {}x{}",
+                limits.max_width, limits.max_height
+            )));
+        }
+        let pixels = u64::from(width) * u64::from(height);
+        if pixels > limits.max_pixels {
+            return Err(ImageTooLarge(format!(
+                "{pixels} pixels exceed {}",
+                limits.max_pixels
+            )));
+        }
+        Ok(())
     }
 
-    /// Saves the image to a new path based on the given `dir` and current time.
+    /// Saves the image to a new path based on the given `dir` and current UTC time.
+    ///
+    /// An existing file is never replaced, the name of a later image gets a number, e.g. `... (1).png`.
     pub async fn save(&self, dir: &Path) -> Result<PathBuf> {
-        let path = Self::create_path(dir, self.format);
-        create_file_and_write_bytes(&path, &self.bytes)
+        self.save_with(dir, Clock::Utc).await
+    }
+
+    /// Saves the image like [Self::save], the name is the current time of the `clock`.
+    pub async fn save_with(&self, dir: &Path, clock: Clock) -> Result<PathBuf> {
+        let (mut file, path) = create_new_file(Self::create_path(dir, self.format, clock))
             .await
-            .map(|_| path)
-            .map_err(SaveFile)
+            .map_err(SaveFile)?;
+        file.write_all(&self.bytes).await.map_err(SaveFile)?;
+        file.flush().await.map_err(SaveFile)?;
+        Ok(path)
     }
 
     /// Converts the image to the PNG format and saves it to a new path based on the given `dir` and current time.
     pub async fn save_as_png(self, dir: &Path) -> Result<PathBuf> {
-        if self.format != ImageFormat::Png {
-            let mut bytes = Vec::<u8>::new();
-            let img = image::io::Reader::with_format(Cursor::new(self.bytes), self.format)
-                .decode()
-                .map_err(DecodeImg)?;
-            img.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
-                .map_err(ConvertImg)?;
-            let path = Self::create_path(dir, ImageFormat::Png);
-            create_file_and_write_bytes(&path, &bytes)
-                .await
-                .map(|_| path)
-                .map_err(SaveFile)
-        } else {
-            self.save(dir).await
+        self.save_as(dir, ImageFormat::Png).await
+    }
+
+    /// Converts the image to the `format` and saves it to a new path based on the given `dir` and current time.
+    ///
+    /// Encoding parameters are taken from the [ImageOutputFormat], e.g. JPEG quality via [ImageOutputFormat::Jpeg].
+    /// The image is re-encoded only when the requested output differs from its current format.
+    pub async fn save_as(
+        self,
+        dir: &Path,
+        format: impl Into<ImageOutputFormat>,
+    ) -> Result<PathBuf> {
+        self.save_as_with(dir, format, Clock::Utc).await
+    }
+
+    /// Converts and saves the image like [Self::save_as], the name is the current time of the `clock`.
+    pub async fn save_as_with(
+        self,
+        dir: &Path,
+        format: impl Into<ImageOutputFormat>,
+        clock: Clock,
+    ) -> Result<PathBuf> {
+        let output = format.into();
+        if output == ImageOutputFormat::from(self.format) {
+            return self.save_with(dir, clock).await;
         }
+        self.convert(output)?.save_with(dir, clock).await
+    }
+
+    /// Re-encodes the image to the `format`, e.g. to reduce its [size][Self::size].
+    ///
+    /// Decoding and encoding is CPU heavy, consider running it on a blocking thread.
+    pub fn convert(self, format: impl Into<ImageOutputFormat>) -> Result<Image> {
+        let output = format.into();
+        let Some(target) = output_to_image_format(&output) else {
+            return Err(UnsupportedFormat(output));
+        };
+        let mut bytes = Vec::<u8>::new();
+        let img = image::io::Reader::with_format(Cursor::new(self.bytes), self.format)
+            .decode()
+            .map_err(DecodeImg)?;
+        img.write_to(&mut Cursor::new(&mut bytes), output)
+            .map_err(ConvertImg)?;
+        Ok(Image {
+            format: target,
+            bytes: bytes.into(),
+        })
+    }
+
+    /// Re-encodes the image upright and without metadata, so that e.g. GPS position or the camera model is not sent.
+    ///
+    /// The EXIF orientation of JPEG images is applied to the pixels (the metadata is dropped by the re-encoding).
+    /// The format is kept when it can be encoded, otherwise the image becomes a PNG.
+    /// Decoding and encoding is CPU heavy, consider running it on a blocking thread.
+    pub fn normalized(self) -> Result<Image> {
+        let orientation = match self.format {
+            ImageFormat::Jpeg => jpeg_orientation(&self.bytes),
+            _ => None,
+        };
+        let img = image::io::Reader::with_format(Cursor::new(&self.bytes), self.format)
+            .decode()
+            .map_err(DecodeImg)?;
+        let img = match orientation {
+            Some(2) => img.fliph(),
+            Some(3) => img.rotate180(),
+            Some(4) => img.flipv(),
+            Some(5) => img.rotate90().fliph(),
+            Some(6) => img.rotate90(),
+            Some(7) => img.rotate270().fliph(),
+            Some(8) => img.rotate270(),
+            _ => img,
+        };
+        let output = match ImageOutputFormat::from(self.format) {
+            ImageOutputFormat::Unsupported(_) => ImageOutputFormat::Png,
+            output => output,
+        };
+        let Some(format) = output_to_image_format(&output) else {
+            return Err(UnsupportedFormat(output));
+        };
+        let mut bytes = Vec::<u8>::new();
+        img.write_to(&mut Cursor::new(&mut bytes), output)
+            .map_err(ConvertImg)?;
+        Ok(Image {
+            format,
+            bytes: bytes.into(),
+        })
     }
 
-    fn create_path(dir: &Path, format: ImageFormat) -> PathBuf {
-        dir.join(format!(
-            "{}.{}",
-            Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+    /// Returns the size of the encoded image in bytes.
+    pub fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Returns the format the image is encoded in.
+    pub fn format(&self) -> ImageFormat {
+        self.format
+    }
+
+    /// Returns the width and height in pixels, only the header is read, the image is not decoded.
+    pub fn dimensions(&self) -> Result<(u32, u32)> {
+        image::io::Reader::with_format(Cursor::new(&self.bytes), self.format)
+            .into_dimensions()
+            .map_err(DecodeImg)
+    }
+
+    fn create_path(dir: &Path, format: ImageFormat, clock: Clock) -> PathBuf {
+        let time = match clock {
+            Clock::Utc => Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            Clock::Local => Local::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        };
+        dir.join(sanitize_file_name(&format!(
+            "{time}.{}",
             // It's safe: <https://docs.rs/image/latest/src/image/image.rs.html#290-309>.
             format.extensions_str()[0]
-        ))
+        )))
     }
 }
-impl From<Image> for Vec<u8> {
+
+/// Time the names of [saved images][Image::save_with] are based on.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Clock {
+    /// Coordinated universal time, e.g. `2024-01-31T18-00-00Z.png`.
+    #[default]
+    Utc,
+    /// Time of the local time zone with its offset, e.g. `2024-01-31T19-00-00+01-00.png`.
+    Local,
+}
+impl std::str::FromStr for Clock {
+    type Err = String;
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s {
+            "utc" => Ok(Clock::Utc),
+            "local" => Ok(Clock::Local),
+            _ => Err(format!("unknown clock `{s}`, expected `utc` or `local`")),
+        }
+    }
+}
+
+/// Limits of [images][Image] protecting from decompression bombs, small files decoding to huge bitmaps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageLimits {
+    /// Size of the encoded image in bytes.
+    pub max_bytes: usize,
+    /// Width in pixels.
+    pub max_width: u32,
+    /// Height in pixels.
+    pub max_height: u32,
+    /// Width times height.
+    pub max_pixels: u64,
+}
+impl ImageLimits {
+    /// Limits of the image crate's decoders.
+    fn decoding(&self) -> image::io::Limits {
+        let mut limits = image::io::Limits::default();
+        limits.max_image_width = Some(self.max_width);
+        limits.max_image_height = Some(self.max_height);
+        limits
+    }
+}
+impl Default for ImageLimits {
+    /// 32 MiB, 16384 pixels wide and high, 64 megapixels in total.
+    fn default() -> Self {
+        ImageLimits {
+            max_bytes: 32 * 1024 * 1024,
+            max_width: 16384,
+            max_height: 16384,
+            max_pixels: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// Parses image dimensions given as `<WIDTH>x<HEIGHT>`, e.g. `8192x8192`.
+pub fn parse_dimensions(s: &str) -> Result<(u32, u32)> {
+    s.split_once('x')
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+        .ok_or_else(|| ParseImageFormat(format!("\"{s}\" are not dimensions like 8192x8192")))
+}
+
+/// Parses an image format given by its extension, e.g. `png`, `webp` or `jpeg:80` (JPEG with quality 80).
+pub fn parse_image_format(s: &str) -> Result<ImageOutputFormat> {
+    let (ext, quality) = match s.split_once(':') {
+        Some((ext, quality)) => (ext, Some(quality)),
+        None => (s, None),
+    };
+    let format = ImageFormat::from_extension(ext)
+        .ok_or_else(|| ParseImageFormat(format!("\"{ext}\" is not a known image format")))?;
+    match (format, quality) {
+        (ImageFormat::Jpeg, Some(quality)) => quality
+            .parse::<u8>()
+            .ok()
+            .filter(|q| (1..=100).contains(q))
+            .map(ImageOutputFormat::Jpeg)
+            .ok_or_else(|| {
+                ParseImageFormat(format!("JPEG quality \"{quality}\" is not in 1..=100"))
+            }),
+        (_, Some(_)) => Err(ParseImageFormat(
+            "only JPEG supports the quality parameter".to_string(),
+        )),
+        (format, None) => match ImageOutputFormat::from(format) {
+            ImageOutputFormat::Unsupported(msg) => Err(ParseImageFormat(msg)),
+            output => Ok(output),
+        },
+    }
+}
+
+/// Maps the encoding format to the image format (used e.g. for the file extension).
+fn output_to_image_format(output: &ImageOutputFormat) -> Option<ImageFormat> {
+    match output {
+        ImageOutputFormat::Png => Some(ImageFormat::Png),
+        ImageOutputFormat::Jpeg(_) => Some(ImageFormat::Jpeg),
+        ImageOutputFormat::Pnm(_) => Some(ImageFormat::Pnm),
+        ImageOutputFormat::Gif => Some(ImageFormat::Gif),
+        ImageOutputFormat::Ico => Some(ImageFormat::Ico),
+        ImageOutputFormat::Bmp => Some(ImageFormat::Bmp),
+        ImageOutputFormat::Farbfeld => Some(ImageFormat::Farbfeld),
+        ImageOutputFormat::Tga => Some(ImageFormat::Tga),
+        ImageOutputFormat::OpenExr => Some(ImageFormat::OpenExr),
+        ImageOutputFormat::Tiff => Some(ImageFormat::Tiff),
+        ImageOutputFormat::Qoi => Some(ImageFormat::Qoi),
+        ImageOutputFormat::WebP => Some(ImageFormat::WebP),
+        _ => None,
+    }
+}
+
+/// Finds the EXIF orientation (1 to 8) of the JPEG, the image crate does not read it.
+///
+/// The orientation is a tag of the first IFD of the TIFF structure in the APP1 "Exif" segment.
+fn jpeg_orientation(jpeg: &[u8]) -> Option<u16> {
+    let mut rest = jpeg.strip_prefix(&[0xFF, 0xD8])?;
+    let tiff = loop {
+        let [0xFF, marker, len_hi, len_lo, ..] = *rest else {
+            return None;
+        };
+        // Start of the scan, no more metadata follows.
+        if marker == 0xDA {
+            return None;
+        }
+        let len = usize::from(u16::from_be_bytes([len_hi, len_lo]));
+        let segment = rest.get(4..2 + len)?;
+        if marker == 0xE1 {
+            if let Some(tiff) = segment.strip_prefix(b"Exif\0\0") {
+                break tiff;
+            }
+        }
+        rest = &rest[2 + len..];
+    };
+    let big_endian = match tiff.get(..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let u16_at = |at: usize| {
+        let bytes = [*tiff.get(at)?, *tiff.get(at + 1)?];
+        Some(match big_endian {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        })
+    };
+    let u32_at = |at: usize| {
+        let bytes = tiff.get(at..at + 4)?.try_into().ok()?;
+        Some(match big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        })
+    };
+    let ifd = usize::try_from(u32_at(4)?).ok()?;
+    (0..usize::from(u16_at(ifd)?))
+        .map(|i| ifd + 2 + 12 * i)
+        .find(|&entry| u16_at(entry) == Some(0x0112))
+        .and_then(|entry| u16_at(entry + 8))
+}
+
+impl From<Image> for Bytes {
     fn from(img: Image) -> Self {
         img.bytes
     }
 }
+impl From<Image> for Vec<u8> {
+    /// Copies the bytes unless the image is their only holder.
+    fn from(img: Image) -> Self {
+        img.bytes.into()
+    }
+}
+impl TryFrom<Bytes> for Image {
+    type Error = Error;
+
+    /// Guesses the format from the bytes, they are not decoded.
+    fn try_from(bytes: Bytes) -> Result<Self> {
+        let format = image::guess_format(&bytes).map_err(DecodeImg)?;
+        Ok(Image { format, bytes })
+    }
+}
+impl TryFrom<Vec<u8>> for Image {
+    type Error = Error;
+
+    /// Guesses the format from the bytes, they are not decoded.
+    fn try_from(bytes: Vec<u8>) -> Result<Self> {
+        Image::try_from(Bytes::from(bytes))
+    }
+}
 
 /// A file type, can be [read from a path][Self::from_path] and [saved to a path][Self::save].
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct File {
     name: String,
-    bytes: Vec<u8>,
+    bytes: Bytes,
 }
 impl File {
+    /// Makes the file of the `bytes` named `name`, e.g. of generated or pasted data.
+    pub fn new(name: impl Into<String>, bytes: impl Into<Bytes>) -> Self {
+        File {
+            name: name.into(),
+            bytes: bytes.into(),
+        }
+    }
+
     /// Reads a file from the `path`, the filename can change if it contained non-unicode symbols.
     pub async fn from_path(path: impl AsRef<Path>) -> Result<Self> {
         let mut bytes = Vec::new();
@@ -156,7 +656,10 @@ impl File {
             Some(os_str) => os_str.to_string_lossy().into_owned(),
             None => "unknown".to_string(),
         };
-        Ok(File { name, bytes })
+        Ok(File {
+            name,
+            bytes: bytes.into(),
+        })
     }
 
     /// Returns the unicode version of the filename.
@@ -164,33 +667,402 @@ impl File {
         &self.name
     }
 
-    /// Saves the file to the `path` under its [name][Self::name].
-    pub async fn save(&self, path: impl AsRef<Path>) -> Result<()> {
-        create_file_and_write_bytes(path.as_ref().join(&self.name), &self.bytes)
+    /// Returns the size in bytes.
+    pub fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Returns the size in bytes, the same as [Self::size].
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Whether the file has no bytes.
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Returns the content, clones of it share the bytes.
+    pub fn bytes(&self) -> &Bytes {
+        &self.bytes
+    }
+
+    /// Saves the file to the `dir` under its [sanitized name][sanitize_file_name], returns the path used.
+    ///
+    /// When the file already exists, the `policy` decides whether it is replaced
+    /// or the name gets a number, e.g. `notes (1).txt`.
+    ///
+    /// Big files can be saved while they arrive, see [ser::Msg::receive_saving_files].
+    pub async fn save(&self, dir: impl AsRef<Path>, policy: OnCollision) -> Result<PathBuf> {
+        let (mut file, path) = create_file(dir.as_ref(), &self.name, policy)
             .await
-            .map_err(SaveFile)
+            .map_err(SaveFile)?;
+        file.write_all(&self.bytes).await.map_err(SaveFile)?;
+        file.flush().await.map_err(SaveFile)?;
+        Ok(path)
+    }
+}
+
+/// What happens when a received file is saved where a file already exists.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnCollision {
+    /// The name gets the first free number, e.g. `notes (2).txt`.
+    #[default]
+    Rename,
+    /// The existing file is replaced.
+    Overwrite,
+}
+impl std::str::FromStr for OnCollision {
+    type Err = String;
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s {
+            "rename" => Ok(OnCollision::Rename),
+            "overwrite" => Ok(OnCollision::Overwrite),
+            _ => Err(format!(
+                "unknown collision policy `{s}`, expected `rename` or `overwrite`"
+            )),
+        }
+    }
+}
+
+/// Characters Windows does not allow in file names, e.g. the `:` of timestamps.
+const RESERVED_CHARS: [char; 7] = ['<', '>', ':', '"', '|', '?', '*'];
+
+/// Names of Windows devices, they do not name a file even with an extension, e.g. `nul.txt`.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Returns the name safe to be joined to a directory, it can not point outside of it.
+///
+/// Only the last component of the name is kept, e.g. `../../x` becomes `x`,
+/// control characters are dropped and an empty name, `.` or `..` becomes `unknown`.
+///
+/// The name is valid on every platform, so that files move between them:
+/// characters reserved by Windows become `-`, e.g. `12:00.png` becomes `12-00.png`,
+/// trailing dots and spaces are dropped and device names get `_`, e.g. `CON.txt` becomes `_CON.txt`.
+pub fn sanitize_file_name(name: &str) -> String {
+    let last = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name: String = last
+        .chars()
+        .filter(|c| !c.is_control())
+        .map(|c| match RESERVED_CHARS.contains(&c) {
+            true => '-',
+            false => c,
+        })
+        .collect();
+    let name = name.trim_end_matches(['.', ' ']);
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    if name.trim().is_empty() {
+        "unknown".to_string()
+    } else if RESERVED_NAMES
+        .iter()
+        .any(|device| device.eq_ignore_ascii_case(stem))
+    {
+        format!("_{name}")
+    } else {
+        name.to_string()
     }
 }
-impl From<File> for (String, Vec<u8>) {
+
+impl From<File> for (String, Bytes) {
     fn from(File { name, bytes }: File) -> Self {
         (name, bytes)
     }
 }
 
-/// Creates a file at the `path` and writes the `bytes` to it, if the file already exists, it is replaced.
-async fn create_file_and_write_bytes(path: impl AsRef<Path>, bytes: &[u8]) -> io::Result<()> {
-    let mut file = fs::File::create(path).await?;
-    file.write_all(bytes).await?;
-    file.flush().await?;
-    Ok(())
+/// Creates the file with the sanitized `name` in the `dir` as the `policy` says, returns it with its path.
+async fn create_file(
+    dir: &Path,
+    name: &str,
+    policy: OnCollision,
+) -> io::Result<(fs::File, PathBuf)> {
+    let path = dir.join(sanitize_file_name(name));
+    match policy {
+        OnCollision::Overwrite => fs::File::create(&path).await.map(|file| (file, path)),
+        OnCollision::Rename => create_new_file(path).await,
+    }
+}
+
+/// Creates a new file at the `path`, or at the path with the first free number when it exists.
+async fn create_new_file(path: PathBuf) -> io::Result<(fs::File, PathBuf)> {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    let mut candidate = path.clone();
+    for n in 1.. {
+        // Creating exclusively, a file appearing meanwhile is not replaced.
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+            .await
+        {
+            Ok(file) => return Ok((file, candidate)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                candidate = path.with_file_name(format!("{stem} ({n}){extension}"));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!("some number is free")
+}
+
+/// Bytes of a [File] written to a temporary file while they arrived, see [ser::Msg::receive_saving_files].
+///
+/// The temporary file is hidden in the directory the file was received to,
+/// it is removed unless it is [kept][Self::keep], also when this is dropped.
+#[derive(Debug)]
+pub struct ReceivedFile {
+    /// Path of the temporary file, empty once it is kept or discarded.
+    path: PathBuf,
+    name: String,
+}
+impl ReceivedFile {
+    /// Returns the path of the temporary file, e.g. to hash the content before deciding where it goes.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the name the file was sent under.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Moves the file to the `dir` under its [sanitized name][sanitize_file_name], returns the path used.
+    ///
+    /// When the file already exists, the `policy` decides whether it is replaced
+    /// or the name gets a number, e.g. `notes (1).txt`, as [File::save] does.
+    /// The `dir` has to be on the file system of the temporary file.
+    pub async fn keep(mut self, dir: impl AsRef<Path>, policy: OnCollision) -> Result<PathBuf> {
+        let target = dir.as_ref().join(sanitize_file_name(&self.name));
+        let path = move_file(&self.path, target, policy)
+            .await
+            .map_err(SaveFile)?;
+        self.path = PathBuf::new();
+        Ok(path)
+    }
+
+    /// Removes the temporary file, e.g. of content saved before.
+    pub async fn discard(mut self) -> Result<()> {
+        let path = std::mem::take(&mut self.path);
+        fs::remove_file(path).await.map_err(SaveFile)
+    }
+}
+impl Drop for ReceivedFile {
+    fn drop(&mut self) {
+        if !self.path.as_os_str().is_empty() {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Moves the file at the `path` to the `target` as the `policy` says, returns the path used.
+async fn move_file(path: &Path, target: PathBuf, policy: OnCollision) -> io::Result<PathBuf> {
+    if policy == OnCollision::Overwrite {
+        fs::rename(path, &target).await?;
+        return Ok(target);
+    }
+    let stem = target.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = target
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    let mut candidate = target.clone();
+    for n in 1.. {
+        // Linking fails when the name is taken, unlike renaming it never replaces a file.
+        match fs::hard_link(path, &candidate).await {
+            Ok(()) => {
+                fs::remove_file(path).await?;
+                return Ok(candidate);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                candidate = target.with_file_name(format!("{stem} ({n}){extension}"));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    unreachable!("some number is free")
+}
+
+/// Animated image or video, sent as it is, it is not decoded, see [Self::from_path].
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Media {
+    name: String,
+    /// MIME type, e.g. `image/gif` or `video/mp4`.
+    mime: String,
+    duration_ms: Option<u64>,
+    bytes: Bytes,
+}
+impl Media {
+    /// Reads the media from the `path`, the filename can change if it contained non-unicode symbols.
+    ///
+    /// The type is recognized by the content: GIF, MP4, QuickTime or WebM, others are [UnsupportedMedia].
+    /// The duration is read from the GIF frames or the MP4 header, the frames are not decoded.
+    pub async fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let (name, bytes): (String, Bytes) = File::from_path(path).await?.into();
+        Self::from_bytes(name, bytes)
+    }
+
+    /// Makes the media of the `bytes` named `name`, see [Self::from_path].
+    pub fn from_bytes(name: impl Into<String>, bytes: impl Into<Bytes>) -> Result<Self> {
+        let bytes = bytes.into();
+        let (mime, duration_ms) = if bytes.starts_with(b"GIF8") {
+            let duration = gif_frames(&bytes).map(|(_, delay)| delay * 10);
+            ("image/gif", duration)
+        } else if bytes.get(4..8) == Some(&b"ftyp"[..]) {
+            let mime = match bytes.get(8..10) {
+                Some(b"qt") => "video/quicktime",
+                _ => "video/mp4",
+            };
+            (mime, mp4_duration_ms(&bytes))
+        } else if bytes.starts_with(&[0x1A, 0x45, 0xDF, 0xA3]) {
+            ("video/webm", None)
+        } else {
+            return Err(UnsupportedMedia);
+        };
+        Ok(Media {
+            name: name.into(),
+            mime: mime.to_string(),
+            duration_ms,
+            bytes,
+        })
+    }
+
+    /// Returns the unicode version of the filename.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the MIME type, e.g. `image/gif` or `video/mp4`.
+    pub fn mime(&self) -> &str {
+        &self.mime
+    }
+
+    /// Returns how long the media plays, when it is known.
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration_ms.map(Duration::from_millis)
+    }
+
+    /// Returns the size in bytes.
+    pub fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Whether the media moves, videos do, GIFs when they have more than one frame.
+    pub fn is_animated(&self) -> bool {
+        match self.mime.as_str() {
+            "image/gif" => gif_frames(&self.bytes).is_some_and(|(frames, _)| frames > 1),
+            _ => true,
+        }
+    }
+
+    /// Saves the media to the `dir` as [File::save] does, returns the path used.
+    pub async fn save(&self, dir: impl AsRef<Path>, policy: OnCollision) -> Result<PathBuf> {
+        let (mut file, path) = create_file(dir.as_ref(), &self.name, policy)
+            .await
+            .map_err(SaveFile)?;
+        file.write_all(&self.bytes).await.map_err(SaveFile)?;
+        file.flush().await.map_err(SaveFile)?;
+        Ok(path)
+    }
+}
+impl From<Media> for (String, Bytes) {
+    fn from(Media { name, bytes, .. }: Media) -> Self {
+        (name, bytes)
+    }
+}
+
+/// Counts the frames of the GIF and sums their delays (in hundredths of a second), nothing is decoded.
+fn gif_frames(gif: &[u8]) -> Option<(u32, u64)> {
+    // Size of the color table following a descriptor with the `packed` fields.
+    let color_table = |packed: u8| match packed & 0x80 {
+        0 => 0,
+        _ => 3 << ((packed & 7) + 1),
+    };
+    // Skips the data sub-blocks starting `at`, returns the index after their terminator.
+    let skip_blocks = |mut at: usize| loop {
+        let size = usize::from(*gif.get(at)?);
+        at += 1 + size;
+        if size == 0 {
+            return Some(at);
+        }
+    };
+    let mut at = 13 + color_table(*gif.get(10)?);
+    let (mut frames, mut delay) = (0, 0);
+    loop {
+        match *gif.get(at)? {
+            // Extension, the graphic control one carries the delay of the next frame.
+            0x21 => {
+                if *gif.get(at + 1)? == 0xF9 {
+                    delay += u64::from(u16::from_le_bytes([*gif.get(at + 4)?, *gif.get(at + 5)?]));
+                }
+                at = skip_blocks(at + 2)?;
+            }
+            // Image descriptor, followed by the color table, the LZW code size and the data.
+            0x2C => {
+                frames += 1;
+                at = skip_blocks(at + 11 + color_table(*gif.get(at + 9)?))?;
+            }
+            // Trailer.
+            0x3B => return Some((frames, delay)),
+            _ => return None,
+        }
+    }
+}
+
+/// Reads the duration from the movie header (`moov/mvhd`) of the MP4.
+fn mp4_duration_ms(mp4: &[u8]) -> Option<u64> {
+    let mvhd = mp4_box(mp4_box(mp4, b"moov")?, b"mvhd")?;
+    let u32_at = |at: usize| Some(u32::from_be_bytes(mvhd.get(at..at + 4)?.try_into().ok()?));
+    let (timescale, duration) = match mvhd.first()? {
+        0 => (u32_at(12)?, u64::from(u32_at(16)?)),
+        1 => (
+            u32_at(20)?,
+            u64::from_be_bytes(mvhd.get(24..32)?.try_into().ok()?),
+        ),
+        _ => return None,
+    };
+    (timescale > 0).then(|| duration.saturating_mul(1000) / u64::from(timescale))
+}
+
+/// Finds the content of the first box of the `kind` among the MP4 (ISO base media) boxes.
+fn mp4_box<'a>(mut boxes: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    loop {
+        let size = u32::from_be_bytes(boxes.get(..4)?.try_into().ok()?);
+        let (header, size) = match size {
+            // The box extends to the end.
+            0 => (8, boxes.len()),
+            // The size is the 64-bit number after the kind.
+            1 => (
+                16,
+                usize::try_from(u64::from_be_bytes(boxes.get(8..16)?.try_into().ok()?)).ok()?,
+            ),
+            size => (8, usize::try_from(size).ok()?),
+        };
+        let whole = boxes.get(..size).filter(|whole| whole.len() >= header)?;
+        if whole[4..8] == *kind {
+            return Some(&whole[header..]);
+        }
+        boxes = &boxes[size..];
+    }
 }
 
-/// Basic data type, wrapper around [Text][Data::Text], [File] and [Image] types.
+/// Basic data type, wrapper around [Text][Data::Text], [File], [Image] and [Media] types.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum Data {
+    /// Plain text, possibly of several lines.
     Text(String),
+    /// File with its name.
     File(File),
+    /// Image checked to be decodable when loaded.
     Image(Image),
+    /// Animated image or video, it is not decoded.
+    Media(Media),
 }
 impl From<File> for Data {
     fn from(value: File) -> Data {
@@ -202,17 +1074,25 @@ impl From<Image> for Data {
         Data::Image(value)
     }
 }
+impl From<Media> for Data {
+    fn from(value: Media) -> Data {
+        Data::Media(value)
+    }
+}
 impl Display for Data {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Text(text) => write!(f, "{text}"),
             Self::File(File { name, .. }) => write!(f, "File {{ name: {name:?} }}"),
             Self::Image(Image { format, .. }) => write!(f, "Image {{ format: {format:?} }}"),
+            Self::Media(Media { name, mime, .. }) => {
+                write!(f, "Media {{ name: {name:?}, mime: {mime:?} }}")
+            }
         }
     }
 }
 
-/// A user type.
+/// A user type, the username.
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
 pub struct User(String);
 impl Display for User {
@@ -231,60 +1111,664 @@ impl From<User> for String {
     }
 }
 
+/// What users tell about themselves, see [cli::Msg::SetProfile].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct UserProfile {
+    /// Name shown instead of the username.
+    pub display_name: Option<String>,
+    /// Short text about what the user is up to.
+    pub status: Option<String>,
+    /// Picture of the user.
+    pub avatar: Option<Image>,
+}
+impl Display for UserProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let avatar = self.avatar.as_ref().map(|avatar| avatar.format);
+        write!(
+            f,
+            "UserProfile {{ display_name: {:?}, status: {:?}, avatar: {avatar:?} }}",
+            self.display_name, self.status
+        )
+    }
+}
+
+/// Whether a user is around, kept by the server per session, see [cli::Msg::SetPresence].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Presence {
+    /// Around, the default of every session.
+    #[default]
+    Online,
+    /// Not around for a while.
+    Away,
+    /// Around but not to be disturbed.
+    DoNotDisturb,
+}
+
+/// Notification preferences of a user, kept by the server so they follow the account
+/// to every client, see [cli::Msg::SetPrefs].
+///
+/// The server only stores them, clients apply them to what they show and notify.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct Preferences {
+    /// Users whose messages are hidden.
+    pub muted: Vec<User>,
+    /// Texts containing any of the patterns are hidden.
+    pub patterns: Vec<String>,
+    /// Notify only of texts mentioning the user.
+    pub mentions_only: bool,
+    /// Messages replayed by default when the history is asked for, the client's own default when `None`.
+    pub history_length: Option<u32>,
+}
+
 /// Module for client [messages][cli::Msg].
 pub mod cli {
     use crate::*;
 
+    /// Username and password of the user.
     #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
     pub struct Credentials {
+        /// The username.
         pub user: User,
+        /// The password in plain text, the server keeps only its hash.
         pub password: String,
     }
 
     /// Authentication variants.
     #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
     pub enum Auth {
+        /// Logs in as an existing user.
         LogIn(Credentials),
+        /// Creates the user and logs in as them.
         SignUp(Credentials),
+        /// Joins as a guest of the name, not registered and forgotten on disconnection,
+        /// servers accept it only when they allow guests.
+        Guest(User),
+        /// Logs in by a bearer token (JWT) of the OpenID Connect provider the server trusts,
+        /// the user is created on the first log-in of the token's subject.
+        OidcToken(String),
+        /// Asks for a password reset code sent to the address of the user, see [ProfileChange::Email].
+        /// The server answers the same whether anybody uses the address or not.
+        RequestReset(String),
+        /// Sets the new password of the user the code was sent to and logs in as them, the code is used up.
+        ResetPassword {
+            /// Code of the [request][Self::RequestReset].
+            code: String,
+            /// The new password.
+            new_password: String,
+        },
+        /// Current code of the user's authenticator app, the second step of logging in
+        /// after [ser::Msg::TotpRequired], see [TwoFactor].
+        TotpCode(String),
+        /// Creates the user like [SignUp][Self::SignUp] with a code of an [invitation][Admin::CreateInvite],
+        /// servers allowing sign-ups by invitation only require it, others ignore the code.
+        SignUpInvited {
+            /// The new user.
+            credentials: Credentials,
+            /// Code of the invitation, one of its uses is taken.
+            invite: String,
+        },
+        /// Nonce solving the [challenge][ser::Msg::Challenge] of a sign-up, see [challenge][crate::challenge].
+        Solution(u64),
+    }
+
+    /// Two-factor authentication of the user by time-based one-time codes (TOTP).
+    #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+    pub enum TwoFactor {
+        /// Makes a new secret, the server answers with [ser::Msg::TotpEnrollment].
+        /// It is required only after it is [confirmed][Self::Confirm].
+        Enable,
+        /// Requires the new secret from the next log-in on, the code proves the app was set up.
+        Confirm(String),
+        /// Stops requiring codes, the current one must be given.
+        Disable(String),
+    }
+
+    /// Commands only administrators are allowed to send.
+    #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+    pub enum Admin {
+        /// Sets the message of the day, it is announced to everyone and sent to every client after authentication.
+        SetMotd(String),
+        /// Sets how many bytes of attachments the user can upload in total, `None` returns them to the server's default.
+        SetQuota {
+            /// User whose quota changes.
+            user: User,
+            /// The new quota in bytes.
+            bytes: Option<u64>,
+        },
+        /// Makes a code others [sign up][Auth::SignUpInvited] with, answered by [ser::Msg::Invite].
+        CreateInvite {
+            /// How many users can sign up with the code.
+            uses: u32,
+            /// How long the code is valid, it does not expire when `None`.
+            valid_for: Option<Duration>,
+        },
+    }
+
+    /// Change of one part of the user's [profile][UserProfile].
+    #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+    pub enum ProfileChange {
+        /// Sets [UserProfile::display_name].
+        DisplayName(String),
+        /// Sets [UserProfile::status].
+        Status(String),
+        /// Sets [UserProfile::avatar].
+        Avatar(Image),
+        /// Sets the address password reset codes are sent to, an empty one removes it.
+        /// It is not a part of the profile others see.
+        Email(String),
     }
 
+    /// Message of a client to the server.
     #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
     pub enum Msg {
+        /// Logs in or signs up, the server answers with [ser::Msg::Authenticated] or an error.
         Auth(Auth),
         /// Message with data intended to be forwarded to everyone.
         ToAll(Data),
+        /// Command reserved for administrators.
+        Admin(Admin),
+        /// The message with the [id][ser::Msg::DataFrom::msg_id] was read by the user.
+        MarkRead {
+            /// Id of the stored message.
+            msg_id: i64,
+        },
+        /// Asks who read the message with the id, the user must be its sender, see [ser::Msg::ReadBy].
+        ReadStatus {
+            /// Id of the stored message.
+            msg_id: i64,
+        },
+        /// Changes a part of the user's profile.
+        SetProfile(ProfileChange),
+        /// Asks for the profile of the user, see [ser::Msg::Profile].
+        GetProfile(User),
+        /// Sets up or turns off the user's two-factor authentication.
+        TwoFactor(TwoFactor),
+        /// Asks how the server is doing, see [ser::Msg::Stats].
+        Stats,
+        /// Sets the presence of the session, announced by [ser::Msg::PresenceChanged].
+        SetPresence {
+            /// The new presence.
+            presence: Presence,
+            /// Told to those who mention the user while they are not [online][Presence::Online].
+            message: Option<String>,
+        },
+        /// Text to everyone flagged [urgent][ser::Msg::DataFrom::urgent], servers limit how often users send them.
+        Urgent(String),
+        /// Replaces the user's [preferences][Preferences] stored by the server.
+        SetPrefs(Preferences),
+        /// Asks for the user's preferences, see [ser::Msg::Prefs].
+        GetPrefs,
+        /// Ids of the [codecs][Codec::id] the client can use, the preferred first, before the authentication.
+        ///
+        /// The server answers with the one it chose, [ser::Msg::CodecChosen], both use it from the message
+        /// after [ser::Msg::Authenticated] on. Bincode is used when nothing is negotiated.
+        /// Ids rather than codecs are sent, the client may know codecs the server does not.
+        Codecs(Vec<u8>),
+    }
+    impl Msg {
+        /// Wraps the message with the `id`.
+        pub fn tagged(self, id: MsgId) -> Envelope {
+            Envelope {
+                id: Some(id),
+                msg: self,
+            }
+        }
     }
     impl Display for Msg {
         fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
             match self {
                 Self::ToAll(data) => write!(f, "ToAll({data})"),
+                Self::SetProfile(ProfileChange::Avatar(avatar)) => {
+                    write!(f, "SetProfile(Avatar({:?}))", avatar.format)
+                }
                 other => write!(f, "{other:?}"),
             }
         }
     }
-    impl Messageable for Msg {}
-}
+    /// Sent and received in an [Envelope] without an id, the id of a [tagged][Msg::tagged] one received is dropped.
+    impl Messageable for Msg {
+        fn to_bytes_with(&self, codec: Codec) -> Result<Bytes> {
+            encode(
+                codec,
+                &EnvelopeRef {
+                    id: None,
+                    msg: self,
+                },
+            )
+        }
 
-/// Module for server [messages][ser::Msg].
-pub mod ser {
-    use crate::*;
+        fn from_bytes_with(codec: Codec, bytes: &[u8]) -> Result<Self> {
+            Envelope::from_bytes_with(codec, bytes).map(|envelope| envelope.msg)
+        }
+    }
 
-    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
-    pub enum Error {
-        ReceiveMsg(String),
-        SendMsgTo(cli::Msg, User),
+    /// Frame of a client, the message with the id the client chose, if any.
+    ///
+    /// The server refers to the id in [ser::Msg::Ack] and [ser::Msg::Rejected].
+    /// The message can not be another envelope, so frames do not nest however they are crafted.
+    #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+    pub struct Envelope {
+        /// Id of the message, unique within the connection, see [Msg::tagged].
+        pub id: Option<MsgId>,
+        /// The message.
+        pub msg: Msg,
+    }
+    impl From<Msg> for Envelope {
+        fn from(msg: Msg) -> Self {
+            Envelope { id: None, msg }
+        }
+    }
+    impl Display for Envelope {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self.id {
+                Some(id) => write!(f, "{} {id}", self.msg),
+                None => write!(f, "{}", self.msg),
+            }
+        }
+    }
+    impl Messageable for Envelope {}
+
+    /// [Envelope] of a borrowed message, encoded the same, so that the message is not cloned to be sent.
+    #[derive(serde::Serialize)]
+    struct EnvelopeRef<'a> {
+        id: Option<MsgId>,
+        msg: &'a Msg,
+    }
+}
+
+/// Id of a [client message][cli::Envelope], unique within one connection.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct MsgId(pub u64);
+impl Display for MsgId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// Module for server [messages][ser::Msg].
+pub mod ser {
+    use crate::*;
+
+    /// Error of the server, each kind has its [code][Self::code], also known as [WireError].
+    ///
+    /// On the wire it is the code, the [description][Display] and the payload of the kind,
+    /// an error of a newer server is received as [Other][Self::Other], so new kinds can be added
+    /// without breaking older clients. Codes of the kinds never change.
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[serde(into = "WireError", from = "WireError")]
+    #[non_exhaustive]
+    pub enum Error {
+        /// The message could not be decoded, its frame had `len` bytes.
+        Malformed {
+            /// Bytes of the frame.
+            len: u64,
+        },
+        /// The message could not be sent to the user.
+        SendMsgTo(cli::Msg, User),
+        /// The message needs an authenticated user, it is given back.
         NotAuthenticated(cli::Msg),
+        /// The user is authenticated already.
         AlreadyAuthenticated,
+        /// No user of the username exists,
+        /// servers hiding which usernames exist answer [InvalidCredentials][Self::InvalidCredentials] instead.
         WrongUser,
+        /// The password does not match, or [InvalidCredentials][Self::InvalidCredentials] as above.
         WrongPassword,
+        /// Someone already signed up with the username.
         UsernameTaken,
+        /// The command is reserved for administrators.
+        NotAdmin,
+        /// No message with the id was sent by the user.
+        UnknownMessage(i64),
+        /// No user of the username exists.
+        UnknownUser(User),
+        /// The connection was not accepted, the server closes it.
+        Refused(Refusal),
+        /// The message did not pass the server's filters, the reason is given.
+        Rejected(String),
+        /// The attachment would exceed the user's storage quota, both are in bytes.
+        QuotaExceeded {
+            /// Bytes the user uploaded so far.
+            used: u64,
+            /// Bytes the user can upload in total.
+            limit: u64,
+        },
+        /// The user is logged in elsewhere and the server allows one session per user.
+        SessionActive,
+        /// The server does not let [guests][cli::Auth::Guest] in.
+        GuestsNotAllowed,
+        /// The [token][cli::Auth::OidcToken] is not accepted, the reason is given.
+        InvalidToken(String),
+        /// The user does not exist or the password does not match, the server does not tell which.
+        InvalidCredentials,
+        /// The password reset code is not valid, it was used up, has expired or was never sent.
+        InvalidResetCode,
+        /// The [two-factor code][cli::Auth::TotpCode] is wrong or was not given.
+        InvalidTotpCode,
+        /// The server lets users sign up only [with an invitation][cli::Auth::SignUpInvited].
+        InviteRequired,
+        /// The invitation code is not valid, it was used up, has expired or was never made.
+        InvalidInvite,
+        /// The [challenge][Msg::Challenge] of the sign-up was not solved.
+        ChallengeFailed,
+        /// Error with a code not known to this side, or with a payload it can not decode.
+        ///
+        /// Servers send failures without a kind of their own, e.g. of their database,
+        /// as `Other` with the [code][ErrorCode] they log them with.
+        Other {
+            /// The [code][Self::code] of the error.
+            code: u16,
+            /// Description of the error by the server.
+            detail: String,
+        },
+    }
+    impl Error {
+        /// Number identifying the kind of the error, stable across versions, see [ErrorCode].
+        pub fn code(&self) -> ErrorCode {
+            let code = match self {
+                Self::Malformed { .. } => 1,
+                Self::SendMsgTo(..) => 2,
+                Self::NotAuthenticated(_) => 3,
+                Self::AlreadyAuthenticated => 4,
+                Self::WrongUser => 5,
+                Self::WrongPassword => 6,
+                Self::UsernameTaken => 7,
+                Self::NotAdmin => 8,
+                Self::UnknownMessage(_) => 9,
+                Self::UnknownUser(_) => 10,
+                Self::Refused(_) => 11,
+                Self::Rejected(_) => 12,
+                Self::QuotaExceeded { .. } => 13,
+                Self::SessionActive => 14,
+                Self::GuestsNotAllowed => 15,
+                Self::InvalidToken(_) => 16,
+                Self::InvalidCredentials => 17,
+                Self::InvalidResetCode => 18,
+                Self::InvalidTotpCode => 19,
+                Self::InviteRequired => 20,
+                Self::InvalidInvite => 21,
+                Self::ChallengeFailed => 22,
+                Self::Other { code, .. } => *code,
+            };
+            ErrorCode(code)
+        }
+
+        /// Kind of the error, by its variant, the one of the [code][ErrorCode::kind] for [Other][Self::Other].
+        pub fn kind(&self) -> ErrorKind {
+            match self {
+                Self::Malformed { .. } => ErrorKind::Protocol,
+                Self::SendMsgTo(..) => ErrorKind::Io,
+                Self::NotAuthenticated(_)
+                | Self::AlreadyAuthenticated
+                | Self::WrongUser
+                | Self::WrongPassword
+                | Self::UsernameTaken
+                | Self::NotAdmin
+                | Self::SessionActive
+                | Self::GuestsNotAllowed
+                | Self::InvalidToken(_)
+                | Self::InvalidCredentials
+                | Self::InvalidResetCode
+                | Self::InvalidTotpCode
+                | Self::InviteRequired
+                | Self::InvalidInvite
+                | Self::ChallengeFailed => ErrorKind::Auth,
+                Self::UnknownMessage(_) | Self::UnknownUser(_) => ErrorKind::NotFound,
+                Self::Refused(_) | Self::QuotaExceeded { .. } => ErrorKind::Limit,
+                Self::Rejected(_) => ErrorKind::Rejected,
+                Self::Other { code, .. } => ErrorCode(*code).kind(),
+            }
+        }
+
+        /// Encodes the data of the kind, the code says how to decode it.
+        fn payload(&self) -> bincode::Result<Vec<u8>> {
+            match self {
+                Self::Malformed { len } => bincode::serialize(len),
+                Self::SendMsgTo(msg, user) => bincode::serialize(&(msg, user)),
+                Self::NotAuthenticated(msg) => bincode::serialize(msg),
+                Self::UnknownMessage(msg_id) => bincode::serialize(msg_id),
+                Self::UnknownUser(user) => bincode::serialize(user),
+                Self::Refused(refusal) => bincode::serialize(refusal),
+                Self::Rejected(reason) => bincode::serialize(reason),
+                Self::InvalidToken(reason) => bincode::serialize(reason),
+                Self::QuotaExceeded { used, limit } => bincode::serialize(&(used, limit)),
+                _ => Ok(vec![]),
+            }
+        }
+
+        /// Decodes the error of the `code` from its `payload`, `None` when the code is unknown.
+        fn from_payload(code: u16, payload: &[u8]) -> Option<bincode::Result<Error>> {
+            let error = match code {
+                1 => bincode::deserialize(payload).map(|len| Self::Malformed { len }),
+                2 => bincode::deserialize(payload).map(|(msg, user)| Self::SendMsgTo(msg, user)),
+                3 => bincode::deserialize(payload).map(Self::NotAuthenticated),
+                4 => Ok(Self::AlreadyAuthenticated),
+                5 => Ok(Self::WrongUser),
+                6 => Ok(Self::WrongPassword),
+                7 => Ok(Self::UsernameTaken),
+                8 => Ok(Self::NotAdmin),
+                9 => bincode::deserialize(payload).map(Self::UnknownMessage),
+                10 => bincode::deserialize(payload).map(Self::UnknownUser),
+                11 => bincode::deserialize(payload).map(Self::Refused),
+                12 => bincode::deserialize(payload).map(Self::Rejected),
+                13 => bincode::deserialize(payload)
+                    .map(|(used, limit)| Self::QuotaExceeded { used, limit }),
+                14 => Ok(Self::SessionActive),
+                15 => Ok(Self::GuestsNotAllowed),
+                16 => bincode::deserialize(payload).map(Self::InvalidToken),
+                17 => Ok(Self::InvalidCredentials),
+                18 => Ok(Self::InvalidResetCode),
+                19 => Ok(Self::InvalidTotpCode),
+                20 => Ok(Self::InviteRequired),
+                21 => Ok(Self::InvalidInvite),
+                22 => Ok(Self::ChallengeFailed),
+                _ => return None,
+            };
+            Some(error)
+        }
+    }
+    impl Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Self::Malformed { len } => write!(f, "the message of {len} bytes is malformed"),
+                Self::SendMsgTo(msg, user) => write!(f, "sending {msg} to {user} failed"),
+                Self::NotAuthenticated(msg) => write!(f, "{msg} needs authentication first"),
+                Self::AlreadyAuthenticated => write!(f, "already authenticated"),
+                Self::WrongUser => write!(f, "the user does not exist"),
+                Self::WrongPassword => write!(f, "the password is wrong"),
+                Self::UsernameTaken => write!(f, "the username is taken"),
+                Self::NotAdmin => write!(f, "reserved for administrators"),
+                Self::UnknownMessage(msg_id) => write!(f, "no message {msg_id} of the user"),
+                Self::UnknownUser(user) => write!(f, "there is no user {user}"),
+                Self::Refused(refusal) => write!(f, "the connection is refused, {refusal:?}"),
+                Self::Rejected(reason) => write!(f, "the message is rejected, {reason}"),
+                Self::QuotaExceeded { used, limit } => {
+                    write!(
+                        f,
+                        "the storage quota is exceeded, {used} of {limit} bytes used"
+                    )
+                }
+                Self::SessionActive => write!(f, "the user is logged in elsewhere"),
+                Self::GuestsNotAllowed => write!(f, "guests are not allowed"),
+                Self::InvalidToken(reason) => write!(f, "the token is invalid, {reason}"),
+                Self::InvalidCredentials => write!(f, "the username or the password is wrong"),
+                Self::InvalidResetCode => write!(f, "the password reset code is not valid"),
+                Self::InvalidTotpCode => write!(f, "the two-factor code is wrong"),
+                Self::InviteRequired => write!(f, "signing up requires an invitation"),
+                Self::InvalidInvite => write!(f, "the invitation code is not valid"),
+                Self::ChallengeFailed => write!(f, "the sign-up challenge was not solved"),
+                Self::Other { code, detail } => write!(f, "error {code}: {detail}"),
+            }
+        }
+    }
+    impl std::error::Error for Error {}
+
+    /// [Error] as it is sent, the payload is opaque to those who do not know the code.
+    #[derive(Serialize, Deserialize)]
+    struct WireError {
+        code: u16,
+        detail: String,
+        payload: Vec<u8>,
+    }
+    impl From<Error> for WireError {
+        fn from(error: Error) -> Self {
+            let (detail, payload) = match &error {
+                Error::Other { detail, .. } => (detail.clone(), vec![]),
+                // Every payload is serializable, the detail is there anyway.
+                error => (error.to_string(), error.payload().unwrap_or_default()),
+            };
+            WireError {
+                code: error.code().0,
+                detail,
+                payload,
+            }
+        }
+    }
+    impl From<WireError> for Error {
+        fn from(
+            WireError {
+                code,
+                detail,
+                payload,
+            }: WireError,
+        ) -> Self {
+            match Error::from_payload(code, &payload) {
+                Some(Ok(error)) => error,
+                _ => Error::Other { code, detail },
+            }
+        }
+    }
+
+    /// Why the server refused the connection.
+    #[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum Refusal {
+        /// The server is at its limit of connections.
+        TooManyConnections,
+        /// The address has as many connections as it is allowed to.
+        TooManyFromAddress,
+        /// The address is not allowed to connect.
+        AddressNotAllowed,
     }
 
+    /// Message of the server to a client.
     #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
     pub enum Msg {
+        /// The user was logged in or signed up.
         Authenticated,
+        /// The password is right and the user requires two-factor authentication,
+        /// the log-in is finished by [cli::Auth::TotpCode].
+        TotpRequired,
+        /// New two-factor secret of the user as an `otpauth://` URL for authenticator apps,
+        /// it is required once [confirmed][cli::TwoFactor::Confirm].
+        TotpEnrollment {
+            /// The URL, also shown as a QR code by clients.
+            url: String,
+        },
+        /// The last message caused the error, [Rejected][Self::Rejected] is sent for tagged ones.
         Error(Error),
-        DataFrom { data: Data, from: User },
+        /// Data sent to everyone by the user.
+        DataFrom {
+            /// What was sent.
+            data: Data,
+            /// Who sent it.
+            from: User,
+            /// Id of the message stored by the server, `None` when storing failed.
+            msg_id: Option<i64>,
+            /// Display name of the sender if they set any.
+            display_name: Option<String>,
+            /// Users mentioned in the text as `@username`.
+            mentions: Vec<User>,
+            /// The sender is a [guest][cli::Auth::Guest], not a registered user.
+            guest: bool,
+            /// The text was sent [urgent][cli::Msg::Urgent], clients show and notify it even when muted.
+            urgent: bool,
+        },
+        /// The [tagged][cli::Msg::tagged] data was stored under the `msg_id`.
+        Stored {
+            /// Id the client tagged the data with.
+            id: MsgId,
+            /// Id the server stored the data with.
+            msg_id: i64,
+        },
+        /// Profile of the user, empty if they did not set any.
+        Profile {
+            /// Whose profile it is.
+            user: User,
+            /// The profile.
+            profile: UserProfile,
+        },
+        /// Users who [read][cli::Msg::MarkRead] the message, in the order they did.
+        ReadBy {
+            /// Id of the stored message.
+            msg_id: i64,
+            /// Readers of the message.
+            users: Vec<User>,
+        },
+        /// Announcement of the server itself (e.g. the message of the day), not of any user.
+        ServerInfo(String),
+        /// The [tagged][cli::Msg::tagged] message was processed.
+        Ack(MsgId),
+        /// The [tagged][cli::Msg::tagged] message was not processed because of the error.
+        Rejected(MsgId, Error),
+        /// Another session of the user began, sent to the sessions the user already has.
+        NewSession {
+            /// Address the new session connected from.
+            addr: SocketAddr,
+            /// RFC 3339 time the new session began.
+            time: String,
+        },
+        /// Numbers of the server answering [cli::Msg::Stats].
+        Stats {
+            /// How long the server runs.
+            uptime: Duration,
+            /// Sessions of clients connected now.
+            connected: u64,
+            /// Data sent to everyone since the midnight (UTC).
+            messages_today: u64,
+            /// Version of the server.
+            version: String,
+        },
+        /// The user [set][cli::Msg::SetPresence] their presence, also the auto-reply to a mention of them
+        /// while they are not [online][Presence::Online].
+        PresenceChanged {
+            /// Whose presence it is.
+            user: User,
+            /// The presence.
+            presence: Presence,
+            /// Message the user left, if any.
+            message: Option<String>,
+        },
+        /// Preferences of the user answering [cli::Msg::GetPrefs], the defaults if they did not set any.
+        Prefs(Preferences),
+        /// Invitation answering [cli::Admin::CreateInvite].
+        Invite {
+            /// Code to [sign up][cli::Auth::SignUpInvited] with, the server keeps only its hash.
+            code: String,
+            /// How many users can sign up with it.
+            uses: u32,
+            /// RFC 3339 time it expires, `None` when it does not.
+            expires: Option<String>,
+        },
+        /// Proof of work asked of a client signing up before the sign-up is carried out,
+        /// answered by [cli::Auth::Solution], see [challenge][crate::challenge].
+        Challenge {
+            /// Random prefix of the hashed input.
+            prefix: String,
+            /// Leading zero bits the hash has to have.
+            difficulty: u8,
+        },
+        /// Codec of the session, the first one of the [offered][cli::Msg::Codecs] the server knows,
+        /// [Bincode][Codec::Bincode] when it knows none.
+        CodecChosen(Codec),
+    }
+    impl Msg {
+        /// Wraps the `error` so it refers to the message with the `id`, if there is any.
+        pub fn error_for(id: Option<MsgId>, error: Error) -> Msg {
+            match id {
+                Some(id) => Msg::Rejected(id, error),
+                None => Msg::Error(error),
+            }
+        }
     }
     impl From<Error> for Msg {
         fn from(value: Error) -> Self {
@@ -294,17 +1778,194 @@ pub mod ser {
     impl Display for Msg {
         fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
             match self {
-                Self::DataFrom { data, from } => {
-                    write!(f, "DataFrom {{ data: {data}, from: {from:?} }}")
+                Self::DataFrom {
+                    data,
+                    from,
+                    msg_id,
+                    display_name,
+                    mentions,
+                    guest,
+                    urgent,
+                } => write!(
+                    f,
+                    "DataFrom {{ data: {data}, from: {from:?}, msg_id: {msg_id:?}, display_name: {display_name:?}, mentions: {mentions:?}, guest: {guest}, urgent: {urgent} }}"
+                ),
+                Self::Profile { user, profile } => {
+                    write!(f, "Profile {{ user: {user:?}, profile: {profile} }}")
                 }
                 other => write!(f, "{other:?}"),
             }
         }
     }
     impl Messageable for Msg {}
+    impl Msg {
+        /// Receives a message like [Messageable::receive], but the bytes of a [File] it carries
+        /// are written to a temporary file in the `dir` while they arrive, they are never held in memory at once.
+        ///
+        /// Returns the message, with the bytes of the file left empty, and the [ReceivedFile] if there was any.
+        /// No other file is touched, the caller [keeps][ReceivedFile::keep] the file where it belongs
+        /// or [discards][ReceivedFile::discard] it.
+        /// The whole message is read even when writing fails, the stream can be read on.
+        /// The message is encoded by the default [Codec], see [receive_saving_files_with][Self::receive_saving_files_with].
+        ///
+        /// The bytes of the message read so far and the total are reported to `progress`.
+        pub async fn receive_saving_files<R>(
+            reader: &mut R,
+            dir: &Path,
+            progress: impl FnMut(u64, u64) + Send,
+        ) -> Result<(Msg, Option<Result<ReceivedFile>>)>
+        where
+            R: AsyncRead + Unpin + Send,
+        {
+            receive_saving_files(reader, dir, progress).await
+        }
+
+        /// Receives a message encoded by the `codec` like [receive_saving_files][Self::receive_saving_files].
+        ///
+        /// Only the default codec tells where the bytes of the file are, the frame of another one
+        /// is read whole (it is no longer than [MAX_FRAME_SIZE]) and the file is written after.
+        pub async fn receive_saving_files_with<R>(
+            codec: Codec,
+            reader: &mut R,
+            dir: &Path,
+            progress: impl FnMut(u64, u64) + Send,
+        ) -> Result<(Msg, Option<Result<ReceivedFile>>)>
+        where
+            R: AsyncRead + Unpin + Send,
+        {
+            if codec == Codec::Bincode {
+                receive_saving_files(reader, dir, progress).await
+            } else {
+                receive_decoding_files(codec, reader, dir, progress).await
+            }
+        }
+    }
+}
+
+/// Module for messages between the server and bots connected to its control socket.
+///
+/// A bot first says [Hello][bot::Action::Hello] with its name, then it can [subscribe][bot::Action::Subscribe]
+/// to [events][bot::Event] and send data on its own.
+pub mod bot {
+    use crate::*;
+
+    /// What the bot asks the server to do.
+    #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+    pub enum Action {
+        /// Introduces the bot, the name is shown as the sender of its data.
+        Hello(String),
+        /// Starts sending [Event]s to the bot.
+        Subscribe,
+        /// Sends the data to every client.
+        Broadcast(Data),
+        /// Sends the data to every session of the user.
+        Whisper {
+            /// Receiver of the data.
+            to: User,
+            /// What is sent.
+            data: Data,
+        },
+    }
+    impl Messageable for Action {}
+
+    /// What happened at the server.
+    #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+    pub enum Event {
+        /// A user sent the data to everyone, data of bots is not reported.
+        Data {
+            /// Who sent it.
+            from: User,
+            /// What was sent.
+            data: Data,
+            /// Id of the stored message, `None` when storing failed.
+            msg_id: Option<i64>,
+        },
+        /// The last action was not carried out.
+        Error(String),
+    }
+    impl Messageable for Event {}
+}
+
+/// Wire encodings of [Messageable]s, [bincode][Codec::Bincode] is the default.
+///
+/// Every codec has its [id][Self::id], the same whichever features are enabled, it is what goes on the wire.
+/// Peers agree on one by the [negotiation][cli::Msg::Codecs].
+/// Run `cargo bench --features postcard` to compare them.
+#[derive(Serialize, Deserialize, Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[serde(into = "u8", try_from = "u8")]
+#[non_exhaustive]
+#[repr(u8)]
+pub enum Codec {
+    /// Fixed size integers, the enum variants as `u32`s, see [bincode](https://docs.rs/bincode).
+    #[default]
+    Bincode = 0,
+    /// Compact varint based encoding, needs the `postcard` feature.
+    #[cfg(feature = "postcard")]
+    Postcard = 1,
+    /// JSON text, the easiest for implementations in other languages, needs the `json` feature.
+    ///
+    /// Bytes (of files and images) are arrays of numbers, enum variants are externally tagged.
+    /// The negotiation before the authentication is bincode, as with any codec.
+    #[cfg(feature = "json")]
+    Json = 2,
+}
+impl Codec {
+    /// Codecs of this build, the most compact first.
+    pub const ALL: &'static [Codec] = &[
+        #[cfg(feature = "postcard")]
+        Codec::Postcard,
+        Codec::Bincode,
+        #[cfg(feature = "json")]
+        Codec::Json,
+    ];
+
+    /// Number identifying the codec on the wire.
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+
+    /// Codec of the id, `None` when it is unknown or not enabled in this build.
+    pub fn from_id(id: u8) -> Option<Codec> {
+        Codec::ALL.iter().copied().find(|codec| codec.id() == id)
+    }
+
+    /// The first of the offered ids this build knows, [Bincode][Codec::Bincode] when it knows none,
+    /// see [cli::Msg::Codecs].
+    pub fn choose(offered: &[u8]) -> Codec {
+        offered
+            .iter()
+            .find_map(|&id| Codec::from_id(id))
+            .unwrap_or_default()
+    }
+}
+impl From<Codec> for u8 {
+    fn from(codec: Codec) -> u8 {
+        codec.id()
+    }
+}
+impl TryFrom<u8> for Codec {
+    type Error = String;
+
+    fn try_from(id: u8) -> std::result::Result<Codec, String> {
+        Codec::from_id(id).ok_or_else(|| format!("unknown codec {id}"))
+    }
+}
+
+/// Serializes the `value` into bytes using the `codec`.
+fn encode<T: Serialize + ?Sized>(codec: Codec, value: &T) -> Result<Bytes> {
+    let bytes = match codec {
+        Codec::Bincode => bincode::serialize(value).map_err(SerializeMsg)?,
+        #[cfg(feature = "postcard")]
+        Codec::Postcard => postcard::to_allocvec(value).map_err(SerializePostcard)?,
+        #[cfg(feature = "json")]
+        Codec::Json => serde_json::to_vec(value).map_err(SerializeJson)?,
+    };
+    Ok(bytes.into())
 }
 
 /// Enables types to be sent on one end and received on the other.
+///
+/// Also known as [Message].
 #[async_trait]
 pub trait Messageable
 where
@@ -312,66 +1973,874 @@ where
     for<'de> Self: serde::de::Deserialize<'de>,
 {
     /// Serializes the Messageable into bytes.
-    fn to_bytes(&self) -> Result<Vec<u8>> {
-        bincode::serialize(self).map_err(SerializeMsg)
+    fn to_bytes(&self) -> Result<Bytes> {
+        self.to_bytes_with(Codec::default())
     }
 
     /// Deserialize a Messageable from bytes.
     fn from_bytes(bytes: &[u8]) -> Result<Self> {
-        bincode::deserialize(bytes).map_err(DeserializeMsg)
+        Self::from_bytes_with(Codec::default(), bytes)
+    }
+
+    /// Serializes the Messageable into bytes using the `codec`.
+    fn to_bytes_with(&self, codec: Codec) -> Result<Bytes> {
+        encode(codec, self)
+    }
+
+    /// Deserialize a Messageable from bytes encoded by the `codec`.
+    fn from_bytes_with(codec: Codec, bytes: &[u8]) -> Result<Self> {
+        match codec {
+            Codec::Bincode => bincode::deserialize(bytes).map_err(DeserializeMsg),
+            #[cfg(feature = "postcard")]
+            Codec::Postcard => postcard::from_bytes(bytes).map_err(DeserializePostcard),
+            #[cfg(feature = "json")]
+            Codec::Json => serde_json::from_slice(bytes).map_err(DeserializeJson),
+        }
     }
 
     /// Tries to read a Messageable from the async reader.
     async fn receive<R>(reader: &mut R) -> Result<Self>
     where
-        R: AsyncReadExt + std::marker::Unpin + std::marker::Send,
+        R: AsyncRead + Unpin + Send,
     {
         Self::from_bytes(&read_bytes(reader).await?)
     }
 
+    /// Tries to read a Messageable encoded by the `codec` from the async reader.
+    async fn receive_with<R>(codec: Codec, reader: &mut R) -> Result<Self>
+    where
+        R: AsyncRead + Unpin + Send,
+    {
+        Self::from_bytes_with(codec, &read_bytes(reader).await?)
+    }
+
+    /// Tries to read a Messageable like [receive][Self::receive], returns the size of its frame as well.
+    ///
+    /// The outer error is of the stream, the inner one of the decoding,
+    /// the frame was read whole then and the stream can be read on.
+    async fn receive_sized<R>(reader: &mut R) -> Result<(usize, Result<Self>)>
+    where
+        R: AsyncRead + Unpin + Send,
+    {
+        let bytes = read_bytes(reader).await?;
+        Ok((bytes.len(), Self::from_bytes(&bytes)))
+    }
+
+    /// Tries to read a Messageable encoded by the `codec` like [receive_sized][Self::receive_sized],
+    /// `reserve` is awaited with the size of the frame, as its length prefix tells, before the rest of it is read.
+    ///
+    /// E.g. a server reserves memory for the frame first, so it holds no bytes beyond its budget,
+    /// what `reserve` resolves to is returned along. Frames longer than [MAX_FRAME_SIZE] are
+    /// [FrameTooLarge] before anything is reserved, the rest of a frame not read within the `timeout`
+    /// is [TimedOut] and the reservation is dropped.
+    async fn receive_reserving<R, F, Fut>(
+        codec: Codec,
+        reader: &mut R,
+        reserve: F,
+        timeout: Duration,
+    ) -> Result<(Fut::Output, usize, Result<Self>)>
+    where
+        R: AsyncRead + Unpin + Send,
+        F: FnOnce(usize) -> Fut + Send,
+        Fut: std::future::Future + Send,
+        Fut::Output: Send,
+    {
+        let len = read_len(reader).await?;
+        let reserved = reserve(len).await;
+        let bytes = retry::deadline(timeout, read_body(reader, len)).await?;
+        Ok((reserved, len, Self::from_bytes_with(codec, &bytes)))
+    }
+
     /// Writes the Messageable to the async writer.
     async fn send<W>(&self, writer: &mut W) -> Result<()>
     where
-        W: AsyncWriteExt + std::marker::Unpin + std::marker::Send,
+        W: AsyncWrite + Unpin + Send,
     {
         write_bytes(writer, &self.to_bytes()?).await
     }
+
+    /// Writes the Messageable encoded by the `codec` to the async writer.
+    async fn send_with<W>(&self, codec: Codec, writer: &mut W) -> Result<()>
+    where
+        W: AsyncWrite + Unpin + Send,
+    {
+        write_bytes(writer, &self.to_bytes_with(codec)?).await
+    }
+
+    /// Writes the Messageable like [send][Self::send], the bytes written so far and the total are reported to `progress`.
+    async fn send_with_progress<W, P>(&self, writer: &mut W, progress: P) -> Result<()>
+    where
+        W: AsyncWrite + Unpin + Send,
+        P: FnMut(u64, u64) + Send,
+    {
+        write_bytes_with_progress(writer, &self.to_bytes()?, progress).await
+    }
+
+    /// Writes the Messageable encoded by the `codec` like [send_with_progress][Self::send_with_progress].
+    async fn send_with_progress_with<W, P>(
+        &self,
+        codec: Codec,
+        writer: &mut W,
+        progress: P,
+    ) -> Result<()>
+    where
+        W: AsyncWrite + Unpin + Send,
+        P: FnMut(u64, u64) + Send,
+    {
+        write_bytes_with_progress(writer, &self.to_bytes_with(codec)?, progress).await
+    }
 }
 
+/// [Messageable] encoded once to be sent many times, e.g. to every client, clones share the bytes.
+///
+/// It is sent in the same frame as by [Messageable::send], received as the Messageable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Encoded(Bytes);
+impl Encoded {
+    /// Encodes the Messageable by the default [Codec].
+    pub fn new(msg: &impl Messageable) -> Result<Self> {
+        Ok(Encoded(msg.to_bytes()?))
+    }
+
+    /// Encodes the Messageable by the `codec`, it is received by [Messageable::receive_with] then.
+    pub fn new_with(codec: Codec, msg: &impl Messageable) -> Result<Self> {
+        Ok(Encoded(msg.to_bytes_with(codec)?))
+    }
+
+    /// The encoded Messageable, without the length of the frame.
+    pub fn bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Writes the frame to the async writer.
+    pub async fn send<W>(&self, writer: &mut W) -> Result<()>
+    where
+        W: AsyncWrite + Unpin + Send,
+    {
+        write_bytes(writer, &self.0).await
+    }
+}
+
+/// Length of the longest frame sent or received, longer ones are [FrameTooLarge].
+///
+/// A peer can not make the other side wait for or hold more, whatever its length prefix claims.
+pub const MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+
 /// Reads bytes from the async reader, use it along with [write_bytes].
-pub async fn read_bytes(stream: &mut (impl AsyncReadExt + std::marker::Unpin)) -> Result<Vec<u8>> {
-    fn map_err(e: io::Error) -> Error {
-        if e.kind() == ErrorKind::UnexpectedEof {
-            DisconnectedStream(e)
-        } else {
-            ReceiveBytes(e)
-        }
+///
+/// The bytes are prefixed by their length, a big-endian `u32`.
+pub(crate) async fn read_bytes(stream: &mut (impl AsyncRead + Unpin)) -> Result<Vec<u8>> {
+    let len = read_len(stream).await?;
+    read_body(stream, len).await
+}
+
+/// Reads the length prefix of a frame, see [read_bytes], one longer than [MAX_FRAME_SIZE] is [FrameTooLarge].
+async fn read_len(stream: &mut (impl AsyncRead + Unpin)) -> Result<usize> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).await.map_err(receive_error)?;
+    match u32::from_be_bytes(len) as usize {
+        len if len > MAX_FRAME_SIZE => Err(FrameTooLarge(len)),
+        len => Ok(len),
+    }
+}
+
+/// Reads the `len` bytes of a frame following its length prefix, see [read_bytes].
+async fn read_body(stream: &mut (impl AsyncRead + Unpin), len: usize) -> Result<Vec<u8>> {
+    // The length is not trusted, the buffer grows as the bytes arrive.
+    let mut bytes = Vec::with_capacity(len.min(CHUNK));
+    let read = stream
+        .take(len as u64)
+        .read_to_end(&mut bytes)
+        .await
+        .map_err(receive_error)?;
+    if read < len {
+        return Err(DisconnectedStream(io::ErrorKind::UnexpectedEof.into()));
     }
-    let len = stream.read_u32().await.map_err(map_err)?;
-    let mut bytes = vec![0u8; len as usize];
-    stream.read_exact(&mut bytes).await.map_err(map_err)?;
     Ok(bytes)
 }
 
+fn receive_error(e: io::Error) -> Error {
+    if e.kind() == io::ErrorKind::UnexpectedEof {
+        DisconnectedStream(e)
+    } else {
+        ReceiveBytes(e)
+    }
+}
+
+/// Bytes of a streamed file held in memory at once.
+const CHUNK: usize = 64 * 1024;
+
+/// See [ser::Msg::receive_saving_files].
+///
+/// Where the bytes of the file are is told by the [FileLayout], the decoded message is checked to carry
+/// the file found there, so a change of the encoding fails loudly instead of corrupting files.
+async fn receive_saving_files(
+    stream: &mut (impl AsyncRead + Unpin),
+    dir: &Path,
+    progress: impl FnMut(u64, u64),
+) -> Result<(ser::Msg, Option<Result<ReceivedFile>>)> {
+    let len = read_len(stream).await?;
+    let mut frame = Frame::new(stream, len, progress);
+    let mut bytes = Vec::with_capacity(frame.left.min(CHUNK));
+    let layout = FileLayout::new();
+    let header = layout.prefix.len() + layout.len_size;
+    if frame.left < header + layout.len_size {
+        frame.read_rest(&mut bytes).await?;
+        return Ok((ser::Msg::from_bytes(&bytes)?, None));
+    }
+    frame.read_more(&mut bytes, header).await?;
+    let name_len = layout.len(&bytes[layout.prefix.len()..]);
+    if bytes[..layout.prefix.len()] != layout.prefix || name_len > frame.left - layout.len_size {
+        frame.read_rest(&mut bytes).await?;
+        return Ok((ser::Msg::from_bytes(&bytes)?, None));
+    }
+    frame
+        .read_more(&mut bytes, name_len + layout.len_size)
+        .await?;
+    let file_len = layout.len(&bytes[header + name_len..]);
+    if file_len > frame.left {
+        frame.read_rest(&mut bytes).await?;
+        return Ok((ser::Msg::from_bytes(&bytes)?, None));
+    }
+    let name = String::from_utf8_lossy(&bytes[header..header + name_len]).into_owned();
+    let received = frame.save(dir, &name, file_len).await?;
+    // The message is decoded with no bytes of the file.
+    bytes.truncate(header + name_len);
+    bytes.extend(layout.empty_len());
+    frame.read_rest(&mut bytes).await?;
+    let msg = ser::Msg::from_bytes(&bytes)?;
+    match &msg {
+        ser::Msg::DataFrom {
+            data: Data::File(file),
+            ..
+        } if file.name == name && file.bytes.is_empty() => Ok((msg, Some(received))),
+        _ => Err(DeserializeMsg(Box::new(bincode::ErrorKind::Custom(
+            "the file is not where the layout of the message says".to_string(),
+        )))),
+    }
+}
+
+/// See [ser::Msg::receive_saving_files_with], the file is taken out of the message decoded whole.
+async fn receive_decoding_files(
+    codec: Codec,
+    stream: &mut (impl AsyncRead + Unpin),
+    dir: &Path,
+    progress: impl FnMut(u64, u64),
+) -> Result<(ser::Msg, Option<Result<ReceivedFile>>)> {
+    let len = read_len(stream).await?;
+    let mut bytes = Vec::new();
+    Frame::new(stream, len, progress)
+        .read_rest(&mut bytes)
+        .await?;
+    let mut msg = ser::Msg::from_bytes_with(codec, &bytes)?;
+    let received = match &mut msg {
+        ser::Msg::DataFrom {
+            data: Data::File(file),
+            ..
+        } => {
+            let bytes = std::mem::take(&mut file.bytes);
+            Some(save_received(dir, &file.name, &bytes).await)
+        }
+        _ => None,
+    };
+    Ok((msg, received))
+}
+
+/// Temporary path in the `dir` of a file named `name` being received.
+fn part_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!(".{}.part", sanitize_file_name(name)))
+}
+
+/// Writes the `bytes` of a file named `name`, received whole, to a temporary file in the `dir`.
+async fn save_received(dir: &Path, name: &str, bytes: &[u8]) -> Result<ReceivedFile> {
+    let (mut file, path) = create_new_file(part_path(dir, name))
+        .await
+        .map_err(SaveFile)?;
+    // The temporary file is removed when the received one is dropped.
+    let received = ReceivedFile {
+        path,
+        name: name.to_string(),
+    };
+    file.write_all(bytes).await.map_err(SaveFile)?;
+    file.flush().await.map_err(SaveFile)?;
+    Ok(received)
+}
+
+/// Layout of a [ser::Msg::DataFrom] with a [File] encoded by the default [Codec], found out by encoding probes.
+///
+/// The variant indices (the `prefix`) come first, then the length of the name, the name
+/// and the length of the bytes, the bytes and the other fields follow.
+struct FileLayout {
+    prefix: Vec<u8>,
+    /// Bytes of an encoded length.
+    len_size: usize,
+}
+impl FileLayout {
+    fn new() -> Self {
+        let probe = |name: &str| {
+            ser::Msg::DataFrom {
+                data: Data::File(File::new(name, Bytes::new())),
+                from: User(String::new()),
+                msg_id: None,
+                display_name: None,
+                mentions: vec![],
+                guest: false,
+                urgent: false,
+            }
+            .to_bytes()
+            .expect("the probe is serializable")
+        };
+        // The probes differ from the length of the name on.
+        let (unnamed, named) = (probe(""), probe("x"));
+        let prefix_len = unnamed
+            .iter()
+            .zip(named.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        FileLayout {
+            prefix: unnamed[..prefix_len].to_vec(),
+            len_size: FileLayout::encode_len(0).len(),
+        }
+    }
+
+    fn encode_len(len: u64) -> Vec<u8> {
+        bincode::serialize(&len).expect("lengths are serializable")
+    }
+
+    /// The length of nothing.
+    fn empty_len(&self) -> Vec<u8> {
+        FileLayout::encode_len(0)
+    }
+
+    /// Decodes the length the `bytes` start with, [usize::MAX] when it is no length.
+    fn len(&self, bytes: &[u8]) -> usize {
+        bincode::deserialize::<u64>(&bytes[..self.len_size])
+            .ok()
+            .and_then(|len| usize::try_from(len).ok())
+            .unwrap_or(usize::MAX)
+    }
+}
+
+/// Frame being read from the stream, the bytes read so far and the total are reported to `progress`.
+struct Frame<'a, R, P> {
+    stream: &'a mut R,
+    total: usize,
+    /// Bytes not read yet.
+    left: usize,
+    progress: P,
+}
+impl<'a, R: AsyncRead + Unpin, P: FnMut(u64, u64)> Frame<'a, R, P> {
+    fn new(stream: &'a mut R, total: usize, progress: P) -> Self {
+        Frame {
+            stream,
+            total,
+            left: total,
+            progress,
+        }
+    }
+
+    /// Reads the next bytes of the frame to fill the `buf` in chunks.
+    async fn read(&mut self, buf: &mut [u8]) -> Result<()> {
+        for chunk in buf.chunks_mut(CHUNK) {
+            self.stream.read_exact(chunk).await.map_err(receive_error)?;
+            self.left -= chunk.len();
+            (self.progress)((self.total - self.left) as u64, self.total as u64);
+        }
+        Ok(())
+    }
+
+    /// Appends `n` next bytes of the frame to the `bytes`.
+    ///
+    /// The lengths are not trusted, the buffer grows by a chunk at a time as the bytes arrive.
+    async fn read_more(&mut self, bytes: &mut Vec<u8>, n: usize) -> Result<()> {
+        let end = bytes.len() + n;
+        while bytes.len() < end {
+            let start = bytes.len();
+            bytes.resize(end.min(start + CHUNK), 0);
+            if let Err(e) = self.read(&mut bytes[start..]).await {
+                bytes.truncate(start);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn read_rest(&mut self, bytes: &mut Vec<u8>) -> Result<()> {
+        self.read_more(bytes, self.left).await
+    }
+
+    /// Writes the next `len` bytes of the frame to a temporary file in the `dir` for the file named `name`.
+    ///
+    /// The outer error is of the stream, the inner one of the file,
+    /// a file which can not be written is removed and the rest of its bytes is skipped.
+    async fn save(
+        &mut self,
+        dir: &Path,
+        name: &str,
+        mut len: usize,
+    ) -> Result<Result<ReceivedFile>> {
+        let mut target = create_new_file(part_path(dir, name))
+            .await
+            .map(|(file, path)| {
+                let received = ReceivedFile {
+                    path,
+                    name: name.to_string(),
+                };
+                (file, received)
+            });
+        let mut chunk = vec![0u8; CHUNK.min(len)];
+        while len > 0 {
+            let chunk = &mut chunk[..CHUNK.min(len)];
+            self.read(chunk).await?;
+            len -= chunk.len();
+            if let Ok((file, _)) = &mut target {
+                // The temporary file is removed when the received one is dropped.
+                if let Err(e) = file.write_all(chunk).await {
+                    target = Err(e);
+                }
+            }
+        }
+        let (mut file, received) = match target {
+            Ok(target) => target,
+            Err(e) => return Ok(Err(SaveFile(e))),
+        };
+        Ok(file.flush().await.map(|_| received).map_err(SaveFile))
+    }
+}
+
 /// Writes bytes to the async writer, use it alongside [read_bytes].
 // todo: tried to use future.and_then, but the writer was borrowed multiple times...
-pub async fn write_bytes(
-    writer: &mut (impl AsyncWriteExt + std::marker::Unpin),
+pub(crate) async fn write_bytes(
+    writer: &mut (impl AsyncWrite + Unpin),
+    bytes: &[u8],
+) -> Result<()> {
+    write_bytes_with_progress(writer, bytes, |_, _| ()).await
+}
+
+/// Writes bytes like [write_bytes] in chunks, the bytes written so far and the total are reported to `progress`.
+async fn write_bytes_with_progress(
+    writer: &mut (impl AsyncWrite + Unpin),
     bytes: &[u8],
+    mut progress: impl FnMut(u64, u64),
 ) -> Result<()> {
     fn map_err(e: io::Error) -> Error {
-        if e.kind() == ErrorKind::BrokenPipe {
+        if e.kind() == io::ErrorKind::BrokenPipe {
             DisconnectedStream(e)
         } else {
             SendBytes(e)
         }
     }
 
+    if bytes.len() > MAX_FRAME_SIZE {
+        return Err(FrameTooLarge(bytes.len()));
+    }
     writer
-        .write_u32(bytes.len() as u32)
+        .write_all(&(bytes.len() as u32).to_be_bytes())
         .await
         .map_err(map_err)?;
-    writer.write_all(bytes).await.map_err(map_err)?;
+    let mut written = 0;
+    for chunk in bytes.chunks(CHUNK) {
+        writer.write_all(chunk).await.map_err(map_err)?;
+        written += chunk.len();
+        progress(written as u64, bytes.len() as u64);
+    }
     writer.flush().await.map_err(map_err)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    /// Time a frame has to arrive in the tests of [Messageable::receive_reserving].
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cli_ser_{name}_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Black PNG of the size, encoded in memory.
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image::DynamicImage::new_rgb8(width, height)
+            .write_to(&mut Cursor::new(&mut bytes), ImageOutputFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    fn data_from(data: Data) -> ser::Msg {
+        ser::Msg::DataFrom {
+            data,
+            from: "sender".to_string().into(),
+            msg_id: Some(7),
+            display_name: None,
+            mentions: vec![],
+            guest: false,
+            urgent: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn reserved_before_read() {
+        let msg = data_from(Data::Text("reserved".to_string()));
+        let (mut reader, mut writer) = tokio::io::duplex(CHUNK);
+        msg.send(&mut writer).await.unwrap();
+        let frame = msg.to_bytes().unwrap().len();
+        let (reserved, len, received) = ser::Msg::receive_reserving(
+            Codec::Bincode,
+            &mut reader,
+            |len| async move { len * 2 },
+            TIMEOUT,
+        )
+        .await
+        .unwrap();
+        assert_eq!((reserved, len), (frame * 2, frame));
+        assert_eq!(received.unwrap(), msg);
+
+        // A frame cut short is of the stream, after the reservation.
+        writer.write_all(&100u32.to_be_bytes()).await.unwrap();
+        writer.write_all(&[0; 10]).await.unwrap();
+        drop(writer);
+        let mut reserved = None;
+        let result = ser::Msg::receive_reserving(
+            Codec::Bincode,
+            &mut reader,
+            |len| {
+                reserved = Some(len);
+                std::future::ready(())
+            },
+            TIMEOUT,
+        )
+        .await;
+        assert!(matches!(result, Err(DisconnectedStream(_))));
+        assert_eq!(reserved, Some(100));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stalled_and_oversized_frames() {
+        let reservation = Arc::new(());
+        let (mut reader, mut writer) = tokio::io::duplex(CHUNK);
+
+        // Nothing is reserved for a frame longer than the maximum.
+        let too_long = (MAX_FRAME_SIZE + 1) as u32;
+        writer.write_all(&too_long.to_be_bytes()).await.unwrap();
+        let result = ser::Msg::receive_reserving(
+            Codec::Bincode,
+            &mut reader,
+            |_| async { unreachable!("reserved for an oversized frame") },
+            TIMEOUT,
+        )
+        .await;
+        assert!(matches!(result, Err(FrameTooLarge(len)) if len == MAX_FRAME_SIZE + 1));
+
+        // The rest of a frame which never comes is given up along with its reservation.
+        writer
+            .write_all(&(MAX_FRAME_SIZE as u32).to_be_bytes())
+            .await
+            .unwrap();
+        writer.write_all(&[0; 10]).await.unwrap();
+        let reserve = |_| {
+            let reservation = reservation.clone();
+            async move { reservation }
+        };
+        let result =
+            ser::Msg::receive_reserving(Codec::Bincode, &mut reader, reserve, TIMEOUT).await;
+        assert!(matches!(result, Err(TimedOut(t)) if t == TIMEOUT));
+        assert_eq!(Arc::strong_count(&reservation), 1);
+
+        let oversized = vec![0; MAX_FRAME_SIZE + 1];
+        let sent = write_bytes(&mut writer, &oversized).await;
+        assert!(matches!(sent, Err(FrameTooLarge(_))));
+    }
+
+    #[tokio::test]
+    async fn receive_saving_files() {
+        let dir = temp_dir("streamed");
+        let path = dir.join("big.bin");
+        let bytes: Vec<u8> = (0..200_000).map(|i| i as u8).collect();
+        std::fs::write(&path, &bytes).unwrap();
+        let file = File::from_path(&path).await.unwrap();
+
+        let (mut writer, mut reader) = tokio::io::duplex(1024);
+        let sent = tokio::spawn(async move {
+            let mut sent = (0, 0);
+            data_from(Data::File(file))
+                .send_with_progress(&mut writer, |done, total| sent = (done, total))
+                .await
+                .unwrap();
+            assert!(sent.0 == sent.1 && sent.1 > 200_000, "{sent:?}");
+            data_from(Data::Text("text".to_string()))
+                .send(&mut writer)
+                .await
+                .unwrap();
+        });
+        let mut progress = (0, 0);
+        let (msg, received) = ser::Msg::receive_saving_files(&mut reader, &dir, |done, total| {
+            progress = (done, total)
+        })
+        .await
+        .unwrap();
+        assert!(
+            progress.0 == progress.1 && progress.1 > 200_000,
+            "{progress:?}"
+        );
+        let received = received.unwrap().unwrap();
+        assert_eq!(received.name(), "big.bin");
+        assert_eq!(std::fs::read(received.path()).unwrap(), bytes);
+        // Nothing but the temporary file is written until the file is kept.
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        let kept = received.keep(&dir, OnCollision::Rename).await.unwrap();
+        assert_eq!(kept, dir.join("big (1).bin"));
+        assert_eq!(std::fs::read(&kept).unwrap(), bytes);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        let ser::Msg::DataFrom {
+            data: Data::File(file),
+            msg_id,
+            ..
+        } = msg
+        else {
+            panic!("{msg:?}");
+        };
+        assert_eq!((file.name(), file.size(), msg_id), ("big.bin", 0, Some(7)));
+
+        let (msg, received) = ser::Msg::receive_saving_files(&mut reader, &dir, |_, _| ())
+            .await
+            .unwrap();
+        assert_eq!(msg, data_from(Data::Text("text".to_string())));
+        assert!(received.is_none());
+        sent.await.unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn frame_grows_as_read() {
+        let (mut reader, mut writer) = tokio::io::duplex(CHUNK);
+        writer.write_all(&[1; 10]).await.unwrap();
+        drop(writer);
+        let mut frame = Frame::new(&mut reader, MAX_FRAME_SIZE, |_, _| ());
+        let mut bytes = Vec::new();
+        let read = frame.read_rest(&mut bytes).await;
+        assert!(matches!(read, Err(DisconnectedStream(_))));
+        // Nothing near the claimed length was allocated for the bytes which never came.
+        assert!(bytes.capacity() <= CHUNK, "{}", bytes.capacity());
+    }
+
+    #[cfg(feature = "json")]
+    #[tokio::test]
+    async fn receive_saving_files_decoded_whole() {
+        let dir = temp_dir("decoded");
+        let (mut writer, mut reader) = tokio::io::duplex(1024);
+        let msg = data_from(Data::File(File::new("notes.txt", b"json".to_vec())));
+        let sent = tokio::spawn(async move { msg.send_with(Codec::Json, &mut writer).await });
+        let (msg, received) =
+            ser::Msg::receive_saving_files_with(Codec::Json, &mut reader, &dir, |_, _| ())
+                .await
+                .unwrap();
+        sent.await.unwrap().unwrap();
+        let received = received.unwrap().unwrap();
+        assert_eq!(std::fs::read(received.path()).unwrap(), b"json");
+        assert_eq!(
+            msg,
+            data_from(Data::File(File::new("notes.txt", Vec::new())))
+        );
+        drop(received);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn received_files_kept_or_removed() {
+        let dir = temp_dir("received");
+        let receive = |name: &'static str, content: &'static [u8]| {
+            let dir = dir.clone();
+            async move {
+                let (mut writer, mut reader) = tokio::io::duplex(1024);
+                let msg = data_from(Data::File(File::new(name, content)));
+                let sent = tokio::spawn(async move { msg.send(&mut writer).await });
+                let (_, received) = ser::Msg::receive_saving_files(&mut reader, &dir, |_, _| ())
+                    .await
+                    .unwrap();
+                sent.await.unwrap().unwrap();
+                received.unwrap().unwrap()
+            }
+        };
+        std::fs::write(dir.join("notes.txt"), "old").unwrap();
+
+        let overwritten = receive("notes.txt", b"new")
+            .await
+            .keep(&dir, OnCollision::Overwrite)
+            .await
+            .unwrap();
+        assert_eq!(overwritten, dir.join("notes.txt"));
+        assert_eq!(std::fs::read_to_string(&overwritten).unwrap(), "new");
+
+        let discarded = receive("notes.txt", b"newer").await;
+        let temp = discarded.path().to_path_buf();
+        discarded.discard().await.unwrap();
+        assert!(!temp.exists());
+        let dropped = receive("notes.txt", b"newest").await;
+        let temp = dropped.path().to_path_buf();
+        drop(dropped);
+        assert!(!temp.exists());
+        assert_eq!(
+            std::fs::read_to_string(dir.join("notes.txt")).unwrap(),
+            "new"
+        );
+
+        // The name comes from the sender, it can not lead out of the directory.
+        let kept = receive("../escape.txt", b"x")
+            .await
+            .keep(&dir, OnCollision::Rename)
+            .await
+            .unwrap();
+        assert_eq!(kept, dir.join("escape.txt"));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn image_from_bytes() {
+        let image = Image::from_bytes(png(3, 2)).unwrap();
+        assert_eq!(image.format(), ImageFormat::Png);
+        assert_eq!(image.dimensions().unwrap(), (3, 2));
+        assert!(matches!(
+            Image::from_bytes(b"not an image".to_vec()),
+            Err(DecodeImg(_))
+        ));
+        // A known header is not enough, the image has to decode.
+        let mut cut = png(3, 2);
+        cut.truncate(cut.len() / 2);
+        assert!(matches!(Image::from_bytes(cut), Err(DecodeImg(_))));
+        let limits = ImageLimits {
+            max_width: 2,
+            ..ImageLimits::default()
+        };
+        assert!(matches!(
+            Image::from_bytes_with_limits(png(3, 2), &limits),
+            Err(ImageTooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn file_new() {
+        let file = File::new("notes.txt", b"hello".to_vec());
+        assert_eq!(
+            (file.name(), file.size(), file.len(), file.is_empty()),
+            ("notes.txt", 5, 5, false)
+        );
+        assert_eq!(file.bytes().as_ref(), b"hello");
+        assert!(File::new("empty.txt", Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn clones_share_bytes() {
+        let image = Image::from_bytes(png(1, 1)).unwrap();
+        let data = Data::Image(image.clone());
+        let Data::Image(cloned) = data.clone() else {
+            unreachable!()
+        };
+        let (original, cloned) = (Bytes::from(image), Bytes::from(cloned));
+        assert_eq!(original.as_ptr(), cloned.as_ptr());
+        let msg = cli::Msg::ToAll(data);
+        assert_eq!(cli::Msg::from_bytes(&msg.to_bytes().unwrap()).unwrap(), msg);
+
+        let file = File::new("notes.txt", b"hello".to_vec());
+        let Data::File(cloned) = Data::File(file.clone()).clone() else {
+            unreachable!()
+        };
+        assert_eq!(file.bytes().as_ptr(), cloned.bytes().as_ptr());
+    }
+
+    #[test]
+    fn envelopes_do_not_nest() {
+        let tagged = cli::Msg::Stats.tagged(MsgId(2)).to_bytes().unwrap();
+        assert_eq!(
+            cli::Msg::from_bytes(&tagged).unwrap(),
+            cli::Msg::Stats,
+            "the id is dropped"
+        );
+        let bare = cli::Msg::Stats.to_bytes().unwrap();
+        assert_eq!(
+            cli::Envelope::from_bytes(&bare).unwrap(),
+            cli::Envelope::from(cli::Msg::Stats)
+        );
+
+        // A tagged envelope where the message should be is not a message.
+        let mut nested = vec![1];
+        nested.extend(1u64.to_le_bytes());
+        nested.extend_from_slice(&tagged);
+        assert!(matches!(
+            cli::Envelope::from_bytes(&nested),
+            Err(DeserializeMsg(_))
+        ));
+        // However deep, it is rejected without recursing.
+        let deep: Vec<u8> = [1, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff].repeat(100_000);
+        assert!(matches!(
+            cli::Envelope::from_bytes(&deep),
+            Err(DeserializeMsg(_))
+        ));
+        #[cfg(feature = "json")]
+        assert!(matches!(
+            cli::Envelope::from_bytes_with(
+                Codec::Json,
+                br#"{"id":1,"msg":{"id":2,"msg":"Stats"}}"#
+            ),
+            Err(DeserializeJson(_))
+        ));
+    }
+
+    #[test]
+    fn codec_ids() {
+        assert_eq!(Codec::Bincode.id(), 0);
+        #[cfg(feature = "postcard")]
+        assert_eq!(Codec::Postcard.id(), 1);
+        #[cfg(feature = "json")]
+        assert_eq!(Codec::Json.id(), 2);
+        for &codec in Codec::ALL {
+            assert_eq!(Codec::from_id(codec.id()), Some(codec));
+        }
+        assert_eq!(Codec::from_id(200), None);
+    }
+
+    #[test]
+    fn codec_chosen() {
+        // Unknown ids are skipped, nothing known falls back to bincode.
+        assert_eq!(Codec::choose(&[200, 0]), Codec::Bincode);
+        assert_eq!(Codec::choose(&[200]), Codec::Bincode);
+        assert_eq!(Codec::choose(&[]), Codec::Bincode);
+        #[cfg(feature = "postcard")]
+        assert_eq!(Codec::choose(&[1, 0]), Codec::Postcard);
+        #[cfg(feature = "json")]
+        assert_eq!(Codec::choose(&[2, 0]), Codec::Json);
+    }
+
+    #[test]
+    fn codec_serialized_by_id() {
+        // The id is the wire form, it does not depend on the enabled features.
+        let chosen = ser::Msg::CodecChosen(Codec::Bincode).to_bytes().unwrap();
+        assert_eq!(chosen.last(), Some(&Codec::Bincode.id()));
+        let mut unknown = chosen.to_vec();
+        *unknown.last_mut().unwrap() = 200;
+        assert!(ser::Msg::from_bytes(&unknown).is_err());
+    }
+
+    #[test]
+    fn file_layout_of_the_codec() {
+        let layout = FileLayout::new();
+        let msg = data_from(Data::File(File::new("notes.txt", b"hello".to_vec())));
+        let bytes = msg.to_bytes().unwrap();
+        assert!(bytes.starts_with(&layout.prefix));
+        let name_at = layout.prefix.len() + layout.len_size;
+        assert_eq!(layout.len(&bytes[layout.prefix.len()..]), "notes.txt".len());
+        assert_eq!(&bytes[name_at..name_at + 9], b"notes.txt");
+        assert_eq!(layout.len(&bytes[name_at + 9..]), 5);
+        let text = data_from(Data::Text("notes.txt".to_string()))
+            .to_bytes()
+            .unwrap();
+        assert!(!text.starts_with(&layout.prefix));
+    }
+}
@@ -0,0 +1,186 @@
+//! Deadlines of sending and receiving and retries with backoff, so that a stalled peer can not hang a task.
+//!
+//! ```no_run
+//! # async fn chat(stream: &mut tokio::net::TcpStream) -> cli_ser::Result<()> {
+//! use std::time::Duration;
+//!
+//! use cli_ser::{retry::*, ser};
+//!
+//! let msg: ser::Msg = receive_with_timeout(stream, Duration::from_secs(30)).await?;
+//! # Ok(())
+//! # }
+//! ```
+use std::{future::Future, time::Duration};
+
+use crate::{
+    rt::{self, AsyncRead, AsyncWrite, Executor, Runtime},
+    Error::TimedOut,
+    Messageable, Result,
+};
+
+/// How long a peer has to answer when nothing else is said, e.g. to an authentication.
+pub const TIMEOUT_DEFAULT: Duration = Duration::from_secs(30);
+
+/// Awaits the `future` for the `timeout` at most, it is [TimedOut] then.
+pub async fn deadline<T>(timeout: Duration, future: impl Future<Output = Result<T>>) -> Result<T> {
+    rt::timeout(timeout, future)
+        .await
+        .ok_or(TimedOut(timeout))?
+}
+
+/// Sends the `msg` like [Messageable::send] unless it takes longer than the `timeout`.
+///
+/// A message timed out may be written partly, the stream should not be written on.
+pub async fn send_with_timeout<M, W>(msg: &M, writer: &mut W, timeout: Duration) -> Result<()>
+where
+    M: Messageable + Sync,
+    W: AsyncWrite + Unpin + Send,
+{
+    deadline(timeout, msg.send(writer)).await
+}
+
+/// Receives a message like [Messageable::receive] unless it does not arrive in the `timeout`.
+///
+/// A message timed out may be read partly, the stream should not be read on.
+pub async fn receive_with_timeout<M, R>(reader: &mut R, timeout: Duration) -> Result<M>
+where
+    M: Messageable,
+    R: AsyncRead + Unpin + Send,
+{
+    deadline(timeout, M::receive(reader)).await
+}
+
+/// How many times and how often an operation is attempted by [retry].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts in total, the first one included, at least one is made.
+    pub attempts: u32,
+    /// Wait before the first retry, it doubles with each further one.
+    pub delay: Duration,
+    /// The wait does not grow over it.
+    pub max_delay: Duration,
+}
+impl Default for RetryPolicy {
+    /// Three attempts, half a second apart and then a second.
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 3,
+            delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+impl RetryPolicy {
+    /// Wait before the retry of the number, the first one is 1.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let doubled = 2_u32.saturating_pow(retry.saturating_sub(1));
+        self.delay.saturating_mul(doubled).min(self.max_delay)
+    }
+}
+
+/// Runs the `op` until it succeeds or the [attempts][RetryPolicy::attempts] run out,
+/// waiting longer and longer between them, the last error is returned.
+pub async fn retry<T, E, F, Fut>(policy: &RetryPolicy, op: F) -> std::result::Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = std::result::Result<T, E>>,
+{
+    retry_if(policy, op, |_| true).await
+}
+
+/// Like [retry], only errors which are `retryable` are retried, others are returned right away.
+pub async fn retry_if<T, E, F, Fut>(
+    policy: &RetryPolicy,
+    mut op: F,
+    retryable: impl Fn(&E) -> bool,
+) -> std::result::Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = std::result::Result<T, E>>,
+{
+    let mut retries = 0;
+    loop {
+        match op().await {
+            Err(e) if retries + 1 < policy.attempts && retryable(&e) => {
+                retries += 1;
+                Runtime::sleep(policy.delay(retries)).await;
+            }
+            result => break result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use tokio::time::Instant;
+
+    use super::*;
+    use crate::{cli, Error};
+
+    #[tokio::test(start_paused = true)]
+    async fn stalled_peer_times_out() {
+        let (mut client, mut server) = tokio::io::duplex(64);
+        let timeout = Duration::from_secs(10);
+        let started = Instant::now();
+        let received = receive_with_timeout::<cli::Msg, _>(&mut server, timeout).await;
+        assert!(matches!(received, Err(TimedOut(t)) if t == timeout));
+        assert_eq!(started.elapsed(), timeout);
+
+        // Nobody reads, the buffer of the duplex fills up.
+        let msg = cli::Msg::Urgent("x".repeat(1000));
+        let sent = send_with_timeout(&msg, &mut client, timeout).await;
+        assert!(matches!(sent, Err(TimedOut(_))));
+
+        let msg = cli::Msg::Stats;
+        let (mut client, mut server) = tokio::io::duplex(64);
+        send_with_timeout(&msg, &mut client, timeout).await.unwrap();
+        let received: cli::Msg = receive_with_timeout(&mut server, timeout).await.unwrap();
+        assert_eq!(received, msg);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retried_with_backoff() {
+        let policy = RetryPolicy {
+            attempts: 4,
+            delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(3),
+        };
+        assert_eq!(policy.delay(1), Duration::from_secs(1));
+        assert_eq!(policy.delay(2), Duration::from_secs(2));
+        assert_eq!(policy.delay(3), Duration::from_secs(3));
+
+        let calls = AtomicU32::new(0);
+        let started = Instant::now();
+        let result: std::result::Result<u32, u32> = retry(&policy, || async {
+            match calls.fetch_add(1, Ordering::SeqCst) {
+                n if n < 2 => Err(n),
+                n => Ok(n),
+            }
+        })
+        .await;
+        assert_eq!(result, Ok(2));
+        assert_eq!(started.elapsed(), Duration::from_secs(3));
+
+        calls.store(0, Ordering::SeqCst);
+        let result: std::result::Result<(), u32> = retry(&policy, || async {
+            Err(calls.fetch_add(1, Ordering::SeqCst))
+        })
+        .await;
+        assert_eq!(result, Err(3));
+
+        calls.store(0, Ordering::SeqCst);
+        let result: std::result::Result<(), Error> = retry_if(
+            &policy,
+            || async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err(Error::UnsupportedMedia)
+            },
+            |e| matches!(e, TimedOut(_)),
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,135 @@
+//! Async primitives of the runtime chosen by the features.
+//!
+//! * `tokio` (default) - [tokio](https://docs.rs/tokio) traits, file system, blocking tasks and timers.
+//! * `futures-io` - [futures-io](https://docs.rs/futures-io) traits used by `async-std`, `smol` and others,
+//!   the file system is provided by [async-fs](https://docs.rs/async-fs), blocking tasks by
+//!   [blocking](https://docs.rs/blocking) and timers by [async-io](https://docs.rs/async-io),
+//!   all of them run on any executor.
+//!
+//! Blocking tasks and timers are used through the [Executor] trait of the [Runtime], see also [timeout].
+//!
+//! When both are enabled, `tokio` wins, so disable the default features to use `futures-io`:
+//! ```toml
+//! cli-ser = { version = "0.2.0", default-features = false, features = ["futures-io"] }
+//! ```
+use std::{
+    future::{poll_fn, Future},
+    pin::pin,
+    task::Poll,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+
+#[cfg(feature = "tokio")]
+pub use tokio::{
+    fs,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+};
+
+#[cfg(all(feature = "futures-io", not(feature = "tokio")))]
+pub use {
+    async_fs as fs,
+    futures_lite::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+};
+
+#[cfg(not(any(feature = "tokio", feature = "futures-io")))]
+compile_error!(
+    "cli-ser needs an async runtime, enable the \"tokio\" or the \"futures-io\" feature"
+);
+
+/// The [Executor] chosen by the features.
+#[cfg(feature = "tokio")]
+pub type Runtime = Tokio;
+/// The [Executor] chosen by the features.
+#[cfg(all(feature = "futures-io", not(feature = "tokio")))]
+pub type Runtime = FuturesIo;
+
+/// Blocking tasks and timers of a runtime.
+#[async_trait]
+pub trait Executor {
+    /// Runs the blocking `f` on a thread where it does not hold up other tasks, returns its result.
+    ///
+    /// A panic of `f` is resumed in the caller.
+    async fn spawn_blocking<T, F>(f: F) -> T
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static;
+
+    /// Waits for the `duration`.
+    async fn sleep(duration: Duration);
+}
+
+/// Awaits the `future` for the `duration` at most, measured by the [Runtime], `None` when it did not finish by then.
+pub async fn timeout<T>(duration: Duration, future: impl Future<Output = T>) -> Option<T> {
+    let mut future = pin!(future);
+    let mut sleep = pin!(Runtime::sleep(duration));
+    poll_fn(|cx| match future.as_mut().poll(cx) {
+        Poll::Ready(output) => Poll::Ready(Some(output)),
+        Poll::Pending => sleep.as_mut().poll(cx).map(|()| None),
+    })
+    .await
+}
+
+/// [Executor] of [tokio](https://docs.rs/tokio), its timers follow the paused clock of tests.
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, Copy)]
+pub struct Tokio;
+#[cfg(feature = "tokio")]
+#[async_trait]
+impl Executor for Tokio {
+    async fn spawn_blocking<T, F>(f: F) -> T
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        match tokio::task::spawn_blocking(f).await {
+            Ok(output) => output,
+            // Blocking tasks are not aborted, they are cancelled only by the runtime shutting down.
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+
+    async fn sleep(duration: Duration) {
+        tokio::time::sleep(duration).await
+    }
+}
+
+/// [Executor] running under any executor, e.g. of `async-std` or `smol`.
+#[cfg(feature = "futures-io")]
+#[derive(Debug, Clone, Copy)]
+pub struct FuturesIo;
+#[cfg(feature = "futures-io")]
+#[async_trait]
+impl Executor for FuturesIo {
+    async fn spawn_blocking<T, F>(f: F) -> T
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        blocking::unblock(f).await
+    }
+
+    async fn sleep(duration: Duration) {
+        async_io::Timer::after(duration).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn timed_out() {
+        let slow = Runtime::sleep(Duration::from_secs(2));
+        assert_eq!(timeout(Duration::from_secs(1), slow).await, None);
+        assert_eq!(timeout(Duration::from_secs(1), async { 7 }).await, Some(7));
+    }
+
+    #[tokio::test]
+    async fn spawn_blocking() {
+        assert_eq!(Runtime::spawn_blocking(|| 7).await, 7);
+        let panicked = tokio::spawn(Runtime::spawn_blocking(|| panic!("blocking")));
+        assert!(panicked.await.unwrap_err().is_panic());
+    }
+}
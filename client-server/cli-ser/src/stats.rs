@@ -0,0 +1,285 @@
+//! Traffic of a connection, counted as it passes the stream, see [ConnectionStats].
+#[cfg(feature = "tokio")]
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "tokio")]
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Shared handle to the traffic of a connection, its clones count into the same numbers.
+///
+/// Streams [counted][Self::count] by it add the bytes passing them and the messages, their whole frames,
+/// so the numbers are the same whichever way the messages are sent and received.
+/// The server shows them per client, the client of its connection.
+///
+/// ```
+/// # async fn chat() -> cli_ser::Result<()> {
+/// use cli_ser::{cli, ConnectionStats, Messageable};
+///
+/// let stats = ConnectionStats::new();
+/// let (client, _server) = tokio::io::duplex(1024);
+/// let mut client = stats.count(client);
+/// cli::Msg::Stats.send(&mut client).await?;
+/// assert_eq!(stats.snapshot().messages_sent, 1);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ConnectionStats(Arc<Inner>);
+
+#[derive(Debug)]
+struct Inner {
+    since: Instant,
+    sent: Direction,
+    received: Direction,
+}
+
+/// Numbers of one direction of the traffic.
+#[derive(Debug, Default)]
+struct Direction {
+    bytes: AtomicU64,
+    messages: AtomicU64,
+    last: Mutex<Option<Instant>>,
+}
+impl Direction {
+    fn add(&self, bytes: usize, messages: u64) {
+        if bytes == 0 {
+            return;
+        }
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.messages.fetch_add(messages, Ordering::Relaxed);
+        *self.last.lock().expect("stats lock poisoned") = Some(Instant::now());
+    }
+}
+
+impl Default for ConnectionStats {
+    fn default() -> Self {
+        ConnectionStats(Arc::new(Inner {
+            since: Instant::now(),
+            sent: Direction::default(),
+            received: Direction::default(),
+        }))
+    }
+}
+impl ConnectionStats {
+    /// Numbers of a connection starting now.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wraps the `stream`, the traffic passing it is counted here.
+    pub fn count<S>(&self, stream: S) -> Counted<S> {
+        Counted {
+            inner: stream,
+            stats: self.clone(),
+            reading: Frames::default(),
+            writing: Frames::default(),
+        }
+    }
+
+    /// Numbers at the moment.
+    pub fn snapshot(&self) -> Traffic {
+        let Inner { sent, received, .. } = &*self.0;
+        let last = |direction: &Direction| *direction.last.lock().expect("stats lock poisoned");
+        Traffic {
+            bytes_sent: sent.bytes.load(Ordering::Relaxed),
+            bytes_received: received.bytes.load(Ordering::Relaxed),
+            messages_sent: sent.messages.load(Ordering::Relaxed),
+            messages_received: received.messages.load(Ordering::Relaxed),
+            last_sent: last(sent),
+            last_received: last(received),
+        }
+    }
+
+    /// Time since anything was sent or received, since the stats were made when nothing was.
+    pub fn idle(&self) -> Duration {
+        self.snapshot()
+            .last_activity()
+            .unwrap_or(self.0.since)
+            .elapsed()
+    }
+}
+
+/// Traffic of a connection at one moment, see [ConnectionStats::snapshot].
+///
+/// Bytes include the lengths prefixing the frames, a message is counted once its frame passed whole.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Traffic {
+    /// Bytes written to the stream.
+    pub bytes_sent: u64,
+    /// Bytes read from the stream.
+    pub bytes_received: u64,
+    /// Whole frames written.
+    pub messages_sent: u64,
+    /// Whole frames read.
+    pub messages_received: u64,
+    /// When the last byte was written, `None` before the first one.
+    pub last_sent: Option<Instant>,
+    /// When the last byte was read, `None` before the first one.
+    pub last_received: Option<Instant>,
+}
+impl Traffic {
+    /// When the last byte was sent or received.
+    pub fn last_activity(&self) -> Option<Instant> {
+        self.last_sent.max(self.last_received)
+    }
+}
+
+/// Stream whose traffic is counted by [ConnectionStats], it is read and written as the stream it wraps.
+///
+/// The counting needs the `tokio` feature.
+#[derive(Debug)]
+pub struct Counted<S> {
+    inner: S,
+    stats: ConnectionStats,
+    reading: Frames,
+    writing: Frames,
+}
+impl<S> Counted<S> {
+    /// Handle the traffic is counted by.
+    pub fn stats(&self) -> &ConnectionStats {
+        &self.stats
+    }
+
+    /// The wrapped stream.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// The wrapped stream, a frame passed partly is not counted by a stream wrapped again.
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<S: AsyncRead + Unpin> AsyncRead for Counted<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            let read = &buf.filled()[before..];
+            let messages = this.reading.advance(read);
+            this.stats.0.received.add(read.len(), messages);
+        }
+        poll
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<S: AsyncWrite + Unpin> AsyncWrite for Counted<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            let messages = this.writing.advance(&buf[..written]);
+            this.stats.0.sent.add(written, messages);
+        }
+        poll
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// Where the bytes passing one direction are within the frames, see [read_bytes][crate::read_bytes].
+#[derive(Debug, Default)]
+struct Frames {
+    /// Length of the current frame as far as it passed.
+    prefix: [u8; 4],
+    prefix_len: usize,
+    /// Bytes of the current frame yet to pass once its length did.
+    left: u64,
+}
+impl Frames {
+    /// Goes over the bytes, returns how many frames they finished.
+    fn advance(&mut self, mut bytes: &[u8]) -> u64 {
+        let mut finished = 0;
+        while !bytes.is_empty() {
+            if self.prefix_len < self.prefix.len() {
+                let take = (self.prefix.len() - self.prefix_len).min(bytes.len());
+                self.prefix[self.prefix_len..][..take].copy_from_slice(&bytes[..take]);
+                self.prefix_len += take;
+                bytes = &bytes[take..];
+                if self.prefix_len < self.prefix.len() {
+                    break;
+                }
+                self.left = u32::from_be_bytes(self.prefix) as u64;
+            } else {
+                let take = self.left.min(bytes.len() as u64);
+                self.left -= take;
+                bytes = &bytes[take as usize..];
+            }
+            if self.left == 0 {
+                finished += 1;
+                self.prefix_len = 0;
+            }
+        }
+        finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cli, Messageable};
+
+    #[test]
+    fn frames_split_anywhere() {
+        let mut frames = Frames::default();
+        // Frames of 3 and 0 bytes, then the start of one of 2.
+        let bytes = [0, 0, 0, 3, b'a', b'b', b'c', 0, 0, 0, 0, 0, 0, 0, 2, b'x'];
+        assert_eq!(frames.advance(&bytes[..2]), 0);
+        assert_eq!(frames.advance(&bytes[2..5]), 0);
+        assert_eq!(frames.advance(&bytes[5..11]), 2);
+        assert_eq!(frames.advance(&bytes[11..]), 0);
+        assert_eq!(frames.advance(b"y"), 1);
+        assert_eq!(frames.advance(&[]), 0);
+    }
+
+    #[tokio::test]
+    async fn both_directions_counted() {
+        let (client, server) = tokio::io::duplex(1024);
+        let (client_stats, server_stats) = (ConnectionStats::new(), ConnectionStats::new());
+        let (mut client, mut server) = (client_stats.count(client), server_stats.count(server));
+        let msg = cli::Msg::Urgent("hello".to_string());
+        msg.send(&mut client).await.unwrap();
+        msg.send(&mut client).await.unwrap();
+        let frame = 4 + msg.to_bytes().unwrap().len() as u64;
+        assert_eq!(cli::Msg::receive(&mut server).await.unwrap(), msg);
+
+        let sent = client_stats.snapshot();
+        assert_eq!((sent.messages_sent, sent.bytes_sent), (2, 2 * frame));
+        assert_eq!((sent.messages_received, sent.bytes_received), (0, 0));
+        assert!(sent.last_sent.is_some() && sent.last_received.is_none());
+        let received = server_stats.snapshot();
+        assert_eq!(received.messages_received, 1);
+        assert!(received.bytes_received >= frame);
+        assert_eq!(received.last_activity(), received.last_received);
+        assert!(server_stats.idle() < Duration::from_secs(60));
+    }
+}
@@ -0,0 +1,48 @@
+//! The client of [Connection] passes the client conformance suite.
+//!
+//! ```sh
+//! cargo test --features conformance
+//! ```
+use std::net::SocketAddr;
+
+use async_trait::async_trait;
+
+use cli_ser::{cli, conformance, conn::Connection, ser, Data};
+
+/// [Connection] driven by the suite, the last one made.
+#[derive(Default)]
+struct ConnectionClient(Option<Connection>);
+impl ConnectionClient {
+    fn connection(&mut self) -> Result<&mut Connection, String> {
+        self.0.as_mut().ok_or_else(|| "not connected".to_string())
+    }
+}
+#[async_trait]
+impl conformance::Client for ConnectionClient {
+    async fn connect(&mut self, addr: SocketAddr, auth: cli::Auth) -> Result<(), String> {
+        self.0 = None;
+        let connection = match auth {
+            cli::Auth::LogIn(creds) => Connection::connect(addr, creds).await,
+            cli::Auth::SignUp(creds) => Connection::sign_up(addr, creds).await,
+            other => return Err(format!("{other:?} is not driven")),
+        };
+        self.0 = Some(connection.map_err(|e| e.to_string())?);
+        Ok(())
+    }
+
+    async fn send(&mut self, data: Data) -> Result<(), String> {
+        let connection = self.connection()?;
+        connection.send(data).await.map_err(|e| e.to_string())
+    }
+
+    async fn recv(&mut self) -> Result<ser::Msg, String> {
+        let connection = self.connection()?;
+        connection.recv().await.map_err(|e| e.to_string())
+    }
+}
+
+#[tokio::test]
+async fn connection_conforms() {
+    let report = conformance::run_client_suite(&mut ConnectionClient::default()).await;
+    assert!(report.passed(), "\n{report}");
+}
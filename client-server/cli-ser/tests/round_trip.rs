@@ -0,0 +1,36 @@
+//! Every message decodes to itself in each codec.
+//!
+//! ```sh
+//! cargo test --features postcard,json,proptest
+//! ```
+use std::fmt::Debug;
+
+use proptest::prelude::*;
+
+use cli_ser::{cli, ser, Codec, Messageable};
+
+fn round_trip<M: Messageable + PartialEq + Debug>(msg: &M) -> Result<(), TestCaseError> {
+    for &codec in Codec::ALL {
+        let bytes = msg.to_bytes_with(codec).unwrap();
+        let decoded = M::from_bytes_with(codec, &bytes).unwrap();
+        prop_assert_eq!(&decoded, msg, "{:?}", codec);
+    }
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn client_messages(msg in any::<cli::Msg>()) {
+        round_trip(&msg)?;
+    }
+
+    #[test]
+    fn client_envelopes(envelope in any::<cli::Envelope>()) {
+        round_trip(&envelope)?;
+    }
+
+    #[test]
+    fn server_messages(msg in any::<ser::Msg>()) {
+        round_trip(&msg)?;
+    }
+}
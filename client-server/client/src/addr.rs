@@ -0,0 +1,268 @@
+//! Address of the server, a host name or an IP address with a port, resolved when connecting, see [ServerAddr].
+use std::{
+    fmt,
+    future::Future,
+    io,
+    net::{Ipv6Addr, SocketAddr},
+    str::FromStr,
+};
+
+use serde::Deserialize;
+
+use crate::PORT_DEFAULT;
+
+/// Host and port of the server, e.g. `localhost`, `chat.example.com:11111`, `10.0.0.1` or `[::1]:11111`.
+///
+/// The host is resolved on every connection, its addresses are tried one by one in the [IpPreference] order.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct ServerAddr {
+    /// Host name or IP address, IPv6 ones without brackets.
+    pub host: String,
+    pub port: u16,
+}
+impl ServerAddr {
+    /// Address of the host, it is not checked.
+    pub fn new(host: impl Into<String>, port: u16) -> Self {
+        ServerAddr {
+            host: host.into(),
+            port,
+        }
+    }
+
+    /// Parses the host with an optional port, the `port` is used when it has none.
+    /// IPv6 addresses with a port are in brackets, e.g. `[::1]:11111`.
+    pub fn parse(s: &str, port: u16) -> Result<Self, String> {
+        let s = s.trim();
+        let (host, given) = match s.strip_prefix('[') {
+            Some(rest) => match rest.split_once(']') {
+                Some((host, "")) => (host, None),
+                Some((host, rest)) => match rest.strip_prefix(':') {
+                    Some(port) => (host, Some(port)),
+                    None => {
+                        return Err(format!("unexpected `{rest}` after the address `[{host}]`"))
+                    }
+                },
+                None => return Err(format!("`{s}` lacks the closing bracket")),
+            },
+            // Colons of a bare IPv6 address are not a port.
+            None if s.parse::<Ipv6Addr>().is_ok() => (s, None),
+            None => match s.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (s, None),
+            },
+        };
+        if host.is_empty() {
+            return Err(format!("`{s}` has no host"));
+        }
+        let port = match given {
+            Some(given) => given
+                .parse()
+                .map_err(|_| format!("`{given}` is not a port number"))?,
+            None => port,
+        };
+        Ok(ServerAddr::new(host, port))
+    }
+
+    /// Looks up the addresses of the host, ordered as the `preference` says.
+    pub async fn resolve(&self, preference: IpPreference) -> io::Result<Vec<SocketAddr>> {
+        let addrs: Vec<_> = tokio::net::lookup_host((self.host.as_str(), self.port))
+            .await?
+            .collect();
+        match addrs.is_empty() {
+            true => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{self} has no address"),
+            )),
+            false => Ok(preference.order(addrs)),
+        }
+    }
+
+    /// Resolves the host and makes the `attempt` at each address in turn until one connects.
+    ///
+    /// Only failures to [connect][cli_ser::Error::Connect] or [in time][cli_ser::Error::TimedOut]
+    /// move on to the next address, others are returned right away, so is the last failure.
+    pub async fn connect<T, F, Fut>(
+        &self,
+        preference: IpPreference,
+        mut attempt: F,
+    ) -> cli_ser::Result<T>
+    where
+        F: FnMut(SocketAddr) -> Fut,
+        Fut: Future<Output = cli_ser::Result<T>>,
+    {
+        let addrs = self
+            .resolve(preference)
+            .await
+            .map_err(cli_ser::Error::Connect)?;
+        let (last, others) = addrs.split_last().expect("resolved to some address");
+        for addr in others {
+            match attempt(*addr).await {
+                Err(cli_ser::Error::Connect(_) | cli_ser::Error::TimedOut(_)) => {}
+                connected => return connected,
+            }
+        }
+        attempt(*last).await
+    }
+}
+impl FromStr for ServerAddr {
+    type Err = String;
+
+    /// Parses the host with an optional port, [PORT_DEFAULT] when it has none.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ServerAddr::parse(s, PORT_DEFAULT)
+    }
+}
+impl TryFrom<String> for ServerAddr {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+impl From<SocketAddr> for ServerAddr {
+    fn from(addr: SocketAddr) -> Self {
+        ServerAddr::new(addr.ip().to_string(), addr.port())
+    }
+}
+impl fmt::Display for ServerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.host.contains(':') {
+            true => write!(f, "[{}]:{}", self.host, self.port),
+            false => write!(f, "{}:{}", self.host, self.port),
+        }
+    }
+}
+
+/// Which addresses of a host with both are tried first, they alternate then, as happy eyeballs
+/// ([RFC 8305](https://www.rfc-editor.org/rfc/rfc8305)) does.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IpPreference {
+    #[default]
+    Ipv6,
+    Ipv4,
+}
+impl IpPreference {
+    /// Orders the addresses alternating between the families, the preferred one first,
+    /// each family keeps the order of the resolver.
+    pub fn order(self, addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+        let (v6, v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(SocketAddr::is_ipv6);
+        let (first, second) = match self {
+            IpPreference::Ipv6 => (v6, v4),
+            IpPreference::Ipv4 => (v4, v6),
+        };
+        let mut ordered = Vec::with_capacity(first.len() + second.len());
+        let (mut first, mut second) = (first.into_iter(), second.into_iter());
+        loop {
+            match (first.next(), second.next()) {
+                (None, None) => return ordered,
+                (a, b) => ordered.extend(a.into_iter().chain(b)),
+            }
+        }
+    }
+}
+impl FromStr for IpPreference {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ipv6" => Ok(IpPreference::Ipv6),
+            "ipv4" => Ok(IpPreference::Ipv4),
+            _ => Err(format!(
+                "unknown IP preference `{s}`, expected `ipv6` or `ipv4`"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_addresses() {
+        let parse = |s| ServerAddr::parse(s, 22222);
+        assert_eq!(parse("localhost"), Ok(ServerAddr::new("localhost", 22222)));
+        assert_eq!(
+            parse(" chat.example.com:11111 "),
+            Ok(ServerAddr::new("chat.example.com", 11111))
+        );
+        assert_eq!(parse("10.0.0.1"), Ok(ServerAddr::new("10.0.0.1", 22222)));
+        assert_eq!(parse("::1"), Ok(ServerAddr::new("::1", 22222)));
+        assert_eq!(parse("[::1]"), Ok(ServerAddr::new("::1", 22222)));
+        assert_eq!(
+            parse("[fe80::1]:11111"),
+            Ok(ServerAddr::new("fe80::1", 11111))
+        );
+        assert!(parse("").is_err());
+        assert!(parse(":11111").is_err());
+        assert!(parse("localhost:port").is_err());
+        assert!(parse("[::1").is_err());
+        assert!(parse("[::1]11111").is_err());
+        assert_eq!(
+            "localhost".parse(),
+            Ok(ServerAddr::new("localhost", PORT_DEFAULT))
+        );
+
+        assert_eq!(ServerAddr::new("::1", 11111).to_string(), "[::1]:11111");
+        let addr = SocketAddr::from(([10, 0, 0, 1], 11111));
+        assert_eq!(ServerAddr::from(addr).to_string(), "10.0.0.1:11111");
+    }
+
+    #[test]
+    fn preferred_order() {
+        let addrs: Vec<SocketAddr> = [
+            "10.0.0.1:1",
+            "10.0.0.2:1",
+            "[::1]:1",
+            "10.0.0.3:1",
+            "[::2]:1",
+        ]
+        .iter()
+        .map(|a| a.parse().unwrap())
+        .collect();
+        let ordered = |preference: IpPreference| -> Vec<String> {
+            preference
+                .order(addrs.clone())
+                .iter()
+                .map(SocketAddr::to_string)
+                .collect()
+        };
+        assert_eq!(
+            ordered(IpPreference::Ipv6),
+            [
+                "[::1]:1",
+                "10.0.0.1:1",
+                "[::2]:1",
+                "10.0.0.2:1",
+                "10.0.0.3:1"
+            ]
+        );
+        assert_eq!(
+            ordered(IpPreference::Ipv4),
+            [
+                "10.0.0.1:1",
+                "[::1]:1",
+                "10.0.0.2:1",
+                "[::2]:1",
+                "10.0.0.3:1"
+            ]
+        );
+        assert_eq!("ipv4".parse(), Ok(IpPreference::Ipv4));
+        assert!("both".parse::<IpPreference>().is_err());
+    }
+
+    #[tokio::test]
+    async fn resolve_localhost() {
+        let addrs = ServerAddr::new("localhost", 11111)
+            .resolve(IpPreference::Ipv4)
+            .await
+            .unwrap();
+        assert!(addrs.iter().all(|addr| addr.ip().is_loopback()));
+        assert!(ServerAddr::new("127.0.0.1", 11111)
+            .resolve(IpPreference::Ipv6)
+            .await
+            .unwrap()
+            .contains(&SocketAddr::from(([127, 0, 0, 1], 11111))));
+    }
+}
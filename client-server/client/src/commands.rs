@@ -0,0 +1,635 @@
+//! Input commands beginning with a dot, the built-in ones, custom ones and templates, see [Commands].
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use cli_ser::{cli, Presence};
+
+use crate::{Command, MsgCmd, ParseInputError, PrefsChange};
+
+/// What a [custom command][Commands::register] resolves to, the message to send, if any.
+type Handled = Pin<Box<dyn Future<Output = anyhow::Result<Option<cli::Msg>>> + Send>>;
+
+/// Handler of a custom command, called with the arguments (the rest of the line, trimmed).
+type Handler = Arc<dyn Fn(String) -> Handled + Send + Sync>;
+
+/// Command of the client itself, parsed into a [Command] from its arguments.
+struct Builtin {
+    name: &'static str,
+    args: &'static str,
+    help: &'static str,
+    parse: fn(&str) -> Result<Command, String>,
+}
+
+/// Built-in commands in the order `.help` lists them.
+const BUILTINS: [Builtin; 33] = [
+    Builtin {
+        name: "signup",
+        args: "<USER> <PASSWORD> [INVITE]",
+        help: "sends request to create the user, with the invitation code if the server requires one",
+        parse: |args| match words(args)[..] {
+            [name, pswd] => Ok(MsgCmd::SignUp(name.to_string(), pswd.to_string(), None).into()),
+            [name, pswd, invite] => {
+                let invite = Some(invite.to_string());
+                Ok(MsgCmd::SignUp(name.to_string(), pswd.to_string(), invite).into())
+            }
+            _ => Err(
+                "command \".signup\" needs a username, password, an invitation code at most!".into(),
+            ),
+        },
+    },
+    Builtin {
+        name: "login",
+        args: "<USER> <PASSWORD>",
+        help: "sends a request to log in with the user",
+        parse: |args| match words(args)[..] {
+            [name, pswd] => Ok(MsgCmd::LogIn(name.to_string(), pswd.to_string()).into()),
+            _ => Err("command \".login\" needs a username, password and nothing else!".into()),
+        },
+    },
+    Builtin {
+        name: "guest",
+        args: "<NAME>",
+        help: "joins as a guest of the name, unregistered, when the server allows guests",
+        parse: |args| match words(args)[..] {
+            [name] => Ok(MsgCmd::Guest(name.to_string()).into()),
+            _ => Err("command \".guest\" requires a name as the only argument!".into()),
+        },
+    },
+    Builtin {
+        name: "token",
+        args: "[TOKEN]",
+        help: "logs in with a token of the identity provider the server trusts, \
+               obtains one from the configured provider when none is given",
+        parse: |args| match words(args)[..] {
+            [] => Ok(MsgCmd::Token(None).into()),
+            [token] => Ok(MsgCmd::Token(Some(token.to_string())).into()),
+            _ => Err("command \".token\" takes at most the token as an argument!".into()),
+        },
+    },
+    Builtin {
+        name: "forgot",
+        args: "<EMAIL>",
+        help: "asks for a password reset code sent to the email of your user",
+        parse: |args| match words(args)[..] {
+            [email] => Ok(MsgCmd::RequestReset(email.to_string()).into()),
+            _ => Err("command \".forgot\" requires the email as the only argument!".into()),
+        },
+    },
+    Builtin {
+        name: "reset",
+        args: "<CODE> <NEW_PASSWORD>",
+        help: "sets a new password by the reset code and logs in",
+        parse: |args| match words(args)[..] {
+            [code, pswd] => Ok(MsgCmd::ResetPassword(code.to_string(), pswd.to_string()).into()),
+            _ => Err("command \".reset\" needs the code, new password and nothing else!".into()),
+        },
+    },
+    Builtin {
+        name: "totp",
+        args: "<CODE>",
+        help: "finishes logging in with the code of your authenticator app",
+        parse: |args| match words(args)[..] {
+            [code] => Ok(MsgCmd::Totp(code.to_string()).into()),
+            _ => Err("command \".totp\" requires the code as the only argument!".into()),
+        },
+    },
+    Builtin {
+        name: "file",
+        args: "<PATH>",
+        help: "tries to load and send the file",
+        parse: |args| match words(args)[..] {
+            [path] => Ok(MsgCmd::File(path.to_string()).into()),
+            _ => Err("command \".file\" requires a path as the only argument!".into()),
+        },
+    },
+    Builtin {
+        name: "image",
+        args: "<PATH>",
+        help: "tries to load and send the image, animated GIFs and videos are sent as they are",
+        parse: |args| match words(args)[..] {
+            [path] => Ok(MsgCmd::Image(path.to_string()).into()),
+            _ => Err("command \".image\" requires the path as the only argument!".into()),
+        },
+    },
+    Builtin {
+        name: "transform",
+        args: "<NAME> <TEXT>",
+        help: "sends the text transformed, e.g. `.transform slugify Hello World!`",
+        parse: |args| match args.trim_start().split_once(char::is_whitespace) {
+            Some((name, text)) => match name.parse::<text_tool::Transformation>() {
+                Ok(_) => {
+                    Ok(MsgCmd::Transform(name.to_string(), text.trim_start().to_string()).into())
+                }
+                Err(e) => Err(format!("command \".transform\": {e}")),
+            },
+            None => Err("command \".transform\" needs a transformation name and the text!".into()),
+        },
+    },
+    Builtin {
+        name: "motd",
+        args: "<TEXT>",
+        help: "sets the message of the day, administrators only",
+        parse: |args| match args.trim() {
+            "" => Err("command \".motd\" needs the message of the day!".into()),
+            motd => Ok(MsgCmd::SetMotd(motd.to_string()).into()),
+        },
+    },
+    Builtin {
+        name: "quota",
+        args: "<USER> <BYTES|default>",
+        help: "sets how many bytes of attachments the user can upload, administrators only",
+        parse: |args| match words(args)[..] {
+            [user, "default"] => Ok(MsgCmd::SetQuota(user.to_string(), None).into()),
+            [user, bytes] => match bytes.parse() {
+                Ok(bytes) => Ok(MsgCmd::SetQuota(user.to_string(), Some(bytes)).into()),
+                Err(_) => Err(format!(
+                    "command \".quota\": \"{bytes}\" is not a number of bytes!"
+                )),
+            },
+            _ => Err("command \".quota\" needs the user and the bytes or \"default\"!".into()),
+        },
+    },
+    Builtin {
+        name: "invite",
+        args: "create [USES] [DAYS]",
+        help: "makes an invitation code signing up USES (default 1) users, valid for DAYS, administrators only",
+        parse: |args| match words(args)[..] {
+            ["create"] => Ok(MsgCmd::Invite(1, None).into()),
+            ["create", uses] => Ok(MsgCmd::Invite(number("invite", uses)?, None).into()),
+            ["create", uses, days] => {
+                let days = Some(number("invite", days)?);
+                Ok(MsgCmd::Invite(number("invite", uses)?, days).into())
+            }
+            _ => Err("command \".invite\" needs \"create\" and at most the uses and days!".into()),
+        },
+    },
+    Builtin {
+        name: "read",
+        args: "[ID]",
+        help: "shows who read your message with the id, the last one sent when no id is given",
+        parse: |args| match words(args)[..] {
+            [] => Ok(MsgCmd::ReadStatus(None).into()),
+            [msg_id] => match msg_id.parse() {
+                Ok(msg_id) => Ok(MsgCmd::ReadStatus(Some(msg_id)).into()),
+                Err(_) => Err(format!(
+                    "command \".read\": \"{msg_id}\" is not a message id!"
+                )),
+            },
+            _ => Err("command \".read\" takes at most the message id!".into()),
+        },
+    },
+    Builtin {
+        name: "nick",
+        args: "<NAME>",
+        help: "sets your display name, shown next to your username",
+        parse: |args| match args.trim() {
+            "" => Err("command \".nick\" needs the display name!".into()),
+            name => Ok(MsgCmd::Nick(name.to_string()).into()),
+        },
+    },
+    Builtin {
+        name: "status",
+        args: "<TEXT>",
+        help: "sets your status text",
+        parse: |args| match args.trim() {
+            "" => Err("command \".status\" needs the status text!".into()),
+            status => Ok(MsgCmd::Status(status.to_string()).into()),
+        },
+    },
+    Builtin {
+        name: "email",
+        args: "[EMAIL]",
+        help: "sets the email password reset codes are sent to, removes it when none is given",
+        parse: |args| match words(args)[..] {
+            [] => Ok(MsgCmd::Email(String::new()).into()),
+            [email] => Ok(MsgCmd::Email(email.to_string()).into()),
+            _ => Err("command \".email\" takes one email at most!".into()),
+        },
+    },
+    Builtin {
+        name: "2fa",
+        args: "enable|confirm <CODE>|disable <CODE>",
+        help: "sets up two-factor authentication, the QR code is saved among the images",
+        parse: |args| match words(args)[..] {
+            ["enable"] => Ok(MsgCmd::TwoFactor(cli::TwoFactor::Enable).into()),
+            ["confirm", code] => Ok(MsgCmd::TwoFactor(cli::TwoFactor::Confirm(code.into())).into()),
+            ["disable", code] => Ok(MsgCmd::TwoFactor(cli::TwoFactor::Disable(code.into())).into()),
+            _ => Err("command \".2fa\" needs enable, or confirm or disable with a code!".into()),
+        },
+    },
+    Builtin {
+        name: "avatar",
+        args: "<PATH>",
+        help: "tries to load the image and sets it as your avatar",
+        parse: |args| match words(args)[..] {
+            [path] => Ok(MsgCmd::Avatar(path.to_string()).into()),
+            _ => Err("command \".avatar\" requires the path as the only argument!".into()),
+        },
+    },
+    Builtin {
+        name: "profile",
+        args: "<USER>",
+        help: "shows the user's profile, the avatar is saved among the images",
+        parse: |args| match words(args)[..] {
+            [user] => Ok(MsgCmd::Profile(user.to_string()).into()),
+            _ => Err("command \".profile\" requires a username as the only argument!".into()),
+        },
+    },
+    Builtin {
+        name: "stats",
+        args: "",
+        help: "shows the uptime, version, connected clients and messages of the day of the server",
+        parse: |args| match words(args)[..] {
+            [] => Ok(MsgCmd::Stats.into()),
+            _ => Err("command \".stats\" can not be followed by any text!".into()),
+        },
+    },
+    Builtin {
+        name: "away",
+        args: "[MESSAGE]",
+        help: "tells everyone you are away, the message is the auto-reply to those who mention you",
+        parse: |args| Ok(MsgCmd::Presence(Presence::Away, message(args)).into()),
+    },
+    Builtin {
+        name: "dnd",
+        args: "[MESSAGE]",
+        help: "tells everyone you do not want to be disturbed, with the auto-reply as .away",
+        parse: |args| Ok(MsgCmd::Presence(Presence::DoNotDisturb, message(args)).into()),
+    },
+    Builtin {
+        name: "back",
+        args: "",
+        help: "tells everyone you are back online",
+        parse: |args| match words(args)[..] {
+            [] => Ok(MsgCmd::Presence(Presence::Online, None).into()),
+            _ => Err("command \".back\" can not be followed by any text!".into()),
+        },
+    },
+    Builtin {
+        name: "urgent",
+        args: "<TEXT>",
+        help: "sends the text as urgent, it gets through the filters of others and notifies them",
+        parse: |args| match args.trim() {
+            "" => Err("command \".urgent\" needs the text to send!".into()),
+            text => Ok(MsgCmd::Urgent(text.to_string()).into()),
+        },
+    },
+    Builtin {
+        name: "mute",
+        args: "<USER>",
+        help: "hides messages of the user, saved to the configuration file and your account",
+        parse: |args| match words(args)[..] {
+            [user] => Ok(Command::Mute(user.to_string())),
+            _ => Err("command \".mute\" requires a username as the only argument!".into()),
+        },
+    },
+    Builtin {
+        name: "filter",
+        args: "add <REGEX>",
+        help: "hides messages matching the regular expression, saved to the configuration file and your account",
+        parse: |args| match args.trim().split_once(char::is_whitespace) {
+            Some(("add", pattern)) => match regex::Regex::new(pattern.trim_start()) {
+                Ok(_) => Ok(Command::AddFilter(pattern.trim_start().to_string())),
+                Err(e) => Err(format!("command \".filter\": {e}")),
+            },
+            _ => Err("command \".filter\" needs add and a regular expression!".into()),
+        },
+    },
+    Builtin {
+        name: "filters",
+        args: "",
+        help: "lists the muted users and hidden patterns",
+        parse: |args| match words(args)[..] {
+            [] => Ok(Command::Filters),
+            _ => Err("command \".filters\" can not be followed by any text!".into()),
+        },
+    },
+    Builtin {
+        name: "history",
+        args: "[N]",
+        help: "prints the last N (default 20 or as your preferences say) messages of your local history",
+        parse: |args| match words(args)[..] {
+            [] => Ok(Command::History(None)),
+            [n] => match n.parse() {
+                Ok(n) => Ok(Command::History(Some(n))),
+                Err(_) => Err(format!(
+                    "command \".history\": \"{n}\" is not a number of messages!"
+                )),
+            },
+            _ => Err("command \".history\" takes at most the number of messages!".into()),
+        },
+    },
+    Builtin {
+        name: "prefs",
+        args: "[mentions-only on|off|history <N>]",
+        help: "shows or changes the preferences kept by the server for your account",
+        parse: |args| match words(args)[..] {
+            [] => Ok(Command::Prefs(None)),
+            ["mentions-only", "on"] => Ok(Command::Prefs(Some(PrefsChange::MentionsOnly(true)))),
+            ["mentions-only", "off"] => Ok(Command::Prefs(Some(PrefsChange::MentionsOnly(false)))),
+            ["history", n] => match n.parse() {
+                Ok(n) => Ok(Command::Prefs(Some(PrefsChange::HistoryLength(n)))),
+                Err(_) => Err(format!(
+                    "command \".prefs history\": \"{n}\" is not a number of messages!"
+                )),
+            },
+            _ => Err(
+                "command \".prefs\" takes mentions-only on|off, history and a number or nothing!"
+                    .into(),
+            ),
+        },
+    },
+    Builtin {
+        name: "switch",
+        args: "<PROFILE>",
+        help: "disconnects and connects again as specified by the profile",
+        parse: |args| match words(args)[..] {
+            [profile] => Ok(Command::Switch(profile.to_string())),
+            _ => Err("command \".switch\" requires a profile name as the only argument!".into()),
+        },
+    },
+    Builtin {
+        name: "help",
+        args: "",
+        help: "lists the commands",
+        parse: |_| Ok(Command::Help),
+    },
+    Builtin {
+        name: "quit",
+        args: "",
+        help: "tells the application to shut down",
+        parse: |args| match words(args)[..] {
+            [] => Ok(Command::Quit),
+            _ => Err(".quit command can not be followed by any text!".into()),
+        },
+    },
+];
+
+fn words(args: &str) -> Vec<&str> {
+    args.split_whitespace().collect()
+}
+
+/// Parses the argument of the command as a number.
+fn number(cmd: &str, arg: &str) -> Result<u32, String> {
+    arg.parse()
+        .map_err(|_| format!("command \".{cmd}\": \"{arg}\" is not a number!"))
+}
+
+/// Optional message after the command, `None` when there is only whitespace.
+fn message(args: &str) -> Option<String> {
+    Some(args.trim())
+        .filter(|m| !m.is_empty())
+        .map(str::to_string)
+}
+
+/// Fills the template in, `{date}` is the date (e.g. 2024-05-01), `{time}` the time (e.g. 09:30)
+/// and `{args}` the text after the alias, which is appended when there is no `{args}`.
+pub fn expand(template: &str, args: &str, now: chrono::DateTime<chrono::Local>) -> String {
+    let text = template
+        .replace("{date}", &now.format("%Y-%m-%d").to_string())
+        .replace("{time}", &now.format("%H:%M").to_string());
+    match (template.contains("{args}"), args) {
+        (true, _) => text.replace("{args}", args),
+        (false, "") => text,
+        (false, args) => format!("{text} {args}"),
+    }
+}
+
+/// Command registered by a library user.
+#[derive(Clone)]
+struct Custom {
+    name: String,
+    args: String,
+    help: String,
+    handler: Handler,
+}
+
+/// Text sent by an alias, see [Commands::template].
+#[derive(Clone)]
+struct Template {
+    name: String,
+    text: String,
+}
+
+/// Registry of the input commands, the built-in ones (see [client][crate#user-input-commands]),
+/// custom ones and templates given to [run_with][crate::run_with].
+///
+/// `.help` lists all of them, the custom ones and templates are completed by the tab as well.
+#[derive(Clone, Default)]
+pub struct Commands {
+    custom: Vec<Custom>,
+    templates: Vec<Template>,
+}
+impl Commands {
+    /// Adds the command `.<name>`, the `handler` is called with the rest of the line (trimmed)
+    /// and the message it returns is sent to the server.
+    ///
+    /// `args` and `help` are shown by `.help`, e.g. `<USER> [TEXT]` and `greets the user`.
+    /// The command replaces a built-in or custom one of the same name.
+    pub fn register<F, Fut>(
+        mut self,
+        name: impl Into<String>,
+        args: impl Into<String>,
+        help: impl Into<String>,
+        handler: F,
+    ) -> Self
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<Option<cli::Msg>>> + Send + 'static,
+    {
+        let name = name.into();
+        self.custom.retain(|custom| custom.name != name);
+        self.templates.retain(|template| template.name != name);
+        self.custom.push(Custom {
+            name,
+            args: args.into(),
+            help: help.into(),
+            handler: Arc::new(move |args| Box::pin(handler(args))),
+        });
+        self
+    }
+
+    /// Adds the alias `.<name>` sending the `text` [expanded][expand], e.g. `.brb` sending "Be right back".
+    ///
+    /// The alias replaces a built-in or custom command of the same name, or another template.
+    pub fn template(mut self, name: impl Into<String>, text: impl Into<String>) -> Self {
+        let name = name.into();
+        self.custom.retain(|custom| custom.name != name);
+        self.templates.retain(|template| template.name != name);
+        self.templates.push(Template {
+            name,
+            text: text.into(),
+        });
+        self
+    }
+
+    /// Parses the line, lines without a leading dot are texts.
+    pub(crate) fn parse(&self, line: &str) -> Result<Command, ParseInputError> {
+        let trimmed = line.trim_start();
+        let (word, args) = trimmed
+            .split_once(char::is_whitespace)
+            .unwrap_or((trimmed, ""));
+        let Some(name) = word.strip_prefix('.') else {
+            return Ok(MsgCmd::NoCmd(line.to_string()).into());
+        };
+        if self.handler(name).is_some() {
+            return Ok(Command::Custom(name.to_string(), args.trim().to_string()));
+        }
+        if let Some(template) = self.templates.iter().find(|t| t.name == name) {
+            let text = expand(&template.text, args.trim(), chrono::Local::now());
+            return Ok(MsgCmd::NoCmd(text).into());
+        }
+        match BUILTINS.iter().find(|builtin| builtin.name == name) {
+            Some(builtin) => (builtin.parse)(args).map_err(ParseInputError),
+            None => Err(ParseInputError(format!(
+                "command \".{name}\" is not supported, see .help"
+            ))),
+        }
+    }
+
+    /// Handler of the custom command.
+    pub(crate) fn handler(&self, name: &str) -> Option<Handler> {
+        self.custom
+            .iter()
+            .find(|custom| custom.name == name)
+            .map(|custom| custom.handler.clone())
+    }
+
+    /// Whether a custom command or a template replaces the built-in one of the name.
+    fn replaces(&self, name: &str) -> bool {
+        self.handler(name).is_some() || self.templates.iter().any(|t| t.name == name)
+    }
+
+    /// Commands with the leading dot, the built-in ones first.
+    pub(crate) fn names(&self) -> Vec<String> {
+        self.usage().into_iter().map(|(name, _, _)| name).collect()
+    }
+
+    /// Name, arguments and help of each command.
+    fn usage(&self) -> Vec<(String, &str, &str)> {
+        let builtins = BUILTINS
+            .iter()
+            .filter(|builtin| !self.replaces(builtin.name))
+            .map(|builtin| (format!(".{}", builtin.name), builtin.args, builtin.help));
+        let custom = self.custom.iter().map(|custom| {
+            (
+                format!(".{}", custom.name),
+                custom.args.as_str(),
+                custom.help.as_str(),
+            )
+        });
+        let templates = self.templates.iter().map(|template| {
+            (
+                format!(".{}", template.name),
+                "[TEXT]",
+                template.text.lines().next().unwrap_or_default(),
+            )
+        });
+        builtins.chain(custom).chain(templates).collect()
+    }
+
+    /// Lists the commands with their arguments, as `.help` prints them.
+    pub fn help(&self) -> String {
+        let lines: Vec<_> = self
+            .usage()
+            .into_iter()
+            .map(|(name, args, help)| (format!("{name} {args}").trim_end().to_string(), help))
+            .collect();
+        let width = lines
+            .iter()
+            .map(|(usage, _)| usage.len())
+            .max()
+            .unwrap_or(0);
+        let mut help: Vec<_> = lines
+            .into_iter()
+            .map(|(usage, help)| format!("{usage:width$} - {help}"))
+            .collect();
+        help.push(format!(
+            "{:width$} - composes text of several lines",
+            ".multi ... .end"
+        ));
+        help.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use cli_ser::Data;
+
+    #[test]
+    fn custom_commands() {
+        let commands = Commands::default()
+            .register("shrug", "[TEXT]", "sends the text with a shrug", |text| {
+                let shrug = format!("{text} ¯\\_(ツ)_/¯").trim_start().to_string();
+                async move { Ok(Some(cli::Msg::ToAll(Data::Text(shrug)))) }
+            })
+            .register("quit", "", "asks first", |_| async { Ok(None) });
+        assert_eq!(
+            commands.parse("  .shrug  oh well ").unwrap(),
+            Command::Custom("shrug".to_string(), "oh well".to_string())
+        );
+        assert_eq!(
+            commands.parse(".quit").unwrap(),
+            Command::Custom("quit".to_string(), String::new())
+        );
+        assert_eq!(Commands::default().parse(".quit").unwrap(), Command::Quit);
+        assert!(commands.parse(".shrugs").is_err());
+        assert!(commands.handler("shrug").is_some());
+
+        let names = commands.names();
+        assert_eq!(names.iter().filter(|name| *name == ".quit").count(), 1);
+        assert_eq!(names.last().map(String::as_str), Some(".quit"));
+        let help = commands.help();
+        assert!(help.contains(".shrug [TEXT]"));
+        assert!(help.contains(".login <USER> <PASSWORD> "));
+    }
+
+    #[test]
+    fn templates() {
+        let commands = Commands::default()
+            .template("brb", "Be right back")
+            .template("standup", "Standup {date} {time}\nYesterday: {args}")
+            .template("help", "Ask me anything");
+        assert_eq!(
+            commands.parse(".brb").unwrap(),
+            Command::Msg(MsgCmd::NoCmd("Be right back".to_string()))
+        );
+        assert_eq!(
+            commands.parse(".brb  in 5 minutes ").unwrap(),
+            Command::Msg(MsgCmd::NoCmd("Be right back in 5 minutes".to_string()))
+        );
+        assert!(matches!(
+            commands.parse(".standup tests").unwrap(),
+            Command::Msg(MsgCmd::NoCmd(text)) if text.starts_with("Standup 20") && text.ends_with("\nYesterday: tests")
+        ));
+        assert_eq!(
+            commands.parse(".help").unwrap(),
+            Command::Msg(MsgCmd::NoCmd("Ask me anything".to_string()))
+        );
+        let help = commands.help();
+        assert!(help.contains(".standup [TEXT]"));
+        // Only the first line of the template.
+        assert!(help.contains(" - Standup {date} {time}\n"));
+        assert!(!help.contains("Yesterday"));
+        assert_eq!(help.matches(".help").count(), 1);
+
+        let now = chrono::Local
+            .with_ymd_and_hms(2024, 5, 1, 9, 30, 0)
+            .unwrap();
+        assert_eq!(
+            expand("{date} {time}: {args}, {args}", "done", now),
+            "2024-05-01 09:30: done, done"
+        );
+        assert_eq!(expand("Hi {name}", "", now), "Hi {name}");
+    }
+
+    #[test]
+    fn parse_help() {
+        assert_eq!(Commands::default().parse(".help").unwrap(), Command::Help);
+        assert_eq!(
+            Commands::default().parse("hello .help").unwrap(),
+            Command::Msg(MsgCmd::NoCmd("hello .help".to_string()))
+        );
+    }
+}
@@ -0,0 +1,135 @@
+//! Client configuration file, see [ConfigFile].
+use std::{collections::BTreeMap, fs, path::Path, path::PathBuf, time::Duration};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::Profiles;
+
+/// TOML configuration of the client, every value is optional, e.g.
+/// ```toml
+/// host = "chat.example.com"
+/// port = 11111
+/// prefer_ip = "ipv4"
+/// file_dir = "files"
+/// img_dir = "images"
+/// on_collision = "overwrite"
+/// image_clock = "local"
+/// layout = "sender"
+/// convert_images = "png"
+/// profile = "work"
+/// history_file = "history/{user}.jsonl"
+/// strip_exif = true
+/// auto_attach = "always"
+/// max_image_bytes = 33554432
+/// max_image_dimensions = "16384x16384"
+/// max_image_pixels = 67108864
+/// color = "never"
+/// theme = "light"
+/// lang = "cs"
+///
+/// [identity_provider]
+/// issuer = "https://accounts.example.com"
+/// client_id = "chat"
+///
+/// [filters]
+/// muted = ["spammer"]
+/// patterns = ["(?i)crypto"]
+///
+/// [notify]
+/// cmd = 'notify-send "$1" "$2"'
+/// bell = true
+/// idle = 60
+/// mentions_only = true
+///
+/// [templates]
+/// brb = "Be right back"
+/// standup = """
+/// Standup {date}
+/// Yesterday: {args}"""
+///
+/// [profiles.work]
+/// addr = "chat.example.com:11111"
+/// user = "alice"
+/// password = "secret"
+/// theme = "mono"
+///
+/// [profiles.work.notify]
+/// bell = false
+/// ```
+/// Profiles are the same as in the [profiles file][crate::load_profiles], which takes precedence.
+/// Command line arguments take precedence over environment variables,
+/// those over the file and the file over the defaults.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    /// Host as in `--host`, e.g. "localhost" or "[::1]:11111".
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    /// Family of the addresses tried first, "ipv6" or "ipv4".
+    pub prefer_ip: Option<crate::IpPreference>,
+    pub file_dir: Option<PathBuf>,
+    pub img_dir: Option<PathBuf>,
+    pub on_collision: Option<cli_ser::OnCollision>,
+    pub image_clock: Option<cli_ser::Clock>,
+    pub layout: Option<crate::StorageLayout>,
+    /// Format as in `--convert-images`, e.g. "jpeg:80".
+    pub convert_images: Option<String>,
+    pub profile: Option<String>,
+    pub history_file: Option<PathBuf>,
+    pub strip_exif: Option<bool>,
+    pub auto_attach: Option<crate::AutoAttach>,
+    pub max_image_bytes: Option<usize>,
+    /// Dimensions as in `--max-image-dimensions`, e.g. "8192x8192".
+    pub max_image_dimensions: Option<String>,
+    pub max_image_pixels: Option<u64>,
+    pub color: Option<crate::ColorChoice>,
+    pub theme: Option<crate::Theme>,
+    pub lang: Option<crate::Lang>,
+    #[serde(default)]
+    pub notify: NotifyConfig,
+    /// Provider `.token` obtains tokens from, see [IdentityProvider][crate::IdentityProvider].
+    pub identity_provider: Option<crate::IdentityProvider>,
+    /// Messages hidden, changed by `.mute` and `.filter add`, see [Filters][crate::filters::Filters].
+    #[serde(default)]
+    pub filters: crate::filters::Filters,
+    /// Aliases sending the texts, see [Commands::template][crate::Commands::template].
+    #[serde(default)]
+    pub templates: BTreeMap<String, String>,
+    #[serde(default)]
+    pub profiles: Profiles,
+}
+impl ConfigFile {
+    /// Reads and parses the file.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Reading the configuration {path:?} failed"))?;
+        toml::from_str(&content).with_context(|| format!("Configuration {path:?} is malformed"))
+    }
+}
+
+/// Notifications, see [Notifications][crate::Notifications].
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NotifyConfig {
+    pub cmd: Option<String>,
+    pub bell: Option<bool>,
+    /// Seconds without typing.
+    pub idle: Option<u64>,
+    pub mentions_only: Option<bool>,
+}
+impl NotifyConfig {
+    /// The `notifications` with the values set here in place of theirs.
+    pub fn over(&self, notifications: &crate::Notifications) -> crate::Notifications {
+        crate::Notifications {
+            cmd: self.cmd.clone().or_else(|| notifications.cmd.clone()),
+            bell: self.bell.unwrap_or(notifications.bell),
+            idle: self
+                .idle
+                .map(Duration::from_secs)
+                .unwrap_or(notifications.idle),
+            mentions_only: self.mentions_only.unwrap_or(notifications.mentions_only),
+        }
+    }
+}
@@ -0,0 +1,283 @@
+//! Headless mode exchanging messages through directories, for other programs, see [run].
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use ::notify::{
+    event::{AccessKind, AccessMode, ModifyKind, RenameMode},
+    Event, EventKind, RecursiveMode, Watcher,
+};
+use anyhow::{bail, Context};
+use tokio::{fs, io::AsyncWriteExt, select, sync::mpsc};
+
+use cli_ser::{
+    cli,
+    conn::{Reader, Writer},
+    ser, Data, File, MsgId,
+};
+
+use crate::{
+    explain, failure,
+    unattended::{self, Line},
+    Config,
+};
+
+/// Outbox files with the extension are sent as texts, any other as files.
+pub const TEXT_EXTENSION: &str = "msg";
+/// Subdirectory of the outbox where files are moved once the server confirms them.
+pub const SENT_DIR: &str = "sent";
+/// Subdirectory of the outbox where files the server rejected are moved.
+pub const FAILED_DIR: &str = "failed";
+/// File of the inbox incoming messages are appended to, one JSON [Line] each.
+pub const INBOX_FILE: &str = "messages.jsonl";
+
+/// Outbox files sent and waiting for the server's answer.
+type Pending = Arc<Mutex<HashMap<MsgId, PathBuf>>>;
+
+/// Logs in as the configuration says, sends files appearing in the `outbox` and writes incoming messages to the `inbox`.
+///
+/// Files already in the outbox are sent first (by their names), then the outbox is watched.
+/// A file is sent once it is closed after writing or moved into the outbox,
+/// names starting with a dot are ignored, so a file can be written as `.name` and renamed when complete.
+/// Files with the [TEXT_EXTENSION] are sent as texts, any other as files.
+/// When the server confirms them, they are moved to [SENT_DIR], when it rejects them to [FAILED_DIR].
+///
+/// Received messages are appended to [INBOX_FILE] as JSON lines, received files and images
+/// are saved in the `files` and `images` subdirectories of the inbox.
+///
+/// Runs until the server closes the connection, which is an error, so that e.g. systemd restarts it.
+pub async fn run(config: &Config, outbox: &Path, inbox: &Path) -> anyhow::Result<()> {
+    let config = Config {
+        file_dir: inbox.join("files"),
+        img_dir: inbox.join("images"),
+        ..config.resolved()?
+    };
+    for dir in [
+        &outbox.join(SENT_DIR),
+        &outbox.join(FAILED_DIR),
+        &config.file_dir,
+        &config.img_dir,
+    ] {
+        fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("Directory {dir:?} couldn't be created"))?;
+    }
+    let (reader, mut writer) = unattended::connect(&config).await?.split();
+    let pending = Pending::default();
+
+    let (paths, mut ready) = mpsc::channel(128);
+    let mut watcher = ::notify::recommended_watcher(move |event: ::notify::Result<Event>| {
+        match event {
+            Ok(event) if is_complete(&event.kind) => {
+                for path in event.paths {
+                    // The receiver is gone only when the daemon stops.
+                    let _ = paths.blocking_send(path);
+                }
+            }
+            Ok(_) => (),
+            Err(e) => eprintln!("Watching the outbox failed! Err: {e}"),
+        }
+    })
+    .context("The outbox watcher couldn't be created")?;
+    watcher
+        .watch(outbox, RecursiveMode::NonRecursive)
+        .with_context(|| format!("Watching the outbox {outbox:?} failed"))?;
+
+    let mut receiving = tokio::spawn(receive(
+        config.clone(),
+        reader,
+        inbox.join(INBOX_FILE),
+        outbox.to_path_buf(),
+        pending.clone(),
+    ));
+
+    let mut existing = Vec::new();
+    let mut entries = fs::read_dir(outbox)
+        .await
+        .with_context(|| format!("Reading the outbox {outbox:?} failed"))?;
+    while let Some(entry) = entries.next_entry().await? {
+        existing.push(entry.path());
+    }
+    existing.sort();
+    let mut next_id = 0;
+    for path in existing {
+        send(&mut writer, &pending, &mut next_id, outbox, path).await?;
+    }
+    loop {
+        select!(
+            received = &mut receiving => break received.context("the receiving task panicked")?,
+            path = ready.recv() => match path {
+                Some(path) => send(&mut writer, &pending, &mut next_id, outbox, path).await?,
+                None => bail!("the outbox watcher stopped"),
+            },
+        )
+    }
+}
+
+/// Whether the event means a file is complete: written and closed, or moved in.
+fn is_complete(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Access(AccessKind::Close(AccessMode::Write))
+            | EventKind::Modify(ModifyKind::Name(RenameMode::To | RenameMode::Both))
+    )
+}
+
+/// Sends the outbox file unless it is not one to be sent, only failing writes are errors.
+async fn send(
+    writer: &mut Writer,
+    pending: &Pending,
+    next_id: &mut u64,
+    outbox: &Path,
+    path: PathBuf,
+) -> anyhow::Result<()> {
+    let hidden = path
+        .file_name()
+        .map_or(true, |name| name.to_string_lossy().starts_with('.'));
+    if hidden || path.parent() != Some(outbox) || !path.is_file() {
+        return Ok(());
+    }
+    if pending
+        .lock()
+        .expect("lock poisoned")
+        .values()
+        .any(|p| *p == path)
+    {
+        return Ok(());
+    }
+    let data = match load(&path).await {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("{}", failure(&e));
+            return Ok(());
+        }
+    };
+    *next_id += 1;
+    let id = MsgId(*next_id);
+    pending.lock().expect("lock poisoned").insert(id, path);
+    writer
+        .send_msg(cli::Msg::ToAll(data).tagged(id))
+        .await
+        .context("Sending to the server failed")
+}
+
+/// Loads the outbox file as a text or a file, see [TEXT_EXTENSION].
+async fn load(path: &Path) -> anyhow::Result<Data> {
+    match path.extension().is_some_and(|ext| ext == TEXT_EXTENSION) {
+        true => {
+            Ok(Data::Text(fs::read_to_string(path).await.with_context(
+                || format!("Reading the text {path:?} failed"),
+            )?))
+        }
+        false => Ok(File::from_path(path)
+            .await
+            .with_context(|| format!("Loading the file {path:?} failed"))?
+            .into()),
+    }
+}
+
+/// Writes incoming messages to the inbox and moves the confirmed or rejected outbox files.
+async fn receive(
+    config: Config,
+    mut reader: Reader,
+    inbox: PathBuf,
+    outbox: PathBuf,
+    pending: Pending,
+) -> anyhow::Result<()> {
+    let mut messages = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&inbox)
+        .await
+        .with_context(|| format!("Opening the inbox {inbox:?} failed"))?;
+    loop {
+        let msg = match reader.recv().await {
+            Ok(msg) => msg,
+            Err(cli_ser::Error::DisconnectedStream(_)) => bail!("the server closed the connection"),
+            Err(e @ cli_ser::Error::DeserializeMsg(_)) => {
+                eprintln!("A message from the server was malformed, it is skipped! Err: {e}");
+                continue;
+            }
+            Err(e) => return Err(e).context("Reading a message from the server failed"),
+        };
+        match msg {
+            ser::Msg::Ack(id) => {
+                let sent = pending.lock().expect("lock poisoned").remove(&id);
+                if let Some(path) = sent {
+                    settle(&outbox, &path, SENT_DIR).await;
+                }
+            }
+            ser::Msg::Rejected(id, err) => {
+                let sent = pending.lock().expect("lock poisoned").remove(&id);
+                if let Some(path) = sent {
+                    eprintln!("{path:?} was rejected: {}", explain(&err));
+                    settle(&outbox, &path, FAILED_DIR).await;
+                }
+            }
+            msg => {
+                if let Some(line) = Line::of(&config, msg).await {
+                    let mut json = serde_json::to_string(&line)?;
+                    json.push('\n');
+                    messages
+                        .write_all(json.as_bytes())
+                        .await
+                        .with_context(|| format!("Writing to the inbox {inbox:?} failed"))?;
+                }
+            }
+        }
+    }
+}
+
+/// Moves the sent file to the subdirectory of the outbox.
+async fn settle(outbox: &Path, path: &Path, dir: &str) {
+    let Some(name) = path.file_name() else {
+        return;
+    };
+    if let Err(e) = fs::rename(path, outbox.join(dir).join(name)).await {
+        eprintln!("Moving {path:?} to {dir:?} failed! Err: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn load_texts_and_files() {
+        let dir = std::env::temp_dir().join(format!("outbox_{}", std::process::id()));
+        fs::create_dir_all(&dir).await.unwrap();
+        let text = dir.join(format!("hello.{TEXT_EXTENSION}"));
+        fs::write(&text, "hello there").await.unwrap();
+        let file = dir.join("hello.txt");
+        fs::write(&file, "hello there").await.unwrap();
+
+        assert_eq!(
+            load(&text).await.unwrap(),
+            Data::Text("hello there".to_string())
+        );
+        match load(&file).await.unwrap() {
+            Data::File(f) => assert_eq!(f.name(), "hello.txt"),
+            data => panic!("expected a file, got {data:?}"),
+        }
+        assert!(load(&dir.join("missing.txt")).await.is_err());
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[test]
+    fn complete_events() {
+        assert!(is_complete(&EventKind::Access(AccessKind::Close(
+            AccessMode::Write
+        ))));
+        assert!(is_complete(&EventKind::Modify(ModifyKind::Name(
+            RenameMode::To
+        ))));
+        assert!(!is_complete(&EventKind::Modify(ModifyKind::Name(
+            RenameMode::From
+        ))));
+        assert!(!is_complete(&EventKind::Create(
+            ::notify::event::CreateKind::File
+        )));
+    }
+}
@@ -0,0 +1,132 @@
+//! Suppression of duplicate downloads, see [save_once].
+//!
+//! Every directory received content is saved to keeps the SHA-256 hashes of its files in the [INDEX_FILE],
+//! a line `<hash> <name>` per file, so the same attachment received again is not saved twice.
+use std::{
+    future::Future,
+    io,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+
+use cli_ser::Error::SaveFile;
+
+/// Index of the hashes in a directory, hidden by the leading dot.
+pub(crate) const INDEX_FILE: &str = ".hashes";
+
+/// Where the received content is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Saved {
+    /// It was saved just now.
+    New(PathBuf),
+    /// The same content was saved before, nothing was written.
+    Duplicate(PathBuf),
+}
+impl Saved {
+    pub(crate) fn into_path(self) -> PathBuf {
+        match self {
+            Saved::New(path) | Saved::Duplicate(path) => path,
+        }
+    }
+}
+
+/// Returns the hash of the bytes as lowercase hex.
+pub(crate) fn hash(bytes: &[u8]) -> String {
+    hex(&Sha256::digest(bytes))
+}
+
+/// Returns the [hash] of the file, it is read in chunks.
+pub(crate) async fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut chunk = vec![0; 64 * 1024];
+    loop {
+        match file.read(&mut chunk).await? {
+            0 => return Ok(hex(&hasher.finalize())),
+            n => hasher.update(&chunk[..n]),
+        }
+    }
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Returns the file in the `dir` with the `hash`, entries of removed or changed files are skipped.
+pub(crate) async fn find(dir: &Path, hash: &str) -> Option<PathBuf> {
+    let index = fs::read_to_string(dir.join(INDEX_FILE)).await.ok()?;
+    for (_, name) in index
+        .lines()
+        .rev()
+        .filter_map(|line| line.split_once(' '))
+        .filter(|(indexed, _)| *indexed == hash)
+    {
+        let path = dir.join(name);
+        if hash_file(&path).await.is_ok_and(|current| current == hash) {
+            return Some(path);
+        }
+    }
+    None
+}
+
+/// Adds the file at the `path` to the index of its directory.
+pub(crate) async fn remember(path: &Path, hash: &str) -> io::Result<()> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Ok(());
+    };
+    let mut index = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(INDEX_FILE))
+        .await?;
+    let line = format!("{hash} {}\n", name.to_string_lossy());
+    index.write_all(line.as_bytes()).await?;
+    index.flush().await
+}
+
+/// Saves the content with the `hash` to the `dir` by `save`, unless the same content is there already.
+pub(crate) async fn save_once(
+    dir: &Path,
+    hash: &str,
+    save: impl Future<Output = cli_ser::Result<PathBuf>>,
+) -> cli_ser::Result<Saved> {
+    if let Some(path) = find(dir, hash).await {
+        return Ok(Saved::Duplicate(path));
+    }
+    let path = save.await?;
+    remember(&path, hash).await.map_err(SaveFile)?;
+    Ok(Saved::New(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn duplicates_not_saved() {
+        let dir = std::env::temp_dir().join(format!("dedup_{}", std::process::id()));
+        fs::create_dir_all(&dir).await.unwrap();
+        let meme = cli_ser::File::new("meme.txt", b"same old joke".to_vec());
+        let hash = super::hash(meme.bytes());
+        let save = || meme.save(&dir, cli_ser::OnCollision::Rename);
+
+        let first = save_once(&dir, &hash, save()).await.unwrap();
+        assert_eq!(first, Saved::New(dir.join("meme.txt")));
+        let again = save_once(&dir, &hash, save()).await.unwrap();
+        assert_eq!(again, Saved::Duplicate(dir.join("meme.txt")));
+        assert_eq!(hash_file(&dir.join("meme.txt")).await.unwrap(), hash);
+
+        // A changed file is not a duplicate any more.
+        fs::write(dir.join("meme.txt"), "edited").await.unwrap();
+        let changed = save_once(&dir, &hash, save()).await.unwrap();
+        assert_eq!(changed, Saved::New(dir.join("meme (1).txt")));
+        assert_eq!(find(&dir, &hash).await, Some(dir.join("meme (1).txt")));
+        assert_eq!(find(&dir, &super::hash(b"other")).await, None);
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+}
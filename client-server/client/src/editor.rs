@@ -0,0 +1,226 @@
+//! Line editing of the user input with history and tab completion, see [InputHelper],
+//! input of several lines is joined by [Multiline].
+use std::{
+    collections::BTreeSet,
+    mem,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context as _;
+use rustyline::{
+    completion::{Completer, FilenameCompleter, Pair},
+    error::ReadlineError,
+    history::DefaultHistory,
+    CompletionType, Context, Editor, Helper, Highlighter, Hinter, Validator,
+};
+
+/// Usernames seen since the client started, they are completed after `.profile` and `@`.
+pub(crate) type Users = Arc<Mutex<BTreeSet<String>>>;
+
+/// Source of the lines the user enters, made and read by the parser thread.
+pub(crate) trait Lines {
+    /// Reads the next line, `None` when the input ends (Ctrl-C or Ctrl-D).
+    fn read(&mut self, prompt: &str) -> anyhow::Result<Option<String>>;
+
+    /// Keeps the line for the arrow keys.
+    fn remember(&mut self, _line: &str) {}
+}
+impl Lines for Editor<InputHelper, DefaultHistory> {
+    fn read(&mut self, prompt: &str) -> anyhow::Result<Option<String>> {
+        match self.readline(prompt) {
+            Ok(line) => Ok(Some(line)),
+            Err(ReadlineError::Eof | ReadlineError::Interrupted) => Ok(None),
+            Err(e) => Err(e).with_context(|| "Reading a line from stdin failed."),
+        }
+    }
+
+    fn remember(&mut self, line: &str) {
+        let _ = self.add_history_entry(line);
+    }
+}
+
+/// Editor of the terminal's lines completing the `commands` and the `users` as usernames.
+pub(crate) fn line_editor(
+    users: Users,
+    commands: Vec<String>,
+) -> anyhow::Result<Editor<InputHelper, DefaultHistory>> {
+    let mut editor = Editor::with_config(
+        rustyline::Config::builder()
+            .completion_type(CompletionType::List)
+            .build(),
+    )
+    .with_context(|| "Creating the line editor failed.")?;
+    editor.set_helper(Some(InputHelper::new(users, commands)));
+    Ok(editor)
+}
+
+/// Lines composing text of several lines, offered for completion besides the [commands][crate::Commands].
+const MULTILINE: [&str; 2] = [".multi", ".end"];
+
+/// Completes commands at the start of the line, paths of `.file`, `.image` and `.avatar`,
+/// and usernames of `.profile` and of mentions, e.g. `@ali` to `@alice`.
+#[derive(Helper, Highlighter, Hinter, Validator)]
+pub(crate) struct InputHelper {
+    users: Users,
+    /// Commands with the leading dot, see [Commands::names][crate::Commands::names].
+    commands: Vec<String>,
+    files: FilenameCompleter,
+}
+impl InputHelper {
+    pub(crate) fn new(users: Users, commands: Vec<String>) -> Self {
+        InputHelper {
+            users,
+            commands,
+            files: FilenameCompleter::new(),
+        }
+    }
+
+    fn usernames(&self, prefix: &str) -> Vec<Pair> {
+        let users = self.users.lock().expect("lock poisoned");
+        candidates(users.iter().map(String::as_str), prefix)
+    }
+}
+impl Completer for InputHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let before = &line[..pos];
+        let start = before.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let word = &before[start..];
+        if start == 0 && word.starts_with('.') {
+            let commands = self.commands.iter().map(String::as_str);
+            return Ok((0, candidates(commands.chain(MULTILINE), word)));
+        }
+        match before.split_whitespace().next() {
+            Some(".file" | ".image" | ".avatar") => self.files.complete(line, pos, ctx),
+            Some(".profile") => Ok((start, self.usernames(word))),
+            _ => match word.strip_prefix('@') {
+                Some(prefix) => Ok((start + 1, self.usernames(prefix))),
+                None => Ok((pos, vec![])),
+            },
+        }
+    }
+}
+
+/// Joins lines of one input, either continued by a trailing backslash or composed between `.multi` and `.end`.
+#[derive(Debug, Default)]
+pub(crate) struct Multiline {
+    lines: Vec<String>,
+    composing: bool,
+}
+/// Complete input of one or more lines.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Input {
+    /// Parsed as any line, e.g. a command continued on the next line.
+    Line(String),
+    /// Composed text, sent as it is even when it starts with a dot.
+    Text(String),
+}
+impl Multiline {
+    /// Prompt telling whether a further line is expected.
+    pub(crate) fn prompt(&self) -> &'static str {
+        match self.composing || !self.lines.is_empty() {
+            true => "... ",
+            false => "",
+        }
+    }
+
+    /// Takes the next line, returns the input once it is complete.
+    pub(crate) fn feed(&mut self, line: String) -> Option<Input> {
+        if self.composing {
+            if line.trim() != ".end" {
+                self.lines.push(line);
+                return None;
+            }
+            self.composing = false;
+            let text = mem::take(&mut self.lines).join("\n");
+            return (!text.trim().is_empty()).then_some(Input::Text(text));
+        }
+        if self.lines.is_empty() && line.trim() == ".multi" {
+            self.composing = true;
+            return None;
+        }
+        match line.strip_suffix('\\') {
+            Some(start) => {
+                self.lines.push(start.to_string());
+                None
+            }
+            None => {
+                self.lines.push(line);
+                Some(Input::Line(mem::take(&mut self.lines).join("\n")))
+            }
+        }
+    }
+}
+
+fn candidates<'a>(words: impl IntoIterator<Item = &'a str>, prefix: &str) -> Vec<Pair> {
+    words
+        .into_iter()
+        .filter(|word| word.starts_with(prefix))
+        .map(|word| Pair {
+            display: word.to_string(),
+            replacement: word.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustyline::history::DefaultHistory;
+
+    fn complete(helper: &InputHelper, line: &str) -> (usize, Vec<String>) {
+        let history = DefaultHistory::new();
+        let (start, pairs) = helper
+            .complete(line, line.len(), &Context::new(&history))
+            .unwrap();
+        (
+            start,
+            pairs.into_iter().map(|pair| pair.replacement).collect(),
+        )
+    }
+
+    #[test]
+    fn complete_commands_and_users() {
+        let users: Users = Arc::default();
+        users
+            .lock()
+            .unwrap()
+            .extend(["alice".to_string(), "bob".to_string()]);
+        let helper = InputHelper::new(users, crate::Commands::default().names());
+
+        assert_eq!(
+            complete(&helper, ".s"),
+            (
+                0,
+                vec![".signup".into(), ".status".into(), ".switch".into()]
+            )
+        );
+        assert_eq!(complete(&helper, ".profile a"), (9, vec!["alice".into()]));
+        assert_eq!(complete(&helper, "hi @b"), (4, vec!["bob".into()]));
+        assert_eq!(complete(&helper, "hi b"), (4, vec![]));
+    }
+
+    #[test]
+    fn multiline() {
+        let mut multiline = Multiline::default();
+        let mut feed = |line: &str| multiline.feed(line.to_string());
+        assert_eq!(feed("one"), Some(Input::Line("one".into())));
+        assert_eq!(feed("first \\"), None);
+        assert_eq!(feed("second"), Some(Input::Line("first \nsecond".into())));
+        assert_eq!(feed(".multi"), None);
+        assert_eq!(feed(".file not a command"), None);
+        assert_eq!(feed("  indented\\"), None);
+        assert_eq!(
+            feed(".end"),
+            Some(Input::Text(".file not a command\n  indented\\".into()))
+        );
+        assert_eq!(feed(".multi"), None);
+        assert_eq!(feed(".end"), None);
+    }
+}
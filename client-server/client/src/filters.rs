@@ -0,0 +1,145 @@
+//! Messages hidden from the conversation, see [Filters].
+use std::{collections::BTreeSet, fs, path::Path};
+
+use anyhow::Context;
+use regex::Regex;
+use serde::Deserialize;
+use toml_edit::{Array, DocumentMut};
+
+/// Users muted and patterns of hidden messages, the `[filters]` table of the [configuration file][crate::ConfigFile], e.g.
+/// ```toml
+/// [filters]
+/// muted = ["spammer"]
+/// patterns = ["(?i)crypto", "^!bot"]
+/// ```
+/// `.mute` and `.filter add` change them while chatting, the table is then [saved][Filters::save].
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Filters {
+    /// Users whose messages are not shown.
+    #[serde(default)]
+    pub muted: BTreeSet<String>,
+    /// Regular expressions, received messages matching any of them are not shown.
+    #[serde(default)]
+    pub patterns: Vec<String>,
+}
+impl Filters {
+    /// Writes the filters to the `[filters]` table of the TOML file, the rest of it (comments included) is kept.
+    ///
+    /// The file is created when missing.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let content = match path.exists() {
+            true => fs::read_to_string(path)
+                .with_context(|| format!("Reading the configuration {path:?} failed"))?,
+            false => String::new(),
+        };
+        let mut doc: DocumentMut = content
+            .parse()
+            .with_context(|| format!("Configuration {path:?} is malformed"))?;
+        let table = doc
+            .entry("filters")
+            .or_insert(toml_edit::table())
+            .as_table_mut()
+            .with_context(|| format!("\"filters\" in {path:?} is not a table"))?;
+        table["muted"] = toml_edit::value(self.muted.iter().map(String::as_str).collect::<Array>());
+        table["patterns"] =
+            toml_edit::value(self.patterns.iter().map(String::as_str).collect::<Array>());
+        fs::write(path, doc.to_string())
+            .with_context(|| format!("Writing the configuration {path:?} failed"))
+    }
+}
+
+/// [Filters] with the patterns compiled, applied to received messages.
+#[derive(Debug, Default)]
+pub(crate) struct Active {
+    filters: Filters,
+    regexes: Vec<Regex>,
+}
+impl Active {
+    /// Compiles the patterns, fails on the first invalid one.
+    pub(crate) fn new(filters: Filters) -> Result<Self, regex::Error> {
+        let regexes = filters
+            .patterns
+            .iter()
+            .map(|pattern| Regex::new(pattern))
+            .collect::<Result<_, _>>()?;
+        Ok(Active { filters, regexes })
+    }
+
+    pub(crate) fn filters(&self) -> &Filters {
+        &self.filters
+    }
+
+    /// Hides messages of the user, returns false when they were muted already.
+    pub(crate) fn mute(&mut self, user: &str) -> bool {
+        self.filters.muted.insert(user.to_string())
+    }
+
+    /// Hides messages matching the pattern, returns false when it was added already.
+    pub(crate) fn add(&mut self, pattern: &str) -> Result<bool, regex::Error> {
+        if self.filters.patterns.iter().any(|added| added == pattern) {
+            return Ok(false);
+        }
+        self.regexes.push(Regex::new(pattern)?);
+        self.filters.patterns.push(pattern.to_string());
+        Ok(true)
+    }
+
+    /// Whether the message `from` the user, described by the `text`, is not to be shown.
+    pub(crate) fn hides(&self, from: &str, text: &str) -> bool {
+        self.filters.muted.contains(from) || self.regexes.iter().any(|regex| regex.is_match(text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn muted_and_matching_hidden() {
+        let filters = Filters {
+            muted: BTreeSet::from(["spammer".to_string()]),
+            patterns: vec!["(?i)crypto".to_string()],
+        };
+        let mut active = Active::new(filters).unwrap();
+        assert!(active.hides("spammer", "hello"));
+        assert!(active.hides("alice", "Buy CRYPTO now"));
+        assert!(!active.hides("alice", "hello"));
+
+        assert!(active.mute("alice"));
+        assert!(!active.mute("alice"));
+        assert!(active.hides("alice", "hello"));
+        assert!(active.add("^!bot").unwrap());
+        assert!(!active.add("^!bot").unwrap());
+        assert!(active.hides("bob", "!bot ping"));
+        assert!(active.add("(unclosed").is_err());
+        assert_eq!(active.filters().patterns, ["(?i)crypto", "^!bot"]);
+        assert!(Active::new(Filters {
+            patterns: vec!["[".to_string()],
+            ..Default::default()
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn saved_into_config() {
+        let path = std::env::temp_dir().join(format!("filters_{}.toml", std::process::id()));
+        fs::write(&path, "# my settings\nport = 11111\n").unwrap();
+        let filters = Filters {
+            muted: BTreeSet::from(["spammer".to_string()]),
+            patterns: vec!["^!bot".to_string()],
+        };
+        filters.save(&path).unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        assert!(saved.starts_with("# my settings\nport = 11111\n"));
+        let file = crate::ConfigFile::load(&path).unwrap();
+        assert_eq!(file.filters, filters);
+        assert_eq!(file.port, Some(11111));
+
+        // Saved again over the previous table.
+        let filters = Filters::default();
+        filters.save(&path).unwrap();
+        assert_eq!(crate::ConfigFile::load(&path).unwrap().filters, filters);
+        fs::remove_file(path).unwrap();
+    }
+}
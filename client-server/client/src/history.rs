@@ -0,0 +1,117 @@
+//! Local history of sent and received messages, one JSON object per line, see [History].
+use std::{
+    fmt,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use chrono::{offset::Utc, SecondsFormat};
+use serde::{Deserialize, Serialize};
+
+/// Default location of history files, `{user}` is replaced by the username.
+pub const FILE_DEFAULT: &str = "history/{user}.jsonl";
+
+/// One message of the history.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// RFC 3339 time of sending or receiving.
+    pub time: String,
+    /// Sender, `None` for messages sent by the user.
+    pub from: Option<String>,
+    /// The text or the description of the attachment.
+    pub text: String,
+}
+impl Entry {
+    /// Entry of the message sent or received right now.
+    pub fn now(from: Option<String>, text: String) -> Self {
+        Entry {
+            time: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            from,
+            text,
+        }
+    }
+}
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let from = self.from.as_deref().unwrap_or("you");
+        write!(f, "[{}] {from}: {}", self.time, self.text)
+    }
+}
+
+/// History file of one user.
+#[derive(Debug, Clone, PartialEq)]
+pub struct History {
+    path: PathBuf,
+}
+impl History {
+    /// History of the user in the file given by the `template`, where `{user}` stands for the username.
+    pub fn of_user(template: &Path, user: &str) -> Self {
+        let user = user.replace(['/', '\\'], "_");
+        let path = template.to_string_lossy().replace("{user}", &user);
+        History {
+            path: PathBuf::from(path),
+        }
+    }
+
+    /// Appends the entry, the file and its directory are created when missing.
+    pub fn append(&self, entry: &Entry) -> anyhow::Result<()> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("History directory {dir:?} couldn't be created"))?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("History {:?} couldn't be opened", self.path))?;
+        writeln!(file, "{}", serde_json::to_string(entry)?)
+            .with_context(|| format!("Writing to the history {:?} failed", self.path))
+    }
+
+    /// Returns the last `n` entries, oldest first, malformed lines are skipped.
+    pub fn last(&self, n: usize) -> anyhow::Result<Vec<Entry>> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => {
+                Err(e).with_context(|| format!("Reading the history {:?} failed", self.path))?
+            }
+        };
+        let entries: Vec<Entry> = content
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+        Ok(entries[entries.len().saturating_sub(n)..].to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn append_and_read_last() {
+        let dir = std::env::temp_dir().join(format!(
+            "client-history-{}",
+            Utc::now().timestamp_nanos_opt().unwrap()
+        ));
+        let template = dir.join("{user}.jsonl");
+        let history = History::of_user(&template, "../alice");
+        assert_eq!(history.path, dir.join(".._alice.jsonl"));
+        assert_eq!(history.last(5).unwrap(), vec![]);
+        let entries = [
+            Entry::now(None, "hi".to_string()),
+            Entry::now(Some("bob".to_string()), "hello".to_string()),
+            Entry::now(None, "sent the file a.txt".to_string()),
+        ];
+        for entry in &entries {
+            history.append(entry).unwrap();
+        }
+        assert_eq!(history.last(2).unwrap(), entries[1..]);
+        assert_eq!(history.last(10).unwrap(), entries);
+        assert!(entries[1].to_string().ends_with("] bob: hello"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,427 @@
+//! Texts shown to the user in their language, see [Lang] and [Text].
+//!
+//! Every text has a translation in each language, checked by the compiler as the catalog
+//! is a `match`. Values are put in by the [t] macro in place of `{name}` placeholders.
+use std::{
+    fmt::Display,
+    str::FromStr,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use serde::Deserialize;
+
+/// Language of the process, set by [Lang::apply].
+static LANG: AtomicU8 = AtomicU8::new(Lang::En as u8);
+
+/// Language of the texts.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[repr(u8)]
+pub enum Lang {
+    /// English.
+    #[default]
+    En,
+    /// Czech.
+    Cs,
+}
+impl FromStr for Lang {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "en" => Ok(Lang::En),
+            "cs" => Ok(Lang::Cs),
+            _ => Err(format!("unknown language `{s}`, expected `en` or `cs`")),
+        }
+    }
+}
+impl Lang {
+    /// Language of the locale in the environment (`LC_ALL`, `LC_MESSAGES` or `LANG`, e.g. "cs_CZ.UTF-8"),
+    /// `None` when it is not set or there are no texts in it.
+    pub fn from_env() -> Option<Lang> {
+        let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|locale| !locale.is_empty())?;
+        Self::of_locale(&locale)
+    }
+
+    fn of_locale(locale: &str) -> Option<Lang> {
+        let lang = locale.split(['_', '.', '@', '-']).next()?;
+        lang.to_lowercase().parse().ok()
+    }
+
+    /// Applies the language to all texts of the process.
+    pub fn apply(self) {
+        LANG.store(self as u8, Ordering::Relaxed);
+    }
+
+    /// Language of the process.
+    pub fn current() -> Lang {
+        match LANG.load(Ordering::Relaxed) {
+            l if l == Lang::Cs as u8 => Lang::Cs,
+            _ => Lang::En,
+        }
+    }
+}
+
+/// Key of a text in the catalog.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Text {
+    Switching,
+    NoProfile,
+    Malformed,
+    ReceivedFile,
+    FileSaved,
+    FileNotSaved,
+    /// The file of a hidden message was received to a temporary file which could not be removed.
+    HiddenFileNotRemoved,
+    ReceivedImage,
+    ImageSaved,
+    ImageNotSaved,
+    ReceivedMedia,
+    MediaSaved,
+    MediaNotSaved,
+    /// The same content was saved before, it is not saved again.
+    AlreadySaved,
+    Welcome,
+    NotReadYet,
+    ReadBy,
+    Status,
+    AvatarSaved,
+    AvatarNotSaved,
+    TotpRequired,
+    TotpEnrollment,
+    TotpQrSaved,
+    TotpQrNotSaved,
+    MsgRejected,
+    MsgUnanswered,
+    NewSession,
+    Stats,
+    Traffic,
+    Online,
+    Away,
+    DoNotDisturb,
+    Muted,
+    FilterAdded,
+    MutedUsers,
+    HiddenPatterns,
+    NothingHidden,
+    FiltersNotSaved,
+    PatternSkipped,
+    PrefsLoaded,
+    PrefsNotLoaded,
+    NotifyAll,
+    NotifyMentions,
+    HistoryLength,
+    Invite,
+    InviteExpires,
+    SolvingChallenge,
+    ChallengeTooHard,
+    ApproveLogIn,
+    SentFile,
+    SentImage,
+    SentMedia,
+    You,
+    SendImage,
+    SendFile,
+    /// Answers agreeing to a question, separated by spaces.
+    Yes,
+    CommandNotParsed,
+    HistoryNotKept,
+    CommandFailed,
+    MessageNotMade,
+    Receiving,
+    Sending,
+    WrongPassword,
+    WrongUser,
+    InvalidCredentials,
+    UsernameTaken,
+    NotAuthenticated,
+    AlreadyAuthenticated,
+    NotAdmin,
+    UnknownMessage,
+    UnknownUser,
+    ServerRejected,
+    SessionActive,
+    GuestsNotAllowed,
+    InvalidToken,
+    InvalidResetCode,
+    InvalidTotpCode,
+    InviteRequired,
+    InvalidInvite,
+    ChallengeFailed,
+    /// Marks senders who are guests.
+    Guest,
+    /// Marks urgent texts.
+    Urgent,
+    QuotaExceeded,
+    ServerFull,
+    TooManyFromAddress,
+    AddressNotAllowed,
+    UnknownError,
+    OtherError,
+}
+
+/// The text in the language, with `{name}` placeholders.
+fn catalog(text: Text, lang: Lang) -> &'static str {
+    use Text::*;
+    match lang {
+        Lang::En => match text {
+            Switching => "Switching to {profile} at {addr}...",
+            NoProfile => "There is no profile {profile}, reconnecting as before.",
+            Malformed => "A message from the server was malformed, it is skipped! Err: {err}",
+            ReceivedFile => "Received {name} from {from}",
+            FileSaved => "...file was saved to {path}",
+            FileNotSaved => "...saving the file {name} failed! Err: {err}",
+            HiddenFileNotRemoved => "A hidden message's file received to {path} could not be removed! Err: {err}",
+            ReceivedImage => "Received image from {from}...",
+            ImageSaved => "...image was saved to {path}",
+            ImageNotSaved => "...saving the image failed! Err: {err}",
+            ReceivedMedia => "Received {media} from {from}...",
+            MediaSaved => "...it was saved to {path}",
+            MediaNotSaved => "...saving it failed! Err: {err}",
+            AlreadySaved => "...it was already saved as {path}",
+            Welcome => "Welcome!",
+            NotReadYet => "Your message {msg_id}{sent} was not read yet.",
+            ReadBy => "Your message {msg_id}{sent} was read by {users}.",
+            Status => "  status: {status}",
+            AvatarSaved => "  avatar was saved to {path}",
+            AvatarNotSaved => "  saving the avatar failed! Err: {err}",
+            TotpRequired => "Enter the code of your authenticator app with .totp <CODE>",
+            TotpEnrollment => {
+                "Add this to your authenticator app, then confirm a code with .2fa confirm <CODE>: {url}"
+            }
+            TotpQrSaved => "  QR code of it was saved to {path}",
+            TotpQrNotSaved => "  saving the QR code failed! Err: {err}",
+            MsgRejected => "Your message {id}{sent} was rejected: {reason}",
+            MsgUnanswered => "Your message {id}{sent} got no answer from the server",
+            NewSession => "Your account logged in from {addr} at {time}",
+            Stats => "Server {version}, up {uptime}, {connected} connected, {messages} messages today",
+            Traffic => {
+                "This connection sent {sent} messages ({sent_bytes} B), received {received} ({received_bytes} B), idle {idle}"
+            }
+            Online => "{user} is back",
+            Away => "{user} is away{message}",
+            DoNotDisturb => "{user} does not want to be disturbed{message}",
+            Muted => "Messages of {user} are hidden",
+            FilterAdded => "Messages matching {pattern} are hidden",
+            MutedUsers => "Muted users: {users}",
+            HiddenPatterns => "Hidden patterns: {patterns}",
+            NothingHidden => "No messages are hidden",
+            FiltersNotSaved => "Saving the filters failed, they apply until the client quits! Err: {err}",
+            PatternSkipped => "The stored pattern {pattern} is not valid, it is skipped! Err: {err}",
+            PrefsLoaded => "Preferences of your account were loaded, see .prefs",
+            PrefsNotLoaded => "Preferences are kept by the server once you log in (.login) or sign up (.signup).",
+            NotifyAll => "All messages are notified about",
+            NotifyMentions => "Only messages mentioning you are notified about",
+            HistoryLength => ".history prints {n} messages",
+            Invite => "Invitation code {code}, it signs up {uses} users",
+            InviteExpires => "Invitation code {code}, it signs up {uses} users until {expires}",
+            SolvingChallenge => "The server asks for a proof of work before signing up, solving it...",
+            ChallengeTooHard => "The proof of work of {difficulty} bits is more than this client solves.",
+            ApproveLogIn => "Open {uri} and enter the code {code} to log in, waiting for your approval...",
+            SentFile => "sent the file {name}",
+            SentImage => "sent an image",
+            SentMedia => "sent {media}",
+            You => "you",
+            SendImage => "Send the image {path}? [Y/n] ",
+            SendFile => "Send the file {path}? [Y/n] ",
+            Yes => "y Y yes",
+            CommandNotParsed => "Couldn't parse your command! {err}",
+            HistoryNotKept => "The history is kept once you .login or .signup.",
+            CommandFailed => "Command \".{name}\" failed! {err}",
+            MessageNotMade => "Couldn't make your message! {err}",
+            Receiving => "Receiving",
+            Sending => "Sending",
+            WrongPassword => "Given password is not correct",
+            WrongUser => "The user does not exist, you can create it with a .signup",
+            InvalidCredentials => "The username or the password is not correct",
+            UsernameTaken => "Unfortunately this username is already taken, choose another one.",
+            NotAuthenticated => {
+                "You need to .login or .signup before sending a message (parsed message: {msg})"
+            }
+            AlreadyAuthenticated => {
+                "You are currently logged in, if you want to log in as another user first log out."
+            }
+            NotAdmin => "Only administrators are allowed to do that.",
+            UnknownMessage => "You did not send any message {msg_id}.",
+            UnknownUser => "There is no user {user}.",
+            ServerRejected => "The server does not accept it, {reason}.",
+            SessionActive => "You are logged in elsewhere and the server allows only one session.",
+            GuestsNotAllowed => "The server does not let guests in, .login or .signup instead.",
+            InvalidToken => "The server does not accept the token, {reason}.",
+            InvalidResetCode => "The reset code is not valid, it may have expired, ask for a new one with .forgot",
+            InvalidTotpCode => "The code is not correct, enter the current one of your authenticator app.",
+            InviteRequired => "The server signs up only invited users, .signup with the code of your invitation.",
+            InvalidInvite => "The invitation code is not valid, it may be used up or expired.",
+            ChallengeFailed => "The proof of work was not solved, the sign-up is refused.",
+            Guest => "guest",
+            Urgent => "URGENT",
+            QuotaExceeded => {
+                "Your storage quota is used up ({used} of {limit} bytes), ask an administrator for more."
+            }
+            ServerFull => "The server is full, try again later.",
+            TooManyFromAddress => {
+                "Too many connections from your address, close some of them first."
+            }
+            AddressNotAllowed => "Your address is not allowed to connect to the server.",
+            UnknownError => {
+                "The server reported an error this client does not know: {detail}"
+            }
+            OtherError => "Error: {err}",
+        },
+        Lang::Cs => match text {
+            Switching => "Přepínám na {profile} na adrese {addr}...",
+            NoProfile => "Profil {profile} neexistuje, připojuji se znovu jako předtím.",
+            Malformed => "Zpráva ze serveru byla poškozená, přeskakuji ji! Chyba: {err}",
+            ReceivedFile => "Přijat soubor {name} od {from}",
+            FileSaved => "...soubor byl uložen do {path}",
+            FileNotSaved => "...uložení souboru {name} selhalo! Chyba: {err}",
+            HiddenFileNotRemoved => "Soubor skryté zprávy přijatý do {path} nešlo odstranit! Chyba: {err}",
+            ReceivedImage => "Přijat obrázek od {from}...",
+            ImageSaved => "...obrázek byl uložen do {path}",
+            ImageNotSaved => "...uložení obrázku selhalo! Chyba: {err}",
+            ReceivedMedia => "Přijato {media} od {from}...",
+            MediaSaved => "...bylo uloženo do {path}",
+            MediaNotSaved => "...uložení selhalo! Chyba: {err}",
+            AlreadySaved => "...už bylo uloženo jako {path}",
+            Welcome => "Vítejte!",
+            NotReadYet => "Vaši zprávu {msg_id}{sent} zatím nikdo nepřečetl.",
+            ReadBy => "Vaši zprávu {msg_id}{sent} přečetli: {users}.",
+            Status => "  stav: {status}",
+            AvatarSaved => "  avatar byl uložen do {path}",
+            AvatarNotSaved => "  uložení avataru selhalo! Chyba: {err}",
+            TotpRequired => "Zadejte kód z vaší ověřovací aplikace příkazem .totp <KÓD>",
+            TotpEnrollment => {
+                "Přidejte tohle do ověřovací aplikace a potvrďte kód příkazem .2fa confirm <KÓD>: {url}"
+            }
+            TotpQrSaved => "  QR kód byl uložen do {path}",
+            TotpQrNotSaved => "  uložení QR kódu selhalo! Chyba: {err}",
+            MsgRejected => "Vaše zpráva {id}{sent} byla odmítnuta: {reason}",
+            MsgUnanswered => "Vaše zpráva {id}{sent} nedostala od serveru odpověď",
+            NewSession => "Váš účet se přihlásil z {addr} v {time}",
+            Stats => "Server {version} běží {uptime}, připojeno {connected}, dnešních zpráv {messages}",
+            Traffic => {
+                "Toto spojení odeslalo {sent} zpráv ({sent_bytes} B), přijalo {received} ({received_bytes} B), nečinné {idle}"
+            }
+            Online => "{user} je zpět",
+            Away => "{user} je pryč{message}",
+            DoNotDisturb => "{user} prosí o klid{message}",
+            Muted => "Zprávy od {user} jsou skryté",
+            FilterAdded => "Zprávy odpovídající {pattern} jsou skryté",
+            MutedUsers => "Ztlumení uživatelé: {users}",
+            HiddenPatterns => "Skryté vzory: {patterns}",
+            NothingHidden => "Žádné zprávy nejsou skryté",
+            FiltersNotSaved => "Uložení filtrů selhalo, platí jen do ukončení klienta! Chyba: {err}",
+            PatternSkipped => "Uložený vzor {pattern} není platný, přeskakuje se! Chyba: {err}",
+            PrefsLoaded => "Předvolby vašeho účtu byly načteny, viz .prefs",
+            PrefsNotLoaded => "Předvolby uchovává server, jakmile se přihlásíte (.login) nebo zaregistrujete (.signup).",
+            NotifyAll => "Oznamují se všechny zprávy",
+            NotifyMentions => "Oznamují se jen zprávy, které vás zmiňují",
+            HistoryLength => ".history vypíše {n} zpráv",
+            Invite => "Kód pozvánky {code}, zaregistruje {uses} uživatelů",
+            InviteExpires => "Kód pozvánky {code}, zaregistruje {uses} uživatelů do {expires}",
+            SolvingChallenge => "Server před registrací žádá důkaz práce, řeším ho...",
+            ChallengeTooHard => "Důkaz práce o {difficulty} bitech je víc, než tento klient vyřeší.",
+            ApproveLogIn => "Otevřete {uri} a zadejte kód {code} pro přihlášení, čekám na vaše schválení...",
+            SentFile => "posílá soubor {name}",
+            SentImage => "posílá obrázek",
+            SentMedia => "posílá {media}",
+            You => "vy",
+            SendImage => "Poslat obrázek {path}? [A/n] ",
+            SendFile => "Poslat soubor {path}? [A/n] ",
+            Yes => "a A ano y Y yes",
+            CommandNotParsed => "Vašemu příkazu nerozumím! {err}",
+            HistoryNotKept => "Historie se vede, jakmile se přihlásíte (.login) nebo zaregistrujete (.signup).",
+            CommandFailed => "Příkaz \".{name}\" selhal! {err}",
+            MessageNotMade => "Vaši zprávu se nepodařilo vytvořit! {err}",
+            Receiving => "Přijímám",
+            Sending => "Odesílám",
+            WrongPassword => "Zadané heslo není správné",
+            WrongUser => "Uživatel neexistuje, můžete jej vytvořit příkazem .signup",
+            InvalidCredentials => "Uživatelské jméno nebo heslo není správné",
+            UsernameTaken => "Toto uživatelské jméno je bohužel obsazené, zvolte jiné.",
+            NotAuthenticated => {
+                "Před odesláním zprávy se musíte přihlásit (.login) nebo zaregistrovat (.signup) (zpráva: {msg})"
+            }
+            AlreadyAuthenticated => {
+                "Už jste přihlášeni, chcete-li se přihlásit jako jiný uživatel, nejdřív se odhlaste."
+            }
+            NotAdmin => "To smějí jen administrátoři.",
+            UnknownMessage => "Žádnou zprávu {msg_id} jste neposlali.",
+            UnknownUser => "Uživatel {user} neexistuje.",
+            ServerRejected => "Server to nepřijímá, {reason}.",
+            SessionActive => "Jste přihlášeni jinde a server dovoluje jen jedno připojení.",
+            GuestsNotAllowed => {
+                "Server hosty nepouští, přihlaste se (.login) nebo zaregistrujte (.signup)."
+            }
+            InvalidToken => "Server token nepřijímá, {reason}.",
+            InvalidResetCode => "Kód pro obnovení hesla neplatí, možná vypršel, požádejte o nový příkazem .forgot",
+            InvalidTotpCode => "Kód není správný, zadejte aktuální kód z vaší ověřovací aplikace.",
+            InviteRequired => "Server registruje jen pozvané, použijte .signup s kódem vaší pozvánky.",
+            InvalidInvite => "Kód pozvánky neplatí, možná je vyčerpaný nebo vypršel.",
+            ChallengeFailed => "Důkaz práce nebyl vyřešen, registrace je odmítnuta.",
+            Guest => "host",
+            Urgent => "NALÉHAVÉ",
+            QuotaExceeded => {
+                "Vaše kvóta úložiště je vyčerpaná ({used} z {limit} bajtů), požádejte administrátora o víc."
+            }
+            ServerFull => "Server je plný, zkuste to později.",
+            TooManyFromAddress => "Z vaší adresy je příliš mnoho připojení, některá nejdřív zavřete.",
+            AddressNotAllowed => "Vaše adresa se k serveru nesmí připojit.",
+            UnknownError => "Server ohlásil chybu, kterou tento klient nezná: {detail}",
+            OtherError => "Chyba: {err}",
+        },
+    }
+}
+
+/// The text in the current language, without placeholders.
+pub(crate) fn text(key: Text) -> &'static str {
+    catalog(key, Lang::current())
+}
+
+/// The text in the current language with the `{name}` placeholders replaced by the values.
+pub(crate) fn tr(text: Text, values: &[(&str, &dyn Display)]) -> String {
+    fill(catalog(text, Lang::current()), values)
+}
+
+fn fill(template: &str, values: &[(&str, &dyn Display)]) -> String {
+    values
+        .iter()
+        .fold(template.to_string(), |filled, (name, value)| {
+            filled.replace(&format!("{{{name}}}"), &value.to_string())
+        })
+}
+
+/// Translates the text and puts in the values, e.g. `t!(Text::UnknownUser, user = "bob")`.
+macro_rules! t {
+    ($text:expr $(, $name:ident = $value:expr)* $(,)?) => {
+        $crate::i18n::tr(
+            $text,
+            &[$((stringify!($name), &$value as &dyn std::fmt::Display)),*],
+        )
+    };
+}
+pub(crate) use t;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn translations() {
+        assert_eq!(Lang::of_locale("cs_CZ.UTF-8"), Some(Lang::Cs));
+        assert_eq!(Lang::of_locale("en_US"), Some(Lang::En));
+        assert_eq!(Lang::of_locale("C"), None);
+        let values: [(&str, &dyn Display); 1] = [("user", &"bob")];
+        assert_eq!(
+            fill(catalog(Text::UnknownUser, Lang::En), &values),
+            "There is no user bob."
+        );
+        assert_eq!(
+            fill(catalog(Text::UnknownUser, Lang::Cs), &values),
+            "Uživatel bob neexistuje."
+        );
+        assert_eq!(t!(Text::Welcome), catalog(Text::Welcome, Lang::current()));
+    }
+}
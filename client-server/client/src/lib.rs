@@ -11,18 +11,181 @@
 //!
 //! When interacting with the running client, input beginning with a dot is interpreted as a command. Here is the list of available commands:
 //!
-//! * `.signup <USER> <PASSWORD>` - sends request to create the user.
+//! * `.signup <USER> <PASSWORD> [INVITE]` - sends request to create the user, with the code of an invitation
+//!   on servers signing up invited users only. A proof of work the server asks for is solved on its own.
 //! * `.login <USER> <PASSWORD>` - sends a request to log in with the user.
+//! * `.guest <NAME>` - joins as a guest of the name, unregistered, when the server allows guests.
+//! * `.token [TOKEN]` - logs in with a token of the identity provider the server trusts,
+//!   e.g. an OpenID Connect ID token obtained by the provider's own tool. Without one it is obtained
+//!   from the [configured provider][Config::identity_provider], you approve the log-in in a browser.
+//! * `.forgot <EMAIL>` - asks for a password reset code sent to the email of your user.
+//! * `.reset <CODE> <NEW_PASSWORD>` - sets a new password by the reset code and logs in.
+//! * `.totp <CODE>` - finishes logging in with the code of your authenticator app.
 //! * `.file <PATH>` - tries to load and send the file.
-//! * `.image <PATH>` - tries to load and send the image.
+//! * `.image <PATH>` - tries to load and send the image, animated GIFs and videos (MP4, WebM) are sent as they are.
+//! * `.transform <NAME> <TEXT>` - sends the text transformed by [text_tool], e.g. `.transform slugify Hello World!`.
+//! * `.motd <TEXT>` - sets the message of the day, administrators only.
+//! * `.quota <USER> <BYTES|default>` - sets how many bytes of attachments the user can upload, administrators only.
+//! * `.invite create [USES] [DAYS]` - makes an invitation code signing up USES (default 1) users,
+//!   valid for DAYS or until used up, administrators only.
+//! * `.read [ID]` - shows who read your message with the id, the last one sent when no id is given.
+//! * `.nick <NAME>` - sets your display name, shown next to your username.
+//! * `.status <TEXT>` - sets your status text.
+//! * `.email [EMAIL]` - sets the email password reset codes are sent to, removes it when none is given.
+//! * `.2fa enable|confirm <CODE>|disable <CODE>` - sets up two-factor authentication, the QR code is saved among the images.
+//!   Codes are required once one is confirmed, the log-in is then finished by `.totp`.
+//! * `.avatar <PATH>` - tries to load the image and sets it as your avatar.
+//! * `.profile <USER>` - shows the user's profile, the avatar is saved among the images.
+//! * `.away [MESSAGE]` - tells everyone you are away, the message is the auto-reply to those who mention you.
+//! * `.dnd [MESSAGE]` - tells everyone you do not want to be disturbed, with the auto-reply as `.away`.
+//! * `.back` - tells everyone you are back online.
+//! * `.urgent <TEXT>` - sends the text as urgent, it gets through the filters of others and notifies them.
+//! * `.stats` - shows the uptime, version, connected clients and messages of the day of the server,
+//!   and the messages and bytes this connection sent and received.
+//! * `.mute <USER>` - hides messages of the user.
+//! * `.filter add <REGEX>` - hides messages matching the regular expression, see [regex](https://docs.rs/regex).
+//! * `.filters` - lists the muted users and hidden patterns.
+//! * `.history [N]` - prints the last N (default 20 or as your preferences say) messages of your local history.
+//! * `.prefs [mentions-only on|off|history <N>]` - shows or changes the [preferences](self#preferences)
+//!   kept by the server for your account.
+//! * `.switch <PROFILE>` - disconnects and connects again as specified by the [profile][Profile].
+//! * `.help` - lists the commands.
 //! * `.quit` - tells the application to shut down.
 //!
 //! Any text without a leading dot is transmitted as a **text** message.
-// TODO: Add ".help" or similar to see how to make messages right from the client.
+//! Sent messages are shown as in the conversation, marked `…` until the server confirms them (`✓`)
+//! or rejects them (`✗`), those it does not answer within 30 seconds are marked `✗` too.
+//! A line ending with `\` continues on the next one, text of several lines can also be composed
+//! between `.multi` and `.end` lines, it is sent as it is, even the lines beginning with a dot.
+//!
+//! A line consisting only of the path of an existing file, e.g. dragged into the terminal,
+//! is offered to be sent as the file (or the image), see `--auto-attach` and [AutoAttach].
+//!
+//! Lines can be edited, the arrow keys go through the lines entered before (except log-ins and sign-ups).
+//! Tab completes commands, paths after `.file`, `.image` and `.avatar`,
+//! and usernames seen so far after `.profile` and `@`.
+//!
+//! ## Custom Commands
+//!
+//! Applications built on the library can add commands of their own, see [Commands]:
+//! ```no_run
+//! use cli_ser::{cli, Data};
+//!
+//! # async fn chat(config: client::Config) -> anyhow::Result<()> {
+//! let commands = client::Commands::default().register(
+//!     "shrug",
+//!     "<TEXT>",
+//!     "sends the text with a shrug",
+//!     |text| async move { Ok(Some(cli::Msg::ToAll(Data::Text(format!("{text} ¯\\_(ツ)_/¯"))))) },
+//! );
+//! client::run_with(config, commands).await
+//! # }
+//! ```
+//!
+//! ## Templates
+//!
+//! Canned replies are aliases defined in the `[templates]` table of the configuration file,
+//! e.g. `brb = "Be right back"` makes `.brb` send the text. `{date}` and `{time}` are replaced
+//! by the current ones and `{args}` by the text after the alias, see [commands::expand].
+//!
+//! ## Configuration
+//!
+//! Options can be given by a TOML file (`--config <FILE>`, `client.toml` when it exists), see [ConfigFile].
+//! Command line arguments come first, then environment variables (`CLIENT_HOST`, ..., listed in `--help`),
+//! then the file and finally the defaults.
+//!
+//! The server is given by `--host`, a name such as `localhost` or an IPv4 or IPv6 address, optionally
+//! with the port, e.g. `chat.example.com:11111` or `[::1]:11111`, see [ServerAddr]. The name is resolved
+//! on each connection and its addresses are tried in turn, IPv6 and IPv4 ones alternating,
+//! the family `--prefer-ip` says first.
+//!
+//! ## Received Files
+//!
+//! Files are written to `--file-dir` while they arrive, a name already taken gets a number,
+//! e.g. `notes (1).txt`, unless `--on-collision overwrite` is given.
+//! Images are saved to `--img-dir` named by the time they arrived in UTC, or in the local time zone
+//! with `--image-clock local`, an image arriving in the same second gets a number too.
+//! `--layout sender` puts both into a subdirectory per sender, e.g. `files/alice/notes.txt`,
+//! `--layout date` per local date, e.g. `files/2024-05-01/notes.txt`.
+//! Content identical to a file saved in the directory before is not saved again,
+//! the client says where it is instead. The SHA-256 hashes are kept in a `.hashes` file of each directory.
+//! Sending or receiving more than a megabyte shows a progress bar.
+//!
+//! ## Filters
+//!
+//! Messages of muted users and those matching a pattern are not shown nor notified about,
+//! they are kept in the history though. `.mute` and `.filter add` save the filters
+//! to the `[filters]` table of the configuration file, see [Filters]. They are removed by editing it.
+//! Urgent texts are always shown.
+//!
+//! ## Preferences
+//!
+//! The server keeps the muted users, hidden patterns, whether only mentions are notified about
+//! and the default length of `.history` for your account, so they follow you to every client, see [Preferences].
+//! They are loaded after logging in and merged with the filters of the configuration file,
+//! filters the account lacks are stored to it. `.mute`, `.filter add` and `.prefs` store the changes,
+//! a filter removed from the configuration file comes back while the account keeps it.
+//! Only mentions are notified about when either the preferences or `--notify-mentions-only` say so.
+//! Guests have no stored preferences.
+//!
+//! ## History
+//!
+//! Sent and received messages are kept in a local file per user, `history/<USER>.jsonl` by default,
+//! see `--history-file` and [History].
+//!
+//! ## Notifications
+//!
+//! Messages arriving while nobody types (for 30 seconds by default, see `--notify-idle`)
+//! can ring the terminal bell (`--bell`) or run a command (`--notify-cmd <CMD>`), see [Notifications].
+//!
+//! Texts mentioning you as `@username` are highlighted, with `--notify-mentions-only`
+//! only they are notified about. Urgent texts (`.urgent`) are marked and notified about even while you type.
+//!
+//! ## Colors
+//!
+//! Each sender has a color of their own, times are dim and errors red, see [render].
+//! `--color never` (or the `NO_COLOR` environment variable) turns the colors off,
+//! `--color always` keeps them even when the output is not a terminal.
+//! `--theme light` suits light terminals, `--theme mono` uses no colors but bold, see [Theme].
+//!
+//! ## Profiles
+//!
+//! A [profile][Profile] may have its own directories for received files and images, [theme][Theme]
+//! and notification rules, they apply when it is connected to, so work and hobby servers do not mix.
+//!
+//! ## Languages
+//!
+//! Texts of the client are in English or Czech, as `--lang` says or else the locale (`LANG`), see [i18n].
+//! Help of the commands and the output of the scripting subcommands are in English only.
+//!
+//! ## Scripting
+//!
+//! Besides the interactive `chat` (the default), the client has subcommands for scripts,
+//! they log in with `--user` and `--password` (or the profile's credentials), see [unattended]:
+//!
+//! * `client send --text <TEXT>` - sends the text and exits once the server confirms it.
+//! * `client send-file <PATH>` - sends the file the same way.
+//! * `client listen [--json]` - prints incoming messages, with `--json` as one JSON object per line.
+//! * `client daemon [--outbox <DIR>] [--inbox <DIR>]` - runs without a terminal, sends files put in the outbox
+//!   and writes incoming messages to the inbox, see [daemon].
 // TODO: Make CMD_PREFIX configurable by the user.
-use std::{net::SocketAddr, path::PathBuf, str::FromStr};
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::Path,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
+use anstream::{eprintln, println};
 use anyhow::{anyhow, Context};
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::Deserialize;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
     net::TcpStream,
@@ -30,14 +193,72 @@ use tokio::{
     sync::{mpsc, oneshot},
 };
 
-use cli_ser::{cli, ser, Data, File, Image, Messageable};
+use cli_ser::{
+    challenge, cli,
+    retry::{deadline, retry, RetryPolicy, TIMEOUT_DEFAULT},
+    ser, Codec, ConnectionStats, Data,
+    Error::{DeserializeMsg, DisconnectedStream, SaveFile},
+    ErrorCode, File, Image, ImageLimits, ImageOutputFormat, Media, Messageable, MsgId, Preferences,
+    Presence, ReceivedFile,
+};
+
+use dedup::Saved;
+use i18n::{t, Text};
+
+pub use addr::{IpPreference, ServerAddr};
+pub use cli_ser::{parse_image_format, Clock, OnCollision};
+pub use commands::Commands;
+pub use config::ConfigFile;
+pub use filters::Filters;
+pub use history::History;
+pub use i18n::Lang;
+pub use notify::Notifications;
+pub use oidc::IdentityProvider;
+pub use render::{ColorChoice, Theme};
+
+pub mod addr;
+pub mod commands;
+pub mod config;
+pub mod daemon;
+mod dedup;
+mod editor;
+pub mod filters;
+pub mod history;
+pub mod i18n;
+pub mod notify;
+pub mod oidc;
+pub mod render;
+pub mod unattended;
 
 /// Default server host.
 pub const HOST_DEFAULT: [u8; 4] = [127, 0, 0, 1];
 /// Default server port.
 pub const PORT_DEFAULT: u16 = 11111;
 
+/// Terminal escape sequence moving the cursor a line up and clearing the line.
+const LINE_UP: &str = "\x1b[1A\x1b[2K";
+
+/// Markers of the user's own messages, waiting for the server, confirmed and rejected.
+const PENDING: &str = "…";
+const CONFIRMED: &str = "✓";
+const FAILED: &str = "✗";
+/// Longer echoes may wrap on the terminal, they are never replaced in place.
+const ECHO_REPLACED_UP_TO: usize = 80;
+
+/// Sent messages the server does not answer for longer are given up, see [Session::expire].
+const ANSWER_TIMEOUT: Duration = TIMEOUT_DEFAULT;
+/// How often the pending messages are checked for the [ANSWER_TIMEOUT].
+const EXPIRY_CHECK: Duration = Duration::from_secs(1);
+
+/// Transfers of more bytes show a progress bar.
+const PROGRESS_OVER: u64 = 1024 * 1024;
+
+/// Messages `.history` prints unless told or the [preferences][Preferences::history_length] say otherwise.
+const HISTORY_DEFAULT: usize = 20;
+
 /// Client configurations.
+///
+/// Values of the active [profile][Self::profile] take precedence, see [Self::resolved].
 // Idea: maybe implement std Default for this...
 #[derive(Clone)]
 pub struct Config {
@@ -45,50 +266,327 @@ pub struct Config {
     pub file_dir: PathBuf,
     /// Path to save received images.
     pub img_dir: PathBuf,
+    /// What happens when a received file has the name of a file already saved.
+    pub on_collision: OnCollision,
+    /// Time the names of received images are based on.
+    pub image_clock: Clock,
+    /// How received files and images are organized in their directories.
+    pub layout: StorageLayout,
     /// Address of the server to connect to.
-    pub addr: SocketAddr,
-    /// Whether to save all images as PNGs.
-    pub save_png: bool,
+    pub addr: ServerAddr,
+    /// Which addresses of the server are tried first.
+    pub prefer_ip: IpPreference,
+    /// Credentials to log in with right after connecting.
+    pub credentials: Option<cli::Credentials>,
+    /// Profiles available for [switching][self#user-input-commands].
+    pub profiles: Profiles,
+    /// Name of the active profile.
+    pub profile: Option<String>,
+    /// Format to convert all received images to, images are saved as they are when `None`.
+    pub convert_images: Option<ImageOutputFormat>,
+    /// Notifications about messages arriving while the user is away.
+    pub notify: Notifications,
+    /// Colors of the output.
+    pub theme: Theme,
+    /// History file, `{user}` is replaced by the username, see [history::FILE_DEFAULT].
+    pub history_file: PathBuf,
+    /// Sent images and avatars are [normalized][Image::normalized]: turned upright and stripped of metadata.
+    pub strip_exif: bool,
+    /// Images bigger than the limits are not sent, the server would reject them anyway.
+    pub image_limits: ImageLimits,
+    /// What happens to a line consisting only of the path of an existing file.
+    pub auto_attach: AutoAttach,
+    /// Messages not shown, see [Filters].
+    pub filters: Filters,
+    /// Configuration file the filters changed while chatting are saved to, they are not saved when `None`.
+    pub config_file: Option<PathBuf>,
+    /// Provider `.token` obtains a token from when none is given.
+    pub identity_provider: Option<IdentityProvider>,
+}
+impl Config {
+    /// Returns the configuration with values of the active profile applied.
+    pub fn resolved(&self) -> anyhow::Result<Config> {
+        let Some(name) = &self.profile else {
+            return Ok(self.clone());
+        };
+        let profile = self
+            .profiles
+            .get(name)
+            .with_context(|| format!("Profile {name:?} is not defined"))?;
+        Ok(Config {
+            file_dir: profile.file_dir.clone().unwrap_or(self.file_dir.clone()),
+            img_dir: profile.img_dir.clone().unwrap_or(self.img_dir.clone()),
+            addr: profile.addr.clone(),
+            credentials: profile.credentials(),
+            notify: profile.notify.over(&self.notify),
+            theme: profile.theme.unwrap_or(self.theme),
+            ..self.clone()
+        })
+    }
+}
+
+/// Connection profile, a server to connect to and optionally an identity to log in as.
+///
+/// Optional values not set by the profile are taken from the [Config].
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct Profile {
+    /// Server, a host with an optional port as `--host` takes, e.g. "chat.example.com:11111".
+    pub addr: ServerAddr,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    /// Path to save received files.
+    pub file_dir: Option<PathBuf>,
+    /// Path to save received images.
+    pub img_dir: Option<PathBuf>,
+    pub theme: Option<Theme>,
+    /// Notification rules, those not set are kept.
+    #[serde(default)]
+    pub notify: config::NotifyConfig,
+}
+impl Profile {
+    /// Returns credentials if both the user and the password are set.
+    pub fn credentials(&self) -> Option<cli::Credentials> {
+        match (&self.user, &self.password) {
+            (Some(user), Some(password)) => Some(cli::Credentials {
+                user: user.clone().into(),
+                password: password.clone(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Named [profiles][Profile].
+pub type Profiles = HashMap<String, Profile>;
+
+/// Loads profiles from a TOML file where each table is one profile, e.g.
+/// ```toml
+/// [work]
+/// addr = "10.0.0.1:11111"
+/// user = "alice"
+/// password = "secret"
+/// file_dir = "work/files"
+/// img_dir = "work/images"
+/// theme = "light"
+///
+/// [work.notify]
+/// mentions_only = true
+/// ```
+pub fn load_profiles(path: impl AsRef<Path>) -> anyhow::Result<Profiles> {
+    let path = path.as_ref();
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Reading profiles from {path:?} failed"))?;
+    toml::from_str(&content).with_context(|| format!("Profiles in {path:?} are malformed"))
 }
 
 /// Connects to the server, sends messages (read form the terminal) to it, and prints received ones.
 ///
 /// Spawns stdin parser thread, tcp sender and tcp receiver tasks.
+/// When the user [switches][self#user-input-commands] the profile,
+/// the connection is closed and a new one is made as the profile says, the stdin parser keeps running.
+///
+/// When the user quits or the session fails (e.g. the server is lost), the function returns right away,
+/// it does not wait for the stdin parser blocked reading a line, see [Input::stop].
 ///
 /// For input commands see [client][self].
 pub async fn run(config: Config) -> anyhow::Result<()> {
-    let (reader, writer) = TcpStream::connect(config.addr)
+    run_with(config, Commands::default()).await
+}
+
+/// Same as [run] with the custom commands added to the built-in ones.
+pub async fn run_with(mut config: Config, commands: Commands) -> anyhow::Result<()> {
+    let filters = filters::Active::new(config.filters.clone())
+        .with_context(|| "A pattern of the filters is not a valid regular expression")?;
+    let filters = Arc::new(Mutex::new(filters));
+    let users = editor::Users::default();
+    let completed = users.clone();
+    let names = commands.names();
+    let mut input = Input::spawn(
+        move || editor::line_editor(completed, names),
+        commands.clone(),
+        config.auto_attach,
+    );
+
+    let chatted = loop {
+        match connect_and_chat(&config, &commands, &mut input.parsed, &users, &filters).await {
+            Ok(Some(profile)) => match config.profiles.get(&profile) {
+                Some(next) => {
+                    println!(
+                        "{}",
+                        t!(Text::Switching, profile = profile, addr = next.addr)
+                    );
+                    config.profile = Some(profile);
+                }
+                None => eprintln!(
+                    "{}",
+                    render::error(t!(Text::NoProfile, profile = format!("{profile:?}")))
+                ),
+            },
+            Ok(None) => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
+    let stopped = input.stop().await;
+    chatted?;
+    stopped.with_context(|| "standard input parser crashed.")
+}
+
+/// Connects to the server and handles one session, returns the profile to switch to if requested.
+///
+/// The configuration is [resolved][Config::resolved], its theme applied and directories are created first.
+async fn connect_and_chat(
+    config: &Config,
+    commands: &Commands,
+    inputs: &mut mpsc::Receiver<Result<Command, ParseInputError>>,
+    users: &editor::Users,
+    filters: &Arc<Mutex<filters::Active>>,
+) -> anyhow::Result<Option<String>> {
+    let config = config.resolved()?;
+    config.theme.apply();
+    tokio::fs::create_dir_all(&config.file_dir)
+        .await
+        .with_context(|| {
+            format!(
+                "Directory for files {:?} couldn't be created",
+                config.file_dir
+            )
+        })?;
+    tokio::fs::create_dir_all(&config.img_dir)
+        .await
+        .with_context(|| {
+            format!(
+                "Directory for images {:?} couldn't be created",
+                config.img_dir
+            )
+        })?;
+    // A server just starting may not listen yet.
+    let connect = || {
+        config.addr.connect(config.prefer_ip, |addr| {
+            deadline(TIMEOUT_DEFAULT, async move {
+                TcpStream::connect(addr)
+                    .await
+                    .map_err(cli_ser::Error::Connect)
+            })
+        })
+    };
+    let (reader, writer) = retry(&RetryPolicy::default(), connect)
         .await
         .with_context(|| {
             "Connection to the server failed, please make sure the server is running."
         })?
         .into_split();
+    let session = Arc::new(Session {
+        users: users.clone(),
+        filters: filters.clone(),
+        ..Default::default()
+    });
+    let (reader, mut writer) = (session.traffic.count(reader), session.traffic.count(writer));
+    // The server chooses one of them, it is taken up once the user is authenticated.
+    if Codec::ALL.len() > 1 {
+        let ids = Codec::ALL.iter().map(|codec| codec.id()).collect();
+        cli::Msg::Codecs(ids)
+            .send(&mut writer)
+            .await
+            .with_context(|| "Offering codecs failed")?;
+    }
+    if let Some(credentials) = &config.credentials {
+        *session.logging_in.lock().expect("lock poisoned") = Some(credentials.user.to_string());
+        cli::Msg::Auth(cli::Auth::LogIn(credentials.clone()))
+            .send(&mut writer)
+            .await
+            .with_context(|| "Logging in failed")?;
+    }
     // Channel to indicate to stop receiving for messages.
     let (quit_sender, quit_receiver) = oneshot::channel();
-    // Channel to pass input read in blocking thread to the async handle task.
-    let (input_producer, input_consumer) = mpsc::channel(128);
-
-    let stdin_parser = std::thread::spawn(move || parse_stdin(input_producer));
-    let msg_receiver = tokio::spawn(receive_in_loop(config.clone(), reader, quit_receiver));
-    let msg_sender = tokio::spawn(handle_input(input_consumer, writer, quit_sender));
-
-    // Awaiting the msg_receiver first is important for crash to show up when it happens.
-    msg_receiver
-        .await?
-        .with_context(|| "Receiver went through an unrecoverable error")?;
-    msg_sender
-        .await?
-        .with_context(|| "Message sender crashed.")?;
-
-    // thread .join()'s Err variant does not implement Error trait -> .expect.
-    stdin_parser
-        .join()
-        .expect("stdin parser thread should never panic")
-        .with_context(|| "standard input parser crashed.")?;
-    Ok(())
+    // Channel of the receiver's answers to the server (read receipts, preferences) to the sender.
+    let (reply_sender, replies) = mpsc::channel(128);
+
+    let mut msg_receiver = tokio::spawn(receive_in_loop(
+        config.clone(),
+        reader,
+        session.clone(),
+        reply_sender,
+        quit_receiver,
+    ));
+    // Selecting on the msg_receiver is important for crash to show up when it happens.
+    let chatted = select!(
+        received = &mut msg_receiver => {
+            received?.with_context(|| "Receiver went through an unrecoverable error")?;
+            Err(anyhow!("Receiver stopped unexpectedly"))
+        }
+        handled = handle_input(&config, commands, inputs, replies, writer, session.clone(), quit_sender) => {
+            let switch = handled.with_context(|| "Message sender crashed.")?;
+            msg_receiver
+                .await?
+                .with_context(|| "Receiver went through an unrecoverable error")?;
+            Ok(switch)
+        }
+    );
+    // The next connection (of a profile switch) starts with nothing pending.
+    for line in session.abandon() {
+        eprintln!("{line}");
+    }
+    chatted
+}
+
+/// Time [Input::stop] waits for the parser thread to finish.
+const INPUT_STOP_GRACE: Duration = Duration::from_millis(100);
+
+/// User input parsed in a thread of its own, see [parse_lines].
+struct Input {
+    parsed: mpsc::Receiver<Result<Command, ParseInputError>>,
+    /// Tells the parser not to pass further lines on, it stops at the next one.
+    stop: Arc<AtomicBool>,
+    thread: std::thread::JoinHandle<anyhow::Result<()>>,
+}
+impl Input {
+    /// Spawns the thread making the line source and parsing its lines.
+    fn spawn<L, F>(source: F, commands: Commands, auto_attach: AutoAttach) -> Self
+    where
+        L: editor::Lines,
+        F: FnOnce() -> anyhow::Result<L> + Send + 'static,
+    {
+        // Channel to pass input read in blocking thread to the async handle task.
+        let (sender, parsed) = mpsc::channel(128);
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopping = stop.clone();
+        let thread = std::thread::spawn(move || {
+            parse_lines(source()?, sender, &commands, auto_attach, &stopping)
+        });
+        Input {
+            parsed,
+            stop,
+            thread,
+        }
+    }
+
+    /// Stops the parser and returns its result once it finishes within the [grace][INPUT_STOP_GRACE].
+    ///
+    /// A parser still blocked reading a line can not be cancelled, it is left behind (not joined)
+    /// and stops without passing the line on, so the client does not wait for the user to press Enter.
+    async fn stop(mut self) -> anyhow::Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.parsed.close();
+        let deadline = Instant::now() + INPUT_STOP_GRACE;
+        while !self.thread.is_finished() && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        match self.thread.is_finished() {
+            // thread .join()'s Err variant does not implement Error trait -> .expect.
+            true => self
+                .thread
+                .join()
+                .expect("stdin parser thread should never panic"),
+            false => Ok(()),
+        }
+    }
 }
 
-/// Reads lines from standard input, parses them and sends the result over the `sender` channel until a [Quit][Command::Quit] is parsed.
+/// Reads lines from the `lines`, parses them and sends the result over the `sender` channel until a [Quit][Command::Quit] is parsed.
+///
+/// Lines are parsed by the `commands`, the terminal's ones are edited as [editor::line_editor] says.
+/// Stops as well on Ctrl-C or Ctrl-D, when the channel gets closed or the `stop` flag is set,
+/// the client is shutting down then.
 // The practice of spawning a blocking thread for interactive user input, is advised in
 // the [tokio documentation](https://docs.rs/tokio_wasi/latest/tokio/io/fn.stdin.html).
 //
@@ -100,30 +598,528 @@ pub async fn run(config: Config) -> anyhow::Result<()> {
 // ["For technical reasons, stdin is implemented by using an ordinary blocking read
 // on a separate thread, and it is impossible to cancel that read.
 // This can make shutdown of the runtime hang until the user presses enter."](https://docs.rs/tokio/latest/tokio/io/struct.Stdin.html)
-fn parse_stdin(sender: mpsc::Sender<Result<MsgCmd, ParseInputError>>) -> anyhow::Result<()> {
-    for line in std::io::stdin().lines() {
-        let line = line.with_context(|| "Reading a line from stdin failed.")?;
-        let parsed = match line.parse::<Command>() {
-            Ok(Command::Quit) => break,
-            Ok(Command::Msg(cmd)) => Ok(cmd),
-            Err(e) => Err(e),
+// Hence the thread is not waited for, see [Input::stop].
+fn parse_lines(
+    mut lines: impl editor::Lines,
+    sender: mpsc::Sender<Result<Command, ParseInputError>>,
+    commands: &Commands,
+    auto_attach: AutoAttach,
+    stop: &AtomicBool,
+) -> anyhow::Result<()> {
+    let mut multiline = editor::Multiline::default();
+    loop {
+        let Some(line) = lines.read(multiline.prompt())? else {
+            break;
         };
-        sender
-            .blocking_send(parsed)
-            .with_context(|| "Sending the parsed input over the channel failed.")?;
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+        // Passwords and tokens are not kept.
+        if ![".login", ".signup", ".token", ".reset"]
+            .iter()
+            .any(|secret| line.trim_start().starts_with(secret))
+        {
+            lines.remember(&line);
+        }
+        let parsed = match multiline.feed(line) {
+            None => continue,
+            Some(editor::Input::Text(text)) => Ok(MsgCmd::NoCmd(text).into()),
+            Some(editor::Input::Line(line)) => match commands.parse(&line) {
+                Ok(Command::Quit) => break,
+                Ok(Command::Msg(MsgCmd::NoCmd(text))) => {
+                    let attach = match (auto_attach, attachment(&text)) {
+                        (AutoAttach::Off, _) | (_, None) => None,
+                        (AutoAttach::Always, Some(cmd)) => Some(cmd),
+                        (AutoAttach::Ask, Some(cmd)) => {
+                            let prompt = match &cmd {
+                                MsgCmd::Image(path) => t!(Text::SendImage, path = path),
+                                _ => t!(Text::SendFile, path = text.trim()),
+                            };
+                            match lines.read(&prompt)? {
+                                Some(answer) => {
+                                    let answer = answer.trim();
+                                    let yes = i18n::text(Text::Yes).split(' ').any(|y| y == answer);
+                                    (answer.is_empty() || yes).then_some(cmd)
+                                }
+                                None => break,
+                            }
+                        }
+                    };
+                    Ok(attach.unwrap_or(MsgCmd::NoCmd(text)).into())
+                }
+                other => other,
+            },
+        };
+        if stop.load(Ordering::Relaxed) || sender.blocking_send(parsed).is_err() {
+            break;
+        }
     }
     Ok(())
 }
 
+/// What happens to a line consisting only of the path of an existing file, e.g. dragged into the terminal.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AutoAttach {
+    /// The user is asked whether to send the file instead of the text.
+    #[default]
+    Ask,
+    /// The file is sent right away.
+    Always,
+    /// The line is sent as a text.
+    Off,
+}
+impl FromStr for AutoAttach {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ask" => Ok(AutoAttach::Ask),
+            "always" => Ok(AutoAttach::Always),
+            "off" => Ok(AutoAttach::Off),
+            _ => Err(format!(
+                "unknown auto-attach mode `{s}`, expected `ask`, `always` or `off`"
+            )),
+        }
+    }
+}
+
+/// How received files and images are organized in [Config::file_dir] and [Config::img_dir].
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageLayout {
+    /// Everything is saved right in the directory.
+    #[default]
+    Flat,
+    /// In a subdirectory named by the sender, e.g. `files/alice/notes.txt`.
+    Sender,
+    /// In a subdirectory named by the local date of receiving, e.g. `files/2024-05-01/notes.txt`.
+    Date,
+}
+impl StorageLayout {
+    /// Returns the directory in the `base` where data from the user is saved.
+    pub fn dir(&self, base: &Path, from: &str) -> PathBuf {
+        match self {
+            StorageLayout::Flat => base.to_path_buf(),
+            StorageLayout::Sender => base.join(cli_ser::sanitize_file_name(from)),
+            StorageLayout::Date => base.join(chrono::Local::now().format("%Y-%m-%d").to_string()),
+        }
+    }
+
+    /// Returns the [directory][Self::dir] and creates it when it is missing.
+    pub(crate) async fn create_dir(&self, base: &Path, from: &str) -> cli_ser::Result<PathBuf> {
+        let dir = self.dir(base, from);
+        tokio::fs::create_dir_all(&dir).await.map_err(SaveFile)?;
+        Ok(dir)
+    }
+}
+impl FromStr for StorageLayout {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flat" => Ok(StorageLayout::Flat),
+            "sender" => Ok(StorageLayout::Sender),
+            "date" => Ok(StorageLayout::Date),
+            _ => Err(format!(
+                "unknown layout `{s}`, expected `flat`, `sender` or `date`"
+            )),
+        }
+    }
+}
+
+/// Saves the file from the `sender` to its directory of the [layout][Config::layout],
+/// unless the same content is there already, see [dedup].
+///
+/// A file `received` while it arrived (see [ser::Msg::receive_saving_files]) is looked up by its content
+/// before it is kept there, a duplicate is discarded, the file saved before is never touched.
+pub(crate) async fn save_file(
+    config: &Config,
+    sender: &str,
+    file: &File,
+    received: Option<cli_ser::Result<ReceivedFile>>,
+) -> cli_ser::Result<Saved> {
+    let dir = config.layout.create_dir(&config.file_dir, sender).await?;
+    let received = match received {
+        Some(received) => received?,
+        None => {
+            let hash = dedup::hash(file.bytes());
+            return dedup::save_once(&dir, &hash, file.save(&dir, config.on_collision)).await;
+        }
+    };
+    let hash = dedup::hash_file(received.path()).await.map_err(SaveFile)?;
+    if let Some(path) = dedup::find(&dir, &hash).await {
+        received.discard().await?;
+        return Ok(Saved::Duplicate(path));
+    }
+    let path = received.keep(&dir, config.on_collision).await?;
+    dedup::remember(&path, &hash).await.map_err(SaveFile)?;
+    Ok(Saved::New(path))
+}
+
+/// Saves the image from the `sender` like [save_file], [converted][Config::convert_images] if required.
+pub(crate) async fn save_image(
+    config: &Config,
+    sender: &str,
+    image: Image,
+) -> cli_ser::Result<Saved> {
+    let dir = config.layout.create_dir(&config.img_dir, sender).await?;
+    // The received bytes are hashed, the same image is converted to the same one.
+    let hash = dedup::hash(&cli_ser::Bytes::from(image.clone()));
+    let save = async {
+        match &config.convert_images {
+            Some(format) => {
+                image
+                    .save_as_with(&dir, format.clone(), config.image_clock)
+                    .await
+            }
+            None => image.save_with(&dir, config.image_clock).await,
+        }
+    };
+    dedup::save_once(&dir, &hash, save).await
+}
+
+/// Saves the media from the `sender` like [save_file].
+pub(crate) async fn save_media(
+    config: &Config,
+    sender: &str,
+    media: &Media,
+) -> cli_ser::Result<Saved> {
+    let dir = config.layout.create_dir(&config.img_dir, sender).await?;
+    let (_, bytes): (String, cli_ser::Bytes) = media.clone().into();
+    let save = media.save(&dir, config.on_collision);
+    dedup::save_once(&dir, &dedup::hash(&bytes), save).await
+}
+
+/// Returns the command sending the file the `text` is the path of, images as images.
+///
+/// Terminals paste dragged files quoted (`'my notes.txt'`), with escaped spaces (`my\ notes.txt`)
+/// or as `file://` URLs, all of them are recognized.
+fn attachment(text: &str) -> Option<MsgCmd> {
+    let text = text.trim();
+    if text.contains('\n') {
+        return None;
+    }
+    let unquoted = ['\'', '"']
+        .iter()
+        .find_map(|q| text.strip_prefix(*q)?.strip_suffix(*q))
+        .map(str::to_string)
+        .unwrap_or_else(|| text.replace("\\ ", " "));
+    let path = unquoted.strip_prefix("file://").unwrap_or(&unquoted);
+    if path.is_empty() || !Path::new(path).is_file() {
+        return None;
+    }
+    match cli_ser::ImageFormat::from_path(path) {
+        Ok(_) => Some(MsgCmd::Image(path.to_string())),
+        Err(_) => Some(MsgCmd::File(path.to_string())),
+    }
+}
+
 /// A command to make a message.
 #[derive(Debug, PartialEq)]
 enum MsgCmd {
     File(String),
     Image(String),
     LogIn(String, String),
-    SignUp(String, String),
+    /// Username, password and the code of an invitation.
+    SignUp(String, String, Option<String>),
+    /// Name to join as a guest with.
+    Guest(String),
+    /// Bearer token of the identity provider, obtained from the [configured][Config::identity_provider] one when `None`.
+    Token(Option<String>),
+    /// Email to send the password reset code to.
+    RequestReset(String),
+    /// Reset code and the new password.
+    ResetPassword(String, String),
+    /// Code of the authenticator app, the second step of logging in.
+    Totp(String),
+    TwoFactor(cli::TwoFactor),
+    /// Transformation name and the text to transform.
+    Transform(String, String),
+    SetMotd(String),
+    /// User and their storage quota in bytes, the server's default when `None`.
+    SetQuota(String, Option<u64>),
+    /// Sign-ups of the invitation and the days it is valid for, until used up when `None`.
+    Invite(u32, Option<u32>),
+    /// Stored id of the message, the last sent one when `None`.
+    ReadStatus(Option<i64>),
+    Nick(String),
+    Status(String),
+    /// Email for password resets, removed when empty.
+    Email(String),
+    /// Path of the avatar image.
+    Avatar(String),
+    Profile(String),
+    Stats,
+    /// Presence and the message left for those who mention the user.
+    Presence(Presence, Option<String>),
+    Urgent(String),
     NoCmd(String),
 }
+/// Shows the command as the user typed it, passwords and tokens are hidden.
+impl fmt::Display for MsgCmd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File(path) => write!(f, ".file {path}"),
+            Self::Image(path) => write!(f, ".image {path}"),
+            Self::LogIn(name, _) => write!(f, ".login {name} ***"),
+            Self::SignUp(name, _, None) => write!(f, ".signup {name} ***"),
+            Self::SignUp(name, _, Some(_)) => write!(f, ".signup {name} *** ***"),
+            Self::Guest(name) => write!(f, ".guest {name}"),
+            Self::Token(Some(_)) => write!(f, ".token ***"),
+            Self::Token(None) => write!(f, ".token"),
+            Self::RequestReset(email) => write!(f, ".forgot {email}"),
+            Self::ResetPassword(..) => write!(f, ".reset *** ***"),
+            Self::Totp(_) => write!(f, ".totp ***"),
+            Self::TwoFactor(cli::TwoFactor::Enable) => write!(f, ".2fa enable"),
+            Self::TwoFactor(cli::TwoFactor::Confirm(_)) => write!(f, ".2fa confirm ***"),
+            Self::TwoFactor(cli::TwoFactor::Disable(_)) => write!(f, ".2fa disable ***"),
+            Self::Transform(name, text) => write!(f, ".transform {name} {text}"),
+            Self::SetMotd(motd) => write!(f, ".motd {motd}"),
+            Self::SetQuota(user, Some(bytes)) => write!(f, ".quota {user} {bytes}"),
+            Self::SetQuota(user, None) => write!(f, ".quota {user} default"),
+            Self::Invite(uses, Some(days)) => write!(f, ".invite create {uses} {days}"),
+            Self::Invite(uses, None) => write!(f, ".invite create {uses}"),
+            Self::ReadStatus(Some(msg_id)) => write!(f, ".read {msg_id}"),
+            Self::ReadStatus(None) => write!(f, ".read"),
+            Self::Nick(name) => write!(f, ".nick {name}"),
+            Self::Status(status) => write!(f, ".status {status}"),
+            Self::Email(email) => write!(f, ".email {email}"),
+            Self::Avatar(path) => write!(f, ".avatar {path}"),
+            Self::Profile(user) => write!(f, ".profile {user}"),
+            Self::Stats => write!(f, ".stats"),
+            Self::Presence(presence, message) => {
+                let cmd = match presence {
+                    Presence::Online => ".back",
+                    Presence::Away => ".away",
+                    Presence::DoNotDisturb => ".dnd",
+                };
+                match message {
+                    Some(message) => write!(f, "{cmd} {message}"),
+                    None => write!(f, "{cmd}"),
+                }
+            }
+            Self::Urgent(text) => write!(f, ".urgent {text}"),
+            Self::NoCmd(text) => write!(f, "{text}"),
+        }
+    }
+}
+
+/// State of one connection shared by the sending and the receiving task.
+#[derive(Default)]
+struct Session {
+    /// Sent messages waiting for the server's [Ack][ser::Msg::Ack] or [Rejected][ser::Msg::Rejected].
+    pending: Mutex<HashMap<MsgId, Pending>>,
+    /// Descriptions of sent messages the server [stored][ser::Msg::Stored], by their stored id.
+    stored: Mutex<HashMap<i64, String>>,
+    /// Stored id of the last sent message.
+    last_stored: Mutex<Option<i64>>,
+    /// When the user last entered anything.
+    last_input: Mutex<Option<Instant>>,
+    /// User of the last login or sign up, until the server authenticates them.
+    logging_in: Mutex<Option<String>>,
+    /// History of the authenticated user.
+    history: Mutex<Option<History>>,
+    /// The authenticated user.
+    user: Mutex<Option<String>>,
+    /// Users seen so far, offered by the tab completion.
+    users: editor::Users,
+    /// Own messages printed with the [PENDING] marker until the server answers, see [Session::echo].
+    echoes: Mutex<HashMap<MsgId, Echo>>,
+    /// Number of outputs so far, tells whether an echo is still the last line printed.
+    outputs: AtomicU64,
+    /// Filters of received messages, kept over all connections.
+    filters: Arc<Mutex<filters::Active>>,
+    /// Preferences the server keeps for the user, `None` until it sends them.
+    prefs: Mutex<Option<Preferences>>,
+    /// The user of the last log-in is a guest, the server keeps no preferences of guests.
+    guest: AtomicBool,
+    /// Traffic of the connection, shown by `.stats`.
+    traffic: ConnectionStats,
+    /// Codec of the messages, the one the server [chose][ser::Msg::CodecChosen] from its [Authenticated][ser::Msg::Authenticated] on.
+    codec: Mutex<Codec>,
+}
+/// Description of a sent message and when it was sent, see [Session::pending].
+struct Pending {
+    sent: String,
+    at: Instant,
+}
+/// Own message printed as `line` with the [PENDING] marker, as the output number `at`.
+struct Echo {
+    line: String,
+    at: u64,
+}
+impl Session {
+    /// Returns the line showing the user's own data as pending, it is [marked][Self::mark] once the server answers.
+    fn echo(&self, id: MsgId, data: &Data) -> String {
+        let user = self.user.lock().expect("lock poisoned").clone();
+        let line = format!(
+            "{}: {}",
+            user.as_deref().unwrap_or(i18n::text(Text::You)),
+            indented(&summary(data))
+        );
+        let at = self.outputs.fetch_add(1, Ordering::Relaxed) + 1;
+        let pending = format!("{line} {PENDING}");
+        self.echoes
+            .lock()
+            .expect("lock poisoned")
+            .insert(id, Echo { line, at });
+        pending
+    }
+
+    /// Returns the echo of the message with the marker, it replaces the pending one
+    /// when nothing was printed after it, `None` when the message was not echoed.
+    fn mark(&self, id: MsgId, marker: &str) -> Option<String> {
+        let echo = self.echoes.lock().expect("lock poisoned").remove(&id)?;
+        let last = self.outputs.fetch_add(1, Ordering::Relaxed) == echo.at;
+        let one_line = !echo.line.contains('\n') && echo.line.chars().count() < ECHO_REPLACED_UP_TO;
+        let up = if last && one_line { LINE_UP } else { "" };
+        Some(format!("{up}{} {marker}", echo.line))
+    }
+
+    /// Gives up the pending messages sent before the [ANSWER_TIMEOUT], returns the lines reporting them.
+    fn expire(&self, now: Instant) -> Vec<String> {
+        let expired: Vec<MsgId> = self
+            .pending
+            .lock()
+            .expect("lock poisoned")
+            .iter()
+            .filter(|(_, pending)| now.duration_since(pending.at) >= ANSWER_TIMEOUT)
+            .map(|(id, _)| *id)
+            .collect();
+        self.give_up(expired)
+    }
+
+    /// Gives up all the pending messages, the server of a closed connection does not answer them.
+    fn abandon(&self) -> Vec<String> {
+        let pending: Vec<MsgId> = self
+            .pending
+            .lock()
+            .expect("lock poisoned")
+            .keys()
+            .copied()
+            .collect();
+        self.give_up(pending)
+    }
+
+    /// Forgets the pending messages, returns their echoes marked as failed and the errors reporting them.
+    fn give_up(&self, mut ids: Vec<MsgId>) -> Vec<String> {
+        ids.sort_by_key(|id| id.0);
+        let mut lines = Vec::new();
+        for id in ids {
+            let Some(pending) = self.pending.lock().expect("lock poisoned").remove(&id) else {
+                continue;
+            };
+            lines.extend(self.mark(id, FAILED));
+            let sent = format!(" ({})", pending.sent);
+            lines.push(render::error(t!(Text::MsgUnanswered, id = id, sent = sent)));
+        }
+        lines
+    }
+
+    /// Counts an output other than echoes, later echoes are marked on lines of their own.
+    fn printed(&self) {
+        self.outputs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Whether the authenticated user is among the `mentions`.
+    fn mentioned(&self, mentions: &[cli_ser::User]) -> bool {
+        let user = self.user.lock().expect("lock poisoned");
+        mentions.iter().any(|m| Some(m.to_string()) == *user)
+    }
+
+    /// Whether the message is received data the [filters][Filters] hide, urgent texts are never hidden.
+    fn hides(&self, msg: &ser::Msg) -> bool {
+        match msg {
+            ser::Msg::DataFrom { urgent: true, .. } => false,
+            ser::Msg::DataFrom { data, from, .. } => self
+                .filters
+                .lock()
+                .expect("lock poisoned")
+                .hides(&from.to_string(), &summary(data)),
+            _ => false,
+        }
+    }
+
+    /// Puts the preferences the server sent in place, merged with the filters.
+    ///
+    /// Stored patterns which are not valid are reported and skipped, filters new to the configuration are saved to it.
+    /// Returns the merged preferences when the server lacks some of the filters, they are to be stored back.
+    fn adopt(&self, config: &Config, stored: &Preferences) -> Option<Preferences> {
+        let merged = self.merge_filters(config, stored);
+        *self.prefs.lock().expect("lock poisoned") = Some(merged.clone());
+        let lacking = merged.muted.iter().any(|user| !stored.muted.contains(user))
+            || merged
+                .patterns
+                .iter()
+                .any(|pattern| !stored.patterns.contains(pattern));
+        lacking.then_some(merged)
+    }
+
+    /// Adds the stored filters to the active ones, returns the preferences with all of them.
+    fn merge_filters(&self, config: &Config, stored: &Preferences) -> Preferences {
+        let mut filters = self.filters.lock().expect("lock poisoned");
+        let mut added = false;
+        for user in &stored.muted {
+            added |= filters.mute(&user.to_string());
+        }
+        for pattern in &stored.patterns {
+            match filters.add(pattern) {
+                Ok(new) => added |= new,
+                Err(e) => eprintln!(
+                    "{}",
+                    render::error(t!(
+                        Text::PatternSkipped,
+                        pattern = format!("`{pattern}`"),
+                        err = e
+                    ))
+                ),
+            }
+        }
+        if added {
+            save_filters(config, filters.filters());
+        }
+        with_filters(stored.clone(), filters.filters())
+    }
+
+    /// The stored preferences with the current filters, `None` until the server sent them.
+    fn synced_prefs(&self) -> Option<Preferences> {
+        let mut prefs = self.prefs.lock().expect("lock poisoned");
+        let filters = self.filters.lock().expect("lock poisoned");
+        let synced = with_filters(prefs.take()?, filters.filters());
+        *prefs = Some(synced.clone());
+        Some(synced)
+    }
+
+    /// The notifications of the configuration, only of mentions when the stored preferences say so too.
+    fn notifications(&self, notify: &Notifications) -> Notifications {
+        let prefs = self.prefs.lock().expect("lock poisoned");
+        let stored = prefs.as_ref().is_some_and(|prefs| prefs.mentions_only);
+        Notifications {
+            mentions_only: notify.mentions_only || stored,
+            ..notify.clone()
+        }
+    }
+
+    /// Codec the messages are sent and received by now.
+    fn codec(&self) -> Codec {
+        *self.codec.lock().expect("lock poisoned")
+    }
+
+    /// Messages `.history` prints when not told how many.
+    fn history_length(&self) -> usize {
+        let prefs = self.prefs.lock().expect("lock poisoned");
+        let stored = prefs.as_ref().and_then(|prefs| prefs.history_length);
+        stored.map_or(HISTORY_DEFAULT, |n| n as usize)
+    }
+
+    /// Adds the message to the history, if someone is authenticated.
+    fn record(&self, from: Option<String>, text: String) {
+        if let Some(history) = &*self.history.lock().expect("lock poisoned") {
+            if let Err(e) = history.append(&history::Entry::now(from, text)) {
+                eprintln!("{}", render::error(failure(&e)));
+            }
+        }
+    }
+}
 
 #[derive(Debug)]
 struct ParseInputError(String);
@@ -132,175 +1128,974 @@ struct ParseInputError(String);
 #[derive(Debug, PartialEq)]
 enum Command {
     Msg(MsgCmd),
+    /// Number of the last messages to print, as the [preferences][Preferences] say when `None`.
+    History(Option<usize>),
+    Switch(String),
+    Mute(String),
+    /// Pattern of the messages to hide, a valid regular expression.
+    AddFilter(String),
+    Filters,
+    /// Shows the [preferences][Preferences] or changes one of them.
+    Prefs(Option<PrefsChange>),
+    Help,
+    /// Name and arguments of a [custom command][Commands::register].
+    Custom(String, String),
     Quit,
 }
+/// Change of one of the [preferences][Preferences] besides the filters, see `.prefs`.
+#[derive(Debug, PartialEq)]
+enum PrefsChange {
+    MentionsOnly(bool),
+    HistoryLength(u32),
+}
 impl From<MsgCmd> for Command {
     fn from(cmd: MsgCmd) -> Self {
         Self::Msg(cmd)
     }
 }
+/// Parses the built-in commands, see [Commands].
 impl FromStr for Command {
     type Err = ParseInputError;
 
     fn from_str(line: &str) -> Result<Self, Self::Err> {
-        let mut words = line.split_whitespace();
-        match words.next().and_then(|s| s.strip_prefix('.')) {
-            Some("quit") => match words.next() {
-                None => Ok(Self::Quit),
-                Some(_) => Err(ParseInputError(
-                    ".quit command can not be followed by any text!".to_string(),
-                )),
-            },
-            Some("file") => match (words.next(), words.next()) {
-                (Some(file), None) => Ok(MsgCmd::File(file.to_string()).into()),
-                _ => Err(ParseInputError(
-                    "command \".file\" requires a path as the only argument!".to_string(),
-                )),
-            },
-            Some("image") => match (words.next(), words.next()) {
-                (Some(path), None) => Ok(MsgCmd::Image(path.to_string()).into()),
-                _ => Err(ParseInputError(
-                    "command \".image\" requires the path as the only argument!".to_string(),
-                )),
-            },
-            Some("login") => match (words.next(), words.next(), words.next()) {
-                (Some(name), Some(pswd), None) => {
-                    Ok(MsgCmd::LogIn(name.to_string(), pswd.to_string()).into())
-                }
-                _ => Err(ParseInputError(
-                    "command \".login\" needs a username, password and nothing else!".to_string(),
-                )),
-            },
-            Some("signup") => match (words.next(), words.next(), words.next()) {
-                (Some(name), Some(pswd), None) => {
-                    Ok(MsgCmd::SignUp(name.to_string(), pswd.to_string()).into())
-                }
-                _ => Err(ParseInputError(
-                    "command \".signup\" needs a username, password and nothing else!".to_string(),
-                )),
-            },
-            Some(cmd) => Err(ParseInputError(format!(
-                "command \".{cmd}\" is not supported"
-            ))),
-            _ => Ok(MsgCmd::NoCmd(line.to_string()).into()),
-        }
+        Commands::default().parse(line)
     }
 }
 
 /// Receives and processes messages from the server until quit message comes.
+///
+/// Read receipts of received data are sent to `replies`, so are the requests of the [preferences][Session::adopt]
+/// after logging in and the merged ones to store back, and the solutions of [sign-up challenges][solve_challenge].
+/// A message which can not be deserialized is reported and skipped, its frame was read whole
+/// (as its length prefix says), so the next message is read from the right place.
+/// The codec the server chose is taken up by the [session][Session::codec] once the user is authenticated.
 async fn receive_in_loop<R>(
     config: Config,
     mut reader: R,
+    session: Arc<Session>,
+    replies: mpsc::Sender<cli::Msg>,
     mut quit: oneshot::Receiver<()>,
 ) -> anyhow::Result<()>
 where
     R: AsyncReadExt + std::marker::Unpin + std::marker::Send,
 {
+    let mut chosen = Codec::default();
     loop {
         select!(
-            msg = ser::Msg::receive(&mut reader) => process_msg(&config, msg.with_context(|| "reading a message from server failed")?).await,
+            msg = ser::Msg::receive_saving_files_with(session.codec(), &mut reader, &config.file_dir, progress_bar(i18n::text(Text::Receiving))) => match msg {
+                Ok((msg, received)) => {
+                    match &msg {
+                        ser::Msg::CodecChosen(codec) => chosen = *codec,
+                        ser::Msg::Authenticated => *session.codec.lock().expect("lock poisoned") = chosen,
+                        _ => {}
+                    }
+                    let msg_id = match &msg {
+                        ser::Msg::DataFrom { data, from, msg_id, display_name, mentions, urgent, .. } => {
+                            session.users.lock().expect("lock poisoned").insert(from.to_string());
+                            let text = summary(data);
+                            let last_input = *session.last_input.lock().expect("lock poisoned");
+                            let mentioned = session.mentioned(mentions);
+                            if !session.hides(&msg) {
+                                let notify = session.notifications(&config.notify);
+                                notify.notify(last_input, mentioned, *urgent, &from.to_string(), &text);
+                            }
+                            session.record(Some(sender(from, display_name.clone())), text);
+                            *msg_id
+                        }
+                        _ => None,
+                    };
+                    let reply = match &msg {
+                        ser::Msg::Authenticated if !session.guest.load(Ordering::Relaxed) => Some(cli::Msg::GetPrefs),
+                        ser::Msg::Prefs(prefs) => session.adopt(&config, prefs).map(cli::Msg::SetPrefs),
+                        ser::Msg::Challenge { prefix, difficulty } => solve_challenge(prefix, *difficulty).await,
+                        _ => None,
+                    };
+                    process_msg(&config, &session, msg, received).await;
+                    // The sender is gone only when the session is over.
+                    if let Some(msg_id) = msg_id {
+                        let _ = replies.send(cli::Msg::MarkRead { msg_id }).await;
+                    }
+                    if let Some(reply) = reply {
+                        let _ = replies.send(reply).await;
+                    }
+                }
+                Err(DisconnectedStream(_)) => break Err(anyhow!("the server closed the connection")),
+                Err(e @ DeserializeMsg(_)) => eprintln!("{}", render::error(t!(Text::Malformed, err = e))),
+                Err(e) => break Err(e).with_context(|| "reading a message from server failed"),
+            },
             _ = &mut quit => break Ok(()),
         )
     }
 }
 
+/// Solves the proof of work the server asks for before signing up, on a blocking thread.
+///
+/// Returns the solution to send, `None` when the challenge is too hard, the server then refuses the sign-up.
+async fn solve_challenge(prefix: &str, difficulty: u8) -> Option<cli::Msg> {
+    println!("{}", render::info(i18n::text(Text::SolvingChallenge)));
+    let prefix = prefix.to_string();
+    let solve = tokio::task::spawn_blocking(move || challenge::solve(&prefix, difficulty));
+    match solve.await.ok().flatten() {
+        Some(nonce) => Some(cli::Msg::Auth(cli::Auth::Solution(nonce))),
+        None => {
+            let too_hard = t!(Text::ChallengeTooHard, difficulty = difficulty);
+            eprintln!("{}", render::error(too_hard));
+            None
+        }
+    }
+}
+
 /// Processes the message, depending on the type, it either prints it or writes it to a file.
-async fn process_msg(config: &Config, msg: ser::Msg) {
+///
+/// A file `received` while it arrived is [saved][save_file] from where it was received to.
+/// Rejections are reported together with the [pending][Session::pending] message they refer to.
+/// Echoes of the user's own messages are marked as confirmed or rejected.
+/// Data the [filters][Filters] hide is skipped, a file received of it is discarded,
+/// the files saved in the directories are not touched.
+async fn process_msg(
+    config: &Config,
+    session: &Session,
+    msg: ser::Msg,
+    received: Option<cli_ser::Result<ReceivedFile>>,
+) {
+    if session.hides(&msg) {
+        if let Some(Ok(received)) = received {
+            let path = format!("{:?}", received.path());
+            if let Err(e) = received.discard().await {
+                let err = format!("{e:?}");
+                eprintln!(
+                    "{}",
+                    render::error(t!(Text::HiddenFileNotRemoved, path = path, err = err))
+                );
+            }
+        }
+        return;
+    }
+    if !matches!(
+        msg,
+        ser::Msg::Ack(_) | ser::Msg::Stored { .. } | ser::Msg::Rejected(..)
+    ) {
+        session.printed();
+    }
     match msg {
         ser::Msg::DataFrom {
             data: Data::Text(text),
             from,
-        } => println!("{from}: {text}"),
+            display_name,
+            mentions,
+            guest,
+            urgent,
+            ..
+        } => {
+            let from = render::sender(&from.to_string(), display_name, guest);
+            match (urgent, session.mentioned(&mentions)) {
+                (true, _) => println!("{from}: {}", render::urgent(indented(&text))),
+                (false, true) => println!("{from}: {}", render::mention(indented(&text))),
+                (false, false) => println!("{from}: {}", indented(&text)),
+            }
+        }
         ser::Msg::DataFrom {
             data: Data::File(f),
             from,
+            display_name,
+            guest,
+            ..
         } => {
-            println!("Received {:?} from {from}", f.name());
-            f.save(&config.file_dir).await.unwrap_or_else(|e| {
-                eprintln!("...saving the file \"{:?}\" failed! Err: {:?}", f.name(), e)
-            });
+            let sender = from.to_string();
+            let from = render::sender(&sender, display_name, guest);
+            let name = format!("{:?}", f.name());
+            println!("{}", t!(Text::ReceivedFile, name = name, from = from));
+            match save_file(config, &sender, &f, received).await {
+                Ok(Saved::New(path)) => {
+                    println!("{}", t!(Text::FileSaved, path = format!("{path:?}")))
+                }
+                Ok(Saved::Duplicate(path)) => {
+                    println!("{}", t!(Text::AlreadySaved, path = format!("{path:?}")))
+                }
+                Err(e) => eprintln!(
+                    "{}",
+                    render::error(t!(Text::FileNotSaved, name = name, err = format!("{e:?}")))
+                ),
+            }
         }
         ser::Msg::DataFrom {
             data: Data::Image(image),
             from,
+            display_name,
+            guest,
+            ..
+        } => {
+            let sender = from.to_string();
+            let from = render::sender(&sender, display_name, guest);
+            println!("{}", t!(Text::ReceivedImage, from = from));
+            match save_image(config, &sender, image).await {
+                Ok(Saved::New(path)) => {
+                    println!("{}", t!(Text::ImageSaved, path = format!("{path:?}")))
+                }
+                Ok(Saved::Duplicate(path)) => {
+                    println!("{}", t!(Text::AlreadySaved, path = format!("{path:?}")))
+                }
+                Err(e) => eprintln!(
+                    "{}",
+                    render::error(t!(Text::ImageNotSaved, err = format!("{e:?}")))
+                ),
+            }
+        }
+        ser::Msg::DataFrom {
+            data: Data::Media(media),
+            from,
+            display_name,
+            guest,
+            ..
         } => {
-            println!("Received image from {from}...");
-            match if config.save_png {
-                image.save_as_png(&config.img_dir).await
-            } else {
-                image.save(&config.img_dir).await
-            } {
-                Ok(path) => println!("...image was saved to {:?}", path),
-                Err(e) => eprintln!("...saving the image failed! Err: {:?}", e),
+            let sender = from.to_string();
+            let from = render::sender(&sender, display_name, guest);
+            println!(
+                "{}",
+                t!(Text::ReceivedMedia, media = describe(&media), from = from)
+            );
+            match save_media(config, &sender, &media).await {
+                Ok(Saved::New(path)) => {
+                    println!("{}", t!(Text::MediaSaved, path = format!("{path:?}")))
+                }
+                Ok(Saved::Duplicate(path)) => {
+                    println!("{}", t!(Text::AlreadySaved, path = format!("{path:?}")))
+                }
+                Err(e) => eprintln!(
+                    "{}",
+                    render::error(t!(Text::MediaNotSaved, err = format!("{e:?}")))
+                ),
             }
         }
-        ser::Msg::Authenticated => println!("Welcome!"),
-        ser::Msg::Error(ser::Error::WrongPassword) => {
-            eprintln!("Given password is not correct")
+        ser::Msg::Authenticated => {
+            if let Some(user) = session.logging_in.lock().expect("lock poisoned").take() {
+                let history = History::of_user(&config.history_file, &user);
+                *session.history.lock().expect("lock poisoned") = Some(history);
+                *session.user.lock().expect("lock poisoned") = Some(user);
+            }
+            println!("{}", i18n::text(Text::Welcome))
         }
-        ser::Msg::Error(ser::Error::WrongUser) => {
-            eprintln!("The user does not exist, you can create it with a .signup")
+        ser::Msg::ServerInfo(info) => println!("{}", render::info(info)),
+        ser::Msg::TotpRequired => println!("{}", render::info(i18n::text(Text::TotpRequired))),
+        ser::Msg::TotpEnrollment { url } => {
+            println!("{}", t!(Text::TotpEnrollment, url = url));
+            let saved = match qr_code(&url) {
+                Ok(qr) => qr
+                    .save_as_with(
+                        &config.img_dir,
+                        cli_ser::ImageFormat::Png,
+                        config.image_clock,
+                    )
+                    .await
+                    .map_err(Into::into),
+                Err(e) => Err(e),
+            };
+            match saved {
+                Ok(path) => println!("{}", t!(Text::TotpQrSaved, path = format!("{path:?}"))),
+                Err(e) => eprintln!(
+                    "{}",
+                    render::error(t!(Text::TotpQrNotSaved, err = format!("{e:?}")))
+                ),
+            }
         }
-        ser::Msg::Error(ser::Error::UsernameTaken) => {
-            eprintln!("Unfortunately this username is already taken, choose another one.")
+        ser::Msg::Ack(id) => {
+            session.pending.lock().expect("lock poisoned").remove(&id);
+            if let Some(confirmed) = session.mark(id, CONFIRMED) {
+                println!("{confirmed}");
+            }
         }
-        ser::Msg::Error(ser::Error::NotAuthenticated(msg)) => {
-            eprintln!(
-                "You need to .login or .signup before sending a message (parsed message: {msg})"
-            )
+        ser::Msg::Stored { id, msg_id } => {
+            if let Some(pending) = session.pending.lock().expect("lock poisoned").get(&id) {
+                let mut stored = session.stored.lock().expect("lock poisoned");
+                stored.insert(msg_id, pending.sent.clone());
+            }
+            *session.last_stored.lock().expect("lock poisoned") = Some(msg_id);
+        }
+        ser::Msg::ReadBy { msg_id, users } => {
+            let sent = session
+                .stored
+                .lock()
+                .expect("lock poisoned")
+                .get(&msg_id)
+                .cloned();
+            let sent = sent.map(|cmd| format!(" ({cmd})")).unwrap_or_default();
+            match users.as_slice() {
+                [] => println!("{}", t!(Text::NotReadYet, msg_id = msg_id, sent = sent)),
+                users => {
+                    let users: Vec<_> = users.iter().map(|u| u.to_string()).collect();
+                    let mut known = session.users.lock().expect("lock poisoned");
+                    known.extend(users.iter().cloned());
+                    drop(known);
+                    let users = users.join(", ");
+                    println!(
+                        "{}",
+                        t!(Text::ReadBy, msg_id = msg_id, sent = sent, users = users)
+                    )
+                }
+            }
+        }
+        ser::Msg::Profile { user, profile } => {
+            session
+                .users
+                .lock()
+                .expect("lock poisoned")
+                .insert(user.to_string());
+            println!(
+                "{}",
+                render::sender(&user.to_string(), profile.display_name, false)
+            );
+            if let Some(status) = profile.status {
+                println!("{}", t!(Text::Status, status = status));
+            }
+            if let Some(avatar) = profile.avatar {
+                match avatar.save_with(&config.img_dir, config.image_clock).await {
+                    Ok(path) => println!("{}", t!(Text::AvatarSaved, path = format!("{path:?}"))),
+                    Err(e) => eprintln!(
+                        "{}",
+                        render::error(t!(Text::AvatarNotSaved, err = format!("{e:?}")))
+                    ),
+                }
+            }
         }
-        ser::Msg::Error(ser::Error::AlreadyAuthenticated) => {
+        ser::Msg::Rejected(id, err) => {
+            if let Some(failed) = session.mark(id, FAILED) {
+                println!("{failed}");
+            }
+            session.printed();
+            let sent = session.pending.lock().expect("lock poisoned").remove(&id);
+            let sent = sent
+                .map(|pending| format!(" ({})", pending.sent))
+                .unwrap_or_default();
             eprintln!(
-                "You are currently logged in, if you want to log in as another user first log out."
+                "{}",
+                render::error(t!(
+                    Text::MsgRejected,
+                    id = id,
+                    sent = sent,
+                    reason = explain(&err)
+                ))
             )
         }
-        ser::Msg::Error(err) => eprintln!("Error: {err:?}"),
+        ser::Msg::Error(err) => eprintln!("{}", render::error(explain(&err))),
+        ser::Msg::NewSession { addr, time } => {
+            let notice = t!(Text::NewSession, addr = addr, time = time);
+            println!("{}", render::info(notice))
+        }
+        ser::Msg::Stats {
+            uptime,
+            connected,
+            messages_today,
+            version,
+        } => {
+            let stats = t!(
+                Text::Stats,
+                version = version,
+                uptime = format_uptime(uptime),
+                connected = connected,
+                messages = messages_today
+            );
+            println!("{}", render::info(stats));
+            let traffic = session.traffic.snapshot();
+            let traffic = t!(
+                Text::Traffic,
+                sent = traffic.messages_sent,
+                sent_bytes = traffic.bytes_sent,
+                received = traffic.messages_received,
+                received_bytes = traffic.bytes_received,
+                idle = format_uptime(session.traffic.idle())
+            );
+            println!("{}", render::info(traffic))
+        }
+        ser::Msg::PresenceChanged {
+            user,
+            presence,
+            message,
+        } => println!(
+            "{}",
+            render::info(describe_presence(&user, presence, message))
+        ),
+        // Put in place by the receiver already.
+        ser::Msg::Prefs(_) => println!("{}", render::info(i18n::text(Text::PrefsLoaded))),
+        // Solved by the receiver already.
+        ser::Msg::Challenge { .. } => {}
+        // Taken up by the receiver already.
+        ser::Msg::CodecChosen(_) => {}
+        ser::Msg::Invite {
+            code,
+            uses,
+            expires,
+        } => {
+            let invite = match expires {
+                Some(expires) => t!(
+                    Text::InviteExpires,
+                    code = code,
+                    uses = uses,
+                    expires = expires
+                ),
+                None => t!(Text::Invite, code = code, uses = uses),
+            };
+            println!("{}", render::info(invite))
+        }
     };
 }
 
-/// Makes messages from incoming parsed input, when successful, writes them to the `writer`.
-///
-/// When `inputs` are closed, sends a quit signal to the `quit` one-shot channel.
-async fn handle_input<W>(
-    mut inputs: mpsc::Receiver<Result<MsgCmd, ParseInputError>>,
-    mut writer: W,
-    quit: oneshot::Sender<()>,
-) -> anyhow::Result<()>
-where
-    W: AsyncWriteExt + std::marker::Unpin + std::marker::Send,
+/// Tells the presence of the user and the message they left, if any.
+fn describe_presence(user: &cli_ser::User, presence: Presence, message: Option<String>) -> String {
+    let text = match presence {
+        Presence::Online => Text::Online,
+        Presence::Away => Text::Away,
+        Presence::DoNotDisturb => Text::DoNotDisturb,
+    };
+    let message = message.map(|m| format!(": {m}")).unwrap_or_default();
+    t!(text, user = user, message = message)
+}
+
+/// Formats the duration by its two largest units, e.g. `2d 5h`, `3h 20m` or `45s`.
+fn format_uptime(uptime: Duration) -> String {
+    let secs = uptime.as_secs();
+    let (days, hours, mins) = (secs / 86_400, secs / 3600 % 24, secs / 60 % 60);
+    match (days, hours, mins) {
+        (0, 0, 0) => format!("{secs}s"),
+        (0, 0, mins) => format!("{mins}m {}s", secs % 60),
+        (0, hours, mins) => format!("{hours}h {mins}m"),
+        (days, hours, _) => format!("{days}d {hours}h"),
+    }
+}
+
+/// Describes the data in a line, files and images by their kind.
+fn summary(data: &Data) -> String {
+    match data {
+        Data::Text(text) => text.clone(),
+        Data::File(file) => t!(Text::SentFile, name = file.name()),
+        Data::Image(_) => i18n::text(Text::SentImage).to_string(),
+        Data::Media(media) => t!(Text::SentMedia, media = describe(media)),
+    }
+}
+
+/// Describes the media by its name, type and duration, e.g. `"cat.gif" (image/gif, 2.5 s)`.
+fn describe(media: &Media) -> String {
+    match media.duration() {
+        Some(duration) => format!(
+            "{:?} ({}, {:.1} s)",
+            media.name(),
+            media.mime(),
+            duration.as_secs_f64()
+        ),
+        None => format!("{:?} ({})", media.name(), media.mime()),
+    }
+}
+
+/// Pixels of one module of [QR codes][qr_code] and modules of the light border around them.
+const QR_MODULE_PIXELS: usize = 8;
+const QR_QUIET_ZONE: usize = 4;
+
+/// Draws the text as a QR code, e.g. a two-factor URL for authenticator apps to scan.
+fn qr_code(text: &str) -> anyhow::Result<Image> {
+    let code = qrcode::QrCode::new(text).context("The text does not fit a QR code")?;
+    let modules = code.width();
+    let colors = code.to_colors();
+    let side = (modules + 2 * QR_QUIET_ZONE) * QR_MODULE_PIXELS;
+    // Binary PGM, any decoder reads it and it is converted on saving.
+    let mut pgm = format!("P5 {side} {side} 255\n").into_bytes();
+    for y in 0..side {
+        for x in 0..side {
+            let module = |at: usize| (at / QR_MODULE_PIXELS).checked_sub(QR_QUIET_ZONE);
+            let dark = match (module(x), module(y)) {
+                (Some(x), Some(y)) if x < modules && y < modules => {
+                    colors[y * modules + x] == qrcode::Color::Dark
+                }
+                _ => false,
+            };
+            pgm.push(if dark { 0 } else { 255 });
+        }
+    }
+    Ok(Image::try_from(pgm)?)
+}
+
+/// Shows the sender as "Display Name (username)" when the display name is set.
+fn sender(user: &cli_ser::User, display_name: Option<String>) -> String {
+    match display_name {
+        Some(name) => format!("{name} ({user})"),
+        None => user.to_string(),
+    }
+}
+
+/// Explains the server error to the user, followed by its [code][ErrorCode].
+fn explain(err: &ser::Error) -> String {
+    let text = match err {
+        ser::Error::WrongPassword => i18n::text(Text::WrongPassword).to_string(),
+        ser::Error::WrongUser => i18n::text(Text::WrongUser).to_string(),
+        ser::Error::InvalidCredentials => i18n::text(Text::InvalidCredentials).to_string(),
+        ser::Error::UsernameTaken => i18n::text(Text::UsernameTaken).to_string(),
+        ser::Error::NotAuthenticated(msg) => t!(Text::NotAuthenticated, msg = msg),
+        ser::Error::AlreadyAuthenticated => i18n::text(Text::AlreadyAuthenticated).to_string(),
+        ser::Error::NotAdmin => i18n::text(Text::NotAdmin).to_string(),
+        ser::Error::UnknownMessage(msg_id) => t!(Text::UnknownMessage, msg_id = msg_id),
+        ser::Error::UnknownUser(user) => t!(Text::UnknownUser, user = user),
+        ser::Error::Rejected(reason) => t!(Text::ServerRejected, reason = reason),
+        ser::Error::SessionActive => i18n::text(Text::SessionActive).to_string(),
+        ser::Error::GuestsNotAllowed => i18n::text(Text::GuestsNotAllowed).to_string(),
+        ser::Error::InvalidToken(reason) => t!(Text::InvalidToken, reason = reason),
+        ser::Error::InvalidResetCode => i18n::text(Text::InvalidResetCode).to_string(),
+        ser::Error::InvalidTotpCode => i18n::text(Text::InvalidTotpCode).to_string(),
+        ser::Error::InviteRequired => i18n::text(Text::InviteRequired).to_string(),
+        ser::Error::InvalidInvite => i18n::text(Text::InvalidInvite).to_string(),
+        ser::Error::ChallengeFailed => i18n::text(Text::ChallengeFailed).to_string(),
+        ser::Error::QuotaExceeded { used, limit } => {
+            t!(Text::QuotaExceeded, used = used, limit = limit)
+        }
+        ser::Error::Refused(ser::Refusal::TooManyConnections) => {
+            i18n::text(Text::ServerFull).to_string()
+        }
+        ser::Error::Refused(ser::Refusal::TooManyFromAddress) => {
+            i18n::text(Text::TooManyFromAddress).to_string()
+        }
+        ser::Error::Refused(ser::Refusal::AddressNotAllowed) => {
+            i18n::text(Text::AddressNotAllowed).to_string()
+        }
+        ser::Error::Other { detail, .. } => t!(Text::UnknownError, detail = detail),
+        err => t!(Text::OtherError, err = err),
+    };
+    format!("{text} [{}]", err.code())
+}
+
+/// The error with its causes, followed by its [code][ErrorCode] when it has one.
+fn failure(e: &anyhow::Error) -> String {
+    match ErrorCode::of(e.as_ref()) {
+        Some(code) => format!("{e:#} [{code}]"),
+        None => format!("{e:#}"),
+    }
+}
+
+/// Saves the filters to the configuration file, when there is one, reports a failure.
+fn save_filters(config: &Config, filters: &Filters) {
+    if let Some(path) = &config.config_file {
+        if let Err(e) = filters.save(path) {
+            eprintln!(
+                "{}",
+                render::error(t!(Text::FiltersNotSaved, err = failure(&e)))
+            );
+        }
+    }
+}
+
+/// The preferences with the muted users and hidden patterns of the filters.
+fn with_filters(prefs: Preferences, filters: &Filters) -> Preferences {
+    Preferences {
+        muted: filters
+            .muted
+            .iter()
+            .cloned()
+            .map(cli_ser::User::from)
+            .collect(),
+        patterns: filters.patterns.clone(),
+        ..prefs
+    }
+}
+
+/// Tells the stored preferences besides the filters, `.history` prints `history_length` messages.
+fn describe_prefs(prefs: &Preferences, history_length: usize) -> String {
+    let notified = match prefs.mentions_only {
+        true => Text::NotifyMentions,
+        false => Text::NotifyAll,
+    };
+    let history = t!(Text::HistoryLength, n = history_length);
+    format!("{}\n{history}", i18n::text(notified))
+}
+
+/// Lists the muted users and the hidden patterns.
+fn describe_filters(filters: &Filters) -> String {
+    let mut lines = Vec::new();
+    if !filters.muted.is_empty() {
+        let users = filters.muted.iter().cloned().collect::<Vec<_>>().join(", ");
+        lines.push(t!(Text::MutedUsers, users = users));
+    }
+    if !filters.patterns.is_empty() {
+        let patterns = filters.patterns.iter().map(|p| format!("`{p}`"));
+        let patterns = patterns.collect::<Vec<_>>().join(", ");
+        lines.push(t!(Text::HiddenPatterns, patterns = patterns));
+    }
+    match lines.is_empty() {
+        true => i18n::text(Text::NothingHidden).to_string(),
+        false => lines.join("\n"),
+    }
+}
+
+/// Indents the lines of a multi-line text after the first one, so that they stand out from other messages.
+fn indented(text: &str) -> String {
+    text.replace('\n', "\n    ")
+}
+
+/// Returns a progress callback showing a bar on the terminal for transfers over [PROGRESS_OVER] bytes.
+fn progress_bar(action: &'static str) -> impl FnMut(u64, u64) + Send {
+    progress_with(action, ProgressBar::new)
+}
+
+/// Progress callback like [progress_bar], the bar is made by `new_bar` from the total bytes of the transfer.
+fn progress_with(
+    action: &'static str,
+    mut new_bar: impl FnMut(u64) -> ProgressBar + Send,
+) -> impl FnMut(u64, u64) + Send {
+    let mut bar: Option<ProgressBar> = None;
+    move |done, total| {
+        if total <= PROGRESS_OVER {
+            return;
+        }
+        let bar = bar.get_or_insert_with(|| {
+            let style = ProgressStyle::with_template(
+                "{msg} [{bar:40}] {bytes}/{total_bytes} ({bytes_per_sec})",
+            )
+            .expect("the template is valid")
+            .progress_chars("=> ");
+            new_bar(total).with_style(style).with_message(action)
+        });
+        bar.set_position(done);
+        if done == total {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+/// Makes messages from incoming parsed input, when successful, writes them to the `writer`.
+///
+/// Every message is tagged with a new [MsgId] and remembered as [pending] until the server answers,
+/// those unanswered for the [ANSWER_TIMEOUT] are given up.
+/// Custom commands are carried out by their handlers from the `commands`.
+/// When `inputs` are closed or a profile switch is requested, sends a quit signal to the `quit` one-shot channel.
+/// Returns the name of the profile to switch to, if any.
+async fn handle_input<W>(
+    config: &Config,
+    commands: &Commands,
+    inputs: &mut mpsc::Receiver<Result<Command, ParseInputError>>,
+    mut replies: mpsc::Receiver<cli::Msg>,
+    mut writer: W,
+    session: Arc<Session>,
+    quit: oneshot::Sender<()>,
+) -> anyhow::Result<Option<String>>
+where
+    W: AsyncWriteExt + std::marker::Unpin + std::marker::Send,
 {
-    while let Some(input) = inputs.recv().await {
+    let mut switch = None;
+    let mut ids = (1..).map(MsgId);
+    let mut expiry = tokio::time::interval(EXPIRY_CHECK);
+    loop {
+        let input = select!(
+            input = inputs.recv() => match input {
+                Some(input) => input,
+                None => break,
+            },
+            Some(reply) = replies.recv() => {
+                reply
+                    .send_with(session.codec(), &mut writer)
+                    .await
+                    .with_context(|| "sending an answer of the receiver to the server failed")?;
+                continue;
+            }
+            _ = expiry.tick() => {
+                for line in session.expire(Instant::now()) {
+                    eprintln!("{line}");
+                }
+                continue;
+            }
+        );
+        *session.last_input.lock().expect("lock poisoned") = Some(Instant::now());
+        session.printed();
         match input {
             Err(e) => {
-                eprintln!("Couldn't parse your command! {e:?}");
+                eprintln!(
+                    "{}",
+                    render::error(t!(Text::CommandNotParsed, err = format!("{e:?}")))
+                );
+            }
+            Ok(Command::Quit) => break,
+            Ok(Command::Switch(profile)) => {
+                switch = Some(profile);
+                break;
+            }
+            Ok(Command::History(n)) => {
+                let n = n.unwrap_or_else(|| session.history_length());
+                let history = session.history.lock().expect("lock poisoned").clone();
+                match history.map(|history| history.last(n)) {
+                    Some(Ok(entries)) => entries
+                        .iter()
+                        .for_each(|entry| println!("{}", render::entry(entry))),
+                    Some(Err(e)) => eprintln!("{}", render::error(failure(&e))),
+                    None => eprintln!("{}", render::error(i18n::text(Text::HistoryNotKept))),
+                }
+            }
+            Ok(Command::Mute(user)) => {
+                let sent = format!(".mute {user}");
+                let muted = {
+                    let mut filters = session.filters.lock().expect("lock poisoned");
+                    let muted = filters.mute(&user);
+                    if muted {
+                        save_filters(config, filters.filters());
+                    }
+                    muted
+                };
+                println!("{}", t!(Text::Muted, user = user));
+                if let Some(prefs) = session.synced_prefs().filter(|_| muted) {
+                    let id = ids.next().expect("ids are endless");
+                    send_tagged(cli::Msg::SetPrefs(prefs), id, sent, &session, &mut writer).await?
+                }
+            }
+            Ok(Command::AddFilter(pattern)) => {
+                let sent = format!(".filter add {pattern}");
+                let added = {
+                    let mut filters = session.filters.lock().expect("lock poisoned");
+                    let added = filters.add(&pattern);
+                    if let Ok(true) = added {
+                        save_filters(config, filters.filters());
+                    }
+                    added
+                };
+                let added = match added {
+                    Ok(added) => added,
+                    Err(e) => {
+                        eprintln!("{}", render::error(t!(Text::CommandNotParsed, err = e)));
+                        continue;
+                    }
+                };
+                println!(
+                    "{}",
+                    t!(Text::FilterAdded, pattern = format!("`{pattern}`"))
+                );
+                if let Some(prefs) = session.synced_prefs().filter(|_| added) {
+                    let id = ids.next().expect("ids are endless");
+                    send_tagged(cli::Msg::SetPrefs(prefs), id, sent, &session, &mut writer).await?
+                }
+            }
+            Ok(Command::Prefs(change)) => {
+                let Some(mut prefs) = session.synced_prefs() else {
+                    eprintln!("{}", render::error(i18n::text(Text::PrefsNotLoaded)));
+                    continue;
+                };
+                let Some(change) = change else {
+                    println!("{}", describe_prefs(&prefs, session.history_length()));
+                    continue;
+                };
+                let sent = match change {
+                    PrefsChange::MentionsOnly(on) => {
+                        prefs.mentions_only = on;
+                        format!(".prefs mentions-only {}", if on { "on" } else { "off" })
+                    }
+                    PrefsChange::HistoryLength(n) => {
+                        prefs.history_length = Some(n);
+                        format!(".prefs history {n}")
+                    }
+                };
+                *session.prefs.lock().expect("lock poisoned") = Some(prefs.clone());
+                println!("{}", describe_prefs(&prefs, session.history_length()));
+                let id = ids.next().expect("ids are endless");
+                send_tagged(cli::Msg::SetPrefs(prefs), id, sent, &session, &mut writer).await?
+            }
+            Ok(Command::Filters) => {
+                let filters = session.filters.lock().expect("lock poisoned");
+                println!("{}", describe_filters(filters.filters()));
+            }
+            Ok(Command::Help) => println!("{}", commands.help()),
+            Ok(Command::Custom(name, args)) => {
+                let sent = format!(".{name} {args}").trim_end().to_string();
+                let handled = match commands.handler(&name) {
+                    Some(handler) => handler(args).await,
+                    None => Err(anyhow!("the command is not registered")),
+                };
+                match handled {
+                    Ok(Some(msg)) => {
+                        let id = ids.next().expect("ids are endless");
+                        send_tagged(msg, id, sent, &session, &mut writer).await?
+                    }
+                    Ok(None) => {}
+                    Err(e) => eprintln!(
+                        "{}",
+                        render::error(t!(Text::CommandFailed, name = name, err = failure(&e)))
+                    ),
+                }
+            }
+            Ok(Command::Msg(cmd)) => {
+                let sent = cmd.to_string();
+                match &cmd {
+                    MsgCmd::LogIn(user, _) | MsgCmd::SignUp(user, ..) | MsgCmd::Guest(user) => {
+                        *session.logging_in.lock().expect("lock poisoned") = Some(user.clone());
+                        let guest = matches!(cmd, MsgCmd::Guest(_));
+                        session.guest.store(guest, Ordering::Relaxed);
+                    }
+                    // The user of a token or a reset code is known to the server only.
+                    MsgCmd::Token(_) | MsgCmd::ResetPassword(..) => {
+                        *session.logging_in.lock().expect("lock poisoned") = None;
+                        session.guest.store(false, Ordering::Relaxed);
+                    }
+                    _ => {}
+                }
+                match make_message(cmd, config, &session).await {
+                    Ok(msg) => {
+                        let id = ids.next().expect("ids are endless");
+                        send_tagged(msg, id, sent, &session, &mut writer).await?
+                    }
+                    Err(e) => eprintln!(
+                        "{}",
+                        render::error(t!(Text::MessageNotMade, err = format!("{e:?}")))
+                    ),
+                }
             }
-            Ok(cmd) => match make_message(cmd).await {
-                Ok(msg) => msg
-                    .send(&mut writer)
-                    .await
-                    .with_context(|| "sending your message to the server failed")?,
-                Err(e) => eprintln!("Couldn't make your message! {e:?}"),
-            },
         }
     }
     quit.send(())
         .map_err(|_| anyhow!("Sending a quit signal to the message receiver failed"))?;
-    Ok(())
+    Ok(switch)
+}
+
+/// Sends the message tagged with the `id`, it is [pending][Session::pending] as `sent` until the server answers.
+///
+/// A message which fails to be sent is not pending, the server never gets it.
+///
+/// Data to all (urgent texts included) is added to the history and [echoed][Session::echo].
+async fn send_tagged<W>(
+    msg: cli::Msg,
+    id: MsgId,
+    sent: String,
+    session: &Session,
+    writer: &mut W,
+) -> anyhow::Result<()>
+where
+    W: AsyncWriteExt + std::marker::Unpin + std::marker::Send,
+{
+    let urgent;
+    let data = match &msg {
+        cli::Msg::ToAll(data) => Some(data),
+        cli::Msg::Urgent(text) => {
+            urgent = Data::Text(text.clone());
+            Some(&urgent)
+        }
+        _ => None,
+    };
+    if let Some(data) = data {
+        session.record(None, summary(data));
+        println!("{}", session.echo(id, data));
+    }
+    let pending = Pending {
+        sent,
+        at: Instant::now(),
+    };
+    session
+        .pending
+        .lock()
+        .expect("lock poisoned")
+        .insert(id, pending);
+    let sending = msg
+        .tagged(id)
+        .send_with_progress_with(
+            session.codec(),
+            writer,
+            progress_bar(i18n::text(Text::Sending)),
+        )
+        .await;
+    if sending.is_err() {
+        session.pending.lock().expect("lock poisoned").remove(&id);
+        if let Some(failed) = session.mark(id, FAILED) {
+            println!("{failed}");
+        }
+    }
+    sending.with_context(|| "sending your message to the server failed")
 }
 
 /// Makes a message from the [MsgCmd].
-async fn make_message(command: MsgCmd) -> anyhow::Result<cli::Msg> {
+///
+/// Images are [normalized][Image::normalized] when the configuration says so.
+async fn make_message(
+    command: MsgCmd,
+    config: &Config,
+    session: &Session,
+) -> anyhow::Result<cli::Msg> {
+    let load_image = |path: String| async move {
+        let image = Image::from_path_with_limits(path, &config.image_limits).await?;
+        match config.strip_exif {
+            true => tokio::task::spawn_blocking(|| image.normalized()).await?,
+            false => Ok(image),
+        }
+        .context("Normalizing the image failed")
+    };
     let msg = match command {
         MsgCmd::File(path) => cli::Msg::ToAll(File::from_path(path).await?.into()),
-        MsgCmd::Image(path) => cli::Msg::ToAll(Image::from_path(path).await?.into()),
+        // Only the first frame would be checked (and converted), animations are sent as they are.
+        MsgCmd::Image(path) => match Media::from_path(&path).await {
+            Ok(media) if media.is_animated() => cli::Msg::ToAll(media.into()),
+            _ => cli::Msg::ToAll(load_image(path).await?.into()),
+        },
         MsgCmd::LogIn(username, password) => cli::Msg::Auth(cli::Auth::LogIn(cli::Credentials {
             user: username.to_string().into(),
             password: password.to_string(),
         })),
-        MsgCmd::SignUp(username, password) => cli::Msg::Auth(cli::Auth::SignUp(cli::Credentials {
-            user: username.to_string().into(),
-            password: password.to_string(),
-        })),
+        MsgCmd::SignUp(username, password, invite) => {
+            let credentials = cli::Credentials {
+                user: username.to_string().into(),
+                password: password.to_string(),
+            };
+            match invite {
+                Some(invite) => cli::Msg::Auth(cli::Auth::SignUpInvited {
+                    credentials,
+                    invite,
+                }),
+                None => cli::Msg::Auth(cli::Auth::SignUp(credentials)),
+            }
+        }
+        MsgCmd::Guest(name) => cli::Msg::Auth(cli::Auth::Guest(name.into())),
+        MsgCmd::Token(Some(token)) => cli::Msg::Auth(cli::Auth::OidcToken(token)),
+        MsgCmd::Token(None) => {
+            let provider = config.identity_provider.as_ref().ok_or_else(|| {
+                anyhow!("No identity provider is configured, give the token: .token <TOKEN>")
+            })?;
+            let token = provider
+                .token(|authorization| {
+                    let uri = authorization
+                        .verification_uri_complete
+                        .as_ref()
+                        .unwrap_or(&authorization.verification_uri);
+                    let code = &authorization.user_code;
+                    println!(
+                        "{}",
+                        render::info(t!(Text::ApproveLogIn, uri = uri, code = code))
+                    );
+                })
+                .await?;
+            cli::Msg::Auth(cli::Auth::OidcToken(token))
+        }
+        MsgCmd::RequestReset(email) => cli::Msg::Auth(cli::Auth::RequestReset(email)),
+        MsgCmd::ResetPassword(code, new_password) => {
+            cli::Msg::Auth(cli::Auth::ResetPassword { code, new_password })
+        }
+        MsgCmd::Totp(code) => cli::Msg::Auth(cli::Auth::TotpCode(code)),
+        MsgCmd::TwoFactor(cmd) => cli::Msg::TwoFactor(cmd),
+        MsgCmd::Transform(name, text) => cli::Msg::ToAll(Data::Text(
+            text_tool::apply(&name, &text).map_err(|e| anyhow!(e))?,
+        )),
+        MsgCmd::SetMotd(motd) => cli::Msg::Admin(cli::Admin::SetMotd(motd)),
+        MsgCmd::SetQuota(user, bytes) => cli::Msg::Admin(cli::Admin::SetQuota {
+            user: user.into(),
+            bytes,
+        }),
+        MsgCmd::Invite(uses, days) => cli::Msg::Admin(cli::Admin::CreateInvite {
+            uses,
+            valid_for: days.map(|days| Duration::from_secs(u64::from(days) * 24 * 60 * 60)),
+        }),
+        MsgCmd::ReadStatus(Some(msg_id)) => cli::Msg::ReadStatus { msg_id },
+        MsgCmd::ReadStatus(None) => cli::Msg::ReadStatus {
+            msg_id: session
+                .last_stored
+                .lock()
+                .expect("lock poisoned")
+                .context("You have not sent any message yet")?,
+        },
+        MsgCmd::Nick(name) => cli::Msg::SetProfile(cli::ProfileChange::DisplayName(name)),
+        MsgCmd::Status(status) => cli::Msg::SetProfile(cli::ProfileChange::Status(status)),
+        MsgCmd::Email(email) => cli::Msg::SetProfile(cli::ProfileChange::Email(email)),
+        MsgCmd::Avatar(path) => {
+            cli::Msg::SetProfile(cli::ProfileChange::Avatar(load_image(path).await?))
+        }
+        MsgCmd::Profile(user) => cli::Msg::GetProfile(user.into()),
+        MsgCmd::Stats => cli::Msg::Stats,
+        MsgCmd::Presence(presence, message) => cli::Msg::SetPresence { presence, message },
+        MsgCmd::Urgent(text) => cli::Msg::Urgent(text),
         MsgCmd::NoCmd(text) => cli::Msg::ToAll(Data::Text(text)),
     };
     Ok(msg)
@@ -342,6 +2137,1068 @@ mod tests {
         assert!("    .image   foo   bar".parse::<Command>().is_err());
     }
 
+    #[test]
+    fn parse_cmd_transform() {
+        assert_eq!(
+            ".transform wrap=10  Hello  World!"
+                .parse::<Command>()
+                .unwrap(),
+            Command::Msg(MsgCmd::Transform(
+                "wrap=10".to_string(),
+                "Hello  World!".to_string()
+            ))
+        );
+        assert!(".transform slugify".parse::<Command>().is_err());
+        assert!(".transform unknown text".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn parse_cmd_motd() {
+        assert_eq!(
+            ".motd   Maintenance at noon. ".parse::<Command>().unwrap(),
+            Command::Msg(MsgCmd::SetMotd("Maintenance at noon.".to_string()))
+        );
+        assert!(".motd   ".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn parse_cmd_quota() {
+        assert_eq!(
+            ".quota alice 1048576".parse::<Command>().unwrap(),
+            Command::Msg(MsgCmd::SetQuota("alice".to_string(), Some(1048576)))
+        );
+        assert_eq!(
+            ".quota alice default".parse::<Command>().unwrap(),
+            Command::Msg(MsgCmd::SetQuota("alice".to_string(), None))
+        );
+        assert!(".quota alice".parse::<Command>().is_err());
+        assert!(".quota alice lots".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn parse_cmd_invite() {
+        assert_eq!(
+            ".invite create".parse::<Command>().unwrap(),
+            Command::Msg(MsgCmd::Invite(1, None))
+        );
+        assert_eq!(
+            ".invite create 5 7".parse::<Command>().unwrap(),
+            Command::Msg(MsgCmd::Invite(5, Some(7)))
+        );
+        assert!(".invite".parse::<Command>().is_err());
+        assert!(".invite create many".parse::<Command>().is_err());
+        assert!(".invite create 1 2 3".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn display_cmd_hides_password() {
+        assert_eq!(
+            MsgCmd::LogIn("Alice".to_string(), "secret".to_string()).to_string(),
+            ".login Alice ***"
+        );
+        assert_eq!(
+            MsgCmd::SignUp(
+                "Alice".to_string(),
+                "secret".to_string(),
+                Some("c0de".to_string())
+            )
+            .to_string(),
+            ".signup Alice *** ***"
+        );
+        assert_eq!(
+            ".token eyJhbGciOi.e30.c2ln".parse::<Command>().unwrap(),
+            Command::Msg(MsgCmd::Token(Some("eyJhbGciOi.e30.c2ln".to_string())))
+        );
+        assert_eq!(
+            MsgCmd::Token(Some("eyJhbGciOi.e30.c2ln".to_string())).to_string(),
+            ".token ***"
+        );
+        assert_eq!(
+            ".token".parse::<Command>().unwrap(),
+            Command::Msg(MsgCmd::Token(None))
+        );
+        assert_eq!(MsgCmd::Token(None).to_string(), ".token");
+        assert_eq!(
+            MsgCmd::File("foo.bin".to_string()).to_string(),
+            ".file foo.bin"
+        );
+        assert_eq!(MsgCmd::NoCmd("Hi!".to_string()).to_string(), "Hi!");
+    }
+
+    #[test]
+    fn parse_cmd_read() {
+        assert_eq!(
+            ".read".parse::<Command>().unwrap(),
+            Command::Msg(MsgCmd::ReadStatus(None))
+        );
+        assert_eq!(
+            ".read 42".parse::<Command>().unwrap(),
+            Command::Msg(MsgCmd::ReadStatus(Some(42)))
+        );
+        assert!(".read last".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn parse_cmd_profile() {
+        assert_eq!(
+            ".nick  Alice Liddell ".parse::<Command>().unwrap(),
+            Command::Msg(MsgCmd::Nick("Alice Liddell".to_string()))
+        );
+        assert_eq!(
+            ".status out for lunch".parse::<Command>().unwrap(),
+            Command::Msg(MsgCmd::Status("out for lunch".to_string()))
+        );
+        assert_eq!(
+            ".email alice@example.com".parse::<Command>().unwrap(),
+            Command::Msg(MsgCmd::Email("alice@example.com".to_string()))
+        );
+        assert_eq!(
+            ".email".parse::<Command>().unwrap(),
+            Command::Msg(MsgCmd::Email(String::new()))
+        );
+        assert_eq!(
+            ".reset 0123abcd new_pass".parse::<Command>().unwrap(),
+            Command::Msg(MsgCmd::ResetPassword(
+                "0123abcd".to_string(),
+                "new_pass".to_string()
+            ))
+        );
+        assert!(".reset 0123abcd".parse::<Command>().is_err());
+        assert_eq!(
+            ".avatar me.png".parse::<Command>().unwrap(),
+            Command::Msg(MsgCmd::Avatar("me.png".to_string()))
+        );
+        assert_eq!(
+            ".profile alice".parse::<Command>().unwrap(),
+            Command::Msg(MsgCmd::Profile("alice".to_string()))
+        );
+        assert!(".nick".parse::<Command>().is_err());
+        assert!(".avatar a.png b.png".parse::<Command>().is_err());
+        assert!(".profile".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn parse_two_factor() {
+        assert_eq!(
+            ".2fa enable".parse::<Command>().unwrap(),
+            Command::Msg(MsgCmd::TwoFactor(cli::TwoFactor::Enable))
+        );
+        assert_eq!(
+            ".2fa confirm 123456".parse::<Command>().unwrap(),
+            Command::Msg(MsgCmd::TwoFactor(cli::TwoFactor::Confirm(
+                "123456".to_string()
+            )))
+        );
+        assert_eq!(
+            ".totp 123456".parse::<Command>().unwrap(),
+            Command::Msg(MsgCmd::Totp("123456".to_string()))
+        );
+        assert_eq!(
+            MsgCmd::TwoFactor(cli::TwoFactor::Disable("123456".to_string())).to_string(),
+            ".2fa disable ***"
+        );
+        assert!(".2fa disable".parse::<Command>().is_err());
+        assert!(".2fa".parse::<Command>().is_err());
+        assert!(".totp".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn qr_code_image() {
+        let qr = qr_code("otpauth://totp/Chat:alice?secret=JBSWY3DPEHPK3PXP").unwrap();
+        assert_eq!(qr.format(), cli_ser::ImageFormat::Pnm);
+    }
+
+    #[test]
+    fn parse_presence() {
+        let away = ".away  out for lunch ".parse::<Command>().unwrap();
+        let message = Some("out for lunch".to_string());
+        assert_eq!(
+            away,
+            Command::Msg(MsgCmd::Presence(Presence::Away, message.clone()))
+        );
+        assert_eq!(
+            MsgCmd::Presence(Presence::Away, message.clone()).to_string(),
+            ".away out for lunch"
+        );
+        assert_eq!(
+            ".dnd".parse::<Command>().unwrap(),
+            Command::Msg(MsgCmd::Presence(Presence::DoNotDisturb, None))
+        );
+        assert_eq!(
+            ".back".parse::<Command>().unwrap(),
+            Command::Msg(MsgCmd::Presence(Presence::Online, None))
+        );
+        assert!(".back again".parse::<Command>().is_err());
+
+        let alice = cli_ser::User::from("alice".to_string());
+        assert_eq!(
+            describe_presence(&alice, Presence::Away, message),
+            "alice is away: out for lunch"
+        );
+        assert_eq!(
+            describe_presence(&alice, Presence::Online, None),
+            "alice is back"
+        );
+    }
+
+    #[test]
+    fn parse_urgent() {
+        let urgent = ".urgent  the build is broken".parse::<Command>().unwrap();
+        let cmd = MsgCmd::Urgent("the build is broken".to_string());
+        assert_eq!(urgent, Command::Msg(cmd));
+        assert_eq!(
+            MsgCmd::Urgent("the build is broken".to_string()).to_string(),
+            ".urgent the build is broken"
+        );
+        assert!(".urgent ".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn parse_filters() {
+        assert_eq!(
+            ".mute spammer".parse::<Command>().unwrap(),
+            Command::Mute("spammer".to_string())
+        );
+        assert!(".mute".parse::<Command>().is_err());
+        assert_eq!(
+            ".filter add (?i)buy now".parse::<Command>().unwrap(),
+            Command::AddFilter("(?i)buy now".to_string())
+        );
+        assert!(".filter add".parse::<Command>().is_err());
+        assert!(".filter add (unclosed".parse::<Command>().is_err());
+        assert!(".filter remove x".parse::<Command>().is_err());
+        assert_eq!(".filters".parse::<Command>().unwrap(), Command::Filters);
+
+        assert_eq!(
+            describe_filters(&Filters::default()),
+            "No messages are hidden"
+        );
+        let filters = Filters {
+            muted: ["bob".to_string(), "alice".to_string()].into(),
+            patterns: vec!["^!bot".to_string()],
+        };
+        assert_eq!(
+            describe_filters(&filters),
+            "Muted users: alice, bob\nHidden patterns: `^!bot`"
+        );
+    }
+
+    #[test]
+    fn parse_prefs() {
+        assert_eq!(".prefs".parse::<Command>().unwrap(), Command::Prefs(None));
+        assert_eq!(
+            ".prefs mentions-only on".parse::<Command>().unwrap(),
+            Command::Prefs(Some(PrefsChange::MentionsOnly(true)))
+        );
+        assert_eq!(
+            ".prefs history 50".parse::<Command>().unwrap(),
+            Command::Prefs(Some(PrefsChange::HistoryLength(50)))
+        );
+        assert!(".prefs mentions-only maybe".parse::<Command>().is_err());
+        assert!(".prefs history -1".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn stored_prefs_apply() {
+        let session = Session::default();
+        assert_eq!(session.history_length(), HISTORY_DEFAULT);
+        assert!(session.synced_prefs().is_none());
+        session.filters.lock().unwrap().mute("spammer");
+        *session.prefs.lock().unwrap() = Some(Preferences {
+            mentions_only: true,
+            history_length: Some(50),
+            ..Default::default()
+        });
+        assert_eq!(session.history_length(), 50);
+        assert!(
+            session
+                .notifications(&Notifications::default())
+                .mentions_only
+        );
+        let synced = session.synced_prefs().unwrap();
+        assert_eq!(synced.muted, [cli_ser::User::from("spammer".to_string())]);
+        assert_eq!(
+            describe_prefs(&synced, session.history_length()),
+            "Only messages mentioning you are notified about\n.history prints 50 messages"
+        );
+    }
+
+    #[test]
+    fn parse_stats() {
+        assert_eq!(
+            ".stats".parse::<Command>().unwrap(),
+            Command::Msg(MsgCmd::Stats)
+        );
+        assert!(".stats now".parse::<Command>().is_err());
+        assert_eq!(format_uptime(Duration::from_secs(45)), "45s");
+        assert_eq!(format_uptime(Duration::from_secs(125)), "2m 5s");
+        assert_eq!(
+            format_uptime(Duration::from_secs(3 * 3600 + 20 * 60)),
+            "3h 20m"
+        );
+        assert_eq!(
+            format_uptime(Duration::from_secs(2 * 86_400 + 5 * 3600 + 7)),
+            "2d 5h"
+        );
+    }
+
+    #[test]
+    fn parse_history() {
+        assert_eq!(
+            ".history".parse::<Command>().unwrap(),
+            Command::History(None)
+        );
+        assert_eq!(
+            ".history 5".parse::<Command>().unwrap(),
+            Command::History(Some(5))
+        );
+        assert!(".history five".parse::<Command>().is_err());
+        assert!(".history 5 6".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn parse_switch() {
+        assert_eq!(
+            ".switch work".parse::<Command>().unwrap(),
+            Command::Switch("work".to_string())
+        );
+        assert!(".switch".parse::<Command>().is_err());
+        assert!(".switch work home".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn echo_marked() {
+        let session = Session::default();
+        let text = Data::Text("hi".to_string());
+        assert_eq!(session.echo(MsgId(1), &text), format!("you: hi {PENDING}"));
+        assert_eq!(
+            session.mark(MsgId(1), CONFIRMED),
+            Some(format!("{LINE_UP}you: hi {CONFIRMED}"))
+        );
+        assert_eq!(session.mark(MsgId(1), CONFIRMED), None);
+
+        *session.user.lock().unwrap() = Some("alice".to_string());
+        session.echo(MsgId(2), &text);
+        session.printed();
+        assert_eq!(
+            session.mark(MsgId(2), FAILED),
+            Some(format!("alice: hi {FAILED}"))
+        );
+    }
+
+    #[test]
+    fn unanswered_given_up() {
+        let session = Session::default();
+        let sent = Instant::now();
+        let text = Data::Text("hi".to_string());
+        session.echo(MsgId(1), &text);
+        for (id, at) in [(1, sent), (2, sent + Duration::from_secs(10))] {
+            let pending = Pending {
+                sent: format!("msg {id}"),
+                at,
+            };
+            session.pending.lock().unwrap().insert(MsgId(id), pending);
+        }
+
+        assert!(session.expire(sent + Duration::from_secs(1)).is_empty());
+        let unanswered = t!(Text::MsgUnanswered, id = MsgId(1), sent = " (msg 1)");
+        assert_eq!(
+            session.expire(sent + ANSWER_TIMEOUT),
+            [
+                format!("{LINE_UP}you: hi {FAILED}"),
+                render::error(unanswered)
+            ]
+        );
+        assert_eq!(session.pending.lock().unwrap().len(), 1);
+        assert!(session.echoes.lock().unwrap().is_empty());
+
+        // The closed connection gives up the rest.
+        let unanswered = t!(Text::MsgUnanswered, id = MsgId(2), sent = " (msg 2)");
+        assert_eq!(session.abandon(), [render::error(unanswered)]);
+        assert!(session.pending.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_send_not_pending() {
+        let session = Session::default();
+        let (mut writer, reader) = tokio::io::duplex(64);
+        drop(reader);
+        let msg = cli::Msg::ToAll(Data::Text("hi".to_string()));
+        let sent = send_tagged(msg, MsgId(1), "hi".to_string(), &session, &mut writer).await;
+        assert!(sent.is_err());
+        assert!(session.pending.lock().unwrap().is_empty());
+        assert!(session.echoes.lock().unwrap().is_empty());
+    }
+
+    /// Lines of a user who stops typing after the last one, reading blocks then.
+    struct Script(std::collections::VecDeque<&'static str>);
+    impl editor::Lines for Script {
+        fn read(&mut self, _prompt: &str) -> anyhow::Result<Option<String>> {
+            match self.0.pop_front() {
+                Some(line) => Ok(Some(line.to_string())),
+                None => loop {
+                    std::thread::park();
+                },
+            }
+        }
+    }
+
+    fn script(lines: &[&'static str]) -> Input {
+        let lines = Script(lines.iter().copied().collect());
+        Input::spawn(move || Ok(lines), Commands::default(), AutoAttach::Off)
+    }
+
+    #[tokio::test]
+    async fn input_quit() {
+        let mut input = script(&[".help", ".quit", "never read"]);
+        assert_eq!(input.parsed.recv().await.unwrap().unwrap(), Command::Help);
+        assert!(input.parsed.recv().await.is_none());
+        assert!(input.stop().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn input_stops_promptly() {
+        let mut input = script(&["hello"]);
+        assert_eq!(
+            input.parsed.recv().await.unwrap().unwrap(),
+            Command::Msg(MsgCmd::NoCmd("hello".to_string()))
+        );
+        // The parser waits for a line which never comes.
+        let stopped = tokio::time::timeout(Duration::from_secs(1), input.stop()).await;
+        assert!(matches!(stopped, Ok(Ok(()))));
+    }
+
+    #[test]
+    fn parse_profiles() {
+        let profiles: Profiles = toml::from_str(
+            r#"
+            [work]
+            addr = "10.0.0.1:11111"
+            user = "alice"
+            password = "secret"
+
+            [hobby]
+            addr = "127.0.0.1:22222"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            profiles["work"].credentials(),
+            Some(cli::Credentials {
+                user: "alice".to_string().into(),
+                password: "secret".to_string()
+            })
+        );
+        assert_eq!(profiles["hobby"].credentials(), None);
+        assert_eq!(profiles["hobby"].addr, ServerAddr::new("127.0.0.1", 22222));
+    }
+
+    #[test]
+    fn parse_config_file() {
+        let config: ConfigFile = toml::from_str(
+            r#"
+            port = 22222
+            convert_images = "png"
+
+            [profiles.work]
+            addr = "10.0.0.1:11111"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.port, Some(22222));
+        assert_eq!(config.host, None);
+        assert_eq!(config.convert_images.as_deref(), Some("png"));
+        assert_eq!(
+            config.profiles["work"].addr,
+            ServerAddr::new("10.0.0.1", 11111)
+        );
+        assert!(toml::from_str::<ConfigFile>("hots = \"10.0.0.1\"").is_err());
+    }
+
+    #[test]
+    fn resolve_profile() {
+        let profiles: Profiles = toml::from_str(
+            r#"
+            [work]
+            addr = "10.0.0.1:11111"
+            file_dir = "work/files"
+            theme = "mono"
+
+            [work.notify]
+            mentions_only = true
+            bell = false
+            "#,
+        )
+        .unwrap();
+        let config = Config {
+            file_dir: PathBuf::from("files"),
+            img_dir: PathBuf::from("images"),
+            on_collision: OnCollision::Rename,
+            image_clock: Clock::Utc,
+            layout: StorageLayout::Flat,
+            addr: ServerAddr::new("127.0.0.1", PORT_DEFAULT),
+            prefer_ip: IpPreference::default(),
+            credentials: None,
+            profiles,
+            profile: None,
+            convert_images: None,
+            notify: Notifications {
+                bell: true,
+                cmd: Some("notify-send \"$1\"".to_string()),
+                ..Notifications::default()
+            },
+            theme: Theme::Light,
+            history_file: PathBuf::from(history::FILE_DEFAULT),
+            strip_exif: false,
+            image_limits: ImageLimits::default(),
+            auto_attach: AutoAttach::Off,
+            filters: Filters::default(),
+            config_file: None,
+            identity_provider: None,
+        };
+        let resolved = config.resolved().unwrap();
+        assert_eq!(resolved.file_dir, config.file_dir);
+        assert_eq!(resolved.theme, Theme::Light);
+        assert_eq!(resolved.addr, config.addr);
+
+        let work = Config {
+            profile: Some("work".to_string()),
+            ..config.clone()
+        };
+        let resolved = work.resolved().unwrap();
+        assert_eq!(resolved.file_dir, PathBuf::from("work/files"));
+        assert_eq!(resolved.img_dir, config.img_dir);
+        assert_eq!(resolved.addr, ServerAddr::new("10.0.0.1", 11111));
+        assert_eq!(resolved.theme, Theme::Mono);
+        // Rules the profile sets replace those of the configuration, the others are kept.
+        assert_eq!(
+            resolved.notify,
+            Notifications {
+                bell: false,
+                mentions_only: true,
+                ..config.notify.clone()
+            }
+        );
+
+        let unknown = Config {
+            profile: Some("hobby".to_string()),
+            ..config
+        };
+        assert!(unknown.resolved().is_err());
+    }
+
+    #[tokio::test]
+    async fn save_file() {
+        let dir = std::env::temp_dir().join(format!("save_file_{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join("notes.txt");
+        std::fs::write(&path, "first").unwrap();
+        let file = File::from_path(&path).await.unwrap();
+
+        let renamed = file.save(&dir, OnCollision::Rename).await.unwrap();
+        assert_eq!(renamed, dir.join("notes (1).txt"));
+        let renamed = file.save(&dir, OnCollision::Rename).await.unwrap();
+        assert_eq!(renamed, dir.join("notes (2).txt"));
+        let overwritten = file.save(&dir, OnCollision::Overwrite).await.unwrap();
+        assert_eq!(overwritten, path);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn storage_layouts() {
+        let base = Path::new("files");
+        assert_eq!(StorageLayout::Flat.dir(base, "alice"), base);
+        assert_eq!(StorageLayout::Sender.dir(base, "alice"), base.join("alice"));
+        assert_eq!(StorageLayout::Sender.dir(base, "../bob"), base.join("bob"));
+        let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+        assert_eq!(StorageLayout::Date.dir(base, "alice"), base.join(today));
+        assert_eq!("sender".parse(), Ok(StorageLayout::Sender));
+        assert!("nested".parse::<StorageLayout>().is_err());
+
+        let dir = std::env::temp_dir().join(format!("storage_layouts_{}", std::process::id()));
+        let alice = StorageLayout::Sender
+            .create_dir(&dir, "alice")
+            .await
+            .unwrap();
+        assert_eq!(alice, dir.join("alice"));
+        assert!(alice.is_dir());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn save_images_without_overwriting() {
+        let dir = std::env::temp_dir().join(format!("save_images_{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let qr = qr_code("otpauth://totp/Chat:alice?secret=JBSWY3DPEHPK3PXP").unwrap();
+
+        let first = qr.save(&dir).await.unwrap();
+        let second = qr.save_with(&dir, Clock::Utc).await.unwrap();
+        let local = qr.save_with(&dir, Clock::Local).await.unwrap();
+        assert_ne!(first, second);
+        assert_ne!(second, local);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 3);
+        assert_eq!("local".parse(), Ok(Clock::Local));
+        assert!("martian".parse::<Clock>().is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Width and height of a BMP.
+    fn bmp_size(bmp: &[u8]) -> (i32, i32) {
+        let at = |i: usize| i32::from_le_bytes(bmp[i..i + 4].try_into().unwrap());
+        (at(18), at(22).abs())
+    }
+
+    /// GIF of 1x1 pixels with the `frames`, each shown for a quarter of a second.
+    fn gif(frames: usize) -> Vec<u8> {
+        let mut gif = b"GIF89a\x01\x00\x01\x00\x00\x00\x00".to_vec();
+        for _ in 0..frames {
+            // Graphic control extension with the delay, image descriptor and the image data.
+            gif.extend(b"\x21\xF9\x04\x00\x19\x00\x00\x00");
+            gif.extend(b"\x2C\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02\x44\x01\x00");
+        }
+        gif.push(0x3B);
+        gif
+    }
+
+    #[test]
+    fn animated_media() {
+        let animated = Media::from_bytes("cat.gif", gif(2)).unwrap();
+        assert!(animated.is_animated());
+        assert_eq!(animated.mime(), "image/gif");
+        assert_eq!(
+            animated.duration(),
+            Some(std::time::Duration::from_millis(500))
+        );
+        assert_eq!(describe(&animated), r#""cat.gif" (image/gif, 0.5 s)"#);
+        assert_eq!(
+            summary(&Data::Media(animated)),
+            r#"sent "cat.gif" (image/gif, 0.5 s)"#
+        );
+
+        assert!(!Media::from_bytes("still.gif", gif(1))
+            .unwrap()
+            .is_animated());
+        assert!(Media::from_bytes("notes.txt", b"hello".to_vec()).is_err());
+    }
+
+    #[test]
+    fn strip_exif() {
+        // 2x1 BMP with 24 bits per pixel, the row is padded to 8 bytes.
+        let mut bmp = b"BM".to_vec();
+        for n in [62u32, 0, 54, 40, 2, 1] {
+            bmp.extend(n.to_le_bytes());
+        }
+        bmp.extend(1u16.to_le_bytes());
+        bmp.extend(24u16.to_le_bytes());
+        for n in [0u32, 8, 2835, 2835, 0, 0] {
+            bmp.extend(n.to_le_bytes());
+        }
+        bmp.extend([0, 0, 255, 255, 255, 255, 0, 0]);
+        let jpeg: Vec<u8> = Image::try_from(bmp)
+            .unwrap()
+            .convert(ImageOutputFormat::Jpeg(90))
+            .unwrap()
+            .into();
+        // APP1 segment with the EXIF orientation 6 (rotated by 90 degrees) right after the start of the image.
+        let mut exif =
+            b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0\x06\0\0\0\0\0\0".to_vec();
+        let mut app1 = vec![0xFF, 0xE1];
+        app1.extend((exif.len() as u16 + 2).to_be_bytes());
+        app1.append(&mut exif);
+        let tagged: Vec<u8> = [&jpeg[..2], &app1[..], &jpeg[2..]].concat();
+
+        let normalized = Image::try_from(tagged).unwrap().normalized().unwrap();
+        assert_eq!(normalized.format(), cli_ser::ImageFormat::Jpeg);
+        assert_eq!(normalized.dimensions().unwrap(), (1, 2));
+        let bytes: Vec<u8> = normalized.clone().into();
+        assert!(!bytes.windows(4).any(|w| w == b"Exif"));
+        let bmp: Vec<u8> = normalized
+            .convert(cli_ser::ImageFormat::Bmp)
+            .unwrap()
+            .into();
+        assert_eq!(bmp_size(&bmp), (1, 2));
+    }
+
+    /// Configuration keeping everything in the `dir`.
+    fn config_in(dir: &Path) -> Config {
+        Config {
+            file_dir: dir.join("files"),
+            img_dir: dir.join("images"),
+            on_collision: OnCollision::Rename,
+            image_clock: Clock::Utc,
+            layout: StorageLayout::Flat,
+            addr: ServerAddr::new("127.0.0.1", PORT_DEFAULT),
+            prefer_ip: IpPreference::default(),
+            credentials: None,
+            profiles: Profiles::new(),
+            profile: None,
+            convert_images: None,
+            notify: Notifications::default(),
+            theme: Theme::default(),
+            history_file: dir.join("{user}.jsonl"),
+            strip_exif: false,
+            image_limits: ImageLimits::default(),
+            auto_attach: AutoAttach::Off,
+            filters: Filters::default(),
+            config_file: None,
+            identity_provider: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn transformed_message() {
+        let config = config_in(&std::env::temp_dir());
+        let session = Session::default();
+        let cmd = MsgCmd::Transform("wrap=5".to_string(), "Hello World!".to_string());
+        assert_eq!(
+            make_message(cmd, &config, &session).await.unwrap(),
+            cli::Msg::ToAll(Data::Text("Hello\nWorld!".to_string()))
+        );
+        // Parsed fine, fails on the text.
+        let cmd = MsgCmd::Transform("json-pretty".to_string(), "{".to_string());
+        assert!(make_message(cmd, &config, &session).await.is_err());
+    }
+
+    /// Bar factory for [progress_with] keeping the bars it made in `bars`, they are hidden from the terminal.
+    fn hidden_bars(bars: Arc<Mutex<Vec<ProgressBar>>>) -> impl FnMut(u64) -> ProgressBar + Send {
+        move |total| {
+            let bar = ProgressBar::hidden();
+            bar.set_length(total);
+            bars.lock().unwrap().push(bar.clone());
+            bar
+        }
+    }
+
+    #[test]
+    fn progress_bar_for_big_transfers() {
+        let bars = Arc::new(Mutex::new(Vec::new()));
+        let mut small = progress_with("Sending", hidden_bars(bars.clone()));
+        small(0, PROGRESS_OVER);
+        small(PROGRESS_OVER, PROGRESS_OVER);
+        assert!(bars.lock().unwrap().is_empty(), "no bar up to a megabyte");
+
+        let total = 3 * PROGRESS_OVER;
+        let mut big = progress_with("Receiving", hidden_bars(bars.clone()));
+        big(PROGRESS_OVER, total);
+        {
+            let bars = bars.lock().unwrap();
+            assert_eq!(bars.len(), 1);
+            assert_eq!(bars[0].message(), "Receiving");
+            assert_eq!(
+                (bars[0].position(), bars[0].length()),
+                (PROGRESS_OVER, Some(total))
+            );
+            assert!(!bars[0].is_finished());
+        }
+        big(2 * PROGRESS_OVER, total);
+        big(total, total);
+        let bars = bars.lock().unwrap();
+        assert_eq!(bars.len(), 1, "one bar for the whole transfer");
+        assert_eq!(bars[0].position(), total);
+        assert!(bars[0].is_finished());
+    }
+
+    /// Uploading and downloading a big file each shows a bar finished with the transfer.
+    #[tokio::test]
+    async fn progress_bars_follow_transfers() {
+        let bytes: Vec<u8> = (0..2 * PROGRESS_OVER).map(|i| i as u8).collect();
+        let msg = ser::Msg::DataFrom {
+            data: Data::File(File::new("big.bin", bytes)),
+            from: "alice".to_string().into(),
+            msg_id: None,
+            display_name: None,
+            mentions: vec![],
+            guest: false,
+            urgent: false,
+        };
+        let (uploads, downloads) = (Arc::default(), Arc::default());
+        let (mut writer, mut reader) = tokio::io::duplex(64 * 1024);
+        let sent = {
+            let progress = progress_with("Sending", hidden_bars(Arc::clone(&uploads)));
+            tokio::spawn(async move { msg.send_with_progress(&mut writer, progress).await })
+        };
+        let dir = std::env::temp_dir().join(format!("progress_bars_{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let progress = progress_with("Receiving", hidden_bars(Arc::clone(&downloads)));
+        let (_, received) = ser::Msg::receive_saving_files(&mut reader, &dir, progress)
+            .await
+            .unwrap();
+        sent.await.unwrap().unwrap();
+        let received = received.unwrap().unwrap();
+        let size = std::fs::metadata(received.path()).unwrap().len();
+        assert_eq!(size, 2 * PROGRESS_OVER);
+
+        for bars in [uploads, downloads] {
+            let bars = bars.lock().unwrap();
+            assert_eq!(bars.len(), 1);
+            assert_eq!(bars[0].position(), bars[0].length().unwrap());
+            assert!(
+                bars[0].position() > 2 * PROGRESS_OVER,
+                "the whole frame is counted"
+            );
+            assert!(bars[0].is_finished());
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Sends the `data` from the `sender` and receives it saving files to the `dir`.
+    async fn streamed(dir: &Path, sender: &str, data: Data) -> (ser::Msg, Option<ReceivedFile>) {
+        let msg = ser::Msg::DataFrom {
+            data,
+            from: sender.to_string().into(),
+            msg_id: None,
+            display_name: None,
+            mentions: vec![],
+            guest: false,
+            urgent: false,
+        };
+        let (mut writer, mut reader) = tokio::io::duplex(1024);
+        let sent = tokio::spawn(async move { msg.send(&mut writer).await });
+        let (msg, received) = ser::Msg::receive_saving_files(&mut reader, dir, |_, _| ())
+            .await
+            .unwrap();
+        sent.await.unwrap().unwrap();
+        (msg, received.map(Result::unwrap))
+    }
+
+    /// A duplicate of the file saved under the same name must not replace it and then remove it.
+    #[tokio::test]
+    async fn save_duplicate_overwriting() {
+        let dir = std::env::temp_dir().join(format!("duplicate_{}", std::process::id()));
+        let config = Config {
+            on_collision: OnCollision::Overwrite,
+            ..config_in(&dir)
+        };
+        tokio::fs::create_dir_all(&config.file_dir).await.unwrap();
+        let notes = File::new("notes.txt", b"the only copy".to_vec());
+        let saved = save_file(&config, "alice", &notes, None).await.unwrap();
+        let path = config.file_dir.join("notes.txt");
+        assert_eq!(saved, Saved::New(path.clone()));
+
+        let (_, received) = streamed(&config.file_dir, "alice", Data::File(notes.clone())).await;
+        let saved = save_file(&config, "alice", &notes, received.map(Ok)).await;
+        assert_eq!(saved.unwrap(), Saved::Duplicate(path.clone()));
+        assert_eq!(std::fs::read(&path).unwrap(), b"the only copy");
+        let names: Vec<_> = std::fs::read_dir(&config.file_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names.len(), 2, "only the file and the index: {names:?}");
+
+        // New content of the name replaces it as the policy says.
+        let edited = File::new("notes.txt", b"edited".to_vec());
+        let (_, received) = streamed(&config.file_dir, "alice", Data::File(edited.clone())).await;
+        let saved = save_file(&config, "alice", &edited, received.map(Ok)).await;
+        assert_eq!(saved.unwrap(), Saved::New(path.clone()));
+        assert_eq!(std::fs::read(&path).unwrap(), b"edited");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Files of muted users are discarded, even when they have the name of a saved file.
+    #[tokio::test]
+    async fn hidden_files_discarded() {
+        let dir = std::env::temp_dir().join(format!("hidden_files_{}", std::process::id()));
+        let config = Config {
+            on_collision: OnCollision::Overwrite,
+            ..config_in(&dir)
+        };
+        tokio::fs::create_dir_all(&config.file_dir).await.unwrap();
+        let path = config.file_dir.join("notes.txt");
+        std::fs::write(&path, "mine").unwrap();
+        let session = Session::default();
+        session.filters.lock().unwrap().mute("spammer");
+
+        let junk = Data::File(File::new("notes.txt", b"junk".to_vec()));
+        let (msg, received) = streamed(&config.file_dir, "spammer", junk).await;
+        assert!(session.hides(&msg));
+        process_msg(&config, &session, msg, received.map(Ok)).await;
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "mine");
+        assert_eq!(std::fs::read_dir(&config.file_dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// The client offers its codecs, the one the server chooses carries everything after the authentication.
+    #[cfg(feature = "postcard")]
+    #[tokio::test]
+    async fn negotiated_codec_end_to_end() {
+        let dir = std::env::temp_dir().join(format!("negotiated_{}", std::process::id()));
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0))
+            .await
+            .unwrap();
+        let config = Config {
+            addr: ServerAddr::new("127.0.0.1", listener.local_addr().unwrap().port()),
+            credentials: Some(cli::Credentials {
+                user: "negotiator".to_string().into(),
+                password: "pass".to_string(),
+            }),
+            ..config_in(&dir)
+        };
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let cli::Msg::Codecs(offered) = cli::Msg::receive(&mut socket).await.unwrap() else {
+                panic!("the client offered no codecs");
+            };
+            assert!(offered.contains(&Codec::Postcard.id()), "{offered:?}");
+            let chosen = ser::Msg::CodecChosen(Codec::Postcard);
+            chosen.send(&mut socket).await.unwrap();
+            let log_in = cli::Msg::receive(&mut socket).await.unwrap();
+            assert!(matches!(log_in, cli::Msg::Auth(cli::Auth::LogIn(_))));
+            ser::Msg::Authenticated.send(&mut socket).await.unwrap();
+
+            let get_prefs = cli::Msg::receive_with(Codec::Postcard, &mut socket);
+            assert_eq!(get_prefs.await.unwrap(), cli::Msg::GetPrefs);
+            ser::Msg::DataFrom {
+                data: Data::File(File::new("notes.txt", b"in postcard".to_vec())),
+                from: "sender".to_string().into(),
+                msg_id: Some(5),
+                display_name: None,
+                mentions: vec![],
+                guest: false,
+                urgent: false,
+            }
+            .send_with(Codec::Postcard, &mut socket)
+            .await
+            .unwrap();
+            let mark_read = cli::Msg::receive_with(Codec::Postcard, &mut socket);
+            assert_eq!(mark_read.await.unwrap(), cli::Msg::MarkRead { msg_id: 5 });
+        });
+
+        let (_inputs, mut parsed) = mpsc::channel(1);
+        let filters = Arc::default();
+        let chatted = connect_and_chat(
+            &config,
+            &Commands::default(),
+            &mut parsed,
+            &editor::Users::default(),
+            &filters,
+        );
+        let chatted = tokio::time::timeout(Duration::from_secs(10), chatted).await;
+        assert!(
+            chatted.unwrap().is_err(),
+            "only the server hanging up ends it"
+        );
+        server.await.unwrap();
+        let saved = std::fs::read(config.file_dir.join("notes.txt")).unwrap();
+        assert_eq!(saved, b"in postcard");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn skip_malformed_messages() {
+        let dir = std::env::temp_dir().join(format!("malformed_{}", std::process::id()));
+        let config = config_in(&dir);
+        let session = Arc::new(Session::default());
+        let (receipts, mut receipt_consumer) = mpsc::channel(8);
+        let (_quit, quit_receiver) = oneshot::channel();
+
+        let (mut writer, reader) = tokio::io::duplex(1024);
+        // Frames of garbage and of nothing, each prefixed by its length.
+        writer.write_all(&10u32.to_be_bytes()).await.unwrap();
+        writer.write_all(&[0xff; 10]).await.unwrap();
+        writer.write_all(&0u32.to_be_bytes()).await.unwrap();
+        ser::Msg::DataFrom {
+            data: Data::Text("still here".to_string()),
+            from: "sender".to_string().into(),
+            msg_id: Some(3),
+            display_name: None,
+            mentions: vec![],
+            guest: false,
+            urgent: false,
+        }
+        .send(&mut writer)
+        .await
+        .unwrap();
+        drop(writer);
+
+        let received = receive_in_loop(config, reader, session.clone(), receipts, quit_receiver);
+        assert!(received.await.is_err(), "only the disconnection ends it");
+        assert_eq!(
+            receipt_consumer.recv().await,
+            Some(cli::Msg::MarkRead { msg_id: 3 })
+        );
+        assert!(session.users.lock().unwrap().contains("sender"));
+    }
+
+    #[test]
+    fn error_codes() {
+        let through_wire = |err: ser::Error| {
+            let bytes = ser::Msg::Error(err).to_bytes().unwrap();
+            match ser::Msg::from_bytes(&bytes).unwrap() {
+                ser::Msg::Error(err) => err,
+                other => panic!("{other:?}"),
+            }
+        };
+        for err in [
+            ser::Error::Malformed { len: 4 },
+            ser::Error::WrongPassword,
+            ser::Error::UnknownUser("alice".to_string().into()),
+            ser::Error::Refused(ser::Refusal::TooManyFromAddress),
+            ser::Error::NotAuthenticated(cli::Msg::ReadStatus { msg_id: 7 }),
+        ] {
+            assert_eq!(through_wire(err.clone()), err);
+        }
+        let future = ser::Error::Other {
+            code: 999,
+            detail: "slow down".to_string(),
+        };
+        assert_eq!(through_wire(future.clone()), future);
+        assert_eq!(future.code(), ErrorCode(999));
+        let known = ser::Error::Other {
+            code: ser::Error::WrongUser.code().0,
+            detail: String::new(),
+        };
+        assert_eq!(through_wire(known), ser::Error::WrongUser);
+    }
+
+    #[test]
+    fn indent_lines() {
+        assert_eq!(indented("one line"), "one line");
+        assert_eq!(indented("first\nsecond"), "first\n    second");
+    }
+
+    #[test]
+    fn sanitize_file_name() {
+        use cli_ser::sanitize_file_name;
+        assert_eq!(sanitize_file_name("notes.txt"), "notes.txt");
+        assert_eq!(sanitize_file_name("../../x"), "x");
+        assert_eq!(sanitize_file_name("..\\..\\x"), "x");
+        assert_eq!(sanitize_file_name("/etc/passwd"), "passwd");
+        assert_eq!(sanitize_file_name("a\nb\u{7}"), "ab");
+        for unsafe_name in ["", "..", ".", "dir/", " ", "..."] {
+            assert_eq!(sanitize_file_name(unsafe_name), "unknown");
+        }
+        assert_eq!(
+            sanitize_file_name("2024-01-31T18:00:00Z.png"),
+            "2024-01-31T18-00-00Z.png"
+        );
+        assert_eq!(
+            sanitize_file_name("what? <no>|\"*\".txt"),
+            "what- -no-----.txt"
+        );
+        assert_eq!(sanitize_file_name("notes. . "), "notes");
+        assert_eq!(sanitize_file_name("CON"), "_CON");
+        assert_eq!(sanitize_file_name("nul.tar.gz"), "_nul.tar.gz");
+        assert_eq!(sanitize_file_name("lpt1 .txt"), "_lpt1 .txt");
+        assert_eq!(sanitize_file_name("console.txt"), "console.txt");
+    }
+
+    /// Names invalid on Windows are saved there under their sanitized names.
+    #[cfg(windows)]
+    #[tokio::test]
+    async fn save_reserved_names_on_windows() {
+        let dir = std::env::temp_dir().join(format!("reserved_names_{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        for name in ["12:00 report?.txt", "CON.txt", "trailing. "] {
+            let file = File::new(name, b"content".to_vec());
+            let path = file.save(&dir, OnCollision::Rename).await.unwrap();
+            assert_eq!(std::fs::read(&path).unwrap(), b"content");
+        }
+        let qr = qr_code("otpauth://totp/Chat:alice?secret=JBSWY3DPEHPK3PXP").unwrap();
+        qr.save_with(&dir, Clock::Local).await.unwrap();
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 4);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
     #[test]
     fn parse_login() {
         assert!("    .login  ".parse::<Command>().is_err());
@@ -360,13 +3217,25 @@ mod tests {
     fn parse_signup() {
         assert!(".signup  ".parse::<Command>().is_err());
         assert!(".signup first  ".parse::<Command>().is_err());
-        assert!("  .signup first second third".parse::<Command>().is_err());
+        assert!("  .signup first second third fourth"
+            .parse::<Command>()
+            .is_err());
         let (name, password) = ("some-name", "3l1k54j2l6kj23");
         assert_eq!(
             format!(".signup {name} {password}")
                 .parse::<Command>()
                 .unwrap(),
-            Command::Msg(MsgCmd::SignUp(name.to_string(), password.to_string()))
+            Command::Msg(MsgCmd::SignUp(name.to_string(), password.to_string(), None))
+        );
+        assert_eq!(
+            format!(".signup {name} {password} 0123456789abcdef")
+                .parse::<Command>()
+                .unwrap(),
+            Command::Msg(MsgCmd::SignUp(
+                name.to_string(),
+                password.to_string(),
+                Some("0123456789abcdef".to_string())
+            ))
         );
     }
 
@@ -378,6 +3247,64 @@ mod tests {
         assert!("...all good".parse::<Command>().is_err());
     }
 
+    #[test]
+    fn parse_image_formats() {
+        assert_eq!(parse_image_format("png").unwrap(), ImageOutputFormat::Png);
+        assert_eq!(parse_image_format("webp").unwrap(), ImageOutputFormat::WebP);
+        assert_eq!(
+            parse_image_format("jpg:80").unwrap(),
+            ImageOutputFormat::Jpeg(80)
+        );
+        assert!(parse_image_format("jpeg:0").is_err());
+        assert!(parse_image_format("png:80").is_err());
+        assert!(parse_image_format("foo").is_err());
+    }
+
+    #[test]
+    fn detect_attachments() {
+        let image = "../example-images/rustacean-orig-noshadow.png";
+        assert_eq!(
+            attachment(&format!("  {image} ")),
+            Some(MsgCmd::Image(image.to_string()))
+        );
+        assert_eq!(
+            attachment(&format!("'{image}'")),
+            Some(MsgCmd::Image(image.to_string()))
+        );
+        assert_eq!(
+            attachment(&format!("file://{image}")),
+            Some(MsgCmd::Image(image.to_string()))
+        );
+        assert_eq!(
+            attachment("\"Cargo.toml\""),
+            Some(MsgCmd::File("Cargo.toml".to_string()))
+        );
+
+        let dir = std::env::temp_dir().join(format!("attach_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let spaced = dir.join("my notes.txt");
+        std::fs::write(&spaced, "notes").unwrap();
+        let spaced = spaced.to_string_lossy().to_string();
+        assert_eq!(
+            attachment(&spaced.replace(' ', "\\ ")),
+            Some(MsgCmd::File(spaced.clone()))
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+
+        for text in [
+            "Cargo.toml is here",
+            "missing.txt",
+            "src",
+            "",
+            "Cargo.toml\nCargo.toml",
+        ] {
+            assert_eq!(attachment(text), None, "{text:?}");
+        }
+        assert_eq!("ask".parse(), Ok(AutoAttach::Ask));
+        assert_eq!("always".parse(), Ok(AutoAttach::Always));
+        assert!("sometimes".parse::<AutoAttach>().is_err());
+    }
+
     #[test]
     fn parse_no_cmd() {
         for s in ["some text", "            ", "bye.quit"] {
@@ -1,48 +1,351 @@
 use std::{
-    fs,
-    net::{IpAddr, SocketAddr},
-    path::PathBuf,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
 };
 
-use anyhow::Context;
-use clap::Parser;
+use anyhow::{anyhow, Context};
+use clap::{builder::BoolishValueParser, Parser, Subcommand};
 
-use client::{Config, HOST_DEFAULT, PORT_DEFAULT};
+use cli_ser::{cli::Credentials, Data, File, ImageLimits, ImageOutputFormat};
+use client::{
+    AutoAttach, Clock, ColorChoice, Commands, Config, ConfigFile, IdentityProvider, IpPreference,
+    Lang, Notifications, OnCollision, ServerAddr, StorageLayout, Theme, HOST_DEFAULT, PORT_DEFAULT,
+};
+
+/// Profiles file looked for when none is given.
+const PROFILES_DEFAULT: &str = "profiles.toml";
+/// Configuration file looked for when none is given.
+const CONFIG_DEFAULT: &str = "client.toml";
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
 
-    let file_dir = PathBuf::from("files");
-    let img_dir = PathBuf::from("images");
-    fs::create_dir_all(&file_dir).with_context(|| "Directory for files couldn't be created")?;
-    fs::create_dir_all(&img_dir).with_context(|| "Directory for images couldn't be created")?;
+    let file = match (&args.config, Path::new(CONFIG_DEFAULT).exists()) {
+        (Some(path), _) => ConfigFile::load(path)?,
+        (None, true) => ConfigFile::load(CONFIG_DEFAULT)?,
+        (None, false) => ConfigFile::default(),
+    };
+    // Filters changed while chatting are saved there, the default file is created if need be.
+    let config_file = args.config.unwrap_or(PathBuf::from(CONFIG_DEFAULT));
+    args.color.or(file.color).unwrap_or_default().apply();
+    args.lang
+        .or(file.lang)
+        .or_else(Lang::from_env)
+        .unwrap_or_default()
+        .apply();
+    let mut profiles = file.profiles;
+    match (&args.profiles, Path::new(PROFILES_DEFAULT).exists()) {
+        (Some(path), _) => profiles.extend(client::load_profiles(path)?),
+        (None, true) => profiles.extend(client::load_profiles(PROFILES_DEFAULT)?),
+        (None, false) => (),
+    };
+    // A port given with the host takes precedence.
+    let port = args.port.or(file.port).unwrap_or(PORT_DEFAULT);
+    let addr = match args.host.or(file.host) {
+        Some(host) => ServerAddr::parse(&host, port).map_err(|e| anyhow!("Server host: {e}"))?,
+        None => SocketAddr::from((HOST_DEFAULT, port)).into(),
+    };
+    let convert_images = match (args.convert_images, file.convert_images) {
+        (Some(format), _) => Some(format),
+        (None, Some(format)) => Some(
+            client::parse_image_format(&format)
+                .with_context(|| "Image format in the configuration file")?,
+        ),
+        (None, None) => None,
+    };
 
-    let host: IpAddr = args.host.parse()?;
-    let addr = SocketAddr::from((host, args.port));
+    let defaults = ImageLimits::default();
+    let (max_width, max_height) = match (args.max_image_dimensions, file.max_image_dimensions) {
+        (Some(dimensions), _) => dimensions,
+        (None, Some(dimensions)) => cli_ser::parse_dimensions(&dimensions)
+            .with_context(|| "Image dimensions in the configuration file")?,
+        (None, None) => (defaults.max_width, defaults.max_height),
+    };
+    let image_limits = ImageLimits {
+        max_bytes: args
+            .max_image_bytes
+            .or(file.max_image_bytes)
+            .unwrap_or(defaults.max_bytes),
+        max_width,
+        max_height,
+        max_pixels: args
+            .max_image_pixels
+            .or(file.max_image_pixels)
+            .unwrap_or(defaults.max_pixels),
+    };
 
-    client::run(Config {
-        file_dir,
-        img_dir,
+    let config = Config {
+        file_dir: args
+            .file_dir
+            .or(file.file_dir)
+            .unwrap_or(PathBuf::from("files")),
+        img_dir: args
+            .img_dir
+            .or(file.img_dir)
+            .unwrap_or(PathBuf::from("images")),
+        on_collision: args.on_collision.or(file.on_collision).unwrap_or_default(),
+        image_clock: args.image_clock.or(file.image_clock).unwrap_or_default(),
+        layout: args.layout.or(file.layout).unwrap_or_default(),
         addr,
-        save_png: args.save_png,
-    })
-    .await
+        prefer_ip: args.prefer_ip.or(file.prefer_ip).unwrap_or_default(),
+        credentials: match (args.user, args.password) {
+            (Some(user), Some(password)) => Some(Credentials {
+                user: user.into(),
+                password,
+            }),
+            (None, None) => None,
+            _ => anyhow::bail!("--user and --password must be given together"),
+        },
+        profiles,
+        profile: args.profile.or(file.profile),
+        convert_images,
+        history_file: args
+            .history_file
+            .or(file.history_file)
+            .unwrap_or(PathBuf::from(client::history::FILE_DEFAULT)),
+        strip_exif: args.strip_exif.or(file.strip_exif).unwrap_or(false),
+        image_limits,
+        auto_attach: args.auto_attach.or(file.auto_attach).unwrap_or_default(),
+        notify: Notifications {
+            cmd: args.notify_cmd.or(file.notify.cmd),
+            bell: args.bell.or(file.notify.bell).unwrap_or(false),
+            idle: args
+                .notify_idle
+                .or(file.notify.idle)
+                .map(Duration::from_secs)
+                .unwrap_or(client::notify::IDLE_DEFAULT),
+            mentions_only: args
+                .notify_mentions_only
+                .or(file.notify.mentions_only)
+                .unwrap_or(false),
+        },
+        theme: args.theme.or(file.theme).unwrap_or_default(),
+        filters: file.filters,
+        config_file: Some(config_file),
+        identity_provider: match (args.oidc_issuer, args.oidc_client_id) {
+            (Some(issuer), Some(client_id)) => Some(IdentityProvider { issuer, client_id }),
+            (None, None) => file.identity_provider,
+            _ => anyhow::bail!("--oidc-issuer and --oidc-client-id must be given together"),
+        },
+    };
+    // Fail early on a wrong profile name.
+    config.resolved()?;
+    match args.command.unwrap_or(Command::Chat) {
+        Command::Chat => {
+            let commands = file
+                .templates
+                .into_iter()
+                .fold(Commands::default(), |commands, (name, text)| {
+                    commands.template(name, text)
+                });
+            client::run_with(config, commands).await
+        }
+        Command::Send { text } => {
+            client::unattended::send(&config, Data::Text(text)).await?;
+            Ok(())
+        }
+        Command::SendFile { path } => {
+            let file = File::from_path(&path)
+                .await
+                .with_context(|| format!("Loading the file {path:?} failed"))?;
+            client::unattended::send(&config, file.into()).await?;
+            Ok(())
+        }
+        Command::Listen { json } => client::unattended::listen(&config, json).await,
+        Command::Daemon { outbox, inbox } => client::daemon::run(&config, &outbox, &inbox).await,
+    }
 }
 
 /// Client executable, interactively sends messages to the specified server.
+///
+/// Options are taken from the command line, then from the environment variables,
+/// then from the configuration file and finally the defaults are used.
+/// Switches take an optional value, e.g. `--bell=false` turns off what the file turns on.
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Server host
-    #[arg(long, default_value_t = IpAddr::from(HOST_DEFAULT).to_string())]
-    host: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// User to log in as right after connecting, needs --password
+    #[arg(long, global = true, env = "CLIENT_USER")]
+    user: Option<String>,
+
+    /// Password of the --user
+    #[arg(long, global = true, env = "CLIENT_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+
+    /// TOML configuration file, "client.toml" is used when it exists,
+    /// filters set by .mute and .filter add are saved to it
+    #[arg(long, value_name = "FILE", env = "CLIENT_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Server host, a name or an IP address, optionally with the port, e.g. "localhost" or "[::1]:11111" [default: 127.0.0.1]
+    #[arg(long, env = "CLIENT_HOST")]
+    host: Option<String>,
+
+    /// Server port, unless the host has one [default: 11111]
+    #[arg(short, long, env = "CLIENT_PORT")]
+    port: Option<u16>,
+
+    /// Try the "ipv6" or the "ipv4" addresses of the host first [default: ipv6]
+    #[arg(long, value_name = "FAMILY", env = "CLIENT_PREFER_IP")]
+    prefer_ip: Option<IpPreference>,
+
+    /// Connect and log in as the profile says, overrides host and port
+    #[arg(long, env = "CLIENT_PROFILE")]
+    profile: Option<String>,
+
+    /// TOML file with profiles, "profiles.toml" is used when it exists
+    #[arg(long, value_name = "FILE", env = "CLIENT_PROFILES")]
+    profiles: Option<PathBuf>,
+
+    /// Directory to save received files to [default: files]
+    #[arg(long, value_name = "DIR", env = "CLIENT_FILE_DIR")]
+    file_dir: Option<PathBuf>,
+
+    /// Directory to save received images to [default: images]
+    #[arg(long, value_name = "DIR", env = "CLIENT_IMG_DIR")]
+    img_dir: Option<PathBuf>,
+
+    /// Convert all received images to the format, e.g. "png", "webp" or "jpeg:80" (JPEG quality 80).
+    #[arg(short, long, value_name = "FORMAT", value_parser = client::parse_image_format, env = "CLIENT_CONVERT_IMAGES")]
+    convert_images: Option<ImageOutputFormat>,
+
+    /// When a received file exists already, "rename" it to e.g. "notes (1).txt" or "overwrite" it [default: rename]
+    #[arg(long, value_name = "POLICY", env = "CLIENT_ON_COLLISION")]
+    on_collision: Option<OnCollision>,
+
+    /// Name received images by the "utc" or "local" time [default: utc]
+    #[arg(long, value_name = "CLOCK", env = "CLIENT_IMAGE_CLOCK")]
+    image_clock: Option<Clock>,
+
+    /// Save received files and images "flat" in their directories, in subdirectories by "sender" or by "date" [default: flat]
+    #[arg(long, value_name = "LAYOUT", env = "CLIENT_LAYOUT")]
+    layout: Option<StorageLayout>,
 
-    /// Server port
-    #[arg(short, long, default_value_t = PORT_DEFAULT)]
-    port: u16,
+    /// Local history of messages, "{user}" is replaced by the username [default: history/{user}.jsonl]
+    #[arg(long, value_name = "FILE", env = "CLIENT_HISTORY_FILE")]
+    history_file: Option<PathBuf>,
+
+    /// When a line is only the path of an existing file (e.g. dragged in), "ask" whether to send the file, send it "always" or "off" [default: ask]
+    #[arg(long, value_name = "MODE", env = "CLIENT_AUTO_ATTACH")]
+    auto_attach: Option<AutoAttach>,
+
+    /// Turn sent images upright and strip their metadata (EXIF with GPS position, camera, ...)
+    #[arg(
+        long,
+        env = "CLIENT_STRIP_EXIF",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_parser = BoolishValueParser::new(),
+    )]
+    strip_exif: Option<bool>,
+
+    /// Do not send images bigger than this many bytes [default: 33554432]
+    #[arg(long, value_name = "BYTES", env = "CLIENT_MAX_IMAGE_BYTES")]
+    max_image_bytes: Option<usize>,
+
+    /// Do not send images wider or higher, e.g. "8192x8192" [default: 16384x16384]
+    #[arg(long, value_name = "WIDTHxHEIGHT", value_parser = cli_ser::parse_dimensions, env = "CLIENT_MAX_IMAGE_DIMENSIONS")]
+    max_image_dimensions: Option<(u32, u32)>,
+
+    /// Do not send images of more pixels (width times height) [default: 67108864]
+    #[arg(long, value_name = "PIXELS", env = "CLIENT_MAX_IMAGE_PIXELS")]
+    max_image_pixels: Option<u64>,
+
+    /// Shell command run when a message arrives while you are away, gets the sender and the text as "$1" and "$2"
+    #[arg(long, value_name = "CMD", env = "CLIENT_NOTIFY_CMD")]
+    notify_cmd: Option<String>,
+
+    /// Ring the terminal bell when a message arrives while you are away
+    #[arg(
+        long,
+        env = "CLIENT_BELL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_parser = BoolishValueParser::new(),
+    )]
+    bell: Option<bool>,
+
+    /// Seconds without typing after which you are considered away [default: 30]
+    #[arg(long, value_name = "SECS", env = "CLIENT_NOTIFY_IDLE")]
+    notify_idle: Option<u64>,
+
+    /// Notify only about messages mentioning you as @username
+    #[arg(
+        long,
+        env = "CLIENT_NOTIFY_MENTIONS_ONLY",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_parser = BoolishValueParser::new(),
+    )]
+    notify_mentions_only: Option<bool>,
+
+    /// Issuer of the identity provider `.token` obtains tokens from, e.g. "https://accounts.example.com"
+    #[arg(long, value_name = "URL", env = "CLIENT_OIDC_ISSUER")]
+    oidc_issuer: Option<String>,
+
+    /// Client id registered at the identity provider, given with --oidc-issuer
+    #[arg(long, value_name = "ID", env = "CLIENT_OIDC_CLIENT_ID")]
+    oidc_client_id: Option<String>,
+
+    /// When to color the output, "auto" (on a terminal, unless NO_COLOR is set), "always" or "never" [default: auto]
+    #[arg(long, value_name = "WHEN", env = "CLIENT_COLOR")]
+    color: Option<ColorChoice>,
+
+    /// Colors of the output, "dark", "light" or "mono", a profile may have its own [default: dark]
+    #[arg(long, value_name = "THEME", env = "CLIENT_THEME")]
+    theme: Option<Theme>,
+
+    /// Language of the texts, "en" or "cs" [default: the language of LANG if there are texts in it, else en]
+    #[arg(long, value_name = "LANG", env = "CLIENT_LANG")]
+    lang: Option<Lang>,
+}
 
-    /// Save all images as PNG.
-    #[arg(short, long, default_value_t = false)]
-    save_png: bool,
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Chat interactively, the default
+    Chat,
+    /// Send a text and exit once the server confirms it
+    Send {
+        /// Text to send
+        #[arg(long)]
+        text: String,
+    },
+    /// Send a file and exit once the server confirms it
+    SendFile {
+        /// File to send
+        path: PathBuf,
+    },
+    /// Print incoming messages until the server closes the connection
+    Listen {
+        /// Print each message as a line of JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Send files put in the outbox and write incoming messages to the inbox, without a terminal
+    Daemon {
+        /// Directory watched for files to send, "*.msg" files are sent as texts
+        #[arg(
+            long,
+            value_name = "DIR",
+            default_value = "outbox",
+            env = "CLIENT_OUTBOX"
+        )]
+        outbox: PathBuf,
+        /// Directory to write incoming messages, files and images to
+        #[arg(
+            long,
+            value_name = "DIR",
+            default_value = "inbox",
+            env = "CLIENT_INBOX"
+        )]
+        inbox: PathBuf,
+    },
 }
@@ -0,0 +1,111 @@
+//! Notifications about incoming messages while nobody is typing, see [Notifications].
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+use tokio::process::Command;
+
+/// Default inactivity after which the terminal is considered unattended.
+pub const IDLE_DEFAULT: Duration = Duration::from_secs(30);
+
+/// How incoming messages are brought to attention when the user did not type for [idle][Self::idle].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Notifications {
+    /// Shell command run for every such message.
+    ///
+    /// The sender and the text are passed as the arguments `$1` and `$2`
+    /// and as the environment variables `CHAT_FROM` and `CHAT_TEXT`, e.g. `notify-send "$1" "$2"`.
+    pub cmd: Option<String>,
+    /// Whether to ring the terminal bell.
+    pub bell: bool,
+    pub idle: Duration,
+    /// Only messages mentioning the user are notified about.
+    pub mentions_only: bool,
+}
+impl Default for Notifications {
+    fn default() -> Self {
+        Notifications {
+            cmd: None,
+            bell: false,
+            idle: IDLE_DEFAULT,
+            mentions_only: false,
+        }
+    }
+}
+impl Notifications {
+    /// Notifies about the message unless the user typed something recently
+    /// or it does not mention them when [only mentions][Self::mentions_only] count.
+    /// Urgent messages are notified about always.
+    ///
+    /// The command is not waited for.
+    pub(crate) fn notify(
+        &self,
+        last_input: Option<Instant>,
+        mentioned: bool,
+        urgent: bool,
+        from: &str,
+        text: &str,
+    ) {
+        if !urgent && last_input.is_some_and(|input| input.elapsed() < self.idle) {
+            return;
+        }
+        if !urgent && self.mentions_only && !mentioned {
+            return;
+        }
+        if self.bell {
+            print!("\x07");
+            let _ = io::stdout().flush();
+        }
+        if let Some(mut cmd) = self.command(from, text) {
+            if let Err(e) = cmd.spawn() {
+                eprintln!("Running the notification command failed! Err: {e}");
+            }
+        }
+    }
+
+    /// Makes the notification command, run by the system shell.
+    fn command(&self, from: &str, text: &str) -> Option<Command> {
+        let line = self.cmd.as_ref()?;
+        let mut cmd = match cfg!(windows) {
+            true => {
+                let mut cmd = Command::new("cmd");
+                cmd.args(["/C", line]);
+                cmd
+            }
+            false => {
+                let mut cmd = Command::new("sh");
+                cmd.args(["-c", line, "sh", from, text]);
+                cmd
+            }
+        };
+        cmd.env("CHAT_FROM", from)
+            .env("CHAT_TEXT", text)
+            .stdin(std::process::Stdio::null());
+        Some(cmd)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn command_gets_sender_and_text() {
+        let notifications = Notifications {
+            cmd: Some(r#"printf '%s|%s' "$1" "$CHAT_TEXT""#.to_string()),
+            ..Default::default()
+        };
+        let output = notifications
+            .command("alice", "it's 'quoted'")
+            .unwrap()
+            .output()
+            .await
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "alice|it's 'quoted'"
+        );
+        assert!(Notifications::default().command("alice", "hi").is_none());
+    }
+}
@@ -0,0 +1,262 @@
+//! Tokens of an OpenID Connect identity provider for `.token`, see [IdentityProvider].
+//!
+//! They are obtained by the [device authorization grant](https://www.rfc-editor.org/rfc/rfc8628):
+//! the user approves the log-in in a browser, possibly on another device, while the client waits.
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+use tokio::time::Instant;
+
+/// Grant type of the token requests, RFC 8628 section 3.4.
+const GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// Provider the client logs in by, the one the server [trusts][cli_ser::cli::Auth::OidcToken].
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IdentityProvider {
+    /// Issuer of the tokens, e.g. "https://accounts.example.com",
+    /// its endpoints are discovered from `<issuer>/.well-known/openid-configuration`.
+    pub issuer: String,
+    /// Client id registered at the provider, the audience the server expects.
+    pub client_id: String,
+}
+
+/// What the user does to approve the log-in.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Authorization {
+    device_code: String,
+    /// Code the user enters at the [verification_uri][Self::verification_uri].
+    pub user_code: String,
+    pub verification_uri: String,
+    /// URI with the code filled in, the user only approves there.
+    pub verification_uri_complete: Option<String>,
+    /// Seconds the user has.
+    pub expires_in: u64,
+    /// Seconds between the token requests.
+    #[serde(default = "interval_default")]
+    interval: u64,
+}
+
+/// Interval when the provider gives none, RFC 8628 section 3.2.
+fn interval_default() -> u64 {
+    5
+}
+
+#[derive(Deserialize)]
+struct Discovery {
+    device_authorization_endpoint: String,
+    token_endpoint: String,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TokenResponse {
+    Issued {
+        id_token: String,
+    },
+    Failed {
+        error: String,
+        error_description: Option<String>,
+    },
+}
+
+impl IdentityProvider {
+    /// Returns the ID token of the user once they approve the log-in as `show` tells them.
+    ///
+    /// The provider is asked as often as it allows until it issues the token,
+    /// it fails when the user denies the log-in or does not approve it in time.
+    pub async fn token(&self, show: impl FnOnce(&Authorization)) -> anyhow::Result<String> {
+        let http = reqwest::Client::new();
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.issuer.trim_end_matches('/')
+        );
+        let discovery: Discovery = json(http.get(&url), &url).await?;
+        let url = discovery.device_authorization_endpoint;
+        let request = http
+            .post(&url)
+            .form(&[("client_id", &*self.client_id), ("scope", "openid profile")]);
+        let authorization: Authorization = json(request, &url).await?;
+        show(&authorization);
+
+        let expires = Instant::now() + Duration::from_secs(authorization.expires_in);
+        let mut interval = Duration::from_secs(authorization.interval);
+        let url = discovery.token_endpoint;
+        loop {
+            tokio::time::sleep(interval).await;
+            let request = http.post(&url).form(&[
+                ("grant_type", GRANT_TYPE),
+                ("device_code", &authorization.device_code),
+                ("client_id", &self.client_id),
+            ]);
+            // Errors are answered with 400 and the JSON telling why.
+            let response = request
+                .send()
+                .await
+                .with_context(|| format!("Requesting {url} failed."))?;
+            match response
+                .json()
+                .await
+                .with_context(|| format!("{url} did not answer with the expected JSON."))?
+            {
+                TokenResponse::Issued { id_token } => return Ok(id_token),
+                TokenResponse::Failed { error, .. } if error == "authorization_pending" => {}
+                TokenResponse::Failed { error, .. } if error == "slow_down" => {
+                    interval += Duration::from_secs(5);
+                }
+                TokenResponse::Failed {
+                    error,
+                    error_description,
+                } => {
+                    let reason = error_description.unwrap_or(error);
+                    return Err(anyhow!(
+                        "The identity provider did not log you in: {reason}"
+                    ));
+                }
+            }
+            if Instant::now() + interval >= expires {
+                return Err(anyhow!("The log-in was not approved in time."));
+            }
+        }
+    }
+}
+
+async fn json<T: serde::de::DeserializeOwned>(
+    request: reqwest::RequestBuilder,
+    url: &str,
+) -> anyhow::Result<T> {
+    request
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .with_context(|| format!("Requesting {url} failed."))?
+        .json()
+        .await
+        .with_context(|| format!("{url} did not answer with the expected JSON."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// Serves the discovery, the authorization and then the token `answers` in turn, one per connection.
+    /// Returns the issuer and the request bodies of the token endpoint.
+    async fn provider(
+        answers: Vec<serde_json::Value>,
+    ) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let base = issuer.clone();
+        let serve = tokio::spawn(async move {
+            let mut token_requests = Vec::new();
+            let mut answers = answers.into_iter();
+            for _ in 0..2 + answers.len() {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                while !String::from_utf8_lossy(&request).contains("\r\n\r\n") {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let request = String::from_utf8(request).unwrap();
+                let (head, body) = request.split_once("\r\n\r\n").unwrap();
+                let length: usize = head
+                    .lines()
+                    .find_map(|line| {
+                        line.to_lowercase()
+                            .strip_prefix("content-length: ")
+                            .map(|n| n.parse().unwrap())
+                    })
+                    .unwrap_or(0);
+                let mut body = body.to_string();
+                while body.len() < length {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    body.push_str(std::str::from_utf8(&buf[..n]).unwrap());
+                }
+                let (status, answer) = match head.split_whitespace().nth(1).unwrap() {
+                    "/.well-known/openid-configuration" => (
+                        "200 OK",
+                        json!({
+                            "device_authorization_endpoint": format!("{base}/device"),
+                            "token_endpoint": format!("{base}/token"),
+                        }),
+                    ),
+                    "/device" => (
+                        "200 OK",
+                        json!({
+                            "device_code": "device-42",
+                            "user_code": "WDJB-MJHT",
+                            "verification_uri": format!("{base}/activate"),
+                            "expires_in": 60,
+                            "interval": 0,
+                        }),
+                    ),
+                    "/token" => {
+                        token_requests.push(body);
+                        let answer = answers.next().unwrap();
+                        match answer.get("error") {
+                            Some(_) => ("400 Bad Request", answer),
+                            None => ("200 OK", answer),
+                        }
+                    }
+                    path => panic!("unexpected request of {path}"),
+                };
+                let answer = answer.to_string();
+                let response = format!(
+                    "HTTP/1.1 {status}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{answer}",
+                    answer.len()
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+            token_requests
+        });
+        (issuer, serve)
+    }
+
+    #[tokio::test]
+    async fn token_once_approved() {
+        let pending = json!({"error": "authorization_pending"});
+        let answers = vec![pending.clone(), pending, json!({"id_token": "eyJ.e30.sig"})];
+        let (issuer, serve) = provider(answers).await;
+        let provider = IdentityProvider {
+            issuer,
+            client_id: "chat".to_string(),
+        };
+        let mut shown = None;
+        let token = provider
+            .token(|authorization| shown = Some(authorization.user_code.clone()))
+            .await
+            .unwrap();
+        assert_eq!(token, "eyJ.e30.sig");
+        assert_eq!(shown.as_deref(), Some("WDJB-MJHT"));
+        let requests = serve.await.unwrap();
+        assert_eq!(requests.len(), 3);
+        assert!(requests
+            .iter()
+            .all(|body| body.contains("device_code=device-42")
+                && body.contains("client_id=chat")
+                && body.contains(
+                    "grant_type=urn%3Aietf%3Aparams%3Aoauth%3Agrant-type%3Adevice_code"
+                )));
+    }
+
+    #[tokio::test]
+    async fn token_denied() {
+        let denied = json!({"error": "access_denied", "error_description": "the user said no"});
+        let (issuer, serve) = provider(vec![denied]).await;
+        let provider = IdentityProvider {
+            issuer,
+            client_id: "chat".to_string(),
+        };
+        let err = provider.token(|_| {}).await.unwrap_err();
+        assert!(err.to_string().contains("the user said no"), "{err}");
+        serve.await.unwrap();
+    }
+}
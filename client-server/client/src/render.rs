@@ -0,0 +1,227 @@
+//! Colors of the terminal output, each sender has one of their own, errors are red and times dim.
+//!
+//! Texts are styled with escape sequences, the output of `anstream` strips them
+//! when colors are off, see [ColorChoice]. Which colors are used the [Theme] says.
+use std::{
+    fmt::Display,
+    str::FromStr,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use anstyle::{AnsiColor, Style};
+use serde::Deserialize;
+
+use crate::{
+    history,
+    i18n::{self, Text},
+};
+
+/// Colors senders are told apart by, red is left for errors.
+const SENDER_COLORS: [AnsiColor; 6] = [
+    AnsiColor::Cyan,
+    AnsiColor::Green,
+    AnsiColor::Yellow,
+    AnsiColor::Blue,
+    AnsiColor::Magenta,
+    AnsiColor::BrightCyan,
+];
+
+/// Colors of senders readable on a light background, without yellow and bright ones.
+const LIGHT_SENDER_COLORS: [AnsiColor; 4] = [
+    AnsiColor::Blue,
+    AnsiColor::Green,
+    AnsiColor::Magenta,
+    AnsiColor::Cyan,
+];
+
+/// Theme of the process, set by [Theme::apply].
+static THEME: AtomicU8 = AtomicU8::new(Theme::Dark as u8);
+
+/// Colors of the output, the profile connected to may have its own, see [Profile][crate::Profile].
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[repr(u8)]
+pub enum Theme {
+    /// For dark terminals.
+    #[default]
+    Dark,
+    /// For light terminals.
+    Light,
+    /// Without colors, senders and errors are bold.
+    Mono,
+}
+impl FromStr for Theme {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dark" => Ok(Theme::Dark),
+            "light" => Ok(Theme::Light),
+            "mono" => Ok(Theme::Mono),
+            _ => Err(format!(
+                "unknown theme `{s}`, expected `dark`, `light` or `mono`"
+            )),
+        }
+    }
+}
+impl Theme {
+    /// Applies the theme to all output of the process from now on.
+    pub fn apply(self) {
+        THEME.store(self as u8, Ordering::Relaxed);
+    }
+
+    /// Theme of the process.
+    pub fn current() -> Theme {
+        match THEME.load(Ordering::Relaxed) {
+            t if t == Theme::Light as u8 => Theme::Light,
+            t if t == Theme::Mono as u8 => Theme::Mono,
+            _ => Theme::Dark,
+        }
+    }
+
+    fn sender(self, user: &str) -> Style {
+        let colors: &[AnsiColor] = match self {
+            Theme::Dark => &SENDER_COLORS,
+            Theme::Light => &LIGHT_SENDER_COLORS,
+            Theme::Mono => return Style::new().bold(),
+        };
+        let hash = user.bytes().fold(0_usize, |hash, b| {
+            hash.wrapping_mul(31).wrapping_add(b as usize)
+        });
+        Style::new().fg_color(Some(colors[hash % colors.len()].into()))
+    }
+
+    fn error(self) -> Style {
+        match self {
+            Theme::Mono => Style::new().bold(),
+            Theme::Dark | Theme::Light => Style::new().fg_color(Some(AnsiColor::Red.into())),
+        }
+    }
+}
+
+/// When the output is colored.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorChoice {
+    /// On a terminal, unless the `NO_COLOR` environment variable is set (`CLICOLOR_FORCE` forces colors).
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+impl FromStr for ColorChoice {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!(
+                "unknown color choice `{s}`, expected `auto`, `always` or `never`"
+            )),
+        }
+    }
+}
+impl ColorChoice {
+    /// Applies the choice to all output of the process.
+    pub fn apply(self) {
+        match self {
+            ColorChoice::Auto => anstream::ColorChoice::Auto,
+            ColorChoice::Always => anstream::ColorChoice::Always,
+            ColorChoice::Never => anstream::ColorChoice::Never,
+        }
+        .write_global()
+    }
+}
+
+fn paint(style: Style, text: impl Display) -> String {
+    format!("{}{text}{}", style.render(), style.render_reset())
+}
+
+/// Sender as "Display Name (username)", in the color of the username, guests are marked.
+pub(crate) fn sender(user: &str, display_name: Option<String>, guest: bool) -> String {
+    sender_in(Theme::current(), user, display_name, guest)
+}
+
+fn sender_in(theme: Theme, user: &str, display_name: Option<String>, guest: bool) -> String {
+    let mut shown = match display_name {
+        Some(name) => format!("{name} ({user})"),
+        None => user.to_string(),
+    };
+    if guest {
+        shown = format!("{shown} [{}]", i18n::text(Text::Guest));
+    }
+    paint(theme.sender(user), shown)
+}
+
+/// Text mentioning the user.
+pub(crate) fn mention(text: impl Display) -> String {
+    paint(Style::new().bold(), text)
+}
+
+/// Urgent text, marked so in the user's language.
+pub(crate) fn urgent(text: impl Display) -> String {
+    let style = Theme::current().error().bold();
+    paint(style, format!("[{}] {text}", i18n::text(Text::Urgent)))
+}
+
+/// Announcement of the server.
+pub(crate) fn info(text: impl Display) -> String {
+    paint(Style::new().italic(), format!("*** {text} ***"))
+}
+
+pub(crate) fn error(text: impl Display) -> String {
+    paint(Theme::current().error(), text)
+}
+
+pub(crate) fn time(time: impl Display) -> String {
+    paint(Style::new().dimmed(), time)
+}
+
+/// Entry of the history as `[time] sender: text`, sent messages are from "you" (in the user's language).
+pub(crate) fn entry(entry: &history::Entry) -> String {
+    let from = match &entry.from {
+        Some(from) => sender(from, None, false),
+        None => i18n::text(Text::You).to_string(),
+    };
+    format!(
+        "{} {from}: {}",
+        time(format!("[{}]", entry.time)),
+        entry.text
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sender_colors() {
+        assert_eq!(sender("alice", None, false), sender("alice", None, false));
+        assert!(sender("alice", Some("Alice".to_string()), false).contains("Alice (alice)"));
+        assert!(sender("alice", None, true).contains("alice [guest]"));
+        let colors: std::collections::HashSet<_> = ["alice", "bob", "carol", "dave"]
+            .iter()
+            .map(|user| sender(user, None, false).replace(user, ""))
+            .collect();
+        assert!(colors.len() > 1, "senders should not share one color");
+        assert_eq!("never".parse(), Ok(ColorChoice::Never));
+        assert!("sometimes".parse::<ColorChoice>().is_err());
+    }
+
+    #[test]
+    fn themes() {
+        let light: std::collections::HashSet<_> = ["alice", "bob", "carol", "dave", "erin"]
+            .iter()
+            .map(|user| sender_in(Theme::Light, user, None, false).replace(user, ""))
+            .collect();
+        assert!(light.len() > 1, "senders should not share one color");
+        let yellow = Style::new().fg_color(Some(AnsiColor::Yellow.into()));
+        assert!(!light
+            .iter()
+            .any(|shown| shown.contains(&yellow.render().to_string())));
+        let mono = sender_in(Theme::Mono, "alice", None, false);
+        assert_eq!(mono, paint(Style::new().bold(), "alice"));
+        assert_eq!("light".parse(), Ok(Theme::Light));
+        assert!("neon".parse::<Theme>().is_err());
+    }
+}
@@ -0,0 +1,259 @@
+//! Commands running without the terminal, for scripts: [send] once and [listen].
+use std::path::PathBuf;
+
+use anyhow::{anyhow, bail, Context};
+use chrono::{offset::Utc, SecondsFormat};
+use serde::Serialize;
+
+use cli_ser::{
+    cli,
+    retry::{deadline, TIMEOUT_DEFAULT},
+    ser, Connection, Data, ErrorCode, MsgId, Presence,
+};
+
+use crate::{explain, save_file, save_image, save_media, Config};
+
+/// Logs in as the configuration says, sends the `data` and waits until the server confirms it.
+///
+/// Returns the id the data was stored with, if it was stored.
+/// The server has [TIMEOUT_DEFAULT] for each answer.
+pub async fn send(config: &Config, data: Data) -> anyhow::Result<Option<i64>> {
+    let mut conn = connect(config).await?;
+    let id = MsgId(1);
+    conn.send_msg(cli::Msg::ToAll(data).tagged(id))
+        .await
+        .context("Sending the message failed")?;
+    let mut stored = None;
+    loop {
+        let answer = deadline(TIMEOUT_DEFAULT, conn.recv()).await;
+        match answer.context("Waiting for the server failed")? {
+            ser::Msg::Stored { msg_id, .. } => stored = Some(msg_id),
+            ser::Msg::Ack(acked) if acked == id => break Ok(stored),
+            ser::Msg::Rejected(_, err) | ser::Msg::Error(err) => {
+                bail!("The message was not sent: {}", explain(&err))
+            }
+            // Messages of others and announcements are not of interest.
+            _ => continue,
+        }
+    }
+}
+
+/// Logs in as the configuration says and prints incoming messages until the server closes the connection.
+///
+/// Files and images are saved as in the chat, with `json` every message is printed as a [Line].
+pub async fn listen(config: &Config, json: bool) -> anyhow::Result<()> {
+    let config = config.resolved()?;
+    for dir in [&config.file_dir, &config.img_dir] {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("Directory {dir:?} couldn't be created"))?;
+    }
+    let mut conn = connect(&config).await?;
+    let session = crate::Session::default();
+    loop {
+        let msg = match conn.recv().await {
+            Ok(msg) => msg,
+            Err(cli_ser::Error::DisconnectedStream(_)) => break Ok(()),
+            Err(e) => break Err(e).context("Reading a message from the server failed"),
+        };
+        match json {
+            true => {
+                if let Some(line) = Line::of(&config, msg).await {
+                    println!("{}", serde_json::to_string(&line)?);
+                }
+            }
+            false => crate::process_msg(&config, &session, msg, None).await,
+        }
+    }
+}
+
+/// Connects to the server and logs in with the credentials of the configuration.
+pub(crate) async fn connect(config: &Config) -> anyhow::Result<Connection> {
+    let config = config.resolved()?;
+    let creds = config.credentials.ok_or_else(|| {
+        anyhow!("Credentials are needed, give --user and --password or a profile with them")
+    })?;
+    config
+        .addr
+        .connect(config.prefer_ip, |addr| {
+            Connection::connect(addr, creds.clone())
+        })
+        .await
+        .with_context(|| format!("Connecting to the server at {} failed", config.addr))
+}
+
+/// Message printed by [listen] as a line of JSON, e.g.
+/// ```json
+/// {"time":"2024-01-31T12:00:00Z","from":"alice","msg_id":7,"type":"text","text":"hi"}
+/// ```
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Line {
+    /// RFC 3339 time of receiving.
+    pub time: String,
+    /// Sender, `None` for the server itself.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    /// Id of the stored message.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub msg_id: Option<i64>,
+    #[serde(flatten)]
+    pub content: Content,
+}
+
+/// What the [Line] is about.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Content {
+    Text {
+        text: String,
+    },
+    /// Received file saved at the path.
+    File {
+        path: PathBuf,
+    },
+    /// Received image saved at the path.
+    Image {
+        path: PathBuf,
+    },
+    /// Received animation or video saved at the path.
+    Media {
+        path: PathBuf,
+        mime: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        duration_ms: Option<u64>,
+    },
+    /// Announcement of the server.
+    Info {
+        text: String,
+    },
+    Error {
+        error: String,
+        /// See [ErrorCode].
+        code: ErrorCode,
+    },
+}
+
+impl Line {
+    /// Makes the line of the message, attachments are saved first.
+    ///
+    /// Returns `None` for messages answering the client's own ones.
+    pub(crate) async fn of(config: &Config, msg: ser::Msg) -> Option<Line> {
+        let (from, display_name, msg_id, content) = match msg {
+            ser::Msg::DataFrom {
+                data,
+                from,
+                msg_id,
+                display_name,
+                ..
+            } => {
+                let sender = from.to_string();
+                let content = match data {
+                    Data::Text(text) => Content::Text { text },
+                    Data::File(file) => match save_file(config, &sender, &file, None).await {
+                        Ok(saved) => Content::File {
+                            path: saved.into_path(),
+                        },
+                        Err(e) => Content::Error {
+                            error: format!("saving the file {:?} failed: {e}", file.name()),
+                            code: e.code(),
+                        },
+                    },
+                    Data::Media(media) => match save_media(config, &sender, &media).await {
+                        Ok(saved) => Content::Media {
+                            path: saved.into_path(),
+                            mime: media.mime().to_string(),
+                            duration_ms: media.duration().map(|d| d.as_millis() as u64),
+                        },
+                        Err(e) => Content::Error {
+                            error: format!("saving the media {:?} failed: {e}", media.name()),
+                            code: e.code(),
+                        },
+                    },
+                    Data::Image(image) => match save_image(config, &sender, image).await {
+                        Ok(saved) => Content::Image {
+                            path: saved.into_path(),
+                        },
+                        Err(e) => Content::Error {
+                            error: format!("saving the image failed: {e}"),
+                            code: e.code(),
+                        },
+                    },
+                };
+                (Some(sender), display_name, msg_id, content)
+            }
+            ser::Msg::ServerInfo(text) => (None, None, None, Content::Info { text }),
+            ser::Msg::NewSession { addr, time } => {
+                let text = format!("your account logged in from {addr} at {time}");
+                (None, None, None, Content::Info { text })
+            }
+            ser::Msg::PresenceChanged {
+                user,
+                presence,
+                message,
+            } => {
+                let message = message.map(|m| format!(": {m}")).unwrap_or_default();
+                let text = match presence {
+                    Presence::Online => format!("{user} is back"),
+                    Presence::Away => format!("{user} is away{message}"),
+                    Presence::DoNotDisturb => {
+                        format!("{user} does not want to be disturbed{message}")
+                    }
+                };
+                (None, None, None, Content::Info { text })
+            }
+            ser::Msg::Error(err) => (
+                None,
+                None,
+                None,
+                Content::Error {
+                    error: explain(&err),
+                    code: err.code(),
+                },
+            ),
+            _ => return None,
+        };
+        Some(Line {
+            time: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            from,
+            display_name,
+            msg_id,
+            content,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_as_json() {
+        let line = Line {
+            time: "2024-01-31T12:00:00Z".to_string(),
+            from: Some("alice".to_string()),
+            display_name: None,
+            msg_id: Some(7),
+            content: Content::Text {
+                text: "hi".to_string(),
+            },
+        };
+        assert_eq!(
+            serde_json::to_string(&line).unwrap(),
+            r#"{"time":"2024-01-31T12:00:00Z","from":"alice","msg_id":7,"type":"text","text":"hi"}"#
+        );
+        let info = Line {
+            from: None,
+            msg_id: None,
+            content: Content::Info {
+                text: "maintenance".to_string(),
+            },
+            ..line
+        };
+        assert_eq!(
+            serde_json::to_string(&info).unwrap(),
+            r#"{"time":"2024-01-31T12:00:00Z","type":"info","text":"maintenance"}"#
+        );
+    }
+}
@@ -0,0 +1,47 @@
+use core::time::Duration;
+use std::{env, net::SocketAddr};
+
+use tokio::{net::TcpListener, time::timeout};
+
+use client::*;
+
+/// The client must exit on its own when the server goes away, even though nobody presses Enter.
+#[tokio::test]
+async fn exits_on_server_loss() {
+    let listener = TcpListener::bind(SocketAddr::from((HOST_DEFAULT, 0)))
+        .await
+        .expect("TCP listener creation should not fail.");
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (socket, _) = listener.accept().await.expect("Accepting client failed!");
+        drop(socket);
+    });
+    let res = timeout(
+        Duration::from_secs(5),
+        run(Config {
+            img_dir: env::temp_dir().join("imgs"),
+            file_dir: env::temp_dir().join("fls"),
+            on_collision: client::OnCollision::Rename,
+            addr: addr.into(),
+            credentials: None,
+            profiles: Profiles::new(),
+            profile: None,
+            convert_images: None,
+            notify: client::Notifications::default(),
+            theme: client::Theme::default(),
+            history_file: std::env::temp_dir().join("client-test-{user}.jsonl"),
+            strip_exif: false,
+            image_limits: cli_ser::ImageLimits::default(),
+            auto_attach: AutoAttach::Off,
+            image_clock: Clock::default(),
+            layout: StorageLayout::default(),
+            prefer_ip: IpPreference::default(),
+            filters: Filters::default(),
+            config_file: None,
+            identity_provider: None,
+        }),
+    )
+    .await
+    .expect("The client should exit promptly after losing the server.");
+    assert!(res.is_err());
+}
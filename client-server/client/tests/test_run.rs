@@ -1,5 +1,5 @@
 use core::time::Duration;
-use std::{net::SocketAddr, path::PathBuf};
+use std::{env, net::SocketAddr};
 
 use tokio::net::TcpStream;
 
@@ -7,10 +7,10 @@ use client::*;
 
 #[tokio::test]
 async fn test_run() {
-    let addr = SocketAddr::from((HOST_DEFAULT, PORT_DEFAULT));
-    let listener = tokio::net::TcpListener::bind(addr)
+    let listener = tokio::net::TcpListener::bind(SocketAddr::from((HOST_DEFAULT, 0)))
         .await
         .expect("TCP listener creation should not fail.");
+    let addr = listener.local_addr().unwrap();
     let server_thread = tokio::spawn(async move {
         let mut v: Vec<TcpStream> = Vec::new();
         loop {
@@ -19,10 +19,26 @@ async fn test_run() {
         }
     });
     let client_thread = tokio::spawn(run(Config {
-        img_dir: PathBuf::from("imgs"),
-        file_dir: PathBuf::from("fls"),
-        addr,
-        save_png: true,
+        img_dir: env::temp_dir().join("imgs"),
+        file_dir: env::temp_dir().join("fls"),
+        on_collision: client::OnCollision::Rename,
+        addr: addr.into(),
+        credentials: None,
+        profiles: Profiles::new(),
+        profile: None,
+        convert_images: Some(cli_ser::ImageOutputFormat::Png),
+        notify: client::Notifications::default(),
+        theme: client::Theme::default(),
+        history_file: std::env::temp_dir().join("client-test-{user}.jsonl"),
+        strip_exif: false,
+        image_limits: cli_ser::ImageLimits::default(),
+        auto_attach: AutoAttach::Off,
+        image_clock: Clock::default(),
+        layout: StorageLayout::default(),
+        prefer_ip: IpPreference::default(),
+        filters: Filters::default(),
+        config_file: None,
+        identity_provider: None,
     }));
     tokio::time::sleep(Duration::from_secs(1)).await;
     assert!(!server_thread.is_finished());
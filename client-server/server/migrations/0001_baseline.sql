@@ -0,0 +1,94 @@
+-- Schema as created by the server before migrations were introduced.
+--
+-- Databases created back then already have it, so every statement is a no-op on them.
+
+-- Since the username is not the primary key, it can be changed later.
+CREATE TABLE IF NOT EXISTS "users" (
+  "id" bigserial PRIMARY KEY,
+  "username" text NOT NULL,
+  "password" text NOT NULL
+);
+ALTER TABLE "users" ADD COLUMN IF NOT EXISTS "is_admin" boolean NOT NULL DEFAULT false;
+
+CREATE TABLE IF NOT EXISTS "messages" (
+  "id" bigserial PRIMARY KEY,
+  "from_user_id" bigint NOT NULL,
+  "text_id" bigint,
+  "file_id" bigint,
+  "img_id" bigint,
+  "arrived" timestamp with time zone NOT NULL,
+  check(
+    (
+      ("text_id" IS NOT NULL)::integer +
+      ("file_id" IS NOT NULL)::integer +
+      ("img_id" IS NOT NULL)::integer
+    ) = 1
+  )
+);
+
+CREATE TABLE IF NOT EXISTS "chats" (
+  "id" bigserial PRIMARY KEY,
+  "msg_id" bigint NOT NULL,
+  "to_user_id" bigint NOT NULL,
+  "when_recv" timestamp
+);
+
+CREATE TABLE IF NOT EXISTS "texts" (
+  "id" bigserial PRIMARY KEY,
+  "text" text
+);
+
+-- Content-addressable storage, the same bytes are stored only once (keyed by their SHA-256 hash).
+CREATE TABLE IF NOT EXISTS "blobs" (
+  "id" bigserial PRIMARY KEY,
+  "hash" bytea NOT NULL UNIQUE,
+  "bytes" bytea NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS "files" (
+  "id" bigserial PRIMARY KEY,
+  "name" text,
+  "blob_id" bigint
+);
+
+-- TODO: img format
+CREATE TABLE IF NOT EXISTS "images" (
+  "id" bigserial PRIMARY KEY,
+  "blob_id" bigint
+);
+
+-- Tables created before the blob storage kept bytes inline, those rows stay as they are.
+ALTER TABLE "files" ADD COLUMN IF NOT EXISTS "blob_id" bigint;
+ALTER TABLE "images" ADD COLUMN IF NOT EXISTS "blob_id" bigint;
+-- Original bytes of an image re-encoded by the server.
+ALTER TABLE "images" ADD COLUMN IF NOT EXISTS "original_blob_id" bigint REFERENCES "blobs" ("id");
+
+-- What users tell about themselves, a row exists only for users who set anything.
+CREATE TABLE IF NOT EXISTS "profiles" (
+  "user_id" bigint PRIMARY KEY REFERENCES "users" ("id"),
+  "display_name" text,
+  "status" text,
+  "avatar_blob_id" bigint REFERENCES "blobs" ("id")
+);
+
+-- Foreign keys are added unless present under their default names, e.g. "messages_text_id_fkey".
+DO $$
+DECLARE
+  fkey text[];
+BEGIN
+  FOREACH fkey SLICE 1 IN ARRAY ARRAY[
+    ['messages', 'from_user_id', 'users'],
+    ['messages', 'text_id', 'texts'],
+    ['messages', 'file_id', 'files'],
+    ['messages', 'img_id', 'images'],
+    ['files', 'blob_id', 'blobs'],
+    ['images', 'blob_id', 'blobs'],
+    ['chats', 'msg_id', 'messages'],
+    ['chats', 'to_user_id', 'users']
+  ] LOOP
+    IF NOT EXISTS (SELECT 1 FROM pg_constraint WHERE conname = fkey[1] || '_' || fkey[2] || '_fkey') THEN
+      EXECUTE format('ALTER TABLE %I ADD FOREIGN KEY (%I) REFERENCES %I ("id")', fkey[1], fkey[2], fkey[3]);
+    END IF;
+  END LOOP;
+END
+$$;
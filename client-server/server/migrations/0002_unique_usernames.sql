@@ -0,0 +1,3 @@
+-- Usernames are unique by the index rather than by checking before inserting,
+-- so that sign-ups do not need to be serialized.
+CREATE UNIQUE INDEX IF NOT EXISTS "users_username_key" ON "users" ("username");
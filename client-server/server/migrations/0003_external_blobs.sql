@@ -0,0 +1,2 @@
+-- Bytes of blobs kept by an external blob store are not in the database, only their hash is.
+ALTER TABLE "blobs" ALTER COLUMN "bytes" DROP NOT NULL;
@@ -0,0 +1,3 @@
+-- Bytes of attachments each user uploaded, and their quota when it differs from the server's default.
+ALTER TABLE "users" ADD COLUMN "storage_used" BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE "users" ADD COLUMN "storage_quota" BIGINT;
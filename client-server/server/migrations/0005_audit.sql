@@ -0,0 +1,9 @@
+-- Security events, kept apart from the users so that failed log-ins of unknown users are recorded too.
+CREATE TABLE IF NOT EXISTS "audit" (
+  "id" bigserial PRIMARY KEY,
+  "at" timestamp with time zone NOT NULL DEFAULT now(),
+  "event" text NOT NULL,
+  "username" text,
+  "addr" text,
+  "detail" text
+);
@@ -0,0 +1,7 @@
+-- Subjects of identity providers, each one logs in as the user created on their first log-in.
+CREATE TABLE IF NOT EXISTS "identities" (
+  "issuer" text NOT NULL,
+  "subject" text NOT NULL,
+  "user_id" bigint NOT NULL REFERENCES "users" ("id") ON DELETE CASCADE,
+  PRIMARY KEY ("issuer", "subject")
+);
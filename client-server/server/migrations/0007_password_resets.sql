@@ -0,0 +1,9 @@
+-- Address password reset codes are sent to, used by one user at most.
+ALTER TABLE "users" ADD COLUMN "email" text;
+CREATE UNIQUE INDEX IF NOT EXISTS "users_email_key" ON "users" (lower("email"));
+-- Password reset codes waiting to be used, one per user at most, only their hashes are kept.
+CREATE TABLE IF NOT EXISTS "password_resets" (
+  "user_id" bigint PRIMARY KEY REFERENCES "users" ("id") ON DELETE CASCADE,
+  "code_hash" bytea NOT NULL UNIQUE,
+  "expires" timestamp with time zone NOT NULL
+);
@@ -0,0 +1,4 @@
+-- TOTP secrets of the users' two-factor authentication, sealed by the key of the server.
+-- The pending one waits for its first code, then it replaces the active one.
+ALTER TABLE "users" ADD COLUMN "totp_secret" bytea;
+ALTER TABLE "users" ADD COLUMN "totp_pending" bytea;
@@ -0,0 +1,2 @@
+-- Texts sent as urgent, they get through the filters of clients.
+ALTER TABLE "messages" ADD COLUMN "urgent" BOOLEAN NOT NULL DEFAULT FALSE;
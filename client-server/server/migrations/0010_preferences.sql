@@ -0,0 +1,8 @@
+-- Notification preferences of users, a row exists only for users who set them.
+CREATE TABLE IF NOT EXISTS "preferences" (
+  "user_id" bigint PRIMARY KEY REFERENCES "users" ("id") ON DELETE CASCADE,
+  "muted" text[] NOT NULL DEFAULT '{}',
+  "patterns" text[] NOT NULL DEFAULT '{}',
+  "mentions_only" boolean NOT NULL DEFAULT FALSE,
+  "history_length" integer
+);
@@ -0,0 +1,8 @@
+-- Invitations to sign up while the server allows it by invitation only, only hashes of the codes are kept.
+CREATE TABLE IF NOT EXISTS "invites" (
+  "code_hash" bytea PRIMARY KEY,
+  "created_by" bigint REFERENCES "users" ("id") ON DELETE SET NULL,
+  "created" timestamp with time zone NOT NULL DEFAULT now(),
+  "uses_left" integer NOT NULL CHECK ("uses_left" >= 0),
+  "expires" timestamp with time zone
+);
@@ -0,0 +1,216 @@
+//! Admission of incoming connections, checked right after they are accepted, see [AccessPolicy].
+use std::{
+    collections::HashMap,
+    fmt,
+    net::IpAddr,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use cli_ser::ser::Refusal;
+
+/// Network given as `address/prefix`, e.g. "10.0.0.0/8", a lone address stands for itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+impl Cidr {
+    /// Whether the address belongs to the network.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let (net, ip, bits) = match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => (u32::from(net).into(), u32::from(ip).into(), 32),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => (u128::from(net), u128::from(ip), 128),
+            _ => return false,
+        };
+        let host_bits = bits - u32::from(self.prefix);
+        (net ^ ip).checked_shr(host_bits).unwrap_or(0) == 0
+    }
+}
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = s.split_once('/').unwrap_or((s, ""));
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("\"{s}\" does not start with an IP address"))?;
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            "" => bits,
+            prefix => match prefix.parse() {
+                Ok(prefix) if prefix <= bits => prefix,
+                _ => return Err(format!("\"{s}\" has no prefix length from 0 to {bits}")),
+            },
+        };
+        Ok(Cidr { addr, prefix })
+    }
+}
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Who may connect and how many connections are allowed, nothing is limited by default.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccessPolicy {
+    /// Connections open at once.
+    pub max_connections: Option<usize>,
+    /// Connections open at once from a single IP address.
+    pub max_per_ip: Option<usize>,
+    /// When not empty, only addresses from these networks are let in.
+    pub allow: Vec<Cidr>,
+    /// Addresses from these networks are refused, even when allowed.
+    pub deny: Vec<Cidr>,
+}
+
+/// What happens when a user logs in while they already have a session,
+/// the existing sessions are told about the new one by [NewSession][cli_ser::ser::Msg::NewSession].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SessionPolicy {
+    /// Every session stays.
+    #[default]
+    Allow,
+    /// The existing sessions are disconnected.
+    KickOld,
+    /// The log-in fails as [SessionActive][cli_ser::ser::Error::SessionActive].
+    RejectNew,
+}
+impl FromStr for SessionPolicy {
+    type Err = String;
+
+    /// Parses "allow", "kick-old" or "reject-new".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(SessionPolicy::Allow),
+            "kick-old" => Ok(SessionPolicy::KickOld),
+            "reject-new" => Ok(SessionPolicy::RejectNew),
+            _ => Err(format!("\"{s}\" is not allow, kick-old nor reject-new")),
+        }
+    }
+}
+
+/// Counts open connections and admits new ones according to the [AccessPolicy].
+#[derive(Debug)]
+pub(crate) struct Gate {
+    policy: AccessPolicy,
+    open: Mutex<Open>,
+}
+#[derive(Debug, Default)]
+struct Open {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+impl Gate {
+    pub(crate) fn new(policy: AccessPolicy) -> Self {
+        Gate {
+            policy,
+            open: Mutex::default(),
+        }
+    }
+
+    /// Admits a connection from the address, it counts as open until the [Admission] is dropped.
+    pub(crate) fn admit(self: &Arc<Self>, ip: IpAddr) -> Result<Admission, Refusal> {
+        let policy = &self.policy;
+        let allowed = policy.allow.is_empty() || policy.allow.iter().any(|net| net.contains(ip));
+        if !allowed || policy.deny.iter().any(|net| net.contains(ip)) {
+            return Err(Refusal::AddressNotAllowed);
+        }
+        let mut open = self.open.lock().expect("lock poisoned");
+        if policy.max_connections.is_some_and(|max| open.total >= max) {
+            return Err(Refusal::TooManyConnections);
+        }
+        let from_ip = open.per_ip.get(&ip).copied().unwrap_or(0);
+        if policy.max_per_ip.is_some_and(|max| from_ip >= max) {
+            return Err(Refusal::TooManyFromAddress);
+        }
+        open.total += 1;
+        open.per_ip.insert(ip, from_ip + 1);
+        Ok(Admission {
+            gate: self.clone(),
+            ip,
+        })
+    }
+}
+
+/// An admitted connection, released on drop.
+#[derive(Debug)]
+pub(crate) struct Admission {
+    gate: Arc<Gate>,
+    ip: IpAddr,
+}
+impl Drop for Admission {
+    fn drop(&mut self) {
+        let mut open = self.gate.open.lock().expect("lock poisoned");
+        open.total -= 1;
+        if let Some(count) = open.per_ip.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                open.per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_session_policy() {
+        assert_eq!("allow".parse(), Ok(SessionPolicy::Allow));
+        assert_eq!("kick-old".parse(), Ok(SessionPolicy::KickOld));
+        assert_eq!("reject-new".parse(), Ok(SessionPolicy::RejectNew));
+        assert!("kick".parse::<SessionPolicy>().is_err());
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn cidr_contains() {
+        let net: Cidr = "10.1.0.0/16".parse().unwrap();
+        assert!(net.contains(ip("10.1.255.3")));
+        assert!(!net.contains(ip("10.2.0.1")));
+        assert!(!net.contains(ip("::1")));
+        assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains(ip("1.2.3.4")));
+        let single: Cidr = "::1".parse().unwrap();
+        assert_eq!(single.to_string(), "::1/128");
+        assert!(single.contains(ip("::1")));
+        assert!("fd00::/8".parse::<Cidr>().unwrap().contains(ip("fd12::7")));
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("localhost/8".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn gate_limits() {
+        let gate = Arc::new(Gate::new(AccessPolicy {
+            max_connections: Some(2),
+            max_per_ip: Some(1),
+            allow: vec!["10.0.0.0/8".parse().unwrap()],
+            deny: vec!["10.0.0.66".parse().unwrap()],
+        }));
+        let first = gate.admit(ip("10.0.0.1")).unwrap();
+        assert_eq!(
+            gate.admit(ip("10.0.0.1")).unwrap_err(),
+            Refusal::TooManyFromAddress
+        );
+        let _second = gate.admit(ip("10.0.0.2")).unwrap();
+        assert_eq!(
+            gate.admit(ip("10.0.0.3")).unwrap_err(),
+            Refusal::TooManyConnections
+        );
+        drop(first);
+        assert!(gate.admit(ip("10.0.0.1")).is_ok());
+        assert_eq!(
+            gate.admit(ip("10.0.0.66")).unwrap_err(),
+            Refusal::AddressNotAllowed
+        );
+        assert_eq!(
+            gate.admit(ip("192.168.0.1")).unwrap_err(),
+            Refusal::AddressNotAllowed
+        );
+    }
+}
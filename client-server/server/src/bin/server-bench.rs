@@ -0,0 +1,192 @@
+//! Load generator, N clients send M messages per second each to a running server,
+//! the latency of the broadcasts is measured by the other clients and reported as percentiles.
+//!
+//! ```sh
+//! cargo run --release --bin server-bench -- --clients 50 --rate 20 --duration 30
+//! ```
+//! The clients sign up as new users named by the `--prefix` and the time of the run.
+use std::{
+    net::SocketAddr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use clap::Parser;
+use tokio::{
+    task::JoinSet,
+    time::{self, Instant},
+};
+
+use cli_ser::{
+    cli::{self, Credentials},
+    conn::{Reader, Writer},
+    ser, Connection, Data, MsgId,
+};
+
+/// Texts of the benchmark start with it, other messages are not measured.
+const MARKER: &str = "#bench";
+
+/// Load generator of the chat server.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Address of the running server
+    #[arg(long, default_value = "127.0.0.1:11111")]
+    addr: SocketAddr,
+
+    /// Clients connected at once
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    clients: usize,
+
+    /// Messages each client sends per second
+    #[arg(long, value_name = "M", default_value_t = 10.0)]
+    rate: f64,
+
+    /// Seconds the clients send for
+    #[arg(long, value_name = "SECS", default_value_t = 10)]
+    duration: u64,
+
+    /// Bytes of each text
+    #[arg(long, value_name = "BYTES", default_value_t = 64)]
+    size: usize,
+
+    /// Seconds the clients wait for late broadcasts after the sending stops
+    #[arg(long, value_name = "SECS", default_value_t = 2)]
+    drain: u64,
+
+    /// Beginning of the names of the users signed up
+    #[arg(long, default_value = "bench")]
+    prefix: String,
+}
+
+/// What a client saw.
+#[derive(Debug, Default)]
+struct Report {
+    sent: usize,
+    rejected: usize,
+    /// From sending a text to receiving its broadcast, in microseconds.
+    latencies: Vec<u64>,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    anyhow::ensure!(args.rate > 0.0, "The rate must be positive");
+    let run = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let mut connections = Vec::with_capacity(args.clients);
+    for client in 0..args.clients {
+        let creds = Credentials {
+            user: format!("{}_{run}_{client}", args.prefix).into(),
+            password: format!("{}_{run}", args.prefix),
+        };
+        let connection = Connection::sign_up(args.addr, creds)
+            .await
+            .with_context(|| format!("Signing up client {client} at {} failed", args.addr))?;
+        connections.push(connection);
+    }
+    println!(
+        "{} clients connected, each sends {} messages per second of {} bytes for {} s",
+        args.clients, args.rate, args.size, args.duration
+    );
+
+    let start = Instant::now();
+    let stop = start + Duration::from_secs(args.duration);
+    let deadline = stop + Duration::from_secs(args.drain);
+    let mut clients = JoinSet::new();
+    for connection in connections {
+        let (reader, writer) = connection.split();
+        let (size, rate) = (args.size, args.rate);
+        clients.spawn(async move {
+            let sending = tokio::spawn(send(writer, start, stop, rate, size));
+            let mut report = receive(reader, start, deadline).await;
+            report.sent = sending.await??;
+            anyhow::Ok(report)
+        });
+    }
+    let mut total = Report::default();
+    while let Some(report) = clients.join_next().await {
+        let report = report??;
+        total.sent += report.sent;
+        total.rejected += report.rejected;
+        total.latencies.extend(report.latencies);
+    }
+    print_report(&args, total);
+    Ok(())
+}
+
+/// Sends tagged texts at the rate until the `stop`, returns how many were sent.
+async fn send(
+    mut writer: Writer,
+    start: Instant,
+    stop: Instant,
+    rate: f64,
+    size: usize,
+) -> anyhow::Result<usize> {
+    let mut ticks = time::interval(Duration::from_secs_f64(1.0 / rate));
+    let mut sent = 0;
+    while ticks.tick().await < stop {
+        let micros = start.elapsed().as_micros();
+        let mut text = format!("{MARKER} {micros} ");
+        text.extend(std::iter::repeat('x').take(size.saturating_sub(text.len())));
+        sent += 1;
+        let msg = cli::Msg::ToAll(Data::Text(text)).tagged(MsgId(sent as u64));
+        writer.send_msg(msg).await?;
+    }
+    Ok(sent)
+}
+
+/// Measures the broadcasts of the benchmark received until the `deadline`.
+async fn receive(mut reader: Reader, start: Instant, deadline: Instant) -> Report {
+    let mut report = Report::default();
+    while let Ok(received) = time::timeout_at(deadline, reader.recv()).await {
+        match received {
+            Ok(ser::Msg::DataFrom {
+                data: Data::Text(text),
+                ..
+            }) => {
+                let sent = text
+                    .strip_prefix(MARKER)
+                    .and_then(|rest| rest.split_whitespace().next())
+                    .and_then(|micros| micros.parse::<u64>().ok());
+                if let Some(sent) = sent {
+                    let now = start.elapsed().as_micros() as u64;
+                    report.latencies.push(now.saturating_sub(sent));
+                }
+            }
+            Ok(ser::Msg::Rejected(..) | ser::Msg::Error(_)) => report.rejected += 1,
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("Receiving failed! Error {e}");
+                break;
+            }
+        }
+    }
+    report
+}
+
+fn print_report(args: &Args, mut total: Report) {
+    let expected = total.sent * args.clients.saturating_sub(1);
+    let received = total.latencies.len();
+    println!(
+        "sent {} messages, {} rejected, {received} of {expected} broadcasts received",
+        total.sent, total.rejected
+    );
+    println!(
+        "throughput {:.0} broadcasts per second",
+        received as f64 / args.duration as f64
+    );
+    if received == 0 {
+        return;
+    }
+    total.latencies.sort_unstable();
+    for p in [50.0, 90.0, 99.0, 99.9, 100.0] {
+        let micros = percentile(&total.latencies, p);
+        println!("p{p:<5} {:>10.3} ms", micros as f64 / 1000.0);
+    }
+}
+
+/// Value of the sorted non-empty `values` which the `p` percent of them do not exceed.
+fn percentile(values: &[u64], p: f64) -> u64 {
+    let rank = (p / 100.0 * values.len() as f64).ceil() as usize;
+    values[rank.clamp(1, values.len()) - 1]
+}
@@ -0,0 +1,156 @@
+//! Where the bytes of attachments are kept, see [BlobStore].
+use std::{
+    fmt::{self, Display, Write},
+    io,
+    path::PathBuf,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use tokio::fs;
+
+/// Keeps the bytes of files, images and avatars, the database always keeps their hash.
+///
+/// Blobs stored before switching the store stay where they are and are still found.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum BlobStore {
+    /// Bytes are stored inline in the `blobs` table.
+    #[default]
+    Database,
+    /// Bytes are stored in files named by their SHA-256 hash under the directory,
+    /// e.g. `<dir>/3a/3a7bd3e2...`.
+    LocalDirectory(PathBuf),
+}
+impl BlobStore {
+    /// Keeps the bytes with the hash, returns them when they belong inline in the database.
+    pub(crate) async fn put<'a>(
+        &self,
+        hash: &[u8],
+        bytes: &'a [u8],
+    ) -> io::Result<Option<&'a [u8]>> {
+        let BlobStore::LocalDirectory(dir) = self else {
+            return Ok(Some(bytes));
+        };
+        let path = dir.join(path(hash));
+        if fs::try_exists(&path).await? {
+            return Ok(None);
+        }
+        fs::create_dir_all(path.parent().expect("the path has the prefix directory")).await?;
+        // Written aside and renamed, so that a blob is never seen half written.
+        static TEMPORARY: AtomicU64 = AtomicU64::new(0);
+        let temporary = path.with_extension(format!(
+            "{}-{}.tmp",
+            std::process::id(),
+            TEMPORARY.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&temporary, bytes).await?;
+        fs::rename(&temporary, &path).await?;
+        Ok(None)
+    }
+
+    /// Removes the bytes kept by the store under the hash, inline ones go with their row.
+    pub(crate) async fn remove(&self, hash: &[u8]) -> io::Result<()> {
+        let BlobStore::LocalDirectory(dir) = self else {
+            return Ok(());
+        };
+        match fs::remove_file(dir.join(path(hash))).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the `inline` bytes, or the bytes kept by the store under the hash.
+    pub(crate) async fn get(&self, hash: &[u8], inline: Option<Vec<u8>>) -> io::Result<Vec<u8>> {
+        match (inline, self) {
+            (Some(bytes), _) => Ok(bytes),
+            (None, BlobStore::LocalDirectory(dir)) => fs::read(dir.join(path(hash))).await,
+            (None, BlobStore::Database) => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "the blob is kept outside of the database, configure its blob store",
+            )),
+        }
+    }
+}
+
+/// Relative path of the blob, a directory by the first byte keeps directories small.
+fn path(hash: &[u8]) -> PathBuf {
+    let hex = hash.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    });
+    [&hex[..2], &hex].iter().collect()
+}
+
+/// Parses "database" or "local:<dir>".
+impl FromStr for BlobStore {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "database" => Ok(BlobStore::Database),
+            Some(("local", dir)) if !dir.is_empty() => Ok(BlobStore::LocalDirectory(dir.into())),
+            _ => Err(format!(
+                "unknown blob store `{s}`, expected `database` or `local:<dir>`"
+            )),
+        }
+    }
+}
+impl Display for BlobStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlobStore::Database => write!(f, "database"),
+            BlobStore::LocalDirectory(dir) => write!(f, "local:{}", dir.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("database".parse(), Ok(BlobStore::Database));
+        assert_eq!(
+            "local:/var/lib/chat".parse(),
+            Ok(BlobStore::LocalDirectory("/var/lib/chat".into()))
+        );
+        assert!("local:".parse::<BlobStore>().is_err());
+        assert!("s3".parse::<BlobStore>().is_err());
+        let store = BlobStore::LocalDirectory("blobs".into());
+        assert_eq!(store.to_string().parse(), Ok(store));
+    }
+
+    #[test]
+    fn test_path() {
+        assert_eq!(path(&[0x3a, 0x7b, 0x0d]), PathBuf::from("3a/3a7b0d"));
+    }
+
+    #[tokio::test]
+    async fn test_local_directory() {
+        let dir = std::env::temp_dir().join(format!("blobs_test_{}", std::process::id()));
+        let store = BlobStore::LocalDirectory(dir.clone());
+        let hash = [1, 2, 3];
+        assert_eq!(store.put(&hash, b"bytes").await.unwrap(), None);
+        assert_eq!(store.put(&hash, b"bytes").await.unwrap(), None);
+        assert_eq!(store.get(&hash, None).await.unwrap(), b"bytes");
+        assert_eq!(
+            store.get(&hash, Some(b"inline".to_vec())).await.unwrap(),
+            b"inline"
+        );
+        assert!(store.get(&[4], None).await.is_err());
+        store.remove(&hash).await.unwrap();
+        assert!(store.get(&hash, None).await.is_err());
+        store.remove(&hash).await.unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_database() {
+        let store = BlobStore::Database;
+        assert_eq!(
+            store.put(&[1], b"bytes").await.unwrap(),
+            Some(&b"bytes"[..])
+        );
+        assert!(store.get(&[1], None).await.is_err());
+    }
+}
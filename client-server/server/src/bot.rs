@@ -0,0 +1,165 @@
+//! Control socket for bots, the protocol is in [cli_ser::bot].
+//!
+//! Only the owner of the server process may connect, bots are trusted: their data is neither filtered nor stored.
+use cli_ser::bot::Event;
+use dashmap::DashMap;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+/// Events waiting for a bot, a slow bot misses events rather than holding up the clients.
+const EVENTS_QUEUE: usize = 256;
+
+/// Event channels of subscribed bots by their connection number.
+pub(crate) type Subscribers = DashMap<u64, mpsc::Sender<Event>>;
+
+/// Sends the event to every subscribed bot.
+pub(crate) fn notify(subscribers: &Subscribers, event: Event) {
+    for bot in subscribers.iter() {
+        if let Err(e) = bot.try_send(event.clone()) {
+            warn!("Bot {} misses an event! Error {e}", bot.key());
+        }
+    }
+}
+
+#[cfg(unix)]
+pub(crate) use unix::{bind, listen};
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        fs,
+        os::unix::fs::{FileTypeExt, PermissionsExt},
+        path::Path,
+    };
+
+    use anyhow::Context;
+    use cli_ser::{bot::Action, ser, Codec, Data, Error::DisconnectedStream, Messageable, User};
+    use tokio::net::{unix::OwnedReadHalf, UnixListener, UnixStream};
+    use tracing::{error, info, info_span, Instrument, Span};
+
+    use super::*;
+    use crate::{queue, sessions_of, Shared, Task::*, READ_TIMEOUT};
+
+    /// Binds the socket at the `path`, a socket left there by a previous run is replaced.
+    pub(crate) fn bind(path: &Path) -> anyhow::Result<UnixListener> {
+        if fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+            fs::remove_file(path)
+                .with_context(|| format!("Removing the old bot socket {path:?} failed"))?;
+        }
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("Binding the bot socket {path:?} failed"))?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+            .with_context(|| format!("Restricting the bot socket {path:?} failed"))?;
+        info!("Bots are accepted at {path:?}");
+        Ok(listener)
+    }
+
+    /// Accepts bots, each one is served inside a `bot` span.
+    pub(crate) async fn listen(listener: UnixListener, shared: Shared) -> anyhow::Result<()> {
+        let mut conn = 0_u64;
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    conn += 1;
+                    let span = info_span!("bot", id = conn);
+                    tokio::spawn(serve(conn, stream, shared.clone()).instrument(span));
+                }
+                Err(e) => error!("incoming bot error: {e:?}"),
+            }
+        }
+    }
+
+    /// Carries out the bot's actions until it disconnects.
+    async fn serve(conn: u64, stream: UnixStream, shared: Shared) {
+        let (mut reader, mut writer) = stream.into_split();
+        let (events, mut queued) = mpsc::channel::<Event>(EVENTS_QUEUE);
+        let writing = tokio::spawn(
+            async move {
+                while let Some(event) = queued.recv().await {
+                    if let Err(e) = event.send(&mut writer).await {
+                        warn!("Sending an event to the bot failed! Error {e}");
+                        break;
+                    }
+                }
+            }
+            .in_current_span(),
+        );
+        if let Err(e) = handle_actions(conn, &mut reader, &events, &shared).await {
+            error!("Serving the bot failed! Error {e:#}");
+        }
+        shared.bots.remove(&conn);
+        drop(events);
+        let _ = writing.await;
+        info!("bot disconnected");
+    }
+
+    async fn handle_actions(
+        conn: u64,
+        reader: &mut OwnedReadHalf,
+        events: &mpsc::Sender<Event>,
+        shared: &Shared,
+    ) -> anyhow::Result<()> {
+        let mut name: Option<User> = None;
+        loop {
+            let reserve = |len| shared.budget.reserve(len);
+            let (reservation, action) = match Action::receive_reserving(
+                Codec::Bincode,
+                reader,
+                reserve,
+                READ_TIMEOUT,
+            )
+            .await
+            {
+                Ok((reservation, _, Ok(action))) => (reservation, action),
+                Err(DisconnectedStream(_)) => break Ok(()),
+                Ok((_, _, Err(e))) | Err(e) => Err(e).context("Reading the bot's action failed")?,
+            };
+            let done = match (action, &name) {
+                (Action::Hello(hello), None) => {
+                    info!("bot \"{hello}\" said hello");
+                    name = Some(User::from(hello));
+                    Ok(())
+                }
+                (Action::Hello(_), Some(_)) => Err("the bot already said hello".to_string()),
+                (_, None) => Err("say hello first".to_string()),
+                (Action::Subscribe, Some(_)) => {
+                    shared.bots.insert(conn, events.clone());
+                    Ok(())
+                }
+                (Action::Broadcast(data), Some(name)) => {
+                    let msg = data_from(name, data);
+                    let broadcast = Broadcast(None, msg, reservation, Span::current());
+                    queue(&shared.tasks, broadcast).await?;
+                    Ok(())
+                }
+                (Action::Whisper { to, data }, Some(name)) => {
+                    let ids = sessions_of(&shared.sessions, &to);
+                    if ids.is_empty() {
+                        Err(format!("{to} is not connected"))
+                    } else {
+                        for id in ids {
+                            queue(&shared.tasks, Reply(id, data_from(name, data.clone()))).await?;
+                        }
+                        Ok(())
+                    }
+                }
+            };
+            if let Err(e) = done {
+                events.send(Event::Error(e)).await?;
+            }
+        }
+    }
+
+    /// Data sent by the bot as the clients see it.
+    fn data_from(bot: &User, data: Data) -> ser::Msg {
+        ser::Msg::DataFrom {
+            data,
+            from: bot.clone(),
+            msg_id: None,
+            display_name: None,
+            mentions: vec![],
+            guest: false,
+            urgent: false,
+        }
+    }
+}
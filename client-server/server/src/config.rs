@@ -0,0 +1,334 @@
+//! Server configuration file, see [ConfigFile].
+use std::{fs, net::SocketAddr, path::Path, path::PathBuf};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+/// TOML configuration of the server, every value is optional, e.g.
+/// ```toml
+/// host = "0.0.0.0"
+/// port = 11111
+/// # Replaces host and port when given.
+/// listen = ["0.0.0.0:11111", "[::]:11111"]
+/// database_url = "postgres://postgres:pp@localhost:5432/postgres"
+/// max_inflight_bytes = 268435456
+/// motd = "Welcome!"
+/// storage_quota = 1073741824
+/// totp_key = "<32 bytes in base64>"
+/// bot_socket = "/run/chat/bots.sock"
+///
+/// [tls]
+/// cert = "tls/cert.pem"
+/// key = "tls/key.pem"
+///
+/// [rate_limit]
+/// max = 100
+/// period = 60
+///
+/// [database]
+/// max_connections = 10
+/// acquire_timeout = 30
+/// statement_timeout = 60
+/// connect_retries = 5
+/// retry_delay = 1
+/// batch_size = 64
+/// flush_interval_ms = 100
+/// blob_store = "local:/var/lib/chat/blobs"
+///
+/// [passwords]
+/// memory_kib = 65536
+/// iterations = 3
+/// parallelism = 4
+///
+/// [images]
+/// reencode_over = 1048576
+/// format = "jpeg:80"
+/// keep_original = true
+/// max_bytes = 33554432
+/// max_dimensions = "16384x16384"
+/// max_pixels = 67108864
+///
+/// [filters]
+/// max_text_length = 4000
+/// banned_words = "banned.txt"
+/// allowed_attachments = ["png", "jpeg", "pdf"]
+/// max_urgent = 3
+/// urgent_period = 600
+///
+/// [moderation]
+/// url = "http://localhost:8000/review"
+/// timeout_ms = 1000
+/// on_failure = "closed"
+/// cache_size = 10000
+///
+/// [access]
+/// max_connections = 1000
+/// max_per_ip = 10
+/// allow = ["10.0.0.0/8", "::1"]
+/// deny = ["10.0.0.66"]
+/// sessions_of_user = "kick-old"
+/// allow_guests = true
+/// invite_only = false
+/// signup_difficulty = 20
+/// distinct_login_errors = false
+///
+/// [retention]
+/// max_age_days = 30
+/// max_messages = 100000
+/// interval = 3600
+/// dry_run = false
+///
+/// [oidc]
+/// issuer = "https://accounts.example.com"
+/// audience = "chat"
+/// jwks = "https://accounts.example.com/keys"
+///
+/// [http]
+/// port = 8080
+/// token = "secret"
+///
+/// [log]
+/// dir = "logs"
+/// rotation = "daily"
+/// keep = 14
+/// level = "info,server::db=debug"
+/// ```
+/// Command line arguments take precedence over environment variables,
+/// those over the file and the file over the defaults.
+/// The message of the day, the `[filters]` and the log level are read again on `SIGHUP`,
+/// see [Reloadable][crate::Reloadable].
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub listen: Option<Vec<SocketAddr>>,
+    pub database_url: Option<String>,
+    pub max_inflight_bytes: Option<usize>,
+    pub motd: Option<String>,
+    pub motd_file: Option<PathBuf>,
+    pub storage_quota: Option<u64>,
+    pub bot_socket: Option<PathBuf>,
+    /// Key of the two-factor secrets as in `--totp-key`, 32 bytes in base64.
+    pub totp_key: Option<String>,
+    #[serde(default)]
+    pub tls: TlsConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub database: DatabaseConfig,
+    #[serde(default)]
+    pub passwords: PasswordsConfig,
+    #[serde(default)]
+    pub images: ImagesConfig,
+    #[serde(default)]
+    pub filters: FiltersConfig,
+    #[serde(default)]
+    pub moderation: ModerationConfig,
+    #[serde(default)]
+    pub access: AccessConfig,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    #[serde(default)]
+    pub oidc: OidcConfig,
+    #[serde(default)]
+    pub http: HttpConfig,
+    #[serde(default)]
+    pub log: LogConfig,
+}
+impl ConfigFile {
+    /// Reads and parses the file.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Reading the configuration {path:?} failed"))?;
+        toml::from_str(&content).with_context(|| format!("Configuration {path:?} is malformed"))
+    }
+}
+
+/// Certificate chain and key of the TLS connections, PEM files, see [Server::tls][crate::Server::tls].
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+}
+
+/// Messages each user may send per `period` seconds, see [RateLimit][crate::RateLimit].
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub max: Option<usize>,
+    pub period: Option<u64>,
+}
+
+/// Database connection, see [DatabaseOptions][crate::DatabaseOptions], times are in seconds.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub max_connections: Option<u32>,
+    pub acquire_timeout: Option<u64>,
+    pub statement_timeout: Option<u64>,
+    pub connect_retries: Option<u32>,
+    pub retry_delay: Option<u64>,
+    pub batch_size: Option<usize>,
+    pub flush_interval_ms: Option<u64>,
+    /// Store as in `--blob-store`, e.g. "local:/var/lib/chat/blobs".
+    pub blob_store: Option<String>,
+}
+
+/// Cost of the password hashes, see [PasswordHashing][crate::PasswordHashing].
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PasswordsConfig {
+    pub memory_kib: Option<u32>,
+    pub iterations: Option<u32>,
+    pub parallelism: Option<u32>,
+}
+
+/// Re-encoding of images, see [ImagePolicy][crate::ImagePolicy], and their [limits][cli_ser::ImageLimits].
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImagesConfig {
+    pub reencode_over: Option<usize>,
+    /// Format as in `--reencode-format`, e.g. "jpeg:80".
+    pub format: Option<String>,
+    pub keep_original: Option<bool>,
+    pub max_bytes: Option<usize>,
+    /// Dimensions as in `--max-image-dimensions`, e.g. "8192x8192".
+    pub max_dimensions: Option<String>,
+    pub max_pixels: Option<u64>,
+}
+
+/// Built-in [filters][crate::filter] of incoming data.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FiltersConfig {
+    pub max_text_length: Option<usize>,
+    /// File with one banned word per line.
+    pub banned_words: Option<PathBuf>,
+    pub allowed_attachments: Option<Vec<String>>,
+    /// Urgent texts each user may send per `urgent_period` seconds, see [UrgentLimit][crate::UrgentLimit].
+    pub max_urgent: Option<usize>,
+    pub urgent_period: Option<u64>,
+}
+
+/// Review of texts by a moderation service, see [ModerationPolicy][crate::ModerationPolicy].
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModerationConfig {
+    pub url: Option<String>,
+    pub timeout_ms: Option<u64>,
+    /// Policy as in `--moderation-on-failure`, "open" or "closed".
+    pub on_failure: Option<String>,
+    pub cache_size: Option<usize>,
+}
+
+/// Connection limits, see [AccessPolicy][crate::AccessPolicy].
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccessConfig {
+    pub max_connections: Option<usize>,
+    pub max_per_ip: Option<usize>,
+    /// Networks as in `--allow`, e.g. "10.0.0.0/8".
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+    /// Policy as in `--sessions-of-user`, e.g. "reject-new".
+    pub sessions_of_user: Option<String>,
+    pub allow_guests: Option<bool>,
+    /// Whether signing up needs an invitation, as `--invite-only`.
+    pub invite_only: Option<bool>,
+    /// Bits of the proof of work asked of clients signing up, as `--signup-difficulty`, 0 is off.
+    pub signup_difficulty: Option<u8>,
+    /// Whether clients are told unknown users from wrong passwords, as `--distinct-login-errors`.
+    pub distinct_login_errors: Option<bool>,
+}
+
+/// Deletion of old messages, see [RetentionPolicy][crate::RetentionPolicy], the interval is in seconds.
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionConfig {
+    pub max_age_days: Option<u64>,
+    pub max_messages: Option<u64>,
+    pub interval: Option<u64>,
+    pub dry_run: Option<bool>,
+}
+
+/// Identity provider whose tokens log users in, see [Oidc][crate::Oidc].
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OidcConfig {
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+    /// URL or path of the keys, discovered from the issuer when not given.
+    pub jwks: Option<String>,
+}
+
+/// HTTP API, see [Server::http][crate::Server::http].
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpConfig {
+    pub port: Option<u16>,
+    pub token: Option<String>,
+}
+
+/// Log files, see [Logs][crate::Logs].
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LogConfig {
+    pub dir: Option<PathBuf>,
+    /// Rotation as in `--log-rotation`, e.g. "daily".
+    pub rotation: Option<String>,
+    pub keep: Option<usize>,
+    /// Filter of the standard output as in `--log-level`, e.g. "info,server::db=debug".
+    pub level: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_config_file() {
+        let config: ConfigFile = toml::from_str(
+            r#"
+            port = 12345
+            listen = ["127.0.0.1:12345", "[::1]:12345"]
+            database_url = "postgres://localhost/chat"
+
+            [tls]
+            cert = "tls/cert.pem"
+            key = "tls/key.pem"
+
+            [log]
+            rotation = "hourly"
+            keep = 3
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            ConfigFile {
+                port: Some(12345),
+                listen: Some(vec![
+                    "127.0.0.1:12345".parse().unwrap(),
+                    "[::1]:12345".parse().unwrap()
+                ]),
+                database_url: Some("postgres://localhost/chat".to_string()),
+                tls: TlsConfig {
+                    cert: Some(PathBuf::from("tls/cert.pem")),
+                    key: Some(PathBuf::from("tls/key.pem")),
+                },
+                log: LogConfig {
+                    rotation: Some("hourly".to_string()),
+                    keep: Some(3),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }
+        );
+        assert!(toml::from_str::<ConfigFile>("prot = 1").is_err());
+    }
+}
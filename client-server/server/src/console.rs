@@ -0,0 +1,318 @@
+//! Administration from the server's own terminal, one [Command] per line of the standard input.
+use std::{collections::HashSet, net::SocketAddr, str::FromStr, time::Duration};
+
+use cli_ser::{cli, ser, Presence};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use tracing_subscriber::filter::Targets;
+
+use crate::{
+    administer, audit, db::AuditEvent, parse_level, queue, reload, Shared, Task::Announce,
+};
+
+/// Entries printed by `audit` without a number.
+const AUDIT_DEFAULT: i64 = 20;
+
+/// Help printed by the `help` command.
+const HELP: &str = "\
+list              - connected clients, their presence and traffic
+kick <USER|ADDR>  - disconnects all sessions of the user or the one at the address
+notice <TEXT>     - sends the text to every client
+motd <TEXT>       - sets the message of the day and announces it
+quota <USER> <BYTES|default> - sets the storage quota of the user
+invite [USES] [DAYS] - prints a code signing up USES (default 1) users, valid for DAYS or forever
+audit [N]         - the last N (default 20) entries of the audit log
+stats             - clients, in-flight memory, uptime, messages today and deleted messages
+reload            - reads the configuration again, e.g. the banned words, as SIGHUP does
+log [FILTER]      - shows or sets what is logged to the output, e.g. info,server::db=debug
+shutdown          - stops the server
+help              - this help";
+
+/// Console command.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Command {
+    List,
+    /// Username or address of the session.
+    Kick(String),
+    /// Server information sent to every client.
+    Notice(String),
+    /// Same commands as remote administrators have.
+    Admin(cli::Admin),
+    /// Number of the newest audit log entries to print.
+    Audit(i64),
+    Stats,
+    Reload,
+    /// Filter of the log level to put in place, the current one is printed when `None`.
+    Log(Option<Targets>),
+    Shutdown,
+    Help,
+}
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let line = line.trim();
+        let (cmd, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let rest = rest.trim();
+        match (cmd, rest.is_empty()) {
+            ("list", true) => Ok(Command::List),
+            ("kick", false) => Ok(Command::Kick(rest.to_string())),
+            ("notice", false) => Ok(Command::Notice(rest.to_string())),
+            ("motd", false) => Ok(Command::Admin(cli::Admin::SetMotd(rest.to_string()))),
+            ("quota", false) => {
+                let (user, bytes) = rest
+                    .split_once(' ')
+                    .ok_or("\"quota\" needs a user and bytes or \"default\"")?;
+                let bytes = match bytes.trim() {
+                    "default" => None,
+                    bytes => Some(
+                        bytes
+                            .parse()
+                            .map_err(|_| format!("\"{bytes}\" is not a number of bytes"))?,
+                    ),
+                };
+                Ok(Command::Admin(cli::Admin::SetQuota {
+                    user: user.to_string().into(),
+                    bytes,
+                }))
+            }
+            ("invite", _) => {
+                let mut numbers = rest.split_whitespace().map(|n| {
+                    n.parse::<u32>()
+                        .map_err(|_| format!("\"{n}\" is not a number"))
+                });
+                let uses = numbers.next().transpose()?.unwrap_or(1);
+                let days = numbers.next().transpose()?;
+                if numbers.next().is_some() {
+                    return Err("\"invite\" takes the uses and days at most".to_string());
+                }
+                let valid_for =
+                    days.map(|days| Duration::from_secs(u64::from(days) * 24 * 60 * 60));
+                Ok(Command::Admin(cli::Admin::CreateInvite { uses, valid_for }))
+            }
+            ("audit", true) => Ok(Command::Audit(AUDIT_DEFAULT)),
+            ("audit", false) => rest
+                .parse()
+                .map(Command::Audit)
+                .map_err(|_| format!("\"{rest}\" is not a number of entries")),
+            ("stats", true) => Ok(Command::Stats),
+            ("reload", true) => Ok(Command::Reload),
+            ("log", true) => Ok(Command::Log(None)),
+            ("log", false) => parse_level(rest).map(|filter| Command::Log(Some(filter))),
+            ("shutdown", true) => Ok(Command::Shutdown),
+            ("help", true) => Ok(Command::Help),
+            ("list" | "stats" | "reload" | "shutdown" | "help", false) => {
+                Err(format!("\"{cmd}\" takes no arguments"))
+            }
+            ("kick" | "notice" | "motd" | "quota", true) => {
+                Err(format!("\"{cmd}\" needs an argument"))
+            }
+            _ => Err(format!("unknown command \"{cmd}\", try \"help\"")),
+        }
+    }
+}
+
+/// Executes commands from the standard input, returns when a shutdown is requested.
+///
+/// When the standard input is closed, the console stops but the server keeps running.
+pub(crate) async fn run(shared: Shared) {
+    let (sender, mut lines) = mpsc::channel(16);
+    // Blocking read of stdin can not be cancelled, a detached thread does not hold up the shutdown.
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines() {
+            let Ok(line) = line else { break };
+            if sender.blocking_send(line).is_err() {
+                break;
+            }
+        }
+    });
+    while let Some(line) = lines.recv().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match line.parse() {
+            Ok(Command::Shutdown) => return,
+            Ok(cmd) => execute(cmd, &shared).await,
+            Err(e) => println!("{e}"),
+        }
+    }
+    info!("Console input closed, the server runs on.");
+    std::future::pending().await
+}
+
+async fn execute(cmd: Command, shared: &Shared) {
+    match cmd {
+        Command::List => {
+            for session in shared.sessions.iter() {
+                let elapsed = session.since.elapsed().as_secs();
+                let (id, addr, user) = (session.key(), session.addr, &session.user);
+                let guest = if session.guest { " guest" } else { "" };
+                let presence = match (session.presence, &session.presence_message) {
+                    (Presence::Online, _) => String::new(),
+                    (presence, None) => format!(" {presence:?}"),
+                    (presence, Some(message)) => format!(" {presence:?} {message:?}"),
+                };
+                let traffic = session.traffic.snapshot();
+                let idle = session.traffic.idle().as_secs();
+                println!(
+                    "{id} {addr} {user}{guest}{presence} ({elapsed}s, idle {idle}s, \
+                    sent {} messages {} B, received {} messages {} B)",
+                    traffic.messages_sent,
+                    traffic.bytes_sent,
+                    traffic.messages_received,
+                    traffic.bytes_received,
+                );
+            }
+        }
+        Command::Kick(who) => {
+            let addr = who.parse::<SocketAddr>().ok();
+            let kicked: Vec<_> = shared
+                .sessions
+                .iter()
+                .filter(|s| Some(s.addr) == addr || s.user.to_string() == who)
+                .map(|s| (s.addr, s.user.clone(), s.kick.clone(), s.sender.clone()))
+                .collect();
+            if kicked.is_empty() {
+                println!("No session of \"{who}\"");
+            }
+            for (addr, user, kick, channel) in kicked {
+                let bye = ser::Msg::ServerInfo("You were disconnected by the server.".into());
+                if let Err(e) = channel.send(bye.into()).await {
+                    warn!("Notifying kicked {addr} failed! Error {e}");
+                }
+                kick.notify_one();
+                info!("{addr} kicked from the console");
+                let console = Some("from the console".to_string());
+                audit(&shared.db, AuditEvent::Kick, Some(&user), addr, console).await;
+            }
+        }
+        Command::Notice(text) => {
+            if let Err(e) = queue(&shared.tasks, Announce(text)).await {
+                warn!("{e:#}");
+            }
+        }
+        Command::Admin(cmd) => {
+            info!("{cmd:?} from the console");
+            match administer(cmd, None, shared).await {
+                Ok(tasks) => {
+                    for task in tasks {
+                        if let Err(e) = queue(&shared.tasks, task).await {
+                            warn!("{e:#}");
+                        }
+                    }
+                }
+                Err(e) => println!("{e}"),
+            }
+        }
+        Command::Audit(n) => match shared.db.audit_log(None, n).await {
+            Ok(entries) => {
+                for entry in entries.iter().rev() {
+                    let user = entry.username.as_deref().unwrap_or("-");
+                    let addr = entry.addr.as_deref().unwrap_or("-");
+                    let detail = entry.detail.as_deref().unwrap_or_default();
+                    println!("{} {} {user} {addr} {detail}", entry.at, entry.event);
+                }
+            }
+            Err(e) => println!("Reading the audit log failed: {e}"),
+        },
+        Command::Stats => {
+            let users: HashSet<_> = shared.sessions.iter().map(|s| s.user.to_string()).collect();
+            println!("clients: {} ({} users)", shared.sessions.len(), users.len());
+            println!("in-flight bytes: {}", shared.budget.used());
+            println!("uptime: {}s", shared.stats.uptime().as_secs());
+            println!("messages today: {}", shared.stats.messages_today());
+            if shared.retention.runs() > 0 {
+                println!("retention: {}", shared.retention);
+            }
+        }
+        Command::Reload => match reload::reload(shared).await {
+            Ok(()) => println!("Settings reloaded"),
+            Err(e) => println!("Reloading failed, the settings are kept: {e:#}"),
+        },
+        Command::Log(filter) => match (&shared.log_level, filter) {
+            (None, _) => println!("The log level is not controlled by the server"),
+            (Some(level), None) => println!("{}", level.current()),
+            (Some(level), Some(filter)) => match level.set(filter) {
+                Ok(()) => info!("log level set to {} from the console", level.current()),
+                Err(e) => println!("{e:#}"),
+            },
+        },
+        Command::Help => println!("{HELP}"),
+        Command::Shutdown => unreachable!("handled by the caller"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!("list".parse(), Ok(Command::List));
+        assert_eq!(" stats ".parse(), Ok(Command::Stats));
+        assert_eq!("reload".parse(), Ok(Command::Reload));
+        assert_eq!("log".parse(), Ok(Command::Log(None)));
+        assert_eq!(
+            "log warn,server=debug".parse(),
+            Ok(Command::Log(Some(
+                parse_level("warn,server=debug").unwrap()
+            )))
+        );
+        assert_eq!("audit".parse(), Ok(Command::Audit(AUDIT_DEFAULT)));
+        assert_eq!("audit 5".parse(), Ok(Command::Audit(5)));
+        assert_eq!(
+            "kick 127.0.0.1:5000".parse(),
+            Ok(Command::Kick("127.0.0.1:5000".to_string()))
+        );
+        assert_eq!(
+            "notice Back in 5  minutes".parse(),
+            Ok(Command::Notice("Back in 5  minutes".to_string()))
+        );
+        assert_eq!(
+            "motd Hello!".parse(),
+            Ok(Command::Admin(cli::Admin::SetMotd("Hello!".to_string())))
+        );
+        assert_eq!(
+            "quota alice 1048576".parse(),
+            Ok(Command::Admin(cli::Admin::SetQuota {
+                user: "alice".to_string().into(),
+                bytes: Some(1048576)
+            }))
+        );
+        assert_eq!(
+            "quota alice default".parse(),
+            Ok(Command::Admin(cli::Admin::SetQuota {
+                user: "alice".to_string().into(),
+                bytes: None
+            }))
+        );
+        assert_eq!(
+            "invite".parse(),
+            Ok(Command::Admin(cli::Admin::CreateInvite {
+                uses: 1,
+                valid_for: None
+            }))
+        );
+        assert_eq!(
+            "invite 5 7".parse(),
+            Ok(Command::Admin(cli::Admin::CreateInvite {
+                uses: 5,
+                valid_for: Some(Duration::from_secs(7 * 24 * 60 * 60))
+            }))
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!("kick".parse::<Command>().is_err());
+        assert!("shutdown now".parse::<Command>().is_err());
+        assert!("reboot".parse::<Command>().is_err());
+        assert!("audit all".parse::<Command>().is_err());
+        assert!("log server=loud".parse::<Command>().is_err());
+        assert!("quota alice".parse::<Command>().is_err());
+        assert!("quota alice lots".parse::<Command>().is_err());
+        assert!("invite many".parse::<Command>().is_err());
+        assert!("invite 1 2 3".parse::<Command>().is_err());
+    }
+}
@@ -1,12 +1,29 @@
 //! All database related stuff.
 use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
-    Argon2,
+    password_hash::{
+        rand_core::{OsRng, RngCore},
+        PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+    },
+    Algorithm, Argon2, Params, Version,
 };
-use sqlx::postgres::{PgPool, PgPoolOptions};
-use tokio::sync::Mutex;
+use std::{net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
 
-use cli_ser::{cli, Data};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::{
+    postgres::{PgConnectOptions, PgConnection, PgPool, PgPoolOptions},
+    Connection, PgExecutor,
+};
+use tokio::sync::{mpsc, oneshot, Semaphore};
+use tracing::{info, warn, Instrument};
+
+use cli_ser::{
+    cli,
+    retry::{retry_if, RetryPolicy},
+    ser, Bytes, Data, ErrorCode, ErrorKind, Image, Preferences, UserProfile,
+};
+
+use crate::{blobs::BlobStore, retention::Purged};
 
 #[derive(Clone, Debug, sqlx::FromRow)]
 pub(crate) struct User {
@@ -22,77 +39,155 @@ impl From<cli::Credentials> for User {
     }
 }
 
-/// User table, since the username is not the primary key, it can be changed later.
-const CREATE_USERS: &str = r#"
-CREATE TABLE IF NOT EXISTS "users" (
-  "id" bigserial PRIMARY KEY,
-  "username" text NOT NULL,
-  "password" text NOT NULL
-);
-"#;
+/// Message as listed by the HTTP API, exactly one of `text`, `file` and `image` is set.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, sqlx::FromRow)]
+pub(crate) struct Stored {
+    pub(crate) id: i64,
+    pub(crate) from: String,
+    /// RFC 3339 time of arrival.
+    pub(crate) arrived: String,
+    pub(crate) text: Option<String>,
+    /// Name of the file.
+    pub(crate) file: Option<String>,
+    pub(crate) image: bool,
+    pub(crate) urgent: bool,
+}
 
-const CREATE_MESSAGES: &str = r#"
-CREATE TABLE IF NOT EXISTS "messages" (
-  "id" bigserial PRIMARY KEY,
-  "from_user_id" bigint NOT NULL,
-  "text_id" bigint,
-  "file_id" bigint,
-  "img_id" bigint,
-  "arrived" timestamp with time zone NOT NULL,
-  check(
-    (
-      ("text_id" IS NOT NULL)::integer +
-      ("file_id" IS NOT NULL)::integer +
-      ("img_id" IS NOT NULL)::integer
-    ) = 1
-  )
-);
-"#;
-const CREATE_CHATS: &str = r#"
-CREATE TABLE IF NOT EXISTS "chats" (
-  "id" bigserial PRIMARY KEY,
-  "msg_id" bigint NOT NULL,
-  "to_user_id" bigint NOT NULL,
-  "when_recv" timestamp
-);
-"#;
-const CREATE_TEXTS: &str = r#"
-CREATE TABLE IF NOT EXISTS "texts" (
-  "id" bigserial PRIMARY KEY,
-  "text" text
-);
-"#;
-const CREATE_FILES: &str = r#"
-CREATE TABLE IF NOT EXISTS "files" (
-  "id" bigserial PRIMARY KEY,
-  "name" text,
-  "bytes" bytea
-);
-"#;
-// TODO: img format
-const CREATE_IMAGES: &str = r#"
-CREATE TABLE IF NOT EXISTS "images" (
-  "id" bigserial PRIMARY KEY,
-  "bytes" bytea
-);
-"#;
-const ALTER_MESSAGES_USERS: &str = r#"
-ALTER TABLE "messages" ADD FOREIGN KEY ("from_user_id") REFERENCES "users" ("id");
-"#;
-const ALTER_MESSAGES_TEXTS: &str = r#"
-ALTER TABLE "messages" ADD FOREIGN KEY ("text_id") REFERENCES "texts" ("id");
-"#;
-const ALTER_MESSAGES_FILES: &str = r#"
-ALTER TABLE "messages" ADD FOREIGN KEY ("file_id") REFERENCES "files" ("id");
-"#;
-const ALTER_MESSAGES_IMAGES: &str = r#"
-ALTER TABLE "messages" ADD FOREIGN KEY ("img_id") REFERENCES "images" ("id");
-"#;
-const ALTER_CHATS_MESSAGES: &str = r#"
-ALTER TABLE "chats" ADD FOREIGN KEY ("msg_id") REFERENCES "messages" ("id");
+/// Kind of a security event recorded in the audit log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum AuditEvent {
+    LogIn,
+    FailedLogIn,
+    SignUp,
+    /// A session was disconnected by an administrator.
+    Kick,
+    /// A connection from an address the access policy denies.
+    Denied,
+    /// A password reset code was sent to the user.
+    ResetRequested,
+    /// The user set a new password by a reset code.
+    PasswordReset,
+}
+impl AuditEvent {
+    /// Name of the event as it is stored.
+    fn as_str(self) -> &'static str {
+        match self {
+            AuditEvent::LogIn => "login",
+            AuditEvent::FailedLogIn => "failed_login",
+            AuditEvent::SignUp => "signup",
+            AuditEvent::Kick => "kick",
+            AuditEvent::Denied => "denied",
+            AuditEvent::ResetRequested => "reset_requested",
+            AuditEvent::PasswordReset => "password_reset",
+        }
+    }
+}
+
+/// Entry of the audit log as listed by the HTTP API and the console.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, sqlx::FromRow)]
+pub(crate) struct Audited {
+    pub(crate) id: i64,
+    /// RFC 3339 time of the event.
+    pub(crate) at: String,
+    pub(crate) event: String,
+    pub(crate) username: Option<String>,
+    /// Source address of the connection.
+    pub(crate) addr: Option<String>,
+    pub(crate) detail: Option<String>,
+}
+
+/// Message sent to all users waiting to be recorded, its id is [reserved][Database::reserve_msg_ids] upfront.
+#[derive(Debug)]
+pub(crate) struct Record {
+    pub(crate) id: i64,
+    pub(crate) from: String,
+    pub(crate) arrived: DateTime<Utc>,
+    pub(crate) content: Content,
+    /// Sent as [urgent][cli::Msg::Urgent].
+    pub(crate) urgent: bool,
+}
+impl Record {
+    /// Record of the `data` from the `user` arrived right now,
+    /// the `original` of a re-encoded image is stored along with it.
+    pub(crate) fn now(
+        id: i64,
+        user: cli_ser::User,
+        data: Data,
+        original: Option<Image>,
+        urgent: bool,
+    ) -> Self {
+        let content = match data {
+            Data::Text(text) => Content::Text(text),
+            Data::File(file) => {
+                let (name, bytes) = file.into();
+                Content::File { name, bytes }
+            }
+            Data::Image(image) => Content::Image {
+                bytes: image.into(),
+                original: original.map(Bytes::from),
+            },
+            // Kept as a file, its name keeps the extension.
+            Data::Media(media) => {
+                let (name, bytes) = media.into();
+                Content::File { name, bytes }
+            }
+        };
+        Record {
+            id,
+            from: user.into(),
+            arrived: Utc::now(),
+            content,
+            urgent,
+        }
+    }
+}
+/// What the [Record] keeps, attachments share their bytes with the broadcast message.
+#[derive(Debug)]
+pub(crate) enum Content {
+    Text(String),
+    File {
+        name: String,
+        bytes: Bytes,
+    },
+    Image {
+        bytes: Bytes,
+        original: Option<Bytes>,
+    },
+}
+
+/// Stored message with the bytes of its attachment, if any.
+#[derive(Clone, Debug, sqlx::FromRow)]
+pub(crate) struct Archived {
+    #[sqlx(flatten)]
+    pub(crate) msg: Stored,
+    pub(crate) bytes: Option<Vec<u8>>,
+}
+
+/// [Archived] as queried, the bytes may be kept by the [BlobStore].
+#[derive(sqlx::FromRow)]
+struct ArchivedRow {
+    #[sqlx(flatten)]
+    msg: Stored,
+    hash: Option<Vec<u8>>,
+    bytes: Option<Vec<u8>>,
+}
+
+/// Columns of [Stored].
+const STORED_COLUMNS: &str = r#"
+  messages.id,
+  users.username AS "from",
+  to_char(messages.arrived AT TIME ZONE 'UTC', 'YYYY-MM-DD"T"HH24:MI:SS"Z"') AS arrived,
+  texts.text,
+  files.name AS file,
+  messages.img_id IS NOT NULL AS image,
+  messages.urgent
 "#;
-const ALTER_CHATS_USERS: &str = r#"
-ALTER TABLE "chats" ADD FOREIGN KEY ("to_user_id") REFERENCES "users" ("id");
+/// Tables the [columns][STORED_COLUMNS] come from.
+const STORED_TABLES: &str = r#"
+FROM messages
+JOIN users ON users.id = messages.from_user_id
+LEFT JOIN texts ON texts.id = messages.text_id
+LEFT JOIN files ON files.id = messages.file_id
 "#;
 
 #[derive(thiserror::Error, Debug)]
@@ -103,60 +198,823 @@ pub enum Error {
     UserDoesNotExist(String),
     #[error("Username `{0}` is already taken")]
     UsernameTaken(String),
+    #[error("Email `{0}` is used by another user")]
+    EmailTaken(String),
+    #[error("The invitation code is not valid")]
+    InvalidInvite,
+    #[error("Storage quota exceeded, {used} of {limit} bytes used")]
+    QuotaExceeded { used: u64, limit: u64 },
     #[error("Inner database fail, contact the implementer!")]
-    Database(sqlx::Error),
+    Database(#[source] sqlx::Error),
+    #[error(
+        "No database connection became free in time, the database is overloaded or unreachable"
+    )]
+    PoolExhausted,
+    #[error("Database task stopped, contact the implementer!")]
+    Stopped,
+    #[error("Blob store failed, check the attachment storage!")]
+    Blob(#[source] std::io::Error),
     #[error("Fail during password check, contact the implementer!")]
     Security(argon2::password_hash::Error),
+    #[error("Password hashing task failed, contact the implementer!")]
+    Hashing(#[source] tokio::task::JoinError),
+}
+impl Error {
+    /// Code of the error in the [shared code space][ErrorCode], logged and sent to clients.
+    pub fn code(&self) -> ErrorCode {
+        let code = match self {
+            Error::Blob(_) => 1501,
+            Error::Database(_) => 4501,
+            Error::PoolExhausted => 4502,
+            Error::Stopped => 4503,
+            Error::WrongPassword(_) => 5501,
+            Error::UserDoesNotExist(_) => 5502,
+            Error::UsernameTaken(_) => 5503,
+            Error::EmailTaken(_) => 5504,
+            Error::Security(_) => 5505,
+            Error::Hashing(_) => 5506,
+            Error::InvalidInvite => 5507,
+            Error::QuotaExceeded { .. } => 6501,
+        };
+        ErrorCode(code)
+    }
+
+    /// Kind of the error by its variant, the range of its [code][Self::code] agrees.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Blob(_) => ErrorKind::Io,
+            Error::Database(_) | Error::PoolExhausted | Error::Stopped => ErrorKind::Database,
+            Error::WrongPassword(_)
+            | Error::UserDoesNotExist(_)
+            | Error::UsernameTaken(_)
+            | Error::EmailTaken(_)
+            | Error::Security(_)
+            | Error::Hashing(_)
+            | Error::InvalidInvite => ErrorKind::Auth,
+            Error::QuotaExceeded { .. } => ErrorKind::Limit,
+        }
+    }
+}
+/// Failures without a kind of the protocol reach clients as [Other][ser::Error::Other] with their code.
+impl From<&Error> for ser::Error {
+    fn from(e: &Error) -> Self {
+        ser::Error::Other {
+            code: e.code().0,
+            detail: e.to_string(),
+        }
+    }
+}
+impl From<sqlx::Error> for Error {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::PoolTimedOut => Error::PoolExhausted,
+            e => Error::Database(e),
+        }
+    }
 }
 
 type Result<T> = std::result::Result<T, Error>;
 
-/// Database handle.
-///
-/// ## Why tokio::Mutex
+/// How the database is connected to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DatabaseOptions {
+    /// Connections open at once at most, as many commands are carried out at once.
+    pub max_connections: u32,
+    /// How long a query waits for a free connection, then it fails as [PoolExhausted][Error::PoolExhausted].
+    pub acquire_timeout: Duration,
+    /// Statements running longer are cancelled by the database, they are not limited when `None`.
+    pub statement_timeout: Option<Duration>,
+    /// Attempts to connect after the first one fails, e.g. when the database is still starting.
+    pub connect_retries: u32,
+    /// Wait before the first retry, it doubles with each further one.
+    pub retry_delay: Duration,
+    /// Messages stored at once, see [Persister][crate::persist::Persister].
+    pub batch_size: usize,
+    /// Buffered messages are stored at least this often.
+    pub flush_interval: Duration,
+    /// Where the bytes of attachments are kept.
+    pub blob_store: BlobStore,
+    /// Cost of the password hashes.
+    pub password_hashing: PasswordHashing,
+}
+impl Default for DatabaseOptions {
+    fn default() -> Self {
+        DatabaseOptions {
+            max_connections: 5,
+            acquire_timeout: Duration::from_secs(30),
+            statement_timeout: None,
+            connect_retries: 5,
+            retry_delay: Duration::from_secs(1),
+            batch_size: 64,
+            flush_interval: Duration::from_millis(100),
+            blob_store: BlobStore::Database,
+            password_hashing: PasswordHashing::default(),
+        }
+    }
+}
+
+/// Cost of the Argon2id password hashes, the defaults are those of [argon2::Params].
 ///
-/// During user signing up (inserting to the database),
-/// there can not be interruption between the check if exist and insert,
-/// otherwise two users with the same name can be created at the same time.
+/// The parameters are kept in each hash (the PHC string), so hashes of other costs are still verified.
+/// A hash cheaper than the current cost in any parameter is replaced on the next successful log-in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PasswordHashing {
+    /// Memory in KiB.
+    pub memory_kib: u32,
+    pub iterations: u32,
+    /// Threads (lanes) the hash is computed by.
+    pub parallelism: u32,
+}
+impl Default for PasswordHashing {
+    fn default() -> Self {
+        PasswordHashing {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+impl PasswordHashing {
+    /// Hasher of the cost, fails when the parameters are out of the Argon2 bounds.
+    fn hasher(&self) -> std::result::Result<Argon2<'static>, argon2::Error> {
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, None)?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+
+    /// Whether the hash is not Argon2id or is cheaper than the current cost.
+    fn outdated(&self, hash: &PasswordHash) -> bool {
+        let Ok(params) = Params::try_from(hash) else {
+            return true;
+        };
+        hash.algorithm != argon2::ARGON2ID_IDENT
+            || params.m_cost() < self.memory_kib
+            || params.t_cost() < self.iterations
+            || params.p_cost() < self.parallelism
+    }
+}
+
+/// Database handle, the queries are carried out by the task owning the connection pool.
 ///
-/// std mutex is preferred over tokio mutex even in asynchronous settings...
-/// however sqlx::query needs .await, so we need tokio mutex to be held
-/// for the whole the select and insert.
+/// Each method sends a [Command] to the task and waits for the reply,
+/// the task carries out at most [max_connections][DatabaseOptions::max_connections] commands at once,
+/// further ones wait in the queue.
 ///
-/// Besides ["The primary use case for the async mutex is to provide shared mutable access to IO resources such as a database connection."](https://docs.rs/tokio/latest/tokio/sync/struct.Mutex.html).
+/// ## Concurrency
 ///
-/// In order to get rid of the tokio mutex there is a posibility to refactor the database with actor model.
+/// Commands run concurrently, every write is a single statement
+/// whose outcome does not depend on a previous read.
+/// E.g. signing up relies on the unique index of usernames (`INSERT ... ON CONFLICT DO NOTHING`),
+/// so two users with the same name can not be created at the same time.
 ///
 /// ## Argon2
 ///
-/// Currently a default argon2 is created for every log-in and sign-up.
-/// The struct has lifetime (of the secret key) which makes it complicated for
-/// tasks etc.
-/// If this would be a problem (performance), the actor model would solve it.
+/// Passwords are hashed and verified on blocking threads, they take long on purpose
+/// and would stall the other tasks otherwise. The cost is given by [PasswordHashing].
 pub(crate) struct Database {
-    pool: Mutex<PgPool>,
+    commands: mpsc::Sender<Command>,
 }
 impl Database {
-    /// Connects to database specified by `url` and creates tables.
+    /// Connects to database specified by `url`, runs the pending migrations and starts the task.
     ///
     /// The `url` specification can be read [here](https://docs.rs/sqlx/latest/sqlx/trait.ConnectOptions.html#implementors).
-    pub(crate) async fn try_new(url: &str) -> sqlx::Result<Database> {
-        let pool = PgPoolOptions::new().max_connections(5).connect(url).await?;
-        sqlx::query(CREATE_USERS).execute(&pool).await?;
-        sqlx::query(CREATE_MESSAGES).execute(&pool).await?;
-        sqlx::query(CREATE_CHATS).execute(&pool).await?;
-        sqlx::query(CREATE_TEXTS).execute(&pool).await?;
-        sqlx::query(CREATE_FILES).execute(&pool).await?;
-        sqlx::query(CREATE_IMAGES).execute(&pool).await?;
-        sqlx::query(ALTER_MESSAGES_USERS).execute(&pool).await?;
-        sqlx::query(ALTER_MESSAGES_TEXTS).execute(&pool).await?;
-        sqlx::query(ALTER_MESSAGES_FILES).execute(&pool).await?;
-        sqlx::query(ALTER_MESSAGES_IMAGES).execute(&pool).await?;
-        sqlx::query(ALTER_CHATS_MESSAGES).execute(&pool).await?;
-        sqlx::query(ALTER_CHATS_USERS).execute(&pool).await?;
-        Ok(Database {
-            pool: Mutex::new(pool),
+    pub(crate) async fn try_new(url: &str, options: &DatabaseOptions) -> sqlx::Result<Database> {
+        let store = Store::connect(url, options).await?;
+        let (commands, queued) = mpsc::channel(COMMANDS_QUEUE);
+        let limit = usize::try_from(options.max_connections).unwrap_or(usize::MAX);
+        tokio::spawn(serve(Arc::new(store), queued, limit).in_current_span());
+        Ok(Database { commands })
+    }
+
+    /// Sends the command made with the reply channel and waits for the reply.
+    async fn ask<T>(&self, command: impl FnOnce(Reply<T>) -> Command) -> Result<T> {
+        let (reply, answer) = oneshot::channel();
+        self.commands
+            .send(command(reply))
+            .await
+            .map_err(|_| Error::Stopped)?;
+        answer.await.map_err(|_| Error::Stopped)?
+    }
+
+    pub(crate) async fn log_in(&self, user: impl Into<User>) -> Result<()> {
+        self.ask(|reply| LogIn(user.into(), reply)).await
+    }
+
+    pub(crate) async fn sign_up(&self, user: impl Into<User>) -> Result<()> {
+        self.ask(|reply| SignUp(user.into(), reply)).await
+    }
+
+    /// Signs up the user taking one use of the invitation, fails with [Error::InvalidInvite] when none is left.
+    pub(crate) async fn sign_up_invited(&self, user: impl Into<User>, invite: &str) -> Result<()> {
+        self.ask(|reply| SignUpInvited(user.into(), invite.to_string(), reply))
+            .await
+    }
+
+    /// Makes an invitation of the `creator` for `uses` sign-ups, valid for the duration if given.
+    /// Returns the code and when it expires.
+    pub(crate) async fn create_invite(
+        &self,
+        creator: Option<&cli_ser::User>,
+        uses: u32,
+        valid_for: Option<Duration>,
+    ) -> Result<(String, Option<DateTime<Utc>>)> {
+        let creator = creator.cloned();
+        self.ask(|reply| CreateInvite {
+            creator,
+            uses,
+            valid_for,
+            reply,
+        })
+        .await
+    }
+
+    /// Returns the user the subject of the identity provider logs in as, `None` before their first log-in.
+    pub(crate) async fn identity(
+        &self,
+        issuer: &str,
+        subject: &str,
+    ) -> Result<Option<cli_ser::User>> {
+        self.ask(|reply| Identity {
+            issuer: issuer.to_string(),
+            subject: subject.to_string(),
+            reply,
+        })
+        .await
+    }
+
+    /// Returns the user the subject of the identity provider logs in as,
+    /// the user is created with the `username` on the subject's first log-in.
+    pub(crate) async fn identity_user(
+        &self,
+        issuer: &str,
+        subject: &str,
+        username: &str,
+    ) -> Result<cli_ser::User> {
+        self.ask(|reply| IdentityUser {
+            issuer: issuer.to_string(),
+            subject: subject.to_string(),
+            username: username.to_string(),
+            reply,
+        })
+        .await
+    }
+
+    /// Signs up the user and grants them administrator rights.
+    pub(crate) async fn create_admin(&self, user: impl Into<User>) -> Result<()> {
+        self.ask(|reply| CreateAdmin(user.into(), reply)).await
+    }
+
+    /// Returns whether the `user` has administrator rights, unknown users have none.
+    pub(crate) async fn is_admin(&self, user: &cli_ser::User) -> Result<bool> {
+        self.ask(|reply| IsAdmin(user.clone(), reply)).await
+    }
+
+    /// Reserves `n` ids for messages which are going to be [recorded][Self::record_msgs].
+    pub(crate) async fn reserve_msg_ids(&self, n: i64) -> Result<Vec<i64>> {
+        self.ask(|reply| ReserveMsgIds(n, reply)).await
+    }
+
+    /// Records the messages in one transaction, either all of them are stored or none.
+    pub(crate) async fn record_msgs(&self, records: Vec<Arc<Record>>) -> Result<()> {
+        self.ask(|reply| RecordMsgs(records, reply)).await
+    }
+
+    /// Changes one part of the user's profile, the rest stays as it is.
+    pub(crate) async fn set_profile(
+        &self,
+        user: &cli_ser::User,
+        change: cli::ProfileChange,
+    ) -> Result<()> {
+        self.ask(|reply| SetProfile(user.clone(), change, reply))
+            .await
+    }
+
+    /// Makes a new password reset code of the user with the `email`, the previous one is not valid anymore.
+    /// Returns the user and the code, `None` when nobody uses the address.
+    pub(crate) async fn reset_code(&self, email: &str) -> Result<Option<(cli_ser::User, String)>> {
+        self.ask(|reply| ResetCode(email.to_string(), reply)).await
+    }
+
+    /// Returns the user the valid `code` was made for, `None` when it is not valid, the code is not used up.
+    pub(crate) async fn reset_code_user(&self, code: &str) -> Result<Option<cli_ser::User>> {
+        self.ask(|reply| ResetCodeUser(code.to_string(), reply))
+            .await
+    }
+
+    /// Sets the new password of the user the valid `code` was made for, the code is used up.
+    /// Returns the user, `None` when the code is not valid.
+    pub(crate) async fn reset_password(
+        &self,
+        code: &str,
+        new_password: String,
+    ) -> Result<Option<cli_ser::User>> {
+        self.ask(|reply| ResetPassword(code.to_string(), new_password, reply))
+            .await
+    }
+
+    /// Returns the sealed TOTP secrets of the user, the active one and the one waiting for confirmation.
+    pub(crate) async fn totp(
+        &self,
+        user: &cli_ser::User,
+    ) -> Result<(Option<Vec<u8>>, Option<Vec<u8>>)> {
+        self.ask(|reply| Totp(user.clone(), reply)).await
+    }
+
+    /// Keeps the sealed secret until it is [activated][Self::activate_totp], replacing any earlier pending one.
+    pub(crate) async fn set_totp_pending(
+        &self,
+        user: &cli_ser::User,
+        sealed: Vec<u8>,
+    ) -> Result<()> {
+        self.ask(|reply| SetTotpPending(user.clone(), sealed, reply))
+            .await
+    }
+
+    /// Makes the pending secret the active one, `false` when it was replaced meanwhile.
+    pub(crate) async fn activate_totp(
+        &self,
+        user: &cli_ser::User,
+        sealed: Vec<u8>,
+    ) -> Result<bool> {
+        self.ask(|reply| ActivateTotp(user.clone(), sealed, reply))
+            .await
+    }
+
+    /// Removes both secrets of the user, codes are not required anymore.
+    pub(crate) async fn disable_totp(&self, user: &cli_ser::User) -> Result<()> {
+        self.ask(|reply| DisableTotp(user.clone(), reply)).await
+    }
+
+    /// Returns the profile of the user, `None` when the user does not exist.
+    pub(crate) async fn profile(&self, user: &cli_ser::User) -> Result<Option<UserProfile>> {
+        self.ask(|reply| Profile(user.clone(), reply)).await
+    }
+
+    /// Replaces the notification preferences of the user.
+    pub(crate) async fn set_prefs(&self, user: &cli_ser::User, prefs: Preferences) -> Result<()> {
+        self.ask(|reply| SetPrefs(user.clone(), prefs, reply)).await
+    }
+
+    /// Returns the notification preferences of the user, the defaults if they did not set any.
+    pub(crate) async fn prefs(&self, user: &cli_ser::User) -> Result<Preferences> {
+        self.ask(|reply| Prefs(user.clone(), reply)).await
+    }
+
+    /// Returns the display name of the user if they set any.
+    pub(crate) async fn display_name(&self, user: &cli_ser::User) -> Result<Option<String>> {
+        self.ask(|reply| DisplayName(user.clone(), reply)).await
+    }
+
+    /// Records that the `user` read the message, reading it again keeps the first time.
+    pub(crate) async fn mark_read(&self, user: &cli_ser::User, msg_id: i64) -> Result<()> {
+        self.ask(|reply| MarkRead(user.clone(), msg_id, reply))
+            .await
+    }
+
+    /// Adds the `bytes` to the storage the user used, fails as [QuotaExceeded][Error::QuotaExceeded]
+    /// when it would exceed their quota.
+    ///
+    /// The `default_quota` applies to users without their own, without any quota the storage is unlimited.
+    pub(crate) async fn reserve_storage(
+        &self,
+        user: &cli_ser::User,
+        bytes: u64,
+        default_quota: Option<u64>,
+    ) -> Result<()> {
+        self.ask(|reply| ReserveStorage {
+            user: user.clone(),
+            bytes,
+            default_quota,
+            reply,
         })
+        .await
+    }
+
+    /// Gives back the storage [reserved][Self::reserve_storage] for an attachment which was not stored.
+    pub(crate) async fn release_storage(&self, user: &cli_ser::User, bytes: u64) -> Result<()> {
+        self.ask(|reply| ReleaseStorage(user.clone(), bytes, reply))
+            .await
+    }
+
+    /// Sets the storage quota of the user, `None` returns them to the server's default.
+    pub(crate) async fn set_quota(&self, user: &cli_ser::User, bytes: Option<u64>) -> Result<()> {
+        self.ask(|reply| SetQuota(user.clone(), bytes, reply)).await
+    }
+
+    /// Returns all usernames in alphabetical order.
+    pub(crate) async fn usernames(&self) -> Result<Vec<String>> {
+        self.ask(Usernames).await
+    }
+
+    /// Returns at most `limit` messages older than the message `before`, newest first.
+    pub(crate) async fn messages(&self, before: Option<i64>, limit: i64) -> Result<Vec<Stored>> {
+        self.ask(|reply| Messages {
+            before,
+            limit,
+            reply,
+        })
+        .await
+    }
+
+    /// Returns messages which arrived since the time along with their attachments, oldest first.
+    pub(crate) async fn archive(&self, since: Option<DateTime<Utc>>) -> Result<Vec<Archived>> {
+        self.ask(|reply| Archive(since, reply)).await
+    }
+
+    /// Returns users who read the message in the order they did, `None` when the message is not from the `sender`.
+    pub(crate) async fn read_by(
+        &self,
+        sender: &cli_ser::User,
+        msg_id: i64,
+    ) -> Result<Option<Vec<String>>> {
+        self.ask(|reply| ReadBy(sender.clone(), msg_id, reply))
+            .await
+    }
+
+    /// Records the security event of the user (when known) connected from the address.
+    pub(crate) async fn audit(
+        &self,
+        event: AuditEvent,
+        username: Option<String>,
+        addr: Option<SocketAddr>,
+        detail: Option<String>,
+    ) -> Result<()> {
+        self.ask(|reply| Audit {
+            event,
+            username,
+            addr,
+            detail,
+            reply,
+        })
+        .await
+    }
+
+    /// Returns at most `limit` entries of the audit log older than the entry `before`, newest first.
+    pub(crate) async fn audit_log(&self, before: Option<i64>, limit: i64) -> Result<Vec<Audited>> {
+        self.ask(|reply| AuditLog {
+            before,
+            limit,
+            reply,
+        })
+        .await
+    }
+
+    /// Deletes messages which arrived `before` the time and all but the `keep` newest ones,
+    /// along with their attachments and the blobs nothing else refers to.
+    ///
+    /// With `dry_run` the messages are only counted.
+    pub(crate) async fn purge(
+        &self,
+        before: Option<DateTime<Utc>>,
+        keep: Option<u64>,
+        dry_run: bool,
+    ) -> Result<Purged> {
+        self.ask(|reply| Purge {
+            before,
+            keep,
+            dry_run,
+            reply,
+        })
+        .await
+    }
+}
+
+/// Commands waiting for the database task.
+const COMMANDS_QUEUE: usize = 1024;
+
+/// How long a password reset code is valid, as a Postgres interval.
+const RESET_CODE_VALIDITY: &str = "15 minutes";
+
+type Reply<T> = oneshot::Sender<Result<T>>;
+
+/// What the database task is asked to do, see the methods of [Database].
+enum Command {
+    LogIn(User, Reply<()>),
+    SignUp(User, Reply<()>),
+    SignUpInvited(User, String, Reply<()>),
+    CreateInvite {
+        creator: Option<cli_ser::User>,
+        uses: u32,
+        valid_for: Option<Duration>,
+        reply: Reply<(String, Option<DateTime<Utc>>)>,
+    },
+    Identity {
+        issuer: String,
+        subject: String,
+        reply: Reply<Option<cli_ser::User>>,
+    },
+    IdentityUser {
+        issuer: String,
+        subject: String,
+        username: String,
+        reply: Reply<cli_ser::User>,
+    },
+    CreateAdmin(User, Reply<()>),
+    IsAdmin(cli_ser::User, Reply<bool>),
+    ReserveMsgIds(i64, Reply<Vec<i64>>),
+    RecordMsgs(Vec<Arc<Record>>, Reply<()>),
+    SetProfile(cli_ser::User, cli::ProfileChange, Reply<()>),
+    Profile(cli_ser::User, Reply<Option<UserProfile>>),
+    SetPrefs(cli_ser::User, Preferences, Reply<()>),
+    Prefs(cli_ser::User, Reply<Preferences>),
+    ResetCode(String, Reply<Option<(cli_ser::User, String)>>),
+    ResetCodeUser(String, Reply<Option<cli_ser::User>>),
+    ResetPassword(String, String, Reply<Option<cli_ser::User>>),
+    Totp(cli_ser::User, Reply<(Option<Vec<u8>>, Option<Vec<u8>>)>),
+    SetTotpPending(cli_ser::User, Vec<u8>, Reply<()>),
+    ActivateTotp(cli_ser::User, Vec<u8>, Reply<bool>),
+    DisableTotp(cli_ser::User, Reply<()>),
+    DisplayName(cli_ser::User, Reply<Option<String>>),
+    MarkRead(cli_ser::User, i64, Reply<()>),
+    ReserveStorage {
+        user: cli_ser::User,
+        bytes: u64,
+        default_quota: Option<u64>,
+        reply: Reply<()>,
+    },
+    ReleaseStorage(cli_ser::User, u64, Reply<()>),
+    SetQuota(cli_ser::User, Option<u64>, Reply<()>),
+    ReadBy(cli_ser::User, i64, Reply<Option<Vec<String>>>),
+    Usernames(Reply<Vec<String>>),
+    Messages {
+        before: Option<i64>,
+        limit: i64,
+        reply: Reply<Vec<Stored>>,
+    },
+    Archive(Option<DateTime<Utc>>, Reply<Vec<Archived>>),
+    Audit {
+        event: AuditEvent,
+        username: Option<String>,
+        addr: Option<SocketAddr>,
+        detail: Option<String>,
+        reply: Reply<()>,
+    },
+    AuditLog {
+        before: Option<i64>,
+        limit: i64,
+        reply: Reply<Vec<Audited>>,
+    },
+    Purge {
+        before: Option<DateTime<Utc>>,
+        keep: Option<u64>,
+        dry_run: bool,
+        reply: Reply<Purged>,
+    },
+}
+use Command::*;
+
+/// Carries out the commands until every [Database] handle is dropped, at most `limit` at once.
+async fn serve(store: Arc<Store>, mut commands: mpsc::Receiver<Command>, limit: usize) {
+    let permits = Arc::new(Semaphore::new(limit));
+    while let Some(command) = commands.recv().await {
+        let permit = permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed");
+        let store = store.clone();
+        tokio::spawn(
+            async move {
+                store.carry_out(command).await;
+                drop(permit);
+            }
+            .in_current_span(),
+        );
+    }
+}
+
+/// Owner of the connection pool and the blob store, answers the [commands][Command].
+struct Store {
+    pool: PgPool,
+    blobs: BlobStore,
+    hashing: PasswordHashing,
+    hasher: Argon2<'static>,
+    /// Hash of a random password, unknown users are checked against it, see [Self::log_in].
+    dummy_hash: String,
+}
+impl Store {
+    /// Carries out the command, the reply is dropped when nobody waits for it.
+    async fn carry_out(&self, command: Command) {
+        match command {
+            LogIn(user, reply) => {
+                let _ = reply.send(self.log_in(user).await);
+            }
+            SignUp(user, reply) => {
+                let _ = reply.send(self.sign_up(user).await);
+            }
+            SignUpInvited(user, invite, reply) => {
+                let _ = reply.send(self.sign_up_invited(user, &invite).await);
+            }
+            CreateInvite {
+                creator,
+                uses,
+                valid_for,
+                reply,
+            } => {
+                let created = self.create_invite(creator.as_ref(), uses, valid_for).await;
+                let _ = reply.send(created);
+            }
+            Identity {
+                issuer,
+                subject,
+                reply,
+            } => {
+                let known = self.identity(&issuer, &subject).await;
+                let _ = reply.send(known.map(|known| known.map(Into::into)));
+            }
+            IdentityUser {
+                issuer,
+                subject,
+                username,
+                reply,
+            } => {
+                let _ = reply.send(self.identity_user(&issuer, &subject, &username).await);
+            }
+            CreateAdmin(user, reply) => {
+                let _ = reply.send(self.create_admin(user).await);
+            }
+            IsAdmin(user, reply) => {
+                let _ = reply.send(self.is_admin(&user).await);
+            }
+            ReserveMsgIds(n, reply) => {
+                let _ = reply.send(self.reserve_msg_ids(n).await);
+            }
+            RecordMsgs(records, reply) => {
+                let _ = reply.send(self.record_msgs(records).await);
+            }
+            SetProfile(user, change, reply) => {
+                let _ = reply.send(self.set_profile(&user, change).await);
+            }
+            Profile(user, reply) => {
+                let _ = reply.send(self.profile(&user).await);
+            }
+            SetPrefs(user, prefs, reply) => {
+                let _ = reply.send(self.set_prefs(&user, prefs).await);
+            }
+            Prefs(user, reply) => {
+                let _ = reply.send(self.prefs(&user).await);
+            }
+            ResetCode(email, reply) => {
+                let _ = reply.send(self.reset_code(&email).await);
+            }
+            ResetCodeUser(code, reply) => {
+                let _ = reply.send(self.reset_code_user(&code).await);
+            }
+            ResetPassword(code, new_password, reply) => {
+                let _ = reply.send(self.reset_password(&code, new_password).await);
+            }
+            Totp(user, reply) => {
+                let _ = reply.send(self.totp(&user).await);
+            }
+            SetTotpPending(user, sealed, reply) => {
+                let _ = reply.send(self.set_totp_pending(&user, sealed).await);
+            }
+            ActivateTotp(user, sealed, reply) => {
+                let _ = reply.send(self.activate_totp(&user, sealed).await);
+            }
+            DisableTotp(user, reply) => {
+                let _ = reply.send(self.disable_totp(&user).await);
+            }
+            DisplayName(user, reply) => {
+                let _ = reply.send(self.display_name(&user).await);
+            }
+            MarkRead(user, msg_id, reply) => {
+                let _ = reply.send(self.mark_read(&user, msg_id).await);
+            }
+            ReadBy(sender, msg_id, reply) => {
+                let _ = reply.send(self.read_by(&sender, msg_id).await);
+            }
+            ReserveStorage {
+                user,
+                bytes,
+                default_quota,
+                reply,
+            } => {
+                let _ = reply.send(self.reserve_storage(&user, bytes, default_quota).await);
+            }
+            ReleaseStorage(user, bytes, reply) => {
+                let _ = reply.send(self.release_storage(&user, bytes).await);
+            }
+            SetQuota(user, bytes, reply) => {
+                let _ = reply.send(self.set_quota(&user, bytes).await);
+            }
+            Usernames(reply) => {
+                let _ = reply.send(self.usernames().await);
+            }
+            Messages {
+                before,
+                limit,
+                reply,
+            } => {
+                let _ = reply.send(self.messages(before, limit).await);
+            }
+            Archive(since, reply) => {
+                let _ = reply.send(self.archive(since).await);
+            }
+            Audit {
+                event,
+                username,
+                addr,
+                detail,
+                reply,
+            } => {
+                let _ = reply.send(self.audit(event, username, addr, detail).await);
+            }
+            AuditLog {
+                before,
+                limit,
+                reply,
+            } => {
+                let _ = reply.send(self.audit_log(before, limit).await);
+            }
+            Purge {
+                before,
+                keep,
+                dry_run,
+                reply,
+            } => {
+                let _ = reply.send(self.purge(before, keep, dry_run).await);
+            }
+        }
+    }
+
+    /// Connects to database specified by `url` and runs the pending migrations from `migrations/`.
+    async fn connect(url: &str, options: &DatabaseOptions) -> sqlx::Result<Store> {
+        let hasher = options
+            .password_hashing
+            .hasher()
+            .map_err(|e| sqlx::Error::Configuration(format!("Password hashing: {e}").into()))?;
+        let dummy_hash = hasher
+            .hash_password(
+                SaltString::generate(&mut OsRng).as_str().as_bytes(),
+                &SaltString::generate(&mut OsRng),
+            )
+            .map_err(|e| sqlx::Error::Configuration(format!("Password hashing: {e}").into()))?
+            .to_string();
+        let mut connect = PgConnectOptions::from_str(url)?;
+        if let Some(timeout) = options.statement_timeout {
+            connect = connect.options([("statement_timeout", timeout.as_millis().to_string())]);
+        }
+        Self::wait_until_reachable(&connect, options).await?;
+        let pool = PgPoolOptions::new()
+            .max_connections(options.max_connections)
+            .acquire_timeout(options.acquire_timeout)
+            .connect_with(connect)
+            .await?;
+        Self::drop_duplicate_foreign_keys(&pool).await?;
+        sqlx::migrate!().run(&pool).await?;
+        Ok(Store {
+            pool,
+            blobs: options.blob_store.clone(),
+            hashing: options.password_hashing,
+            hasher,
+            dummy_hash,
+        })
+    }
+
+    /// Checks that the database accepts connections, an unreachable one is retried with a doubling delay.
+    async fn wait_until_reachable(
+        connect: &PgConnectOptions,
+        options: &DatabaseOptions,
+    ) -> sqlx::Result<()> {
+        let policy = RetryPolicy {
+            attempts: options.connect_retries + 1,
+            delay: options.retry_delay,
+            max_delay: Duration::MAX,
+        };
+        let conn = retry_if(
+            &policy,
+            || PgConnection::connect_with(connect),
+            |e| match e {
+                sqlx::Error::Io(_) => {
+                    warn!("Connecting to the database failed, it is retried! Error: {e}");
+                    true
+                }
+                _ => false,
+            },
+        )
+        .await?;
+        conn.close().await
+    }
+
+    /// Drops copies of foreign keys, e.g. "messages_text_id_fkey1",
+    /// the server used to add them on every start before the migrations were introduced.
+    ///
+    /// Each one is dropped by itself, there may be too many of them to lock at once.
+    async fn drop_duplicate_foreign_keys(pool: &PgPool) -> sqlx::Result<()> {
+        let duplicates: Vec<(String, String)> = sqlx::query_as(
+            r#"
+SELECT conrelid::regclass::text, conname::text FROM pg_constraint
+WHERE contype = 'f'
+AND conname ~ '^(messages_(from_user|text|file|img)_id|(files|images)_blob_id|chats_(msg|to_user)_id)_fkey\d+$';"#,
+        )
+        .fetch_all(pool)
+        .await?;
+        for (table, name) in duplicates {
+            sqlx::query(&format!(r#"ALTER TABLE {table} DROP CONSTRAINT "{name}";"#))
+                .execute(pool)
+                .await?;
+        }
+        Ok(())
     }
 
     /// Queries user by username.
@@ -165,47 +1023,364 @@ impl Database {
             .bind(username)
             .fetch_optional(pool)
             .await
-            .map_err(Error::Database)
+            .map_err(Error::from)
     }
 
-    pub(crate) async fn log_in(&self, user: impl Into<User>) -> Result<()> {
+    /// Stores the `bytes` unless they are already present, returns id of the blob.
+    ///
+    /// The bytes are handed to the [BlobStore] first, the row keeps them only when the store says so.
+    /// The conflicting row is updated to be returned,
+    /// unlike a separate select it is seen even when inserted concurrently.
+    async fn store_blob(&self, executor: impl PgExecutor<'_>, bytes: &[u8]) -> Result<i64> {
+        let hash = Sha256::digest(bytes);
+        let inline = self.blobs.put(&hash, bytes).await.map_err(Error::Blob)?;
+        sqlx::query_scalar(
+            "\
+INSERT INTO blobs (hash, bytes) VALUES ($1, $2)
+ON CONFLICT (hash) DO UPDATE SET bytes = COALESCE(blobs.bytes, EXCLUDED.bytes)
+RETURNING id;",
+        )
+        .bind(hash.as_slice())
+        .bind(inline)
+        .fetch_one(executor)
+        .await
+        .map_err(Error::from)
+    }
+
+    /// Returns the bytes of the blob queried as its hash and inline bytes.
+    ///
+    /// A blob missing in the store is logged and left out, it does not fail the whole query.
+    async fn load_blob(&self, hash: Option<Vec<u8>>, inline: Option<Vec<u8>>) -> Option<Vec<u8>> {
+        match self.blobs.get(&hash?, inline).await {
+            Ok(bytes) => Some(bytes),
+            Err(e) => {
+                warn!(
+                    "Loading a blob from the {} store failed! Error {e}",
+                    self.blobs
+                );
+                None
+            }
+        }
+    }
+
+    /// Checks the password, its hash is replaced when it is [outdated][PasswordHashing::outdated].
+    ///
+    /// The password of an unknown user is checked against the dummy hash,
+    /// so that the time of the answer does not tell whether the user exists.
+    async fn log_in(&self, user: impl Into<User>) -> Result<()> {
         let User { username, password } = user.into();
-        let user_db = {
-            let pool = self.pool.lock().await;
-            Self::query_user(&pool, &username)
-                .await?
-                .ok_or_else(|| Error::UserDoesNotExist(username.clone()))?
+        let stored = Self::query_user(&self.pool, &username)
+            .await?
+            .map(|user| user.password);
+        let checked = stored.clone().unwrap_or_else(|| self.dummy_hash.clone());
+        let (hasher, hashing) = (self.hasher.clone(), self.hashing);
+        let verified = tokio::task::spawn_blocking(move || {
+            let parsed = PasswordHash::new(&checked).map_err(Error::Security)?;
+            Ok::<_, Error>(match hasher.verify_password(password.as_bytes(), &parsed) {
+                Ok(()) => Some((password, hashing.outdated(&parsed))),
+                Err(_) => None,
+            })
+        })
+        .await
+        .map_err(Error::Hashing)??;
+        let Some(hash) = stored else {
+            return Err(Error::UserDoesNotExist(username));
         };
-        Argon2::default()
-            .verify_password(
-                password.as_bytes(),
-                &PasswordHash::new(&user_db.password).map_err(Error::Security)?,
-            )
-            .map_err(|_| Error::WrongPassword(username))
+        let Some((password, outdated)) = verified else {
+            return Err(Error::WrongPassword(username));
+        };
+        if outdated {
+            match self.rehash(&username, &hash, password).await {
+                Ok(()) => info!("Password hash of {username} upgraded"),
+                Err(e) => warn!("Upgrading the password hash of {username} failed! Error {e}"),
+            }
+        }
+        Ok(())
     }
 
-    pub(crate) async fn sign_up(&self, user: impl Into<User>) -> Result<()> {
+    /// Replaces the `old` hash of the user's password by one of the current cost,
+    /// unless the password was changed meanwhile.
+    async fn rehash(&self, username: &str, old: &str, password: String) -> Result<()> {
+        let new = self.hash_password(password).await?;
+        sqlx::query("UPDATE users SET password = $3 WHERE username = $1 AND password = $2;")
+            .bind(username)
+            .bind(old)
+            .bind(new)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+
+    /// Hashes the password with a new salt on a blocking thread.
+    async fn hash_password(&self, password: String) -> Result<String> {
+        let hasher = self.hasher.clone();
+        tokio::task::spawn_blocking(move || {
+            hasher
+                .hash_password(password.as_bytes(), &SaltString::generate(&mut OsRng))
+                .map(|hash| hash.to_string())
+                .map_err(Error::Security)
+        })
+        .await
+        .map_err(Error::Hashing)?
+    }
+
+    async fn sign_up(&self, user: impl Into<User>) -> Result<()> {
         let User { username, password } = user.into();
-        let password = Argon2::default()
-            .hash_password(password.as_bytes(), &SaltString::generate(&mut OsRng))
-            .map_err(Error::Security)?
-            .to_string();
+        let password = self.hash_password(password).await?;
 
-        let pool = self.pool.lock().await;
-        if Self::query_user(&pool, &username).await?.is_some() {
+        let inserted = sqlx::query(
+            "INSERT INTO users (username, password) VALUES ($1, $2) ON CONFLICT (username) DO NOTHING;",
+        )
+        .bind(username.clone())
+        .bind(password)
+        .execute(&self.pool)
+        .await?;
+        match inserted.rows_affected() {
+            0 => Err(Error::UsernameTaken(username)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Takes one use of the invitation and signs up the user in one transaction,
+    /// the use is given back when the username is taken.
+    async fn sign_up_invited(&self, user: impl Into<User>, invite: &str) -> Result<()> {
+        let User { username, password } = user.into();
+        // Hashed even for invalid codes, the answer takes as long.
+        let password = self.hash_password(password).await?;
+        let mut tx = self.pool.begin().await?;
+        let used = sqlx::query(
+            "\
+UPDATE invites SET uses_left = uses_left - 1
+WHERE code_hash = $1 AND uses_left > 0 AND (expires IS NULL OR expires > now());",
+        )
+        .bind(Sha256::digest(invite.trim().as_bytes()).to_vec())
+        .execute(&mut *tx)
+        .await?;
+        if used.rows_affected() == 0 {
+            return Err(Error::InvalidInvite);
+        }
+        let inserted = sqlx::query(
+            "INSERT INTO users (username, password) VALUES ($1, $2) ON CONFLICT (username) DO NOTHING;",
+        )
+        .bind(username.clone())
+        .bind(password)
+        .execute(&mut *tx)
+        .await?;
+        if inserted.rows_affected() == 0 {
             return Err(Error::UsernameTaken(username));
         }
-        sqlx::query("INSERT INTO users (username, password) VALUES ($1, $2);")
-            .bind(username.clone())
-            .bind(password)
-            .execute(&*pool)
+        tx.commit().await.map_err(Error::from)
+    }
+
+    /// Stores a new invitation, the code is 64 random bits, only its hash is stored.
+    ///
+    /// Durations too long to add to the current time never expire, uses beyond the range of the column
+    /// are as good as unlimited.
+    async fn create_invite(
+        &self,
+        creator: Option<&cli_ser::User>,
+        uses: u32,
+        valid_for: Option<Duration>,
+    ) -> Result<(String, Option<DateTime<Utc>>)> {
+        let code = format!("{:016x}", OsRng.next_u64());
+        let expires = valid_for
+            .and_then(|valid_for| chrono::Duration::from_std(valid_for).ok())
+            .and_then(|valid_for| Utc::now().checked_add_signed(valid_for));
+        sqlx::query(
+            "\
+INSERT INTO invites (code_hash, created_by, uses_left, expires)
+VALUES ($1, (SELECT id FROM users WHERE username = $2), $3, $4);",
+        )
+        .bind(Sha256::digest(code.as_bytes()).to_vec())
+        .bind(creator.map(|creator| creator.to_string()))
+        .bind(i32::try_from(uses).unwrap_or(i32::MAX))
+        .bind(expires)
+        .execute(&self.pool)
+        .await?;
+        Ok((code, expires))
+    }
+
+    /// Returns the user the subject logs in as, creates them on the first log-in.
+    ///
+    /// The user and the identity are inserted by one statement, a subject logging in twice at once
+    /// is looked up again. Nobody knows the password of the user, it is a hash of a random salt.
+    async fn identity_user(
+        &self,
+        issuer: &str,
+        subject: &str,
+        username: &str,
+    ) -> Result<cli_ser::User> {
+        if let Some(known) = self.identity(issuer, subject).await? {
+            return Ok(known.into());
+        }
+        let password = self
+            .hash_password(SaltString::generate(&mut OsRng).as_str().to_string())
+            .await?;
+        let created: Option<i64> = sqlx::query_scalar(
+            r#"
+WITH created AS (
+    INSERT INTO users (username, password) VALUES ($3, $4)
+    ON CONFLICT (username) DO NOTHING
+    RETURNING id
+)
+INSERT INTO identities (issuer, subject, user_id)
+SELECT $1, $2, id FROM created
+ON CONFLICT (issuer, subject) DO NOTHING
+RETURNING user_id;
+"#,
+        )
+        .bind(issuer)
+        .bind(subject)
+        .bind(username)
+        .bind(password)
+        .fetch_optional(&self.pool)
+        .await?;
+        match created {
+            Some(_) => Ok(username.to_string().into()),
+            None => match self.identity(issuer, subject).await? {
+                Some(known) => Ok(known.into()),
+                None => Err(Error::UsernameTaken(username.to_string())),
+            },
+        }
+    }
+
+    /// Username of the subject of the issuer, `None` before their first log-in.
+    async fn identity(&self, issuer: &str, subject: &str) -> Result<Option<String>> {
+        sqlx::query_scalar(
+            r#"
+SELECT users.username FROM identities
+JOIN users ON users.id = identities.user_id
+WHERE identities.issuer = $1 AND identities.subject = $2;
+"#,
+        )
+        .bind(issuer)
+        .bind(subject)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Error::from)
+    }
+
+    /// Signs up the user and grants them administrator rights.
+    async fn create_admin(&self, user: impl Into<User>) -> Result<()> {
+        let user = user.into();
+        let username = user.username.clone();
+        self.sign_up(user).await?;
+        sqlx::query("UPDATE users SET is_admin = true WHERE username = $1;")
+            .bind(username)
+            .execute(&self.pool)
             .await
             .map(|_| ())
-            .map_err(Error::Database)
+            .map_err(Error::from)
+    }
+
+    /// Returns whether the `user` has administrator rights, unknown users have none.
+    async fn is_admin(&self, user: &cli_ser::User) -> Result<bool> {
+        sqlx::query_scalar("SELECT is_admin FROM users WHERE username = $1;")
+            .bind(user.to_string())
+            .fetch_optional(&self.pool)
+            .await
+            .map(|is_admin| is_admin.unwrap_or(false))
+            .map_err(Error::from)
     }
 
-    /// Records information to the database about the `data` send to all users by the `user`.
-    pub(crate) async fn record_msg_to_all(&self, user: cli_ser::User, data: Data) -> Result<()> {
+    /// Adds the `bytes` to the storage the user used unless it would exceed their quota.
+    ///
+    /// The reservation is a single conditional update, the usage is read only to explain a failure.
+    async fn reserve_storage(
+        &self,
+        user: &cli_ser::User,
+        bytes: u64,
+        default_quota: Option<u64>,
+    ) -> Result<()> {
+        let bytes = i64::try_from(bytes).unwrap_or(i64::MAX);
+        let default_quota = default_quota.map(|quota| i64::try_from(quota).unwrap_or(i64::MAX));
+        loop {
+            let reserved = sqlx::query(
+                "\
+UPDATE users SET storage_used = storage_used + $2
+WHERE username = $1 AND (
+    COALESCE(storage_quota, $3::BIGINT) IS NULL
+    OR storage_used + $2 <= COALESCE(storage_quota, $3::BIGINT)
+);",
+            )
+            .bind(user.to_string())
+            .bind(bytes)
+            .bind(default_quota)
+            .execute(&self.pool)
+            .await?;
+            if reserved.rows_affected() > 0 {
+                return Ok(());
+            }
+            let usage: Option<(i64, Option<i64>)> = sqlx::query_as(
+                "SELECT storage_used, COALESCE(storage_quota, $2::BIGINT) FROM users WHERE username = $1;",
+            )
+            .bind(user.to_string())
+            .bind(default_quota)
+            .fetch_optional(&self.pool)
+            .await?;
+            match usage {
+                Some((used, Some(limit))) => {
+                    break Err(Error::QuotaExceeded {
+                        used: u64::try_from(used).unwrap_or_default(),
+                        limit: u64::try_from(limit).unwrap_or_default(),
+                    })
+                }
+                // The quota was lifted in the meantime.
+                Some((_, None)) => continue,
+                None => break Err(Error::UserDoesNotExist(user.to_string())),
+            }
+        }
+    }
+
+    /// Gives back the storage reserved for an attachment which was not stored.
+    async fn release_storage(&self, user: &cli_ser::User, bytes: u64) -> Result<()> {
+        sqlx::query(
+            "UPDATE users SET storage_used = GREATEST(storage_used - $2, 0) WHERE username = $1;",
+        )
+        .bind(user.to_string())
+        .bind(i64::try_from(bytes).unwrap_or(i64::MAX))
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(Error::from)
+    }
+
+    /// Sets the storage quota of the user, `None` returns them to the server's default.
+    async fn set_quota(&self, user: &cli_ser::User, bytes: Option<u64>) -> Result<()> {
+        let updated = sqlx::query("UPDATE users SET storage_quota = $2 WHERE username = $1;")
+            .bind(user.to_string())
+            .bind(bytes.map(|bytes| i64::try_from(bytes).unwrap_or(i64::MAX)))
+            .execute(&self.pool)
+            .await?;
+        match updated.rows_affected() {
+            0 => Err(Error::UserDoesNotExist(user.to_string())),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reserves `n` ids for messages which are going to be [recorded][Self::record_msgs].
+    async fn reserve_msg_ids(&self, n: i64) -> Result<Vec<i64>> {
+        sqlx::query_scalar(
+            "SELECT nextval(pg_get_serial_sequence('messages', 'id')) FROM generate_series(1, $1);",
+        )
+        .bind(n)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::from)
+    }
+
+    /// Records the messages in one transaction, either all of them are stored or none.
+    async fn record_msgs(&self, records: Vec<Arc<Record>>) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for record in records {
+            self.record_msg(&mut tx, &record).await?;
+        }
+        tx.commit().await.map_err(Error::from)
+    }
+
+    /// Records information to the database about the message sent to all users.
+    async fn record_msg(&self, conn: &mut PgConnection, record: &Record) -> Result<()> {
         let insert_data_and_msg = |insert_data, data_type| {
             format!(
                 "\
@@ -216,48 +1391,575 @@ WITH
   data as (
     {insert_data} RETURNING id
   )
-INSERT INTO messages (from_user_id, {data_type}, arrived)
-SELECT usr.id, data.id, current_timestamp FROM usr, data;"
+INSERT INTO messages (id, from_user_id, {data_type}, arrived, urgent)
+SELECT $2, usr.id, data.id, $3, {urgent} FROM usr, data;",
+                urgent = record.urgent,
             )
         };
-        let username = String::from(user);
-        let pool = self.pool.lock().await;
-        match data {
-            Data::Text(text) => {
-                sqlx::query(&insert_data_and_msg(
-                    "INSERT INTO texts (text) VALUES ($2)",
-                    "text_id",
-                ))
-                .bind(username)
-                .bind(text)
-                .execute(&*pool)
-                .await
+        let Record {
+            id,
+            from,
+            arrived,
+            content,
+            ..
+        } = record;
+        let sql;
+        let query = match content {
+            Content::Text(text) => {
+                sql = insert_data_and_msg("INSERT INTO texts (text) VALUES ($4)", "text_id");
+                sqlx::query(&sql)
+                    .bind(from)
+                    .bind(id)
+                    .bind(arrived)
+                    .bind(text)
             }
-            Data::File(file) => {
-                let (name, bytes): (String, Vec<u8>) = file.into();
-                sqlx::query(&insert_data_and_msg(
-                    "INSERT INTO files (name, bytes) VALUES ($2, $3)",
+            Content::File { name, bytes } => {
+                let blob_id = self.store_blob(&mut *conn, bytes).await?;
+                sql = insert_data_and_msg(
+                    "INSERT INTO files (name, blob_id) VALUES ($4, $5)",
                     "file_id",
-                ))
-                .bind(username)
-                .bind(name)
-                .bind(bytes)
-                .execute(&*pool)
-                .await
-            }
-            Data::Image(img) => {
-                let bytes: Vec<u8> = img.into();
-                sqlx::query(&insert_data_and_msg(
-                    "INSERT INTO images (bytes) VALUES ($2)",
+                );
+                sqlx::query(&sql)
+                    .bind(from)
+                    .bind(id)
+                    .bind(arrived)
+                    .bind(name)
+                    .bind(blob_id)
+            }
+            Content::Image { bytes, original } => {
+                let blob_id = self.store_blob(&mut *conn, bytes).await?;
+                let original_blob_id = match original {
+                    Some(original) => Some(self.store_blob(&mut *conn, original).await?),
+                    None => None,
+                };
+                sql = insert_data_and_msg(
+                    "INSERT INTO images (blob_id, original_blob_id) VALUES ($4, $5)",
                     "img_id",
-                ))
-                .bind(username)
-                .bind(bytes)
-                .execute(&*pool)
-                .await
+                );
+                sqlx::query(&sql)
+                    .bind(from)
+                    .bind(id)
+                    .bind(arrived)
+                    .bind(blob_id)
+                    .bind(original_blob_id)
+            }
+        };
+        query.execute(conn).await.map(|_| ()).map_err(Error::from)
+    }
+
+    /// Changes one part of the user's profile, the rest stays as it is.
+    async fn set_profile(&self, user: &cli_ser::User, change: cli::ProfileChange) -> Result<()> {
+        let upsert = |column| {
+            format!(
+                "\
+INSERT INTO profiles (user_id, {column})
+SELECT id, $2 FROM users WHERE username = $1
+ON CONFLICT (user_id) DO UPDATE SET {column} = EXCLUDED.{column};"
+            )
+        };
+        let pool = &self.pool;
+        match change {
+            cli::ProfileChange::DisplayName(name) => {
+                sqlx::query(&upsert("display_name"))
+                    .bind(user.to_string())
+                    .bind(name)
+                    .execute(pool)
+                    .await
+            }
+            cli::ProfileChange::Status(status) => {
+                sqlx::query(&upsert("status"))
+                    .bind(user.to_string())
+                    .bind(status)
+                    .execute(pool)
+                    .await
+            }
+            cli::ProfileChange::Avatar(avatar) => {
+                let blob_id = self.store_blob(pool, &Bytes::from(avatar)).await?;
+                sqlx::query(&upsert("avatar_blob_id"))
+                    .bind(user.to_string())
+                    .bind(blob_id)
+                    .execute(pool)
+                    .await
+            }
+            cli::ProfileChange::Email(email) => {
+                let updated = sqlx::query(
+                    "UPDATE users SET email = NULLIF(TRIM($2), '') WHERE username = $1;",
+                )
+                .bind(user.to_string())
+                .bind(&email)
+                .execute(pool)
+                .await;
+                match updated {
+                    Err(sqlx::Error::Database(e)) if e.is_unique_violation() => {
+                        return Err(Error::EmailTaken(email))
+                    }
+                    updated => updated,
+                }
             }
         }
         .map(|_| ())
-        .map_err(Error::Database)
+        .map_err(Error::from)
+    }
+
+    /// Makes a new reset code of the user with the address, valid for [RESET_CODE_VALIDITY].
+    ///
+    /// The code is 64 random bits, only its hash is stored.
+    async fn reset_code(&self, email: &str) -> Result<Option<(cli_ser::User, String)>> {
+        let code = format!("{:016x}", OsRng.next_u64());
+        let username: Option<String> = sqlx::query_scalar(&format!(
+            r#"
+INSERT INTO password_resets (user_id, code_hash, expires)
+SELECT id, $2, now() + interval '{RESET_CODE_VALIDITY}' FROM users WHERE lower(email) = lower(TRIM($1))
+ON CONFLICT (user_id) DO UPDATE SET code_hash = EXCLUDED.code_hash, expires = EXCLUDED.expires
+RETURNING (SELECT username FROM users WHERE users.id = password_resets.user_id);
+"#
+        ))
+        .bind(email)
+        .bind(Sha256::digest(code.as_bytes()).to_vec())
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(username.map(|username| (username.into(), code)))
+    }
+
+    async fn reset_code_user(&self, code: &str) -> Result<Option<cli_ser::User>> {
+        let username: Option<String> = sqlx::query_scalar(
+            r#"
+SELECT users.username FROM password_resets JOIN users ON users.id = password_resets.user_id
+WHERE code_hash = $1 AND expires > now();
+"#,
+        )
+        .bind(Sha256::digest(code.trim().as_bytes()).to_vec())
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(username.map(cli_ser::User::from))
+    }
+
+    /// Uses up the code and sets the new password of its user by one statement,
+    /// so a code can not be used twice.
+    async fn reset_password(
+        &self,
+        code: &str,
+        new_password: String,
+    ) -> Result<Option<cli_ser::User>> {
+        // Hashed even for invalid codes, the answer takes as long.
+        let password = self.hash_password(new_password).await?;
+        let username: Option<String> = sqlx::query_scalar(
+            r#"
+WITH used AS (
+    DELETE FROM password_resets WHERE code_hash = $1 AND expires > now()
+    RETURNING user_id
+)
+UPDATE users SET password = $2 FROM used WHERE users.id = used.user_id
+RETURNING users.username;
+"#,
+        )
+        .bind(Sha256::digest(code.trim().as_bytes()).to_vec())
+        .bind(password)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(username.map(cli_ser::User::from))
+    }
+
+    /// Returns the active and the pending secret, both `None` for unknown users.
+    async fn totp(&self, user: &cli_ser::User) -> Result<(Option<Vec<u8>>, Option<Vec<u8>>)> {
+        let secrets =
+            sqlx::query_as("SELECT totp_secret, totp_pending FROM users WHERE username = $1;")
+                .bind(user.to_string())
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(secrets.unwrap_or_default())
+    }
+
+    async fn set_totp_pending(&self, user: &cli_ser::User, sealed: Vec<u8>) -> Result<()> {
+        sqlx::query("UPDATE users SET totp_pending = $2 WHERE username = $1;")
+            .bind(user.to_string())
+            .bind(sealed)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Activates the secret only if it is still the pending one, so a secret enabled meanwhile
+    /// by another session is not activated unconfirmed.
+    async fn activate_totp(&self, user: &cli_ser::User, sealed: Vec<u8>) -> Result<bool> {
+        let activated = sqlx::query(
+            "UPDATE users SET totp_secret = totp_pending, totp_pending = NULL WHERE username = $1 AND totp_pending = $2;",
+        )
+        .bind(user.to_string())
+        .bind(sealed)
+        .execute(&self.pool)
+        .await?;
+        Ok(activated.rows_affected() == 1)
+    }
+
+    async fn disable_totp(&self, user: &cli_ser::User) -> Result<()> {
+        sqlx::query(
+            "UPDATE users SET totp_secret = NULL, totp_pending = NULL WHERE username = $1;",
+        )
+        .bind(user.to_string())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Returns the profile of the user, `None` when the user does not exist.
+    async fn profile(&self, user: &cli_ser::User) -> Result<Option<UserProfile>> {
+        type Row = (
+            Option<String>,
+            Option<String>,
+            Option<Vec<u8>>,
+            Option<Vec<u8>>,
+        );
+        let row: Option<Row> = sqlx::query_as(
+            "\
+SELECT profiles.display_name, profiles.status, blobs.hash, blobs.bytes FROM users
+LEFT JOIN profiles ON profiles.user_id = users.id
+LEFT JOIN blobs ON blobs.id = profiles.avatar_blob_id
+WHERE users.username = $1;",
+        )
+        .bind(user.to_string())
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Error::from)?;
+        let Some((display_name, status, hash, avatar)) = row else {
+            return Ok(None);
+        };
+        let avatar = self.load_blob(hash, avatar).await;
+        Ok(Some(UserProfile {
+            display_name,
+            status,
+            avatar: avatar.and_then(|bytes| Image::try_from(bytes).ok()),
+        }))
+    }
+
+    /// Replaces the notification preferences of the user.
+    async fn set_prefs(&self, user: &cli_ser::User, prefs: Preferences) -> Result<()> {
+        let Preferences {
+            muted,
+            patterns,
+            mentions_only,
+            history_length,
+        } = prefs;
+        let muted: Vec<String> = muted.into_iter().map(String::from).collect();
+        // Lengths over the range of the column are as good as unlimited.
+        let history_length = history_length.map(|n| i32::try_from(n).unwrap_or(i32::MAX));
+        sqlx::query(
+            "\
+INSERT INTO preferences (user_id, muted, patterns, mentions_only, history_length)
+SELECT id, $2, $3, $4, $5 FROM users WHERE username = $1
+ON CONFLICT (user_id) DO UPDATE SET muted = EXCLUDED.muted, patterns = EXCLUDED.patterns,
+mentions_only = EXCLUDED.mentions_only, history_length = EXCLUDED.history_length;",
+        )
+        .bind(user.to_string())
+        .bind(muted)
+        .bind(patterns)
+        .bind(mentions_only)
+        .bind(history_length)
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(Error::from)
+    }
+
+    /// Returns the notification preferences of the user, the defaults if they did not set any.
+    async fn prefs(&self, user: &cli_ser::User) -> Result<Preferences> {
+        let row: Option<(Vec<String>, Vec<String>, bool, Option<i32>)> = sqlx::query_as(
+            "\
+SELECT preferences.muted, preferences.patterns, preferences.mentions_only, preferences.history_length
+FROM preferences JOIN users ON preferences.user_id = users.id
+WHERE users.username = $1;",
+        )
+        .bind(user.to_string())
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(Error::from)?;
+        let Some((muted, patterns, mentions_only, history_length)) = row else {
+            return Ok(Preferences::default());
+        };
+        Ok(Preferences {
+            muted: muted.into_iter().map(cli_ser::User::from).collect(),
+            patterns,
+            mentions_only,
+            history_length: history_length.and_then(|n| u32::try_from(n).ok()),
+        })
+    }
+
+    /// Returns the display name of the user if they set any.
+    async fn display_name(&self, user: &cli_ser::User) -> Result<Option<String>> {
+        sqlx::query_scalar(
+            "\
+SELECT profiles.display_name FROM profiles JOIN users ON profiles.user_id = users.id
+WHERE users.username = $1;",
+        )
+        .bind(user.to_string())
+        .fetch_optional(&self.pool)
+        .await
+        .map(Option::flatten)
+        .map_err(Error::from)
+    }
+
+    /// Records that the `user` read the message, reading it again keeps the first time.
+    async fn mark_read(&self, user: &cli_ser::User, msg_id: i64) -> Result<()> {
+        sqlx::query(
+            "\
+INSERT INTO chats (msg_id, to_user_id, when_recv)
+SELECT messages.id, users.id, current_timestamp FROM messages, users
+WHERE messages.id = $1 AND users.username = $2 AND NOT EXISTS (
+  SELECT 1 FROM chats WHERE msg_id = $1 AND to_user_id = users.id AND when_recv IS NOT NULL
+);",
+        )
+        .bind(msg_id)
+        .bind(user.to_string())
+        .execute(&self.pool)
+        .await
+        .map(|_| ())
+        .map_err(Error::from)
+    }
+
+    /// Returns all usernames in alphabetical order.
+    async fn usernames(&self) -> Result<Vec<String>> {
+        sqlx::query_scalar("SELECT username FROM users ORDER BY username;")
+            .fetch_all(&self.pool)
+            .await
+            .map_err(Error::from)
+    }
+
+    /// Returns at most `limit` messages older than the message `before`, newest first.
+    async fn messages(&self, before: Option<i64>, limit: i64) -> Result<Vec<Stored>> {
+        sqlx::query_as(&format!(
+            "\
+SELECT {STORED_COLUMNS} {STORED_TABLES}
+WHERE $1::bigint IS NULL OR messages.id < $1
+ORDER BY messages.id DESC
+LIMIT $2;"
+        ))
+        .bind(before)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::from)
+    }
+
+    /// Returns messages which arrived since the time along with their attachments, oldest first.
+    async fn archive(&self, since: Option<DateTime<Utc>>) -> Result<Vec<Archived>> {
+        let rows: Vec<ArchivedRow> = sqlx::query_as(&format!(
+            "\
+SELECT {STORED_COLUMNS}, blobs.hash, blobs.bytes {STORED_TABLES}
+LEFT JOIN images ON images.id = messages.img_id
+LEFT JOIN blobs ON blobs.id = COALESCE(files.blob_id, images.blob_id)
+WHERE $1::timestamptz IS NULL OR messages.arrived >= $1
+ORDER BY messages.id;"
+        ))
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut archived = Vec::with_capacity(rows.len());
+        for ArchivedRow { msg, hash, bytes } in rows {
+            let bytes = self.load_blob(hash, bytes).await;
+            archived.push(Archived { msg, bytes });
+        }
+        Ok(archived)
+    }
+
+    async fn audit(
+        &self,
+        event: AuditEvent,
+        username: Option<String>,
+        addr: Option<SocketAddr>,
+        detail: Option<String>,
+    ) -> Result<()> {
+        sqlx::query("INSERT INTO audit (event, username, addr, detail) VALUES ($1, $2, $3, $4);")
+            .bind(event.as_str())
+            .bind(username)
+            .bind(addr.map(|addr| addr.to_string()))
+            .bind(detail)
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(Error::from)
+    }
+
+    async fn audit_log(&self, before: Option<i64>, limit: i64) -> Result<Vec<Audited>> {
+        sqlx::query_as(
+            r#"
+SELECT id, to_char(at AT TIME ZONE 'UTC', 'YYYY-MM-DD"T"HH24:MI:SS"Z"') AS at,
+  event, username, addr, detail
+FROM audit
+WHERE $1::bigint IS NULL OR id < $1
+ORDER BY id DESC
+LIMIT $2;"#,
+        )
+        .bind(before)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(Error::from)
+    }
+
+    /// Deletes the messages in one transaction, the blobs kept outside of the database
+    /// are removed once it is committed.
+    async fn purge(
+        &self,
+        before: Option<DateTime<Utc>>,
+        keep: Option<u64>,
+        dry_run: bool,
+    ) -> Result<Purged> {
+        let keep = keep.map(|keep| i64::try_from(keep).unwrap_or(i64::MAX));
+        let mut tx = self.pool.begin().await?;
+        let doomed: Vec<(i64, Option<i64>, Option<i64>, Option<i64>)> = sqlx::query_as(
+            "\
+SELECT id, text_id, file_id, img_id FROM messages
+WHERE ($1::timestamptz IS NOT NULL AND arrived < $1)
+OR ($2::bigint IS NOT NULL AND id NOT IN (SELECT id FROM messages ORDER BY id DESC LIMIT $2));",
+        )
+        .bind(before)
+        .bind(keep)
+        .fetch_all(&mut *tx)
+        .await?;
+        let ids: Vec<i64> = doomed.iter().map(|row| row.0).collect();
+        let texts: Vec<i64> = doomed.iter().filter_map(|row| row.1).collect();
+        let files: Vec<i64> = doomed.iter().filter_map(|row| row.2).collect();
+        let images: Vec<i64> = doomed.iter().filter_map(|row| row.3).collect();
+        let mut purged = Purged {
+            messages: ids.len() as u64,
+            attachments: (files.len() + images.len()) as u64,
+            blobs: 0,
+        };
+        if dry_run || ids.is_empty() {
+            return Ok(purged);
+        }
+        sqlx::query("DELETE FROM chats WHERE msg_id = ANY($1);")
+            .bind(&ids)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM messages WHERE id = ANY($1);")
+            .bind(&ids)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM texts WHERE id = ANY($1);")
+            .bind(&texts)
+            .execute(&mut *tx)
+            .await?;
+        let mut blobs: Vec<Option<i64>> =
+            sqlx::query_scalar("DELETE FROM files WHERE id = ANY($1) RETURNING blob_id;")
+                .bind(&files)
+                .fetch_all(&mut *tx)
+                .await?;
+        let image_blobs: Vec<(Option<i64>, Option<i64>)> = sqlx::query_as(
+            "DELETE FROM images WHERE id = ANY($1) RETURNING blob_id, original_blob_id;",
+        )
+        .bind(&images)
+        .fetch_all(&mut *tx)
+        .await?;
+        blobs.extend(
+            image_blobs
+                .into_iter()
+                .flat_map(|(blob, original)| [blob, original]),
+        );
+        let blobs: Vec<i64> = blobs.into_iter().flatten().collect();
+        let removed: Vec<(Vec<u8>, bool)> = sqlx::query_as(
+            "\
+DELETE FROM blobs WHERE id = ANY($1)
+AND NOT EXISTS (SELECT 1 FROM files WHERE files.blob_id = blobs.id)
+AND NOT EXISTS (SELECT 1 FROM images WHERE blobs.id IN (images.blob_id, images.original_blob_id))
+AND NOT EXISTS (SELECT 1 FROM profiles WHERE profiles.avatar_blob_id = blobs.id)
+RETURNING hash, bytes IS NULL;",
+        )
+        .bind(&blobs)
+        .fetch_all(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        purged.blobs = removed.len() as u64;
+
+        let external: Vec<Vec<u8>> = removed
+            .into_iter()
+            .filter_map(|(hash, external)| external.then_some(hash))
+            .collect();
+        // A blob stored again in the meantime has a new row, its bytes are kept.
+        let stored_again: Vec<Vec<u8>> =
+            sqlx::query_scalar("SELECT hash FROM blobs WHERE hash = ANY($1);")
+                .bind(&external)
+                .fetch_all(&self.pool)
+                .await?;
+        for hash in external.iter().filter(|hash| !stored_again.contains(hash)) {
+            if let Err(e) = self.blobs.remove(hash).await {
+                warn!(
+                    "Removing a blob from the {} store failed! Error {e}",
+                    self.blobs
+                );
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Returns users who read the message in the order they did, `None` when the message is not from the `sender`.
+    async fn read_by(&self, sender: &cli_ser::User, msg_id: i64) -> Result<Option<Vec<String>>> {
+        let pool = &self.pool;
+        let sent: Option<i64> = sqlx::query_scalar(
+            "\
+SELECT messages.id FROM messages JOIN users ON messages.from_user_id = users.id
+WHERE messages.id = $1 AND users.username = $2;",
+        )
+        .bind(msg_id)
+        .bind(sender.to_string())
+        .fetch_optional(pool)
+        .await
+        .map_err(Error::from)?;
+        if sent.is_none() {
+            return Ok(None);
+        }
+        sqlx::query_scalar(
+            "\
+SELECT users.username FROM chats JOIN users ON chats.to_user_id = users.id
+WHERE chats.msg_id = $1 AND chats.when_recv IS NOT NULL
+ORDER BY chats.when_recv;",
+        )
+        .bind(msg_id)
+        .fetch_all(pool)
+        .await
+        .map(Some)
+        .map_err(Error::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn every_error_has_its_kind() {
+        let cancelled = tokio::spawn(std::future::pending::<()>());
+        cancelled.abort();
+        let errors = [
+            (Error::WrongPassword(String::new()), ErrorKind::Auth),
+            (Error::UserDoesNotExist(String::new()), ErrorKind::Auth),
+            (Error::UsernameTaken(String::new()), ErrorKind::Auth),
+            (Error::EmailTaken(String::new()), ErrorKind::Auth),
+            (Error::InvalidInvite, ErrorKind::Auth),
+            (Error::QuotaExceeded { used: 2, limit: 1 }, ErrorKind::Limit),
+            (
+                Error::Database(sqlx::Error::RowNotFound),
+                ErrorKind::Database,
+            ),
+            (Error::PoolExhausted, ErrorKind::Database),
+            (Error::Stopped, ErrorKind::Database),
+            (
+                Error::Blob(std::io::Error::new(std::io::ErrorKind::Other, "blob")),
+                ErrorKind::Io,
+            ),
+            (
+                Error::Security(argon2::password_hash::Error::Password),
+                ErrorKind::Auth,
+            ),
+            (
+                Error::Hashing(cancelled.await.unwrap_err()),
+                ErrorKind::Auth,
+            ),
+        ];
+        for (error, kind) in errors {
+            assert_eq!(error.kind(), kind, "{error:?}");
+            // Clients get the code alone, its range tells them the same kind.
+            assert_eq!(error.code().kind(), kind, "{error:?}");
+        }
     }
 }
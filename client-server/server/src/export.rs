@@ -0,0 +1,278 @@
+//! Export of the stored messages to files for backups and compliance, see [Export].
+use std::{
+    fmt, fs,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use anyhow::Context;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+
+use crate::db::Archived;
+
+/// Format of the exported messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// One JSON object per line.
+    Jsonl,
+    Csv,
+    /// Page with a table, images are shown inline.
+    Html,
+}
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsonl" => Ok(Format::Jsonl),
+            "csv" => Ok(Format::Csv),
+            "html" => Ok(Format::Html),
+            _ => Err(format!("\"{s}\" is not jsonl, csv nor html")),
+        }
+    }
+}
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Format::Jsonl => write!(f, "jsonl"),
+            Format::Csv => write!(f, "csv"),
+            Format::Html => write!(f, "html"),
+        }
+    }
+}
+
+/// Parses a date, e.g. "2024-01-31" (midnight UTC), or an RFC 3339 time, e.g. "2024-01-31T12:00:00+01:00".
+pub fn parse_since(s: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map(|date| date.and_time(Default::default()).and_utc())
+        .map_err(|_| format!("\"{s}\" is neither a date (YYYY-MM-DD) nor an RFC 3339 time"))
+}
+
+/// What to export and where.
+///
+/// Attachments are written to the directory `<OUT>_attachments` next to the output,
+/// the exported messages refer to them by relative paths.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Export {
+    pub format: Format,
+    /// Only messages which arrived since then, all of them when `None`.
+    pub since: Option<DateTime<Utc>>,
+    pub out: PathBuf,
+}
+impl Export {
+    /// Directory of the attachments, e.g. `chat_attachments` for `chat.html`.
+    fn attachments(&self) -> PathBuf {
+        let stem = self.out.file_stem().unwrap_or_default().to_string_lossy();
+        self.out.with_file_name(format!("{stem}_attachments"))
+    }
+
+    /// Writes the messages and their attachments.
+    pub(crate) fn save(&self, messages: Vec<Archived>) -> anyhow::Result<()> {
+        let dir = self.attachments();
+        let mut rows = Vec::with_capacity(messages.len());
+        for Archived { msg, bytes } in messages {
+            let attachment = match (bytes, &msg.file, msg.image) {
+                (Some(bytes), file, image) if file.is_some() || image => {
+                    let name = attachment_name(msg.id, file.as_deref(), &bytes);
+                    fs::create_dir_all(&dir)
+                        .with_context(|| format!("Creating the directory {dir:?} failed"))?;
+                    let path = dir.join(&name);
+                    fs::write(&path, bytes)
+                        .with_context(|| format!("Writing the attachment {path:?} failed"))?;
+                    Some(format!(
+                        "{}/{name}",
+                        dir.file_name().unwrap().to_string_lossy()
+                    ))
+                }
+                _ => None,
+            };
+            rows.push(Row {
+                id: msg.id,
+                from: msg.from,
+                arrived: msg.arrived,
+                text: msg.text,
+                file: msg.file,
+                image: msg.image,
+                attachment,
+            });
+        }
+        let file = fs::File::create(&self.out)
+            .with_context(|| format!("Creating the export {:?} failed", self.out))?;
+        let mut out = BufWriter::new(file);
+        write(self.format, &rows, &mut out)
+            .and_then(|_| Ok(out.flush()?))
+            .with_context(|| format!("Writing the export {:?} failed", self.out))
+    }
+}
+
+/// File name of the attachment, prefixed by the message id so that names do not clash.
+fn attachment_name(id: i64, file: Option<&str>, bytes: &[u8]) -> String {
+    match file {
+        Some(name) => format!("{id}_{}", cli_ser::sanitize_file_name(name)),
+        None => match image_extension(bytes) {
+            Some(ext) => format!("{id}.{ext}"),
+            None => id.to_string(),
+        },
+    }
+}
+
+fn image_extension(bytes: &[u8]) -> Option<&'static str> {
+    let image = cli_ser::Image::try_from(bytes.to_vec()).ok()?;
+    image.format().extensions_str().first().copied()
+}
+
+/// Exported message.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Row {
+    id: i64,
+    from: String,
+    arrived: String,
+    text: Option<String>,
+    file: Option<String>,
+    image: bool,
+    /// Path of the attachment relative to the export.
+    attachment: Option<String>,
+}
+
+fn write(format: Format, rows: &[Row], out: &mut impl Write) -> anyhow::Result<()> {
+    match format {
+        Format::Jsonl => {
+            for row in rows {
+                serde_json::to_writer(&mut *out, row)?;
+                writeln!(out)?;
+            }
+        }
+        Format::Csv => {
+            let mut csv = csv::Writer::from_writer(out);
+            for row in rows {
+                csv.serialize(row)?;
+            }
+            csv.flush()?;
+        }
+        Format::Html => write_html(rows, out)?,
+    }
+    Ok(())
+}
+
+fn write_html(rows: &[Row], out: &mut impl Write) -> io::Result<()> {
+    writeln!(
+        out,
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Chat export</title></head>\n<body>\n<table>"
+    )?;
+    writeln!(
+        out,
+        "<tr><th>Id</th><th>Time</th><th>From</th><th>Message</th></tr>"
+    )?;
+    for row in rows {
+        let content = match (&row.text, &row.attachment) {
+            (Some(text), _) => escape(text),
+            (None, Some(path)) if row.image => format!("<img src=\"{}\">", escape(path)),
+            (None, Some(path)) => format!(
+                "<a href=\"{}\">{}</a>",
+                escape(path),
+                escape(row.file.as_deref().unwrap_or(path))
+            ),
+            (None, None) => String::new(),
+        };
+        writeln!(
+            out,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{content}</td></tr>",
+            row.id,
+            escape(&row.arrived),
+            escape(&row.from),
+        )?;
+    }
+    writeln!(out, "</table>\n</body>\n</html>")
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows() -> Vec<Row> {
+        vec![
+            Row {
+                id: 1,
+                from: "alice".to_string(),
+                arrived: "2024-01-31T12:00:00Z".to_string(),
+                text: Some("a, \"quoted\" <b>".to_string()),
+                file: None,
+                image: false,
+                attachment: None,
+            },
+            Row {
+                id: 2,
+                from: "bob".to_string(),
+                arrived: "2024-01-31T12:01:00Z".to_string(),
+                text: None,
+                file: Some("notes.txt".to_string()),
+                image: false,
+                attachment: Some("chat_attachments/2_notes.txt".to_string()),
+            },
+        ]
+    }
+
+    fn written(format: Format) -> String {
+        let mut out = vec![];
+        write(format, &rows(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn formats() {
+        assert!(written(Format::Jsonl).starts_with(
+            "{\"id\":1,\"from\":\"alice\",\"arrived\":\"2024-01-31T12:00:00Z\",\"text\":\"a, \\\"quoted\\\" <b>\""
+        ));
+        assert_eq!(
+            written(Format::Csv).lines().take(2).collect::<Vec<_>>(),
+            [
+                "id,from,arrived,text,file,image,attachment",
+                "1,alice,2024-01-31T12:00:00Z,\"a, \"\"quoted\"\" <b>\",,false,"
+            ]
+        );
+        let html = written(Format::Html);
+        assert!(html.contains("<td>a, &quot;quoted&quot; &lt;b&gt;</td>"));
+        assert!(html.contains("<a href=\"chat_attachments/2_notes.txt\">notes.txt</a>"));
+    }
+
+    #[test]
+    fn since_and_names() {
+        assert_eq!(
+            parse_since("2024-01-31").unwrap().to_rfc3339(),
+            "2024-01-31T00:00:00+00:00"
+        );
+        assert_eq!(
+            parse_since("2024-01-31T12:00:00+01:00")
+                .unwrap()
+                .to_rfc3339(),
+            "2024-01-31T11:00:00+00:00"
+        );
+        assert!(parse_since("yesterday").is_err());
+        assert_eq!(
+            attachment_name(7, Some("../../etc/passwd"), b""),
+            "7_passwd"
+        );
+        assert_eq!(attachment_name(8, None, b"not an image"), "8");
+        let export = Export {
+            format: Format::Html,
+            since: None,
+            out: PathBuf::from("backup/chat.html"),
+        };
+        assert_eq!(
+            export.attachments(),
+            PathBuf::from("backup/chat_attachments")
+        );
+    }
+}
@@ -0,0 +1,160 @@
+//! Content filtering of incoming data before it is broadcast, see [MessageFilter].
+use std::{collections::HashSet, fs, path::Path};
+
+use anyhow::Context;
+use cli_ser::{Data, User};
+
+/// Decides whether the data sent by the user may be broadcast.
+///
+/// Filters are chained by the [Server][crate::Server::filter], the first rejection wins.
+pub trait MessageFilter: Send + Sync {
+    /// Returns the reason of the rejection, it is told to the sender.
+    fn check(&self, user: &User, data: &Data) -> Result<(), String>;
+}
+
+/// Filters applied one after another.
+#[derive(Default)]
+pub(crate) struct Chain(Vec<Box<dyn MessageFilter>>);
+impl Chain {
+    pub(crate) fn push(&mut self, filter: impl MessageFilter + 'static) {
+        self.0.push(Box::new(filter));
+    }
+}
+impl From<Vec<Box<dyn MessageFilter>>> for Chain {
+    fn from(filters: Vec<Box<dyn MessageFilter>>) -> Self {
+        Chain(filters)
+    }
+}
+impl MessageFilter for Chain {
+    fn check(&self, user: &User, data: &Data) -> Result<(), String> {
+        self.0
+            .iter()
+            .try_for_each(|filter| filter.check(user, data))
+    }
+}
+
+/// Rejects texts longer than the given number of characters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaxTextLength(pub usize);
+impl MessageFilter for MaxTextLength {
+    fn check(&self, _: &User, data: &Data) -> Result<(), String> {
+        match data {
+            Data::Text(text) if text.chars().count() > self.0 => {
+                Err(format!("texts are limited to {} characters", self.0))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Rejects texts containing any of the words, letter case does not matter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BannedWords(HashSet<String>);
+impl BannedWords {
+    pub fn new<S: AsRef<str>>(words: impl IntoIterator<Item = S>) -> Self {
+        BannedWords(
+            words
+                .into_iter()
+                .map(|w| w.as_ref().trim().to_lowercase())
+                .filter(|w| !w.is_empty() && !w.starts_with('#'))
+                .collect(),
+        )
+    }
+
+    /// Loads the words from the file, one per line, lines starting with `#` are comments.
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Reading the banned words {path:?} failed"))?;
+        Ok(Self::new(content.lines()))
+    }
+}
+impl MessageFilter for BannedWords {
+    fn check(&self, _: &User, data: &Data) -> Result<(), String> {
+        let Data::Text(text) = data else {
+            return Ok(());
+        };
+        match text
+            .split(|c: char| !c.is_alphanumeric())
+            .find(|word| self.0.contains(&word.to_lowercase()))
+        {
+            Some(word) => Err(format!("the word \"{word}\" is not allowed")),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Lets through only files, images and media of the given types, texts are not affected.
+///
+/// Types are file extensions, e.g. "pdf" or "png", images match by any extension of their format,
+/// files and media by the extension of their name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttachmentTypes(HashSet<String>);
+impl AttachmentTypes {
+    pub fn new<S: AsRef<str>>(types: impl IntoIterator<Item = S>) -> Self {
+        AttachmentTypes(
+            types
+                .into_iter()
+                .map(|t| t.as_ref().trim().trim_start_matches('.').to_lowercase())
+                .collect(),
+        )
+    }
+
+    fn allows_name(&self, name: &str) -> bool {
+        Path::new(name)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| self.0.contains(&ext.to_lowercase()))
+    }
+}
+impl MessageFilter for AttachmentTypes {
+    fn check(&self, _: &User, data: &Data) -> Result<(), String> {
+        let allowed = match data {
+            Data::Text(_) => return Ok(()),
+            Data::File(file) => self.allows_name(file.name()),
+            Data::Media(media) => self.allows_name(media.name()),
+            Data::Image(image) => image
+                .format()
+                .extensions_str()
+                .iter()
+                .any(|ext| self.0.contains(*ext)),
+        };
+        match allowed {
+            true => Ok(()),
+            false => {
+                let mut types: Vec<_> = self.0.iter().map(String::as_str).collect();
+                types.sort();
+                Err(format!("only {} attachments are allowed", types.join(", ")))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Data {
+        Data::Text(s.to_string())
+    }
+
+    #[test]
+    fn chained_filters() {
+        let user = User::from("filtered".to_string());
+        let mut chain = Chain::default();
+        assert!(chain.check(&user, &text("anything")).is_ok());
+        chain.push(MaxTextLength(10));
+        chain.push(BannedWords::new(["# comment", "Spam", ""]));
+        chain.push(AttachmentTypes::new([".PDF", "png"]));
+        assert!(chain.check(&user, &text("short")).is_ok());
+        assert!(chain.check(&user, &text("no spammer")).is_ok());
+        assert_eq!(
+            chain.check(&user, &text("buy SPAM!")),
+            Err("the word \"SPAM\" is not allowed".to_string())
+        );
+        assert_eq!(
+            chain.check(&user, &text("longer than ten")),
+            Err("texts are limited to 10 characters".to_string())
+        );
+    }
+}
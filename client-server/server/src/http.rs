@@ -0,0 +1,186 @@
+//! Optional HTTP API for the message history and administration, the endpoints are listed at [Server::http][crate::Server::http].
+//!
+//! Every request needs the header `Authorization: Bearer <TOKEN>`.
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    extract::{Query, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::net::TcpListener;
+use tracing::{error, info};
+
+use crate::{db, parse_level, queue, Shared, Task::Announce};
+
+/// Messages (or audit log entries) in one page unless the request asks for fewer.
+const PAGE_DEFAULT: i64 = 50;
+/// Messages in one page at most.
+const PAGE_MAX: i64 = 200;
+
+#[derive(Clone)]
+struct Api {
+    shared: Shared,
+    /// Digest of the token, requests are checked by comparing theirs of the same length.
+    token: Arc<[u8]>,
+}
+
+/// Serves the API until the listener fails.
+pub(crate) async fn serve(
+    listener: TcpListener,
+    shared: Shared,
+    token: String,
+) -> anyhow::Result<()> {
+    let api = Api {
+        shared,
+        token: Sha256::digest(token).to_vec().into(),
+    };
+    let app = Router::new()
+        .route("/users", get(users))
+        .route("/messages", get(messages))
+        .route("/audit", get(audit))
+        .route("/announcements", post(announce))
+        .route("/log-level", get(log_level).put(set_log_level))
+        .route("/memory", get(memory))
+        .route_layer(middleware::from_fn_with_state(api.clone(), authorize))
+        .with_state(api);
+    axum::serve(listener, app).await.context("HTTP API failed.")
+}
+
+/// Lets through only requests with the right token.
+async fn authorize(State(api): State<Api>, request: Request, next: Next) -> Response {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match token.is_some_and(|token| same(&Sha256::digest(token), &api.token)) {
+        true => next.run(request).await,
+        false => (StatusCode::UNAUTHORIZED, "missing or wrong token").into_response(),
+    }
+}
+
+/// Compares the digests in time independent of where they differ.
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Failure of the server, the details are logged rather than answered.
+struct Failure(anyhow::Error);
+impl<E: Into<anyhow::Error>> From<E> for Failure {
+    fn from(e: E) -> Self {
+        Failure(e.into())
+    }
+}
+impl IntoResponse for Failure {
+    fn into_response(self) -> Response {
+        error!("HTTP request failed! Error {:#}", self.0);
+        (StatusCode::INTERNAL_SERVER_ERROR, "internal error").into_response()
+    }
+}
+
+async fn users(State(api): State<Api>) -> Result<Json<Vec<String>>, Failure> {
+    Ok(Json(api.shared.db.usernames().await?))
+}
+
+#[derive(Deserialize)]
+struct Page {
+    before: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct Messages {
+    messages: Vec<db::Stored>,
+    next: Option<i64>,
+}
+
+async fn messages(
+    State(api): State<Api>,
+    Query(page): Query<Page>,
+) -> Result<Json<Messages>, Failure> {
+    let limit = page.limit.unwrap_or(PAGE_DEFAULT).clamp(1, PAGE_MAX);
+    api.shared.persister.flush().await;
+    let messages = api.shared.db.messages(page.before, limit).await?;
+    let next = match messages.len() as i64 == limit {
+        true => messages.last().map(|msg| msg.id),
+        false => None,
+    };
+    Ok(Json(Messages { messages, next }))
+}
+
+#[derive(Serialize)]
+struct AuditLog {
+    entries: Vec<db::Audited>,
+    next: Option<i64>,
+}
+
+async fn audit(
+    State(api): State<Api>,
+    Query(page): Query<Page>,
+) -> Result<Json<AuditLog>, Failure> {
+    let limit = page.limit.unwrap_or(PAGE_DEFAULT).clamp(1, PAGE_MAX);
+    let entries = api.shared.db.audit_log(page.before, limit).await?;
+    let next = match entries.len() as i64 == limit {
+        true => entries.last().map(|entry| entry.id),
+        false => None,
+    };
+    Ok(Json(AuditLog { entries, next }))
+}
+
+#[derive(Deserialize)]
+struct Announcement {
+    text: String,
+}
+
+async fn announce(
+    State(api): State<Api>,
+    Json(announcement): Json<Announcement>,
+) -> Result<StatusCode, Failure> {
+    info!("announcement over HTTP");
+    queue(&api.shared.tasks, Announce(announcement.text)).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[derive(Serialize)]
+struct Memory {
+    used: usize,
+    ceiling: usize,
+}
+
+async fn memory(State(api): State<Api>) -> Json<Memory> {
+    let budget = &api.shared.budget;
+    Json(Memory {
+        used: budget.used(),
+        ceiling: budget.ceiling(),
+    })
+}
+
+/// Answered when the server was not given the [LogLevel][crate::LogLevel].
+const NO_LOG_LEVEL: (StatusCode, &str) = (StatusCode::NOT_FOUND, "log level is not controlled");
+
+async fn log_level(State(api): State<Api>) -> Response {
+    match &api.shared.log_level {
+        Some(level) => level.current().into_response(),
+        None => NO_LOG_LEVEL.into_response(),
+    }
+}
+
+async fn set_log_level(State(api): State<Api>, filter: String) -> Result<Response, Failure> {
+    let Some(level) = &api.shared.log_level else {
+        return Ok(NO_LOG_LEVEL.into_response());
+    };
+    let filter = match parse_level(filter.trim()) {
+        Ok(filter) => filter,
+        Err(e) => return Ok((StatusCode::BAD_REQUEST, e).into_response()),
+    };
+    level.set(filter)?;
+    info!("log level set to {} over HTTP", level.current());
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
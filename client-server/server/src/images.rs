@@ -0,0 +1,51 @@
+//! Re-encoding of big incoming images, see [ImagePolicy].
+use cli_ser::{Image, ImageOutputFormat};
+use tracing::{info, warn};
+
+/// Images bigger than the [threshold][Self::threshold] are re-encoded to the [format][Self::format]
+/// before they are broadcast and stored.
+///
+/// The re-encoded image is used only when it is smaller than the original.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImagePolicy {
+    /// Size in bytes above which images are re-encoded.
+    pub threshold: usize,
+    /// Target encoding, e.g. `ImageOutputFormat::Jpeg(75)`.
+    pub format: ImageOutputFormat,
+    /// Whether the original bytes are stored in the database alongside the re-encoded image.
+    pub keep_original: bool,
+}
+impl ImagePolicy {
+    /// Applies the policy on the blocking thread pool, encoding is CPU heavy.
+    ///
+    /// Returns the image to use and the original one when it was replaced.
+    /// When anything fails, the original is returned as it is.
+    pub(crate) async fn apply(&self, image: Image) -> (Image, Option<Image>) {
+        let size = image.size();
+        if size <= self.threshold {
+            return (image, None);
+        }
+        let (original, format) = (image.clone(), self.format.clone());
+        match tokio::task::spawn_blocking(move || image.convert(format)).await {
+            Ok(Ok(converted)) if converted.size() < size => {
+                info!("image re-encoded from {size} to {} bytes", converted.size());
+                (converted, Some(original))
+            }
+            Ok(Ok(converted)) => {
+                info!(
+                    "re-encoding did not help ({size} to {} bytes)",
+                    converted.size()
+                );
+                (original, None)
+            }
+            Ok(Err(e)) => {
+                warn!("Re-encoding the image failed, the original is used! Error {e}");
+                (original, None)
+            }
+            Err(e) => {
+                warn!("Re-encoding task failed, the original image is used! Error {e}");
+                (original, None)
+            }
+        }
+    }
+}
@@ -15,6 +15,23 @@
 //! details about the postgres url can be found [here](https://docs.rs/sqlx/latest/sqlx/postgres/struct.PgConnectOptions.html)
 //! for other databases see [ConnectOptions](https://docs.rs/sqlx/latest/sqlx/trait.ConnectOptions.html#implementors).
 //!
+//! The tables are created and upgraded by the migrations in `migrations/` each time the server starts,
+//! databases created before the migrations were introduced are taken over by the first one.
+//!
+//! A database which is not up yet is retried a few times, the pool and the timeouts
+//! are set by the `--db-*` options, see [DatabaseOptions].
+//! When no connection becomes free in time, queries fail as "the database is overloaded or unreachable".
+//!
+//! Passwords are hashed by Argon2id of the cost set by `--argon2-*`, see [PasswordHashing],
+//! hashes of a lower cost are upgraded when their users log in.
+//!
+//! Bytes of files, images and avatars are kept in the database unless another store is chosen
+//! by `--blob-store`, e.g. `local:/var/lib/chat/blobs`, see [BlobStore]. The database keeps their hashes.
+//!
+//! Messages to all users are broadcast right away and stored in batches afterwards,
+//! a failed batch is logged and retried, see `--db-batch-size` and `--db-flush-interval-ms`.
+//! Buffered messages are stored before read receipts are looked up and when the server shuts down.
+//!
 //! ## TCP Address
 //!
 //! Host and port can be set via command line arguments, see:
@@ -22,31 +39,299 @@
 //! cargo run -- --help
 //! ```
 //! otherwise default [host][HOST_DEFAULT] and [port][PORT_DEFAULT] are used.
+//!
+//! To listen at several addresses, e.g. both IPv4 and IPv6, repeat `--listen`:
+//! ```sh
+//! cargo run -- --listen 0.0.0.0:11111 --listen [::]:11111
+//! ```
+//! IPv6 listeners accept only IPv6 connections, so they can share the port with IPv4 ones.
+//!
+//! With `--tls-cert <FILE>` and `--tls-key <FILE>` (PEM files, e.g. those written by `init`)
+//! the server accepts only TLS connections, see [Server::tls].
+//!
+//! ## Access
+//!
+//! Connections can be limited in total (`--max-connections`) and per IP address (`--max-connections-per-ip`),
+//! addresses can be allowed or denied by networks, e.g. `--allow 10.0.0.0/8 --deny 10.0.0.66`, see [AccessPolicy].
+//! Refused clients get [Refused][ser::Error::Refused] and are disconnected.
+//!
+//! When a user logs in again, their sessions get [NewSession][ser::Msg::NewSession],
+//! `--sessions-of-user` decides whether they stay (default), are kicked or the log-in fails, see [SessionPolicy].
+//! Messages reach the other sessions of their sender too, so the conversation is the same on every device,
+//! only the session which sent a message does not get it back.
+//!
+//! A failed log-in is answered by [InvalidCredentials][ser::Error::InvalidCredentials], whether the user
+//! does not exist or the password is wrong, and takes as long in both cases, so usernames can not be probed.
+//! `--distinct-login-errors` tells the two apart, e.g. for development.
+//!
+//! Users who set their email (a [profile change][cli::ProfileChange::Email]) can reset a forgotten password:
+//! a one-time code valid for 15 minutes is sent to the address by the [Mailer], by default it is only logged.
+//!
+//! With `--allow-guests` clients can join as [guests][cli::Auth::Guest] under a name nobody uses,
+//! e.g. for demos. Guests are not registered, their messages are broadcast [marked][ser::Msg::DataFrom::guest]
+//! but not stored, they have no profile, no quota and no read receipts, nothing of them gets to the database.
+//!
+//! With `--invite-only` clients sign up only with an [invitation][cli::Auth::SignUpInvited],
+//! administrators [create][cli::Admin::CreateInvite] the codes (`invite` in the console) for a number of sign-ups
+//! and optionally a number of days. Only hashes of the codes are stored, each sign-up uses one up.
+//! Other servers accept invitations as plain sign-ups.
+//!
+//! With `--signup-difficulty <BITS>` clients signing up have to solve a [challenge][ser::Msg::Challenge] first,
+//! a proof of work making automated sign-ups costly, see [Server::signup_challenge].
+//!
+//! Users of an OpenID Connect provider log in by its [tokens][cli::Auth::OidcToken] when the server
+//! trusts the provider, `--oidc-issuer <URL>` (with `--oidc-audience` and `--oidc-jwks`), see [Oidc].
+//! The subject of a token is linked to a user created on their first log-in, named by the `preferred_username`
+//! claim, and keeps logging in as that user. Passwords work alongside, such users just have none.
+//!
+//! With `--totp-key <BASE64>` users can [enable][cli::TwoFactor] two-factor authentication, their log-ins
+//! by a password then [require][ser::Msg::TotpRequired] a code of an authenticator app, see [TotpKey].
+//! The secrets are stored encrypted by the key, a client giving three wrong codes is disconnected.
+//!
+//! ## Configuration
+//!
+//! Options can be given by a TOML file (`--config <FILE>`, `server.toml` when it exists), see [ConfigFile].
+//! Command line arguments come first, then environment variables (`SERVER_PORT`, `DATABASE_URL`, ...,
+//! listed in `--help`), then the file and finally the defaults.
+//!
+//! ## Memory
+//!
+//! Bytes held by in-flight messages are limited by `--max-inflight-bytes`
+//! (default [MAX_INFLIGHT_BYTES_DEFAULT]), when the limit is reached,
+//! the server stops reading from the sockets until the queued messages are written.
+//! The bytes held are answered by `GET /memory` of the [HTTP API][Server::http].
+//!
+//! ## Images
+//!
+//! Big images can be re-encoded before they are broadcast and stored, see [ImagePolicy]
+//! and the `--reencode-images-over` option.
+//!
+//! Images (and avatars) exceeding the [limits][ImageLimits] are rejected before they are decoded or passed on,
+//! see [Server::image_limits] and the `--max-image-*` options.
+//!
+//! ## Storage Quotas
+//!
+//! Bytes of attachments (files, images and media) are counted per user, with `--storage-quota <BYTES>`
+//! uploads beyond the quota are answered by [QuotaExceeded][ser::Error::QuotaExceeded].
+//! Administrators set quotas of single users with [cli::Admin::SetQuota], see [Server::storage_quota].
+//!
+//! ## Filters
+//!
+//! Data can be checked before it is broadcast, the sender of rejected data gets [Rejected][ser::Error::Rejected].
+//! Built-in [filters][MessageFilter] are enabled by `--max-text-length`, `--banned-words <FILE>`
+//! and `--allowed-attachments <TYPES>`, others can be chained by [Server::filter].
+//!
+//! Texts which pass the filters can be reviewed by an external moderation service,
+//! `--moderation-url <URL>` (with `--moderation-timeout-ms` and `--moderation-on-failure`),
+//! see [HttpModerator][moderation::HttpModerator], or by any [Moderator] given to [Server::moderator].
+//!
+//! [Urgent][cli::Msg::Urgent] texts get through the filters of clients, so each user may send only
+//! a few of them, see [UrgentLimit] and the `--max-urgent` and `--urgent-period` options.
+//! All messages of a user are limited by `--rate-limit <N>` per `--rate-limit-period` seconds, see [RateLimit].
+//!
+//! ## Reloading
+//!
+//! On `SIGHUP` or `reload` in the console the server reads its configuration file again
+//! and puts the message of the day, the built-in filters (the banned words included), the urgent limit
+//! and the log level in place without dropping connections, options of the command line still take precedence.
+//! A changed message of the day is announced, a configuration which fails to load is logged and ignored,
+//! see [Server::reload_with].
+//!
+//! ## Bots
+//!
+//! With `--bot-socket <PATH>` bots can connect to a Unix socket, follow the data sent by users
+//! and send their own, see [cli_ser::bot].
+//!
+//! ## HTTP API
+//!
+//! With `--http-port <PORT>` and `--http-token <TOKEN>` the server answers HTTP requests at its host,
+//! users and the stored messages can be listed and announcements posted,
+//! every request has to carry the header `Authorization: Bearer <TOKEN>`, see [Server::http].
+//!
+//! ## Message of the Day
+//!
+//! Given by `--motd <TEXT>` or `--motd-file <FILE>`, it is sent to every client after authentication.
+//! Administrators can change it at runtime with [cli::Admin::SetMotd], the new one is announced to everyone.
+//!
+//! ## Audit Log
+//!
+//! Log-ins, failed log-ins, sign-ups, kicks and connections from denied addresses are recorded
+//! in the `audit` table with their time and source address.
+//! Administrators page through it by `GET /audit` of the HTTP API or `audit [N]` in the console.
+//!
+//! ## Logs
+//!
+//! Logs are written to the terminal and to `server.<TIME>.log` files,
+//! rotated daily by default, see [Logs] and the `--log-*` options.
+//! What the terminal gets is filtered by `--log-level`, e.g. `info,server::db=debug`,
+//! it can be changed while the server runs by `log <FILTER>` in the console or `PUT /log-level`
+//! of the HTTP API, without dropping the clients, see [LogLevel].
+//! Failures of the database are logged with their `code`, e.g. `code=E4501`,
+//! clients get it as [Other][ser::Error::Other] when their request failed, see [ErrorCode][cli_ser::ErrorCode].
+//!
+//! ## Console
+//!
+//! When the standard input is a terminal, the server takes commands from it,
+//! e.g. `list`, `kick <USER>`, `notice <TEXT>`, `stats` or `shutdown`, type `help` for all of them.
+//! Disable it with `--no-console`.
+//! Clients get the uptime, the connected sessions and the messages of the day by [Stats][cli::Msg::Stats] too.
+//!
+//! ## Presence
+//!
+//! Each session is online until its client [sets][cli::Msg::SetPresence] it away or do not disturb,
+//! the change is announced to everyone and newly connected clients get the users who are not online.
+//! A user is online when any of their sessions is. A text mentioning a user who is not online
+//! and left a message is answered by it, the auto-reply, see [PresenceChanged][ser::Msg::PresenceChanged].
+//! `list` in the console shows the presence of every session, its messages and bytes sent and received
+//! and how long it is idle, see [ConnectionStats][cli_ser::ConnectionStats].
+//!
+//! ## Preferences
+//!
+//! Users keep their notification [preferences][Preferences] (muted users, filter patterns, mentions only
+//! and the length of the history replay) on the server by [SetPrefs][cli::Msg::SetPrefs],
+//! so they follow the account to every client, which asks for them by [GetPrefs][cli::Msg::GetPrefs].
+//! They are stored per user in the `preferences` table, the server does not apply them itself.
+//! Guests get the defaults and can not store any.
+//!
+//! ## Provisioning
+//!
+//! A new server is set up by
+//! ```sh
+//! cargo run -- init --admin <USER> --tls-name chat.example.com
+//! ```
+//! which creates the database tables and the first administrator account, writes the configuration file
+//! with a new TOTP key and generates a self-signed TLS certificate, see [init] and [Provision].
+//! The configuration names the certificate, so the server started by it accepts only TLS connections.
+//! The password is asked for without echo (and the user if not given), or taken from
+//! the `SERVER_ADMIN_PASSWORD` environment variable, never from the command line.
+//!
+//! ## Export
+//!
+//! Stored messages can be dumped for backups, attachments are written next to the output, e.g.
+//! ```sh
+//! cargo run -- export --format html --since 2024-01-01 --out chat.html
+//! ```
+//! formats are `jsonl`, `csv` and `html`, see [export()].
+//!
+//! ## Retention
+//!
+//! With `--retention-max-age-days <DAYS>` or `--retention-max-messages <N>` older messages are deleted
+//! every `--retention-interval` seconds (hourly by default) along with their attachments,
+//! `--retention-dry-run` only logs what would be deleted, see [RetentionPolicy].
+//! The console's `stats` shows the totals, a single run can be done by
+//! ```sh
+//! cargo run -- purge --retention-max-age-days 30 --retention-dry-run
+//! ```
+//! see [purge()].
+//!
+//! ## Benchmarks
+//!
+//! `server-bench` loads a running server, clients send texts at a given rate
+//! and the latency of their broadcasts is reported as percentiles, e.g.
+//! ```sh
+//! cargo run --release --bin server-bench -- --addr 127.0.0.1:11111 --clients 50 --rate 20
+//! ```
+//! Encoding of the messages is benchmarked in `cli-ser`, see its `benches`.
+//!
+//! ## Testing
+//!
+//! Built at port 0 the server listens at an ephemeral port, see [Server::local_addr].
+//! With the `testing` feature, which the integration tests enable, `TestServer` runs such a server
+//! and the `testing` module has helpers to sign up users of their own, e.g. `testing::signed_up`.
 // TODO: Test client disconnection.
 
-use std::{env, net::SocketAddr, sync::Arc};
+use std::{
+    env,
+    fmt::{self, Display},
+    future::Future,
+    io,
+    net::SocketAddr,
+    path::PathBuf,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+};
 
 use anyhow::Context;
-use chrono::{offset::Utc, SecondsFormat};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use chrono::{SecondsFormat, Utc};
 use dashmap::DashMap;
 use tokio::{
-    net::{
-        tcp::{OwnedReadHalf, OwnedWriteHalf},
-        TcpListener, TcpStream,
+    io::{ReadHalf, WriteHalf},
+    net::{TcpListener, TcpStream},
+    select,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Notify,
     },
-    sync::mpsc::{self, Receiver, Sender},
+    task::JoinSet,
 };
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, info, info_span, instrument, warn, Instrument, Span};
 use tracing_appender::non_blocking::WorkerGuard;
 use tracing_subscriber::{
     filter::LevelFilter, prelude::__tracing_subscriber_SubscriberExt, util::SubscriberInitExt,
     Layer,
 };
 
+mod access;
+mod blobs;
+mod bot;
+pub mod config;
+mod console;
 mod db;
+pub mod export;
+pub mod filter;
+mod http;
+mod images;
+mod logs;
+pub mod mail;
+mod memory;
+pub mod moderation;
+mod oidc;
+mod persist;
+pub mod provision;
+mod rate;
+mod reload;
+pub mod retention;
+#[cfg(test)]
+mod simulation;
+mod stats;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+mod tls;
+mod totp;
+mod urgent;
 
 use crate::Task::*;
-use cli_ser::{cli, ser, Data, Error::DisconnectedStream, Messageable, User};
+pub use access::{AccessPolicy, Cidr, SessionPolicy};
+pub use blobs::BlobStore;
+use cli_ser::{
+    challenge, cli,
+    retry::{deadline, receive_with_timeout, send_with_timeout},
+    ser, Codec, ConnectionStats, Counted, Data, Encoded,
+    Error::{DisconnectedStream, TimedOut},
+    Image, ImageLimits, Messageable, MsgId, Preferences, Presence, User,
+};
+pub use config::ConfigFile;
+pub use db::{DatabaseOptions, PasswordHashing};
+pub use filter::MessageFilter;
+pub use images::ImagePolicy;
+pub use logs::{parse_level, LogLevel, LogRotation, Logs};
+pub use mail::Mailer;
+pub use memory::MAX_INFLIGHT_BYTES_DEFAULT;
+pub use moderation::{ModerationPolicy, Moderator};
+pub use oidc::Oidc;
+pub use provision::Provision;
+pub use rate::RateLimit;
+pub use reload::Reloadable;
+pub use retention::RetentionPolicy;
+#[cfg(any(test, feature = "testing"))]
+pub use testing::TestServer;
+pub use totp::TotpKey;
+pub use urgent::UrgentLimit;
 
 /// Default server host, used when not specified.
 pub const HOST_DEFAULT: [u8; 4] = [127, 0, 0, 1];
@@ -54,31 +339,497 @@ pub const HOST_DEFAULT: [u8; 4] = [127, 0, 0, 1];
 pub const PORT_DEFAULT: u16 = 11111;
 
 /// Tasks to be initially queued at the server and addressed later.
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 enum Task {
-    Broadcast(SocketAddr, User, Data),
-    SendErr(SocketAddr, ser::Error),
+    /// [DataFrom][ser::Msg::DataFrom] or [PresenceChanged][ser::Msg::PresenceChanged] to every session
+    /// except the one it originates from, if any.
+    ///
+    /// Other sessions of the sender get it as well.
+    ///
+    /// The span is the one of the incoming message, the broadcast is logged inside it.
+    Broadcast(Option<Origin>, ser::Msg, memory::Reservation, Span),
+    /// Answer to the client's request.
+    Reply(SessionId, ser::Msg),
+    /// Error caused by the client's message, the id refers to it if it was tagged.
+    SendErr(SessionId, Option<MsgId>, ser::Error),
+    /// Confirmation of the tagged message, queued after the tasks the message caused.
+    Ack(SessionId, MsgId),
+    /// Server information for every client.
+    Announce(String),
 }
 
-/// Channels to tasks which writes to specified Address over TCP.
-type Senders = DashMap<SocketAddr, Sender<ser::Msg>>;
+/// Client whose message caused a [Broadcast].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Origin {
+    session: SessionId,
+    /// Number of the message among the ones read from the client, starting at 1.
+    seq: u64,
+}
+
+/// Message queued for a client, messages for many clients are encoded once and shared by all of them.
+#[derive(Debug, Clone)]
+enum Outgoing {
+    Msg(ser::Msg),
+    Shared(Arc<Fanout>),
+}
+impl From<ser::Msg> for Outgoing {
+    fn from(msg: ser::Msg) -> Self {
+        Outgoing::Msg(msg)
+    }
+}
+impl Display for Outgoing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outgoing::Msg(msg) => write!(f, "{msg}"),
+            Outgoing::Shared(fanout) => write!(f, "{}", fanout.msg),
+        }
+    }
+}
+
+/// Message encoded for many clients, holds the in-flight memory of a [Broadcast] until the last client is written to.
+///
+/// It is encoded once per [Codec] the clients negotiated, by the default one up front.
+#[derive(Debug)]
+struct Fanout {
+    msg: ser::Msg,
+    encoded: std::sync::Mutex<Vec<(Codec, Encoded)>>,
+    _reservation: Option<memory::Reservation>,
+}
+impl Fanout {
+    fn new(msg: ser::Msg, reservation: Option<memory::Reservation>) -> cli_ser::Result<Self> {
+        let encoded = Encoded::new(&msg)?;
+        Ok(Fanout {
+            msg,
+            encoded: std::sync::Mutex::new(vec![(Codec::default(), encoded)]),
+            _reservation: reservation,
+        })
+    }
+
+    /// The message encoded by the `codec`, it is encoded by the first client using it.
+    fn encoded(&self, codec: Codec) -> cli_ser::Result<Encoded> {
+        let mut encoded = self.encoded.lock().expect("fanout lock poisoned");
+        if let Some((_, bytes)) = encoded.iter().find(|(c, _)| *c == codec) {
+            return Ok(bytes.clone());
+        }
+        let bytes = Encoded::new_with(codec, &self.msg)?;
+        encoded.push((codec, bytes.clone()));
+        Ok(bytes)
+    }
+}
+
+/// Id of an authenticated client, the number of its connection, unique within the run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct SessionId(u64);
+impl Display for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// Authenticated client, messages for it go through the `sender` to the task writing to its socket.
+struct Session {
+    user: User,
+    /// The user is a guest, not registered.
+    guest: bool,
+    addr: SocketAddr,
+    since: Instant,
+    /// Notified to disconnect the client.
+    kick: Arc<Notify>,
+    sender: Sender<Outgoing>,
+    presence: Presence,
+    /// Auto-reply to mentions while the session is not online.
+    presence_message: Option<String>,
+    /// Traffic of the client's socket since the authentication.
+    traffic: ConnectionStats,
+}
+
+/// Connected clients by their sessions, a user may have several.
+type Sessions = DashMap<SessionId, Session>;
+
+/// Sessions of the user, oldest first.
+fn sessions_of(sessions: &Sessions, user: &User) -> Vec<SessionId> {
+    let mut ids: Vec<_> = sessions
+        .iter()
+        .filter(|s| s.user == *user)
+        .map(|s| *s.key())
+        .collect();
+    ids.sort_by_key(|id| id.0);
+    ids
+}
+
+/// Presence and message of the user's newest session, `None` when any of their sessions is online.
+fn absence(sessions: &Sessions, user: &User) -> Option<(Presence, Option<String>)> {
+    let ids = sessions_of(sessions, user);
+    let online = ids.iter().any(|id| {
+        sessions
+            .get(id)
+            .is_some_and(|s| s.presence == Presence::Online)
+    });
+    let newest = sessions.get(ids.last()?)?;
+    match online {
+        true => None,
+        false => Some((newest.presence, newest.presence_message.clone())),
+    }
+}
+
+/// [PresenceChanged][ser::Msg::PresenceChanged] of every connected user who is not online.
+fn absent_users(sessions: &Sessions) -> Vec<ser::Msg> {
+    let mut users: Vec<User> = sessions.iter().map(|s| s.user.clone()).collect();
+    users.sort_by_key(|user| user.to_string());
+    users.dedup();
+    users
+        .into_iter()
+        .filter_map(|user| {
+            let (presence, message) = absence(sessions, &user)?;
+            Some(ser::Msg::PresenceChanged {
+                user,
+                presence,
+                message,
+            })
+        })
+        .collect()
+}
+
+/// State shared by the tasks managing the clients.
+#[derive(Clone)]
+struct Shared {
+    sessions: Arc<Sessions>,
+    db: Arc<db::Database>,
+    persister: Arc<persist::Persister>,
+    budget: Arc<memory::Budget>,
+    /// Message of the day, sent to each client right after authentication.
+    motd: Arc<RwLock<Option<String>>>,
+    image_policy: Option<ImagePolicy>,
+    image_limits: ImageLimits,
+    /// Quota of users without their own, see [Server::storage_quota].
+    storage_quota: Option<u64>,
+    filters: Arc<filter::Chain>,
+    /// Filters of the [Reloadable] settings, checked after the others.
+    reloaded_filters: Arc<reload::Swap<filter::Chain>>,
+    moderation: Option<Arc<moderation::Moderation>>,
+    urgent: Arc<urgent::Limiter>,
+    rate_limit: Option<RateLimit>,
+    /// Messages of each user counted towards the `rate_limit`.
+    sent: Arc<rate::Window>,
+    reload: Option<Arc<reload::Loader>>,
+    log_level: Option<LogLevel>,
+    gate: Arc<access::Gate>,
+    tls: Option<tokio_rustls::TlsAcceptor>,
+    sessions_of_user: SessionPolicy,
+    allow_guests: bool,
+    invite_only: bool,
+    signup_challenge: Option<u8>,
+    distinct_login_errors: bool,
+    oidc: Option<Arc<oidc::Verifier>>,
+    mailer: Arc<dyn Mailer>,
+    totp_key: Option<TotpKey>,
+    bots: Arc<bot::Subscribers>,
+    retention: Arc<retention::Metrics>,
+    stats: Arc<stats::Stats>,
+    tasks: Sender<Task>,
+}
 
 /// Server structure, first needs to be [built][Self::build] and then can be [run][Self::run].
 pub struct Server {
-    address: SocketAddr,
+    /// Listener bound when the server was built.
+    listener: TcpListener,
+    /// Further addresses, bound when the server runs.
+    addresses: Vec<SocketAddr>,
     db: Arc<db::Database>,
+    persister: Arc<persist::Persister>,
+    budget: Arc<memory::Budget>,
+    motd: Option<String>,
+    image_policy: Option<ImagePolicy>,
+    image_limits: ImageLimits,
+    storage_quota: Option<u64>,
+    filters: filter::Chain,
+    moderation: Option<Arc<moderation::Moderation>>,
+    urgent_limit: UrgentLimit,
+    rate_limit: Option<RateLimit>,
+    reload: Option<Arc<reload::Loader>>,
+    log_level: Option<LogLevel>,
+    access: AccessPolicy,
+    /// Certificate chain and key of the TLS connections.
+    tls: Option<(PathBuf, PathBuf)>,
+    sessions_of_user: SessionPolicy,
+    allow_guests: bool,
+    invite_only: bool,
+    signup_challenge: Option<u8>,
+    distinct_login_errors: bool,
+    oidc: Option<Arc<oidc::Verifier>>,
+    mailer: Arc<dyn Mailer>,
+    totp_key: Option<TotpKey>,
+    retention: Option<RetentionPolicy>,
+    bot_socket: Option<PathBuf>,
+    /// Address and token of the HTTP API.
+    http: Option<(SocketAddr, String)>,
+    console: bool,
+    shutdown: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
 }
 impl Server {
     /// Builds the server, especially database initialization, takes time.
+    ///
+    /// The server is bound to the `address` right away, port 0 picks a free one, see [Self::local_addr].
+    /// The database is given by the `DATABASE_URL` environment variable.
     pub async fn build(address: impl Into<SocketAddr>) -> anyhow::Result<Self> {
         let url = env::var("DATABASE_URL")
             .context("Environment variable DATABASE_URL was not set!")
             .context("Database specification failed, see server's documentation!")?;
+        Self::build_with_database(address, &url).await
+    }
+
+    /// Builds the server with the database at `url`.
+    pub async fn build_with_database(
+        address: impl Into<SocketAddr>,
+        url: &str,
+    ) -> anyhow::Result<Self> {
+        Self::build_with_database_options(address, url, &DatabaseOptions::default()).await
+    }
+
+    /// Builds the server with the database at `url` connected to as the `options` say.
+    pub async fn build_with_database_options(
+        address: impl Into<SocketAddr>,
+        url: &str,
+        options: &DatabaseOptions,
+    ) -> anyhow::Result<Self> {
         let address = address.into();
-        let db = Arc::new(db::Database::try_new(&url).await.context(
+        let listener =
+            bind(address).with_context(|| format!("Listening at {address:?} failed."))?;
+        let db = Arc::new(db::Database::try_new(url, options).await.context(
             "Database connection and initialization failed, see server's documentation!",
         )?);
-        Ok(Server { address, db })
+        let persister = Arc::new(persist::Persister::start(db.clone(), options));
+        let budget = Arc::new(memory::Budget::new(MAX_INFLIGHT_BYTES_DEFAULT));
+        Ok(Server {
+            listener,
+            addresses: vec![],
+            db,
+            persister,
+            budget,
+            motd: None,
+            image_policy: None,
+            image_limits: ImageLimits::default(),
+            storage_quota: None,
+            filters: filter::Chain::default(),
+            moderation: None,
+            urgent_limit: UrgentLimit::default(),
+            rate_limit: None,
+            reload: None,
+            log_level: None,
+            access: AccessPolicy::default(),
+            tls: None,
+            sessions_of_user: SessionPolicy::default(),
+            allow_guests: false,
+            invite_only: false,
+            signup_challenge: None,
+            distinct_login_errors: false,
+            oidc: None,
+            mailer: Arc::new(mail::LogMailer),
+            totp_key: None,
+            retention: None,
+            bot_socket: None,
+            http: None,
+            console: false,
+            shutdown: None,
+        })
+    }
+
+    /// Address the server was built with, the actual port if it was 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.listener
+            .local_addr()
+            .expect("bound listener has an address")
+    }
+
+    /// Listens at the `address` as well, all listeners share the clients.
+    pub fn listen(mut self, address: impl Into<SocketAddr>) -> Self {
+        self.addresses.push(address.into());
+        self
+    }
+
+    /// Sets the message of the day, administrators can change it while the server runs.
+    pub fn motd(mut self, motd: impl Into<String>) -> Self {
+        self.motd = Some(motd.into());
+        self
+    }
+
+    /// Sets the ceiling of bytes held by in-flight messages, see [MAX_INFLIGHT_BYTES_DEFAULT].
+    pub fn max_inflight_bytes(mut self, bytes: usize) -> Self {
+        self.budget = Arc::new(memory::Budget::new(bytes));
+        self
+    }
+
+    /// Sets the [policy][ImagePolicy] of re-encoding big incoming images, they are sent as they are otherwise.
+    pub fn reencode_images(mut self, policy: ImagePolicy) -> Self {
+        self.image_policy = Some(policy);
+        self
+    }
+
+    /// Sets the [limits][ImageLimits] of incoming images and avatars, [default][ImageLimits::default] otherwise.
+    pub fn image_limits(mut self, limits: ImageLimits) -> Self {
+        self.image_limits = limits;
+        self
+    }
+
+    /// Limits the bytes of attachments each user can upload in total, it is unlimited otherwise.
+    ///
+    /// Administrators can give single users other quotas with [cli::Admin::SetQuota].
+    pub fn storage_quota(mut self, bytes: u64) -> Self {
+        self.storage_quota = Some(bytes);
+        self
+    }
+
+    /// Adds the filter to the ones data has to pass before it is broadcast.
+    pub fn filter(mut self, filter: impl MessageFilter + 'static) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    /// Lets the moderator review texts after the filters, as the `policy` says.
+    pub fn moderator(
+        mut self,
+        moderator: impl Moderator + 'static,
+        policy: ModerationPolicy,
+    ) -> Self {
+        self.moderation = Some(Arc::new(moderation::Moderation::new(moderator, policy)));
+        self
+    }
+
+    /// Sets how many [urgent][cli::Msg::Urgent] texts each user may send, [default][UrgentLimit::default] otherwise.
+    pub fn urgent_limit(mut self, limit: UrgentLimit) -> Self {
+        self.urgent_limit = limit;
+        self
+    }
+
+    /// Limits how many messages each user may send, further ones are [rejected][ser::Error::Rejected],
+    /// they are not limited otherwise.
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// Loads the [Reloadable] settings when the server starts and again on `SIGHUP` or `reload` in the [console],
+    /// they replace the message of the day and the urgent limit given here, their filters follow those of [Self::filter].
+    pub fn reload_with(
+        mut self,
+        load: impl Fn() -> anyhow::Result<Reloadable> + Send + Sync + 'static,
+    ) -> Self {
+        self.reload = Some(Arc::new(load));
+        self
+    }
+
+    /// Lets the [log level][LogLevel] be changed by `log` in the [console], `PUT /log-level` of the [HTTP API][Self::http]
+    /// or on reload, see [Reloadable::log_level].
+    pub fn log_level(mut self, level: LogLevel) -> Self {
+        self.log_level = Some(level);
+        self
+    }
+
+    /// Sets who may connect and how many connections are allowed, see [AccessPolicy].
+    pub fn access(mut self, policy: AccessPolicy) -> Self {
+        self.access = policy;
+        self
+    }
+
+    /// Accepts only TLS connections, by the certificate chain and its key, both PEM files.
+    ///
+    /// The files are read when the server [runs][Self::run], e.g. those [init] writes.
+    pub fn tls(mut self, cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        self.tls = Some((cert.into(), key.into()));
+        self
+    }
+
+    /// Sets what happens when a user logs in while they already have a session, see [SessionPolicy].
+    pub fn sessions_of_user(mut self, policy: SessionPolicy) -> Self {
+        self.sessions_of_user = policy;
+        self
+    }
+
+    /// Lets clients join as [guests][cli::Auth::Guest], they are refused otherwise.
+    pub fn allow_guests(mut self) -> Self {
+        self.allow_guests = true;
+        self
+    }
+
+    /// Signs up only clients with an [invitation][cli::Auth::SignUpInvited] made by an administrator,
+    /// others get [InviteRequired][ser::Error::InviteRequired].
+    /// So do subjects of the [identity provider][Server::oidc] logging in for the first time.
+    pub fn invite_only(mut self) -> Self {
+        self.invite_only = true;
+        self
+    }
+
+    /// Asks clients signing up for a proof of work first, a [challenge][ser::Msg::Challenge]
+    /// of the `difficulty` in bits, each one more doubles the work. Clients give up on more than
+    /// [MAX_DIFFICULTY][challenge::MAX_DIFFICULTY], around 20 takes a second or less.
+    pub fn signup_challenge(mut self, difficulty: u8) -> Self {
+        self.signup_challenge = Some(difficulty);
+        self
+    }
+
+    /// Tells clients whether the user does not exist or the password is wrong,
+    /// e.g. for development, otherwise both are [InvalidCredentials][ser::Error::InvalidCredentials].
+    pub fn distinct_login_errors(mut self) -> Self {
+        self.distinct_login_errors = true;
+        self
+    }
+
+    /// Sends the password reset codes, they are [logged][mail::LogMailer] otherwise.
+    pub fn mailer(mut self, mailer: impl Mailer + 'static) -> Self {
+        self.mailer = Arc::new(mailer);
+        self
+    }
+
+    /// Lets users enable two-factor authentication, their secrets are encrypted by the `key`.
+    pub fn totp_key(mut self, key: TotpKey) -> Self {
+        self.totp_key = Some(key);
+        self
+    }
+
+    /// Logs in users by the tokens of the identity provider, see [Oidc].
+    pub fn oidc(mut self, oidc: Oidc) -> Self {
+        self.oidc = Some(Arc::new(oidc::Verifier::new(oidc)));
+        self
+    }
+
+    /// Deletes old messages in the background as the [policy][RetentionPolicy] says, everything is kept otherwise.
+    pub fn retention(mut self, policy: RetentionPolicy) -> Self {
+        self.retention = Some(policy);
+        self
+    }
+
+    /// Accepts [bots][cli_ser::bot] at the Unix socket, only the owner of the process may connect.
+    pub fn bot_socket(mut self, path: impl Into<PathBuf>) -> Self {
+        self.bot_socket = Some(path.into());
+        self
+    }
+
+    /// Serves the HTTP API at the `address`, requests need the `token`.
+    ///
+    /// Endpoints:
+    /// - `GET /users`, usernames in alphabetical order,
+    /// - `GET /messages?before=<ID>&limit=<N>`, stored messages newest first,
+    ///   `next` in the answer is the `before` of the following page,
+    /// - `GET /audit?before=<ID>&limit=<N>`, entries of the audit log newest first, paged as the messages,
+    /// - `POST /announcements` with `{"text": "..."}`, announces the text to every client,
+    /// - `GET /log-level`, the [log level][Self::log_level] filter as text,
+    /// - `PUT /log-level` with the filter as text, e.g. `info,server::db=debug`, puts it in place,
+    /// - `GET /memory`, bytes held by in-flight messages (`used`) and the [ceiling][Self::max_inflight_bytes].
+    pub fn http(mut self, address: impl Into<SocketAddr>, token: impl Into<String>) -> Self {
+        self.http = Some((address.into(), token.into()));
+        self
+    }
+
+    /// Takes [console] commands from the standard input, the server stops on `shutdown`.
+    pub fn console(mut self) -> Self {
+        self.console = true;
+        self
+    }
+
+    /// Stops the server once the `signal` completes, stored messages are flushed first.
+    pub fn shutdown_on(mut self, signal: impl Future<Output = ()> + Send + 'static) -> Self {
+        self.shutdown = Some(Box::pin(signal));
+        self
     }
 
     /// Runs the server, connections should be accepted immediately.
@@ -87,77 +838,435 @@ impl Server {
     }
 }
 
+/// Provisions a new server: creates the tables and the first administrator account in the database
+/// of the `provision`, then writes its files.
+///
+/// Nothing is done when any of the files exists.
+pub async fn init(
+    provision: &Provision,
+    options: &DatabaseOptions,
+    admin: cli::Credentials,
+) -> anyhow::Result<()> {
+    provision.check()?;
+    init_database(&provision.database_url, options, admin).await?;
+    provision.write()?;
+    info!(
+        "Configuration {:?} and the TLS certificate {:?} written.",
+        provision.config,
+        provision.tls_cert()
+    );
+    Ok(())
+}
+
+/// Provisions the database at `url` only: creates the tables and the first administrator account.
+pub async fn init_database(
+    url: &str,
+    options: &DatabaseOptions,
+    admin: cli::Credentials,
+) -> anyhow::Result<()> {
+    let db = db::Database::try_new(url, options)
+        .await
+        .context("Database connection and initialization failed, see server's documentation!")?;
+    let username = admin.user.to_string();
+    db.create_admin(admin)
+        .await
+        .with_context(|| format!("Creating the administrator \"{username}\" failed!"))?;
+    info!("Database initialized, administrator \"{username}\" created.");
+    Ok(())
+}
+
+/// Exports the messages stored in the database at `url`, see [Export][export::Export].
+pub async fn export(
+    url: &str,
+    options: &DatabaseOptions,
+    export: export::Export,
+) -> anyhow::Result<()> {
+    let db = db::Database::try_new(url, options)
+        .await
+        .context("Database connection and initialization failed, see server's documentation!")?;
+    let messages = db
+        .archive(export.since)
+        .await
+        .context("Reading the messages failed!")?;
+    let count = messages.len();
+    export.save(messages)?;
+    info!("{count} messages exported to {:?}.", export.out);
+    Ok(())
+}
+
+/// Deletes old messages in the database at `url` once, as the `policy` says, see [RetentionPolicy].
+pub async fn purge(
+    url: &str,
+    options: &DatabaseOptions,
+    policy: &RetentionPolicy,
+) -> anyhow::Result<retention::Purged> {
+    let db = db::Database::try_new(url, options)
+        .await
+        .context("Database connection and initialization failed, see server's documentation!")?;
+    let purged = retention::purge(&db, policy)
+        .await
+        .context("Deleting old messages failed!")?;
+    match policy.dry_run {
+        true => info!("Dry run, {purged} would be deleted."),
+        false => info!("{purged} deleted."),
+    }
+    Ok(purged)
+}
+
 /// Asynchronously listen for clients, reads their messages and acts accordingly.
 ///
-/// The server is bound to a specified address.
-/// In the main loop, the server processes tasks one at a time from its queue.
-/// The server is written as if it should run forever.
+/// The server is bound to the specified addresses, each one is served by its own listener.
+/// It runs until a listener fails, the [console] or the [shutdown signal][Server::shutdown_on] stops it.
+/// In the main loop, the server processes tasks in batches from its queue, see [route].
 async fn run(server: Server) -> anyhow::Result<()> {
-    let Server { address, db } = server;
-    let (task_producer, mut task_consumer) = mpsc::channel(1024);
-    let clients: Arc<Senders> = Arc::new(DashMap::new());
-    let listener = tokio::spawn(client_listener(address, task_producer, clients.clone(), db));
-    while let Some(task) = task_consumer.recv().await {
-        match task {
-            Broadcast(addr_from, user_from, data) => {
-                info!("broadcasting \"{data}\" from {user_from} at {addr_from:?}");
-                let msg = ser::Msg::DataFrom {
-                    data: data.clone(),
-                    from: user_from.clone(),
-                };
-                for client in clients.iter() {
-                    let (addr_to, msg_channel) = (client.key(), client.value());
-                    if addr_from != *addr_to {
-                        match msg_channel.send(msg.clone()).await {
-                            Ok(_) => debug!("broadcasting to {addr_to:?}"),
-                            Err(e) => warn!("broadcasting to {addr_to:?} failed, error {e}"),
-                        }
-                    }
+    let Server {
+        listener,
+        addresses,
+        db,
+        persister,
+        budget,
+        motd,
+        image_policy,
+        image_limits,
+        storage_quota,
+        filters,
+        moderation,
+        urgent_limit,
+        rate_limit,
+        reload,
+        log_level,
+        access,
+        tls,
+        sessions_of_user,
+        allow_guests,
+        invite_only,
+        signup_challenge,
+        distinct_login_errors,
+        oidc,
+        mailer,
+        totp_key,
+        retention,
+        bot_socket,
+        http,
+        console,
+        shutdown,
+    } = server;
+    let (task_producer, task_consumer) = mpsc::channel(1024);
+    let sessions: Arc<Sessions> = Arc::new(DashMap::new());
+    let shared = Shared {
+        sessions: sessions.clone(),
+        db,
+        persister: persister.clone(),
+        budget,
+        motd: Arc::new(RwLock::new(motd)),
+        image_policy,
+        image_limits,
+        storage_quota,
+        filters: Arc::new(filters),
+        reloaded_filters: Arc::new(reload::Swap::new(filter::Chain::default())),
+        moderation,
+        urgent: Arc::new(urgent::Limiter::new(urgent_limit)),
+        rate_limit,
+        sent: Arc::new(rate::Window::default()),
+        reload,
+        log_level,
+        gate: Arc::new(access::Gate::new(access)),
+        tls: match tls {
+            Some((cert, key)) => Some(tls::acceptor(&cert, &key)?),
+            None => None,
+        },
+        sessions_of_user,
+        allow_guests,
+        invite_only,
+        signup_challenge,
+        distinct_login_errors,
+        oidc,
+        mailer,
+        totp_key,
+        bots: Arc::new(DashMap::new()),
+        retention: Arc::new(retention::Metrics::default()),
+        stats: Arc::new(stats::Stats::default()),
+        tasks: task_producer,
+    };
+    if let Some(load) = &shared.reload {
+        let settings = load().context("Loading the settings failed!")?;
+        reload::apply(&shared, settings)?;
+    }
+    let mut listeners = JoinSet::new();
+    #[cfg(unix)]
+    if shared.reload.is_some() {
+        listeners.spawn(reload::on_hangup(shared.clone())?);
+    }
+    let conns = Arc::new(AtomicU64::new(0));
+    info!("Server is listening at {:?}", listener.local_addr()?);
+    listeners.spawn(client_listener(listener, shared.clone(), conns.clone()));
+    for address in addresses {
+        let listener =
+            bind(address).with_context(|| format!("Listening at {address:?} failed."))?;
+        info!("Server is listening at {address:?}");
+        listeners.spawn(client_listener(listener, shared.clone(), conns.clone()));
+    }
+    if let Some(path) = bot_socket {
+        #[cfg(unix)]
+        listeners.spawn(bot::listen(bot::bind(&path)?, shared.clone()));
+        #[cfg(not(unix))]
+        anyhow::bail!("Bot socket {path:?} is supported on Unix only.");
+    }
+    if let Some(policy) = retention {
+        info!("Retention {policy:?}");
+        listeners.spawn(retention::run(
+            shared.db.clone(),
+            policy,
+            shared.retention.clone(),
+        ));
+    }
+    if let Some((address, token)) = http {
+        let listener = bind(address).with_context(|| format!("HTTP API at {address:?} failed."))?;
+        info!("HTTP API is served at {address:?}");
+        listeners.spawn(http::serve(listener, shared.clone(), token));
+    }
+    let console = async {
+        match console {
+            true => console::run(shared).await,
+            false => std::future::pending().await,
+        }
+    };
+    let shutdown = async {
+        match shutdown {
+            Some(signal) => signal.await,
+            None => std::future::pending().await,
+        }
+    };
+    // Listeners are aborted when the set is dropped.
+    let result = select!(
+        _ = route(task_consumer, &sessions) => Ok(()),
+        Some(listener) = listeners.join_next() => listener?,
+        _ = console => {
+            info!("Shutting down as requested from the console.");
+            Ok(())
+        }
+        _ = shutdown => {
+            info!("Shutting down on the signal.");
+            Ok(())
+        }
+    );
+    persister.flush().await;
+    result
+}
+
+/// Tasks taken from the queue at once by [route].
+const ROUTE_BATCH: usize = 64;
+
+/// Processes tasks in batches of up to [ROUTE_BATCH] until all task producers are gone.
+///
+/// Consecutive broadcasts of a batch are grouped, each message is encoded once for all the clients
+/// and every client gets the whole group before the next task.
+///
+/// Messages of one sender reach each client in the order they were read ([sequence][Origin::seq]):
+/// the reader of the sender queues them one by one, the queue is routed in order
+/// and every client is written to from its own channel.
+async fn route(mut tasks: Receiver<Task>, sessions: &Sessions) {
+    let mut batch = Vec::with_capacity(ROUTE_BATCH);
+    let mut group = Vec::new();
+    while let Some(task) = tasks.recv().await {
+        batch.push(task);
+        while batch.len() < ROUTE_BATCH {
+            match tasks.try_recv() {
+                Ok(task) => batch.push(task),
+                Err(_) => break,
+            }
+        }
+        for task in batch.drain(..) {
+            match task {
+                Broadcast(from, msg, reservation, span) => {
+                    group.extend(encode(from, msg, reservation, &span));
+                }
+                task => {
+                    broadcast(sessions, &group).await;
+                    group.clear();
+                    route_one(task, sessions).await;
                 }
             }
-            SendErr(addr, err) => {
-                if let Some(channel) = clients.get(&addr) {
-                    if let Err(e) = channel.send(ser::Msg::Error(err.clone()).clone()).await {
-                        warn!("Sending error msg {err:?} to {addr} failed! Error: {e:?}");
-                    }
+        }
+        broadcast(sessions, &group).await;
+        group.clear();
+    }
+}
+
+/// Delivers the task addressed to particular sessions or to all of them.
+async fn route_one(task: Task, sessions: &Sessions) {
+    match task {
+        Broadcast(from, msg, reservation, span) => {
+            let encoded = encode(from, msg, reservation, &span);
+            broadcast(sessions, encoded.as_slice()).await
+        }
+        Reply(id_to, msg) => {
+            if let Some(session) = sessions.get(&id_to) {
+                if let Err(e) = session.sender.send(msg.into()).await {
+                    warn!("Replying to {id_to} failed! Error: {e:?}");
                 }
             }
         }
+        SendErr(id_to, id, err) => {
+            if let Some(session) = sessions.get(&id_to) {
+                let msg = ser::Msg::error_for(id, err.clone());
+                if let Err(e) = session.sender.send(msg.into()).await {
+                    warn!("Sending error msg {err:?} to {id_to} failed! Error: {e:?}");
+                }
+            }
+        }
+        Ack(id_to, id) => {
+            if let Some(session) = sessions.get(&id_to) {
+                if let Err(e) = session.sender.send(ser::Msg::Ack(id).into()).await {
+                    warn!("Acknowledging {id} to {id_to} failed! Error: {e:?}");
+                }
+            }
+        }
+        Announce(info) => {
+            info!("announcing \"{info}\"");
+            let msg = ser::Msg::ServerInfo(info);
+            let msg = match Fanout::new(msg.clone(), None) {
+                Ok(fanout) => Outgoing::Shared(Arc::new(fanout)),
+                Err(e) => {
+                    error!("Encoding {msg} failed! Error {e}");
+                    return;
+                }
+            };
+            for session in sessions.iter() {
+                if let Err(e) = session.sender.send(msg.clone()).await {
+                    warn!("announcing to {} failed, error {e}", session.key());
+                }
+            }
+        }
+    }
+}
+
+/// [Broadcast] message encoded for all the sessions, along with the session it originates from.
+type Encoding = (Option<SessionId>, Outgoing);
+
+/// Encodes the message of the [Broadcast] once, the encoding is logged inside the `span`.
+fn encode(
+    from: Option<Origin>,
+    msg: ser::Msg,
+    reservation: memory::Reservation,
+    span: &Span,
+) -> Option<Encoding> {
+    let seq = from.map(|from| from.seq);
+    let _entered = info_span!(parent: span, "broadcast", seq).entered();
+    let id_from = from.map(|from| from.session);
+    info!("broadcasting {msg} from {id_from:?}");
+    match Fanout::new(msg.clone(), Some(reservation)) {
+        Ok(fanout) => Some((id_from, Outgoing::Shared(Arc::new(fanout)))),
+        Err(e) => {
+            error!("Encoding {msg} failed! Error {e}");
+            None
+        }
+    }
+}
+
+/// Sends the group of messages to every session except the originating one of each, if any,
+/// the sender's other sessions included.
+async fn broadcast(sessions: &Sessions, group: &[Encoding]) {
+    if group.is_empty() {
+        return;
+    }
+    for session in sessions.iter() {
+        let id_to = *session.key();
+        for (id_from, msg) in group {
+            if *id_from != Some(id_to) {
+                if let Err(e) = session.sender.send(msg.clone()).await {
+                    warn!("broadcasting to {id_to} failed, error {e}");
+                    break;
+                }
+            }
+        }
+        debug!("broadcasting {} messages to {id_to}", group.len());
     }
-    listener.await?
+}
+
+/// Binds a listener to the address, IPv6 ones do not accept IPv4 connections.
+fn bind(address: SocketAddr) -> io::Result<TcpListener> {
+    use socket2::{Domain, Protocol, Socket, Type};
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    if address.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    // Same as tokio does, so that the port can be reused right after a restart.
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
 }
 
 /// Listens for connections, spawns task to handle each client.
+///
+/// Each client is handled inside a `conn` span with a connection id from `conns`, unique within the run.
+/// Connections the [AccessPolicy] does not admit are refused before any task handles them.
 async fn client_listener(
-    address: SocketAddr,
-    tasks: Sender<Task>,
-    clients: Arc<Senders>,
-    db: Arc<db::Database>,
+    listener: TcpListener,
+    shared: Shared,
+    conns: Arc<AtomicU64>,
 ) -> anyhow::Result<()> {
-    let listener = TcpListener::bind(address)
-        .await
-        .with_context(|| format!("Listening at {address:?} failed."))?;
-    info!("Server is listening at {address:?}");
     loop {
         match listener.accept().await {
-            Ok((mut socket, addr)) => {
-                info!("incoming {addr:?}");
+            Ok((socket, addr)) => {
+                let conn = conns.fetch_add(1, Ordering::Relaxed) + 1;
+                let span = info_span!("conn", id = conn, %addr);
+                span.in_scope(|| info!("incoming {addr:?}"));
+                let admission = match shared.gate.admit(addr.ip()) {
+                    Ok(admission) => admission,
+                    Err(refusal) => {
+                        span.in_scope(|| warn!("refusing {addr:?}, {refusal:?}"));
+                        if refusal == ser::Refusal::AddressNotAllowed {
+                            let db = shared.db.clone();
+                            tokio::spawn(
+                                async move {
+                                    audit(&db, db::AuditEvent::Denied, None, addr, None).await
+                                }
+                                .instrument(span.clone()),
+                            );
+                        }
+                        let tls = shared.tls.clone();
+                        tokio::spawn(refuse(socket, refusal, tls).instrument(span));
+                        continue;
+                    }
+                };
                 {
-                    let (tasks, clients, db) = (tasks.clone(), clients.clone(), db.clone());
-                    tokio::spawn(async move {
-                        match authenticate(&mut socket, db.clone()).await {
-                            Ok(user) => {
-                                if let Err(e) =
-                                    manage_client(addr, user, socket, clients, db, tasks).await
-                                {
-                                    error!("Managing client at {addr} failed! Error {e:#}");
+                    let shared = shared.clone();
+                    tokio::spawn(
+                        async move {
+                            let _admission = admission;
+                            let mut socket = match tls::secure(socket, shared.tls.as_ref()).await {
+                                Ok(socket) => socket,
+                                Err(e) => {
+                                    warn!("Securing the connection of {addr} failed! Error {e:#}");
+                                    return;
+                                }
+                            };
+                            match authenticate(&mut socket, addr, &shared).await {
+                                Ok((user, guest, codec)) => {
+                                    let id = SessionId(conn);
+                                    let client =
+                                        manage_client(id, addr, user, guest, codec, socket, shared);
+                                    if let Err(e) = client.await {
+                                        error!("Managing client at {addr} failed! Error {e:#}");
+                                    }
+                                }
+                                Err(e) => {
+                                    error!(
+                                        "Authenticating the client at {addr} failed! Error {e:#}"
+                                    )
                                 }
-                            }
-                            Err(e) => {
-                                error!("Authenticating the client at {addr} failed! Error {e:#}")
                             }
                         }
-                    });
+                        .instrument(span),
+                    );
                 }
             }
             Err(e) => error!("incoming stream error: {e:?}"),
@@ -165,25 +1274,84 @@ async fn client_listener(
     }
 }
 
-/// Adds the client to `clients`, reads from and writes to it, then removes it from `clients`.
+/// Tells the client why it is refused, gives up when the client does not read in time.
+///
+/// A TLS client is told after the handshake.
+async fn refuse(socket: TcpStream, refusal: ser::Refusal, tls: Option<tokio_rustls::TlsAcceptor>) {
+    let mut socket = match tls::secure(socket, tls.as_ref()).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            warn!("Securing the refused connection failed! Error {e:#}");
+            return;
+        }
+    };
+    let msg = ser::Msg::Error(ser::Error::Refused(refusal));
+    if let Err(e) = send_with_timeout(&msg, &mut socket, Duration::from_secs(1)).await {
+        warn!("Sending the refusal failed! Error {e}");
+    }
+}
+
+/// Adds the client to `sessions`, reads from and writes to it, then removes it from `sessions`.
+///
+/// The message of the day (if any) is the first message the client gets,
+/// followed by the presence of the users who are not online.
+/// The client is disconnected when its session is kicked.
+/// Messages are read and written by the `codec` the client negotiated.
+#[instrument(skip_all, fields(%user, guest = guest, ?codec))]
 async fn manage_client(
+    id: SessionId,
     addr: SocketAddr,
     user: User,
-    socket: TcpStream,
-    clients: Arc<Senders>,
-    db: Arc<db::Database>,
-    tasks: Sender<Task>,
+    guest: bool,
+    codec: Codec,
+    socket: tls::ClientStream,
+    shared: Shared,
 ) -> anyhow::Result<()> {
-    let (reader, writer) = socket.into_split();
+    let traffic = ConnectionStats::new();
+    let (reader, writer) = tokio::io::split(socket);
+    let (reader, writer) = (traffic.count(reader), traffic.count(writer));
 
     let (msg_producer, msg_consumer) = mpsc::channel(128);
-    let writer_task = tokio::spawn(write_each_msg(msg_consumer, writer));
+    let writer_task = tokio::spawn(write_each_msg(msg_consumer, writer, codec).in_current_span());
 
-    clients.insert(addr, msg_producer);
-    let reader_res = read_in_loop(addr, user, reader, db, tasks.clone()).await;
-    clients
-        .remove(&addr)
-        .with_context(|| "Removing disconnected client \"{addr}\" from clients failed!")?;
+    let motd = shared.motd.read().expect("motd lock poisoned").clone();
+    if let Some(motd) = motd {
+        msg_producer
+            .send(ser::Msg::ServerInfo(motd).into())
+            .await
+            .with_context(|| "Queueing the message of the day failed!")?;
+    }
+    for presence in absent_users(&shared.sessions) {
+        msg_producer
+            .send(presence.into())
+            .await
+            .with_context(|| "Queueing the presence of users failed!")?;
+    }
+    announce_session(addr, &user, &shared).await;
+    let kick = Arc::new(Notify::new());
+    let session = Session {
+        user: user.clone(),
+        guest,
+        addr,
+        since: Instant::now(),
+        kick: kick.clone(),
+        sender: msg_producer,
+        presence: Presence::Online,
+        presence_message: None,
+        traffic,
+    };
+    shared.sessions.insert(id, session);
+    let reader_res = select!(
+        res = read_in_loop(id, user, codec, reader, &shared) => res,
+        _ = kick.notified() => {
+            info!("kicked");
+            Ok(())
+        }
+    );
+    shared
+        .sessions
+        .remove(&id)
+        .with_context(|| format!("Removing disconnected session {id} failed!"))?;
 
     reader_res.with_context(|| "Reading messages at {addr} failed!")?;
     writer_task
@@ -192,79 +1360,943 @@ async fn manage_client(
     Ok(())
 }
 
-async fn authenticate(socket: &mut TcpStream, db: Arc<db::Database>) -> anyhow::Result<User> {
-    let user = loop {
-        let err = match cli::Msg::receive(socket).await? {
+/// Tells the other sessions of the user about the new one at `addr`, kicks them when the [SessionPolicy] says so.
+async fn announce_session(addr: SocketAddr, user: &User, shared: &Shared) {
+    let others: Vec<_> = sessions_of(&shared.sessions, user)
+        .into_iter()
+        .filter_map(|id| {
+            let other = shared.sessions.get(&id)?;
+            Some((other.addr, other.kick.clone(), other.sender.clone()))
+        })
+        .collect();
+    let time = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+    for (other, kick, channel) in others {
+        let notice = ser::Msg::NewSession {
+            addr,
+            time: time.clone(),
+        };
+        if let Err(e) = channel.send(notice.into()).await {
+            warn!("Telling {other} about the new session failed! Error {e}");
+        }
+        if shared.sessions_of_user == SessionPolicy::KickOld {
+            kick.notify_one();
+            info!("{other} kicked by the new session");
+            let detail = Some(format!("replaced by the session at {addr}"));
+            audit(&shared.db, db::AuditEvent::Kick, Some(user), other, detail).await;
+        }
+    }
+}
+
+/// Reads messages until the client logs in or signs up, other messages are answered with errors.
+///
+/// A tagged authentication is acknowledged after the confirmation.
+/// Log-ins, failed ones included, and sign-ups are recorded in the audit log.
+/// Unknown users and wrong passwords are told apart only in the audit log,
+/// unless the server has [distinct log-in errors][Server::distinct_login_errors].
+/// A user with a session can not log in again when the [SessionPolicy] rejects new sessions.
+///
+/// Servers [by invitation only][Server::invite_only] sign up clients with a valid code,
+/// a [challenge][Server::signup_challenge] is solved before any sign-up when required,
+/// a first log-in with a token included.
+/// Guests join when the server [allows][Server::allow_guests] them and their name is not used
+/// by a registered user nor by another session, they are not audited.
+/// Tokens are accepted when the server trusts an [identity provider][Server::oidc].
+/// A requested password reset code is [sent][Server::mailer], the user logs in by setting a new password with it.
+/// Users with two-factor authentication give their code after the password or the token, see [second_factor].
+/// The client may [offer codecs][cli::Msg::Codecs] first, the chosen one is used after the confirmation.
+/// Returns the user, whether they are a guest and the codec.
+async fn authenticate(
+    socket: &mut tls::ClientStream,
+    addr: SocketAddr,
+    shared: &Shared,
+) -> anyhow::Result<(User, bool, Codec)> {
+    let db = &shared.db;
+    let mut codec = Codec::default();
+    let (id, user, guest) = loop {
+        let cli::Envelope { id, msg } = receive_with_timeout(socket, AUTH_TIMEOUT).await?;
+        if let (
+            Some(difficulty),
+            cli::Msg::Auth(cli::Auth::SignUp(_) | cli::Auth::SignUpInvited { .. }),
+        ) = (shared.signup_challenge, &msg)
+        {
+            if !proof_of_work(socket, difficulty).await? {
+                info!("{addr} did not solve the sign-up challenge");
+                ser::Msg::error_for(id, ser::Error::ChallengeFailed)
+                    .send(socket)
+                    .await?;
+                continue;
+            }
+        }
+        let err = match msg {
+            cli::Msg::Codecs(offered) => {
+                codec = Codec::choose(&offered);
+                debug!("{addr} offered codecs {offered:?}, {codec:?} chosen");
+                ser::Msg::CodecChosen(codec).send(socket).await?;
+                if let Some(id) = id {
+                    ser::Msg::Ack(id).send(socket).await?;
+                }
+                continue;
+            }
             cli::Msg::Auth(cli::Auth::LogIn(creds)) => match db.log_in(creds.clone()).await {
-                Ok(()) => break creds.user,
-                Err(db::Error::UserDoesNotExist(_)) => ser::Error::WrongUser,
-                Err(db::Error::WrongPassword(_)) => ser::Error::WrongPassword,
+                Ok(())
+                    if shared.sessions_of_user == SessionPolicy::RejectNew
+                        && !sessions_of(&shared.sessions, &creds.user).is_empty() =>
+                {
+                    let failed = db::AuditEvent::FailedLogIn;
+                    let detail = Some("logged in elsewhere".to_string());
+                    audit(db, failed, Some(&creds.user), addr, detail).await;
+                    ser::Error::SessionActive
+                }
+                Ok(()) => {
+                    let id = second_factor(socket, &creds.user, id, addr, shared).await?;
+                    audit(db, db::AuditEvent::LogIn, Some(&creds.user), addr, None).await;
+                    break (id, creds.user, false);
+                }
+                Err(e @ (db::Error::UserDoesNotExist(_) | db::Error::WrongPassword(_))) => {
+                    let failed = db::AuditEvent::FailedLogIn;
+                    audit(db, failed, Some(&creds.user), addr, Some(e.to_string())).await;
+                    match e {
+                        _ if !shared.distinct_login_errors => ser::Error::InvalidCredentials,
+                        db::Error::WrongPassword(_) => ser::Error::WrongPassword,
+                        _ => ser::Error::WrongUser,
+                    }
+                }
                 Err(e) => return Err(e.into()),
             },
-            cli::Msg::Auth(cli::Auth::SignUp(creds)) => match db.sign_up(creds.clone()).await {
-                Ok(()) => break creds.user,
+            cli::Msg::Auth(cli::Auth::SignUp(_)) if shared.invite_only => {
+                ser::Error::InviteRequired
+            }
+            cli::Msg::Auth(cli::Auth::SignUpInvited {
+                credentials: creds,
+                invite,
+            }) if shared.invite_only => match db.sign_up_invited(creds.clone(), &invite).await {
+                Ok(()) => {
+                    let detail = Some("invited".to_string());
+                    audit(db, db::AuditEvent::SignUp, Some(&creds.user), addr, detail).await;
+                    break (id, creds.user, false);
+                }
+                Err(db::Error::InvalidInvite) => ser::Error::InvalidInvite,
                 Err(db::Error::UsernameTaken(_)) => ser::Error::UsernameTaken,
                 Err(e) => return Err(e.into()),
             },
+            cli::Msg::Auth(
+                cli::Auth::SignUp(creds)
+                | cli::Auth::SignUpInvited {
+                    credentials: creds, ..
+                },
+            ) => match db.sign_up(creds.clone()).await {
+                Ok(()) => {
+                    audit(db, db::AuditEvent::SignUp, Some(&creds.user), addr, None).await;
+                    break (id, creds.user, false);
+                }
+                Err(db::Error::UsernameTaken(_)) => ser::Error::UsernameTaken,
+                Err(e) => return Err(e.into()),
+            },
+            cli::Msg::Auth(cli::Auth::OidcToken(token)) => match &shared.oidc {
+                Some(verifier) => {
+                    match log_in_with_token(socket, verifier, &token, addr, shared).await? {
+                        Ok(user) => {
+                            let id = second_factor(socket, &user, id, addr, shared).await?;
+                            let detail = Some(format!("token of {}", verifier.issuer()));
+                            audit(db, db::AuditEvent::LogIn, Some(&user), addr, detail).await;
+                            break (id, user, false);
+                        }
+                        Err(e) => e,
+                    }
+                }
+                None => ser::Error::InvalidToken("token log-in is not enabled".to_string()),
+            },
+            cli::Msg::Auth(cli::Auth::RequestReset(email)) => {
+                request_reset(email, addr, shared).await?;
+                // The same answer for any address, it does not tell whether anybody uses it.
+                ser::Msg::ServerInfo(RESET_REQUESTED.to_string())
+                    .send(socket)
+                    .await?;
+                if let Some(id) = id {
+                    ser::Msg::Ack(id).send(socket).await?;
+                }
+                continue;
+            }
+            cli::Msg::Auth(cli::Auth::ResetPassword { code, new_password }) => {
+                // A refused reset leaves the code and the password as they were.
+                let active = db.reset_code_user(&code).await?.filter(|user| {
+                    shared.sessions_of_user == SessionPolicy::RejectNew
+                        && !sessions_of(&shared.sessions, user).is_empty()
+                });
+                if let Some(user) = active {
+                    let failed = db::AuditEvent::FailedLogIn;
+                    let detail = Some("password reset while logged in elsewhere".to_string());
+                    audit(db, failed, Some(&user), addr, detail).await;
+                    ser::Msg::error_for(id, ser::Error::SessionActive)
+                        .send(socket)
+                        .await?;
+                    continue;
+                }
+                match db.reset_password(&code, new_password).await {
+                    Ok(Some(user)) => {
+                        audit(db, db::AuditEvent::PasswordReset, Some(&user), addr, None).await;
+                        let id = second_factor(socket, &user, id, addr, shared).await?;
+                        break (id, user, false);
+                    }
+                    Ok(None) => {
+                        let failed = db::AuditEvent::FailedLogIn;
+                        let detail = Some("invalid password reset code".to_string());
+                        audit(db, failed, None, addr, detail).await;
+                        ser::Error::InvalidResetCode
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            cli::Msg::Auth(cli::Auth::Guest(_)) if !shared.allow_guests => {
+                ser::Error::GuestsNotAllowed
+            }
+            cli::Msg::Auth(cli::Auth::Guest(name))
+                if !sessions_of(&shared.sessions, &name).is_empty() =>
+            {
+                ser::Error::UsernameTaken
+            }
+            cli::Msg::Auth(cli::Auth::Guest(name)) => match db.profile(&name).await {
+                Ok(None) => {
+                    info!("{name} joins as a guest");
+                    break (id, name, true);
+                }
+                Ok(Some(_)) => ser::Error::UsernameTaken,
+                Err(e) => return Err(e.into()),
+            },
             m => ser::Error::NotAuthenticated(m),
         };
-        ser::Msg::Error(err).send(socket).await?;
+        ser::Msg::error_for(id, err).send(socket).await?;
     };
     ser::Msg::Authenticated
         .send(socket)
         .await
         .with_context(|| "Sending authentication confirmation failed!")?;
-    Ok(user)
+    if let Some(id) = id {
+        ser::Msg::Ack(id).send_with(codec, socket).await?;
+    }
+    Ok((user, guest, codec))
+}
+
+/// How long a client may take to authenticate, e.g. its user to type `.login`, it is disconnected then.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Wrong two-factor codes a client may give before it is disconnected.
+const TOTP_ATTEMPTS: usize = 3;
+
+/// Asks for the [two-factor code][cli::Auth::TotpCode] when the user requires one, after the password was verified.
+///
+/// Returns the id to acknowledge, the one of the message with the right code if it was asked for.
+/// Nothing but the code is accepted meanwhile, the client is disconnected after [TOTP_ATTEMPTS] wrong ones.
+async fn second_factor(
+    socket: &mut tls::ClientStream,
+    user: &User,
+    id: Option<MsgId>,
+    addr: SocketAddr,
+    shared: &Shared,
+) -> anyhow::Result<Option<MsgId>> {
+    let db = &shared.db;
+    let (Some(sealed), _) = db.totp(user).await? else {
+        return Ok(id);
+    };
+    let Some(totp) = shared.totp_key.as_ref().and_then(|key| key.open(&sealed)) else {
+        let reason = "two-factor authentication is not configured".to_string();
+        ser::Msg::error_for(id, ser::Error::Rejected(reason))
+            .send(socket)
+            .await?;
+        anyhow::bail!("The TOTP secret of {user} can not be opened, is the TOTP key right?");
+    };
+    ser::Msg::TotpRequired.send(socket).await?;
+    let mut wrong = 0;
+    loop {
+        let cli::Envelope { id, msg } = receive_with_timeout(socket, AUTH_TIMEOUT).await?;
+        let err = match msg {
+            cli::Msg::Auth(cli::Auth::TotpCode(code)) if totp.verify(&code) => return Ok(id),
+            cli::Msg::Auth(cli::Auth::TotpCode(_)) => {
+                wrong += 1;
+                let detail = Some("wrong two-factor code".to_string());
+                audit(db, db::AuditEvent::FailedLogIn, Some(user), addr, detail).await;
+                ser::Error::InvalidTotpCode
+            }
+            m => ser::Error::NotAuthenticated(m),
+        };
+        ser::Msg::error_for(id, err).send(socket).await?;
+        if wrong == TOTP_ATTEMPTS {
+            anyhow::bail!("{user} gave {TOTP_ATTEMPTS} wrong two-factor codes");
+        }
+    }
+}
+
+/// Asks the client signing up to solve a [challenge][ser::Msg::Challenge], returns whether it did.
+///
+/// Nothing but the [solution][cli::Auth::Solution] is accepted meanwhile, the sign-up fails otherwise.
+async fn proof_of_work(socket: &mut tls::ClientStream, difficulty: u8) -> anyhow::Result<bool> {
+    let prefix = format!("{:016x}", OsRng.next_u64());
+    let msg = ser::Msg::Challenge {
+        prefix: prefix.clone(),
+        difficulty,
+    };
+    msg.send(socket).await?;
+    let msg: cli::Msg = receive_with_timeout(socket, AUTH_TIMEOUT).await?;
+    match msg {
+        cli::Msg::Auth(cli::Auth::Solution(nonce)) => {
+            Ok(challenge::verify(&prefix, difficulty, nonce))
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Answer to a [password reset request][cli::Auth::RequestReset].
+const RESET_REQUESTED: &str =
+    "If the address belongs to a user, a password reset code was sent to it.";
+
+/// Makes a reset code of the user with the address and sends it by the [Mailer].
+async fn request_reset(email: String, addr: SocketAddr, shared: &Shared) -> anyhow::Result<()> {
+    let Some((user, code)) = shared.db.reset_code(&email).await? else {
+        info!("Password reset requested from {addr} for an address nobody uses");
+        return Ok(());
+    };
+    audit(
+        &shared.db,
+        db::AuditEvent::ResetRequested,
+        Some(&user),
+        addr,
+        None,
+    )
+    .await;
+    let mailer = shared.mailer.clone();
+    let recipient = user.clone();
+    let sent =
+        tokio::task::spawn_blocking(move || mailer.send_reset_code(&email, &recipient, &code))
+            .await?;
+    if let Err(e) = sent {
+        error!("Sending the password reset code of {user} failed! Error {e:#}");
+    }
+    Ok(())
+}
+
+/// Returns the user the token's subject logs in as, or the error for the client.
+///
+/// Servers [by invitation only][Server::invite_only] refuse subjects logging in for the first time,
+/// the token carries no invite and their user would be created.
+/// Otherwise they solve the [challenge][Server::signup_challenge] first when it is required, as any sign-up.
+async fn log_in_with_token(
+    socket: &mut tls::ClientStream,
+    verifier: &oidc::Verifier,
+    token: &str,
+    addr: SocketAddr,
+    shared: &Shared,
+) -> anyhow::Result<Result<User, ser::Error>> {
+    let db = &shared.db;
+    let issuer = verifier.issuer();
+    let identity = match verifier.verify(token).await {
+        Ok(identity) => identity,
+        Err(e) => {
+            let detail = Some(format!("token of {issuer}: {e}"));
+            audit(db, db::AuditEvent::FailedLogIn, None, addr, detail).await;
+            return Ok(Err(ser::Error::InvalidToken(e.to_string())));
+        }
+    };
+    if db.identity(issuer, &identity.subject).await?.is_none() {
+        if shared.invite_only {
+            let detail = Some(format!("first token of {issuer} without an invite"));
+            audit(db, db::AuditEvent::FailedLogIn, None, addr, detail).await;
+            return Ok(Err(ser::Error::InviteRequired));
+        }
+        if let Some(difficulty) = shared.signup_challenge {
+            if !proof_of_work(socket, difficulty).await? {
+                info!("{addr} did not solve the sign-up challenge");
+                return Ok(Err(ser::Error::ChallengeFailed));
+            }
+        }
+    }
+    let user = match db
+        .identity_user(issuer, &identity.subject, &identity.username)
+        .await
+    {
+        Ok(user) => user,
+        Err(db::Error::UsernameTaken(_)) => return Ok(Err(ser::Error::UsernameTaken)),
+        Err(e) => return Err(e.into()),
+    };
+    if shared.sessions_of_user == SessionPolicy::RejectNew
+        && !sessions_of(&shared.sessions, &user).is_empty()
+    {
+        let detail = Some(format!("token of {issuer}, logged in elsewhere"));
+        audit(db, db::AuditEvent::FailedLogIn, Some(&user), addr, detail).await;
+        return Ok(Err(ser::Error::SessionActive));
+    }
+    Ok(Ok(user))
+}
+
+/// Records the event in the audit log, a failure is only logged.
+async fn audit(
+    db: &db::Database,
+    event: db::AuditEvent,
+    user: Option<&User>,
+    addr: SocketAddr,
+    detail: Option<String>,
+) {
+    let username = user.map(User::to_string);
+    if let Err(e) = db.audit(event, username, Some(addr), detail).await {
+        error!("Recording {event:?} from {addr} in the audit log failed! Error {e}");
+    }
 }
 
 /// Receives messages from `reader` until disconnection, sends tasks to the `tasks` queue.
+///
+/// Every message reserves its size from the budget before it is read, as its length prefix tells,
+/// no further messages are read meanwhile. A broadcast keeps the reservation until it is written.
+/// A client sending a frame longer than [MAX_FRAME_SIZE][cli_ser::MAX_FRAME_SIZE] or not finishing one
+/// within [READ_TIMEOUT] is disconnected, its reservation is released.
+/// Tagged messages are acknowledged or rejected with their id.
+/// Messages are numbered in the order they are read, see [Origin::seq].
 async fn read_in_loop(
-    addr: SocketAddr,
+    session: SessionId,
     user: User,
-    mut reader: OwnedReadHalf,
-    db: Arc<db::Database>,
-    tasks: Sender<Task>,
+    codec: Codec,
+    mut reader: Counted<ReadHalf<tls::ClientStream>>,
+    shared: &Shared,
 ) -> anyhow::Result<()> {
+    let tasks = &shared.tasks;
+    let mut seq = 0;
     loop {
-        let task = match cli::Msg::receive(&mut reader).await {
-            Ok(cli::Msg::ToAll(data)) => {
-                if let Err(e) = db.record_msg_to_all(user.clone(), data.clone()).await {
-                    error!("{e}"); // TODO
+        let reserve = |len| shared.budget.reserve(len);
+        let (reservation, len, received) =
+            match cli::Envelope::receive_reserving(codec, &mut reader, reserve, READ_TIMEOUT).await
+            {
+                Ok(received) => received,
+                Err(DisconnectedStream(_)) => break Ok(()),
+                Err(e) => break Err(e).context("Reading from the socket failed!"),
+            };
+        let (id, msg) = match received {
+            Ok(cli::Envelope { id, msg }) => (id, msg),
+            Err(e) => {
+                warn!("Message of {len} bytes is malformed! Error {e}");
+                let err = ser::Error::Malformed { len: len as u64 };
+                queue(tasks, SendErr(session, None, err)).await?;
+                continue;
+            }
+        };
+        seq += 1;
+        let origin = Origin { session, seq };
+        match process_msg(origin, &user, id, len, msg, reservation, shared).await {
+            Ok(caused) => {
+                for task in caused {
+                    queue(tasks, task).await?;
+                }
+                if let Some(id) = id {
+                    queue(tasks, Ack(session, id)).await?;
+                }
+            }
+            Err(err) => queue(tasks, SendErr(session, id, err)).await?,
+        }
+    }
+}
+
+/// How long the rest of a message may take to arrive once its length was read, see [read_in_loop].
+const READ_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Counts the attachment of the `data` towards the user's storage quota, returns the bytes counted.
+///
+/// When the database fails, the attachment is let through uncounted.
+async fn reserve_storage(
+    db: &db::Database,
+    user: &User,
+    data: &Data,
+    default_quota: Option<u64>,
+) -> Result<u64, ser::Error> {
+    let bytes = match data {
+        Data::Text(_) => return Ok(0),
+        Data::File(file) => file.size(),
+        Data::Image(image) => image.size(),
+        Data::Media(media) => media.size(),
+    } as u64;
+    match db.reserve_storage(user, bytes, default_quota).await {
+        Ok(()) => Ok(bytes),
+        Err(db::Error::QuotaExceeded { used, limit }) => {
+            info!("rejected, storage quota of {user} exceeded, {used} of {limit} bytes used");
+            Err(ser::Error::QuotaExceeded { used, limit })
+        }
+        Err(e) => {
+            error!(code = %e.code(), "Reserving storage of {user} failed, the attachment is not counted! Error {e}");
+            Ok(0)
+        }
+    }
+}
+
+/// Rejects the image exceeding the `limits`, it is not decoded.
+fn check_image(image: &Image, limits: &ImageLimits) -> Result<(), ser::Error> {
+    image.check(limits).map_err(|e| {
+        info!("rejected, {e}");
+        ser::Error::Rejected(e.to_string())
+    })
+}
+
+/// Makes tasks of the received message, every log within has the span of the message.
+///
+/// The sender of tagged data is told the id the data was stored with, and the message
+/// of every user mentioned who is not online and left one.
+/// Guests are not in the database, their data is only broadcast and they can not change anything stored.
+/// A broadcast keeps the `reservation` of the message's in-flight memory, it is released otherwise.
+#[instrument(name = "msg", skip_all, fields(seq = origin.seq, id = id.map(|id| id.0), bytes = len))]
+async fn process_msg(
+    origin: Origin,
+    user: &User,
+    id: Option<MsgId>,
+    len: usize,
+    msg: cli::Msg,
+    reservation: memory::Reservation,
+    shared: &Shared,
+) -> Result<Vec<Task>, ser::Error> {
+    let Shared {
+        db,
+        persister,
+        image_policy,
+        image_limits,
+        storage_quota,
+        filters,
+        reloaded_filters,
+        bots,
+        ..
+    } = shared;
+    let session = origin.session;
+    if let Some(limit) = shared.rate_limit {
+        if !shared.sent.admit(user, limit) {
+            info!("rejected, over the rate limit");
+            return Err(ser::Error::Rejected(limit.reason("messages")));
+        }
+    }
+    let guest = shared.sessions.get(&session).is_some_and(|s| s.guest);
+    // Urgent texts are broadcast as any other data, once they pass the tighter limit.
+    let (msg, urgent) = match msg {
+        cli::Msg::Urgent(text) => (cli::Msg::ToAll(Data::Text(text)), true),
+        msg => (msg, false),
+    };
+    match msg {
+        cli::Msg::ToAll(data) => {
+            if let Data::Image(image) = &data {
+                check_image(image, image_limits)?;
+            }
+            let checked = filters
+                .check(user, &data)
+                .and_then(|()| reloaded_filters.load().check(user, &data));
+            if let Err(reason) = checked {
+                info!("rejected, {reason}");
+                return Err(ser::Error::Rejected(reason));
+            }
+            if let Some(moderation) = &shared.moderation {
+                if let Err(reason) = moderation.check(user, &data).await {
+                    info!("rejected by the moderator, {reason}");
+                    return Err(ser::Error::Rejected(reason));
+                }
+            }
+            if urgent {
+                if let Err(reason) = shared.urgent.check(user) {
+                    info!("urgent text rejected, {reason}");
+                    return Err(ser::Error::Rejected(reason));
+                }
+            }
+            let stored_bytes = match guest {
+                true => 0,
+                false => reserve_storage(db, user, &data, *storage_quota).await?,
+            };
+            let (data, original) = match (data, image_policy) {
+                (Data::Image(image), Some(policy)) => {
+                    let (image, original) = policy.apply(image).await;
+                    (
+                        Data::Image(image),
+                        original.filter(|_| policy.keep_original),
+                    )
+                }
+                (data, _) => (data, None),
+            };
+            let msg_id = match guest {
+                true => None,
+                false => match persister
+                    .record(user.clone(), data.clone(), original, urgent)
+                    .await
+                {
+                    Ok(msg_id) => Some(msg_id),
+                    Err(e) => {
+                        error!(code = %e.code(), "Recording the message failed, it is not stored! Error {e}");
+                        if stored_bytes > 0 {
+                            if let Err(e) = db.release_storage(user, stored_bytes).await {
+                                error!(code = %e.code(), "Releasing the storage of {user} failed! Error {e}");
+                            }
+                        }
+                        None
+                    }
+                },
+            };
+            let display_name = match guest {
+                true => None,
+                false => db.display_name(user).await.unwrap_or_else(|e| {
+                    error!(code = %e.code(), "Querying the display name of {user} failed! Error {e}");
+                    None
+                }),
+            };
+            if !bots.is_empty() {
+                let event = cli_ser::bot::Event::Data {
+                    from: user.clone(),
+                    data: data.clone(),
+                    msg_id,
+                };
+                bot::notify(bots, event);
+            }
+            let mentions = match &data {
+                Data::Text(text) => mentions(text),
+                _ => vec![],
+            };
+            let auto_replies: Vec<_> = mentions
+                .iter()
+                .filter(|mentioned| *mentioned != user)
+                .filter_map(|mentioned| match absence(&shared.sessions, mentioned)? {
+                    (presence, Some(message)) => Some(Reply(
+                        session,
+                        ser::Msg::PresenceChanged {
+                            user: mentioned.clone(),
+                            presence,
+                            message: Some(message),
+                        },
+                    )),
+                    (_, None) => None,
+                })
+                .collect();
+            let msg = ser::Msg::DataFrom {
+                data,
+                from: user.clone(),
+                msg_id,
+                display_name,
+                mentions,
+                guest,
+                urgent,
+            };
+            shared.stats.count_message();
+            let broadcast = Broadcast(Some(origin), msg, reservation, Span::current());
+            let mut tasks = vec![broadcast];
+            if let (Some(id), Some(msg_id)) = (id, msg_id) {
+                tasks.push(Reply(session, ser::Msg::Stored { id, msg_id }));
+            }
+            tasks.extend(auto_replies);
+            Ok(tasks)
+        }
+        cli::Msg::MarkRead { .. } if guest => Ok(vec![]),
+        cli::Msg::ReadStatus { msg_id } if guest => Err(ser::Error::UnknownMessage(msg_id)),
+        cli::Msg::SetProfile(_) if guest => {
+            Err(ser::Error::Rejected("guests have no profile".to_string()))
+        }
+        cli::Msg::SetPrefs(_) if guest => Err(ser::Error::Rejected(
+            "guests have no stored preferences".to_string(),
+        )),
+        cli::Msg::GetPrefs if guest => Ok(vec![Reply(
+            session,
+            ser::Msg::Prefs(Preferences::default()),
+        )]),
+        cli::Msg::Admin(_) if guest => Err(ser::Error::NotAdmin),
+        cli::Msg::TwoFactor(_) if guest => Err(ser::Error::Rejected(
+            "guests can not use two-factor authentication".to_string(),
+        )),
+        cli::Msg::MarkRead { msg_id } => {
+            // The message may still wait in the buffer.
+            persister.flush().await;
+            if let Err(e) = db.mark_read(user, msg_id).await {
+                error!(code = %e.code(), "Marking message {msg_id} read by {user} failed! Error {e}");
+            }
+            Ok(vec![])
+        }
+        cli::Msg::ReadStatus { msg_id } => match async {
+            persister.flush().await;
+            db.read_by(user, msg_id).await
+        }
+        .await
+        {
+            Ok(Some(users)) => {
+                let users = users.into_iter().map(User::from).collect();
+                Ok(vec![Reply(session, ser::Msg::ReadBy { msg_id, users })])
+            }
+            Ok(None) => Err(ser::Error::UnknownMessage(msg_id)),
+            Err(e) => {
+                error!(code = %e.code(), "Querying readers of message {msg_id} failed! Error {e}");
+                Err(ser::Error::from(&e))
+            }
+        },
+        cli::Msg::SetProfile(change) => {
+            if let cli::ProfileChange::Avatar(avatar) = &change {
+                check_image(avatar, image_limits)?;
+            }
+            match db.set_profile(user, change).await {
+                Ok(()) => Ok(vec![]),
+                Err(db::Error::EmailTaken(_)) => Err(ser::Error::Rejected(
+                    "the address is used by another user".to_string(),
+                )),
+                Err(e) => {
+                    error!(code = %e.code(), "Setting the profile of {user} failed! Error {e}");
+                    Ok(vec![])
                 }
-                Broadcast(addr, user.clone(), data)
             }
-            Ok(cli::Msg::Auth { .. }) => SendErr(addr, ser::Error::AlreadyAuthenticated),
-            Err(DisconnectedStream(_)) => break Ok(()),
-            Err(e) => SendErr(addr, ser::Error::ReceiveMsg(e.to_string())),
+        }
+        cli::Msg::GetProfile(of) => match db.profile(&of).await {
+            Ok(Some(profile)) => Ok(vec![Reply(
+                session,
+                ser::Msg::Profile { user: of, profile },
+            )]),
+            Ok(None) => Err(ser::Error::UnknownUser(of)),
+            Err(e) => {
+                error!(code = %e.code(), "Querying the profile of {of} failed! Error {e}");
+                Err(ser::Error::from(&e))
+            }
+        },
+        cli::Msg::SetPrefs(prefs) => match db.set_prefs(user, prefs).await {
+            Ok(()) => Ok(vec![]),
+            Err(e) => {
+                error!(code = %e.code(), "Setting the preferences of {user} failed! Error {e}");
+                Err(ser::Error::from(&e))
+            }
+        },
+        cli::Msg::GetPrefs => match db.prefs(user).await {
+            Ok(prefs) => Ok(vec![Reply(session, ser::Msg::Prefs(prefs))]),
+            Err(e) => {
+                error!(code = %e.code(), "Querying the preferences of {user} failed! Error {e}");
+                Err(ser::Error::from(&e))
+            }
+        },
+        cli::Msg::TwoFactor(cmd) => two_factor(cmd, user, session, shared).await,
+        cli::Msg::Stats => Ok(vec![Reply(
+            session,
+            ser::Msg::Stats {
+                uptime: shared.stats.uptime(),
+                connected: shared.sessions.len() as u64,
+                messages_today: shared.stats.messages_today(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+            },
+        )]),
+        cli::Msg::SetPresence { presence, message } => {
+            if let Some(mut state) = shared.sessions.get_mut(&session) {
+                state.presence = presence;
+                state.presence_message = message.clone();
+            }
+            info!("presence {presence:?}");
+            let msg = ser::Msg::PresenceChanged {
+                user: user.clone(),
+                presence,
+                message,
+            };
+            Ok(vec![Broadcast(
+                Some(origin),
+                msg,
+                reservation,
+                Span::current(),
+            )])
+        }
+        // The codec is negotiated before the authentication only.
+        cli::Msg::Auth { .. } | cli::Msg::Codecs(_) => Err(ser::Error::AlreadyAuthenticated),
+        cli::Msg::Admin(cmd) => match db.is_admin(user).await {
+            Ok(true) => {
+                info!("{cmd:?} by {user}");
+                administer(cmd, Some((user, session)), shared).await
+            }
+            Ok(false) => Err(ser::Error::NotAdmin),
+            Err(e) => {
+                error!(code = %e.code(), "Checking administrator rights of {user} failed! Error {e}");
+                Err(ser::Error::from(&e))
+            }
+        },
+        cli::Msg::Urgent(_) => unreachable!("urgent texts were turned to ToAll above"),
+    }
+}
+
+/// Users mentioned in the text as `@username`, each one once in the order of appearance.
+///
+/// Mentions start a word and end at the first character which is not alphanumeric, `_` or `-`.
+fn mentions(text: &str) -> Vec<User> {
+    let mut mentions: Vec<User> = vec![];
+    for word in text.split_whitespace() {
+        let Some(name) = word.strip_prefix('@') else {
+            continue;
         };
-        tasks
-            .send(task)
-            .await
-            .with_context(|| "Emergency! Task queue stopped working!")?;
+        let end = name
+            .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+            .unwrap_or(name.len());
+        let user = User::from(name[..end].to_string());
+        if end > 0 && !mentions.contains(&user) {
+            mentions.push(user);
+        }
     }
+    mentions
 }
 
-/// Writes every received message from `messages` into `writer`.
-async fn write_each_msg(mut messages: Receiver<ser::Msg>, mut writer: OwnedWriteHalf) {
+/// Sets up or turns off the user's two-factor authentication, see [cli::TwoFactor].
+///
+/// Returns the reply to queue.
+async fn two_factor(
+    cmd: cli::TwoFactor,
+    user: &User,
+    session: SessionId,
+    shared: &Shared,
+) -> Result<Vec<Task>, ser::Error> {
+    let Some(key) = &shared.totp_key else {
+        return Err(ser::Error::Rejected(
+            "two-factor authentication is not configured".to_string(),
+        ));
+    };
+    let failed = |e: db::Error| {
+        error!(code = %e.code(), "Changing the two-factor authentication of {user} failed! Error {e}");
+        ser::Error::Rejected("two-factor authentication is not available".to_string())
+    };
+    let (active, pending) = shared.db.totp(user).await.map_err(failed)?;
+    let info = |text: &str| Ok(vec![Reply(session, ser::Msg::ServerInfo(text.to_string()))]);
+    match cmd {
+        cli::TwoFactor::Enable if active.is_some() => Err(ser::Error::Rejected(
+            "two-factor authentication is enabled already".to_string(),
+        )),
+        cli::TwoFactor::Enable => {
+            let totp = totp::Totp::generate();
+            let url = totp.url(user);
+            let db = &shared.db;
+            db.set_totp_pending(user, key.seal(&totp))
+                .await
+                .map_err(failed)?;
+            Ok(vec![Reply(session, ser::Msg::TotpEnrollment { url })])
+        }
+        cli::TwoFactor::Confirm(code) => {
+            let Some(sealed) = pending else {
+                return Err(ser::Error::Rejected(
+                    "two-factor authentication is not being enabled".to_string(),
+                ));
+            };
+            match key.open(&sealed) {
+                Some(totp) if totp.verify(&code) => {}
+                _ => return Err(ser::Error::InvalidTotpCode),
+            }
+            match shared
+                .db
+                .activate_totp(user, sealed)
+                .await
+                .map_err(failed)?
+            {
+                true => {
+                    info!("{user} enabled two-factor authentication");
+                    info(
+                        "Two-factor authentication is enabled, log-ins require a code from now on.",
+                    )
+                }
+                false => Err(ser::Error::InvalidTotpCode),
+            }
+        }
+        cli::TwoFactor::Disable(code) => {
+            let Some(sealed) = active else {
+                return Err(ser::Error::Rejected(
+                    "two-factor authentication is not enabled".to_string(),
+                ));
+            };
+            match key.open(&sealed) {
+                Some(totp) if totp.verify(&code) => {}
+                _ => return Err(ser::Error::InvalidTotpCode),
+            }
+            shared.db.disable_totp(user).await.map_err(failed)?;
+            info!("{user} disabled two-factor authentication");
+            info("Two-factor authentication is disabled.")
+        }
+    }
+}
+
+/// Carries out the administrator's command, given remotely `by` the user in the session or from the [console].
+///
+/// Returns the task to queue, answers to the console are printed.
+async fn administer(
+    cmd: cli::Admin,
+    by: Option<(&User, SessionId)>,
+    shared: &Shared,
+) -> Result<Vec<Task>, ser::Error> {
+    match cmd {
+        cli::Admin::SetMotd(motd) => {
+            *shared.motd.write().expect("motd lock poisoned") = Some(motd.clone());
+            Ok(vec![Announce(motd)])
+        }
+        cli::Admin::SetQuota { user, bytes } => match shared.db.set_quota(&user, bytes).await {
+            Ok(()) => Ok(vec![]),
+            Err(db::Error::UserDoesNotExist(_)) => Err(ser::Error::UnknownUser(user)),
+            Err(e) => {
+                error!(code = %e.code(), "Setting the storage quota of {user} failed! Error {e}");
+                Err(ser::Error::from(&e))
+            }
+        },
+        cli::Admin::CreateInvite { uses, valid_for } => {
+            let creator = by.map(|(user, _)| user);
+            match shared.db.create_invite(creator, uses, valid_for).await {
+                Ok((code, expires)) => {
+                    let expires = expires.map(|at| at.to_rfc3339_opts(SecondsFormat::Secs, true));
+                    match by {
+                        Some((_, session)) => {
+                            let invite = ser::Msg::Invite {
+                                code,
+                                uses,
+                                expires,
+                            };
+                            Ok(vec![Reply(session, invite)])
+                        }
+                        None => {
+                            let expires = expires.as_deref().unwrap_or("never");
+                            println!("Invitation {code} for {uses} sign-ups, expires {expires}");
+                            Ok(vec![])
+                        }
+                    }
+                }
+                Err(e) => {
+                    error!(code = %e.code(), "Creating an invitation failed! Error {e}");
+                    Err(ser::Error::from(&e))
+                }
+            }
+        }
+    }
+}
+
+async fn queue(tasks: &Sender<Task>, task: Task) -> anyhow::Result<()> {
+    tasks
+        .send(task)
+        .await
+        .with_context(|| "Emergency! Task queue stopped working!")
+}
+
+/// Writes every received message from `messages` into `writer`, encoded by the `codec`.
+///
+/// The in-flight memory of a shared message is released once the last client wrote it.
+/// A client which does not read a message in [WRITE_TIMEOUT] is given up, the writer is closed.
+async fn write_each_msg(
+    mut messages: Receiver<Outgoing>,
+    mut writer: Counted<WriteHalf<tls::ClientStream>>,
+    codec: Codec,
+) {
     while let Some(msg) = messages.recv().await {
-        if let Err(e) = msg.send(&mut writer).await {
-            error!("Writing the message {msg} to {writer:?} failed! Error {e}")
+        let sent = match &msg {
+            Outgoing::Msg(msg) => deadline(WRITE_TIMEOUT, msg.send_with(codec, &mut writer)).await,
+            Outgoing::Shared(fanout) => match fanout.encoded(codec) {
+                Ok(encoded) => deadline(WRITE_TIMEOUT, encoded.send(&mut writer)).await,
+                Err(e) => Err(e),
+            },
+        };
+        match sent {
+            Ok(()) => {}
+            Err(e @ TimedOut(_)) => {
+                warn!("Writing the message {msg} to {writer:?} stalled, the client is given up! Error {e}");
+                break;
+            }
+            Err(e) => error!("Writing the message {msg} to {writer:?} failed! Error {e}"),
         }
     }
 }
 
-/// Subscribes to tracing (and logging), outputs to stdout and a log file.
+/// How long writing a single message to a client may take, see [write_each_msg].
+const WRITE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Subscribes to tracing (and logging), outputs to stdout and [log files][Logs].
 ///
-/// Returns WorkerGuard which must be kept for the intended time of log capturing.
-pub fn init_logging_stdout_and_file() -> anyhow::Result<WorkerGuard> {
-    let term_layer = tracing_subscriber::fmt::layer().with_filter(LevelFilter::INFO);
-
-    let file = std::fs::File::create(format!(
-        "{}.log",
-        Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
-    ))
-    .with_context(|| "Log file creation should be possible, please check your permissions.")?;
-    let (non_blocking, guard) = tracing_appender::non_blocking(file);
+/// Returns WorkerGuard which must be kept for the intended time of log capturing,
+/// and the [LogLevel] of the standard output, the files get everything.
+pub fn init_logging_stdout_and_file(logs: &Logs) -> anyhow::Result<(WorkerGuard, LogLevel)> {
+    let (level, handle) = tracing_subscriber::reload::Layer::new(logs.level.clone());
+    let term_layer = tracing_subscriber::fmt::layer().with_filter(level);
+
+    let (non_blocking, guard) = tracing_appender::non_blocking(logs.writer()?);
     let file_layer = tracing_subscriber::fmt::layer()
         .with_writer(non_blocking)
         .with_filter(LevelFilter::TRACE);
@@ -273,10 +2305,11 @@ pub fn init_logging_stdout_and_file() -> anyhow::Result<WorkerGuard> {
         .with(term_layer)
         .with(file_layer)
         .init(); // sets itself as global default subscriber
-    Ok(guard)
+    Ok((guard, LogLevel::new(handle)))
 }
 
 #[cfg(test)]
 mod tests {
-    // Since most operations are almost exclusively IO, there are currently only integration tests, you can find them in the tests directory.
+    // Since most operations are almost exclusively IO, there are mostly integration tests, you can find them in the tests directory.
+    // The routing is tested without IO by the simulation module.
 }
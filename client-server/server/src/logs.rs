@@ -0,0 +1,283 @@
+//! Log files, rotated by time or size, see [Logs].
+use std::{
+    fmt, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::Context;
+use chrono::offset::Utc;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::{
+    filter::{LevelFilter, Targets},
+    reload, Registry,
+};
+
+/// Log file names are `server.<TIME>.log`, a file started in the same millisecond is `server.<TIME>_<N>.log`.
+const PREFIX: &str = "server";
+const SUFFIX: &str = "log";
+
+/// When a new log file is started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogRotation {
+    Hourly,
+    Daily,
+    /// When the file would grow over the given number of bytes.
+    Size(u64),
+    /// One file for the whole run.
+    Never,
+}
+impl FromStr for LogRotation {
+    type Err = String;
+
+    /// Parses "hourly", "daily", "never" or the size in bytes.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hourly" => Ok(LogRotation::Hourly),
+            "daily" => Ok(LogRotation::Daily),
+            "never" => Ok(LogRotation::Never),
+            bytes => match bytes.parse() {
+                Ok(0) | Err(_) => Err(format!(
+                    "\"{s}\" is not hourly, daily, never nor a positive number of bytes"
+                )),
+                Ok(bytes) => Ok(LogRotation::Size(bytes)),
+            },
+        }
+    }
+}
+impl fmt::Display for LogRotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogRotation::Hourly => write!(f, "hourly"),
+            LogRotation::Daily => write!(f, "daily"),
+            LogRotation::Size(bytes) => write!(f, "{bytes}"),
+            LogRotation::Never => write!(f, "never"),
+        }
+    }
+}
+
+/// Where the log files go and how they are rotated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Logs {
+    pub dir: PathBuf,
+    pub rotation: LogRotation,
+    /// How many of the newest files are kept, all of them when `None`.
+    pub keep: Option<usize>,
+    /// What is printed to the standard output, e.g. `info,server::db=debug`, the files get everything.
+    pub level: Targets,
+}
+impl Default for Logs {
+    fn default() -> Self {
+        Logs {
+            dir: PathBuf::from("."),
+            rotation: LogRotation::Daily,
+            keep: None,
+            level: Targets::new().with_default(LevelFilter::INFO),
+        }
+    }
+}
+impl Logs {
+    /// Opens the writer of the log files.
+    pub(crate) fn writer(&self) -> anyhow::Result<Box<dyn Write + Send>> {
+        let max_bytes = match self.rotation {
+            LogRotation::Hourly => return self.appender(Rotation::HOURLY),
+            LogRotation::Daily => return self.appender(Rotation::DAILY),
+            LogRotation::Size(bytes) => Some(bytes),
+            LogRotation::Never => None,
+        };
+        Ok(Box::new(SizeRolling::new(&self.dir, max_bytes, self.keep)?))
+    }
+
+    /// Time based rotation is left to [tracing_appender].
+    fn appender(&self, rotation: Rotation) -> anyhow::Result<Box<dyn Write + Send>> {
+        let builder = RollingFileAppender::builder()
+            .rotation(rotation)
+            .filename_prefix(PREFIX)
+            .filename_suffix(SUFFIX);
+        let builder = match self.keep {
+            Some(keep) => builder.max_log_files(keep),
+            None => builder,
+        };
+        let appender = builder
+            .build(&self.dir)
+            .with_context(|| format!("Log files in {:?} can not be created", self.dir))?;
+        Ok(Box::new(appender))
+    }
+}
+
+/// Changes what is printed to the standard output while the server runs, see [Logs::level].
+///
+/// Returned by [init_logging_stdout_and_file][crate::init_logging_stdout_and_file],
+/// given to [Server::log_level][crate::Server::log_level] it is set from the console, the HTTP API or on reload.
+#[derive(Clone)]
+pub struct LogLevel(reload::Handle<Targets, Registry>);
+impl LogLevel {
+    pub(crate) fn new(handle: reload::Handle<Targets, Registry>) -> Self {
+        LogLevel(handle)
+    }
+
+    /// The filter in place, e.g. `info,server::db=debug`.
+    pub fn current(&self) -> String {
+        self.0.with_current(Targets::to_string).unwrap_or_default()
+    }
+
+    /// Puts the `filter` in place of the current one.
+    pub fn set(&self, filter: Targets) -> anyhow::Result<()> {
+        self.0
+            .reload(filter)
+            .context("Changing the log level failed, the logging stopped!")
+    }
+}
+
+/// Parses a filter of [Logs::level], e.g. `debug` or `warn,server=debug,server::db=trace`.
+pub fn parse_level(filter: &str) -> Result<Targets, String> {
+    filter
+        .parse()
+        .map_err(|e| format!("\"{filter}\" is not a log level filter: {e}"))
+}
+
+/// Starts a new file when the current one would exceed `max_bytes`, never when it is `None`.
+///
+/// The oldest files above `keep` are removed whenever a file is started.
+struct SizeRolling {
+    dir: PathBuf,
+    max_bytes: Option<u64>,
+    keep: Option<usize>,
+    file: fs::File,
+    written: u64,
+}
+impl SizeRolling {
+    fn new(dir: &Path, max_bytes: Option<u64>, keep: Option<usize>) -> anyhow::Result<Self> {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Log directory {dir:?} can not be created"))?;
+        let file = Self::create(dir).with_context(|| {
+            "Log file creation should be possible, please check your permissions."
+        })?;
+        let rolling = SizeRolling {
+            dir: dir.to_path_buf(),
+            max_bytes,
+            keep,
+            file,
+            written: 0,
+        };
+        rolling.prune()?;
+        Ok(rolling)
+    }
+
+    /// Creates a new file, an existing one is never replaced.
+    fn create(dir: &Path) -> io::Result<fs::File> {
+        let time = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ");
+        // E.g. `server.2024-01-31T18-00-00.000Z.log`, colons are not allowed on Windows.
+        let time = cli_ser::sanitize_file_name(&time.to_string());
+        let mut path = dir.join(format!("{PREFIX}.{time}.{SUFFIX}"));
+        for n in 1.. {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    // `_` sorts after `.`, the numbered file is newer for the pruning.
+                    path = dir.join(format!("{PREFIX}.{time}_{n}.{SUFFIX}"));
+                }
+                created => return created,
+            }
+        }
+        unreachable!("some number is free")
+    }
+
+    /// Removes the oldest log files so that at most `keep` are left, names sort by time.
+    fn prune(&self) -> io::Result<()> {
+        let Some(keep) = self.keep else {
+            return Ok(());
+        };
+        let mut logs: Vec<PathBuf> = fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| {
+                path.file_name().and_then(|n| n.to_str()).is_some_and(|n| {
+                    n.starts_with(&format!("{PREFIX}.")) && n.ends_with(&format!(".{SUFFIX}"))
+                })
+            })
+            .collect();
+        logs.sort();
+        for old in &logs[..logs.len().saturating_sub(keep)] {
+            fs::remove_file(old)?;
+        }
+        Ok(())
+    }
+}
+impl Write for SizeRolling {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(max_bytes) = self.max_bytes {
+            if self.written > 0 && self.written + buf.len() as u64 > max_bytes {
+                self.file.flush()?;
+                self.file = Self::create(&self.dir)?;
+                self.written = 0;
+                self.prune()?;
+            }
+        }
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rotation() {
+        assert_eq!("daily".parse(), Ok(LogRotation::Daily));
+        assert_eq!("hourly".parse(), Ok(LogRotation::Hourly));
+        assert_eq!("never".parse(), Ok(LogRotation::Never));
+        assert_eq!("1048576".parse(), Ok(LogRotation::Size(1048576)));
+        assert!("0".parse::<LogRotation>().is_err());
+        assert!("weekly".parse::<LogRotation>().is_err());
+    }
+
+    #[test]
+    fn parse_levels() {
+        let level = parse_level("warn,server::db=debug").unwrap();
+        assert!(level.would_enable("server::db", &tracing::Level::DEBUG));
+        assert!(!level.would_enable("server", &tracing::Level::INFO));
+        assert_eq!(
+            parse_level("debug").unwrap(),
+            Targets::new().with_default(LevelFilter::DEBUG)
+        );
+        assert!(parse_level("server=loud").is_err());
+    }
+
+    #[test]
+    fn size_rolling_keeps_newest() {
+        let dir = std::env::temp_dir().join(format!(
+            "server-logs-{}",
+            Utc::now().timestamp_nanos_opt().unwrap()
+        ));
+        let mut writer = SizeRolling::new(&dir, Some(10), Some(2)).unwrap();
+        // Files started within a millisecond do not replace each other.
+        for line in ["first 1\n", "second\n", "third 3\n"] {
+            writer.write_all(line.as_bytes()).unwrap();
+        }
+        writer.flush().unwrap();
+        let mut names: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .collect();
+        names.sort();
+        for name in names.iter().filter_map(|path| path.file_name()) {
+            assert!(!name.to_string_lossy().contains(':'), "{name:?}");
+        }
+        let contents: Vec<_> = names
+            .iter()
+            .map(|path| fs::read_to_string(path).unwrap())
+            .collect();
+        assert_eq!(contents, ["second\n", "third 3\n"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,24 @@
+//! Delivery of password reset codes, see [Mailer].
+use cli_ser::User;
+use tracing::info;
+
+/// Sends the password reset codes to the users, e.g. by SMTP or a mailing service.
+///
+/// The server calls it on a blocking thread, so it may block until the code is handed over.
+pub trait Mailer: Send + Sync {
+    /// Sends the `code` to the `email` of the `user`, a failure is logged, the client is not told.
+    fn send_reset_code(&self, email: &str, user: &User, code: &str) -> anyhow::Result<()>;
+}
+
+/// Writes the codes to the log instead of sending them, the default of the [Server][crate::Server::mailer].
+///
+/// Meant for development and for operators passing the codes on themselves,
+/// anyone reading the log can reset the passwords.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LogMailer;
+impl Mailer for LogMailer {
+    fn send_reset_code(&self, email: &str, user: &User, code: &str) -> anyhow::Result<()> {
+        info!("Password reset code of {user} for {email}: {code}");
+        Ok(())
+    }
+}
@@ -1,31 +1,880 @@
-use std::net::{IpAddr, SocketAddr};
+use std::{
+    fs,
+    io::{self, BufRead, IsTerminal, Write},
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use clap::Parser;
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
+use clap::{builder::BoolishValueParser, Parser, Subcommand};
+
+use cli_ser::{challenge, cli::Credentials, ImageLimits, ImageOutputFormat};
+use server::{config::LogConfig, filter, ConfigFile};
+use tracing_subscriber::filter::Targets;
+
+/// Configuration file looked for when none is given.
+const CONFIG_DEFAULT: &str = "server.toml";
 
 /// Server executable, listens at specified address and broadcasts messages to all connected clients.
+///
+/// Options are taken from the command line, then from the environment variables,
+/// then from the configuration file and finally the defaults are used.
+/// Switches take an optional value, e.g. `--allow-guests=false` turns off what the file turns on.
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
-    /// Server host
-    #[arg(long, default_value_t = format!("{}", IpAddr::from(server::HOST_DEFAULT)))]
-    host: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// TOML configuration file, "server.toml" is used when it exists
+    #[arg(long, value_name = "FILE", env = "SERVER_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Database URL, see the server's documentation
+    #[arg(long, global = true, env = "DATABASE_URL")]
+    database_url: Option<String>,
+
+    /// Connections to the database open at once at most [default: 5]
+    #[arg(
+        long,
+        value_name = "N",
+        global = true,
+        env = "DATABASE_MAX_CONNECTIONS"
+    )]
+    db_max_connections: Option<u32>,
+
+    /// Seconds a query waits for a free database connection before it fails [default: 30]
+    #[arg(
+        long,
+        value_name = "SECS",
+        global = true,
+        env = "DATABASE_ACQUIRE_TIMEOUT"
+    )]
+    db_acquire_timeout: Option<u64>,
+
+    /// Seconds after which the database cancels a statement, not limited by default
+    #[arg(
+        long,
+        value_name = "SECS",
+        global = true,
+        env = "DATABASE_STATEMENT_TIMEOUT"
+    )]
+    db_statement_timeout: Option<u64>,
+
+    /// Attempts to connect when the database is not reachable at start [default: 5]
+    #[arg(
+        long,
+        value_name = "N",
+        global = true,
+        env = "DATABASE_CONNECT_RETRIES"
+    )]
+    db_connect_retries: Option<u32>,
+
+    /// Seconds before the first connection retry, doubled with each further one [default: 1]
+    #[arg(long, value_name = "SECS", global = true, env = "DATABASE_RETRY_DELAY")]
+    db_retry_delay: Option<u64>,
+
+    /// Messages stored in the database at once [default: 64]
+    #[arg(long, value_name = "N", global = true, env = "DATABASE_BATCH_SIZE")]
+    db_batch_size: Option<usize>,
+
+    /// Milliseconds after which buffered messages are stored [default: 100]
+    #[arg(
+        long,
+        value_name = "MS",
+        global = true,
+        env = "DATABASE_FLUSH_INTERVAL_MS"
+    )]
+    db_flush_interval_ms: Option<u64>,
+
+    /// Memory in KiB of the Argon2 password hashes [default: 19456]
+    #[arg(
+        long,
+        value_name = "KIB",
+        global = true,
+        env = "SERVER_ARGON2_MEMORY_KIB"
+    )]
+    argon2_memory_kib: Option<u32>,
+
+    /// Iterations of the Argon2 password hashes [default: 2]
+    #[arg(
+        long,
+        value_name = "N",
+        global = true,
+        env = "SERVER_ARGON2_ITERATIONS"
+    )]
+    argon2_iterations: Option<u32>,
+
+    /// Parallelism of the Argon2 password hashes [default: 1]
+    #[arg(
+        long,
+        value_name = "N",
+        global = true,
+        env = "SERVER_ARGON2_PARALLELISM"
+    )]
+    argon2_parallelism: Option<u32>,
+
+    /// Where the bytes of attachments are kept, "database" or "local:<dir>" [default: database]
+    #[arg(long, value_name = "STORE", global = true, env = "SERVER_BLOB_STORE")]
+    blob_store: Option<server::BlobStore>,
+
+    /// Delete messages older than this many days, kept by default
+    #[arg(
+        long,
+        value_name = "DAYS",
+        global = true,
+        env = "SERVER_RETENTION_MAX_AGE_DAYS"
+    )]
+    retention_max_age_days: Option<u64>,
+
+    /// Delete all but this many newest messages, all are kept by default
+    #[arg(
+        long,
+        value_name = "N",
+        global = true,
+        env = "SERVER_RETENTION_MAX_MESSAGES"
+    )]
+    retention_max_messages: Option<u64>,
+
+    /// Seconds between the deletions of old messages [default: 3600]
+    #[arg(long, value_name = "SECS", env = "SERVER_RETENTION_INTERVAL")]
+    retention_interval: Option<u64>,
+
+    /// Only log how many messages would be deleted
+    #[arg(
+        long,
+        global = true,
+        env = "SERVER_RETENTION_DRY_RUN",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_parser = BoolishValueParser::new(),
+    )]
+    retention_dry_run: Option<bool>,
+
+    /// Server host [default: 127.0.0.1]
+    #[arg(long, env = "SERVER_HOST")]
+    host: Option<String>,
+
+    /// Server port [default: 11111]
+    #[arg(short, long, env = "SERVER_PORT")]
+    port: Option<u16>,
+
+    /// Address to listen at, can be repeated, e.g. "0.0.0.0:11111" and "[::]:11111", replaces --host and --port
+    #[arg(
+        long,
+        value_name = "ADDR",
+        env = "SERVER_LISTEN",
+        value_delimiter = ','
+    )]
+    listen: Vec<SocketAddr>,
+
+    /// Certificate chain of the TLS connections, a PEM file, only TLS clients are accepted when given
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "tls_key",
+        env = "SERVER_TLS_CERT"
+    )]
+    tls_cert: Option<PathBuf>,
+
+    /// Private key of the TLS certificate, a PEM file
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "tls_cert",
+        env = "SERVER_TLS_KEY"
+    )]
+    tls_key: Option<PathBuf>,
+
+    /// Reject messages of a user over this many per the rate limit period, unlimited by default
+    #[arg(long, value_name = "N", env = "SERVER_RATE_LIMIT")]
+    rate_limit: Option<usize>,
+
+    /// Period the messages of a user are counted over, in seconds [default: 60]
+    #[arg(long, value_name = "SECS", env = "SERVER_RATE_LIMIT_PERIOD")]
+    rate_limit_period: Option<u64>,
+
+    /// Maximal number of connections open at once
+    #[arg(long, value_name = "N", env = "SERVER_MAX_CONNECTIONS")]
+    max_connections: Option<usize>,
+
+    /// Maximal number of connections open at once from a single IP address
+    #[arg(long, value_name = "N", env = "SERVER_MAX_CONNECTIONS_PER_IP")]
+    max_connections_per_ip: Option<usize>,
+
+    /// When a user with a session logs in again: "allow", "kick-old" or "reject-new" [default: allow]
+    #[arg(long, value_name = "POLICY", env = "SERVER_SESSIONS_OF_USER")]
+    sessions_of_user: Option<server::SessionPolicy>,
+
+    /// Let clients join as guests, unregistered and not stored, e.g. for demos
+    #[arg(
+        long,
+        env = "SERVER_ALLOW_GUESTS",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_parser = BoolishValueParser::new(),
+    )]
+    allow_guests: Option<bool>,
+
+    /// Sign up only clients with an invitation, made by `invite` in the console or by administrators
+    #[arg(
+        long,
+        env = "SERVER_INVITE_ONLY",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_parser = BoolishValueParser::new(),
+    )]
+    invite_only: Option<bool>,
+
+    /// Make clients signing up solve a proof of work of this many bits first, e.g. 20 [default: off]
+    #[arg(long, value_name = "BITS", env = "SERVER_SIGNUP_DIFFICULTY")]
+    signup_difficulty: Option<u8>,
+
+    /// Log users in by tokens of this OpenID Connect provider, e.g. "https://accounts.example.com"
+    #[arg(long, value_name = "URL", env = "SERVER_OIDC_ISSUER")]
+    oidc_issuer: Option<String>,
+
+    /// Client id the tokens have to be issued for, any when not given
+    #[arg(long, value_name = "CLIENT_ID", env = "SERVER_OIDC_AUDIENCE")]
+    oidc_audience: Option<String>,
+
+    /// URL or file of the provider's keys (JWKS), discovered from the issuer when not given
+    #[arg(long, value_name = "URL|FILE", env = "SERVER_OIDC_JWKS")]
+    oidc_jwks: Option<String>,
+
+    /// Key encrypting the two-factor secrets, 32 bytes in base64, e.g. by `openssl rand -base64 32`,
+    /// users can enable two-factor authentication only when it is given
+    #[arg(
+        long,
+        value_name = "BASE64",
+        env = "SERVER_TOTP_KEY",
+        hide_env_values = true
+    )]
+    totp_key: Option<server::TotpKey>,
+
+    /// Tell clients whether the user does not exist or the password is wrong, e.g. for development
+    #[arg(
+        long,
+        env = "SERVER_DISTINCT_LOGIN_ERRORS",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_parser = BoolishValueParser::new(),
+    )]
+    distinct_login_errors: Option<bool>,
+
+    /// Network allowed to connect, e.g. "10.0.0.0/8", can be repeated, everyone is allowed when none is given
+    #[arg(long, value_name = "CIDR", env = "SERVER_ALLOW", value_delimiter = ',')]
+    allow: Vec<server::Cidr>,
+
+    /// Network refused to connect, e.g. "10.0.0.66", can be repeated, takes precedence over --allow
+    #[arg(long, value_name = "CIDR", env = "SERVER_DENY", value_delimiter = ',')]
+    deny: Vec<server::Cidr>,
+
+    /// Ceiling of bytes held by in-flight messages, reading from clients pauses when reached [default: 268435456]
+    #[arg(long, env = "SERVER_MAX_INFLIGHT_BYTES")]
+    max_inflight_bytes: Option<usize>,
+
+    #[command(flatten)]
+    reloadable: ReloadableArgs,
+
+    /// Let the moderation service at this URL review texts before they are broadcast
+    #[arg(long, value_name = "URL", env = "SERVER_MODERATION_URL")]
+    moderation_url: Option<String>,
+
+    /// How long the moderation service may take to answer [default: 1000]
+    #[arg(long, value_name = "MS", env = "SERVER_MODERATION_TIMEOUT_MS")]
+    moderation_timeout_ms: Option<u64>,
+
+    /// Whether texts are let through ("open") or rejected ("closed") when moderation fails [default: open]
+    #[arg(long, value_name = "POLICY", env = "SERVER_MODERATION_ON_FAILURE")]
+    moderation_on_failure: Option<server::moderation::FailurePolicy>,
+
+    /// How many verdicts of the moderation service are cached [default: 10000]
+    #[arg(long, value_name = "N", env = "SERVER_MODERATION_CACHE_SIZE")]
+    moderation_cache_size: Option<usize>,
+
+    /// Re-encode images bigger than this many bytes before broadcasting and storing them
+    #[arg(long, value_name = "BYTES", env = "SERVER_REENCODE_IMAGES_OVER")]
+    reencode_images_over: Option<usize>,
 
-    /// Server port
-    #[arg(short, long, default_value_t = server::PORT_DEFAULT)]
-    port: u16,
+    /// Format of re-encoded images, e.g. "jpeg:75" (JPEG quality 75) or "webp" [default: jpeg:80]
+    #[arg(long, value_name = "FORMAT", value_parser = cli_ser::parse_image_format, env = "SERVER_REENCODE_FORMAT")]
+    reencode_format: Option<ImageOutputFormat>,
+
+    /// Store the originals of re-encoded images as well
+    #[arg(
+        long,
+        env = "SERVER_KEEP_ORIGINAL_IMAGES",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_parser = BoolishValueParser::new(),
+    )]
+    keep_original_images: Option<bool>,
+
+    /// Reject images and avatars bigger than this many bytes [default: 33554432]
+    #[arg(long, value_name = "BYTES", env = "SERVER_MAX_IMAGE_BYTES")]
+    max_image_bytes: Option<usize>,
+
+    /// Reject images wider or higher, e.g. "8192x8192" [default: 16384x16384]
+    #[arg(long, value_name = "WIDTHxHEIGHT", value_parser = cli_ser::parse_dimensions, env = "SERVER_MAX_IMAGE_DIMENSIONS")]
+    max_image_dimensions: Option<(u32, u32)>,
+
+    /// Reject images of more pixels (width times height) [default: 67108864]
+    #[arg(long, value_name = "PIXELS", env = "SERVER_MAX_IMAGE_PIXELS")]
+    max_image_pixels: Option<u64>,
+
+    /// Reject attachments of users who uploaded this many bytes in total, unlimited by default
+    #[arg(long, value_name = "BYTES", env = "SERVER_STORAGE_QUOTA")]
+    storage_quota: Option<u64>,
+
+    /// Directory of the log files [default: .]
+    #[arg(long, value_name = "DIR", env = "SERVER_LOG_DIR")]
+    log_dir: Option<PathBuf>,
+
+    /// When to start a new log file: "hourly", "daily", "never" or the maximal size in bytes [default: daily]
+    #[arg(long, value_name = "WHEN", env = "SERVER_LOG_ROTATION")]
+    log_rotation: Option<server::LogRotation>,
+
+    /// Number of the newest log files to keep, older ones are removed, all are kept by default
+    #[arg(long, value_name = "N", env = "SERVER_LOG_KEEP")]
+    log_keep: Option<usize>,
+
+    /// Unix socket bots can connect to, see the server's documentation
+    #[arg(long, value_name = "PATH", env = "SERVER_BOT_SOCKET")]
+    bot_socket: Option<PathBuf>,
+
+    /// Port of the HTTP API at the server's host, the API is off by default
+    #[arg(long, value_name = "PORT", env = "SERVER_HTTP_PORT")]
+    http_port: Option<u16>,
+
+    /// Token the HTTP API requests have to carry as "Authorization: Bearer <TOKEN>"
+    #[arg(long, value_name = "TOKEN", env = "SERVER_HTTP_TOKEN")]
+    http_token: Option<String>,
+
+    /// Do not take commands from the terminal, by default they are taken when stdin is a terminal
+    #[arg(long, env = "SERVER_NO_CONSOLE")]
+    no_console: bool,
+}
+
+/// Options of the settings reloaded on `SIGHUP` or `reload` in the console, see [server::Reloadable].
+#[derive(clap::Args, Debug, Clone)]
+struct ReloadableArgs {
+    /// Reject texts longer than this many characters
+    #[arg(long, value_name = "N", env = "SERVER_MAX_TEXT_LENGTH")]
+    max_text_length: Option<usize>,
+
+    /// Reject texts containing words from the file, one word per line
+    #[arg(long, value_name = "FILE", env = "SERVER_BANNED_WORDS")]
+    banned_words: Option<PathBuf>,
+
+    /// Only files and images of these types are let through, e.g. "png,jpeg,pdf"
+    #[arg(
+        long,
+        value_name = "TYPES",
+        env = "SERVER_ALLOWED_ATTACHMENTS",
+        value_delimiter = ','
+    )]
+    allowed_attachments: Vec<String>,
+
+    /// Reject urgent texts of a user over this many per the urgent period [default: 3]
+    #[arg(long, value_name = "N", env = "SERVER_MAX_URGENT")]
+    max_urgent: Option<usize>,
+
+    /// Period the urgent texts of a user are counted over, in seconds [default: 600]
+    #[arg(long, value_name = "SECS", env = "SERVER_URGENT_PERIOD")]
+    urgent_period: Option<u64>,
+
+    /// Message of the day, sent to clients right after they authenticate
+    #[arg(long, env = "SERVER_MOTD")]
+    motd: Option<String>,
+
+    /// File with the message of the day
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with = "motd",
+        env = "SERVER_MOTD_FILE"
+    )]
+    motd_file: Option<PathBuf>,
+
+    /// What is logged to the standard output, e.g. "debug" or "info,server::db=debug",
+    /// the log files get everything [default: info]
+    #[arg(long, value_name = "FILTER", value_parser = server::parse_level, env = "SERVER_LOG_LEVEL")]
+    log_level: Option<Targets>,
+}
+impl ReloadableArgs {
+    /// Log level from the option, or from the `file` when it is not given.
+    fn log_level(&self, file: &LogConfig) -> anyhow::Result<Option<Targets>> {
+        match (&self.log_level, &file.level) {
+            (Some(level), _) => Ok(Some(level.clone())),
+            (None, Some(level)) => server::parse_level(level)
+                .map(Some)
+                .map_err(|e| anyhow!("Log level in the configuration file: {e}")),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Settings from the options, or from the `file` when they are not given, the files they name are read.
+    fn load(&self, file: ConfigFile) -> anyhow::Result<server::Reloadable> {
+        let motd = match (&self.motd, &self.motd_file) {
+            (Some(motd), _) => Some(motd.clone()),
+            (None, Some(path)) => Some(read_motd(path)?),
+            (None, None) => match (file.motd, file.motd_file) {
+                (Some(motd), _) => Some(motd),
+                (None, Some(path)) => Some(read_motd(&path)?),
+                (None, None) => None,
+            },
+        };
+        let mut filters: Vec<Box<dyn server::MessageFilter>> = vec![];
+        if let Some(max) = self.max_text_length.or(file.filters.max_text_length) {
+            filters.push(Box::new(filter::MaxTextLength(max)));
+        }
+        if let Some(path) = self.banned_words.clone().or(file.filters.banned_words) {
+            filters.push(Box::new(filter::BannedWords::from_file(path)?));
+        }
+        let allowed = match &self.allowed_attachments {
+            allowed if !allowed.is_empty() => Some(allowed.clone()),
+            _ => file.filters.allowed_attachments,
+        };
+        if let Some(allowed) = allowed {
+            filters.push(Box::new(filter::AttachmentTypes::new(allowed)));
+        }
+        let defaults = server::UrgentLimit::default();
+        let urgent_limit = server::UrgentLimit {
+            max: self
+                .max_urgent
+                .or(file.filters.max_urgent)
+                .unwrap_or(defaults.max),
+            period: self
+                .urgent_period
+                .or(file.filters.urgent_period)
+                .map_or(defaults.period, Duration::from_secs),
+        };
+        Ok(server::Reloadable {
+            motd,
+            filters,
+            urgent_limit,
+            log_level: self.log_level(&file.log)?,
+        })
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Creates the database tables and the first administrator account, writes the configuration file
+    /// (--config, "server.toml" by default) and generates a self-signed TLS certificate, then exits.
+    ///
+    /// The password is taken from SERVER_ADMIN_PASSWORD or asked for without echo.
+    Init {
+        /// Administrator username, asked for when not given
+        #[arg(long)]
+        admin: Option<String>,
+
+        /// Directory the TLS certificate and key are written to
+        #[arg(long, value_name = "DIR", default_value = "tls")]
+        tls_dir: PathBuf,
+
+        /// Host name the certificate is valid for, may be repeated
+        #[arg(long, value_name = "HOST", default_value = "localhost")]
+        tls_name: Vec<String>,
+    },
+    /// Writes the stored messages to a file, attachments go to the directory <OUT>_attachments next to it.
+    Export {
+        /// "jsonl", "csv" or "html"
+        #[arg(long, default_value = "jsonl")]
+        format: server::export::Format,
+
+        /// Only messages since the date, e.g. "2024-01-31", or the RFC 3339 time
+        #[arg(long, value_name = "DATE", value_parser = server::export::parse_since)]
+        since: Option<DateTime<Utc>>,
+
+        /// Output file
+        #[arg(long, value_name = "PATH")]
+        out: PathBuf,
+    },
+    /// Deletes old messages once as the --retention-* options say, then exits.
+    Purge,
+}
+
+/// Prints the `prompt` and reads one line from the standard input.
+fn ask(prompt: &str) -> anyhow::Result<String> {
+    print!("{prompt}: ");
+    io::stdout().flush()?;
+    let line = io::stdin()
+        .lock()
+        .lines()
+        .next()
+        .context("Standard input was closed")??;
+    Ok(line.trim().to_string())
 }
-impl Args {
-    pub fn parse_to_address() -> anyhow::Result<SocketAddr> {
-        let args = Self::parse();
-        let ip: IpAddr = args.host.parse()?;
-        Ok(SocketAddr::from((ip, args.port)))
+
+/// Environment variable with the password of the administrator `init` creates.
+const ADMIN_PASSWORD_ENV: &str = "SERVER_ADMIN_PASSWORD";
+
+/// Password of the administrator from [ADMIN_PASSWORD_ENV], else asked for twice without echo.
+fn admin_password() -> anyhow::Result<String> {
+    if let Ok(password) = std::env::var(ADMIN_PASSWORD_ENV) {
+        return Ok(password);
     }
+    let password = rpassword::prompt_password("Administrator password: ")?;
+    if password.is_empty() {
+        anyhow::bail!("The password is empty");
+    }
+    if rpassword::prompt_password("Repeat the password: ")? != password {
+        anyhow::bail!("The passwords differ");
+    }
+    Ok(password)
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let address = Args::parse_to_address()?;
-    let _log_file_guard = server::init_logging_stdout_and_file()?;
-    let server = server::Server::build(address).await?;
-    server.run().await
+    let args = Args::parse();
+    let file = load_config(args.config.as_deref())?;
+    let log_rotation = match (args.log_rotation, file.log.rotation) {
+        (Some(rotation), _) => rotation,
+        (None, Some(rotation)) => rotation
+            .parse()
+            .map_err(|e| anyhow!("Log rotation in the configuration file: {e}"))?,
+        (None, None) => server::LogRotation::Daily,
+    };
+    let level = args.reloadable.log_level(&file.log)?;
+    let (_log_file_guard, log_level) = server::init_logging_stdout_and_file(&server::Logs {
+        dir: args.log_dir.or(file.log.dir).unwrap_or(PathBuf::from(".")),
+        rotation: log_rotation,
+        keep: args.log_keep.or(file.log.keep),
+        level: level.unwrap_or(server::Logs::default().level),
+    })?;
+    let database_url = args.database_url.or(file.database_url).context(
+        "Database URL is given neither by --database-url, DATABASE_URL nor the configuration file",
+    )?;
+    let defaults = server::DatabaseOptions::default();
+    let database = server::DatabaseOptions {
+        max_connections: args
+            .db_max_connections
+            .or(file.database.max_connections)
+            .unwrap_or(defaults.max_connections),
+        acquire_timeout: args
+            .db_acquire_timeout
+            .or(file.database.acquire_timeout)
+            .map_or(defaults.acquire_timeout, Duration::from_secs),
+        statement_timeout: args
+            .db_statement_timeout
+            .or(file.database.statement_timeout)
+            .map(Duration::from_secs),
+        connect_retries: args
+            .db_connect_retries
+            .or(file.database.connect_retries)
+            .unwrap_or(defaults.connect_retries),
+        retry_delay: args
+            .db_retry_delay
+            .or(file.database.retry_delay)
+            .map_or(defaults.retry_delay, Duration::from_secs),
+        batch_size: args
+            .db_batch_size
+            .or(file.database.batch_size)
+            .unwrap_or(defaults.batch_size),
+        flush_interval: args
+            .db_flush_interval_ms
+            .or(file.database.flush_interval_ms)
+            .map_or(defaults.flush_interval, Duration::from_millis),
+        blob_store: match (args.blob_store, file.database.blob_store) {
+            (Some(store), _) => store,
+            (None, Some(store)) => store
+                .parse()
+                .map_err(anyhow::Error::msg)
+                .with_context(|| "Blob store in the configuration file")?,
+            (None, None) => defaults.blob_store,
+        },
+        password_hashing: server::PasswordHashing {
+            memory_kib: args
+                .argon2_memory_kib
+                .or(file.passwords.memory_kib)
+                .unwrap_or(defaults.password_hashing.memory_kib),
+            iterations: args
+                .argon2_iterations
+                .or(file.passwords.iterations)
+                .unwrap_or(defaults.password_hashing.iterations),
+            parallelism: args
+                .argon2_parallelism
+                .or(file.passwords.parallelism)
+                .unwrap_or(defaults.password_hashing.parallelism),
+        },
+    };
+    let retention_defaults = server::RetentionPolicy::default();
+    let retention = server::RetentionPolicy {
+        max_age: args
+            .retention_max_age_days
+            .or(file.retention.max_age_days)
+            .map(|days| Duration::from_secs(days.saturating_mul(24 * 60 * 60))),
+        max_messages: args.retention_max_messages.or(file.retention.max_messages),
+        interval: args
+            .retention_interval
+            .or(file.retention.interval)
+            .map_or(retention_defaults.interval, Duration::from_secs),
+        dry_run: args
+            .retention_dry_run
+            .or(file.retention.dry_run)
+            .unwrap_or(false),
+    };
+    let retains = retention.max_age.is_some() || retention.max_messages.is_some();
+    match args.command {
+        Some(Command::Init {
+            admin,
+            tls_dir,
+            tls_name,
+        }) => {
+            let provision = server::Provision {
+                config: args.config.unwrap_or(PathBuf::from(CONFIG_DEFAULT)),
+                database_url,
+                tls_dir,
+                hosts: tls_name,
+            };
+            // Fail before asking anything.
+            provision.check()?;
+            let user = match admin {
+                Some(admin) => admin,
+                None => ask("Administrator username")?,
+            };
+            let admin = Credentials {
+                user: user.into(),
+                password: admin_password()?,
+            };
+            server::init(&provision, &database, admin).await
+        }
+        Some(Command::Export { format, since, out }) => {
+            let export = server::export::Export { format, since, out };
+            server::export(&database_url, &database, export).await
+        }
+        Some(Command::Purge) if !retains => Err(anyhow!(
+            "Nothing to delete, give --retention-max-age-days or --retention-max-messages"
+        )),
+        Some(Command::Purge) => server::purge(&database_url, &database, &retention)
+            .await
+            .map(|_| ()),
+        None => {
+            let host: IpAddr = match args.host.or(file.host) {
+                Some(host) => host.parse()?,
+                None => IpAddr::from(server::HOST_DEFAULT),
+            };
+            let port = args.port.or(file.port).unwrap_or(server::PORT_DEFAULT);
+            let mut addresses = match (args.listen, file.listen) {
+                (listen, _) if !listen.is_empty() => listen,
+                (_, Some(listen)) if !listen.is_empty() => listen,
+                _ => vec![SocketAddr::from((host, port))],
+            }
+            .into_iter();
+            let first = addresses.next().expect("at least one address");
+            let mut server =
+                server::Server::build_with_database_options(first, &database_url, &database)
+                    .await?
+                    .max_inflight_bytes(
+                        args.max_inflight_bytes
+                            .or(file.max_inflight_bytes)
+                            .unwrap_or(server::MAX_INFLIGHT_BYTES_DEFAULT),
+                    );
+            for address in addresses {
+                server = server.listen(address);
+            }
+            server = server.log_level(log_level);
+            // The configuration file is read again on each reload, the options stay.
+            let (config, reloadable) = (args.config, args.reloadable);
+            server = server.reload_with(move || reloadable.load(load_config(config.as_deref())?));
+            if let Some(threshold) = args.reencode_images_over.or(file.images.reencode_over) {
+                let format = match (args.reencode_format, file.images.format) {
+                    (Some(format), _) => format,
+                    (None, Some(format)) => cli_ser::parse_image_format(&format)
+                        .with_context(|| "Image format in the configuration file")?,
+                    (None, None) => ImageOutputFormat::Jpeg(80),
+                };
+                server = server.reencode_images(server::ImagePolicy {
+                    threshold,
+                    format,
+                    keep_original: args
+                        .keep_original_images
+                        .or(file.images.keep_original)
+                        .unwrap_or(false),
+                });
+            }
+            let defaults = ImageLimits::default();
+            let (max_width, max_height) =
+                match (args.max_image_dimensions, file.images.max_dimensions) {
+                    (Some(dimensions), _) => dimensions,
+                    (None, Some(dimensions)) => cli_ser::parse_dimensions(&dimensions)
+                        .with_context(|| "Image dimensions in the configuration file")?,
+                    (None, None) => (defaults.max_width, defaults.max_height),
+                };
+            server = server.image_limits(ImageLimits {
+                max_bytes: args
+                    .max_image_bytes
+                    .or(file.images.max_bytes)
+                    .unwrap_or(defaults.max_bytes),
+                max_width,
+                max_height,
+                max_pixels: args
+                    .max_image_pixels
+                    .or(file.images.max_pixels)
+                    .unwrap_or(defaults.max_pixels),
+            });
+            if retains {
+                server = server.retention(retention);
+            }
+            if let Some(quota) = args.storage_quota.or(file.storage_quota) {
+                server = server.storage_quota(quota);
+            }
+            if let Some(url) = args.moderation_url.or(file.moderation.url) {
+                let defaults = server::ModerationPolicy::default();
+                let on_failure = match (args.moderation_on_failure, file.moderation.on_failure) {
+                    (Some(policy), _) => policy,
+                    (None, Some(policy)) => policy.parse().map_err(|e| {
+                        anyhow!("Moderation failure policy in the configuration file: {e}")
+                    })?,
+                    (None, None) => defaults.on_failure,
+                };
+                let policy = server::ModerationPolicy {
+                    timeout: args
+                        .moderation_timeout_ms
+                        .or(file.moderation.timeout_ms)
+                        .map_or(defaults.timeout, Duration::from_millis),
+                    on_failure,
+                    cache_size: args
+                        .moderation_cache_size
+                        .or(file.moderation.cache_size)
+                        .unwrap_or(defaults.cache_size),
+                    ..defaults
+                };
+                server = server.moderator(server::moderation::HttpModerator::new(url), policy);
+            }
+            server = server.access(server::AccessPolicy {
+                max_connections: args.max_connections.or(file.access.max_connections),
+                max_per_ip: args.max_connections_per_ip.or(file.access.max_per_ip),
+                allow: networks(args.allow, file.access.allow)?,
+                deny: networks(args.deny, file.access.deny)?,
+            });
+            let sessions = match (args.sessions_of_user, file.access.sessions_of_user) {
+                (Some(policy), _) => policy,
+                (None, Some(policy)) => policy
+                    .parse()
+                    .map_err(|e| anyhow!("Sessions of a user in the configuration file: {e}"))?,
+                (None, None) => server::SessionPolicy::Allow,
+            };
+            server = server.sessions_of_user(sessions);
+            match (
+                args.tls_cert.or(file.tls.cert),
+                args.tls_key.or(file.tls.key),
+            ) {
+                (Some(cert), Some(key)) => server = server.tls(cert, key),
+                (None, None) => {}
+                _ => anyhow::bail!("TLS certificate and key must be given together"),
+            }
+            if let Some(max) = args.rate_limit.or(file.rate_limit.max) {
+                let period = args
+                    .rate_limit_period
+                    .or(file.rate_limit.period)
+                    .unwrap_or(60);
+                server = server.rate_limit(server::RateLimit {
+                    max,
+                    period: Duration::from_secs(period),
+                });
+            }
+            if args
+                .allow_guests
+                .or(file.access.allow_guests)
+                .unwrap_or(false)
+            {
+                server = server.allow_guests();
+            }
+            if args
+                .invite_only
+                .or(file.access.invite_only)
+                .unwrap_or(false)
+            {
+                server = server.invite_only();
+            }
+            match args.signup_difficulty.or(file.access.signup_difficulty) {
+                Some(bits) if bits > challenge::MAX_DIFFICULTY => {
+                    let max = challenge::MAX_DIFFICULTY;
+                    return Err(anyhow!(
+                        "Sign-up difficulty {bits} is over {max} bits, clients give up on it"
+                    ));
+                }
+                Some(0) | None => {}
+                Some(bits) => server = server.signup_challenge(bits),
+            }
+            if args
+                .distinct_login_errors
+                .or(file.access.distinct_login_errors)
+                .unwrap_or(false)
+            {
+                server = server.distinct_login_errors();
+            }
+            if let Some(issuer) = args.oidc_issuer.or(file.oidc.issuer) {
+                server = server.oidc(server::Oidc {
+                    issuer,
+                    audience: args.oidc_audience.or(file.oidc.audience),
+                    jwks: args.oidc_jwks.or(file.oidc.jwks),
+                });
+            }
+            let totp_key = match (args.totp_key, file.totp_key) {
+                (Some(key), _) => Some(key),
+                (None, Some(key)) => Some(
+                    key.parse()
+                        .map_err(|e| anyhow!("TOTP key in the configuration file: {e}"))?,
+                ),
+                (None, None) => None,
+            };
+            if let Some(key) = totp_key {
+                server = server.totp_key(key);
+            }
+            if let Some(path) = args.bot_socket.or(file.bot_socket) {
+                server = server.bot_socket(path);
+            }
+            if let Some(port) = args.http_port.or(file.http.port) {
+                let token = args.http_token.or(file.http.token).context(
+                    "HTTP API token is given neither by --http-token, SERVER_HTTP_TOKEN nor the configuration file",
+                )?;
+                server = server.http((host, port), token);
+            }
+            if !args.no_console && io::stdin().is_terminal() {
+                server = server.console();
+            }
+            server.run().await
+        }
+    }
+}
+
+/// Reads the configuration `path`, or "server.toml" when it exists, otherwise everything is default.
+fn load_config(path: Option<&Path>) -> anyhow::Result<ConfigFile> {
+    match (path, Path::new(CONFIG_DEFAULT).exists()) {
+        (Some(path), _) => ConfigFile::load(path),
+        (None, true) => ConfigFile::load(CONFIG_DEFAULT),
+        (None, false) => Ok(ConfigFile::default()),
+    }
+}
+
+/// Networks from the command line, or from the configuration file when none are given there.
+fn networks(args: Vec<server::Cidr>, file: Vec<String>) -> anyhow::Result<Vec<server::Cidr>> {
+    if !args.is_empty() {
+        return Ok(args);
+    }
+    file.iter()
+        .map(|net| {
+            net.parse()
+                .map_err(|e| anyhow!("Network in the configuration file: {e}"))
+        })
+        .collect()
+}
+
+/// Reads the message of the day from the file.
+fn read_motd(path: &Path) -> anyhow::Result<String> {
+    Ok(fs::read_to_string(path)
+        .with_context(|| format!("Reading the message of the day {path:?} failed"))?
+        .trim_end()
+        .to_string())
 }
@@ -0,0 +1,69 @@
+//! Accounting of the memory held by in-flight messages.
+//!
+//! Every received message [reserves][Budget::reserve] its size from a shared [Budget] before it is read,
+//! as the length prefix of its frame tells. The [Reservation] of a broadcast travels with the message
+//! through the task queue, the recipients share it with the encoded message until the last one is written.
+//! Readers wait for the budget before reading further, so the server applies backpressure
+//! to the sockets instead of growing without limits.
+use std::sync::Arc;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::{debug, warn};
+
+/// Default ceiling of bytes held by in-flight messages.
+pub const MAX_INFLIGHT_BYTES_DEFAULT: usize = 256 * 1024 * 1024;
+
+/// Shared ceiling of bytes held by in-flight messages.
+#[derive(Debug)]
+pub(crate) struct Budget {
+    semaphore: Arc<Semaphore>,
+    ceiling: usize,
+}
+impl Budget {
+    /// Creates the budget, the `ceiling` is capped to what a single reservation can hold.
+    pub(crate) fn new(ceiling: usize) -> Self {
+        let ceiling = ceiling.clamp(1, u32::MAX as usize);
+        Budget {
+            semaphore: Arc::new(Semaphore::new(ceiling)),
+            ceiling,
+        }
+    }
+
+    /// Waits until `bytes` fit into the budget and reserves them.
+    ///
+    /// Messages bigger than the whole budget wait until nothing else is in flight.
+    pub(crate) async fn reserve(&self, bytes: usize) -> Reservation {
+        let permits = bytes.clamp(1, self.ceiling);
+        if permits > self.semaphore.available_permits() {
+            warn!(
+                "In-flight memory {} of {} bytes, waiting for {bytes} bytes.",
+                self.used(),
+                self.ceiling
+            );
+        }
+        let permit = self
+            .semaphore
+            .clone()
+            .acquire_many_owned(permits as u32)
+            .await
+            .expect("The budget semaphore is never closed.");
+        debug!("in-flight memory {} bytes", self.used());
+        Reservation { _permit: permit }
+    }
+
+    /// Gauge of bytes currently held by in-flight messages.
+    pub(crate) fn used(&self) -> usize {
+        self.ceiling - self.semaphore.available_permits()
+    }
+
+    /// Bytes in-flight messages may hold at most.
+    pub(crate) fn ceiling(&self) -> usize {
+        self.ceiling
+    }
+}
+
+/// Bytes reserved from a [Budget], released on drop.
+#[derive(Debug)]
+pub(crate) struct Reservation {
+    _permit: OwnedSemaphorePermit,
+}
@@ -0,0 +1,301 @@
+//! Moderation of texts by an external service before they are broadcast, see [Moderator].
+//!
+//! Unlike [filters][crate::MessageFilter] moderators are asynchronous, e.g. they ask a classifier over HTTP.
+//! Verdicts are cached by the text, a moderator which fails or does not answer in time
+//! lets the text through or rejects it as the [ModerationPolicy] says.
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    pin::Pin,
+    str::FromStr,
+    sync::Mutex,
+    time::Duration,
+};
+
+use anyhow::Context;
+use cli_ser::{Data, User};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::time::{self, Instant};
+use tracing::warn;
+
+/// What the [Moderator] answers.
+pub type Reviewed<'a> = Pin<Box<dyn Future<Output = anyhow::Result<Verdict>> + Send + 'a>>;
+
+/// Decides whether a text may be broadcast, e.g. by a spam or profanity classifier.
+///
+/// It is given only texts which passed the [filters][crate::MessageFilter], see [Server::moderator][crate::Server::moderator].
+pub trait Moderator: Send + Sync {
+    /// Reviews the text the user sent.
+    fn review<'a>(&'a self, user: &'a User, text: &'a str) -> Reviewed<'a>;
+}
+
+/// Outcome of a [review][Moderator::review].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// The text is broadcast.
+    Allow,
+    /// The text is rejected, the reason is told to the sender.
+    Reject(String),
+}
+
+/// What happens to a text when the [Moderator] fails or does not answer in time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FailurePolicy {
+    /// The text is broadcast, moderation is best effort.
+    #[default]
+    Open,
+    /// The text is rejected, nothing unmoderated gets through.
+    Closed,
+}
+impl FromStr for FailurePolicy {
+    type Err = String;
+
+    /// Parses "open" or "closed".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "open" => Ok(FailurePolicy::Open),
+            "closed" => Ok(FailurePolicy::Closed),
+            _ => Err(format!("\"{s}\" is not open nor closed")),
+        }
+    }
+}
+
+/// How the [Moderator] is used.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModerationPolicy {
+    /// How long a review may take.
+    pub timeout: Duration,
+    pub on_failure: FailurePolicy,
+    /// Verdicts kept at most, the oldest one is forgotten first, 0 disables the cache.
+    pub cache_size: usize,
+    /// How long a verdict is kept.
+    pub cache_ttl: Duration,
+}
+impl Default for ModerationPolicy {
+    /// One second per review, failing open, 10000 verdicts cached for ten minutes.
+    fn default() -> Self {
+        ModerationPolicy {
+            timeout: Duration::from_secs(1),
+            on_failure: FailurePolicy::Open,
+            cache_size: 10_000,
+            cache_ttl: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+/// Asks a moderation service over HTTP.
+///
+/// The text is POSTed as JSON `{"user": "alice", "text": "..."}`, the service answers
+/// `{"allowed": true}` or `{"allowed": false, "reason": "spam"}`, anything else is a failure.
+#[derive(Debug, Clone)]
+pub struct HttpModerator {
+    url: String,
+    client: reqwest::Client,
+}
+impl HttpModerator {
+    pub fn new(url: impl Into<String>) -> Self {
+        HttpModerator {
+            url: url.into(),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Review<'a> {
+    user: &'a str,
+    text: &'a str,
+}
+
+#[derive(Deserialize)]
+struct Answer {
+    allowed: bool,
+    reason: Option<String>,
+}
+
+impl Moderator for HttpModerator {
+    fn review<'a>(&'a self, user: &'a User, text: &'a str) -> Reviewed<'a> {
+        Box::pin(async move {
+            let user = user.to_string();
+            let answer: Answer = self
+                .client
+                .post(&self.url)
+                .json(&Review { user: &user, text })
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status)
+                .with_context(|| format!("Requesting {} failed.", self.url))?
+                .json()
+                .await
+                .with_context(|| format!("{} did not answer with a verdict.", self.url))?;
+            Ok(match answer.allowed {
+                true => Verdict::Allow,
+                false => Verdict::Reject(
+                    answer
+                        .reason
+                        .unwrap_or_else(|| "the moderator rejects the text".to_string()),
+                ),
+            })
+        })
+    }
+}
+
+/// The [Moderator] of the server with its policy and cache.
+pub(crate) struct Moderation {
+    moderator: Box<dyn Moderator>,
+    policy: ModerationPolicy,
+    cache: Mutex<Cache>,
+}
+
+/// Verdicts by the hash of their text, with the order they were made in.
+#[derive(Default)]
+struct Cache {
+    verdicts: HashMap<[u8; 32], (Verdict, Instant)>,
+    order: VecDeque<[u8; 32]>,
+}
+
+impl Moderation {
+    pub(crate) fn new(moderator: impl Moderator + 'static, policy: ModerationPolicy) -> Self {
+        Moderation {
+            moderator: Box::new(moderator),
+            policy,
+            cache: Mutex::default(),
+        }
+    }
+
+    /// Returns the reason of the rejection like [filters][crate::MessageFilter::check],
+    /// only texts are reviewed.
+    pub(crate) async fn check(&self, user: &User, data: &Data) -> Result<(), String> {
+        let Data::Text(text) = data else {
+            return Ok(());
+        };
+        let key: [u8; 32] = Sha256::digest(text.as_bytes()).into();
+        let verdict = match self.cached(&key) {
+            Some(verdict) => verdict,
+            None => {
+                let review = self.moderator.review(user, text);
+                let verdict = match time::timeout(self.policy.timeout, review).await {
+                    Ok(Ok(verdict)) => verdict,
+                    Ok(Err(e)) => return self.failed(format!("{e:#}")),
+                    Err(_) => return self.failed("it timed out".to_string()),
+                };
+                self.remember(key, verdict.clone());
+                verdict
+            }
+        };
+        match verdict {
+            Verdict::Allow => Ok(()),
+            Verdict::Reject(reason) => Err(reason),
+        }
+    }
+
+    /// Failures are not cached, the next text is reviewed again.
+    fn failed(&self, error: String) -> Result<(), String> {
+        warn!("Moderating a text failed! Error {error}");
+        match self.policy.on_failure {
+            FailurePolicy::Open => Ok(()),
+            FailurePolicy::Closed => Err("the text could not be moderated".to_string()),
+        }
+    }
+
+    fn cached(&self, key: &[u8; 32]) -> Option<Verdict> {
+        let cache = self.cache.lock().expect("moderation cache lock poisoned");
+        let (verdict, made) = cache.verdicts.get(key)?;
+        (made.elapsed() < self.policy.cache_ttl).then(|| verdict.clone())
+    }
+
+    fn remember(&self, key: [u8; 32], verdict: Verdict) {
+        if self.policy.cache_size == 0 {
+            return;
+        }
+        let mut cache = self.cache.lock().expect("moderation cache lock poisoned");
+        let Cache { verdicts, order } = &mut *cache;
+        if verdicts.insert(key, (verdict, Instant::now())).is_none() {
+            order.push_back(key);
+        }
+        while verdicts.len() > self.policy.cache_size {
+            let oldest = order.pop_front().expect("every verdict is in the order");
+            verdicts.remove(&oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Rejects texts containing "spam", fails on "fail" and is slow on "slow", counts the reviews.
+    #[derive(Default)]
+    struct Fake(AtomicUsize);
+    impl Moderator for &'static Fake {
+        fn review<'a>(&'a self, _: &'a User, text: &'a str) -> Reviewed<'a> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Box::pin(async move {
+                match text {
+                    "fail" => anyhow::bail!("the service is down"),
+                    "slow" => time::sleep(Duration::from_secs(60)).await,
+                    text if text.contains("spam") => {
+                        return Ok(Verdict::Reject("spam".to_string()))
+                    }
+                    _ => {}
+                }
+                Ok(Verdict::Allow)
+            })
+        }
+    }
+
+    fn text(s: &str) -> Data {
+        Data::Text(s.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_verdicts_cached() {
+        let fake: &'static Fake = Box::leak(Box::default());
+        let policy = ModerationPolicy {
+            cache_size: 2,
+            ..Default::default()
+        };
+        let moderation = Moderation::new(fake, policy);
+        let user = User::from("moderated".to_string());
+        assert_eq!(moderation.check(&user, &text("hi")).await, Ok(()));
+        assert_eq!(
+            moderation.check(&user, &text("buy spam")).await,
+            Err("spam".to_string())
+        );
+        assert_eq!(moderation.check(&user, &text("hi")).await, Ok(()));
+        assert_eq!(fake.0.load(Ordering::Relaxed), 2);
+        // The third verdict pushes out the first one.
+        assert_eq!(moderation.check(&user, &text("hello")).await, Ok(()));
+        assert_eq!(moderation.check(&user, &text("hi")).await, Ok(()));
+        assert_eq!(fake.0.load(Ordering::Relaxed), 4);
+        let image = Data::Image(cli_ser::Image::try_from(b"GIF89a".to_vec()).unwrap());
+        assert_eq!(moderation.check(&user, &image).await, Ok(()));
+        assert_eq!(fake.0.load(Ordering::Relaxed), 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failure_policies() {
+        let fake: &'static Fake = Box::leak(Box::default());
+        let user = User::from("moderated".to_string());
+        let open = Moderation::new(fake, ModerationPolicy::default());
+        assert_eq!(open.check(&user, &text("fail")).await, Ok(()));
+        assert_eq!(open.check(&user, &text("slow")).await, Ok(()));
+        let closed = Moderation::new(
+            fake,
+            ModerationPolicy {
+                on_failure: FailurePolicy::Closed,
+                ..Default::default()
+            },
+        );
+        assert!(closed.check(&user, &text("fail")).await.is_err());
+        assert!(closed.check(&user, &text("slow")).await.is_err());
+        // Failures are asked again.
+        assert!(closed.check(&user, &text("fail")).await.is_err());
+        assert_eq!(fake.0.load(Ordering::Relaxed), 5);
+        assert_eq!("closed".parse(), Ok(FailurePolicy::Closed));
+        assert!("ajar".parse::<FailurePolicy>().is_err());
+    }
+}
@@ -0,0 +1,227 @@
+//! Log-in by tokens of an OpenID Connect identity provider, see [Oidc].
+//!
+//! Tokens are JWTs signed by a key of the provider, the keys (JWKS) are fetched on the first log-in
+//! and again when a token is signed by an unknown one, e.g. after the provider rotated them.
+use std::time::Duration;
+
+use anyhow::Context;
+use jsonwebtoken::{jwk::JwkSet, DecodingKey, Validation};
+use serde::Deserialize;
+use tokio::{sync::Mutex, time::Instant};
+use tracing::{info, warn};
+
+/// The keys are fetched again for an unknown key at most this often.
+const REFETCH_AFTER: Duration = Duration::from_secs(60);
+
+/// Identity provider whose tokens log users in, see [cli::Auth::OidcToken][cli_ser::cli::Auth::OidcToken].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Oidc {
+    /// Issuer of the tokens, e.g. "https://accounts.example.com", the `iss` claim must match it.
+    pub issuer: String,
+    /// Client id registered at the provider, the `aud` claim must contain it, any is accepted when `None`.
+    pub audience: Option<String>,
+    /// URL or path of the provider's keys, discovered from `<issuer>/.well-known/openid-configuration`
+    /// when `None`.
+    pub jwks: Option<String>,
+}
+impl Oidc {
+    /// Provider of the issuer, any audience, the keys are discovered.
+    pub fn new(issuer: impl Into<String>) -> Self {
+        Oidc {
+            issuer: issuer.into(),
+            audience: None,
+            jwks: None,
+        }
+    }
+}
+
+/// Subject the provider vouches for.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Identity {
+    pub(crate) subject: String,
+    /// Name the user is created with on their first log-in, the subject when the token has none.
+    pub(crate) username: String,
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    sub: String,
+    preferred_username: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Discovery {
+    jwks_uri: String,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub(crate) enum Error {
+    #[error("{0}")]
+    Invalid(#[from] jsonwebtoken::errors::Error),
+    #[error("it is signed by an unknown key")]
+    UnknownKey,
+    #[error("the keys of the provider are not available")]
+    Keys(#[source] anyhow::Error),
+}
+
+/// Checks the tokens against the provider's keys, which are kept between log-ins.
+pub(crate) struct Verifier {
+    oidc: Oidc,
+    /// Keys and when they were fetched.
+    keys: Mutex<Option<(JwkSet, Instant)>>,
+}
+impl Verifier {
+    pub(crate) fn new(oidc: Oidc) -> Self {
+        Verifier {
+            oidc,
+            keys: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn issuer(&self) -> &str {
+        &self.oidc.issuer
+    }
+
+    /// Returns the subject of the token when it is signed by the provider, current and meant for the server.
+    pub(crate) async fn verify(&self, token: &str) -> Result<Identity, Error> {
+        let header = jsonwebtoken::decode_header(token)?;
+        let key = self.key(header.kid.as_deref()).await?;
+        // The algorithm has to fit the key, so a public key can not be used as an HMAC secret.
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.oidc.issuer]);
+        match &self.oidc.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        let claims = jsonwebtoken::decode::<Claims>(token, &key, &validation)?.claims;
+        Ok(Identity {
+            username: claims
+                .preferred_username
+                .unwrap_or_else(|| claims.sub.clone()),
+            subject: claims.sub,
+        })
+    }
+
+    /// Key of the id, the only key of the set when the token does not name one.
+    async fn key(&self, kid: Option<&str>) -> Result<DecodingKey, Error> {
+        let mut keys = self.keys.lock().await;
+        let stale = match &*keys {
+            Some((set, _)) if find(set, kid).is_some() => false,
+            Some((_, fetched)) => fetched.elapsed() >= REFETCH_AFTER,
+            None => true,
+        };
+        if stale {
+            let set = self.fetch().await.map_err(|e| {
+                warn!(
+                    "Fetching the keys of {} failed! Error {e:#}",
+                    self.oidc.issuer
+                );
+                Error::Keys(e)
+            })?;
+            info!("{} keys of {} fetched", set.keys.len(), self.oidc.issuer);
+            *keys = Some((set, Instant::now()));
+        }
+        let (set, _) = keys.as_ref().expect("keys are fetched above");
+        let jwk = find(set, kid).ok_or(Error::UnknownKey)?;
+        Ok(DecodingKey::from_jwk(jwk)?)
+    }
+
+    /// Reads the keys from the configured URL or file, or from the URL the issuer publishes.
+    async fn fetch(&self) -> anyhow::Result<JwkSet> {
+        let source = match &self.oidc.jwks {
+            Some(source) => source.clone(),
+            None => {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.oidc.issuer.trim_end_matches('/')
+                );
+                get::<Discovery>(&url).await?.jwks_uri
+            }
+        };
+        match source.starts_with("https://") || source.starts_with("http://") {
+            true => get(&source).await,
+            false => {
+                let bytes = tokio::fs::read(&source)
+                    .await
+                    .with_context(|| format!("Reading {source:?} failed."))?;
+                serde_json::from_slice(&bytes).with_context(|| format!("{source:?} is not a JWKS."))
+            }
+        }
+    }
+}
+
+fn find<'a>(set: &'a JwkSet, kid: Option<&str>) -> Option<&'a jsonwebtoken::jwk::Jwk> {
+    match kid {
+        Some(kid) => set.find(kid),
+        None if set.keys.len() == 1 => set.keys.first(),
+        None => None,
+    }
+}
+
+async fn get<T: serde::de::DeserializeOwned>(url: &str) -> anyhow::Result<T> {
+    reqwest::get(url)
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .with_context(|| format!("Requesting {url} failed."))?
+        .json()
+        .await
+        .with_context(|| format!("{url} did not answer with the expected JSON."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::json;
+
+    const SECRET: &[u8] = b"secret of the tests";
+
+    fn token(claims: serde_json::Value) -> String {
+        let mut header = Header::default();
+        header.kid = Some("test".to_string());
+        jsonwebtoken::encode(&header, &claims, &EncodingKey::from_secret(SECRET)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_verify() {
+        use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+        let path = std::env::temp_dir().join(format!("jwks_{}.json", std::process::id()));
+        let jwks =
+            json!({"keys": [{"kty": "oct", "kid": "test", "k": URL_SAFE_NO_PAD.encode(SECRET)}]});
+        std::fs::write(&path, jwks.to_string()).unwrap();
+        let verifier = Verifier::new(Oidc {
+            audience: Some("chat".to_string()),
+            jwks: Some(path.display().to_string()),
+            ..Oidc::new("https://id.example.com")
+        });
+        let exp = chrono::Utc::now().timestamp() + 60;
+        let claims =
+            json!({"iss": "https://id.example.com", "aud": "chat", "sub": "42", "exp": exp});
+
+        let identity = verifier.verify(&token(claims.clone())).await.unwrap();
+        assert_eq!(identity.subject, "42");
+        assert_eq!(identity.username, "42");
+        let mut named = claims.clone();
+        named["preferred_username"] = json!("alice");
+        assert_eq!(
+            verifier.verify(&token(named)).await.unwrap().username,
+            "alice"
+        );
+
+        for (claim, value) in [
+            ("iss", json!("https://evil.example.com")),
+            ("aud", json!("other")),
+            ("exp", json!(exp - 3600)),
+        ] {
+            let mut wrong = claims.clone();
+            wrong[claim] = value;
+            let result = verifier.verify(&token(wrong)).await;
+            assert!(matches!(result, Err(Error::Invalid(_))), "{claim}");
+        }
+        let forged = format!("{}x", token(claims));
+        assert!(verifier.verify(&forged).await.is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,167 @@
+//! Write-behind storing of the messages sent to all users, see [Persister].
+use std::{collections::VecDeque, sync::Arc, time::Duration};
+
+use cli_ser::{Data, Image, User};
+use tokio::{
+    select,
+    sync::{mpsc, oneshot, Mutex},
+    time::{self, Instant, MissedTickBehavior},
+};
+use tracing::{debug, error, warn, Instrument};
+
+use crate::db::{self, Database, Record};
+
+/// Attempts to store a message before it is given up.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Buffers messages and stores them in batches, so that broadcasting does not wait for the database.
+///
+/// A message gets its id right away, it is stored once [batch][db::DatabaseOptions::batch_size]
+/// messages are buffered, after the [interval][db::DatabaseOptions::flush_interval] or on [flush][Self::flush].
+/// A failed batch is retried message by message, a failed message is retried with the next batch.
+pub(crate) struct Persister {
+    db: Arc<Database>,
+    /// Reserved ids of messages, reserved again in batches when used up.
+    ids: Mutex<VecDeque<i64>>,
+    batch: usize,
+    writes: mpsc::Sender<Write>,
+}
+
+enum Write {
+    Record(Record),
+    /// Stores the buffered messages and reports back.
+    Flush(oneshot::Sender<()>),
+}
+
+/// Buffered message with the number of failed attempts to store it.
+struct Unstored(Arc<Record>, u32);
+
+impl Persister {
+    /// Starts the task storing the messages.
+    pub(crate) fn start(db: Arc<Database>, options: &db::DatabaseOptions) -> Self {
+        let batch = options.batch_size.max(1);
+        let (writes, received) = mpsc::channel(batch * 4);
+        tokio::spawn(
+            write_behind(db.clone(), received, batch, options.flush_interval).in_current_span(),
+        );
+        Persister {
+            db,
+            ids: Mutex::default(),
+            batch,
+            writes,
+        }
+    }
+
+    /// Buffers the `data` from the `user`, returns the id the message is going to be stored with.
+    ///
+    /// The `original` of a re-encoded image is stored along with it.
+    pub(crate) async fn record(
+        &self,
+        user: User,
+        data: Data,
+        original: Option<Image>,
+        urgent: bool,
+    ) -> Result<i64, db::Error> {
+        let id = self.next_id().await?;
+        let record = Record::now(id, user, data, original, urgent);
+        self.writes
+            .send(Write::Record(record))
+            .await
+            .map_err(|_| db::Error::Stopped)?;
+        Ok(id)
+    }
+
+    async fn next_id(&self) -> Result<i64, db::Error> {
+        let mut ids = self.ids.lock().await;
+        if ids.is_empty() {
+            ids.extend(self.db.reserve_msg_ids(self.batch as i64).await?);
+        }
+        ids.pop_front().ok_or(db::Error::Stopped)
+    }
+
+    /// Waits until the messages buffered so far are stored, or retried later when they failed.
+    pub(crate) async fn flush(&self) {
+        let (done, flushed) = oneshot::channel();
+        if self.writes.send(Write::Flush(done)).await.is_ok() {
+            let _ = flushed.await;
+        }
+    }
+}
+
+/// Stores the buffered messages in batches until every [Persister] is dropped.
+async fn write_behind(
+    db: Arc<Database>,
+    mut writes: mpsc::Receiver<Write>,
+    batch: usize,
+    interval: Duration,
+) {
+    let mut buffer: Vec<Unstored> = vec![];
+    let mut ticks = time::interval_at(Instant::now() + interval, interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        select! {
+            write = writes.recv() => match write {
+                Some(Write::Record(record)) => {
+                    buffer.push(Unstored(Arc::new(record), 0));
+                    if buffer.len() >= batch {
+                        buffer = store(&db, buffer).await;
+                    }
+                }
+                Some(Write::Flush(done)) => {
+                    buffer = store(&db, buffer).await;
+                    let _ = done.send(());
+                }
+                None => {
+                    store(&db, buffer).await;
+                    break;
+                }
+            },
+            _ = ticks.tick(), if !buffer.is_empty() => buffer = store(&db, buffer).await,
+        }
+    }
+}
+
+/// Stores the messages, returns the ones to be retried.
+async fn store(db: &Database, batch: Vec<Unstored>) -> Vec<Unstored> {
+    if batch.is_empty() {
+        return batch;
+    }
+    let records = batch
+        .iter()
+        .map(|Unstored(record, _)| record.clone())
+        .collect();
+    match db.record_msgs(records).await {
+        Ok(()) => {
+            debug!("{} messages stored", batch.len());
+            return vec![];
+        }
+        Err(e) if batch.len() > 1 => {
+            warn!(
+                code = %e.code(),
+                "Storing {} messages failed, storing them one by one! Error {e}",
+                batch.len()
+            )
+        }
+        Err(_) => {}
+    }
+    let mut retry = vec![];
+    for Unstored(record, attempts) in batch {
+        match db.record_msgs(vec![record.clone()]).await {
+            Ok(()) => {}
+            Err(e) if attempts + 1 < MAX_ATTEMPTS => {
+                warn!(
+                    code = %e.code(),
+                    "Storing message {} failed, it is retried! Error {e}",
+                    record.id
+                );
+                retry.push(Unstored(record, attempts + 1));
+            }
+            Err(e) => error!(
+                code = %e.code(),
+                "Storing message {} failed {MAX_ATTEMPTS} times, it is given up! Error {e}",
+                record.id
+            ),
+        }
+    }
+    retry
+}
@@ -0,0 +1,76 @@
+//! Limit of the messages each user sends, see [RateLimit].
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::Duration,
+};
+
+use cli_ser::User;
+use tokio::time::Instant;
+
+/// How many messages each user may send within the period, further ones are rejected.
+///
+/// Every message after the authentication counts, whatever it asks the server for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub max: usize,
+    pub period: Duration,
+}
+impl RateLimit {
+    /// Reason given to the sender of a message over the limit, `what` names the messages.
+    pub(crate) fn reason(&self, what: &str) -> String {
+        format!(
+            "only {} {what} are allowed per {} seconds",
+            self.max,
+            self.period.as_secs()
+        )
+    }
+}
+
+/// Times of the messages of each user within the period of a [RateLimit].
+#[derive(Default)]
+pub(crate) struct Window {
+    sent: Mutex<HashMap<User, VecDeque<Instant>>>,
+}
+impl Window {
+    /// Counts a message of the user sent now, returns false when it is over the `limit`.
+    pub(crate) fn admit(&self, user: &User, limit: RateLimit) -> bool {
+        let now = Instant::now();
+        let mut sent = self.sent.lock().expect("rate lock poisoned");
+        // Users who sent none for the period are forgotten.
+        sent.retain(|_, times| {
+            times.retain(|time| now.duration_since(*time) < limit.period);
+            !times.is_empty()
+        });
+        let times = sent.entry(user.clone()).or_default();
+        if times.len() >= limit.max {
+            return false;
+        }
+        times.push_back(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn admitted_per_period() {
+        let window = Window::default();
+        let limit = RateLimit {
+            max: 2,
+            period: Duration::from_secs(1),
+        };
+        let (alice, bob) = (
+            User::from("alice".to_string()),
+            User::from("bob".to_string()),
+        );
+        assert!(window.admit(&alice, limit));
+        assert!(window.admit(&alice, limit));
+        assert!(!window.admit(&alice, limit));
+        assert!(window.admit(&bob, limit));
+        tokio::time::advance(Duration::from_millis(1001)).await;
+        assert!(window.admit(&alice, limit));
+    }
+}
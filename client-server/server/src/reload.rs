@@ -0,0 +1,96 @@
+//! Settings reloaded while the server runs, without dropping connections, see [Reloadable].
+use std::sync::{Arc, RwLock};
+
+use anyhow::Context;
+use tracing::{error, info};
+use tracing_subscriber::filter::Targets;
+
+use crate::{filter, queue, MessageFilter, Shared, Task::Announce, UrgentLimit};
+
+/// Settings of the server which can change while it runs.
+///
+/// They are loaded when the server starts and again on `SIGHUP` or `reload` in the [console][crate::Server::console],
+/// see [Server::reload_with][crate::Server::reload_with]. A reload which fails keeps the settings in place.
+#[derive(Default)]
+pub struct Reloadable {
+    /// Message of the day, a new one is announced to everyone, see [Server::motd][crate::Server::motd].
+    pub motd: Option<String>,
+    /// Filters checked after those of [Server::filter][crate::Server::filter], they replace the loaded ones.
+    pub filters: Vec<Box<dyn MessageFilter>>,
+    pub urgent_limit: UrgentLimit,
+    /// Filter of the [log level][crate::Server::log_level], the one in place is kept when `None`.
+    pub log_level: Option<Targets>,
+}
+
+/// Loads the [Reloadable] settings, e.g. from the configuration file.
+pub(crate) type Loader = dyn Fn() -> anyhow::Result<Reloadable> + Send + Sync;
+
+/// Value read by many tasks and replaced whole, readers keep the one they loaded.
+pub(crate) struct Swap<T>(RwLock<Arc<T>>);
+impl<T> Swap<T> {
+    pub(crate) fn new(value: T) -> Self {
+        Swap(RwLock::new(Arc::new(value)))
+    }
+
+    pub(crate) fn load(&self) -> Arc<T> {
+        self.0.read().expect("swap lock poisoned").clone()
+    }
+
+    pub(crate) fn store(&self, value: T) {
+        *self.0.write().expect("swap lock poisoned") = Arc::new(value);
+    }
+}
+
+/// Puts the settings in place, returns the message of the day when it changed.
+pub(crate) fn apply(shared: &Shared, settings: Reloadable) -> anyhow::Result<Option<String>> {
+    let Reloadable {
+        motd,
+        filters,
+        urgent_limit,
+        log_level,
+    } = settings;
+    if let (Some(level), Some(filter)) = (&shared.log_level, log_level) {
+        level.set(filter)?;
+    }
+    shared.reloaded_filters.store(filter::Chain::from(filters));
+    shared.urgent.set_limit(urgent_limit);
+    let mut current = shared.motd.write().expect("motd lock poisoned");
+    match *current == motd {
+        true => Ok(None),
+        false => {
+            *current = motd.clone();
+            Ok(motd)
+        }
+    }
+}
+
+/// Loads the settings again and puts them in place, a changed message of the day is announced.
+pub(crate) async fn reload(shared: &Shared) -> anyhow::Result<()> {
+    let Some(load) = &shared.reload else {
+        anyhow::bail!("Nothing to reload, the server was not given the settings to load.");
+    };
+    let motd = apply(shared, load()?)?;
+    info!("Settings reloaded.");
+    if let Some(motd) = motd {
+        queue(&shared.tasks, Announce(motd)).await?;
+    }
+    Ok(())
+}
+
+/// Listens for `SIGHUP` right away, the returned task reloads the settings on each, failures are logged.
+#[cfg(unix)]
+pub(crate) fn on_hangup(
+    shared: Shared,
+) -> anyhow::Result<impl std::future::Future<Output = anyhow::Result<()>>> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = signal(SignalKind::hangup()).context("Listening for SIGHUP failed!")?;
+    Ok(async move {
+        while hangups.recv().await.is_some() {
+            if let Err(e) = reload(&shared).await {
+                error!("Reloading the settings failed, they are kept! Error {e:#}");
+            }
+        }
+        Ok(())
+    })
+}
@@ -0,0 +1,181 @@
+//! Deletion of old messages and their attachments, see [RetentionPolicy].
+use std::{
+    fmt::{self, Display},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use tokio::time::{self, Instant, MissedTickBehavior};
+use tracing::{error, info};
+
+use crate::db::{self, Database};
+
+/// Which messages are deleted and how often, e.g. messages older than 30 days every hour.
+///
+/// Deleted messages take their read receipts, texts and attachments with them,
+/// blobs are removed once no attachment or avatar refers to them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetentionPolicy {
+    /// Messages which arrived longer ago are deleted.
+    pub max_age: Option<Duration>,
+    /// Only this many newest messages are kept.
+    pub max_messages: Option<u64>,
+    /// Time between two runs, the first one is right after the start.
+    pub interval: Duration,
+    /// Messages to be deleted are only counted and logged.
+    pub dry_run: bool,
+}
+impl Default for RetentionPolicy {
+    /// Keeps everything, runs hourly.
+    fn default() -> Self {
+        RetentionPolicy {
+            max_age: None,
+            max_messages: None,
+            interval: Duration::from_secs(60 * 60),
+            dry_run: false,
+        }
+    }
+}
+impl RetentionPolicy {
+    /// Messages which arrived before the time are too old at the time `now`.
+    pub(crate) fn cutoff(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let age = self.max_age?;
+        let cutoff = chrono::Duration::from_std(age)
+            .ok()
+            .and_then(|age| now.checked_sub_signed(age));
+        Some(cutoff.unwrap_or(DateTime::<Utc>::MIN_UTC))
+    }
+}
+
+/// What one run deleted, or would delete in a [dry run][RetentionPolicy::dry_run].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Purged {
+    /// Messages with their texts and read receipts.
+    pub messages: u64,
+    /// Files and images of the messages.
+    pub attachments: u64,
+    /// Blobs no longer referred to, they are not counted in a dry run.
+    pub blobs: u64,
+}
+impl Display for Purged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} messages, {} attachments and {} blobs",
+            self.messages, self.attachments, self.blobs
+        )
+    }
+}
+
+/// Totals of the runs since the server started, shown by the console's `stats`.
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    runs: AtomicU64,
+    failures: AtomicU64,
+    messages: AtomicU64,
+    attachments: AtomicU64,
+    blobs: AtomicU64,
+}
+impl Metrics {
+    fn add(&self, purged: Purged) {
+        self.messages.fetch_add(purged.messages, Ordering::Relaxed);
+        self.attachments
+            .fetch_add(purged.attachments, Ordering::Relaxed);
+        self.blobs.fetch_add(purged.blobs, Ordering::Relaxed);
+    }
+
+    /// Number of runs, failed and dry ones included.
+    pub(crate) fn runs(&self) -> u64 {
+        self.runs.load(Ordering::Relaxed)
+    }
+}
+impl Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let purged = Purged {
+            messages: self.messages.load(Ordering::Relaxed),
+            attachments: self.attachments.load(Ordering::Relaxed),
+            blobs: self.blobs.load(Ordering::Relaxed),
+        };
+        write!(
+            f,
+            "{purged} deleted in {} runs, {} failed",
+            self.runs(),
+            self.failures.load(Ordering::Relaxed)
+        )
+    }
+}
+
+/// Applies the policy once, nothing is deleted in a [dry run][RetentionPolicy::dry_run].
+pub(crate) async fn purge(db: &Database, policy: &RetentionPolicy) -> Result<Purged, db::Error> {
+    db.purge(
+        policy.cutoff(Utc::now()),
+        policy.max_messages,
+        policy.dry_run,
+    )
+    .await
+}
+
+/// Applies the policy every [interval][RetentionPolicy::interval] forever, failed runs are logged.
+pub(crate) async fn run(
+    db: Arc<Database>,
+    policy: RetentionPolicy,
+    metrics: Arc<Metrics>,
+) -> anyhow::Result<()> {
+    let mut ticks = time::interval_at(Instant::now(), policy.interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        metrics.runs.fetch_add(1, Ordering::Relaxed);
+        match purge(&db, &policy).await {
+            Ok(purged) if policy.dry_run => info!("Retention dry run, {purged} would be deleted."),
+            Ok(purged) => {
+                metrics.add(purged);
+                info!("Retention deleted {purged}.");
+            }
+            Err(e) => {
+                metrics.failures.fetch_add(1, Ordering::Relaxed);
+                error!(code = %e.code(), "Deleting old messages failed, retrying at the next run! Error {e}");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cutoff() {
+        let now = Utc::now();
+        assert_eq!(RetentionPolicy::default().cutoff(now), None);
+        let policy = RetentionPolicy {
+            max_age: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        assert_eq!(policy.cutoff(now), Some(now - chrono::Duration::minutes(1)));
+        let forever = RetentionPolicy {
+            max_age: Some(Duration::MAX),
+            ..Default::default()
+        };
+        assert_eq!(forever.cutoff(now), Some(DateTime::<Utc>::MIN_UTC));
+    }
+
+    #[test]
+    fn test_metrics() {
+        let metrics = Metrics::default();
+        metrics.runs.store(2, Ordering::Relaxed);
+        metrics.add(Purged {
+            messages: 3,
+            attachments: 1,
+            blobs: 1,
+        });
+        assert_eq!(
+            metrics.to_string(),
+            "3 messages, 1 attachments and 1 blobs deleted in 2 runs, 0 failed"
+        );
+    }
+}
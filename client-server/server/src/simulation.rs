@@ -0,0 +1,255 @@
+//! Deterministic simulation of the message routing.
+//!
+//! Virtual clients connect, disconnect and send messages through [route] on tokio's paused clock,
+//! there are no sockets nor real sleeps, the whole schedule is given by a seed.
+//! After each run global invariants are checked: no lost, duplicated or reordered (per sender) messages
+//! and no in-flight memory left reserved.
+use std::collections::HashMap;
+
+use tokio::{task::JoinHandle, time::Duration};
+
+use super::*;
+
+/// Clients connected for the whole simulation, they must receive every message of the others.
+const STABLE: usize = 4;
+/// Clients randomly connecting and disconnecting.
+const CHURNING: usize = 4;
+/// Number of actions (sends and (dis)connections) of one simulation.
+const STEPS: usize = 2000;
+
+/// Minimal xorshift generator, the schedule must depend on the seed only.
+struct Rng(u64);
+impl Rng {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+fn id(client: usize) -> SessionId {
+    SessionId(client as u64)
+}
+
+fn user(client: usize) -> User {
+    format!("client-{client}").into()
+}
+
+/// Registers the client and collects everything routed to it, as it was routed, until it is removed.
+fn connect_routed(clients: &Sessions, client: usize) -> JoinHandle<Vec<Outgoing>> {
+    let (sender, mut receiver) = mpsc::channel(128);
+    let session = Session {
+        user: user(client),
+        guest: false,
+        addr: SocketAddr::from(([10, 0, 0, 1], 1000 + client as u16)),
+        since: Instant::now(),
+        kick: Arc::new(Notify::new()),
+        sender,
+        presence: Presence::Online,
+        presence_message: None,
+        traffic: ConnectionStats::new(),
+    };
+    clients.insert(id(client), session);
+    tokio::spawn(async move {
+        let mut received = Vec::new();
+        while let Some(msg) = receiver.recv().await {
+            received.push(msg);
+        }
+        received
+    })
+}
+
+/// The message as the client reads it, shared ones are decoded from their encoding.
+fn decode(msg: &Outgoing) -> ser::Msg {
+    match msg {
+        Outgoing::Msg(msg) => msg.clone(),
+        Outgoing::Shared(fanout) => {
+            let encoded = fanout.encoded(Codec::default()).unwrap();
+            ser::Msg::from_bytes(encoded.bytes()).unwrap()
+        }
+    }
+}
+
+/// Registers the client and collects everything routed to it until it is removed.
+fn connect(clients: &Sessions, client: usize) -> JoinHandle<Vec<ser::Msg>> {
+    let routed = connect_routed(clients, client);
+    tokio::spawn(async move { routed.await.unwrap().iter().map(decode).collect() })
+}
+
+/// Broadcast of the `n`-th text of the client, "<client>:<n>", see [parse].
+async fn text_from(budget: &memory::Budget, client: usize, n: usize) -> Task {
+    let text = format!("{client}:{n}");
+    let origin = Origin {
+        session: id(client),
+        seq: n as u64 + 1,
+    };
+    let reservation = budget.reserve(text.len()).await;
+    Broadcast(
+        Some(origin),
+        ser::Msg::DataFrom {
+            data: Data::Text(text),
+            from: user(client),
+            msg_id: None,
+            display_name: None,
+            mentions: vec![],
+            guest: false,
+            urgent: false,
+        },
+        reservation,
+        Span::none(),
+    )
+}
+
+/// Runs one simulation, returns messages received by each client and the number of messages sent by each.
+async fn simulate(seed: u64) -> (Vec<Vec<ser::Msg>>, Vec<usize>) {
+    let mut rng = Rng(seed);
+    let budget = memory::Budget::new(1024);
+    let clients: Arc<Sessions> = Arc::new(DashMap::new());
+    let (tasks, task_consumer) = mpsc::channel(1024);
+    let router = {
+        let clients = clients.clone();
+        tokio::spawn(async move { route(task_consumer, &clients).await })
+    };
+
+    let mut sessions: HashMap<usize, JoinHandle<Vec<ser::Msg>>> = (0..STABLE + CHURNING)
+        .map(|client| (client, connect(&clients, client)))
+        .collect();
+    let mut received = vec![Vec::new(); STABLE + CHURNING];
+    let mut sent = vec![0; STABLE + CHURNING];
+
+    for _ in 0..STEPS {
+        let client = rng.below(STABLE + CHURNING);
+        if client >= STABLE && rng.below(4) == 0 {
+            match sessions.remove(&client) {
+                Some(session) => {
+                    clients.remove(&id(client));
+                    received[client].extend(session.await.unwrap());
+                }
+                None => {
+                    sessions.insert(client, connect(&clients, client));
+                }
+            }
+        } else if sessions.contains_key(&client) {
+            let task = text_from(&budget, client, sent[client]).await;
+            sent[client] += 1;
+            tasks.send(task).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(rng.below(3) as u64)).await;
+    }
+
+    drop(tasks);
+    router.await.unwrap();
+    for (client, session) in sessions {
+        clients.remove(&id(client));
+        received[client].extend(session.await.unwrap());
+    }
+    assert_eq!(budget.used(), 0, "seed {seed}: in-flight memory leaked");
+    (received, sent)
+}
+
+/// Parses the message to its sender and sequence number.
+fn parse(msg: &ser::Msg) -> (usize, usize) {
+    let ser::Msg::DataFrom {
+        data: Data::Text(text),
+        from,
+        ..
+    } = msg
+    else {
+        panic!("unexpected message {msg}");
+    };
+    let (client, seq) = text.split_once(':').unwrap();
+    let client = client.parse().unwrap();
+    assert_eq!(*from, user(client));
+    (client, seq.parse().unwrap())
+}
+
+#[tokio::test(start_paused = true)]
+async fn routing_invariants() {
+    for seed in 1..=10 {
+        let (received, sent) = simulate(seed).await;
+        for (receiver, msgs) in received.iter().enumerate() {
+            let mut by_sender = vec![Vec::new(); STABLE + CHURNING];
+            for (sender, seq) in msgs.iter().map(parse) {
+                assert_ne!(sender, receiver, "seed {seed}: echoed to the sender");
+                by_sender[sender].push(seq);
+            }
+            for (sender, seqs) in by_sender.iter().enumerate() {
+                assert!(
+                    seqs.windows(2).all(|w| w[0] < w[1]),
+                    "seed {seed}: {receiver} got duplicated or reordered messages of {sender}: {seqs:?}"
+                );
+                if receiver < STABLE && sender != receiver {
+                    assert_eq!(
+                        *seqs,
+                        (0..sent[sender]).collect::<Vec<_>>(),
+                        "seed {seed}: {receiver} lost messages of {sender}"
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Frame of a routed message: the shared encoding of a broadcast or a message of its own.
+#[derive(Debug, PartialEq)]
+enum Frame {
+    Shared(usize, usize),
+    Ack(u64),
+}
+impl From<&Outgoing> for Frame {
+    fn from(msg: &Outgoing) -> Self {
+        match (msg, decode(msg)) {
+            (Outgoing::Shared(_), msg) => {
+                let (client, n) = parse(&msg);
+                Frame::Shared(client, n)
+            }
+            (Outgoing::Msg(_), ser::Msg::Ack(MsgId(id))) => Frame::Ack(id),
+            (_, msg) => panic!("unexpected message {msg}"),
+        }
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn batched_routing_keeps_order() {
+    let budget = memory::Budget::new(1 << 20);
+    let clients: Sessions = DashMap::new();
+    let routed: Vec<_> = (0..3)
+        .map(|client| connect_routed(&clients, client))
+        .collect();
+
+    // Queued before routing, so that the batches are full; every fifth task is addressed to client 1 only.
+    let (tasks, task_consumer) = mpsc::channel(1024);
+    let mut expected = vec![Vec::new(); 3];
+    let (mut n0, mut n2) = (0, 0);
+    for i in 0..2 * ROUTE_BATCH + 3 {
+        let task = match i % 5 {
+            4 => {
+                expected[1].push(Frame::Ack(i as u64));
+                Ack(id(1), MsgId(i as u64))
+            }
+            3 => {
+                n2 += 1;
+                expected[0].push(Frame::Shared(2, n2 - 1));
+                expected[1].push(Frame::Shared(2, n2 - 1));
+                text_from(&budget, 2, n2 - 1).await
+            }
+            _ => {
+                n0 += 1;
+                expected[1].push(Frame::Shared(0, n0 - 1));
+                expected[2].push(Frame::Shared(0, n0 - 1));
+                text_from(&budget, 0, n0 - 1).await
+            }
+        };
+        tasks.send(task).await.unwrap();
+    }
+    drop(tasks);
+    route(task_consumer, &clients).await;
+    clients.clear();
+
+    for (client, routed) in routed.into_iter().enumerate() {
+        let frames: Vec<Frame> = routed.await.unwrap().iter().map(Frame::from).collect();
+        assert_eq!(frames, expected[client], "frames of client {client}");
+    }
+    assert_eq!(budget.used(), 0, "in-flight memory leaked");
+}
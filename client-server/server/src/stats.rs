@@ -0,0 +1,72 @@
+//! Numbers of the running server, answered to [Stats][cli_ser::cli::Msg::Stats] and printed by the console.
+use std::{sync::Mutex, time::Duration};
+
+use chrono::{NaiveDate, Utc};
+use tokio::time::Instant;
+
+/// Counters of the server since it started.
+pub(crate) struct Stats {
+    started: Instant,
+    /// Day (UTC) and the messages broadcast during it.
+    today: Mutex<(NaiveDate, u64)>,
+}
+impl Default for Stats {
+    fn default() -> Self {
+        Stats {
+            started: Instant::now(),
+            today: Mutex::new((Utc::now().date_naive(), 0)),
+        }
+    }
+}
+impl Stats {
+    pub(crate) fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Counts a message broadcast now.
+    pub(crate) fn count_message(&self) {
+        self.count_message_on(Utc::now().date_naive());
+    }
+
+    /// Messages broadcast since the midnight.
+    pub(crate) fn messages_today(&self) -> u64 {
+        self.messages_on(Utc::now().date_naive())
+    }
+
+    fn count_message_on(&self, day: NaiveDate) {
+        let mut today = self.today.lock().expect("stats lock poisoned");
+        match today.0 == day {
+            true => today.1 += 1,
+            false => *today = (day, 1),
+        }
+    }
+
+    fn messages_on(&self, day: NaiveDate) -> u64 {
+        let today = self.today.lock().expect("stats lock poisoned");
+        match today.0 == day {
+            true => today.1,
+            false => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_counted_per_day() {
+        let stats = Stats::default();
+        let day = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+        let next = day.succ_opt().unwrap();
+        stats.count_message_on(day);
+        stats.count_message_on(day);
+        assert_eq!(stats.messages_on(day), 2);
+        assert_eq!(stats.messages_on(next), 0);
+        stats.count_message_on(next);
+        assert_eq!(stats.messages_on(next), 1);
+        assert_eq!(stats.messages_today(), 0);
+        stats.count_message();
+        assert_eq!(stats.messages_today(), 1);
+    }
+}
@@ -0,0 +1,160 @@
+//! Support of integration tests, see [TestServer].
+//!
+//! Tests share the database of `DATABASE_URL`, users of [unique] names keep them apart,
+//! e.g. signed up by [signed_up]. A [TestIssuer] signs tokens for [OIDC log-ins][Oidc].
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use cli_ser::{cli::Credentials, conn::Connection, ser};
+use jsonwebtoken::{EncodingKey, Header};
+use serde_json::json;
+use tokio::{sync::oneshot, task::JoinHandle};
+
+use crate::{Oidc, Server, HOST_DEFAULT};
+
+/// Password of the users of [credentials].
+pub const PASSWORD: &str = "test_pass";
+
+/// Name no other test picks, the `prefix` followed by the current time.
+pub fn unique(prefix: &str) -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    format!("{prefix}_{nanos}")
+}
+
+/// Credentials of a new user of a [unique] name and the [PASSWORD].
+pub fn credentials(prefix: &str) -> Credentials {
+    Credentials {
+        user: unique(prefix).into(),
+        password: PASSWORD.to_string(),
+    }
+}
+
+/// Signs up a new user of the [credentials] at the server at `addr`, panics if it fails.
+pub async fn signed_up(addr: SocketAddr, prefix: &str) -> (Credentials, Connection) {
+    let creds = credentials(prefix);
+    let conn = Connection::sign_up(addr, creds.clone())
+        .await
+        .unwrap_or_else(|e| panic!("{} couldn't sign up: {e}", creds.user));
+    (creds, conn)
+}
+
+/// Error the server refused the authentication with, panics for other results.
+pub fn authentication_error(result: Result<Connection, cli_ser::Error>) -> ser::Error {
+    match result {
+        Err(cli_ser::Error::Authentication(e)) => e,
+        Err(e) => panic!("expected an authentication error, got {e}"),
+        Ok(_) => panic!("expected an authentication error, got a connection"),
+    }
+}
+
+/// Server running in the background at its own ephemeral port, so that tests may run in parallel.
+///
+/// Dropping it stops the server as well.
+pub struct TestServer {
+    addr: SocketAddr,
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<anyhow::Result<()>>,
+}
+impl TestServer {
+    /// Builds the server at localhost with the database given by `DATABASE_URL` and spawns it.
+    pub async fn start() -> anyhow::Result<Self> {
+        Ok(Self::spawn(Server::build((HOST_DEFAULT, 0)).await?))
+    }
+
+    /// Spawns the built server, it accepts connections right away.
+    pub fn spawn(server: Server) -> Self {
+        let addr = server.local_addr();
+        let (stop, stopped) = oneshot::channel();
+        let server = server.shutdown_on(async {
+            let _ = stopped.await;
+        });
+        TestServer {
+            addr,
+            stop,
+            task: tokio::spawn(server.run()),
+        }
+    }
+
+    /// Address clients connect to.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Whether the server still runs, it should until shut down.
+    pub fn is_running(&self) -> bool {
+        !self.task.is_finished()
+    }
+
+    /// Stops the server and waits for it, returns what it ended with.
+    pub async fn shutdown(self) -> anyhow::Result<()> {
+        let _ = self.stop.send(());
+        self.task.await?
+    }
+}
+
+/// Identity provider signing the tokens of [OIDC log-ins][Oidc] with a shared secret.
+///
+/// Its keys are in a temporary file, dropping it removes the file.
+pub struct TestIssuer {
+    jwks: PathBuf,
+}
+impl TestIssuer {
+    /// Issuer the tokens name, see [Oidc::issuer].
+    pub const ISSUER: &'static str = "https://id.example.com";
+    /// Audience the tokens are for, see [Oidc::audience].
+    pub const AUDIENCE: &'static str = "chat";
+    const SECRET: &'static [u8] = b"secret of the test issuer";
+    const KEY_ID: &'static str = "test";
+
+    /// Writes the keys, panics if it fails.
+    pub fn new() -> Self {
+        let jwks = std::env::temp_dir().join(format!("{}.json", unique("jwks")));
+        let keys = json!({"keys": [{
+            "kty": "oct",
+            "kid": Self::KEY_ID,
+            "k": URL_SAFE_NO_PAD.encode(Self::SECRET),
+        }]});
+        std::fs::write(&jwks, keys.to_string()).unwrap();
+        TestIssuer { jwks }
+    }
+
+    /// Configuration of a server accepting the tokens of the issuer.
+    pub fn oidc(&self) -> Oidc {
+        Oidc {
+            audience: Some(Self::AUDIENCE.to_string()),
+            jwks: Some(self.jwks.display().to_string()),
+            ..Oidc::new(Self::ISSUER)
+        }
+    }
+
+    /// Token of the `subject` preferring the `username`, valid for a minute.
+    pub fn token(&self, subject: &str, username: &str) -> String {
+        let mut header = Header::default();
+        header.kid = Some(Self::KEY_ID.to_string());
+        let claims = json!({
+            "iss": Self::ISSUER,
+            "aud": Self::AUDIENCE,
+            "sub": subject,
+            "preferred_username": username,
+            "exp": chrono::Utc::now().timestamp() + 60,
+        });
+        jsonwebtoken::encode(&header, &claims, &EncodingKey::from_secret(Self::SECRET)).unwrap()
+    }
+}
+impl Default for TestIssuer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Drop for TestIssuer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.jwks);
+    }
+}
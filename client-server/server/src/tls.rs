@@ -0,0 +1,58 @@
+//! TLS of the client connections, see [Server::tls][crate::Server::tls].
+use std::{path::Path, sync::Arc, time::Duration};
+
+use anyhow::Context;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    time::timeout,
+};
+use tokio_rustls::{
+    rustls::{
+        crypto::ring,
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+        ServerConfig,
+    },
+    TlsAcceptor,
+};
+
+/// How long a client may take to finish the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Byte stream of a client, plain TCP or TLS over it.
+pub(crate) trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> Stream for S {}
+
+/// Stream of a client the server reads from and writes to, see [secure].
+pub(crate) type ClientStream = Box<dyn Stream>;
+
+/// Acceptor of the certificate chain and its key, both PEM files, e.g. those [init][crate::init] writes.
+pub(crate) fn acceptor(cert: &Path, key: &Path) -> anyhow::Result<TlsAcceptor> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Reading the TLS certificate {cert:?} failed"))?;
+    let key = PrivateKeyDer::from_pem_file(key)
+        .with_context(|| format!("Reading the TLS key {key:?} failed"))?;
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .context("TLS protocol versions are not supported")?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("TLS certificate does not match its key")?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Finishes the TLS handshake when the server has an `acceptor`, the socket is used as it is otherwise.
+pub(crate) async fn secure(
+    socket: TcpStream,
+    acceptor: Option<&TlsAcceptor>,
+) -> anyhow::Result<ClientStream> {
+    let Some(acceptor) = acceptor else {
+        return Ok(Box::new(socket));
+    };
+    let stream = timeout(HANDSHAKE_TIMEOUT, acceptor.accept(socket))
+        .await
+        .context("TLS handshake timed out")?
+        .context("TLS handshake failed")?;
+    Ok(Box::new(stream))
+}
@@ -0,0 +1,181 @@
+//! Time-based one-time passwords (RFC 6238) of the second log-in step, see [TotpKey].
+//!
+//! Codes have 6 digits and change every 30 seconds, the previous and the next one are accepted too,
+//! so clocks may differ a little. Secrets are stored sealed by the [TotpKey] of the server.
+use std::{
+    fmt,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use aes_gcm::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+use cli_ser::User;
+
+/// Seconds each code is valid for.
+const PERIOD: u64 = 30;
+/// Bytes of the secrets, as recommended for HMAC-SHA1.
+const SECRET_LEN: usize = 20;
+/// Bytes of the AES-GCM nonce the sealed secrets begin with.
+const NONCE_LEN: usize = 12;
+/// Name of the account shown by authenticator apps.
+const ISSUER: &str = "Chat";
+
+/// AES-256 key the TOTP secrets are encrypted with, given in base64, e.g. by `openssl rand -base64 32`.
+///
+/// Secrets sealed by one key can not be opened by another one, changing the key disables
+/// two-factor authentication of all users.
+#[derive(Clone, PartialEq, Eq)]
+pub struct TotpKey([u8; 32]);
+impl FromStr for TotpKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = STANDARD
+            .decode(s.trim())
+            .map_err(|e| format!("TOTP key is not base64, {e}"))?;
+        let key = bytes
+            .try_into()
+            .map_err(|bytes: Vec<u8>| format!("TOTP key has {} bytes, 32 expected", bytes.len()))?;
+        Ok(TotpKey(key))
+    }
+}
+/// The key is secret, it is never printed.
+impl fmt::Debug for TotpKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TotpKey(***)")
+    }
+}
+impl TotpKey {
+    /// Encrypts the secret, the random nonce is prepended.
+    pub(crate) fn seal(&self, totp: &Totp) -> Vec<u8> {
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&self.0));
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let sealed = cipher
+            .encrypt(&nonce, totp.secret.as_slice())
+            .expect("encrypting a short secret does not fail");
+        [nonce.as_slice(), &sealed].concat()
+    }
+
+    /// Decrypts the secret, `None` when it was sealed by another key or is damaged.
+    pub(crate) fn open(&self, sealed: &[u8]) -> Option<Totp> {
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, sealed) = sealed.split_at(NONCE_LEN);
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&self.0));
+        let secret = cipher.decrypt(Nonce::from_slice(nonce), sealed).ok()?;
+        Some(Totp { secret })
+    }
+}
+
+/// Secret shared by the server and the authenticator app of a user.
+pub(crate) struct Totp {
+    secret: Vec<u8>,
+}
+impl Totp {
+    /// Makes a new random secret.
+    pub(crate) fn generate() -> Self {
+        let mut secret = vec![0; SECRET_LEN];
+        OsRng.fill_bytes(&mut secret);
+        Totp { secret }
+    }
+
+    /// `otpauth://` URL authenticator apps are set up with, usually scanned as a QR code.
+    pub(crate) fn url(&self, user: &User) -> String {
+        let secret = data_encoding::BASE32_NOPAD.encode(&self.secret);
+        format!(
+            "otpauth://totp/{ISSUER}:{}?secret={secret}&issuer={ISSUER}&algorithm=SHA1&digits=6&period={PERIOD}",
+            percent_encoded(&user.to_string())
+        )
+    }
+
+    /// Whether the code is the current one, the previous or the next one.
+    pub(crate) fn verify(&self, code: &str) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        let code = code.trim();
+        [now.saturating_sub(PERIOD), now, now + PERIOD]
+            .iter()
+            .fold(false, |valid, &time| {
+                valid | same(&self.code_at(time), code)
+            })
+    }
+
+    /// Code of the period the unix `time` falls in.
+    fn code_at(&self, time: u64) -> String {
+        let mut mac =
+            Hmac::<Sha1>::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(&(time / PERIOD).to_be_bytes());
+        let hash = mac.finalize().into_bytes();
+        let offset = usize::from(hash[hash.len() - 1] & 0x0f);
+        let truncated = u32::from_be_bytes(hash[offset..offset + 4].try_into().expect("4 bytes"));
+        format!("{:06}", (truncated & 0x7fff_ffff) % 1_000_000)
+    }
+}
+
+/// Compares the codes in time independent of where they differ.
+fn same(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Escapes everything but unreserved characters of URLs.
+fn percent_encoded(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                char::from(b).to_string()
+            }
+            b => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rfc_6238_codes() {
+        // Test vectors of the RFC (SHA-1), the last 6 of their 8 digits.
+        let totp = Totp {
+            secret: b"12345678901234567890".to_vec(),
+        };
+        assert_eq!(totp.code_at(59), "287082");
+        assert_eq!(totp.code_at(1111111109), "081804");
+        assert_eq!(totp.code_at(20000000000), "353130");
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        assert!(totp.verify(&totp.code_at(now)));
+        assert!(totp.verify(&totp.code_at(now - PERIOD)));
+        assert!(!totp.verify(&totp.code_at(now - 3 * PERIOD)));
+    }
+
+    #[test]
+    fn test_seal() {
+        let key: TotpKey = STANDARD.encode([7; 32]).parse().unwrap();
+        let totp = Totp::generate();
+        let sealed = key.seal(&totp);
+        assert_ne!(sealed[NONCE_LEN..], totp.secret[..]);
+        assert_eq!(key.open(&sealed).unwrap().secret, totp.secret);
+        let other: TotpKey = STANDARD.encode([8; 32]).parse().unwrap();
+        assert!(other.open(&sealed).is_none());
+        assert!("c2hvcnQ=".parse::<TotpKey>().is_err());
+        assert!(totp
+            .url(&"alice smith".to_string().into())
+            .starts_with("otpauth://totp/Chat:alice%20smith?secret="));
+    }
+}
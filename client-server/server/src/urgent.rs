@@ -0,0 +1,85 @@
+//! Limit of [urgent][cli_ser::cli::Msg::Urgent] texts, see [UrgentLimit].
+use std::time::Duration;
+
+use cli_ser::User;
+
+use crate::{
+    rate::{RateLimit, Window},
+    reload::Swap,
+};
+
+/// How many urgent texts each user may send within the period, further ones are rejected.
+///
+/// Urgent texts get through the clients' filters and notify everyone, so they are limited tightly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UrgentLimit {
+    pub max: usize,
+    pub period: Duration,
+}
+impl Default for UrgentLimit {
+    /// Three urgent texts per ten minutes.
+    fn default() -> Self {
+        UrgentLimit {
+            max: 3,
+            period: Duration::from_secs(600),
+        }
+    }
+}
+
+/// Times of the urgent texts of each user within the [period][UrgentLimit::period].
+pub(crate) struct Limiter {
+    limit: Swap<UrgentLimit>,
+    sent: Window,
+}
+impl Limiter {
+    pub(crate) fn new(limit: UrgentLimit) -> Self {
+        Limiter {
+            limit: Swap::new(limit),
+            sent: Window::default(),
+        }
+    }
+
+    /// Replaces the limit, texts sent so far count towards the new one.
+    pub(crate) fn set_limit(&self, limit: UrgentLimit) {
+        self.limit.store(limit);
+    }
+
+    /// Counts an urgent text of the user sent now, returns the reason of its rejection when over the limit.
+    pub(crate) fn check(&self, user: &User) -> Result<(), String> {
+        let UrgentLimit { max, period } = *self.limit.load();
+        let limit = RateLimit { max, period };
+        match self.sent.admit(user, limit) {
+            true => Ok(()),
+            false => Err(limit.reason("urgent texts")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn limited_per_period() {
+        let limiter = Limiter::new(UrgentLimit {
+            max: 2,
+            period: Duration::from_secs(60),
+        });
+        let (alice, bob) = (
+            User::from("alice".to_string()),
+            User::from("bob".to_string()),
+        );
+        assert!(limiter.check(&alice).is_ok());
+        assert!(limiter.check(&alice).is_ok());
+        assert!(limiter.check(&alice).is_err());
+        assert!(limiter.check(&bob).is_ok());
+        tokio::time::advance(Duration::from_secs(61)).await;
+        assert!(limiter.check(&alice).is_ok());
+
+        limiter.set_limit(UrgentLimit {
+            max: 1,
+            ..UrgentLimit::default()
+        });
+        assert!(limiter.check(&alice).is_err());
+    }
+}
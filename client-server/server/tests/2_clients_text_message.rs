@@ -8,8 +8,8 @@ use tokio::net::TcpStream;
 
 use server::*;
 
-async fn client(s: &str, creds: Credentials) -> Data {
-    let mut stream = TcpStream::connect(SocketAddr::from((HOST_DEFAULT, PORT_DEFAULT)))
+async fn client(address: SocketAddr, s: &str, creds: Credentials) -> Data {
+    let mut stream = TcpStream::connect(address)
         .await
         .expect("Connecting to the server failed!");
     Auth(LogIn(creds))
@@ -43,17 +43,15 @@ fn data_to_string(m: Data) -> String {
 
 #[tokio::test]
 async fn test_2_clients_text_message() {
-    let address = (HOST_DEFAULT, PORT_DEFAULT);
-    let server = server::Server::build(address).await.unwrap();
-    let server_thread = tokio::spawn(server.run());
-    tokio::time::sleep(Duration::from_millis(100)).await;
+    let server = TestServer::start().await.unwrap();
+    let address = server.addr();
 
     let creds = Credentials {
         user: "test".to_string().into(),
         password: "test_pass".to_string(),
     };
     {
-        let mut stream = TcpStream::connect(SocketAddr::from(address)).await.unwrap();
+        let mut stream = TcpStream::connect(address).await.unwrap();
         Auth(SignUp(creds.clone())).send(&mut stream).await.unwrap();
         match ser::Msg::receive(&mut stream).await.unwrap() {
             ser::Msg::Authenticated | ser::Msg::Error(ser::Error::UsernameTaken) => {}
@@ -61,14 +59,13 @@ async fn test_2_clients_text_message() {
         }
     }
     let s_1 = "hi from 1";
-    let conn_1 = tokio::spawn(client(s_1, creds.clone()));
+    let conn_1 = tokio::spawn(client(address, s_1, creds.clone()));
 
     let s_2 = "hi from 2";
-    let conn_2 = tokio::spawn(client(s_2, creds.clone()));
+    let conn_2 = tokio::spawn(client(address, s_2, creds.clone()));
 
     assert_eq!(data_to_string(conn_1.await.unwrap()), s_2.to_string());
     assert_eq!(data_to_string(conn_2.await.unwrap()), s_1.to_string());
-    if server_thread.is_finished() {
-        server_thread.await.unwrap().unwrap();
-    }
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
 }
@@ -0,0 +1,38 @@
+use std::time::Duration;
+
+use cli_ser::{ser, Messageable};
+use tokio::net::TcpStream;
+
+use server::*;
+
+#[tokio::test]
+async fn test_connections_per_ip() {
+    let server = TestServer::spawn(Server::build((HOST_DEFAULT, 0)).await.unwrap().access(
+        AccessPolicy {
+            max_per_ip: Some(2),
+            ..Default::default()
+        },
+    ));
+    let address = server.addr();
+
+    let first = TcpStream::connect(address).await.unwrap();
+    let _second = TcpStream::connect(address).await.unwrap();
+    let mut third = TcpStream::connect(address).await.unwrap();
+    assert_eq!(
+        ser::Msg::receive(&mut third).await.unwrap(),
+        ser::Error::Refused(ser::Refusal::TooManyFromAddress).into()
+    );
+
+    drop(first);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let mut fourth = TcpStream::connect(address).await.unwrap();
+    assert!(
+        tokio::time::timeout(Duration::from_millis(300), ser::Msg::receive(&mut fourth))
+            .await
+            .is_err(),
+        "the freed slot should be taken without a refusal"
+    );
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
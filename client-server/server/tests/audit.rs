@@ -0,0 +1,72 @@
+use std::net::SocketAddr;
+
+use cli_ser::{cli::Credentials, conn::Connection, ser};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use server::{
+    testing::{authentication_error, signed_up},
+    *,
+};
+
+const TOKEN: &str = "audit_test_token";
+
+async fn get(address: SocketAddr, path: &str) -> Value {
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    let request = format!(
+        "GET {path} HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer {TOKEN}\r\n\
+Connection: close\r\n\r\n"
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200"), "{head}");
+    serde_json::from_str(body).unwrap()
+}
+
+#[tokio::test]
+async fn test_audit_log() {
+    let http = SocketAddr::from((HOST_DEFAULT, 11181));
+    let server = TestServer::spawn(
+        Server::build((HOST_DEFAULT, 0))
+            .await
+            .unwrap()
+            .http(http, TOKEN),
+    );
+    let address = server.addr();
+
+    let (creds, _) = signed_up(address, "audit_user").await;
+    let wrong = Credentials {
+        password: "not_the_pass".to_string(),
+        ..creds.clone()
+    };
+    let refused = Connection::connect(address, wrong).await;
+    assert_eq!(
+        authentication_error(refused),
+        ser::Error::InvalidCredentials
+    );
+    Connection::connect(address, creds.clone()).await.unwrap();
+
+    let page = get(http, "/audit?limit=200").await;
+    let events: Vec<&str> = page["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|entry| entry["username"] == creds.user.to_string())
+        .map(|entry| entry["event"].as_str().unwrap())
+        .collect();
+    assert_eq!(events, ["login", "failed_login", "signup"]);
+
+    let page = get(http, "/audit?limit=1").await;
+    let newest = &page["entries"][0];
+    assert!(newest["addr"].as_str().unwrap().starts_with("127.0.0.1:"));
+    let next = get(http, &format!("/audit?before={}", page["next"])).await;
+    assert!(next["entries"][0]["id"].as_i64() < newest["id"].as_i64());
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
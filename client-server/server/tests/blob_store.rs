@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+use cli_ser::{cli, ser, File, Image, UserProfile};
+use sha2::{Digest, Sha256};
+
+use server::{
+    testing::{signed_up, unique},
+    *,
+};
+
+#[tokio::test]
+async fn test_local_directory() {
+    let dir = std::env::temp_dir().join(unique("blob_store"));
+    let url = std::env::var("DATABASE_URL").unwrap();
+    let options = DatabaseOptions {
+        blob_store: BlobStore::LocalDirectory(dir.clone()),
+        ..Default::default()
+    };
+    let server = TestServer::spawn(
+        Server::build_with_database_options((HOST_DEFAULT, 0), &url, &options)
+            .await
+            .unwrap(),
+    );
+    let address = server.addr();
+
+    let (creds, mut conn) = signed_up(address, "blobs").await;
+
+    let bytes = unique("unique attachment").into_bytes();
+    let file = File::new("attachment.txt", bytes.clone());
+    conn.send(file.into()).await.unwrap();
+    let avatar = Image::from_path("../example-images/hexagon.jpeg")
+        .await
+        .unwrap();
+    let change = cli::ProfileChange::Avatar(avatar.clone());
+    conn.send_msg(cli::Msg::SetProfile(change)).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let hash = Sha256::digest(&bytes);
+    let hex: String = hash.iter().map(|byte| format!("{byte:02x}")).collect();
+    let stored = dir.join(&hex[..2]).join(&hex);
+    assert_eq!(std::fs::read(stored).unwrap(), bytes);
+    let pool = sqlx::PgPool::connect(&url).await.unwrap();
+    let inline: Option<Vec<u8>> = sqlx::query_scalar("SELECT bytes FROM blobs WHERE hash = $1")
+        .bind(hash.as_slice())
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(inline, None, "only the hash is kept in the database");
+
+    conn.send_msg(cli::Msg::GetProfile(creds.user.clone()))
+        .await
+        .unwrap();
+    assert_eq!(
+        conn.recv().await.unwrap(),
+        ser::Msg::Profile {
+            user: creds.user,
+            profile: UserProfile {
+                avatar: Some(avatar),
+                ..Default::default()
+            }
+        }
+    );
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,76 @@
+#![cfg(unix)]
+use std::time::Duration;
+
+use cli_ser::{
+    bot::{Action, Event},
+    ser, Data, Messageable,
+};
+use tokio::net::UnixStream;
+
+use server::{
+    testing::{signed_up, unique},
+    *,
+};
+
+#[tokio::test]
+async fn test_bots() {
+    let socket = std::env::temp_dir().join(format!("{}.sock", unique("server-bots")));
+    let server = TestServer::spawn(
+        Server::build((HOST_DEFAULT, 0))
+            .await
+            .unwrap()
+            .bot_socket(&socket),
+    );
+    let address = server.addr();
+    // The bot socket is bound once the server runs.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut bot = UnixStream::connect(&socket).await.unwrap();
+    Action::Subscribe.send(&mut bot).await.unwrap();
+    assert_eq!(
+        Event::receive(&mut bot).await.unwrap(),
+        Event::Error("say hello first".to_string())
+    );
+    for action in [Action::Hello("dice".to_string()), Action::Subscribe] {
+        action.send(&mut bot).await.unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (creds, mut client) = signed_up(address, "bots").await;
+
+    let roll = Data::Text("/roll".to_string());
+    client.send(roll.clone()).await.unwrap();
+    match Event::receive(&mut bot).await.unwrap() {
+        Event::Data { from, data, .. } => {
+            assert_eq!(from, creds.user);
+            assert_eq!(data, roll);
+        }
+        other => panic!("{other:?}"),
+    }
+
+    let rolled = Data::Text("4".to_string());
+    Action::Whisper {
+        to: creds.user.clone(),
+        data: rolled.clone(),
+    }
+    .send(&mut bot)
+    .await
+    .unwrap();
+    Action::Broadcast(rolled.clone())
+        .send(&mut bot)
+        .await
+        .unwrap();
+    for _ in 0..2 {
+        match client.recv().await.unwrap() {
+            ser::Msg::DataFrom { from, data, .. } => {
+                assert_eq!(from.to_string(), "dice");
+                assert_eq!(data, rolled);
+            }
+            other => panic!("{other:?}"),
+        }
+    }
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+    std::fs::remove_file(socket).unwrap();
+}
@@ -4,8 +4,8 @@ use server::*;
 
 #[tokio::test]
 async fn test_run_1_sec() {
-    let server = Server::build((HOST_DEFAULT, PORT_DEFAULT)).await.unwrap();
-    let server_thread = tokio::spawn(server.run());
+    let server = TestServer::start().await.unwrap();
     std::thread::sleep(Duration::from_secs(1));
-    assert!(!server_thread.is_finished());
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
 }
@@ -0,0 +1,160 @@
+use cli_ser::{cli, ser, Codec, Messageable, MsgId};
+use tokio::net::TcpStream;
+
+use server::{testing::unique, *};
+
+async fn server() -> TestServer {
+    let server = Server::build((HOST_DEFAULT, 0)).await.unwrap();
+    TestServer::spawn(server.allow_guests())
+}
+
+/// Joins as a guest without offering codecs, the connection stays bincode.
+#[cfg(any(feature = "postcard", feature = "json"))]
+async fn bincode_guest(server: &TestServer, name: &str) -> TcpStream {
+    let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+    let msg = cli::Msg::Auth(cli::Auth::Guest(name.to_string().into()));
+    msg.send(&mut stream).await.unwrap();
+    assert_eq!(
+        ser::Msg::receive(&mut stream).await.unwrap(),
+        ser::Msg::Authenticated
+    );
+    stream
+}
+
+#[tokio::test]
+async fn test_codec_negotiated() {
+    let server = server().await;
+    let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+
+    // Unknown ids are skipped, the first known one is chosen.
+    let offer = cli::Msg::Codecs(vec![200, Codec::Bincode.id()]).tagged(MsgId(1));
+    offer.send(&mut stream).await.unwrap();
+    assert_eq!(
+        ser::Msg::receive(&mut stream).await.unwrap(),
+        ser::Msg::CodecChosen(Codec::Bincode)
+    );
+    assert_eq!(
+        ser::Msg::receive(&mut stream).await.unwrap(),
+        ser::Msg::Ack(MsgId(1))
+    );
+
+    // Nothing known falls back to bincode.
+    cli::Msg::Codecs(vec![200]).send(&mut stream).await.unwrap();
+    assert_eq!(
+        ser::Msg::receive(&mut stream).await.unwrap(),
+        ser::Msg::CodecChosen(Codec::Bincode)
+    );
+
+    let msg = cli::Msg::Auth(cli::Auth::Guest(unique("codec_guest").into()));
+    msg.send(&mut stream).await.unwrap();
+    assert_eq!(
+        ser::Msg::receive(&mut stream).await.unwrap(),
+        ser::Msg::Authenticated
+    );
+
+    // The codec is negotiated before the authentication only.
+    let late = cli::Msg::Codecs(vec![Codec::Bincode.id()]).tagged(MsgId(2));
+    late.send(&mut stream).await.unwrap();
+    loop {
+        match ser::Msg::receive(&mut stream).await.unwrap() {
+            ser::Msg::Rejected(MsgId(2), e) => {
+                assert_eq!(e, ser::Error::AlreadyAuthenticated);
+                break;
+            }
+            ser::Msg::PresenceChanged { .. } | ser::Msg::NewSession { .. } => {}
+            other => panic!("expected the rejection, got {other}"),
+        }
+    }
+}
+
+#[cfg(feature = "postcard")]
+#[tokio::test]
+async fn test_postcard_and_bincode_clients() {
+    use cli_ser::{conn::Connection, Data};
+
+    let server = server().await;
+    let mut bincode = bincode_guest(&server, &unique("codec_bincode")).await;
+    let mut postcard = Connection::guest(server.addr(), unique("codec_postcard"))
+        .await
+        .unwrap();
+    assert_eq!(postcard.codec(), Codec::Postcard);
+
+    // The broadcast is encoded for each of them by its codec.
+    let text = Data::Text("compact".to_string());
+    postcard.send(text.clone()).await.unwrap();
+    loop {
+        match ser::Msg::receive(&mut bincode).await.unwrap() {
+            ser::Msg::DataFrom { data, .. } => {
+                assert_eq!(data, text);
+                break;
+            }
+            ser::Msg::PresenceChanged { .. } => {}
+            other => panic!("expected the text, got {other}"),
+        }
+    }
+    let msg = cli::Msg::ToAll(Data::Text("wide".to_string()));
+    msg.send(&mut bincode).await.unwrap();
+    loop {
+        match postcard.recv().await.unwrap() {
+            ser::Msg::DataFrom { data, .. } => {
+                assert_eq!(data, Data::Text("wide".to_string()));
+                break;
+            }
+            ser::Msg::PresenceChanged { .. } => {}
+            other => panic!("expected the text, got {other}"),
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+#[tokio::test]
+async fn test_json_client() {
+    use cli_ser::Data;
+
+    let server = server().await;
+    let mut bincode = bincode_guest(&server, &unique("codec_bincode")).await;
+    // What a client in another language does: bincode until it is authenticated, JSON then.
+    let mut json = TcpStream::connect(server.addr()).await.unwrap();
+    let offer = cli::Msg::Codecs(vec![Codec::Json.id()]);
+    offer.send(&mut json).await.unwrap();
+    assert_eq!(
+        ser::Msg::receive(&mut json).await.unwrap(),
+        ser::Msg::CodecChosen(Codec::Json)
+    );
+    let msg = cli::Msg::Auth(cli::Auth::Guest(unique("codec_json").into()));
+    msg.send(&mut json).await.unwrap();
+    assert_eq!(
+        ser::Msg::receive(&mut json).await.unwrap(),
+        ser::Msg::Authenticated
+    );
+
+    let msg = cli::Msg::ToAll(Data::Text("readable".to_string()));
+    let bytes = msg.to_bytes_with(Codec::Json).unwrap();
+    assert!(std::str::from_utf8(&bytes).unwrap().contains("readable"));
+    msg.send_with(Codec::Json, &mut json).await.unwrap();
+    loop {
+        match ser::Msg::receive(&mut bincode).await.unwrap() {
+            ser::Msg::DataFrom { data, .. } => {
+                assert_eq!(data, Data::Text("readable".to_string()));
+                break;
+            }
+            ser::Msg::PresenceChanged { .. } => {}
+            other => panic!("expected the text, got {other}"),
+        }
+    }
+    let msg = cli::Msg::ToAll(Data::Text("binary".to_string()));
+    msg.send(&mut bincode).await.unwrap();
+    loop {
+        match ser::Msg::receive_with(Codec::Json, &mut json)
+            .await
+            .unwrap()
+        {
+            ser::Msg::DataFrom { data, .. } => {
+                assert_eq!(data, Data::Text("binary".to_string()));
+                break;
+            }
+            ser::Msg::PresenceChanged { .. } => {}
+            other => panic!("expected the text, got {other}"),
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use cli_ser::{cli::Credentials, conn::Connection, ser};
+use tokio::task::JoinSet;
+
+use server::{
+    testing::{authentication_error, unique},
+    *,
+};
+
+#[tokio::test]
+async fn test_same_name_signed_up_once() {
+    let server = TestServer::start().await.unwrap();
+    let address = server.addr();
+
+    let user: cli_ser::User = unique("concurrent").into();
+    let mut sign_ups = JoinSet::new();
+    for i in 0..20 {
+        let creds = Credentials {
+            user: user.clone(),
+            password: format!("concurrent_pass_{i}"),
+        };
+        sign_ups.spawn(Connection::sign_up(address, creds));
+    }
+    let mut authenticated = 0;
+    while let Some(result) = sign_ups.join_next().await {
+        match result.unwrap() {
+            Ok(_) => authenticated += 1,
+            refused => assert_eq!(authentication_error(refused), ser::Error::UsernameTaken),
+        }
+    }
+    assert_eq!(authenticated, 1);
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
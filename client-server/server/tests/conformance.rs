@@ -0,0 +1,18 @@
+use cli_ser::{cli::Credentials, conformance};
+
+use server::*;
+
+#[tokio::test]
+async fn test_conformance() {
+    let server = TestServer::start().await.unwrap();
+
+    let creds = Credentials {
+        user: "conformance_user".to_string().into(),
+        password: "conformance_pass".to_string(),
+    };
+    let report = conformance::run_server_suite(server.addr(), creds).await;
+    assert!(report.passed(), "\n{report}");
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
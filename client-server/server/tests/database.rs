@@ -0,0 +1,119 @@
+use std::time::{Duration, Instant};
+
+use cli_ser::conn::Connection;
+use tokio::task::JoinSet;
+
+use server::{
+    testing::{credentials, signed_up},
+    *,
+};
+
+#[tokio::test]
+async fn test_connect_retries_with_backoff() {
+    let options = DatabaseOptions {
+        connect_retries: 2,
+        retry_delay: Duration::from_millis(50),
+        ..Default::default()
+    };
+    let start = Instant::now();
+    // Nothing listens at the port.
+    let built = Server::build_with_database_options(
+        (HOST_DEFAULT, 0),
+        "postgres://postgres@127.0.0.1:9/postgres",
+        &options,
+    )
+    .await;
+    assert!(built.is_err());
+    assert!(start.elapsed() >= Duration::from_millis(150));
+}
+
+#[tokio::test]
+async fn test_pool_options() {
+    let options = DatabaseOptions {
+        max_connections: 1,
+        acquire_timeout: Duration::from_secs(1),
+        statement_timeout: Some(Duration::from_secs(1)),
+        ..Default::default()
+    };
+    let url = std::env::var("DATABASE_URL").unwrap();
+    let server = TestServer::spawn(
+        Server::build_with_database_options((HOST_DEFAULT, 0), &url, &options)
+            .await
+            .unwrap(),
+    );
+    let address = server.addr();
+
+    let mut sign_ups = JoinSet::new();
+    for i in 0..10 {
+        let creds = credentials(&format!("pool_{i}"));
+        sign_ups.spawn(Connection::sign_up(address, creds));
+    }
+    while let Some(result) = sign_ups.join_next().await {
+        result.unwrap().unwrap();
+    }
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
+
+async fn stored_hash(pool: &sqlx::PgPool, user: &cli_ser::User) -> String {
+    sqlx::query_scalar("SELECT password FROM users WHERE username = $1;")
+        .bind(user.to_string())
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_password_rehashed_on_log_in() {
+    let url = std::env::var("DATABASE_URL").unwrap();
+    let invalid = DatabaseOptions {
+        password_hashing: PasswordHashing {
+            memory_kib: 0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    assert!(
+        Server::build_with_database_options((HOST_DEFAULT, 0), &url, &invalid)
+            .await
+            .is_err()
+    );
+
+    let cheap = DatabaseOptions {
+        password_hashing: PasswordHashing {
+            memory_kib: 1024,
+            iterations: 1,
+            parallelism: 1,
+        },
+        ..Default::default()
+    };
+    let server = TestServer::spawn(
+        Server::build_with_database_options((HOST_DEFAULT, 0), &url, &cheap)
+            .await
+            .unwrap(),
+    );
+    let (creds, _) = signed_up(server.addr(), "rehash").await;
+    server.shutdown().await.unwrap();
+    let pool = sqlx::PgPool::connect(&url).await.unwrap();
+    assert!(stored_hash(&pool, &creds.user)
+        .await
+        .contains("$m=1024,t=1,p=1$"));
+
+    // The server of the default cost upgrades the hash, the password stays the same.
+    let server = TestServer::spawn(
+        Server::build_with_database((HOST_DEFAULT, 0), &url)
+            .await
+            .unwrap(),
+    );
+    Connection::connect(server.addr(), creds.clone())
+        .await
+        .unwrap();
+    assert!(stored_hash(&pool, &creds.user)
+        .await
+        .contains("$m=19456,t=2,p=1$"));
+    Connection::connect(server.addr(), creds).await.unwrap();
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
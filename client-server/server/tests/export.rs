@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use cli_ser::{Data, File};
+use serde_json::Value;
+
+use server::{
+    export::*,
+    testing::{signed_up, unique},
+    *,
+};
+
+#[tokio::test]
+async fn test_export() {
+    let server = TestServer::start().await.unwrap();
+    let address = server.addr();
+
+    let (creds, mut conn) = signed_up(address, "export_user").await;
+    let text = unique("exported");
+    let path = "../example-images/hexagon.jpeg";
+    for data in [
+        Data::Text(text.clone()),
+        File::from_path(path).await.unwrap().into(),
+    ] {
+        conn.send(data).await.unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let dir = std::env::temp_dir().join(unique("server-export"));
+    std::fs::create_dir_all(&dir).unwrap();
+    let export = Export {
+        format: Format::Jsonl,
+        since: Some(Utc::now() - chrono::Duration::minutes(1)),
+        out: dir.join("chat.jsonl"),
+    };
+    let url = std::env::var("DATABASE_URL").unwrap();
+    server::export(&url, &DatabaseOptions::default(), export.clone())
+        .await
+        .unwrap();
+    let rows: Vec<Value> = std::fs::read_to_string(&export.out)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .filter(|row: &Value| row["from"] == creds.user.to_string())
+        .collect();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0]["text"], text);
+    assert_eq!(rows[1]["file"], "hexagon.jpeg");
+    let attachment = dir.join(rows[1]["attachment"].as_str().unwrap());
+    assert_eq!(
+        std::fs::read(attachment).unwrap(),
+        std::fs::read(path).unwrap()
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
@@ -0,0 +1,92 @@
+use std::time::Duration;
+
+use cli_ser::{cli, ser, File, MsgId};
+use sha2::{Digest, Sha256};
+use tokio::{task::JoinSet, time::timeout};
+
+use server::{
+    testing::{signed_up, unique},
+    *,
+};
+
+/// Number of blobs of the `bytes` and of the files referring to them, once there are at least `files` of them.
+///
+/// Messages are acknowledged before they are written, the database is polled until they are.
+async fn stored(bytes: &[u8], files: i64) -> (i64, i64) {
+    let pool = sqlx::PgPool::connect(&std::env::var("DATABASE_URL").unwrap())
+        .await
+        .unwrap();
+    let hash = Sha256::digest(bytes);
+    timeout(Duration::from_secs(10), async {
+        loop {
+            let counts: (i64, i64) = sqlx::query_as(
+                "SELECT count(DISTINCT blobs.id), count(files.id) FROM blobs JOIN files ON files.blob_id = blobs.id WHERE blobs.hash = $1",
+            )
+            .bind(hash.as_slice())
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+            if counts.1 >= files {
+                break counts;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("the files were not stored")
+}
+
+#[tokio::test]
+async fn test_file_deduplication() {
+    let server = TestServer::start().await.unwrap();
+    let address = server.addr();
+
+    let (_, mut conn) = signed_up(address, "dedup_user").await;
+
+    let path = "../example-images/hexagon.jpeg";
+    let file = File::from_path(path).await.unwrap();
+    for _ in 0..2 {
+        conn.send(file.clone().into()).await.unwrap();
+    }
+
+    let (blobs, files) = stored(&std::fs::read(path).unwrap(), 2).await;
+    assert_eq!(blobs, 1);
+    assert!(files >= 2);
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_file_deduplication_concurrent() {
+    let server = TestServer::start().await.unwrap();
+    let address = server.addr();
+
+    // New bytes, the first ones of each sender race to insert the blob.
+    let bytes = unique("concurrent attachment").into_bytes();
+    let file = File::new("race.txt", bytes.clone());
+    let mut senders = JoinSet::new();
+    for i in 0..10 {
+        let (_, mut conn) = signed_up(address, &format!("dedup_race_{i}")).await;
+        let msg = cli::Msg::ToAll(file.clone().into()).tagged(MsgId(1));
+        senders.spawn(async move {
+            conn.send_msg(msg).await.unwrap();
+            loop {
+                match conn.recv().await.unwrap() {
+                    ser::Msg::Ack(MsgId(1)) => break,
+                    ser::Msg::Rejected(id, e) => panic!("{id:?} rejected: {e}"),
+                    ser::Msg::Error(e) => panic!("{e}"),
+                    _ => {}
+                }
+            }
+        });
+    }
+    while let Some(sent) = senders.join_next().await {
+        sent.unwrap();
+    }
+
+    assert_eq!(stored(&bytes, 10).await, (1, 10));
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
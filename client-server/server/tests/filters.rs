@@ -0,0 +1,57 @@
+use cli_ser::{cli, ser, Data, File, Image, MsgId};
+
+use server::{filter::*, testing::signed_up, *};
+
+#[tokio::test]
+async fn test_filters() {
+    let server = TestServer::spawn(
+        Server::build((HOST_DEFAULT, 0))
+            .await
+            .unwrap()
+            .filter(MaxTextLength(20))
+            .filter(BannedWords::new(["spam"]))
+            .filter(AttachmentTypes::new(["png", "jpeg"])),
+    );
+    let address = server.addr();
+
+    let (_, mut conn) = signed_up(address, "filters").await;
+
+    let rejected = [
+        (
+            Data::Text("this text is way too long".to_string()),
+            "texts are limited to 20 characters",
+        ),
+        (
+            Data::Text("cheap Spam".to_string()),
+            "the word \"Spam\" is not allowed",
+        ),
+        (
+            File::from_path("Cargo.toml").await.unwrap().into(),
+            "only jpeg, png attachments are allowed",
+        ),
+    ];
+    for (n, (data, reason)) in rejected.into_iter().enumerate() {
+        let id = MsgId(n as u64);
+        conn.send_msg(cli::Msg::ToAll(data).tagged(id))
+            .await
+            .unwrap();
+        assert_eq!(
+            conn.recv().await.unwrap(),
+            ser::Msg::Rejected(id, ser::Error::Rejected(reason.to_string()))
+        );
+    }
+
+    let image = Image::from_path("../example-images/hexagon.jpeg")
+        .await
+        .unwrap();
+    conn.send_msg(cli::Msg::ToAll(image.into()).tagged(MsgId(10)))
+        .await
+        .unwrap();
+    assert!(matches!(
+        conn.recv().await.unwrap(),
+        ser::Msg::Stored { id: MsgId(10), .. }
+    ));
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
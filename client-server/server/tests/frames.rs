@@ -0,0 +1,45 @@
+use std::time::Duration;
+
+use cli_ser::{cli, ser, Data, Error::DisconnectedStream, Messageable, MAX_FRAME_SIZE};
+use tokio::{io::AsyncWriteExt, net::TcpStream, time::timeout};
+
+use server::{
+    testing::{credentials, signed_up},
+    *,
+};
+
+#[tokio::test]
+async fn test_oversized_frame() {
+    let server = TestServer::start().await.unwrap();
+    let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+    cli::Msg::Auth(cli::Auth::SignUp(credentials("oversized")))
+        .send(&mut stream)
+        .await
+        .unwrap();
+    assert_eq!(
+        ser::Msg::receive(&mut stream).await.unwrap(),
+        ser::Msg::Authenticated
+    );
+
+    // The frame claims more than any may have and never comes, the server hangs up.
+    let too_long = (MAX_FRAME_SIZE + 1) as u32;
+    stream.write_all(&too_long.to_be_bytes()).await.unwrap();
+    let closed = timeout(Duration::from_secs(10), async {
+        loop {
+            match ser::Msg::receive(&mut stream).await {
+                Ok(_) => continue,
+                Err(e) => break e,
+            }
+        }
+    })
+    .await
+    .expect("the server kept the connection");
+    assert!(matches!(closed, DisconnectedStream(_)), "{closed}");
+
+    // Others are served on.
+    let (_, mut conn) = signed_up(server.addr(), "after_oversized").await;
+    conn.send(Data::Text("still here".to_string()))
+        .await
+        .unwrap();
+    server.shutdown().await.unwrap();
+}
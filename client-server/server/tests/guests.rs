@@ -0,0 +1,93 @@
+use cli_ser::{
+    cli::{self, Credentials},
+    conn::Connection,
+    ser, Data, MsgId,
+};
+
+use server::{
+    testing::{authentication_error, signed_up, unique, PASSWORD},
+    *,
+};
+
+async fn server(allow_guests: bool) -> TestServer {
+    let server = Server::build((HOST_DEFAULT, 0)).await.unwrap();
+    TestServer::spawn(match allow_guests {
+        true => server.allow_guests(),
+        false => server,
+    })
+}
+
+#[tokio::test]
+async fn test_guests_not_allowed() {
+    let server = server(false).await;
+    let refused = Connection::guest(server.addr(), unique("guest_refused")).await;
+    assert_eq!(authentication_error(refused), ser::Error::GuestsNotAllowed);
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_guests_broadcast_not_stored() {
+    let server = server(true).await;
+    let (creds, mut user) = signed_up(server.addr(), "guest_host").await;
+    // The user is registered among the sessions once their message is acknowledged.
+    user.send_msg(cli::Msg::ToAll(Data::Text("welcome".to_string())).tagged(MsgId(1)))
+        .await
+        .unwrap();
+    assert!(matches!(
+        user.recv().await.unwrap(),
+        ser::Msg::Stored { id: MsgId(1), .. }
+    ));
+    assert_eq!(user.recv().await.unwrap(), ser::Msg::Ack(MsgId(1)));
+
+    let name = unique("guest");
+    let mut guest = Connection::guest(server.addr(), name.clone())
+        .await
+        .unwrap();
+    guest
+        .send_msg(cli::Msg::ToAll(Data::Text("hello".to_string())).tagged(MsgId(1)))
+        .await
+        .unwrap();
+    // Nothing is stored, so the acknowledgement comes right away.
+    assert_eq!(guest.recv().await.unwrap(), ser::Msg::Ack(MsgId(1)));
+    assert_eq!(
+        user.recv().await.unwrap(),
+        ser::Msg::DataFrom {
+            data: Data::Text("hello".to_string()),
+            from: name.clone().into(),
+            msg_id: None,
+            display_name: None,
+            mentions: vec![],
+            guest: true,
+            urgent: false,
+        }
+    );
+
+    let change = cli::ProfileChange::Status("visiting".to_string());
+    guest
+        .send_msg(cli::Msg::SetProfile(change).tagged(MsgId(2)))
+        .await
+        .unwrap();
+    assert!(matches!(
+        guest.recv().await.unwrap(),
+        ser::Msg::Rejected(MsgId(2), ser::Error::Rejected(_))
+    ));
+
+    // The name is in use while the guest is connected, registered names can not be taken.
+    let twin = Connection::guest(server.addr(), name.clone()).await;
+    assert_eq!(authentication_error(twin), ser::Error::UsernameTaken);
+    let impostor = Connection::guest(server.addr(), creds.user.to_string()).await;
+    assert_eq!(authentication_error(impostor), ser::Error::UsernameTaken);
+
+    // Guests are not registered, the name can not be logged in with.
+    let creds = Credentials {
+        user: name.into(),
+        password: PASSWORD.to_string(),
+    };
+    let login = Connection::connect(server.addr(), creds).await;
+    assert_eq!(authentication_error(login), ser::Error::InvalidCredentials);
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
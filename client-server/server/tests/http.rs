@@ -0,0 +1,100 @@
+use std::{net::SocketAddr, time::Duration};
+
+use cli_ser::{ser, Data};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use server::{
+    testing::{signed_up, unique},
+    *,
+};
+
+const TOKEN: &str = "http_test_token";
+
+/// Sends the request and returns the status code and the body.
+async fn request(address: SocketAddr, head: &str, token: &str, body: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    let request = format!(
+        "{head} HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer {token}\r\n\
+Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, body.to_string())
+}
+
+#[tokio::test]
+async fn test_http_api() {
+    let http = SocketAddr::from((HOST_DEFAULT, 11180));
+    let server = TestServer::spawn(
+        Server::build((HOST_DEFAULT, 0))
+            .await
+            .unwrap()
+            .http(http, TOKEN),
+    );
+    let address = server.addr();
+
+    let (creds, mut conn) = signed_up(address, "http_user").await;
+    let text = unique("http history");
+    conn.send(Data::Text(text.clone())).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    assert_eq!(request(http, "GET /users", "wrong", "").await.0, 401);
+    let prefix = &TOKEN[..TOKEN.len() - 1];
+    assert_eq!(request(http, "GET /users", prefix, "").await.0, 401);
+
+    let (status, body) = request(http, "GET /users", TOKEN, "").await;
+    assert_eq!(status, 200);
+    let users: Vec<String> = serde_json::from_str(&body).unwrap();
+    assert!(users.contains(&creds.user.to_string()));
+
+    let (status, body) = request(http, "GET /messages?limit=1", TOKEN, "").await;
+    assert_eq!(status, 200);
+    let page: Value = serde_json::from_str(&body).unwrap();
+    let newest = &page["messages"][0];
+    assert_eq!(newest["from"], creds.user.to_string());
+    assert_eq!(newest["text"], text);
+    assert_eq!(page["next"], newest["id"]);
+
+    let before = newest["id"].as_i64().unwrap();
+    let (_, body) = request(http, &format!("GET /messages?before={before}"), TOKEN, "").await;
+    let older: Value = serde_json::from_str(&body).unwrap();
+    assert!(older["messages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|msg| msg["id"].as_i64().unwrap() < before));
+
+    let (status, _) = request(
+        http,
+        "POST /announcements",
+        TOKEN,
+        r#"{"text": "maintenance at noon"}"#,
+    )
+    .await;
+    assert_eq!(status, 202);
+    assert_eq!(
+        conn.recv().await.unwrap(),
+        ser::Msg::ServerInfo("maintenance at noon".to_string())
+    );
+
+    let (status, body) = request(http, "GET /memory", TOKEN, "").await;
+    assert_eq!(status, 200);
+    let memory: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(memory["ceiling"], MAX_INFLIGHT_BYTES_DEFAULT);
+    assert!(memory["used"].as_u64().unwrap() <= MAX_INFLIGHT_BYTES_DEFAULT as u64);
+
+    // The test process has no logging of the server to control.
+    let (status, _) = request(http, "PUT /log-level", TOKEN, "debug").await;
+    assert_eq!(status, 404);
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
@@ -0,0 +1,51 @@
+use cli_ser::{cli, ser, Data, Image, ImageLimits, MsgId};
+
+use server::{testing::signed_up, *};
+
+#[tokio::test]
+async fn test_image_limits() {
+    let server = TestServer::spawn(
+        Server::build((HOST_DEFAULT, 0))
+            .await
+            .unwrap()
+            .image_limits(ImageLimits {
+                max_width: 1024,
+                ..ImageLimits::default()
+            }),
+    );
+    let (_, mut conn) = signed_up(server.addr(), "image_limits").await;
+
+    // 1512x2016 pixels.
+    let wide = Image::from_path("../example-images/hexagon.jpeg")
+        .await
+        .unwrap();
+    conn.send_msg(cli::Msg::ToAll(wide.clone().into()).tagged(MsgId(1)))
+        .await
+        .unwrap();
+    assert!(matches!(
+        conn.recv().await.unwrap(),
+        ser::Msg::Rejected(MsgId(1), ser::Error::Rejected(reason)) if reason.contains("1512x2016")
+    ));
+    conn.send_msg(cli::Msg::SetProfile(cli::ProfileChange::Avatar(wide)).tagged(MsgId(2)))
+        .await
+        .unwrap();
+    assert!(matches!(
+        conn.recv().await.unwrap(),
+        ser::Msg::Rejected(MsgId(2), ser::Error::Rejected(_))
+    ));
+
+    let small = Image::from_path("../example-images/rustacean-orig-noshadow.png")
+        .await
+        .unwrap();
+    conn.send_msg(cli::Msg::ToAll(Data::Image(small)).tagged(MsgId(3)))
+        .await
+        .unwrap();
+    assert!(matches!(
+        conn.recv().await.unwrap(),
+        ser::Msg::Stored { id: MsgId(3), .. }
+    ));
+    assert_eq!(conn.recv().await.unwrap(), ser::Msg::Ack(MsgId(3)));
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
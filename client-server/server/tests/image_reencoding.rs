@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+use cli_ser::{ser, Data, Image, ImageOutputFormat};
+use sha2::{Digest, Sha256};
+
+use server::{testing::signed_up, *};
+
+#[tokio::test]
+async fn test_image_reencoding() {
+    let server = TestServer::spawn(
+        Server::build((HOST_DEFAULT, 0))
+            .await
+            .unwrap()
+            .reencode_images(ImagePolicy {
+                threshold: 100 * 1024,
+                format: ImageOutputFormat::Jpeg(50),
+                keep_original: true,
+            }),
+    );
+    let address = server.addr();
+
+    let (_, mut sender) = signed_up(address, "reencoding_sender").await;
+    let (_, mut receiver) = signed_up(address, "reencoding_receiver").await;
+
+    let path = "../example-images/hexagon.jpeg";
+    let original = Image::from_path(path).await.unwrap();
+    sender.send(original.clone().into()).await.unwrap();
+    match receiver.recv().await.unwrap() {
+        ser::Msg::DataFrom {
+            data: Data::Image(image),
+            ..
+        } => assert!(image.size() < original.size()),
+        other => panic!("{other:?}"),
+    }
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let pool = sqlx::PgPool::connect(&std::env::var("DATABASE_URL").unwrap())
+        .await
+        .unwrap();
+    let kept: i64 = sqlx::query_scalar(
+        "SELECT count(*) FROM images JOIN blobs ON images.original_blob_id = blobs.id WHERE blobs.hash = $1",
+    )
+    .bind(Sha256::digest(std::fs::read(path).unwrap()).as_slice())
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert!(kept >= 1);
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
@@ -0,0 +1,38 @@
+use std::{env, fs};
+
+use cli_ser::conn::Connection;
+
+use server::{
+    testing::{credentials, unique},
+    *,
+};
+
+#[tokio::test]
+async fn test_init() {
+    let dir = env::temp_dir().join(unique("init"));
+    fs::create_dir_all(&dir).unwrap();
+    let provision = Provision {
+        config: dir.join("server.toml"),
+        database_url: env::var("DATABASE_URL").unwrap(),
+        tls_dir: dir.join("tls"),
+        hosts: vec!["localhost".to_string()],
+    };
+    let admin = credentials("init_admin");
+    let options = DatabaseOptions::default();
+    init(&provision, &options, admin.clone()).await.unwrap();
+
+    let config = ConfigFile::load(&provision.config).unwrap();
+    assert_eq!(config.database_url, Some(provision.database_url.clone()));
+    assert!(config.totp_key.unwrap().parse::<TotpKey>().is_ok());
+    assert!(provision.tls_cert().exists() && provision.tls_key().exists());
+    assert_eq!(config.tls.cert, Some(provision.tls_cert()));
+
+    let server = TestServer::start().await.unwrap();
+    Connection::connect(server.addr(), admin).await.unwrap();
+
+    // A second run stops before the database, the other administrator is not created.
+    let other = credentials("init_other");
+    assert!(init(&provision, &options, other.clone()).await.is_err());
+    assert!(Connection::connect(server.addr(), other).await.is_err());
+    fs::remove_dir_all(dir).unwrap();
+}
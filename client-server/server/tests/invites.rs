@@ -0,0 +1,84 @@
+use cli_ser::{cli, conn::Connection, ser, MsgId};
+
+use server::{
+    testing::{authentication_error, credentials},
+    *,
+};
+
+#[tokio::test]
+async fn test_invite_only_sign_up() {
+    let admin = credentials("invites_admin");
+    let url = std::env::var("DATABASE_URL").unwrap();
+    init_database(&url, &DatabaseOptions::default(), admin.clone())
+        .await
+        .unwrap();
+    let server = Server::build((HOST_DEFAULT, 0)).await.unwrap();
+    let server = TestServer::spawn(server.invite_only());
+    let address = server.addr();
+
+    let refused = Connection::sign_up(address, credentials("invites_uninvited")).await;
+    assert_eq!(authentication_error(refused), ser::Error::InviteRequired);
+    let guess = "0123456789abcdef";
+    let refused = Connection::sign_up_invited(address, credentials("invites_guess"), guess).await;
+    assert_eq!(authentication_error(refused), ser::Error::InvalidInvite);
+
+    let mut admin = Connection::connect(address, admin).await.unwrap();
+    let create = cli::Admin::CreateInvite {
+        uses: 1,
+        valid_for: None,
+    };
+    admin
+        .send_msg(cli::Msg::Admin(create).tagged(MsgId(1)))
+        .await
+        .unwrap();
+    let code = match admin.recv().await.unwrap() {
+        ser::Msg::Invite {
+            code,
+            uses: 1,
+            expires: None,
+        } => code,
+        other => panic!("expected an invitation, got {other}"),
+    };
+    assert_eq!(admin.recv().await.unwrap(), ser::Msg::Ack(MsgId(1)));
+
+    let invited = credentials("invites_invited");
+    let mut conn = Connection::sign_up_invited(address, invited.clone(), &code)
+        .await
+        .unwrap();
+    conn.send_msg(cli::Msg::GetPrefs).await.unwrap();
+    assert!(matches!(conn.recv().await.unwrap(), ser::Msg::Prefs(_)));
+    Connection::connect(address, invited).await.unwrap();
+
+    // Every use is taken.
+    let refused = Connection::sign_up_invited(address, credentials("invites_late"), &code).await;
+    assert_eq!(authentication_error(refused), ser::Error::InvalidInvite);
+
+    // Only administrators invite.
+    let create = cli::Admin::CreateInvite {
+        uses: 3,
+        valid_for: None,
+    };
+    conn.send_msg(cli::Msg::Admin(create).tagged(MsgId(2)))
+        .await
+        .unwrap();
+    assert_eq!(
+        conn.recv().await.unwrap(),
+        ser::Msg::Rejected(MsgId(2), ser::Error::NotAdmin)
+    );
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_invitations_sign_up_on_open_servers() {
+    let server = TestServer::spawn(Server::build((HOST_DEFAULT, 0)).await.unwrap());
+    let creds = credentials("invites_open");
+    Connection::sign_up_invited(server.addr(), creds.clone(), "anything")
+        .await
+        .unwrap();
+    Connection::connect(server.addr(), creds).await.unwrap();
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
@@ -0,0 +1,29 @@
+use std::{
+    net::{Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use cli_ser::{ser, Data};
+
+use server::{testing::signed_up, *};
+
+#[tokio::test]
+async fn test_ipv4_and_ipv6_listeners() {
+    let server = Server::build((HOST_DEFAULT, 0)).await.unwrap();
+    let v4 = server.local_addr();
+    let v6 = SocketAddr::from((Ipv6Addr::LOCALHOST, v4.port()));
+    let server = TestServer::spawn(server.listen(v6));
+    // The IPv6 listener is bound once the server runs.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let (_, mut sender) = signed_up(v4, "listen_v4").await;
+    let (_, mut receiver) = signed_up(v6, "listen_v6").await;
+    sender.send(Data::Text("across".to_string())).await.unwrap();
+    match receiver.recv().await.unwrap() {
+        ser::Msg::DataFrom { data, .. } => assert_eq!(data, Data::Text("across".to_string())),
+        other => panic!("{other:?}"),
+    }
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
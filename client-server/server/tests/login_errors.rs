@@ -0,0 +1,44 @@
+use cli_ser::{cli::Credentials, conn::Connection, ser};
+
+use server::{
+    testing::{authentication_error, credentials, signed_up},
+    *,
+};
+
+/// Errors of logging in as an unknown user and with a wrong password of a known one.
+async fn login_errors(server: &TestServer, prefix: &str) -> (ser::Error, ser::Error) {
+    let (creds, _) = signed_up(server.addr(), prefix).await;
+    let unknown = credentials(&format!("{prefix}_unknown"));
+    let wrong = Credentials {
+        password: "not_the_pass".to_string(),
+        ..creds
+    };
+    (
+        authentication_error(Connection::connect(server.addr(), unknown).await),
+        authentication_error(Connection::connect(server.addr(), wrong).await),
+    )
+}
+
+#[tokio::test]
+async fn test_login_errors_unified() {
+    let server = TestServer::start().await.unwrap();
+    assert_eq!(
+        login_errors(&server, "unified").await,
+        (
+            ser::Error::InvalidCredentials,
+            ser::Error::InvalidCredentials
+        )
+    );
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_login_errors_distinct() {
+    let server = Server::build((HOST_DEFAULT, 0)).await.unwrap();
+    let server = TestServer::spawn(server.distinct_login_errors());
+    assert_eq!(
+        login_errors(&server, "distinct").await,
+        (ser::Error::WrongUser, ser::Error::WrongPassword)
+    );
+    server.shutdown().await.unwrap();
+}
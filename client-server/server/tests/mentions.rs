@@ -0,0 +1,24 @@
+use cli_ser::{ser, Data, User};
+
+use server::{testing::signed_up, *};
+
+#[tokio::test]
+async fn test_mentions() {
+    let server = TestServer::start().await.unwrap();
+    let address = server.addr();
+
+    let (_, mut sender) = signed_up(address, "mentions_sender").await;
+    let (creds, mut receiver) = signed_up(address, "mentions_receiver").await;
+
+    let text = format!("hi @{0}, @{0} and @x-y! a@b @ @!", creds.user);
+    sender.send(Data::Text(text)).await.unwrap();
+    match receiver.recv().await.unwrap() {
+        ser::Msg::DataFrom { mentions, .. } => {
+            assert_eq!(mentions, vec![creds.user, User::from("x-y".to_string())]);
+        }
+        other => panic!("{other:?}"),
+    }
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
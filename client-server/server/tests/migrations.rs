@@ -0,0 +1,107 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sqlx::{Executor, PgPool};
+
+use server::*;
+
+/// Statements the server ran on every start before the migrations.
+const LEGACY: &str = r#"
+CREATE TABLE IF NOT EXISTS "users" ("id" bigserial PRIMARY KEY, "username" text NOT NULL, "password" text NOT NULL);
+ALTER TABLE "users" ADD COLUMN IF NOT EXISTS "is_admin" boolean NOT NULL DEFAULT false;
+CREATE TABLE IF NOT EXISTS "messages" (
+  "id" bigserial PRIMARY KEY,
+  "from_user_id" bigint NOT NULL,
+  "text_id" bigint,
+  "file_id" bigint,
+  "img_id" bigint,
+  "arrived" timestamp with time zone NOT NULL,
+  check((("text_id" IS NOT NULL)::integer + ("file_id" IS NOT NULL)::integer + ("img_id" IS NOT NULL)::integer) = 1)
+);
+CREATE TABLE IF NOT EXISTS "chats" ("id" bigserial PRIMARY KEY, "msg_id" bigint NOT NULL, "to_user_id" bigint NOT NULL, "when_recv" timestamp);
+CREATE TABLE IF NOT EXISTS "texts" ("id" bigserial PRIMARY KEY, "text" text);
+CREATE TABLE IF NOT EXISTS "blobs" ("id" bigserial PRIMARY KEY, "hash" bytea NOT NULL UNIQUE, "bytes" bytea NOT NULL);
+CREATE TABLE IF NOT EXISTS "files" ("id" bigserial PRIMARY KEY, "name" text, "blob_id" bigint);
+CREATE TABLE IF NOT EXISTS "images" ("id" bigserial PRIMARY KEY, "blob_id" bigint);
+ALTER TABLE "files" ADD COLUMN IF NOT EXISTS "blob_id" bigint;
+ALTER TABLE "images" ADD COLUMN IF NOT EXISTS "blob_id" bigint;
+ALTER TABLE "images" ADD COLUMN IF NOT EXISTS "original_blob_id" bigint REFERENCES "blobs" ("id");
+CREATE TABLE IF NOT EXISTS "profiles" (
+  "user_id" bigint PRIMARY KEY REFERENCES "users" ("id"),
+  "display_name" text,
+  "status" text,
+  "avatar_blob_id" bigint REFERENCES "blobs" ("id")
+);
+ALTER TABLE "messages" ADD FOREIGN KEY ("from_user_id") REFERENCES "users" ("id");
+ALTER TABLE "messages" ADD FOREIGN KEY ("text_id") REFERENCES "texts" ("id");
+ALTER TABLE "messages" ADD FOREIGN KEY ("file_id") REFERENCES "files" ("id");
+ALTER TABLE "messages" ADD FOREIGN KEY ("img_id") REFERENCES "images" ("id");
+ALTER TABLE "files" ADD FOREIGN KEY ("blob_id") REFERENCES "blobs" ("id");
+ALTER TABLE "images" ADD FOREIGN KEY ("blob_id") REFERENCES "blobs" ("id");
+ALTER TABLE "chats" ADD FOREIGN KEY ("msg_id") REFERENCES "messages" ("id");
+ALTER TABLE "chats" ADD FOREIGN KEY ("to_user_id") REFERENCES "users" ("id");
+"#;
+
+/// Columns and constraints of the tables, the migrations bookkeeping aside.
+async fn schema(pool: &PgPool) -> Vec<(String, String, String)> {
+    sqlx::query_as(
+        "\
+SELECT table_name::text, column_name::text, concat_ws(' ', data_type, is_nullable, column_default)
+FROM information_schema.columns
+WHERE table_schema = 'public' AND table_name <> '_sqlx_migrations'
+UNION ALL
+SELECT conrelid::regclass::text, conname::text, pg_get_constraintdef(oid)
+FROM pg_constraint
+WHERE connamespace = 'public'::regnamespace AND conrelid <> '_sqlx_migrations'::regclass
+ORDER BY 1, 2;",
+    )
+    .fetch_all(pool)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn test_fresh_and_existing_databases_converge() {
+    let admin_url = std::env::var("DATABASE_URL").unwrap();
+    let admin = PgPool::connect(&admin_url).await.unwrap();
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let (base, _) = admin_url.rsplit_once('/').unwrap();
+    let [fresh, existing] = ["fresh", "existing"].map(|kind| format!("migrations_{kind}_{nanos}"));
+    for name in [&fresh, &existing] {
+        admin
+            .execute(format!("CREATE DATABASE {name};").as_str())
+            .await
+            .unwrap();
+    }
+    let (fresh_url, existing_url) = (format!("{base}/{fresh}"), format!("{base}/{existing}"));
+
+    let legacy = PgPool::connect(&existing_url).await.unwrap();
+    for _start in 0..2 {
+        legacy.execute(LEGACY).await.unwrap();
+    }
+    legacy.close().await;
+
+    for url in [&fresh_url, &fresh_url, &existing_url, &existing_url] {
+        Server::build_with_database((HOST_DEFAULT, 0), url)
+            .await
+            .unwrap();
+    }
+    let fresh_pool = PgPool::connect(&fresh_url).await.unwrap();
+    let existing_pool = PgPool::connect(&existing_url).await.unwrap();
+    let fresh_schema = schema(&fresh_pool).await;
+    assert!(fresh_schema
+        .iter()
+        .any(|(table, name, _)| table == "messages" && name == "messages_text_id_fkey"));
+    assert_eq!(fresh_schema, schema(&existing_pool).await);
+    fresh_pool.close().await;
+    existing_pool.close().await;
+
+    for name in [&fresh, &existing] {
+        admin
+            .execute(format!("DROP DATABASE {name} WITH (FORCE);").as_str())
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,89 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use axum::{extract::State, routing::post, Json, Router};
+use cli_ser::{cli, conn::Connection, ser, Data, MsgId};
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+
+use server::{moderation::HttpModerator, testing::signed_up, *};
+
+/// Moderation service rejecting texts with "spam", counts the reviews.
+async fn moderation_service() -> (String, Arc<AtomicUsize>) {
+    let reviews = Arc::new(AtomicUsize::new(0));
+    let app = Router::new()
+        .route(
+            "/review",
+            post(
+                |State(reviews): State<Arc<AtomicUsize>>, Json(review): Json<Value>| async move {
+                    reviews.fetch_add(1, Ordering::Relaxed);
+                    match review["text"].as_str().unwrap().contains("spam") {
+                        true => Json(json!({"allowed": false, "reason": "looks like spam"})),
+                        false => Json(json!({"allowed": true})),
+                    }
+                },
+            ),
+        )
+        .with_state(reviews.clone());
+    let listener = TcpListener::bind((HOST_DEFAULT, 0)).await.unwrap();
+    let url = format!("http://{}/review", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    (url, reviews)
+}
+
+async fn send(conn: &mut Connection, text: &str, id: u64) -> ser::Msg {
+    let msg = cli::Msg::ToAll(Data::Text(text.to_string())).tagged(MsgId(id));
+    conn.send_msg(msg).await.unwrap();
+    conn.recv().await.unwrap()
+}
+
+#[tokio::test]
+async fn test_moderation_service() {
+    let (url, reviews) = moderation_service().await;
+    let server = Server::build((HOST_DEFAULT, 0)).await.unwrap();
+    let server =
+        TestServer::spawn(server.moderator(HttpModerator::new(url), ModerationPolicy::default()));
+
+    let (_, mut conn) = signed_up(server.addr(), "moderated").await;
+
+    let rejected = ser::Msg::Rejected(
+        MsgId(1),
+        ser::Error::Rejected("looks like spam".to_string()),
+    );
+    assert_eq!(send(&mut conn, "cheap spam", 1).await, rejected);
+    assert!(matches!(
+        send(&mut conn, "hello", 2).await,
+        ser::Msg::Stored { id: MsgId(2), .. }
+    ));
+    assert_eq!(conn.recv().await.unwrap(), ser::Msg::Ack(MsgId(2)));
+    // The verdict is cached, the service is not asked again.
+    assert_eq!(send(&mut conn, "cheap spam", 1).await, rejected);
+    assert_eq!(reviews.load(Ordering::Relaxed), 2);
+
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_moderation_fails_closed() {
+    let server = Server::build((HOST_DEFAULT, 0)).await.unwrap();
+    // Nothing listens there.
+    let moderator = HttpModerator::new("http://127.0.0.1:9/review");
+    let policy = ModerationPolicy {
+        on_failure: moderation::FailurePolicy::Closed,
+        ..Default::default()
+    };
+    let server = TestServer::spawn(server.moderator(moderator, policy));
+
+    let (_, mut conn) = signed_up(server.addr(), "unmoderated").await;
+    assert_eq!(
+        send(&mut conn, "hello", 1).await,
+        ser::Msg::Rejected(
+            MsgId(1),
+            ser::Error::Rejected("the text could not be moderated".to_string())
+        )
+    );
+
+    server.shutdown().await.unwrap();
+}
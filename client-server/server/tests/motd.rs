@@ -0,0 +1,46 @@
+use cli_ser::{cli, conn::Connection, ser};
+
+use server::{
+    testing::{credentials, signed_up},
+    *,
+};
+
+#[tokio::test]
+async fn test_motd() {
+    let admin = credentials("motd_admin");
+    let url = std::env::var("DATABASE_URL").unwrap();
+    init_database(&url, &DatabaseOptions::default(), admin.clone())
+        .await
+        .unwrap();
+    let server = TestServer::spawn(
+        Server::build((HOST_DEFAULT, 0))
+            .await
+            .unwrap()
+            .motd("Welcome!"),
+    );
+    let address = server.addr();
+
+    let (_, mut user) = signed_up(address, "motd_user").await;
+    assert_eq!(
+        user.recv().await.unwrap(),
+        ser::Msg::ServerInfo("Welcome!".to_string())
+    );
+
+    let denied = cli::Msg::Admin(cli::Admin::SetMotd("Mine now!".to_string()));
+    user.send_msg(denied).await.unwrap();
+    assert_eq!(user.recv().await.unwrap(), ser::Error::NotAdmin.into());
+
+    let mut admin = Connection::connect(address, admin).await.unwrap();
+    admin.recv().await.unwrap();
+    let changed = cli::Msg::Admin(cli::Admin::SetMotd("Maintenance at noon.".to_string()));
+    admin.send_msg(changed).await.unwrap();
+    let announcement = ser::Msg::ServerInfo("Maintenance at noon.".to_string());
+    assert_eq!(user.recv().await.unwrap(), announcement);
+    assert_eq!(admin.recv().await.unwrap(), announcement);
+
+    let (_, mut late) = signed_up(address, "motd_late").await;
+    assert_eq!(late.recv().await.unwrap(), announcement);
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
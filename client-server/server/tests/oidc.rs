@@ -0,0 +1,155 @@
+use cli_ser::{
+    challenge,
+    cli::{self, Credentials},
+    conn::Connection,
+    ser, Messageable,
+};
+use tokio::net::TcpStream;
+
+use server::{
+    testing::{authentication_error, unique, TestIssuer, PASSWORD},
+    *,
+};
+
+#[tokio::test]
+async fn test_token_log_in() {
+    let issuer = TestIssuer::new();
+    let server = TestServer::spawn(
+        Server::build((HOST_DEFAULT, 0))
+            .await
+            .unwrap()
+            .oidc(issuer.oidc()),
+    );
+
+    // The subject logs in as the same user each time, even when their preferred name changes.
+    let (subject, name) = (unique("subject"), unique("oidc_user"));
+    Connection::connect_with_token(server.addr(), issuer.token(&subject, &name))
+        .await
+        .unwrap();
+    Connection::connect_with_token(server.addr(), issuer.token(&subject, "renamed"))
+        .await
+        .unwrap();
+    let creds = Credentials {
+        user: name.clone().into(),
+        password: PASSWORD.to_string(),
+    };
+    let login = Connection::connect(server.addr(), creds).await;
+    assert_eq!(authentication_error(login), ser::Error::InvalidCredentials);
+
+    // Another subject can not take the name of a registered user.
+    let taken =
+        Connection::connect_with_token(server.addr(), issuer.token(&unique("subject"), &name))
+            .await;
+    assert_eq!(authentication_error(taken), ser::Error::UsernameTaken);
+
+    let forged = format!("{}x", issuer.token(&subject, &name));
+    let forged = Connection::connect_with_token(server.addr(), forged).await;
+    assert!(matches!(
+        authentication_error(forged),
+        ser::Error::InvalidToken(_)
+    ));
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_token_log_in_invite_only() {
+    let issuer = TestIssuer::new();
+    let known = unique("subject");
+    let open = TestServer::spawn(
+        Server::build((HOST_DEFAULT, 0))
+            .await
+            .unwrap()
+            .oidc(issuer.oidc()),
+    );
+    Connection::connect_with_token(open.addr(), issuer.token(&known, &unique("oidc_user")))
+        .await
+        .unwrap();
+    open.shutdown().await.unwrap();
+
+    let server = Server::build((HOST_DEFAULT, 0))
+        .await
+        .unwrap()
+        .oidc(issuer.oidc())
+        .invite_only();
+    let server = TestServer::spawn(server);
+    // Subjects who logged in before keep logging in, new ones do not get an account.
+    Connection::connect_with_token(server.addr(), issuer.token(&known, "renamed"))
+        .await
+        .unwrap();
+    let (subject, name) = (unique("subject"), unique("oidc_user"));
+    for _ in 0..2 {
+        let refused =
+            Connection::connect_with_token(server.addr(), issuer.token(&subject, &name)).await;
+        assert_eq!(authentication_error(refused), ser::Error::InviteRequired);
+    }
+    let creds = Credentials {
+        user: name.into(),
+        password: PASSWORD.to_string(),
+    };
+    let login = Connection::connect(server.addr(), creds).await;
+    assert_eq!(authentication_error(login), ser::Error::InvalidCredentials);
+
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_token_log_in_challenge() {
+    let issuer = TestIssuer::new();
+    let server = Server::build((HOST_DEFAULT, 0))
+        .await
+        .unwrap()
+        .oidc(issuer.oidc())
+        .signup_challenge(8);
+    let server = TestServer::spawn(server);
+
+    // The first log-in creates the user, it is a sign-up asked to solve the challenge.
+    let (subject, name) = (unique("subject"), unique("oidc_user"));
+    let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+    let msg = cli::Msg::Auth(cli::Auth::OidcToken(issuer.token(&subject, &name)));
+    msg.send(&mut stream).await.unwrap();
+    let prefix = match ser::Msg::receive(&mut stream).await.unwrap() {
+        ser::Msg::Challenge {
+            prefix,
+            difficulty: 8,
+        } => prefix,
+        other => panic!("expected a challenge, got {other}"),
+    };
+    let wrong = (0..)
+        .find(|&nonce| !challenge::verify(&prefix, 8, nonce))
+        .unwrap();
+    let msg = cli::Msg::Auth(cli::Auth::Solution(wrong));
+    msg.send(&mut stream).await.unwrap();
+    assert_eq!(
+        ser::Msg::receive(&mut stream).await.unwrap(),
+        ser::Msg::Error(ser::Error::ChallengeFailed)
+    );
+
+    Connection::connect_with_token(server.addr(), issuer.token(&subject, &name))
+        .await
+        .unwrap();
+    // Later log-ins are not asked.
+    let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+    let msg = cli::Msg::Auth(cli::Auth::OidcToken(issuer.token(&subject, &name)));
+    msg.send(&mut stream).await.unwrap();
+    assert_eq!(
+        ser::Msg::receive(&mut stream).await.unwrap(),
+        ser::Msg::Authenticated
+    );
+
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_token_log_in_disabled() {
+    let server = TestServer::spawn(Server::build((HOST_DEFAULT, 0)).await.unwrap());
+    let refused =
+        Connection::connect_with_token(server.addr(), TestIssuer::new().token("subject", "nobody"))
+            .await;
+    assert!(matches!(
+        authentication_error(refused),
+        ser::Error::InvalidToken(_)
+    ));
+    server.shutdown().await.unwrap();
+}
@@ -0,0 +1,66 @@
+use cli_ser::{cli, conn::Connection, ser, Data, MsgId};
+
+use server::{testing::signed_up, *};
+
+/// Messages sent by each sender.
+const COUNT: usize = 100;
+
+/// Sends `COUNT` numbered texts back to back without waiting for anything in between.
+async fn send_all(mut conn: Connection, sender: usize) -> Connection {
+    for seq in 0..COUNT {
+        conn.send(Data::Text(format!("{sender}:{seq}")))
+            .await
+            .unwrap();
+    }
+    conn
+}
+
+#[tokio::test]
+async fn test_order_per_sender() {
+    let server = TestServer::start().await.unwrap();
+    let address = server.addr();
+
+    let (creds, mut receiver) = signed_up(address, "ordering_0").await;
+    let mut users = vec![creds.user];
+    // Answered by the router, the receiver is registered by then.
+    receiver
+        .send_msg(cli::Msg::GetProfile(users[0].clone()).tagged(MsgId(1)))
+        .await
+        .unwrap();
+    assert!(matches!(
+        receiver.recv().await.unwrap(),
+        ser::Msg::Profile { .. }
+    ));
+    assert_eq!(receiver.recv().await.unwrap(), ser::Msg::Ack(MsgId(1)));
+
+    let mut senders = Vec::new();
+    for sender in 1..3 {
+        let (creds, conn) = signed_up(address, &format!("ordering_{sender}")).await;
+        users.push(creds.user);
+        senders.push(tokio::spawn(send_all(conn, sender)));
+    }
+
+    let mut next = [0; 3];
+    for _ in 0..2 * COUNT {
+        let ser::Msg::DataFrom {
+            data: Data::Text(text),
+            from,
+            ..
+        } = receiver.recv().await.unwrap()
+        else {
+            panic!("only texts are sent");
+        };
+        let (sender, seq) = text.split_once(':').unwrap();
+        let (sender, seq): (usize, usize) = (sender.parse().unwrap(), seq.parse().unwrap());
+        assert_eq!(from, users[sender]);
+        assert_eq!(seq, next[sender], "messages of {sender} reordered");
+        next[sender] += 1;
+    }
+    assert_eq!(next, [0, COUNT, COUNT]);
+
+    for sender in senders {
+        sender.await.unwrap();
+    }
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
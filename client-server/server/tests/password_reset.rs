@@ -0,0 +1,174 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use cli_ser::{
+    cli::{self, Credentials},
+    conn::Connection,
+    ser, Messageable, MsgId, User,
+};
+use tokio::{net::TcpStream, time::timeout};
+
+use server::{
+    testing::{signed_up, unique},
+    *,
+};
+
+/// Keeps the sent codes instead of mailing them.
+#[derive(Default, Clone)]
+struct Outbox(Arc<Mutex<Vec<(String, User, String)>>>);
+impl Mailer for Outbox {
+    fn send_reset_code(&self, email: &str, user: &User, code: &str) -> anyhow::Result<()> {
+        let sent = (email.to_string(), user.clone(), code.to_string());
+        self.0.lock().unwrap().push(sent);
+        Ok(())
+    }
+}
+
+async fn exchange(stream: &mut TcpStream, msg: cli::Msg, id: u64) -> ser::Msg {
+    msg.tagged(MsgId(id)).send(stream).await.unwrap();
+    ser::Msg::receive(stream).await.unwrap()
+}
+
+#[tokio::test]
+async fn test_password_reset() {
+    let outbox = Outbox::default();
+    let server = Server::build((HOST_DEFAULT, 0)).await.unwrap();
+    let server = TestServer::spawn(server.mailer(outbox.clone()));
+
+    let (creds, mut user) = signed_up(server.addr(), "reset").await;
+    let email = format!("{}@example.com", creds.user);
+    let change = cli::ProfileChange::Email(email.clone());
+    user.send_msg(cli::Msg::SetProfile(change).tagged(MsgId(1)))
+        .await
+        .unwrap();
+    assert_eq!(user.recv().await.unwrap(), ser::Msg::Ack(MsgId(1)));
+
+    // Unknown addresses get the same answer, nothing is sent.
+    // Resetting is not authenticated, the exchange is by hand.
+    let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+    let unknown = cli::Auth::RequestReset(format!("{}@example.com", unique("nobody")));
+    let answer = exchange(&mut stream, cli::Msg::Auth(unknown), 1).await;
+    assert!(matches!(answer, ser::Msg::ServerInfo(_)));
+    assert_eq!(
+        ser::Msg::receive(&mut stream).await.unwrap(),
+        ser::Msg::Ack(MsgId(1))
+    );
+    assert!(outbox.0.lock().unwrap().is_empty());
+
+    let request = cli::Auth::RequestReset(email.to_uppercase());
+    assert_eq!(
+        exchange(&mut stream, cli::Msg::Auth(request), 2).await,
+        answer
+    );
+    assert_eq!(
+        ser::Msg::receive(&mut stream).await.unwrap(),
+        ser::Msg::Ack(MsgId(2))
+    );
+    let (to, of, code) = outbox.0.lock().unwrap().pop().unwrap();
+    assert_eq!((to, of), (email.to_uppercase(), creds.user.clone()));
+
+    let reset = |code: &str| {
+        cli::Msg::Auth(cli::Auth::ResetPassword {
+            code: code.to_string(),
+            new_password: "new_reset_pass".to_string(),
+        })
+    };
+    assert_eq!(
+        exchange(&mut stream, reset("0123456789abcdef"), 3).await,
+        ser::Msg::Rejected(MsgId(3), ser::Error::InvalidResetCode)
+    );
+    assert_eq!(
+        exchange(&mut stream, reset(&code), 4).await,
+        ser::Msg::Authenticated
+    );
+
+    // The code is used up, only the new password works.
+    let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+    assert_eq!(
+        exchange(&mut stream, reset(&code), 1).await,
+        ser::Msg::Rejected(MsgId(1), ser::Error::InvalidResetCode)
+    );
+    assert!(Connection::connect(server.addr(), creds.clone())
+        .await
+        .is_err());
+    let creds = Credentials {
+        password: "new_reset_pass".to_string(),
+        ..creds
+    };
+    Connection::connect(server.addr(), creds).await.unwrap();
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_password_reset_while_logged_in() {
+    let outbox = Outbox::default();
+    let server = Server::build((HOST_DEFAULT, 0)).await.unwrap();
+    let server = server
+        .mailer(outbox.clone())
+        .sessions_of_user(SessionPolicy::RejectNew);
+    let server = TestServer::spawn(server);
+
+    let (creds, mut user) = signed_up(server.addr(), "reset_active").await;
+    let email = format!("{}@example.com", creds.user);
+    user.send_msg(cli::Msg::SetProfile(cli::ProfileChange::Email(email.clone())).tagged(MsgId(1)))
+        .await
+        .unwrap();
+    assert_eq!(user.recv().await.unwrap(), ser::Msg::Ack(MsgId(1)));
+    let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+    let request = cli::Msg::Auth(cli::Auth::RequestReset(email));
+    assert!(matches!(
+        exchange(&mut stream, request, 1).await,
+        ser::Msg::ServerInfo(_)
+    ));
+    assert_eq!(
+        ser::Msg::receive(&mut stream).await.unwrap(),
+        ser::Msg::Ack(MsgId(1))
+    );
+    let (_, _, code) = outbox.0.lock().unwrap().pop().unwrap();
+
+    // The user is logged in elsewhere, the reset is refused before it changes anything.
+    let reset = cli::Msg::Auth(cli::Auth::ResetPassword {
+        code,
+        new_password: "new_reset_pass".to_string(),
+    });
+    assert_eq!(
+        exchange(&mut stream, reset.clone(), 2).await,
+        ser::Msg::Rejected(MsgId(2), ser::Error::SessionActive)
+    );
+
+    // Once the session ends, the old password still works and so does the code.
+    drop(user);
+    let deadline = Duration::from_secs(10);
+    let conn = timeout(deadline, async {
+        loop {
+            match Connection::connect(server.addr(), creds.clone()).await {
+                Err(cli_ser::Error::Authentication(ser::Error::SessionActive)) => {
+                    tokio::time::sleep(Duration::from_millis(20)).await
+                }
+                connected => break connected.unwrap(),
+            }
+        }
+    })
+    .await
+    .expect("the session did not end");
+    drop(conn);
+    timeout(deadline, async {
+        for id in 3.. {
+            match exchange(&mut stream, reset.clone(), id).await {
+                ser::Msg::Rejected(_, ser::Error::SessionActive) => {
+                    tokio::time::sleep(Duration::from_millis(20)).await
+                }
+                answer => return assert_eq!(answer, ser::Msg::Authenticated),
+            }
+        }
+    })
+    .await
+    .expect("the session did not end");
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
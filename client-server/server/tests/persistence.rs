@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+use cli_ser::{cli, conn::Connection, ser, Data, MsgId};
+
+use server::{testing::signed_up, *};
+
+async fn send(conn: &mut Connection, text: &str, id: u64) -> i64 {
+    let msg = cli::Msg::ToAll(Data::Text(text.to_string())).tagged(MsgId(id));
+    conn.send_msg(msg).await.unwrap();
+    let ser::Msg::Stored { msg_id, .. } = conn.recv().await.unwrap() else {
+        panic!("the stored id should come first");
+    };
+    assert_eq!(conn.recv().await.unwrap(), ser::Msg::Ack(MsgId(id)));
+    msg_id
+}
+
+async fn stored(pool: &sqlx::PgPool, ids: &[i64]) -> i64 {
+    let (count,): (i64,) = sqlx::query_as("SELECT count(*) FROM messages WHERE id = ANY($1)")
+        .bind(ids)
+        .fetch_one(pool)
+        .await
+        .unwrap();
+    count
+}
+
+#[tokio::test]
+async fn test_write_behind() {
+    let url = std::env::var("DATABASE_URL").unwrap();
+    let options = DatabaseOptions {
+        batch_size: 3,
+        flush_interval: Duration::from_secs(3600),
+        ..Default::default()
+    };
+    let server = TestServer::spawn(
+        Server::build_with_database_options((HOST_DEFAULT, 0), &url, &options)
+            .await
+            .unwrap(),
+    );
+    let address = server.addr();
+
+    let (_, mut conn) = signed_up(address, "persist").await;
+    let pool = sqlx::PgPool::connect(&url).await.unwrap();
+
+    let mut ids = vec![];
+    for i in 1..=2 {
+        ids.push(send(&mut conn, "buffered", i).await);
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(stored(&pool, &ids).await, 0, "not a whole batch yet");
+
+    ids.push(send(&mut conn, "buffered", 3).await);
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(stored(&pool, &ids).await, 3, "a whole batch is stored");
+
+    let last = send(&mut conn, "flushed", 4).await;
+    conn.send_msg(cli::Msg::ReadStatus { msg_id: last })
+        .await
+        .unwrap();
+    assert_eq!(
+        conn.recv().await.unwrap(),
+        ser::Msg::ReadBy {
+            msg_id: last,
+            users: vec![]
+        }
+    );
+    assert_eq!(stored(&pool, &[last]).await, 1, "flushed before lookup");
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
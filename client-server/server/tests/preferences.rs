@@ -0,0 +1,68 @@
+use cli_ser::{cli, conn::Connection, ser, MsgId, Preferences};
+
+use server::{
+    testing::{signed_up, unique},
+    *,
+};
+
+#[tokio::test]
+async fn test_preferences_follow_the_account() {
+    let server = Server::build((HOST_DEFAULT, 0)).await.unwrap();
+    let server = TestServer::spawn(server.allow_guests());
+    let address = server.addr();
+
+    let (creds, mut laptop) = signed_up(address, "preferences_owner").await;
+    laptop.send_msg(cli::Msg::GetPrefs).await.unwrap();
+    assert_eq!(
+        laptop.recv().await.unwrap(),
+        ser::Msg::Prefs(Preferences::default())
+    );
+
+    let prefs = Preferences {
+        muted: vec!["spammer".into(), "bot".into()],
+        patterns: vec!["lunch".to_string()],
+        mentions_only: true,
+        history_length: Some(50),
+    };
+    let set = cli::Msg::SetPrefs(prefs.clone()).tagged(MsgId(1));
+    laptop.send_msg(set).await.unwrap();
+    assert_eq!(laptop.recv().await.unwrap(), ser::Msg::Ack(MsgId(1)));
+
+    // Another device of the user gets them, the latest ones replace the earlier.
+    let mut phone = Connection::connect(address, creds.clone()).await.unwrap();
+    phone.send_msg(cli::Msg::GetPrefs).await.unwrap();
+    assert_eq!(phone.recv().await.unwrap(), ser::Msg::Prefs(prefs.clone()));
+    let prefs = Preferences {
+        muted: vec![],
+        ..prefs
+    };
+    let set = cli::Msg::SetPrefs(prefs.clone()).tagged(MsgId(2));
+    phone.send_msg(set).await.unwrap();
+    assert_eq!(phone.recv().await.unwrap(), ser::Msg::Ack(MsgId(2)));
+    phone.send_msg(cli::Msg::GetPrefs).await.unwrap();
+    assert_eq!(phone.recv().await.unwrap(), ser::Msg::Prefs(prefs));
+
+    // Others keep their own.
+    let (_, mut other) = signed_up(address, "preferences_other").await;
+    other.send_msg(cli::Msg::GetPrefs).await.unwrap();
+    assert_eq!(
+        other.recv().await.unwrap(),
+        ser::Msg::Prefs(Preferences::default())
+    );
+
+    // Guests get the defaults and can not store any.
+    let mut guest = Connection::guest(address, unique("preferences_guest"))
+        .await
+        .unwrap();
+    let set = cli::Msg::SetPrefs(Preferences::default()).tagged(MsgId(3));
+    guest.send_msg(set).await.unwrap();
+    assert!(matches!(
+        guest.recv().await.unwrap(),
+        ser::Msg::Rejected(MsgId(3), ser::Error::Rejected(_))
+    ));
+    guest.send_msg(cli::Msg::GetPrefs).await.unwrap();
+    assert_eq!(
+        guest.recv().await.unwrap(),
+        ser::Msg::Prefs(Preferences::default())
+    );
+}
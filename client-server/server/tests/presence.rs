@@ -0,0 +1,61 @@
+use cli_ser::{cli, ser, Data, MsgId, Presence};
+
+use server::{testing::signed_up, *};
+
+#[tokio::test]
+async fn test_presence() {
+    let server = TestServer::start().await.unwrap();
+    let address = server.addr();
+
+    let (creds, mut away) = signed_up(address, "presence_away").await;
+    let (_, mut other) = signed_up(address, "presence_other").await;
+
+    let set = cli::Msg::SetPresence {
+        presence: Presence::Away,
+        message: Some("out for lunch".to_string()),
+    };
+    away.send_msg(set.tagged(MsgId(1))).await.unwrap();
+    assert_eq!(away.recv().await.unwrap(), ser::Msg::Ack(MsgId(1)));
+    let changed = ser::Msg::PresenceChanged {
+        user: creds.user.clone(),
+        presence: Presence::Away,
+        message: Some("out for lunch".to_string()),
+    };
+    assert_eq!(other.recv().await.unwrap(), changed);
+
+    // Clients connecting later learn who is not online.
+    let (_, mut late) = signed_up(address, "presence_late").await;
+    assert_eq!(late.recv().await.unwrap(), changed);
+
+    // A mention is answered by the message left.
+    let text = format!("@{} are you there?", creds.user);
+    other.send(Data::Text(text)).await.unwrap();
+    assert_eq!(other.recv().await.unwrap(), changed);
+    assert!(matches!(
+        away.recv().await.unwrap(),
+        ser::Msg::DataFrom { .. }
+    ));
+
+    let back = cli::Msg::SetPresence {
+        presence: Presence::Online,
+        message: None,
+    };
+    away.send_msg(back).await.unwrap();
+    assert!(matches!(
+        other.recv().await.unwrap(),
+        ser::Msg::PresenceChanged {
+            presence: Presence::Online,
+            ..
+        }
+    ));
+    let text = format!("@{} welcome back", creds.user);
+    other.send(Data::Text(text)).await.unwrap();
+    // Only the broadcast comes, no auto-reply.
+    assert!(matches!(
+        away.recv().await.unwrap(),
+        ser::Msg::DataFrom { .. }
+    ));
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use cli_ser::{cli, ser, Data, Image, UserProfile};
+
+use server::{testing::signed_up, *};
+
+#[tokio::test]
+async fn test_profiles() {
+    let server = TestServer::start().await.unwrap();
+    let address = server.addr();
+
+    let (creds, mut owner) = signed_up(address, "profiles_owner").await;
+    let (_, mut other) = signed_up(address, "profiles_other").await;
+
+    let get = cli::Msg::GetProfile(creds.user.clone());
+    other.send_msg(get.clone()).await.unwrap();
+    assert_eq!(
+        other.recv().await.unwrap(),
+        ser::Msg::Profile {
+            user: creds.user.clone(),
+            profile: UserProfile::default()
+        }
+    );
+
+    let avatar = Image::from_path("../example-images/hexagon.jpeg")
+        .await
+        .unwrap();
+    for change in [
+        cli::ProfileChange::DisplayName("Owner".to_string()),
+        cli::ProfileChange::Status("busy".to_string()),
+        cli::ProfileChange::Avatar(avatar.clone()),
+    ] {
+        owner.send_msg(cli::Msg::SetProfile(change)).await.unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    other.send_msg(get).await.unwrap();
+    assert_eq!(
+        other.recv().await.unwrap(),
+        ser::Msg::Profile {
+            user: creds.user.clone(),
+            profile: UserProfile {
+                display_name: Some("Owner".to_string()),
+                status: Some("busy".to_string()),
+                avatar: Some(avatar),
+            }
+        }
+    );
+
+    owner.send(Data::Text("hello".to_string())).await.unwrap();
+    match other.recv().await.unwrap() {
+        ser::Msg::DataFrom {
+            from, display_name, ..
+        } => {
+            assert_eq!(from, creds.user);
+            assert_eq!(display_name, Some("Owner".to_string()));
+        }
+        other => panic!("{other:?}"),
+    }
+
+    let nobody: cli_ser::User = "profiles_nobody_at_all".to_string().into();
+    other
+        .send_msg(cli::Msg::GetProfile(nobody.clone()))
+        .await
+        .unwrap();
+    assert_eq!(
+        other.recv().await.unwrap(),
+        ser::Error::UnknownUser(nobody).into()
+    );
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
@@ -0,0 +1,98 @@
+use cli_ser::{cli, conn::Connection, ser, Data, File, MsgId};
+
+use server::{
+    testing::{credentials, signed_up},
+    *,
+};
+
+#[tokio::test]
+async fn test_storage_quotas() {
+    let admin = credentials("quotas_admin");
+    let url = std::env::var("DATABASE_URL").unwrap();
+    init_database(&url, &DatabaseOptions::default(), admin.clone())
+        .await
+        .unwrap();
+    let server = TestServer::spawn(
+        Server::build((HOST_DEFAULT, 0))
+            .await
+            .unwrap()
+            .storage_quota(1000),
+    );
+    let path = std::env::temp_dir().join(format!("quotas_{}.bin", std::process::id()));
+    tokio::fs::write(&path, [7u8; 600]).await.unwrap();
+    let file: Data = File::from_path(&path).await.unwrap().into();
+
+    let (creds, mut conn) = signed_up(server.addr(), "quotas_user").await;
+    let user = creds.user;
+    conn.send_msg(cli::Msg::ToAll(file.clone()).tagged(MsgId(1)))
+        .await
+        .unwrap();
+    assert!(matches!(
+        conn.recv().await.unwrap(),
+        ser::Msg::Stored { id: MsgId(1), .. }
+    ));
+    assert_eq!(conn.recv().await.unwrap(), ser::Msg::Ack(MsgId(1)));
+
+    conn.send_msg(cli::Msg::ToAll(file.clone()).tagged(MsgId(2)))
+        .await
+        .unwrap();
+    assert_eq!(
+        conn.recv().await.unwrap(),
+        ser::Msg::Rejected(
+            MsgId(2),
+            ser::Error::QuotaExceeded {
+                used: 600,
+                limit: 1000
+            }
+        )
+    );
+    // Texts do not count.
+    conn.send_msg(cli::Msg::ToAll(Data::Text("still here".to_string())).tagged(MsgId(3)))
+        .await
+        .unwrap();
+    assert!(matches!(
+        conn.recv().await.unwrap(),
+        ser::Msg::Stored { id: MsgId(3), .. }
+    ));
+    assert_eq!(conn.recv().await.unwrap(), ser::Msg::Ack(MsgId(3)));
+
+    conn.send_msg(
+        cli::Msg::Admin(cli::Admin::SetQuota {
+            user: user.clone(),
+            bytes: Some(2000),
+        })
+        .tagged(MsgId(4)),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        conn.recv().await.unwrap(),
+        ser::Msg::Rejected(MsgId(4), ser::Error::NotAdmin)
+    );
+
+    let mut admin = Connection::connect(server.addr(), admin).await.unwrap();
+    admin
+        .send_msg(
+            cli::Msg::Admin(cli::Admin::SetQuota {
+                user,
+                bytes: Some(2000),
+            })
+            .tagged(MsgId(1)),
+        )
+        .await
+        .unwrap();
+    assert_eq!(admin.recv().await.unwrap(), ser::Msg::Ack(MsgId(1)));
+
+    conn.send_msg(cli::Msg::ToAll(file).tagged(MsgId(5)))
+        .await
+        .unwrap();
+    assert!(matches!(
+        conn.recv().await.unwrap(),
+        ser::Msg::Stored { id: MsgId(5), .. }
+    ));
+    assert_eq!(conn.recv().await.unwrap(), ser::Msg::Ack(MsgId(5)));
+
+    tokio::fs::remove_file(&path).await.unwrap();
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
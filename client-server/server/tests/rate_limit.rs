@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+use cli_ser::{cli, ser, Data, MsgId};
+
+use server::{testing::signed_up, *};
+
+#[tokio::test]
+async fn test_rate_limit() {
+    let server = TestServer::spawn(Server::build((HOST_DEFAULT, 0)).await.unwrap().rate_limit(
+        RateLimit {
+            max: 2,
+            period: Duration::from_secs(600),
+        },
+    ));
+    let (_, mut sender) = signed_up(server.addr(), "rate_sender").await;
+    let (_, mut other) = signed_up(server.addr(), "rate_other").await;
+
+    for id in 1..=3 {
+        let text = cli::Msg::ToAll(Data::Text(format!("text {id}")));
+        sender.send_msg(text.tagged(MsgId(id))).await.unwrap();
+    }
+    let mut stored = vec![];
+    let rejected = loop {
+        match sender.recv().await.unwrap() {
+            ser::Msg::Stored { id, .. } => stored.push(id),
+            ser::Msg::Rejected(id, ser::Error::Rejected(reason)) => break (id, reason),
+            _ => {}
+        }
+    };
+    assert_eq!(stored, [MsgId(1), MsgId(2)]);
+    assert_eq!(rejected.0, MsgId(3));
+    assert!(rejected.1.contains("messages"), "{}", rejected.1);
+
+    // Each user is limited on their own.
+    let text = cli::Msg::ToAll(Data::Text("still mine".to_string()));
+    other.send_msg(text.tagged(MsgId(1))).await.unwrap();
+    loop {
+        match other.recv().await.unwrap() {
+            ser::Msg::Stored { id, .. } => break assert_eq!(id, MsgId(1)),
+            ser::Msg::Rejected(_, e) => panic!("rejected {e:?}"),
+            _ => {}
+        }
+    }
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use cli_ser::{cli, ser, Data, MsgId};
+
+use server::{testing::signed_up, *};
+
+#[tokio::test]
+async fn test_read_receipts() {
+    let server = TestServer::start().await.unwrap();
+    let address = server.addr();
+
+    let (_, mut sender) = signed_up(address, "receipts_sender").await;
+    let (reader_creds, mut reader) = signed_up(address, "receipts_reader").await;
+
+    let text = cli::Msg::ToAll(Data::Text("read me".to_string()));
+    sender.send_msg(text.tagged(MsgId(1))).await.unwrap();
+    let ser::Msg::Stored { id, msg_id } = sender.recv().await.unwrap() else {
+        panic!("the stored id should come first");
+    };
+    assert_eq!(id, MsgId(1));
+    assert_eq!(sender.recv().await.unwrap(), ser::Msg::Ack(MsgId(1)));
+    match reader.recv().await.unwrap() {
+        ser::Msg::DataFrom { msg_id: got, .. } => assert_eq!(got, Some(msg_id)),
+        other => panic!("{other:?}"),
+    }
+
+    let status = cli::Msg::ReadStatus { msg_id };
+    sender.send_msg(status.clone()).await.unwrap();
+    assert_eq!(
+        sender.recv().await.unwrap(),
+        ser::Msg::ReadBy {
+            msg_id,
+            users: vec![]
+        }
+    );
+
+    for _ in 0..2 {
+        reader
+            .send_msg(cli::Msg::MarkRead { msg_id })
+            .await
+            .unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    sender.send_msg(status.clone()).await.unwrap();
+    assert_eq!(
+        sender.recv().await.unwrap(),
+        ser::Msg::ReadBy {
+            msg_id,
+            users: vec![reader_creds.user]
+        }
+    );
+
+    reader.send_msg(status).await.unwrap();
+    assert_eq!(
+        reader.recv().await.unwrap(),
+        ser::Error::UnknownMessage(msg_id).into()
+    );
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
@@ -0,0 +1,62 @@
+use std::{
+    process,
+    sync::{Arc, Mutex},
+};
+
+use cli_ser::{cli, ser, Data, MsgId};
+
+use server::{testing::signed_up, *};
+
+/// Settings as a configuration file would give them, the message of the day and banned words.
+fn settings(config: &Mutex<(String, Vec<String>)>) -> Reloadable {
+    let (motd, banned) = config.lock().unwrap().clone();
+    Reloadable {
+        motd: Some(motd),
+        filters: vec![Box::new(filter::BannedWords::new(banned))],
+        ..Default::default()
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_reload_on_sighup() {
+    let config = Arc::new(Mutex::new(("Before".to_string(), vec!["spam".to_string()])));
+    let loaded = config.clone();
+    let server = TestServer::spawn(
+        Server::build((HOST_DEFAULT, 0))
+            .await
+            .unwrap()
+            .reload_with(move || Ok(settings(&loaded))),
+    );
+    let (_, mut conn) = signed_up(server.addr(), "reload_user").await;
+    assert_eq!(
+        conn.recv().await.unwrap(),
+        ser::Msg::ServerInfo("Before".to_string())
+    );
+    let spam = cli::Msg::ToAll(Data::Text("spam".to_string()));
+    conn.send_msg(spam.clone().tagged(MsgId(1))).await.unwrap();
+    assert!(matches!(
+        conn.recv().await.unwrap(),
+        ser::Msg::Rejected(MsgId(1), ser::Error::Rejected(_))
+    ));
+
+    *config.lock().unwrap() = ("After".to_string(), vec![]);
+    let hangup = process::Command::new("kill")
+        .args(["-HUP", &process::id().to_string()])
+        .status()
+        .unwrap();
+    assert!(hangup.success());
+    // The connection stays, the new message of the day is announced to it.
+    assert_eq!(
+        conn.recv().await.unwrap(),
+        ser::Msg::ServerInfo("After".to_string())
+    );
+    conn.send_msg(spam.tagged(MsgId(2))).await.unwrap();
+    assert!(matches!(
+        conn.recv().await.unwrap(),
+        ser::Msg::Stored { id: MsgId(2), .. }
+    ));
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
@@ -0,0 +1,59 @@
+use std::time::Duration;
+
+use cli_ser::{cli, ser, Data, File, MsgId};
+
+use server::{
+    testing::{signed_up, unique},
+    *,
+};
+
+#[tokio::test]
+async fn test_retention() {
+    let server = TestServer::start().await.unwrap();
+    let (_, mut conn) = signed_up(server.addr(), "retention").await;
+    for (id, data) in [
+        Data::Text(unique("retained")),
+        File::from_path("Cargo.toml").await.unwrap().into(),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let id = MsgId(id as u64 + 1);
+        conn.send_msg(cli::Msg::ToAll(data).tagged(id))
+            .await
+            .unwrap();
+        assert!(matches!(
+            conn.recv().await.unwrap(),
+            ser::Msg::Stored { .. }
+        ));
+        assert_eq!(conn.recv().await.unwrap(), ser::Msg::Ack(id));
+    }
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let url = std::env::var("DATABASE_URL").unwrap();
+    let options = DatabaseOptions::default();
+    let everything = RetentionPolicy {
+        max_messages: Some(0),
+        dry_run: true,
+        ..Default::default()
+    };
+    let first = purge(&url, &options, &everything).await.unwrap();
+    assert!(first.messages >= 2);
+    assert!(first.attachments >= 1);
+    assert_eq!(first.blobs, 0);
+    // Nothing was deleted.
+    let second = purge(&url, &options, &everything).await.unwrap();
+    assert!(second.messages >= first.messages);
+
+    // Messages of a day ago are gone, the new ones stay.
+    let old = RetentionPolicy {
+        max_age: Some(Duration::from_secs(24 * 60 * 60)),
+        ..Default::default()
+    };
+    purge(&url, &options, &old).await.unwrap();
+    let after = purge(&url, &options, &everything).await.unwrap();
+    assert!(after.messages >= 2);
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
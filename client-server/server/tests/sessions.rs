@@ -0,0 +1,116 @@
+use cli_ser::{
+    cli::{self, Credentials},
+    conn::Connection,
+    ser, Data, Messageable, MsgId,
+};
+use tokio::net::TcpStream;
+
+use server::{
+    testing::{authentication_error, signed_up},
+    *,
+};
+
+async fn server(policy: SessionPolicy) -> TestServer {
+    TestServer::spawn(
+        Server::build((HOST_DEFAULT, 0))
+            .await
+            .unwrap()
+            .sessions_of_user(policy),
+    )
+}
+
+#[tokio::test]
+async fn test_sessions_allowed() {
+    let server = server(SessionPolicy::Allow).await;
+    let (creds, mut first) = signed_up(server.addr(), "sessions_allow").await;
+    // Logged in by hand, so that the address of the session is known.
+    let mut second = TcpStream::connect(server.addr()).await.unwrap();
+    let log_in = cli::Msg::Auth(cli::Auth::LogIn(creds));
+    log_in.send(&mut second).await.unwrap();
+    assert_eq!(
+        ser::Msg::receive(&mut second).await.unwrap(),
+        ser::Msg::Authenticated
+    );
+    match first.recv().await.unwrap() {
+        ser::Msg::NewSession { addr, .. } => assert_eq!(addr, second.local_addr().unwrap()),
+        msg => panic!("expected a new session, got {msg}"),
+    }
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
+
+/// Sends the text from one session of the user, the other one gets it and the sending one only the confirmation.
+async fn echo(from: &mut Connection, to: &mut Connection, creds: &Credentials, text: &str) {
+    from.send_msg(cli::Msg::ToAll(Data::Text(text.to_string())).tagged(MsgId(1)))
+        .await
+        .unwrap();
+    assert!(matches!(
+        from.recv().await.unwrap(),
+        ser::Msg::Stored { id: MsgId(1), .. }
+    ));
+    assert_eq!(from.recv().await.unwrap(), ser::Msg::Ack(MsgId(1)));
+    match to.recv().await.unwrap() {
+        ser::Msg::DataFrom {
+            data: Data::Text(echoed),
+            from,
+            ..
+        } => {
+            assert_eq!(from, creds.user);
+            assert_eq!(echoed, text);
+        }
+        msg => panic!("expected the text of the other session, got {msg}"),
+    }
+}
+
+#[tokio::test]
+async fn test_sessions_echo() {
+    let server = server(SessionPolicy::Allow).await;
+    let (creds, mut first) = signed_up(server.addr(), "sessions_echo").await;
+    let mut second = Connection::connect(server.addr(), creds.clone())
+        .await
+        .unwrap();
+    assert!(matches!(
+        first.recv().await.unwrap(),
+        ser::Msg::NewSession { .. }
+    ));
+    // The second session is registered once it reads messages, so it sends first.
+    echo(&mut second, &mut first, &creds, "from the phone").await;
+    echo(&mut first, &mut second, &creds, "from the laptop").await;
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_sessions_kick_old() {
+    let server = server(SessionPolicy::KickOld).await;
+    let (creds, mut first) = signed_up(server.addr(), "sessions_kick").await;
+    let _second = Connection::connect(server.addr(), creds).await.unwrap();
+    assert!(matches!(
+        first.recv().await.unwrap(),
+        ser::Msg::NewSession { .. }
+    ));
+    assert!(matches!(
+        first.recv().await,
+        Err(cli_ser::Error::DisconnectedStream(_))
+    ));
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_sessions_reject_new() {
+    let server = server(SessionPolicy::RejectNew).await;
+    let (creds, first) = signed_up(server.addr(), "sessions_reject").await;
+    let second = Connection::connect(server.addr(), creds.clone()).await;
+    assert_eq!(authentication_error(second), ser::Error::SessionActive);
+
+    drop(first);
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    Connection::connect(server.addr(), creds).await.unwrap();
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
@@ -0,0 +1,66 @@
+use cli_ser::{
+    challenge,
+    cli::{self, Credentials},
+    conn::Connection,
+    ser, Messageable, MsgId,
+};
+use tokio::net::TcpStream;
+
+use server::{
+    testing::{credentials, signed_up},
+    *,
+};
+
+/// Signs up with the `creds` tagged by the `id`, returns the prefix of the challenge asked for.
+async fn sign_up(stream: &mut TcpStream, creds: Credentials, id: u64) -> String {
+    let msg = cli::Msg::Auth(cli::Auth::SignUp(creds)).tagged(MsgId(id));
+    msg.send(stream).await.unwrap();
+    match ser::Msg::receive(stream).await.unwrap() {
+        ser::Msg::Challenge {
+            prefix,
+            difficulty: 8,
+        } => prefix,
+        other => panic!("expected a challenge, got {other}"),
+    }
+}
+
+async fn solution(stream: &mut TcpStream, nonce: u64) {
+    let msg = cli::Msg::Auth(cli::Auth::Solution(nonce));
+    msg.send(stream).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_signup_challenge() {
+    let server = Server::build((HOST_DEFAULT, 0)).await.unwrap();
+    let server = TestServer::spawn(server.signup_challenge(8));
+
+    // Connections solve it on their own, log-ins are not asked.
+    let (creds, _) = signed_up(server.addr(), "challenge_conn").await;
+    Connection::connect(server.addr(), creds).await.unwrap();
+
+    let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+    let creds = credentials("challenge_raw");
+    let prefix = sign_up(&mut stream, creds.clone(), 1).await;
+    let wrong = (0..)
+        .find(|&nonce| !challenge::verify(&prefix, 8, nonce))
+        .unwrap();
+    solution(&mut stream, wrong).await;
+    assert_eq!(
+        ser::Msg::receive(&mut stream).await.unwrap(),
+        ser::Msg::Rejected(MsgId(1), ser::Error::ChallengeFailed)
+    );
+
+    let prefix = sign_up(&mut stream, creds, 2).await;
+    solution(&mut stream, challenge::solve(&prefix, 8).unwrap()).await;
+    assert_eq!(
+        ser::Msg::receive(&mut stream).await.unwrap(),
+        ser::Msg::Authenticated
+    );
+    assert_eq!(
+        ser::Msg::receive(&mut stream).await.unwrap(),
+        ser::Msg::Ack(MsgId(2))
+    );
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
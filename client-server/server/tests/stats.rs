@@ -0,0 +1,30 @@
+use cli_ser::{cli, ser, Data, MsgId};
+
+use server::{testing::signed_up, *};
+
+#[tokio::test]
+async fn test_stats() {
+    let server = TestServer::start().await.unwrap();
+    let (_, mut user) = signed_up(server.addr(), "stats").await;
+    let text = cli::Msg::ToAll(Data::Text("hello".to_string())).tagged(MsgId(1));
+    user.send_msg(text).await.unwrap();
+    user.send_msg(cli::Msg::Stats.tagged(MsgId(2)))
+        .await
+        .unwrap();
+    let stats = loop {
+        match user.recv().await.unwrap() {
+            ser::Msg::Stats {
+                connected,
+                messages_today,
+                version,
+                ..
+            } => break (connected, messages_today, version),
+            ser::Msg::Rejected(id, e) => panic!("{id} rejected: {e}"),
+            _ => {}
+        }
+    };
+    assert_eq!(stats, (1, 1, env!("CARGO_PKG_VERSION").to_string()));
+    // The broadcast of the text may come in between.
+    while user.recv().await.unwrap() != ser::Msg::Ack(MsgId(2)) {}
+    server.shutdown().await.unwrap();
+}
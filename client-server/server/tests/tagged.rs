@@ -0,0 +1,70 @@
+use cli_ser::{
+    cli::{self, Credentials},
+    ser, Data, Messageable, MsgId,
+};
+use tokio::net::TcpStream;
+
+use server::{testing::credentials, *};
+
+#[tokio::test]
+async fn test_tagged_messages() {
+    let server = TestServer::start().await.unwrap();
+    let address = server.addr();
+
+    // The replies of tagged authentications are under test, the exchange is by hand.
+    let creds = credentials("tagged");
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    let wrong = Credentials {
+        password: "wrong".to_string(),
+        ..creds.clone()
+    };
+    cli::Msg::Auth(cli::Auth::LogIn(wrong))
+        .tagged(MsgId(1))
+        .send(&mut stream)
+        .await
+        .unwrap();
+    assert_eq!(
+        ser::Msg::receive(&mut stream).await.unwrap(),
+        ser::Msg::Rejected(MsgId(1), ser::Error::InvalidCredentials)
+    );
+    cli::Msg::Auth(cli::Auth::SignUp(creds.clone()))
+        .tagged(MsgId(2))
+        .send(&mut stream)
+        .await
+        .unwrap();
+    assert_eq!(
+        ser::Msg::receive(&mut stream).await.unwrap(),
+        ser::Msg::Authenticated
+    );
+    assert_eq!(
+        ser::Msg::receive(&mut stream).await.unwrap(),
+        ser::Msg::Ack(MsgId(2))
+    );
+
+    cli::Msg::ToAll(Data::Text("hi".to_string()))
+        .tagged(MsgId(3))
+        .send(&mut stream)
+        .await
+        .unwrap();
+    assert!(matches!(
+        ser::Msg::receive(&mut stream).await.unwrap(),
+        ser::Msg::Stored { id: MsgId(3), .. }
+    ));
+    assert_eq!(
+        ser::Msg::receive(&mut stream).await.unwrap(),
+        ser::Msg::Ack(MsgId(3))
+    );
+
+    cli::Msg::Auth(cli::Auth::LogIn(creds))
+        .tagged(MsgId(4))
+        .send(&mut stream)
+        .await
+        .unwrap();
+    assert_eq!(
+        ser::Msg::receive(&mut stream).await.unwrap(),
+        ser::Msg::Rejected(MsgId(4), ser::Error::AlreadyAuthenticated)
+    );
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
@@ -0,0 +1,79 @@
+use std::{env, fs, sync::Arc};
+
+use cli_ser::{cli, ser, Data, Messageable, MsgId};
+use tokio::net::TcpStream;
+use tokio_rustls::{
+    rustls::{
+        crypto::ring,
+        pki_types::{pem::PemObject, CertificateDer, ServerName},
+        ClientConfig, RootCertStore,
+    },
+    TlsConnector,
+};
+
+use server::{
+    testing::{credentials, unique},
+    *,
+};
+
+/// Connector trusting only the certificate `init` would write.
+fn connector(provision: &Provision) -> TlsConnector {
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(provision.tls_cert()).unwrap() {
+        roots.add(cert.unwrap()).unwrap();
+    }
+    let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    TlsConnector::from(Arc::new(config))
+}
+
+#[tokio::test]
+async fn test_tls() {
+    let dir = env::temp_dir().join(unique("tls"));
+    fs::create_dir_all(&dir).unwrap();
+    let provision = Provision {
+        config: dir.join("server.toml"),
+        database_url: "postgres://localhost/unused".to_string(),
+        tls_dir: dir.join("tls"),
+        hosts: vec!["localhost".to_string()],
+    };
+    provision.write().unwrap();
+    let server = TestServer::spawn(
+        Server::build((HOST_DEFAULT, 0))
+            .await
+            .unwrap()
+            .tls(provision.tls_cert(), provision.tls_key()),
+    );
+
+    let socket = TcpStream::connect(server.addr()).await.unwrap();
+    let name = ServerName::try_from("localhost").unwrap();
+    let mut stream = connector(&provision).connect(name, socket).await.unwrap();
+    let auth = cli::Msg::Auth(cli::Auth::SignUp(credentials("tls_user")));
+    auth.send(&mut stream).await.unwrap();
+    assert_eq!(
+        ser::Msg::receive(&mut stream).await.unwrap(),
+        ser::Msg::Authenticated
+    );
+    let text = cli::Msg::ToAll(Data::Text("encrypted".to_string())).tagged(MsgId(1));
+    text.send(&mut stream).await.unwrap();
+    loop {
+        match ser::Msg::receive(&mut stream).await.unwrap() {
+            ser::Msg::Stored { id, .. } => break assert_eq!(id, MsgId(1)),
+            ser::Msg::Rejected(_, e) => panic!("rejected {e:?}"),
+            _ => {}
+        }
+    }
+
+    // Clients not speaking TLS get nowhere.
+    let mut plain = TcpStream::connect(server.addr()).await.unwrap();
+    let auth = cli::Msg::Auth(cli::Auth::SignUp(credentials("tls_plain")));
+    auth.send(&mut plain).await.unwrap();
+    assert!(ser::Msg::receive(&mut plain).await.is_err());
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+    fs::remove_dir_all(dir).unwrap();
+}
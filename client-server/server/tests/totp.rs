@@ -0,0 +1,151 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use cli_ser::{cli, conn::Connection, ser, Messageable, MsgId};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use tokio::net::TcpStream;
+
+use server::{
+    testing::{authentication_error, signed_up, unique, TestIssuer},
+    *,
+};
+
+/// Current code of the secret in the `otpauth://` URL, as authenticator apps compute it.
+fn current_code(url: &str) -> String {
+    let secret = url
+        .split(['?', '&'])
+        .find_map(|param| param.strip_prefix("secret="))
+        .unwrap();
+    let secret = data_encoding::BASE32_NOPAD
+        .decode(secret.as_bytes())
+        .unwrap();
+    let step = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        / 30;
+    let mut mac = Hmac::<Sha1>::new_from_slice(&secret).unwrap();
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+    let offset = usize::from(hash[19] & 0x0f);
+    let truncated = u32::from_be_bytes(hash[offset..offset + 4].try_into().unwrap());
+    format!("{:06}", (truncated & 0x7fff_ffff) % 1_000_000)
+}
+
+/// A code which is not the current one.
+fn wrong_code(code: &str) -> String {
+    let digit = (code.as_bytes()[0] - b'0' + 1) % 10;
+    format!("{digit}{}", &code[1..])
+}
+
+async fn exchange(conn: &mut Connection, cmd: cli::TwoFactor, id: u64) -> ser::Msg {
+    let msg = cli::Msg::TwoFactor(cmd).tagged(MsgId(id));
+    conn.send_msg(msg).await.unwrap();
+    conn.recv().await.unwrap()
+}
+
+#[tokio::test]
+async fn test_two_factor_authentication() {
+    let key: TotpKey = STANDARD.encode([42; 32]).parse().unwrap();
+    let server = Server::build((HOST_DEFAULT, 0)).await.unwrap();
+    let server = TestServer::spawn(server.totp_key(key));
+
+    let (creds, mut user) = signed_up(server.addr(), "totp").await;
+
+    let ser::Msg::TotpEnrollment { url } = exchange(&mut user, cli::TwoFactor::Enable, 1).await
+    else {
+        panic!("expected the enrollment");
+    };
+    assert!(url.starts_with(&format!("otpauth://totp/Chat:{}?secret=", creds.user)));
+    assert_eq!(user.recv().await.unwrap(), ser::Msg::Ack(MsgId(1)));
+    // Not required before it is confirmed.
+    Connection::connect(server.addr(), creds.clone())
+        .await
+        .unwrap();
+
+    let code = current_code(&url);
+    assert_eq!(
+        exchange(&mut user, cli::TwoFactor::Confirm(wrong_code(&code)), 2).await,
+        ser::Msg::Rejected(MsgId(2), ser::Error::InvalidTotpCode)
+    );
+    let confirmed = exchange(&mut user, cli::TwoFactor::Confirm(code), 3).await;
+    assert!(matches!(confirmed, ser::Msg::ServerInfo(_)));
+    assert_eq!(user.recv().await.unwrap(), ser::Msg::Ack(MsgId(3)));
+
+    let code = current_code(&url);
+    let required = Connection::connect(server.addr(), creds.clone()).await;
+    assert_eq!(authentication_error(required), ser::Error::InvalidTotpCode);
+    assert!(
+        Connection::connect_with_totp(server.addr(), creds.clone(), wrong_code(&code))
+            .await
+            .is_err()
+    );
+    let mut second = Connection::connect_with_totp(server.addr(), creds.clone(), code)
+        .await
+        .unwrap();
+
+    let code = current_code(&url);
+    let disabled = exchange(&mut second, cli::TwoFactor::Disable(code), 1).await;
+    assert!(matches!(disabled, ser::Msg::ServerInfo(_)));
+    Connection::connect(server.addr(), creds).await.unwrap();
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_two_factor_after_token() {
+    let issuer = TestIssuer::new();
+    let key: TotpKey = STANDARD.encode([42; 32]).parse().unwrap();
+    let server = Server::build((HOST_DEFAULT, 0)).await.unwrap();
+    let server = TestServer::spawn(server.oidc(issuer.oidc()).totp_key(key));
+    let (subject, name) = (unique("totp_subject"), unique("totp_oidc"));
+    let token = || issuer.token(&subject, &name);
+
+    let mut user = Connection::connect_with_token(server.addr(), token())
+        .await
+        .unwrap();
+    let ser::Msg::TotpEnrollment { url } = exchange(&mut user, cli::TwoFactor::Enable, 1).await
+    else {
+        panic!("expected the enrollment");
+    };
+    assert_eq!(user.recv().await.unwrap(), ser::Msg::Ack(MsgId(1)));
+    let confirmed = exchange(&mut user, cli::TwoFactor::Confirm(current_code(&url)), 2).await;
+    assert!(matches!(confirmed, ser::Msg::ServerInfo(_)));
+
+    // The token is the first factor only, the code is asked for on the same stream.
+    let mut stream = TcpStream::connect(server.addr()).await.unwrap();
+    let msg = cli::Msg::Auth(cli::Auth::OidcToken(token()));
+    msg.send(&mut stream).await.unwrap();
+    assert_eq!(
+        ser::Msg::receive(&mut stream).await.unwrap(),
+        ser::Msg::TotpRequired
+    );
+    let code = current_code(&url);
+    let msg = cli::Msg::Auth(cli::Auth::TotpCode(wrong_code(&code)));
+    msg.send(&mut stream).await.unwrap();
+    assert_eq!(
+        ser::Msg::receive(&mut stream).await.unwrap(),
+        ser::Msg::Error(ser::Error::InvalidTotpCode)
+    );
+    let msg = cli::Msg::Auth(cli::Auth::TotpCode(code));
+    msg.send(&mut stream).await.unwrap();
+    assert_eq!(
+        ser::Msg::receive(&mut stream).await.unwrap(),
+        ser::Msg::Authenticated
+    );
+
+    server.shutdown().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_two_factor_not_configured() {
+    let server = TestServer::start().await.unwrap();
+    let (_, mut user) = signed_up(server.addr(), "no_totp").await;
+    assert!(matches!(
+        exchange(&mut user, cli::TwoFactor::Enable, 1).await,
+        ser::Msg::Rejected(MsgId(1), ser::Error::Rejected(_))
+    ));
+    server.shutdown().await.unwrap();
+}
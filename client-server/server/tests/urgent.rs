@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use cli_ser::{ser, Data, MsgId};
+
+use server::{testing::signed_up, *};
+
+#[tokio::test]
+async fn test_urgent() {
+    let server = TestServer::spawn(
+        Server::build((HOST_DEFAULT, 0))
+            .await
+            .unwrap()
+            .urgent_limit(UrgentLimit {
+                max: 1,
+                period: Duration::from_secs(600),
+            }),
+    );
+    let address = server.addr();
+
+    let (creds, mut sender) = signed_up(address, "urgent_sender").await;
+    let (_, mut receiver) = signed_up(address, "urgent_receiver").await;
+
+    let urgent = cli_ser::cli::Msg::Urgent("fire in the kitchen".to_string());
+    sender
+        .send_msg(urgent.clone().tagged(MsgId(1)))
+        .await
+        .unwrap();
+    assert!(matches!(
+        sender.recv().await.unwrap(),
+        ser::Msg::Stored { id: MsgId(1), .. }
+    ));
+    match receiver.recv().await.unwrap() {
+        ser::Msg::DataFrom {
+            data, from, urgent, ..
+        } => {
+            assert_eq!(data, Data::Text("fire in the kitchen".to_string()));
+            assert_eq!(from, creds.user);
+            assert!(urgent);
+        }
+        msg => panic!("unexpected {msg:?}"),
+    }
+
+    // Over the limit, while ordinary texts still pass.
+    sender.send_msg(urgent.tagged(MsgId(2))).await.unwrap();
+    assert!(matches!(
+        sender.recv().await.unwrap(),
+        ser::Msg::Rejected(MsgId(2), ser::Error::Rejected(_))
+    ));
+    sender
+        .send(Data::Text("never mind".to_string()))
+        .await
+        .unwrap();
+    assert!(matches!(
+        receiver.recv().await.unwrap(),
+        ser::Msg::DataFrom { urgent: false, .. }
+    ));
+
+    assert!(server.is_running());
+    server.shutdown().await.unwrap();
+}
@@ -1,18 +1,88 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use core::fmt;
 use regex::Regex;
-use std::{error::Error, str::FromStr};
+use std::{
+    error::Error,
+    io::{self, BufRead, Write},
+    str::FromStr,
+};
 
+/// Parses the transformation `name` (options included, e.g. `wrap=80`) and applies it to the `input`.
+///
+/// A shortcut for other crates, equivalent to parsing a [Transformation] and [transforming][Transformation::transform].
+pub fn apply(name: &str, input: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+    name.parse::<Transformation>()?.transform(input)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Transformation {
     Lowercase,
     Uppercase,
     NoSpaces,
     Slugify,
     OneSpace,
-    Csv,
+    Csv(CsvOptions),
+    /// Reverses the order of characters.
+    Reverse,
+    /// Removes leading and trailing whitespace.
+    Trim,
+    /// Wraps each line into lines of at most the given number of characters, words are not split.
+    Wrap(usize),
+    Base64Encode,
+    /// Decodes Base64, whitespace is ignored and the result must be UTF-8.
+    Base64Decode,
+    JsonPretty,
+}
+
+/// Options of the [CSV][Transformation::Csv] transformation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsvOptions {
+    /// Field delimiter, a comma by default.
+    pub delimiter: u8,
+    /// Whether the first row is a header, the input must have one then.
+    pub header: bool,
+    /// Whether the header is separated from the rest of the table by a line when printed.
+    pub underline: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: b',',
+            header: true,
+            underline: false,
+        }
+    }
+}
+
+impl FromStr for CsvOptions {
+    type Err = ParseTransformationError;
+
+    /// Parses comma separated options `delimiter:<CHAR>` (or `delimiter:tab`), `header`/`noheader`
+    /// and `underline`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut options = CsvOptions::default();
+        for option in s.split(',').map(str::trim) {
+            match option.split_once(':') {
+                Some(("delimiter", "tab")) => options.delimiter = b'\t',
+                Some(("delimiter", d)) if d.len() == 1 => options.delimiter = d.as_bytes()[0],
+                None if option == "header" => options.header = true,
+                None if option == "noheader" || option == "no-header" => options.header = false,
+                None if option == "underline" => options.underline = true,
+                _ => {
+                    return Err(ParseTransformationError(format!(
+                        "CSV option \"{}\" is not supported!",
+                        option
+                    )))
+                }
+            }
+        }
+        Ok(options)
+    }
 }
 
 impl Transformation {
-    pub fn transform(&self, s: &str) -> Result<String, Box<dyn Error>> {
+    pub fn transform(&self, s: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
         match self {
             Transformation::Lowercase => Ok(s.to_lowercase()),
             Transformation::Uppercase => Ok(s.to_uppercase()),
@@ -21,9 +91,97 @@ impl Transformation {
             Transformation::OneSpace => {
                 Ok(Regex::new(r"\s+").map(|p| p.replace_all(s, " ").to_string())?)
             }
-            Transformation::Csv => Ok(Csv::from_str(s)?.to_string()),
+            Transformation::Csv(options) => Ok(Csv::parse(s, options)?.to_string()),
+            Transformation::Reverse => Ok(s.chars().rev().collect()),
+            Transformation::Trim => Ok(s.trim().to_string()),
+            Transformation::Wrap(width) => Ok(s
+                .split_inclusive('\n')
+                .map(|line| wrap_line(line, *width))
+                .collect()),
+            Transformation::Base64Encode => Ok(BASE64.encode(s)),
+            Transformation::Base64Decode => {
+                let encoded: String = s.split_whitespace().collect();
+                Ok(String::from_utf8(BASE64.decode(encoded)?)?)
+            }
+            Transformation::JsonPretty => {
+                Ok(serde_json::to_string_pretty(&serde_json::from_str::<
+                    serde_json::Value,
+                >(s)?)?)
+            }
         }
     }
+
+    /// Reads text from the `reader`, transforms it and writes the result to the `writer`.
+    ///
+    /// The output is the same as the one of [Transformation::transform],
+    /// however the input is processed line by line, so the memory usage does not grow with it.
+    /// CSV is read row by row, the rows are kept as the column widths depend on all of them.
+    /// Reversing, trimming, Base64 decoding and JSON formatting read the whole input first.
+    pub fn transform_reader(
+        &self,
+        mut reader: impl BufRead,
+        mut writer: impl Write,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match self {
+            Transformation::Csv(options) => {
+                write!(writer, "{}", Csv::from_reader(reader, options)?)?;
+                return Ok(writer.flush()?);
+            }
+            Transformation::Reverse
+            | Transformation::Trim
+            | Transformation::Base64Decode
+            | Transformation::JsonPretty => {
+                let mut s = String::new();
+                reader.read_to_string(&mut s)?;
+                write!(writer, "{}", self.transform(&s)?)?;
+                return Ok(writer.flush()?);
+            }
+            Transformation::Base64Encode => {
+                let mut encoder = base64::write::EncoderWriter::new(&mut writer, &BASE64);
+                io::copy(&mut reader, &mut encoder)?;
+                return Ok(encoder.finish()?.flush()?);
+            }
+            _ => {}
+        }
+        let mut line = String::new();
+        // Whitespace waiting for a non-whitespace character (OneSpace).
+        let mut pending_space = false;
+        // Whether a slug was written already, the next one needs a separator (Slugify).
+        let mut slugged = false;
+        while reader.read_line(&mut line)? != 0 {
+            match self {
+                Transformation::OneSpace => {
+                    for c in line.chars() {
+                        if c.is_whitespace() {
+                            pending_space = true;
+                        } else {
+                            if pending_space {
+                                write!(writer, " ")?;
+                                pending_space = false;
+                            }
+                            write!(writer, "{c}")?;
+                        }
+                    }
+                }
+                Transformation::Slugify => {
+                    let slug = slug::slugify(&line);
+                    if !slug.is_empty() {
+                        if slugged {
+                            write!(writer, "-")?;
+                        }
+                        write!(writer, "{slug}")?;
+                        slugged = true;
+                    }
+                }
+                other => write!(writer, "{}", other.transform(&line)?)?,
+            }
+            line.clear();
+        }
+        if pending_space {
+            write!(writer, " ")?;
+        }
+        Ok(writer.flush()?)
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -40,14 +198,33 @@ impl Error for ParseTransformationError {}
 impl FromStr for Transformation {
     type Err = ParseTransformationError;
 
+    /// Parses a transformation name, optionally followed by `=` and its options,
+    /// e.g. `csv=delimiter:;,noheader` (see [CsvOptions]) or `wrap=80`.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.trim().to_lowercase().replace('-', "").as_str() {
-            "lowercase" => Ok(Transformation::Lowercase),
-            "uppercase" => Ok(Transformation::Uppercase),
-            "nospaces" => Ok(Transformation::NoSpaces),
-            "slugify" => Ok(Transformation::Slugify),
-            "onespace" => Ok(Transformation::OneSpace),
-            "csv" => Ok(Transformation::Csv),
+        let (name, options) = match s.trim().split_once('=') {
+            Some((name, options)) => (name, Some(options)),
+            None => (s.trim(), None),
+        };
+        match (name.to_lowercase().replace('-', "").as_str(), options) {
+            ("lowercase", None) => Ok(Transformation::Lowercase),
+            ("uppercase", None) => Ok(Transformation::Uppercase),
+            ("nospaces", None) => Ok(Transformation::NoSpaces),
+            ("slugify", None) => Ok(Transformation::Slugify),
+            ("onespace", None) => Ok(Transformation::OneSpace),
+            ("csv", None) => Ok(Transformation::Csv(CsvOptions::default())),
+            ("csv", Some(options)) => Ok(Transformation::Csv(options.parse()?)),
+            ("reverse", None) => Ok(Transformation::Reverse),
+            ("trim", None) => Ok(Transformation::Trim),
+            ("wrap", Some(width)) => match width.trim().parse() {
+                Ok(width) if width > 0 => Ok(Transformation::Wrap(width)),
+                _ => Err(ParseTransformationError(format!(
+                    "Wrap width \"{}\" must be a positive number!",
+                    width
+                ))),
+            },
+            ("base64encode", None) => Ok(Transformation::Base64Encode),
+            ("base64decode", None) => Ok(Transformation::Base64Decode),
+            ("jsonpretty", None) => Ok(Transformation::JsonPretty),
             _ => Err(ParseTransformationError(format!(
                 "Argument \"{}\" can not be parsed to Transformation!",
                 s
@@ -56,68 +233,262 @@ impl FromStr for Transformation {
     }
 }
 
+/// Wraps a single line (with or without its line feed) into lines of at most `width` characters.
+///
+/// Words longer than `width` are kept whole, whitespace between words is collapsed.
+fn wrap_line(line: &str, width: usize) -> String {
+    let (content, newline) = match line.strip_suffix('\n') {
+        Some(content) => (content, "\n"),
+        None => (line, ""),
+    };
+    let mut wrapped = String::new();
+    let mut length = 0;
+    for word in content.split_whitespace() {
+        let word_length = word.chars().count();
+        if length > 0 && length + 1 + word_length > width {
+            wrapped.push('\n');
+            length = 0;
+        } else if length > 0 {
+            wrapped.push(' ');
+            length += 1;
+        }
+        wrapped.push_str(word);
+        length += word_length;
+    }
+    wrapped + newline
+}
+
 /// Structure to hold CSV data.
-struct Csv<'a> {
+struct Csv {
     row_length: usize,
-    rows: Vec<Vec<&'a str>>,
+    underline: bool,
+    rows: Vec<Vec<String>>,
 }
 
-impl Csv<'_> {
-    /// Parses well-formed CSV from borrowed str.
-    fn from_str(s: &str) -> Result<Csv, Box<dyn Error>> {
-        // Split string into rows and rows into fields.
-        let csv = s
-            .lines()
-            .map(|line| line.split(',').map(|s| s.trim()).collect::<Vec<_>>())
-            .collect::<Vec<_>>();
+impl Csv {
+    /// Parses well-formed CSV (RFC 4180, quoted fields can contain delimiters and newlines).
+    ///
+    /// Fields are trimmed, all rows must have the same number of fields.
+    fn parse(s: &str, options: &CsvOptions) -> Result<Csv, Box<dyn Error + Send + Sync>> {
+        Self::from_reader(s.as_bytes(), options)
+    }
 
-        // Check all rows have the same number of fields.
-        let mut row_lengths = csv.iter().map(|h| h.len());
-        let Some(hdr_len) = row_lengths.next() else {
-            return Err("CSV must have an header.".into());
-        };
-        let true = row_lengths.all(|l| l == hdr_len) else {
-            return Err("Every CSV row must have the same number of fields as the header.".into());
+    /// Parses CSV as [parse][Self::parse] does, reading one row at a time from the `reader`.
+    fn from_reader(
+        reader: impl io::Read,
+        options: &CsvOptions,
+    ) -> Result<Csv, Box<dyn Error + Send + Sync>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(options.delimiter)
+            .has_headers(false)
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let mut record = csv::StringRecord::new();
+        let mut rows = Vec::new();
+        while reader.read_record(&mut record)? {
+            rows.push(record.iter().map(String::from).collect::<Vec<_>>());
+        }
+        let row_length = match rows.first() {
+            Some(first) => first.len(),
+            None if options.header => return Err("CSV must have an header.".into()),
+            None => 0,
         };
-
         Ok(Csv {
-            row_length: hdr_len,
-            rows: csv,
+            row_length,
+            underline: options.header && options.underline,
+            rows,
         })
     }
 }
 
-impl fmt::Display for Csv<'_> {
-    /// Formats Csv as text table.
+impl fmt::Display for Csv {
+    /// Formats Csv as text table, the header is underlined when the [options][CsvOptions::underline] say so.
+    /// Newlines within fields are shown escaped (`\n`).
     /// **Warning!**: Line endings are always LF byte.
     /// If CRLF is desired use `s.replace("\n", "\r\n")`.
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows = self
+            .rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|field| field.replace('\n', "\\n"))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
         // Calculate width for each field.
-        let Some(widths) = (0..self.row_length)
-            .map(|field_idx| self.rows.iter().map(|row| row[field_idx].len()).max())
-            .collect::<Option<Vec<_>>>()
-        else {
-            // CSV calculation of column widths failed, contact the implementer.
-            return Err(fmt::Error);
-        };
+        let widths = (0..self.row_length)
+            .map(|field_idx| {
+                rows.iter()
+                    .map(|row| row[field_idx].len())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect::<Vec<_>>();
         // Pad fields with spaces, join fields with "|", join lines with "\n".
-        write!(
-            f,
-            "{}",
-            self.rows
-                .iter()
-                .map(|row| {
-                    format!(
-                        "|{}|\n",
-                        row.iter()
-                            .zip(widths.iter())
-                            .map(|(field, width)| format!(" {:width$} ", field, width = width))
-                            .collect::<Vec<_>>()
-                            .join("|")
-                    )
-                })
-                .collect::<Vec<_>>()
-                .concat()
-        )
+        for (idx, row) in rows.iter().enumerate() {
+            writeln!(
+                f,
+                "|{}|",
+                row.iter()
+                    .zip(widths.iter())
+                    .map(|(field, width)| format!(" {:width$} ", field, width = width))
+                    .collect::<Vec<_>>()
+                    .join("|")
+            )?;
+            if self.underline && idx == 0 {
+                writeln!(
+                    f,
+                    "|{}|",
+                    widths
+                        .iter()
+                        .map(|width| "-".repeat(width + 2))
+                        .collect::<Vec<_>>()
+                        .join("|")
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_table() {
+        let csv = Transformation::Csv(CsvOptions::default());
+        // The same bytes as before the csv crate parsed it, no line under the header.
+        assert_eq!(
+            csv.transform("name,age\nalice, 30\nbob,4\n").unwrap(),
+            "| name  | age |\n| alice | 30  |\n| bob   | 4   |\n"
+        );
+        assert!(csv.transform("").is_err());
+        assert!(csv.transform("a,b\nc\n").is_err());
+    }
+
+    #[test]
+    fn csv_quoted_commas() {
+        let csv = Transformation::Csv(CsvOptions::default());
+        assert_eq!(
+            csv.transform("name,note\nalice,\"x, y\"\n").unwrap(),
+            "| name  | note |\n| alice | x, y |\n"
+        );
+        assert_eq!(
+            csv.transform("a\n\"two\nlines\"\n").unwrap(),
+            "| a          |\n| two\\nlines |\n"
+        );
+    }
+
+    #[test]
+    fn csv_options() {
+        let csv: Transformation = "csv=delimiter:;,underline".parse().unwrap();
+        assert_eq!(
+            csv.transform("a;b\n1,5;2\n").unwrap(),
+            "| a   | b |\n|-----|---|\n| 1,5 | 2 |\n"
+        );
+        let csv: Transformation = "csv=noheader,underline".parse().unwrap();
+        assert_eq!(csv.transform("").unwrap(), "");
+        assert_eq!(csv.transform("a,b\n").unwrap(), "| a | b |\n");
+        assert!("csv=delimiter:ab".parse::<Transformation>().is_err());
+    }
+
+    #[test]
+    fn apply_by_name() {
+        assert_eq!(apply(" Upper-Case ", "abc").unwrap(), "ABC");
+        assert_eq!(apply("wrap=3", "ab cd").unwrap(), "ab\ncd");
+        assert!(apply("unknown", "abc").is_err());
+        assert!(apply("json-pretty", "not json").is_err());
+    }
+
+    #[test]
+    fn reverse_and_trim() {
+        assert_eq!(apply("reverse", "abč\n").unwrap(), "\nčba");
+        assert_eq!(apply("trim", " \t a b \n").unwrap(), "a b");
+    }
+
+    #[test]
+    fn wrap() {
+        let wrap = Transformation::Wrap(10);
+        assert_eq!(
+            wrap.transform("one two three four\nfive\n").unwrap(),
+            "one two\nthree four\nfive\n"
+        );
+        // Long words are kept whole, whitespace is collapsed.
+        assert_eq!(
+            wrap.transform("a   incomprehensibilities b").unwrap(),
+            "a\nincomprehensibilities\nb"
+        );
+        assert_eq!("wrap=10".parse(), Ok(wrap));
+        assert!("wrap=0".parse::<Transformation>().is_err());
+        assert!("wrap".parse::<Transformation>().is_err());
+    }
+
+    #[test]
+    fn base64() {
+        assert_eq!(apply("base64-encode", "héllo").unwrap(), "aMOpbGxv");
+        assert_eq!(apply("base64-decode", "aMOp\nbGxv\n").unwrap(), "héllo");
+        assert!(apply("base64-decode", "!!").is_err());
+        // Not UTF-8.
+        assert!(apply("base64-decode", "/w==").is_err());
+    }
+
+    #[test]
+    fn json_pretty() {
+        assert_eq!(
+            apply("json-pretty", r#"{"b":1,"a":[true]}"#).unwrap(),
+            "{\n  \"b\": 1,\n  \"a\": [\n    true\n  ]\n}"
+        );
+        assert!(apply("json-pretty", "{").is_err());
+    }
+
+    /// Output of [Transformation::transform_reader] reading the `input` a few bytes at a time.
+    fn transformed_reader(t: &Transformation, input: &str) -> String {
+        let reader = io::BufReader::with_capacity(3, input.as_bytes());
+        let mut output = Vec::new();
+        t.transform_reader(reader, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn csv_reader() {
+        let input = "name,note\nalice,\"x, y\"\nbob,\"two\nlines\"\n";
+        for options in ["csv", "csv=underline", "csv=noheader"] {
+            let csv: Transformation = options.parse().unwrap();
+            assert_eq!(
+                transformed_reader(&csv, input),
+                csv.transform(input).unwrap(),
+                "{options}"
+            );
+        }
+        let csv = Transformation::Csv(CsvOptions::default());
+        let mut output = Vec::new();
+        assert!(csv
+            .transform_reader("a,b\nc\n".as_bytes(), &mut output)
+            .is_err());
+    }
+
+    #[test]
+    fn reader_same_as_transform() {
+        let input = "  Hello,\tWorld!  \n\nsecond   line ÄŒ\nno newline at the end";
+        for t in [
+            "lowercase",
+            "uppercase",
+            "no-spaces",
+            "slugify",
+            "one-space",
+            "wrap=6",
+            "reverse",
+            "trim",
+            "base64-encode",
+        ] {
+            let t: Transformation = t.parse().unwrap();
+            assert_eq!(
+                transformed_reader(&t, input),
+                t.transform(input).unwrap(),
+                "{t:?}"
+            );
+        }
     }
 }
@@ -10,25 +10,66 @@
 //! <example.csv cargo run csv
 //! # multiple transformations example:
 //! <example.txt cargo run >out.txt; echo "Errors? $?"
+//! # files instead of redirection, the second one is edited in place:
+//! cargo run -- wrap=40 --input example.txt --output out.txt
+//! cargo run -- one-space --input out.txt --in-place
 //! ```
 
-use std::{env, error::Error, fs, io, sync::mpsc, thread};
+use std::{
+    error::Error,
+    fs,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::mpsc,
+    thread,
+};
+
+use clap::Parser;
 use text_tool::Transformation;
 
-/// Prints transformed standard input based on its values and arguments given.
+/// Transforms text, either all of it by one transformation or line by line as each line says.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Transformation of the whole input (one-shot mode), e.g. "csv" or "wrap=80"
+    transformation: Option<Transformation>,
+
+    /// File to read instead of the standard input
+    #[arg(short, long, value_name = "FILE")]
+    input: Option<PathBuf>,
+
+    /// File to write instead of the standard output
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Replace the input file with the result, the file is replaced at once when done
+    #[arg(
+        long,
+        requires = "input",
+        requires = "transformation",
+        conflicts_with = "output"
+    )]
+    in_place: bool,
+}
+
+/// Prints transformed input based on its values and arguments given.
 ///
-/// Running the executable with an argument triggers an "one-shot" mode,
+/// Running the executable with a transformation argument triggers an "one-shot" mode,
 /// "interactive" mode runs otherwise.
+/// The input and output are the standard ones unless files are given, see `--help`.
 ///
 /// ## One-shot Mode (single thread)
 ///
 /// The function tries to:
-/// 1. read the standard input,
+/// 1. read the input (line by line, so even huge inputs fit into memory),
 /// 2. apply a transformation* to it,
-/// 3. print the result to the standard output.
+/// 3. write the result to the output.
 ///
 /// * It is chosen based on the argument given to the executable.
 ///
+/// With `--in-place` the result is written into a temporary file next to the input,
+/// which then replaces the input, so the input is never left half written.
+///
 /// ## Interactive Mode (multi-threaded)
 ///
 /// Parses each line of standard input into transformation and its input.
@@ -43,24 +84,27 @@ use text_tool::Transformation;
 /// It looks like both `eprintln!` macro and `io::attempt_print_to_stderr` uses
 /// `stderr().write_fmt(args)` in the end
 /// (see <https://doc.rust-lang.org/src/std/io/stdio.rs.html#1039>).
-fn main() -> Result<(), Box<dyn Error>> {
-    if let Some(argument) = read_single_argument()? {
+fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
+    let args = Args::parse();
+    if let Some(t) = args.transformation {
         // One thread, one transformation, multi-line transformation input
-        let t: Transformation = argument.parse()?;
-        let text = io::read_to_string(io::stdin())?;
-        print!("{}", t.transform(&text)?);
-        Ok(())
+        let input = open_input(args.input.as_deref())?;
+        match (&args.input, args.in_place) {
+            (Some(path), true) => transform_in_place(&t, input, path),
+            _ => t.transform_reader(input, open_output(args.output.as_deref())?),
+        }
     } else {
         // Two threads, many transformations, single-line transformation input
         let (sender, receiver) = mpsc::channel();
+        let mut input = open_input(args.input.as_deref())?;
+        let mut output = open_output(args.output.as_deref())?;
 
         let reader = thread::spawn(move || {
             let mut buffer = String::new();
-            let stdin = io::stdin();
 
             loop {
                 buffer.clear();
-                match stdin.read_line(&mut buffer) {
+                match input.read_line(&mut buffer) {
                     Err(msg) => break Err(msg.to_string()), // wanted to do `e @ Err(_) => return e`
                     Ok(0) => break Ok(()),                  // EOF
                     Ok(_) => {
@@ -88,10 +132,19 @@ fn main() -> Result<(), Box<dyn Error>> {
                             state = general_error;
                             eprintln!("{}", msg);
                         }
-                        Ok(s) => println!("{}", s),
+                        Ok(s) => {
+                            if let Err(msg) = writeln!(output, "{}", s) {
+                                state = general_error;
+                                eprintln!("{}", msg);
+                            }
+                        }
                     },
                 }
             }
+            if let Err(msg) = output.flush() {
+                state = general_error;
+                eprintln!("{}", msg);
+            }
             state
         });
 
@@ -102,20 +155,46 @@ fn main() -> Result<(), Box<dyn Error>> {
     }
 }
 
-/// Returns Option of the only command line argument.
-/// Giving more than one argument results in an error.
-fn read_single_argument() -> Result<Option<String>, &'static str> {
-    let mut args = env::args().skip(1);
-    if args.len() <= 1 {
-        Ok(args.next())
-    } else {
-        Err("More than one argument given!")
-    }
+/// Opens the file at `path` for reading, the standard input is used when `None`.
+fn open_input(path: Option<&Path>) -> io::Result<Box<dyn BufRead + Send>> {
+    Ok(match path {
+        Some(path) => Box::new(BufReader::new(fs::File::open(path)?)),
+        None => Box::new(BufReader::new(io::stdin())),
+    })
+}
+
+/// Creates the file at `path` for writing, the standard output is used when `None`.
+fn open_output(path: Option<&Path>) -> io::Result<Box<dyn Write + Send>> {
+    Ok(match path {
+        Some(path) => Box::new(BufWriter::new(fs::File::create(path)?)),
+        None => Box::new(io::stdout()),
+    })
+}
+
+/// Transforms the `input` into a temporary file which then atomically replaces the file at `path`.
+///
+/// The temporary file is in the same directory (renaming works within one file system only)
+/// and gets the permissions of the original file.
+fn transform_in_place(
+    t: &Transformation,
+    input: impl BufRead,
+    path: &Path,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut temp = tempfile::NamedTempFile::new_in(dir)?;
+    t.transform_reader(input, BufWriter::new(temp.as_file_mut()))?;
+    fs::set_permissions(temp.path(), fs::metadata(path)?.permissions())?;
+    temp.persist(path)?;
+    Ok(())
 }
 
 /// Parses string into Transformation variant and an argument string*
 ///
-/// * In Transformation::Csv case the argument is treated as a file name.
+/// * In Transformation::Csv case the argument is treated as a file name,
+///   options can be given along the transformation, e.g. `csv=delimiter:;,noheader data.csv`.
 ///
 /// The line parsing should be equivalent to the following regex:
 /// `^\s*(?<transformation>\w+) (?<argument>.*)\n?$`
@@ -131,7 +210,7 @@ fn parse_line(raw: &str) -> Result<(Transformation, String), String> {
     if let Some((cmd, arg)) = without_newline.trim_start().split_once(' ') {
         match cmd.parse::<Transformation>() {
             Ok(tr) => match tr {
-                Transformation::Csv => match fs::read_to_string(arg.trim()) {
+                Transformation::Csv(_) => match fs::read_to_string(arg.trim()) {
                     Ok(csv) => Ok((tr, csv)),
                     Err(e) => Err(format!("{} | {}", e, arg)),
                 },
@@ -146,3 +225,71 @@ fn parse_line(raw: &str) -> Result<(Transformation, String), String> {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn in_place_needs_input_and_transformation() {
+        let args = Args::try_parse_from(["text-tool", "trim", "--input", "a.txt", "--in-place"]);
+        assert!(args.unwrap().in_place);
+        assert!(Args::try_parse_from(["text-tool", "trim", "--in-place"]).is_err());
+        assert!(Args::try_parse_from(["text-tool", "--input", "a.txt", "--in-place"]).is_err());
+        let both = [
+            "text-tool",
+            "trim",
+            "-i",
+            "a.txt",
+            "-o",
+            "b.txt",
+            "--in-place",
+        ];
+        assert!(Args::try_parse_from(both).is_err());
+    }
+
+    #[test]
+    fn input_and_output_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let (input, output) = (dir.path().join("in.txt"), dir.path().join("out.txt"));
+        fs::write(&input, "Hello\nWorld\n").unwrap();
+        Transformation::Uppercase
+            .transform_reader(
+                open_input(Some(&input)).unwrap(),
+                open_output(Some(&output)).unwrap(),
+            )
+            .unwrap();
+        assert_eq!(fs::read_to_string(&output).unwrap(), "HELLO\nWORLD\n");
+        assert!(open_input(Some(&dir.path().join("missing.txt"))).is_err());
+    }
+
+    #[test]
+    fn in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("text.txt");
+        fs::write(&path, "  a   b  \n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        }
+        let input = open_input(Some(&path)).unwrap();
+        transform_in_place(&Transformation::OneSpace, input, &path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), " a b ");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o640);
+        }
+        // Only the file is left, the temporary one became it.
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        // A failed transformation leaves the file as it was.
+        fs::write(&path, "{").unwrap();
+        let input = open_input(Some(&path)).unwrap();
+        assert!(transform_in_place(&Transformation::JsonPretty, input, &path).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}